@@ -0,0 +1,75 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use gas_saver_eth::limiter::RateLimiter;
+use gas_saver_eth::nonce::{NonceAllocator, NonceManager};
+use std::hint::black_box;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const CHAIN_ID: u64 = 1;
+
+/// Wall time for `threads` threads to make `iters` calls of `call` between them,
+/// each passing its thread index.
+fn contended(threads: u64, iters: u64, call: impl Fn(u64) + Sync) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for thread in 0..threads {
+            let call = &call;
+            scope.spawn(move || {
+                for _ in 0..iters.div_ceil(threads) {
+                    call(thread);
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn limiter_check_and_consume(c: &mut Criterion) {
+    let mut group = c.benchmark_group("limiter/check_and_consume");
+    for threads in [1, 4, 8] {
+        // never runs dry, so every call takes the token path
+        let limiter = RateLimiter::new(1 << 40, 1 << 40);
+        group.bench_with_input(
+            BenchmarkId::from_parameter(threads),
+            &threads,
+            |b, &threads| {
+                b.iter_custom(|iters| {
+                    contended(threads, iters, |_| {
+                        black_box(limiter.check_and_consume());
+                    })
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+/// `try_next_nonce`, the allocator behind the scheduler's submits, with every
+/// thread on one sender and with a sender per thread.
+fn nonce_try_next_nonce(c: &mut Criterion) {
+    for (name, shared) in [("shared_sender", true), ("sender_per_thread", false)] {
+        let mut group = c.benchmark_group(format!("nonce/try_next_nonce/{}", name));
+        for threads in [1, 4, 8] {
+            let sender = |thread: u64| [if shared { 0 } else { thread as u8 + 1 }; 20];
+            let nonces = NonceManager::new();
+            for thread in 0..threads {
+                nonces.update_nonce(CHAIN_ID, sender(thread).into(), 0);
+            }
+            group.bench_with_input(
+                BenchmarkId::from_parameter(threads),
+                &threads,
+                |b, &threads| {
+                    b.iter_custom(|iters| {
+                        contended(threads, iters, |thread| {
+                            black_box(nonces.try_next_nonce(CHAIN_ID, sender(thread)).unwrap());
+                        })
+                    })
+                },
+            );
+        }
+        group.finish();
+    }
+}
+
+criterion_group!(benches, limiter_check_and_consume, nonce_try_next_nonce);
+criterion_main!(benches);
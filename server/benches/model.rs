@@ -0,0 +1,31 @@
+use criterion::{BenchmarkId, Criterion, criterion_group, criterion_main};
+use gas_saver_eth::model::GasModel;
+use gas_saver_eth::units::{Gwei, Wei};
+use std::hint::black_box;
+
+/// A base fee wandering up to 5 gwei above 40, so volatility has work to do.
+fn fee(i: u64) -> Wei {
+    Wei::from(Gwei(40)) + Wei(u128::from(i) * 7_919 % 5_000_000_000)
+}
+
+fn update_and_volatility(c: &mut Criterion) {
+    let mut group = c.benchmark_group("model/update_and_volatility");
+    for window in [100, 1_000, 10_000] {
+        let model = GasModel::new(window);
+        for i in 0..window as u64 {
+            model.update(fee(i));
+        }
+        let mut i = window as u64;
+        group.bench_with_input(BenchmarkId::from_parameter(window), &model, |b, model| {
+            b.iter(|| {
+                i += 1;
+                model.update(fee(i));
+                black_box(model.get_volatility())
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, update_and_volatility);
+criterion_main!(benches);
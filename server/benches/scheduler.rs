@@ -0,0 +1,101 @@
+use async_trait::async_trait;
+use criterion::{BatchSize, BenchmarkId, Criterion, criterion_group, criterion_main};
+use gas_saver_eth::events::{RestoredTx, SchedulerDecision};
+use gas_saver_eth::limiter::NoopLimiter;
+use gas_saver_eth::model::{GasModel, ModelSnapshot};
+use gas_saver_eth::nonce::{NonceAllocator, NonceManager};
+use gas_saver_eth::scheduler::{BenchState, Scheduler, SchedulerConfig};
+use gas_saver_eth::sink::{DecisionSink, SinkError};
+use gas_saver_eth::testkit::scripted_request;
+use gas_saver_eth::units::{Gwei, Wei};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+const CHAIN_ID: u64 = 1;
+
+/// Counts decisions in place, so a pass doesn't pay for a channel.
+#[derive(Default)]
+struct CountingSink(AtomicU64);
+
+#[async_trait]
+impl DecisionSink for CountingSink {
+    async fn deliver(&self, _decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.0.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// A scheduler whose model has just seen the base fee jump from 40 to 60 gwei,
+/// holding `count` pending requests accepted at 40 and `count` txs submitted at
+/// it, from `scripted_request`'s four senders.
+fn after_fee_jump(count: u64) -> (Scheduler, BenchState) {
+    let accepted_at = Wei::from(Gwei(40));
+    let mut base_fees = vec![accepted_at; 99];
+    base_fees.push(Gwei(60).into());
+    let model = GasModel::new(100);
+    model.import(ModelSnapshot {
+        base_fees,
+        rewards: Vec::new(),
+    });
+
+    let pending: Vec<_> = (0..count)
+        .map(|i| scripted_request(i, accepted_at))
+        .collect();
+    let submitted: Vec<_> = (0..count)
+        .map(|i| {
+            let req = scripted_request(count + i, accepted_at);
+            let gas_price = req.max_priority_fee_per_gas + accepted_at;
+            RestoredTx {
+                req,
+                nonce: i / 4,
+                gas_price,
+                tx_hash: None,
+            }
+        })
+        .collect();
+    let nonces = NonceManager::new();
+    for sender in 0..4 {
+        let from = scripted_request(sender, accepted_at).from;
+        nonces.update_nonce(CHAIN_ID, from.into(), count / 4);
+    }
+
+    let config = SchedulerConfig {
+        reprice_cooldown: Duration::ZERO,
+        ..SchedulerConfig::default()
+    };
+    let scheduler = Scheduler::new(
+        config,
+        Arc::new(model),
+        Arc::new(nonces),
+        Arc::new(NoopLimiter::new()),
+        vec![Box::new(CountingSink::default())],
+    );
+    let state = scheduler.bench_state(pending, submitted);
+    (scheduler, state)
+}
+
+fn re_evaluate_pending(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("scheduler/re_evaluate_pending");
+    group.sample_size(10);
+    for count in [1_000, 10_000] {
+        group.bench_function(BenchmarkId::from_parameter(count), |b| {
+            b.iter_batched(
+                || after_fee_jump(count),
+                |(scheduler, mut state)| {
+                    runtime.block_on(scheduler.bench_re_evaluate_pending(&mut state));
+                    (scheduler, state)
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, re_evaluate_pending);
+criterion_main!(benches);
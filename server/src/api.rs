@@ -0,0 +1,494 @@
+use crate::events::{SchedulerCommand, TransactionRequest, TxStatus, ValidationError};
+use crate::health::{HealthMonitor, HealthReport, HealthStatus};
+use crate::scheduler::SchedulerHandle;
+use axum::extract::rejection::JsonRejection;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use lru::LruCache;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio_util::sync::CancellationToken;
+use tower::limit::ConcurrencyLimitLayer;
+
+/// Largest request body accepted; room for a request deploying a contract of the
+/// maximum init code size, hex encoded.
+pub const MAX_BODY_BYTES: usize = 256 * 1024;
+/// Requests served at once; later ones wait their turn.
+pub const MAX_CONCURRENT_REQUESTS: usize = 256;
+/// Requests whose latest status is kept, the least recently touched going first.
+const TRACKED_TXS: usize = 10_000;
+
+#[derive(Clone)]
+struct ApiState {
+    handle: SchedulerHandle,
+    /// None until the scheduler has taken the request off its queue.
+    statuses: Arc<Mutex<LruCache<u64, Option<TxStatus>>>>,
+}
+
+/// The HTTP API in front of a running scheduler:
+///
+/// - `POST /tx` takes a `TransactionRequest` as JSON and answers 202 with its
+///   `tx_id`, or 400 with the `reason` and structured `error` if it doesn't
+///   validate. An id already being tracked gets 409.
+/// - `GET /tx/{id}` answers with the latest `TxStatus` of a request posted here,
+///   null while it waits in the scheduler's queue.
+/// - `DELETE /tx/{id}` sends `SchedulerCommand::Cancel` for a request posted here
+///   that is pending, and answers 202; the status turns `Dropped` once the
+///   scheduler has cancelled it. Requests still queued or past pending get 409.
+///
+/// Everything goes through `handle`; statuses come from each request's status
+/// stream.
+pub fn router(handle: SchedulerHandle) -> Router {
+    let state = ApiState {
+        handle,
+        statuses: Arc::new(Mutex::new(LruCache::new(
+            NonZeroUsize::new(TRACKED_TXS).unwrap(),
+        ))),
+    };
+    Router::new()
+        .route("/tx", post(submit))
+        .route("/tx/{id}", get(status).delete(cancel))
+        .layer(DefaultBodyLimit::max(MAX_BODY_BYTES))
+        .layer(ConcurrencyLimitLayer::new(MAX_CONCURRENT_REQUESTS))
+        .with_state(state)
+}
+
+/// Health checks for load balancers and watchdogs, from `monitor`:
+///
+/// - `GET /health` answers with the overall `status` and the `problems` behind
+///   it; 200 while healthy or degraded, 503 once unhealthy.
+/// - `GET /status` answers 200 with the whole `HealthReport`.
+pub fn health_router(monitor: Arc<HealthMonitor>) -> Router {
+    Router::new()
+        .route("/health", get(health))
+        .route("/status", get(full_status))
+        .with_state(monitor)
+}
+
+/// Serves `app`, usually `router`, on `listener` until the listener fails, or
+/// until `shutdown` is cancelled and the requests in progress are answered.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    app: Router,
+    shutdown: CancellationToken,
+) -> std::io::Result<()> {
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+}
+
+#[derive(Serialize)]
+struct Accepted {
+    tx_id: u64,
+}
+
+#[derive(Serialize)]
+struct Status {
+    tx_id: u64,
+    status: Option<TxStatus>,
+}
+
+/// An error response: `reason` for people, `error` for validation failures.
+#[derive(Debug, Serialize)]
+struct ApiError {
+    #[serde(skip)]
+    code: StatusCode,
+    reason: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ValidationError>,
+}
+
+impl ApiError {
+    fn new(code: StatusCode, reason: impl Into<String>) -> Self {
+        Self {
+            code,
+            reason: reason.into(),
+            error: None,
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (self.code, Json(self)).into_response()
+    }
+}
+
+async fn submit(
+    State(state): State<ApiState>,
+    body: Result<Json<TransactionRequest>, JsonRejection>,
+) -> Result<(StatusCode, Json<Accepted>), ApiError> {
+    let Json(req) = body.map_err(|e| ApiError::new(e.status(), e.body_text()))?;
+    let now_secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs());
+    if let Err(error) = req.validate(now_secs) {
+        return Err(ApiError {
+            code: StatusCode::BAD_REQUEST,
+            reason: error.to_string(),
+            error: Some(error),
+        });
+    }
+
+    let tx_id = req.id;
+    {
+        let mut statuses = state.statuses.lock();
+        if statuses
+            .peek(&tx_id)
+            .is_some_and(|status| !status.as_ref().is_some_and(TxStatus::is_terminal))
+        {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("tx {} is already being tracked", tx_id),
+            ));
+        }
+        statuses.put(tx_id, None);
+    }
+    let mut updates = match state.handle.submit_with_status(req).await {
+        Ok(updates) => updates,
+        Err(e) => {
+            state.statuses.lock().pop(&tx_id);
+            return Err(ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                e.to_string(),
+            ));
+        }
+    };
+    let statuses = state.statuses.clone();
+    tokio::spawn(async move {
+        while let Some(status) = updates.recv().await {
+            statuses.lock().put(tx_id, Some(status));
+        }
+    });
+    Ok((StatusCode::ACCEPTED, Json(Accepted { tx_id })))
+}
+
+async fn status(
+    State(state): State<ApiState>,
+    Path(tx_id): Path<u64>,
+) -> Result<Json<Status>, ApiError> {
+    let status = state.statuses.lock().get(&tx_id).cloned();
+    match status {
+        Some(status) => Ok(Json(Status { tx_id, status })),
+        None => Err(unknown(tx_id)),
+    }
+}
+
+async fn cancel(
+    State(state): State<ApiState>,
+    Path(tx_id): Path<u64>,
+) -> Result<(StatusCode, Json<Accepted>), ApiError> {
+    let status = state.statuses.lock().get(&tx_id).cloned();
+    match status {
+        None => return Err(unknown(tx_id)),
+        Some(None) => {
+            // commands overtake queued requests, so a cancel now would miss it
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("tx {} is not accepted yet", tx_id),
+            ));
+        }
+        Some(Some(TxStatus::Pending | TxStatus::Deferred(_))) => {}
+        Some(Some(status)) if status.is_terminal() => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("tx {} is already finished", tx_id),
+            ));
+        }
+        Some(_) => {
+            return Err(ApiError::new(
+                StatusCode::CONFLICT,
+                format!("tx {} is already submitted", tx_id),
+            ));
+        }
+    }
+    state
+        .handle
+        .command(SchedulerCommand::Cancel { tx_id })
+        .await
+        .map_err(|e| ApiError::new(StatusCode::SERVICE_UNAVAILABLE, e.to_string()))?;
+    Ok((StatusCode::ACCEPTED, Json(Accepted { tx_id })))
+}
+
+#[derive(Serialize)]
+struct Health {
+    status: HealthStatus,
+    problems: Vec<String>,
+}
+
+async fn health(State(monitor): State<Arc<HealthMonitor>>) -> (StatusCode, Json<Health>) {
+    let HealthReport {
+        status, problems, ..
+    } = monitor.report();
+    let code = match status {
+        HealthStatus::Unhealthy => StatusCode::SERVICE_UNAVAILABLE,
+        HealthStatus::Healthy | HealthStatus::Degraded => StatusCode::OK,
+    };
+    (code, Json(Health { status, problems }))
+}
+
+async fn full_status(State(monitor): State<Arc<HealthMonitor>>) -> Json<HealthReport> {
+    Json(monitor.report())
+}
+
+fn unknown(tx_id: u64) -> ApiError {
+    ApiError::new(
+        StatusCode::NOT_FOUND,
+        format!("tx {} is not tracked", tx_id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::BackpressurePolicy;
+    use crate::events::{FeeMode, GasEvent, SchedulerDecision, SubmissionPrivacy, Urgency};
+    use crate::health::HealthThresholds;
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::{NonceAllocator, NonceManager};
+    use crate::scheduler::{Scheduler, SchedulerConfig};
+    use crate::sink::ChannelSink;
+    use crate::units::Wei;
+    use alloy_primitives::Address;
+    use axum::body::Body;
+    use axum::http::Request;
+    use serde_json::{Value, json};
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use tower::ServiceExt;
+
+    fn request(id: u64, max_fee_per_gas: u128) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(max_fee_per_gas),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    /// A running scheduler behind the API, with its gas feed and decisions.
+    fn start() -> (
+        Router,
+        mpsc::Sender<GasEvent>,
+        mpsc::Receiver<SchedulerDecision>,
+    ) {
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (decision_tx, decision_rx) = mpsc::channel(100);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Arc::new(Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        ));
+        tokio::spawn(scheduler.run(event_rx, req_rx, cmd_rx));
+        (router(handle), event_tx, decision_rx)
+    }
+
+    async fn call(app: &Router, method: &str, uri: &str, body: Body) -> (StatusCode, Value) {
+        let request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .header("content-type", "application/json")
+            .body(body)
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let code = response.status();
+        let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        (code, serde_json::from_slice(&bytes).unwrap_or(Value::Null))
+    }
+
+    async fn post(app: &Router, req: &TransactionRequest) -> (StatusCode, Value) {
+        let body = Body::from(serde_json::to_vec(req).unwrap());
+        call(app, "POST", "/tx", body).await
+    }
+
+    /// Polls `GET /tx/{id}` until the status is `expected`.
+    async fn wait_for_status(app: &Router, tx_id: u64, expected: Value) {
+        let mut last = Value::Null;
+        for _ in 0..100 {
+            let (code, body) = call(app, "GET", &format!("/tx/{}", tx_id), Body::empty()).await;
+            assert_eq!(code, StatusCode::OK);
+            if body["status"] == expected {
+                return;
+            }
+            last = body["status"].clone();
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("tx {} never got past {} to {}", tx_id, last, expected);
+    }
+
+    #[tokio::test]
+    async fn test_post_submit_and_get_status() {
+        let (app, event_tx, mut decisions) = start();
+
+        let (code, body) = post(&app, &request(1, 100)).await;
+        assert_eq!(code, StatusCode::ACCEPTED);
+        assert_eq!(body, json!({"tx_id": 1}));
+        let (code, _) = post(&app, &request(1, 100)).await;
+        assert_eq!(code, StatusCode::CONFLICT);
+
+        event_tx
+            .send(GasEvent::BaseFeeUpdate {
+                base_fee: 50,
+                timestamp: 0,
+            })
+            .await
+            .unwrap();
+        loop {
+            match decisions.recv().await.unwrap() {
+                SchedulerDecision::Submit { tx_id, nonce, .. } => {
+                    assert_eq!((tx_id, nonce), (1, 0));
+                    break;
+                }
+                _ => continue,
+            }
+        }
+        wait_for_status(&app, 1, json!({"Submitted": {"nonce": 0, "gas_price": 52}})).await;
+
+        let (code, body) = call(&app, "DELETE", "/tx/1", Body::empty()).await;
+        assert_eq!(code, StatusCode::CONFLICT);
+        assert_eq!(body["reason"], "tx 1 is already submitted");
+    }
+
+    #[tokio::test]
+    async fn test_cancel_pending_request() {
+        let (app, event_tx, _decisions) = start();
+        event_tx
+            .send(GasEvent::BaseFeeUpdate {
+                base_fee: 50,
+                timestamp: 0,
+            })
+            .await
+            .unwrap();
+
+        // capped under the base fee, so it waits
+        assert_eq!(post(&app, &request(2, 40)).await.0, StatusCode::ACCEPTED);
+        wait_for_status(&app, 2, json!({"Deferred": "fee above cap"})).await;
+        let (code, _) = call(&app, "DELETE", "/tx/2", Body::empty()).await;
+        assert_eq!(code, StatusCode::ACCEPTED);
+        wait_for_status(&app, 2, json!("Cancelled")).await;
+
+        let (code, _) = call(&app, "DELETE", "/tx/3", Body::empty()).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+        let (code, _) = call(&app, "GET", "/tx/3", Body::empty()).await;
+        assert_eq!(code, StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn test_a_full_rejecting_queue_answers_503() {
+        let (handle, mut req_rx, _cmd_rx) =
+            SchedulerHandle::channel_with_policy(1, BackpressurePolicy::Reject);
+        let stats = handle.request_stats();
+        let app = router(handle);
+
+        let (code, _) = post(&app, &request(1, 100)).await;
+        assert_eq!(code, StatusCode::ACCEPTED);
+        let (code, body) = post(&app, &request(2, 100)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["reason"], "scheduler's request queue is full");
+        assert_eq!(stats.rejected(), 1);
+
+        // the rejected tx isn't tracked, so it can be sent again once there's room
+        req_rx.recv().await.unwrap();
+        let (code, _) = post(&app, &request(2, 100)).await;
+        assert_eq!(code, StatusCode::ACCEPTED);
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests_are_refused() {
+        let (app, _event_tx, _decisions) = start();
+
+        let mut invalid = request(4, 100);
+        invalid.gas_limit = 20_000;
+        let (code, body) = post(&app, &invalid).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert_eq!(
+            body["error"],
+            json!({"GasLimitBelowIntrinsic": {"gas_limit": 20_000, "intrinsic": 21_000}})
+        );
+        assert_eq!(body["reason"], invalid.validate(0).unwrap_err().to_string());
+
+        let (code, body) = call(&app, "POST", "/tx", Body::from("{\"id\": 5")).await;
+        assert_eq!(code, StatusCode::BAD_REQUEST);
+        assert!(body["reason"].is_string());
+
+        let mut huge = request(6, 100);
+        huge.data = vec![0; MAX_BODY_BYTES];
+        let (code, _) = post(&app, &huge).await;
+        assert_eq!(code, StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_health_turns_unhealthy_when_the_model_goes_stale() {
+        let (handle, _req_rx, _cmd_rx) = SchedulerHandle::channel(10);
+        let scheduler = Arc::new(Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(NonceManager::new()),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![],
+        ));
+        let thresholds = HealthThresholds {
+            model_stale_after: Duration::from_secs(30),
+            ..HealthThresholds::default()
+        };
+        let monitor = HealthMonitor::new(thresholds)
+            .with_scheduler(&scheduler)
+            .with_handle(&handle);
+        let app = router(handle).merge(health_router(Arc::new(monitor)));
+
+        scheduler.model().update(Wei(40));
+        let (code, body) = call(&app, "GET", "/health", Body::empty()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body, json!({"status": "healthy", "problems": []}));
+
+        tokio::time::advance(Duration::from_secs(31)).await;
+        let (code, body) = call(&app, "GET", "/health", Body::empty()).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unhealthy");
+        assert_eq!(body["problems"], json!(["no base fee for 31s"]));
+
+        let (code, body) = call(&app, "GET", "/status", Body::empty()).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["model"]["stale"], true);
+        assert_eq!(body["model"]["samples"], 1);
+        assert_eq!(body["queues"], json!({"pending": 0, "submitted": 0}));
+        assert_eq!(
+            body["channels"][0],
+            json!({"name": "requests", "depth": 0, "capacity": 10})
+        );
+
+        // a base fee makes it healthy again
+        scheduler.model().update(Wei(41));
+        let (code, _) = call(&app, "GET", "/health", Body::empty()).await;
+        assert_eq!(code, StatusCode::OK);
+    }
+}
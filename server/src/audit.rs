@@ -0,0 +1,657 @@
+use crate::events::{DecisionRecord, GasEvent, SchedulerDecision, TransactionRequest};
+use crate::scheduler::Submission;
+use crate::sink::{DecisionSink, SinkError};
+use async_trait::async_trait;
+use serde::de::{self, MapAccess, Visitor};
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// Lines the writer thread writes before syncing, at most.
+const BATCH: usize = 256;
+
+/// One line of the log: `{"type": ..., "timestamp_ms": ..., "payload": ...}`,
+/// with `type` ahead of the payload it names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    /// When the entry was queued.
+    pub timestamp_ms: u64,
+    pub payload: AuditPayload,
+}
+
+/// What an entry records.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditPayload {
+    Event(GasEvent),
+    Request(Box<TransactionRequest>),
+    Decision(Box<DecisionRecord>),
+}
+
+impl AuditPayload {
+    const KINDS: &[&str] = &["event", "request", "decision"];
+
+    /// The entry's `type`.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuditPayload::Event(_) => "event",
+            AuditPayload::Request(_) => "request",
+            AuditPayload::Decision(_) => "decision",
+        }
+    }
+}
+
+// By hand rather than with a flattened tagged enum, which would buffer payloads
+// in a form that can't hold the u128 amounts in them
+impl Serialize for AuditEntry {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(3))?;
+        map.serialize_entry("type", self.payload.kind())?;
+        map.serialize_entry("timestamp_ms", &self.timestamp_ms)?;
+        match &self.payload {
+            AuditPayload::Event(event) => map.serialize_entry("payload", event)?,
+            AuditPayload::Request(req) => map.serialize_entry("payload", req)?,
+            AuditPayload::Decision(record) => map.serialize_entry("payload", record)?,
+        }
+        map.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for AuditEntry {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntryVisitor;
+
+        impl<'de> Visitor<'de> for EntryVisitor {
+            type Value = AuditEntry;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                write!(f, "an audit entry")
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<AuditEntry, A::Error> {
+                let (mut kind, mut timestamp_ms, mut payload) = (None::<String>, None, None);
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "type" => kind = Some(map.next_value()?),
+                        "timestamp_ms" => timestamp_ms = Some(map.next_value()?),
+                        "payload" => {
+                            payload = Some(match kind.as_deref() {
+                                Some("event") => AuditPayload::Event(map.next_value()?),
+                                Some("request") => AuditPayload::Request(map.next_value()?),
+                                Some("decision") => AuditPayload::Decision(map.next_value()?),
+                                Some(other) => {
+                                    return Err(de::Error::unknown_variant(
+                                        other,
+                                        AuditPayload::KINDS,
+                                    ));
+                                }
+                                None => {
+                                    return Err(de::Error::custom("`type` must precede `payload`"));
+                                }
+                            })
+                        }
+                        _ => {
+                            map.next_value::<de::IgnoredAny>()?;
+                        }
+                    }
+                }
+                Ok(AuditEntry {
+                    timestamp_ms: timestamp_ms
+                        .ok_or_else(|| de::Error::missing_field("timestamp_ms"))?,
+                    payload: payload.ok_or_else(|| de::Error::missing_field("payload"))?,
+                })
+            }
+        }
+
+        deserializer.deserialize_map(EntryVisitor)
+    }
+}
+
+/// When the writer makes the OS put what it wrote on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FsyncPolicy {
+    /// After every batch of lines, before the next is taken.
+    Always,
+    /// At most this often, and when rotating or flushing.
+    Interval(Duration),
+    /// Only when rotating or flushing; otherwise the OS decides.
+    #[default]
+    Never,
+}
+
+/// What happens to an entry the writer's queue has no room for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait for room, so a stalled disk slows whatever is writing. The
+    /// scheduler's sink timeout still bounds how long a decision waits.
+    #[default]
+    Wait,
+    /// Leave the entry out of the log and count it in `AuditStats::dropped`.
+    Drop,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AuditLogConfig {
+    /// Entries queued for the writer, at most.
+    pub capacity: usize,
+    /// Start a new file before one grows past this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Start a new file for the first entry after one has been open this long.
+    pub max_age: Option<Duration>,
+    pub fsync: FsyncPolicy,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for AuditLogConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            max_bytes: None,
+            max_age: None,
+            fsync: FsyncPolicy::default(),
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// What the log made of the entries it was given. Clones see the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct AuditStats {
+    inner: Arc<AuditCounters>,
+}
+
+#[derive(Debug, Default)]
+struct AuditCounters {
+    written: AtomicU64,
+    dropped: AtomicU64,
+    failed: AtomicU64,
+    rotations: AtomicU64,
+}
+
+impl AuditStats {
+    /// Entries written to a file.
+    pub fn written(&self) -> u64 {
+        self.inner.written.load(Ordering::Relaxed)
+    }
+
+    /// Entries left out because the queue was full, under `OverflowPolicy::Drop`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Entries lost to encoding or write errors.
+    pub fn failed(&self) -> u64 {
+        self.inner.failed.load(Ordering::Relaxed)
+    }
+
+    /// Files closed to start a new one.
+    pub fn rotations(&self) -> u64 {
+        self.inner.rotations.load(Ordering::Relaxed)
+    }
+}
+
+/// Appends every event, request and decision it's given to a file as JSON
+/// lines, from a thread of its own. Full files are renamed to `<path>.1`,
+/// `<path>.2` and so on, oldest first, and never touched again. The thread ends
+/// once every clone is dropped and the queue is drained.
+#[derive(Clone)]
+pub struct AuditLog {
+    tx: mpsc::Sender<Write>,
+    overflow: OverflowPolicy,
+    stats: AuditStats,
+}
+
+enum Write {
+    Entry(Box<AuditEntry>),
+    Flush(oneshot::Sender<()>),
+}
+
+impl AuditLog {
+    /// Opens `path` for appending, creating it if needed.
+    pub fn open(path: impl Into<PathBuf>, config: AuditLogConfig) -> std::io::Result<Self> {
+        let stats = AuditStats::default();
+        let mut writer = Writer::open(path.into(), &config, stats.clone())?;
+        let (tx, rx) = mpsc::channel(config.capacity);
+        std::thread::Builder::new()
+            .name("audit-writer".into())
+            .spawn(move || writer.write_queued(rx))?;
+        Ok(Self {
+            tx,
+            overflow: config.overflow,
+            stats,
+        })
+    }
+
+    pub fn stats(&self) -> AuditStats {
+        self.stats.clone()
+    }
+
+    /// Queues `payload`, stamped with the current time, as the overflow policy
+    /// says.
+    pub async fn record(&self, payload: AuditPayload) -> Result<(), SinkError> {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let write = Write::Entry(Box::new(AuditEntry {
+            timestamp_ms,
+            payload,
+        }));
+        match self.overflow {
+            OverflowPolicy::Wait => self.tx.send(write).await.map_err(|_| SinkError::Closed),
+            OverflowPolicy::Drop => self.tx.try_send(write).map_err(|e| match e {
+                TrySendError::Full(_) => {
+                    self.stats.inner.dropped.fetch_add(1, Ordering::Relaxed);
+                    SinkError::Full
+                }
+                TrySendError::Closed(_) => SinkError::Closed,
+            }),
+        }
+    }
+
+    /// Records every event on its way from `events` to the scheduler; pass the
+    /// returned receiver on in place of `events`.
+    pub fn track_events(&self, events: mpsc::Receiver<GasEvent>) -> mpsc::Receiver<GasEvent> {
+        self.tap(events, |event| AuditPayload::Event(event.clone()))
+    }
+
+    /// As `track_events`, for requests.
+    pub fn track_submissions(
+        &self,
+        submissions: mpsc::Receiver<Submission>,
+    ) -> mpsc::Receiver<Submission> {
+        self.tap(submissions, |submission| {
+            AuditPayload::Request(Box::new(submission.req.clone()))
+        })
+    }
+
+    fn tap<T: Send + 'static>(
+        &self,
+        mut items: mpsc::Receiver<T>,
+        payload: fn(&T) -> AuditPayload,
+    ) -> mpsc::Receiver<T> {
+        let (forward, forwarded) = mpsc::channel(items.max_capacity());
+        let log = self.clone();
+        tokio::spawn(async move {
+            while let Some(item) = items.recv().await {
+                // drops are already counted
+                if let Err(e @ SinkError::Closed) = log.record(payload(&item)).await {
+                    warn!("input not audited: {}", e);
+                }
+                if forward.send(item).await.is_err() {
+                    return;
+                }
+            }
+        });
+        forwarded
+    }
+
+    /// Waits until everything queued so far is written and synced, or failed to be.
+    pub async fn flush(&self) -> Result<(), SinkError> {
+        let (done, flushed) = oneshot::channel();
+        self.tx
+            .send(Write::Flush(done))
+            .await
+            .map_err(|_| SinkError::Closed)?;
+        flushed.await.map_err(|_| SinkError::Closed)
+    }
+}
+
+#[async_trait]
+impl DecisionSink for AuditLog {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.deliver_record(decision.into()).await
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.record(AuditPayload::Decision(Box::new(record))).await
+    }
+
+    fn name(&self) -> &str {
+        "audit"
+    }
+}
+
+/// The file being written, on the writer thread.
+struct Writer {
+    path: PathBuf,
+    file: BufWriter<File>,
+    /// Bytes in the current file.
+    size: u64,
+    opened_at: Instant,
+    synced_at: Instant,
+    /// Number the next rotated file gets.
+    next_index: u64,
+    max_bytes: Option<u64>,
+    max_age: Option<Duration>,
+    fsync: FsyncPolicy,
+    stats: AuditStats,
+}
+
+impl Writer {
+    fn open(path: PathBuf, config: &AuditLogConfig, stats: AuditStats) -> std::io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        let next_index = (1..)
+            .find(|&index| !rotated_path(&path, index).exists())
+            .unwrap_or(1);
+        Ok(Self {
+            path,
+            file: BufWriter::new(file),
+            size,
+            opened_at: Instant::now(),
+            synced_at: Instant::now(),
+            next_index,
+            max_bytes: config.max_bytes,
+            max_age: config.max_age,
+            fsync: config.fsync,
+            stats,
+        })
+    }
+
+    fn write_queued(&mut self, mut rx: mpsc::Receiver<Write>) {
+        while let Some(first) = rx.blocking_recv() {
+            let mut batch = vec![first];
+            while batch.len() < BATCH {
+                match rx.try_recv() {
+                    Ok(write) => batch.push(write),
+                    Err(_) => break,
+                }
+            }
+            let mut flushed = Vec::new();
+            for write in batch {
+                match write {
+                    Write::Entry(entry) => self.write(&entry),
+                    Write::Flush(done) => flushed.push(done),
+                }
+            }
+            let sync = !flushed.is_empty()
+                || match self.fsync {
+                    FsyncPolicy::Always => true,
+                    FsyncPolicy::Interval(every) => self.synced_at.elapsed() >= every,
+                    FsyncPolicy::Never => false,
+                };
+            if let Err(e) = self.flush(sync) {
+                warn!("audit log {}: {}", self.path.display(), e);
+            }
+            for done in flushed {
+                let _ = done.send(());
+            }
+        }
+        if let Err(e) = self.flush(true) {
+            warn!("audit log {}: {}", self.path.display(), e);
+        }
+    }
+
+    fn write(&mut self, entry: &AuditEntry) {
+        let mut line = match serde_json::to_vec(entry) {
+            Ok(line) => line,
+            Err(e) => {
+                warn!("audit entry not encoded: {}", e);
+                self.stats.inner.failed.fetch_add(1, Ordering::Relaxed);
+                return;
+            }
+        };
+        line.push(b'\n');
+        let full = self
+            .max_bytes
+            .is_some_and(|max| self.size + line.len() as u64 > max);
+        let old = self
+            .max_age
+            .is_some_and(|age| self.opened_at.elapsed() >= age);
+        if self.size > 0
+            && (full || old)
+            && let Err(e) = self.rotate()
+        {
+            warn!("audit log {} not rotated: {}", self.path.display(), e);
+        }
+        match self.file.write_all(&line) {
+            Ok(()) => {
+                self.size += line.len() as u64;
+                self.stats.inner.written.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(e) => {
+                warn!("audit entry not written: {}", e);
+                self.stats.inner.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Closes the current file under the next rotated name and starts afresh.
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.flush(true)?;
+        std::fs::rename(&self.path, rotated_path(&self.path, self.next_index))?;
+        self.next_index += 1;
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.file = BufWriter::new(file);
+        self.size = 0;
+        self.opened_at = Instant::now();
+        self.stats.inner.rotations.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn flush(&mut self, sync: bool) -> std::io::Result<()> {
+        self.file.flush()?;
+        if sync {
+            self.file.get_ref().sync_data()?;
+            self.synced_at = Instant::now();
+        }
+        Ok(())
+    }
+}
+
+fn rotated_path(path: &Path, index: u64) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+    name.into()
+}
+
+/// The files of the log at `path`, oldest first: the rotated ones, then the
+/// one being written.
+pub fn log_files(path: impl AsRef<Path>) -> Vec<PathBuf> {
+    let path = path.as_ref();
+    (1..)
+        .map(|index| rotated_path(path, index))
+        .take_while(|rotated| rotated.exists())
+        .chain(path.exists().then(|| path.to_path_buf()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{FeeMode, SubmissionPrivacy, Urgency};
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::{NonceAllocator, NonceManager};
+    use crate::scheduler::{Scheduler, SchedulerConfig, SchedulerHandle};
+    use crate::sink::ChannelSink;
+    use crate::units::Wei;
+    use alloy_primitives::Address;
+
+    fn request(id: u64) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    /// A fresh log path of its own for each test.
+    fn log_path(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gas_saver_audit_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{}.jsonl", test));
+        for file in log_files(&path) {
+            std::fs::remove_file(file).unwrap();
+        }
+        path
+    }
+
+    fn read_entries(path: &Path) -> Vec<Vec<AuditEntry>> {
+        log_files(path)
+            .iter()
+            .map(|file| {
+                let entries = std::fs::read_to_string(file).unwrap();
+                let entries = entries
+                    .lines()
+                    .map(|line| serde_json::from_str(line).unwrap());
+                entries.collect()
+            })
+            .collect()
+    }
+
+    fn drop_decision(tx_id: u64) -> SchedulerDecision {
+        SchedulerDecision::Drop {
+            tx_id,
+            reason: "x".into(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotates_at_the_size_boundary() {
+        let path = log_path("size");
+        // every line is as long as this one, timestamps having as many digits
+        let line = AuditEntry {
+            timestamp_ms: 1_700_000_000_000,
+            payload: AuditPayload::Decision(Box::new(drop_decision(1).into())),
+        };
+        let line_len = serde_json::to_vec(&line).unwrap().len() as u64 + 1;
+        let config = AuditLogConfig {
+            max_bytes: Some(2 * line_len),
+            ..AuditLogConfig::default()
+        };
+        let log = AuditLog::open(&path, config).unwrap();
+
+        for tx_id in 1..=5 {
+            log.deliver(drop_decision(tx_id)).await.unwrap();
+        }
+        log.flush().await.unwrap();
+
+        let files = log_files(&path);
+        assert_eq!(files[2], path);
+        let sizes: Vec<_> = (files.iter())
+            .map(|file| std::fs::metadata(file).unwrap().len())
+            .collect();
+        assert_eq!(sizes, [2 * line_len, 2 * line_len, line_len]);
+        let tx_ids: Vec<Vec<_>> = read_entries(&path)
+            .into_iter()
+            .map(|entries| {
+                (entries.into_iter())
+                    .map(|entry| match entry.payload {
+                        AuditPayload::Decision(record) => record.decision.tx_id().unwrap(),
+                        payload => panic!("unexpected entry {:?}", payload),
+                    })
+                    .collect()
+            })
+            .collect();
+        assert_eq!(tx_ids, [vec![1, 2], vec![3, 4], vec![5]]);
+        let stats = log.stats();
+        assert_eq!((stats.written(), stats.rotations()), (5, 2));
+        assert_eq!((stats.dropped(), stats.failed()), (0, 0));
+
+        // a reopened log appends, and rotates on past the files already there
+        drop(log);
+        let log = AuditLog::open(&path, config).unwrap();
+        log.deliver(drop_decision(6)).await.unwrap();
+        log.deliver(drop_decision(7)).await.unwrap();
+        log.flush().await.unwrap();
+        let files = log_files(&path);
+        assert_eq!(files.len(), 4);
+        assert_eq!(read_entries(&path)[2].len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_every_decision_of_a_run_is_logged_once() {
+        let path = log_path("run");
+        let config = AuditLogConfig {
+            max_bytes: Some(2048),
+            fsync: FsyncPolicy::Always,
+            ..AuditLogConfig::default()
+        };
+        let log = AuditLog::open(&path, config).unwrap();
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let (decision_tx, mut decision_rx) = mpsc::channel::<DecisionRecord>(100);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Arc::new(Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![
+                Box::new(log.clone()),
+                Box::new(ChannelSink::new(decision_tx)),
+            ],
+        ));
+        let scheduler_task = tokio::spawn(scheduler.run(
+            log.track_events(event_rx),
+            log.track_submissions(req_rx),
+            cmd_rx,
+        ));
+
+        let event = GasEvent::BaseFeeUpdate {
+            base_fee: 40,
+            timestamp: 0,
+        };
+        event_tx.send(event.clone()).await.unwrap();
+        for id in 1..=10 {
+            handle.submit(request(id)).await.unwrap();
+        }
+        handle.submit(request(3)).await.unwrap();
+        drop((event_tx, handle));
+        scheduler_task.await.unwrap();
+        let mut decided = Vec::new();
+        while let Some(record) = decision_rx.recv().await {
+            decided.push(record);
+        }
+        log.flush().await.unwrap();
+
+        let files = read_entries(&path);
+        assert!(files.len() > 1);
+        let entries: Vec<_> = files.into_iter().flatten().collect();
+        let logged: Vec<_> = (entries.iter())
+            .filter_map(|entry| match &entry.payload {
+                AuditPayload::Decision(record) => Some(*record.clone()),
+                _ => None,
+            })
+            .collect();
+        // the duplicate of 3 is rejected, and that is decided and logged too
+        assert_eq!(decided.len(), 11);
+        assert_eq!(logged, decided);
+        let requests = (entries.iter())
+            .filter(|entry| matches!(entry.payload, AuditPayload::Request(_)))
+            .count();
+        assert_eq!(requests, 11);
+        assert_eq!(entries[0].payload, AuditPayload::Event(event));
+        assert!(entries.iter().all(|entry| entry.timestamp_ms > 0));
+        assert_eq!(log.stats().written(), entries.len() as u64);
+    }
+}
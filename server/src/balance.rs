@@ -0,0 +1,56 @@
+use async_trait::async_trait;
+use parking_lot::RwLock;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BalanceError {
+    /// The backing node or service could not be reached.
+    Unavailable(String),
+    Other(String),
+}
+
+impl std::fmt::Display for BalanceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BalanceError::Unavailable(msg) => write!(f, "balance source unavailable: {}", msg),
+            BalanceError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for BalanceError {}
+
+/// Where the scheduler learns how much a sender can spend, in wei.
+#[async_trait]
+pub trait BalanceProvider: Send + Sync {
+    async fn balance_of(&self, address: &[u8; 20]) -> Result<u128, BalanceError>;
+}
+
+/// Fixed in-memory balances; unknown addresses hold nothing.
+#[derive(Default)]
+pub struct StaticBalances {
+    balances: RwLock<HashMap<[u8; 20], u128>>,
+}
+
+impl StaticBalances {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, address: [u8; 20], balance: u128) {
+        self.balances.write().insert(address, balance);
+    }
+
+    pub fn deposit(&self, address: [u8; 20], amount: u128) {
+        let mut balances = self.balances.write();
+        let balance = balances.entry(address).or_insert(0);
+        *balance = balance.saturating_add(amount);
+    }
+}
+
+#[async_trait]
+impl BalanceProvider for StaticBalances {
+    async fn balance_of(&self, address: &[u8; 20]) -> Result<u128, BalanceError> {
+        Ok(self.balances.read().get(address).copied().unwrap_or(0))
+    }
+}
@@ -0,0 +1,490 @@
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::{Notify, mpsc};
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+
+/// What a send does when the channel is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy {
+    /// Wait for room; the sender slows down to the receiver's pace.
+    #[default]
+    Block,
+    /// Make room by dropping the oldest message, counted in `ChannelStats`. For
+    /// data where only the newest matters, like base fees.
+    DropOldest,
+    /// Hand the message back with `SendError::Full`, for senders that can tell
+    /// their own caller to retry.
+    Reject,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SendError<T> {
+    /// The channel was full and its policy is `Reject`.
+    Full(T),
+    /// The receiver is gone.
+    Closed(T),
+}
+
+impl<T> SendError<T> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Full(value) | SendError::Closed(value) => value,
+        }
+    }
+}
+
+impl<T> std::fmt::Display for SendError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Full(_) => write!(f, "channel is full"),
+            SendError::Closed(_) => write!(f, "channel is closed"),
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::error::Error for SendError<T> {}
+
+/// Counters of a channel's senders, shared with whoever reports on them. Clones
+/// see the same counts, and don't keep the channel open.
+#[derive(Clone)]
+pub struct ChannelStats {
+    inner: Arc<Counters>,
+}
+
+struct Counters {
+    capacity: usize,
+    /// Messages queued, or None once the channel is closed.
+    depth: Box<dyn Fn() -> Option<usize> + Send + Sync>,
+    sent: AtomicU64,
+    dropped: AtomicU64,
+    rejected: AtomicU64,
+    high_water: AtomicUsize,
+    /// Sends that found the channel full and waited.
+    blocked: AtomicU64,
+    wait_nanos: AtomicU64,
+    max_wait_nanos: AtomicU64,
+}
+
+impl ChannelStats {
+    fn new(capacity: usize, depth: Box<dyn Fn() -> Option<usize> + Send + Sync>) -> Self {
+        Self {
+            inner: Arc::new(Counters {
+                capacity,
+                depth,
+                sent: AtomicU64::new(0),
+                dropped: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                high_water: AtomicUsize::new(0),
+                blocked: AtomicU64::new(0),
+                wait_nanos: AtomicU64::new(0),
+                max_wait_nanos: AtomicU64::new(0),
+            }),
+        }
+    }
+
+    /// Depth alone, for a plain channel whose sends go around these counters.
+    pub fn of<T: Send + 'static>(tx: &mpsc::Sender<T>) -> Self {
+        let weak = tx.downgrade();
+        Self::new(
+            tx.max_capacity(),
+            Box::new(move || {
+                let tx = weak.upgrade()?;
+                Some(tx.max_capacity() - tx.capacity())
+            }),
+        )
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.inner.capacity
+    }
+
+    /// Messages queued now; None once the receiver or every sender is gone.
+    pub fn depth(&self) -> Option<usize> {
+        (self.inner.depth)()
+    }
+
+    /// The most messages seen queued after a send.
+    pub fn high_water(&self) -> usize {
+        self.inner.high_water.load(Ordering::Relaxed)
+    }
+
+    /// Messages taken by the channel, dropped ones included.
+    pub fn sent(&self) -> u64 {
+        self.inner.sent.load(Ordering::Relaxed)
+    }
+
+    /// Messages dropped to make room under `DropOldest`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Sends refused under `Reject`.
+    pub fn rejected(&self) -> u64 {
+        self.inner.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Sends that found the channel full and waited for room.
+    pub fn blocked_sends(&self) -> u64 {
+        self.inner.blocked.load(Ordering::Relaxed)
+    }
+
+    /// Time senders spent waiting for room, in total.
+    pub fn send_wait(&self) -> Duration {
+        Duration::from_nanos(self.inner.wait_nanos.load(Ordering::Relaxed))
+    }
+
+    /// The longest a single send waited for room.
+    pub fn max_send_wait(&self) -> Duration {
+        Duration::from_nanos(self.inner.max_wait_nanos.load(Ordering::Relaxed))
+    }
+
+    fn record_send(&self, depth: usize) {
+        self.inner.sent.fetch_add(1, Ordering::Relaxed);
+        self.inner.high_water.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn record_wait(&self, waited: Duration) {
+        let nanos = waited.as_nanos().min(u64::MAX as u128) as u64;
+        self.inner.blocked.fetch_add(1, Ordering::Relaxed);
+        self.inner.wait_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.inner
+            .max_wait_nanos
+            .fetch_max(nanos, Ordering::Relaxed);
+    }
+}
+
+impl std::fmt::Debug for ChannelStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ChannelStats")
+            .field("capacity", &self.capacity())
+            .field("depth", &self.depth())
+            .field("high_water", &self.high_water())
+            .field("sent", &self.sent())
+            .field("dropped", &self.dropped())
+            .field("rejected", &self.rejected())
+            .field("send_wait", &self.send_wait())
+            .finish()
+    }
+}
+
+/// The sending half of an `instrumented_channel`: sends follow the channel's
+/// `BackpressurePolicy` and are counted in its `ChannelStats`.
+pub struct InstrumentedSender<T> {
+    tx: Tx<T>,
+    stats: ChannelStats,
+}
+
+enum Tx<T> {
+    /// `Block` and `Reject` send straight into the receiver's channel.
+    Direct(mpsc::Sender<T>, BackpressurePolicy),
+    /// `DropOldest` queues in a ring a relay task drains into it.
+    Ring(Arc<Ring<T>>),
+}
+
+struct Ring<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    /// Wakes the relay for a new message or the last sender going.
+    pushed: Notify,
+    senders: AtomicUsize,
+    /// Cancelled once the receiver is gone.
+    closed: CancellationToken,
+}
+
+/// A bounded channel of `capacity` whose sends follow `policy`. The receiving
+/// half is a plain `mpsc::Receiver`, so anything that reads one can read this.
+///
+/// With `DropOldest` a relay task moves messages to the receiver, so this must
+/// be called within a tokio runtime.
+pub fn instrumented_channel<T: Send + 'static>(
+    capacity: usize,
+    policy: BackpressurePolicy,
+) -> (InstrumentedSender<T>, mpsc::Receiver<T>) {
+    match policy {
+        BackpressurePolicy::Block | BackpressurePolicy::Reject => {
+            let (tx, rx) = mpsc::channel(capacity);
+            let stats = ChannelStats::of(&tx);
+            (
+                InstrumentedSender {
+                    tx: Tx::Direct(tx, policy),
+                    stats,
+                },
+                rx,
+            )
+        }
+        BackpressurePolicy::DropOldest => {
+            // the ring holds the backlog; one message at a time waits on the receiver
+            let (out, rx) = mpsc::channel(1);
+            let ring = Arc::new(Ring {
+                queue: Mutex::new(VecDeque::with_capacity(capacity)),
+                capacity,
+                pushed: Notify::new(),
+                senders: AtomicUsize::new(1),
+                closed: CancellationToken::new(),
+            });
+            let weak = Arc::downgrade(&ring);
+            let stats = ChannelStats::new(
+                capacity,
+                Box::new(move || {
+                    let ring = weak.upgrade()?;
+                    (!ring.closed.is_cancelled()).then(|| ring.queue.lock().len())
+                }),
+            );
+            tokio::spawn(relay(ring.clone(), out));
+            (
+                InstrumentedSender {
+                    tx: Tx::Ring(ring),
+                    stats,
+                },
+                rx,
+            )
+        }
+    }
+}
+
+/// Moves messages from `ring` to `out` in order, until every sender is gone and
+/// the ring is empty, or the receiver is.
+async fn relay<T>(ring: Arc<Ring<T>>, out: mpsc::Sender<T>) {
+    loop {
+        if ring.queue.lock().is_empty() {
+            if ring.senders.load(Ordering::Acquire) == 0 {
+                return;
+            }
+            tokio::select! {
+                _ = ring.pushed.notified() => continue,
+                _ = out.closed() => break,
+            }
+        }
+        // the message stays in the ring, where it can still be dropped, until
+        // the receiver has room for it
+        let Ok(permit) = out.reserve().await else {
+            break;
+        };
+        if let Some(value) = ring.queue.lock().pop_front() {
+            permit.send(value);
+        }
+    }
+    ring.closed.cancel();
+    ring.queue.lock().clear();
+}
+
+impl<T> InstrumentedSender<T> {
+    /// Sends `value` by the channel's policy: waits for room under `Block`,
+    /// drops the oldest message under `DropOldest`, and fails with
+    /// `SendError::Full` under `Reject`.
+    pub async fn send(&self, value: T) -> Result<(), SendError<T>> {
+        match &self.tx {
+            Tx::Direct(tx, policy) => {
+                self.send_direct(tx, value, *policy == BackpressurePolicy::Reject)
+                    .await
+            }
+            Tx::Ring(ring) => self.push(ring, value),
+        }
+    }
+
+    /// As `send`, but waits for room under `Reject` too; for messages the
+    /// sender has no one to hand back to.
+    pub async fn send_waiting(&self, value: T) -> Result<(), SendError<T>> {
+        match &self.tx {
+            Tx::Direct(tx, _) => self.send_direct(tx, value, false).await,
+            Tx::Ring(ring) => self.push(ring, value),
+        }
+    }
+
+    async fn send_direct(
+        &self,
+        tx: &mpsc::Sender<T>,
+        value: T,
+        reject: bool,
+    ) -> Result<(), SendError<T>> {
+        let value = match tx.try_send(value) {
+            Ok(()) => {
+                self.sent_direct(tx);
+                return Ok(());
+            }
+            Err(mpsc::error::TrySendError::Closed(value)) => {
+                return Err(SendError::Closed(value));
+            }
+            Err(mpsc::error::TrySendError::Full(value)) => value,
+        };
+        if reject {
+            self.stats.inner.rejected.fetch_add(1, Ordering::Relaxed);
+            return Err(SendError::Full(value));
+        }
+        let started = Instant::now();
+        let sent = tx.send(value).await;
+        self.stats.record_wait(started.elapsed());
+        sent.map_err(|e| SendError::Closed(e.0))?;
+        self.sent_direct(tx);
+        Ok(())
+    }
+
+    fn push(&self, ring: &Ring<T>, value: T) -> Result<(), SendError<T>> {
+        if ring.closed.is_cancelled() {
+            return Err(SendError::Closed(value));
+        }
+        let depth = {
+            let mut queue = ring.queue.lock();
+            if queue.len() >= ring.capacity && queue.pop_front().is_some() {
+                self.stats.inner.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+            queue.push_back(value);
+            queue.len()
+        };
+        ring.pushed.notify_one();
+        self.stats.record_send(depth);
+        Ok(())
+    }
+
+    fn sent_direct(&self, tx: &mpsc::Sender<T>) {
+        self.stats.record_send(tx.max_capacity() - tx.capacity());
+    }
+
+    /// True once the receiver is gone.
+    pub fn is_closed(&self) -> bool {
+        match &self.tx {
+            Tx::Direct(tx, _) => tx.is_closed(),
+            Tx::Ring(ring) => ring.closed.is_cancelled(),
+        }
+    }
+
+    /// Waits for the receiver to go.
+    pub async fn closed(&self) {
+        match &self.tx {
+            Tx::Direct(tx, _) => tx.closed().await,
+            Tx::Ring(ring) => ring.closed.cancelled().await,
+        }
+    }
+
+    pub fn stats(&self) -> ChannelStats {
+        self.stats.clone()
+    }
+}
+
+impl<T> Clone for InstrumentedSender<T> {
+    fn clone(&self) -> Self {
+        let tx = match &self.tx {
+            Tx::Direct(tx, policy) => Tx::Direct(tx.clone(), *policy),
+            Tx::Ring(ring) => {
+                ring.senders.fetch_add(1, Ordering::AcqRel);
+                Tx::Ring(ring.clone())
+            }
+        };
+        Self {
+            tx,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+impl<T> Drop for InstrumentedSender<T> {
+    fn drop(&mut self) {
+        if let Tx::Ring(ring) = &self.tx
+            && ring.senders.fetch_sub(1, Ordering::AcqRel) == 1
+        {
+            // lets the relay finish once it has drained the ring
+            ring.pushed.notify_one();
+        }
+    }
+}
+
+/// A plain channel's sender, blocking when full; only depth and sends through
+/// this sender are counted.
+impl<T: Send + 'static> From<mpsc::Sender<T>> for InstrumentedSender<T> {
+    fn from(tx: mpsc::Sender<T>) -> Self {
+        let stats = ChannelStats::of(&tx);
+        Self {
+            tx: Tx::Direct(tx, BackpressurePolicy::Block),
+            stats,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_waits_for_room_and_counts_the_wait() {
+        let (tx, mut rx) = instrumented_channel(2, BackpressurePolicy::Block);
+        let stats = tx.stats();
+        tx.send(1).await.unwrap();
+        tx.send(2).await.unwrap();
+        assert_eq!(stats.depth(), Some(2));
+
+        let sender = tokio::spawn(async move { tx.send(3).await });
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(!sender.is_finished());
+        assert_eq!(rx.recv().await, Some(1));
+        sender.await.unwrap().unwrap();
+
+        assert_eq!(stats.sent(), 3);
+        assert_eq!(stats.high_water(), 2);
+        assert_eq!(stats.blocked_sends(), 1);
+        assert_eq!(stats.send_wait(), Duration::from_secs(5));
+        assert_eq!(stats.max_send_wait(), Duration::from_secs(5));
+        assert_eq!((rx.recv().await, rx.recv().await), (Some(2), Some(3)));
+        // the only sender went with the task
+        assert_eq!(rx.recv().await, None);
+        assert_eq!(stats.depth(), None);
+    }
+
+    #[tokio::test]
+    async fn test_drop_oldest_keeps_the_newest() {
+        let (tx, mut rx) = instrumented_channel(3, BackpressurePolicy::DropOldest);
+        let stats = tx.stats();
+        // the relay takes the first message out to wait on the receiver
+        tx.send(0).await.unwrap();
+        tokio::task::yield_now().await;
+        for n in 1..=10 {
+            tx.send(n).await.unwrap();
+        }
+        assert_eq!(stats.dropped(), 7);
+        assert_eq!(stats.high_water(), 3);
+        assert_eq!(stats.depth(), Some(3));
+        assert_eq!(stats.blocked_sends(), 0);
+
+        drop(tx);
+        let mut received = Vec::new();
+        while let Some(n) = rx.recv().await {
+            received.push(n);
+        }
+        assert_eq!(received, vec![0, 8, 9, 10]);
+        assert_eq!(stats.sent(), 11);
+
+        let (tx, rx) = instrumented_channel(3, BackpressurePolicy::DropOldest);
+        drop(rx);
+        tx.closed().await;
+        assert_eq!(tx.send(1).await, Err(SendError::Closed(1)));
+    }
+
+    #[tokio::test]
+    async fn test_reject_hands_the_message_back() {
+        let (tx, mut rx) = instrumented_channel(1, BackpressurePolicy::Reject);
+        tx.send("a").await.unwrap();
+        assert_eq!(tx.send("b").await, Err(SendError::Full("b")));
+        assert_eq!(tx.stats().rejected(), 1);
+        assert_eq!(rx.recv().await, Some("a"));
+        tx.send("c").await.unwrap();
+        // unless the sender would rather wait
+        let waiting = tokio::spawn({
+            let tx = tx.clone();
+            async move { tx.send_waiting("c2").await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        assert_eq!(rx.recv().await, Some("c"));
+        waiting.await.unwrap().unwrap();
+        assert_eq!(rx.recv().await, Some("c2"));
+
+        drop(rx);
+        assert!(tx.is_closed());
+        assert_eq!(tx.send("d").await, Err(SendError::Closed("d")));
+        assert_eq!(tx.stats().sent(), 3);
+        assert_eq!(tx.stats().blocked_sends(), 1);
+    }
+}
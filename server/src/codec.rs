@@ -0,0 +1,524 @@
+use crate::events::{GasEvent, SchedulerDecision};
+use borsh::{BorshDeserialize, BorshSerialize};
+use bytes::{Buf, BufMut, BytesMut};
+use std::marker::PhantomData;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::codec::{Decoder, Encoder};
+
+/// Largest payload accepted by default. Events and decisions are far smaller; a
+/// bigger length prefix means the stream is corrupt or not ours.
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+const LEN_PREFIX: usize = 4;
+
+/// Events and decisions sharing one stream.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum Message {
+    Event(GasEvent),
+    Decision(SchedulerDecision),
+}
+
+impl From<GasEvent> for Message {
+    fn from(event: GasEvent) -> Self {
+        Message::Event(event)
+    }
+}
+
+impl From<SchedulerDecision> for Message {
+    fn from(decision: SchedulerDecision) -> Self {
+        Message::Decision(decision)
+    }
+}
+
+#[derive(Debug)]
+pub enum FrameError {
+    Io(std::io::Error),
+    /// The length prefix is over the limit; nothing was buffered for it.
+    TooLarge {
+        len: usize,
+        max: usize,
+    },
+    /// The payload isn't a valid `T`, including one with bytes left over.
+    Malformed(std::io::Error),
+    /// The stream ended partway through a frame.
+    Truncated {
+        remaining: usize,
+    },
+}
+
+impl std::fmt::Display for FrameError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameError::Io(err) => write!(f, "io error: {}", err),
+            FrameError::TooLarge { len, max } => {
+                write!(f, "frame of {} bytes exceeds limit of {}", len, max)
+            }
+            FrameError::Malformed(err) => write!(f, "malformed frame: {}", err),
+            FrameError::Truncated { remaining } => {
+                write!(
+                    f,
+                    "stream ended with {} bytes of a partial frame",
+                    remaining
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<std::io::Error> for FrameError {
+    fn from(err: std::io::Error) -> Self {
+        FrameError::Io(err)
+    }
+}
+
+/// Writes each item as a little-endian u32 length and its Borsh encoding, which is
+/// also how Borsh writes a `Vec<u8>`.
+#[derive(Debug)]
+pub struct FrameEncoder<T> {
+    max_frame_len: usize,
+    _item: PhantomData<fn(&T)>,
+}
+
+impl<T> FrameEncoder<T> {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self {
+            max_frame_len,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for FrameEncoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BorshSerialize> Encoder<T> for FrameEncoder<T> {
+    type Error = FrameError;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), FrameError> {
+        let payload = borsh::to_vec(&item)?;
+        if payload.len() > self.max_frame_len {
+            return Err(FrameError::TooLarge {
+                len: payload.len(),
+                max: self.max_frame_len,
+            });
+        }
+        dst.reserve(LEN_PREFIX + payload.len());
+        dst.put_u32_le(payload.len() as u32);
+        dst.put_slice(&payload);
+        Ok(())
+    }
+}
+
+/// Reads frames written by `FrameEncoder`, waiting for more input on partial ones.
+#[derive(Debug)]
+pub struct FrameDecoder<T> {
+    max_frame_len: usize,
+    _item: PhantomData<fn() -> T>,
+}
+
+impl<T> FrameDecoder<T> {
+    pub fn new() -> Self {
+        Self::with_max_frame_len(MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_frame_len(max_frame_len: usize) -> Self {
+        Self {
+            max_frame_len,
+            _item: PhantomData,
+        }
+    }
+}
+
+impl<T> Default for FrameDecoder<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: BorshDeserialize> Decoder for FrameDecoder<T> {
+    type Item = T;
+    type Error = FrameError;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<T>, FrameError> {
+        if src.len() < LEN_PREFIX {
+            return Ok(None);
+        }
+        let len = u32::from_le_bytes(src[..LEN_PREFIX].try_into().unwrap()) as usize;
+        if len > self.max_frame_len {
+            return Err(FrameError::TooLarge {
+                len,
+                max: self.max_frame_len,
+            });
+        }
+        if src.len() < LEN_PREFIX + len {
+            src.reserve(LEN_PREFIX + len - src.len());
+            return Ok(None);
+        }
+        src.advance(LEN_PREFIX);
+        let payload = src.split_to(len);
+        T::try_from_slice(&payload)
+            .map(Some)
+            .map_err(FrameError::Malformed)
+    }
+
+    fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<T>, FrameError> {
+        match self.decode(src)? {
+            Some(item) => Ok(Some(item)),
+            None if src.is_empty() => Ok(None),
+            None => Err(FrameError::Truncated {
+                remaining: src.len(),
+            }),
+        }
+    }
+}
+
+/// Writes one frame to `writer`, for callers without a `Framed` stream.
+pub async fn write_event<W, T>(writer: &mut W, item: &T) -> Result<(), FrameError>
+where
+    W: AsyncWrite + Unpin,
+    T: BorshSerialize,
+{
+    let payload = borsh::to_vec(item)?;
+    if payload.len() > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge {
+            len: payload.len(),
+            max: MAX_FRAME_LEN,
+        });
+    }
+    writer
+        .write_all(&(payload.len() as u32).to_le_bytes())
+        .await?;
+    writer.write_all(&payload).await?;
+    Ok(())
+}
+
+/// Reads one frame from `reader`. `None` at a clean end of stream, i.e. between
+/// frames.
+pub async fn read_event<R, T>(reader: &mut R) -> Result<Option<T>, FrameError>
+where
+    R: AsyncRead + Unpin,
+    T: BorshDeserialize,
+{
+    let mut prefix = [0u8; LEN_PREFIX];
+    let mut filled = 0;
+    while filled < LEN_PREFIX {
+        match reader.read(&mut prefix[filled..]).await? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(FrameError::Truncated { remaining: filled }),
+            n => filled += n,
+        }
+    }
+    let len = u32::from_le_bytes(prefix) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLarge {
+            len,
+            max: MAX_FRAME_LEN,
+        });
+    }
+    let mut payload = vec![0; len];
+    let mut filled = 0;
+    while filled < len {
+        match reader.read(&mut payload[filled..]).await? {
+            0 => {
+                return Err(FrameError::Truncated {
+                    remaining: LEN_PREFIX + filled,
+                });
+            }
+            n => filled += n,
+        }
+    }
+    T::try_from_slice(&payload)
+        .map(Some)
+        .map_err(FrameError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{DeferReason, FeeMode, RetryHint, SubmissionPrivacy};
+    use crate::units::Wei;
+    use futures::{SinkExt, StreamExt};
+    use tokio_util::codec::{FramedRead, FramedWrite};
+
+    fn messages() -> Vec<Message> {
+        let events = vec![
+            GasEvent::BaseFeeUpdate {
+                base_fee: 30,
+                timestamp: 12,
+            },
+            GasEvent::MempoolTx {
+                tx_hash: [0x11; 32],
+                max_fee: 40,
+                max_priority_fee: 2,
+                gas_limit: 21_000,
+            },
+            GasEvent::NewBlock {
+                number: 7,
+                base_fee: 31,
+                gas_used: 15_000_000,
+                gas_limit: 30_000_000,
+                block_hash: [0x22; 32],
+                parent_hash: [0x21; 32],
+            },
+            GasEvent::TxConfirmed {
+                tx_hash: [0x33; 32],
+                block_number: 7,
+                effective_gas_price: 31,
+                gas_used: 21_000,
+            },
+            GasEvent::TxDropped {
+                tx_hash: [0x33; 32],
+                reason: "evicted".to_string(),
+            },
+            GasEvent::TxFailed {
+                tx_hash: [0x33; 32],
+                block_number: 8,
+            },
+            GasEvent::TxReplaced {
+                old_tx_hash: [0x33; 32],
+                new_tx_hash: [0x44; 32],
+                nonce: 3,
+            },
+            GasEvent::BlobBaseFeeUpdate { blob_base_fee: 1 },
+            GasEvent::FeeHistory {
+                oldest_block: 5,
+                base_fees: vec![30, 31, 29],
+                gas_used_ratios: vec![0.6, 0.25],
+                rewards: vec![vec![1, 2], vec![1, 3]],
+            },
+            GasEvent::OracleEstimate {
+                source: "etherscan".into(),
+                base_fee: 30,
+                priority_fee_fast: 3,
+                priority_fee_standard: 1,
+            },
+            GasEvent::L1BaseFeeUpdate { base_fee: 9 },
+        ];
+        let decisions = vec![
+            SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(1_092_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: Some(Wei(9)),
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Defer {
+                tx_id: 2,
+                reason: DeferReason::FeeAboveMax,
+                retry_hint: Some(RetryHint::WhenFeeBelow(Wei(30))),
+            },
+            SchedulerDecision::Reprice {
+                tx_id: 1,
+                old_nonce: 0,
+                new_gas_price: Wei(60),
+                max_priority_fee_per_gas: Wei(2),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Drop {
+                tx_id: 2,
+                reason: "expired".to_string(),
+            },
+            SchedulerDecision::Rejected {
+                tx_id: 3,
+                reason: "zero fee cap".to_string(),
+                error: Some(crate::events::ValidationError::ZeroMaxFee),
+            },
+            SchedulerDecision::ModeChanged { spike: true },
+            SchedulerDecision::NonceInitRequired {
+                chain_id: 1,
+                address: [0xAA; 20],
+            },
+            SchedulerDecision::NonceGapDetected {
+                chain_id: 1,
+                address: [0xAA; 20],
+                missing: vec![4, 5],
+            },
+            SchedulerDecision::FillNonceGap {
+                tx_id: 9,
+                chain_id: 1,
+                address: [0xAA; 20],
+                nonce: 4,
+                gas_price: Wei(60),
+                fee_mode: FeeMode::Eip1559,
+            },
+            SchedulerDecision::NonceConsumed { tx_id: 1, nonce: 0 },
+            SchedulerDecision::NonceConflict {
+                tx_id: 5,
+                chain_id: 1,
+                address: [0xAA; 20],
+                nonce: 2,
+                reason: "nonce already used".to_string(),
+            },
+            SchedulerDecision::Reorg {
+                fork_block: 6,
+                depth: 1,
+                unconfirmed: vec![1],
+            },
+            SchedulerDecision::MarketUpdate {
+                current_fee: Wei(31),
+                volatility: 0.5,
+                trend: -1.25,
+                spike: false,
+            },
+            SchedulerDecision::Confirmed {
+                tx_id: 1,
+                block_number: 7,
+                effective_gas_price: Wei(31),
+                realized_cost_wei: Wei(651_000),
+                savings_vs_max_fee_wei: Wei(441_000),
+                savings_vs_acceptance_wei: Wei(0),
+            },
+        ];
+        // interleave so neither kind arrives in one run
+        let mut messages = Vec::new();
+        let mut decisions = decisions.into_iter();
+        for event in events {
+            messages.push(Message::from(event));
+            messages.extend(decisions.next().map(Message::from));
+        }
+        messages.extend(decisions.map(Message::from));
+        messages
+    }
+
+    #[tokio::test]
+    async fn test_framed_round_trip_over_duplex() {
+        // a buffer smaller than most frames, so they arrive over several reads
+        let (client, server) = tokio::io::duplex(16);
+        let sent = messages();
+        let writer = {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                let mut framed = FramedWrite::new(client, FrameEncoder::new());
+                for message in sent {
+                    framed.send(message).await.unwrap();
+                }
+            })
+        };
+
+        let received: Vec<Message> = FramedRead::new(server, FrameDecoder::new())
+            .map(Result::unwrap)
+            .collect()
+            .await;
+        writer.await.unwrap();
+        assert_eq!(received, sent);
+    }
+
+    #[test]
+    fn test_decoder_waits_for_split_frame() {
+        let mut encoded = BytesMut::new();
+        let mut encoder = FrameEncoder::new();
+        for message in messages().into_iter().take(2) {
+            encoder.encode(message, &mut encoded).unwrap();
+        }
+
+        let mut decoder = FrameDecoder::<Message>::new();
+        let mut buf = BytesMut::new();
+        let mut decoded = Vec::new();
+        for byte in encoded {
+            buf.put_u8(byte);
+            if let Some(message) = decoder.decode(&mut buf).unwrap() {
+                decoded.push(message);
+            }
+        }
+        assert!(buf.is_empty());
+        assert_eq!(decoded, messages()[..2]);
+    }
+
+    #[test]
+    fn test_oversized_frame_is_refused() {
+        let mut decoder = FrameDecoder::<Message>::with_max_frame_len(64);
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(u32::MAX);
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(FrameError::TooLarge {
+                len,
+                max: 64,
+            }) if len == u32::MAX as usize
+        ));
+        assert!(buf.capacity() < 64, "nothing reserved for the payload");
+
+        let mut encoder = FrameEncoder::with_max_frame_len(4);
+        let event = GasEvent::BlobBaseFeeUpdate { blob_base_fee: 1 };
+        assert!(matches!(
+            encoder.encode(Message::from(event), &mut buf),
+            Err(FrameError::TooLarge { len: 10, max: 4 })
+        ));
+    }
+
+    #[test]
+    fn test_trailing_garbage() {
+        let event = Message::from(GasEvent::BlobBaseFeeUpdate { blob_base_fee: 1 });
+        let mut decoder = FrameDecoder::<Message>::new();
+
+        // bytes left over inside a frame
+        let mut payload = borsh::to_vec(&event).unwrap();
+        payload.push(0xFF);
+        let mut buf = BytesMut::new();
+        buf.put_u32_le(payload.len() as u32);
+        buf.put_slice(&payload);
+        assert!(matches!(
+            decoder.decode(&mut buf),
+            Err(FrameError::Malformed(_))
+        ));
+
+        // a partial frame at the end of the stream
+        let mut buf = BytesMut::new();
+        FrameEncoder::new().encode(event.clone(), &mut buf).unwrap();
+        buf.put_slice(&[1, 0]);
+        assert_eq!(decoder.decode_eof(&mut buf).unwrap(), Some(event));
+        assert!(matches!(
+            decoder.decode_eof(&mut buf),
+            Err(FrameError::Truncated { remaining: 2 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_read_and_write_event() {
+        let (mut client, mut server) = tokio::io::duplex(8);
+        let sent = messages();
+        let writer = {
+            let sent = sent.clone();
+            tokio::spawn(async move {
+                for message in &sent {
+                    write_event(&mut client, message).await.unwrap();
+                }
+                // half a length prefix, then EOF
+                client.write_all(&[3, 0]).await.unwrap();
+            })
+        };
+
+        let mut received = Vec::new();
+        let end = loop {
+            match read_event::<_, Message>(&mut server).await {
+                Ok(Some(message)) => received.push(message),
+                other => break other,
+            }
+        };
+        writer.await.unwrap();
+        assert_eq!(received, sent);
+        assert!(matches!(end, Err(FrameError::Truncated { remaining: 2 })));
+
+        let mut empty: &[u8] = &[];
+        assert!(matches!(
+            read_event::<_, Message>(&mut empty).await,
+            Ok(None)
+        ));
+    }
+}
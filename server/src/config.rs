@@ -0,0 +1,982 @@
+use crate::audit::{AuditLogConfig, FsyncPolicy, OverflowPolicy};
+use crate::channel::BackpressurePolicy;
+use crate::health::HealthThresholds;
+use crate::l2::{DEFAULT_DECIMALS, DEFAULT_OVERHEAD, L1DataFeeEstimator, L1FeeParams};
+use crate::limiter::{LimiterConfigError, RateLimiterConfig};
+use crate::nonce::NonceManager;
+use crate::scheduler::{self, SchedulerConfig};
+use crate::units::{Gwei, WEI_PER_GWEI};
+use alloy_primitives::Address;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Environment variables starting with this override keys of the file, one
+/// `__`-separated part per level: `GAS_SAVER__LIMITER__RATE=5` sets `rate` in
+/// `[limiter]`.
+pub const ENV_PREFIX: &str = "GAS_SAVER__";
+
+/// Everything the server can be configured with. Keys left out of a file keep
+/// the defaults the binary's flags have.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AppConfig {
+    /// Chain of requests that don't name one.
+    pub chain_id: u64,
+    pub scheduler: SchedulerSection,
+    pub model: ModelSection,
+    pub limiter: LimiterSection,
+    pub nonces: NonceSection,
+    pub server: ServerSection,
+    pub feeds: FeedSection,
+    pub executor: ExecutorSection,
+    pub storage: StorageSection,
+    pub audit: AuditSection,
+    pub health: HealthSection,
+    /// Settings of particular chains, keyed by chain id: `[chains.10]`.
+    pub chains: BTreeMap<String, ChainSection>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SchedulerSection {
+    /// In gwei.
+    pub target_base_fee: u64,
+    /// Tip ceiling, in gwei.
+    pub max_priority_fee: u64,
+    /// Volatility at which the scheduler enters spike mode, in gwei per block.
+    pub spike_threshold_high: f64,
+    /// Volatility at which it leaves spike mode again, in gwei per block.
+    pub spike_threshold_low: f64,
+    /// Fall in the base fee, in gwei per block, past which a tx over its fee
+    /// cap waits for the trend.
+    pub trend_threshold: f64,
+    /// Least time between reprices of a tx, in milliseconds.
+    pub reprice_cooldown_ms: u64,
+    /// Fill nonce gaps with self-transfers once they stand this many seconds.
+    pub fill_nonce_gaps_after: Option<u64>,
+    /// JSON file the requests still queued or in flight are restored from at
+    /// startup and saved to on shutdown.
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelSection {
+    /// Base fee samples the model keeps.
+    pub window: usize,
+    /// JSON file the fee history is restored from at startup and saved to on
+    /// shutdown.
+    pub state_file: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LimiterKind {
+    /// `rate` and `burst` apply.
+    TokenBucket,
+    /// No pacing at all.
+    Noop,
+}
+
+/// Response shape of the gas oracle at `feeds.oracle_url`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OracleProvider {
+    /// Etherscan's gastracker `gasoracle`.
+    Etherscan,
+    /// Blocknative's `blockprices`.
+    Blocknative,
+}
+
+/// When the audit log is synced to disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditFsync {
+    /// After every batch of entries.
+    Always,
+    /// Every `fsync_interval` seconds at most.
+    Interval,
+    /// Only on rotation and shutdown.
+    Never,
+}
+
+/// What happens to audit entries the writer has no room for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOverflow {
+    /// Whatever is writing waits for room.
+    Wait,
+    /// The entry is left out and counted.
+    Drop,
+}
+
+/// What a send into a full channel does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChannelOverflow {
+    /// The sender waits for room.
+    Block,
+    /// The oldest queued message makes room and is counted.
+    DropOldest,
+    /// The send fails and is counted.
+    Reject,
+}
+
+impl From<ChannelOverflow> for BackpressurePolicy {
+    fn from(overflow: ChannelOverflow) -> Self {
+        match overflow {
+            ChannelOverflow::Block => BackpressurePolicy::Block,
+            ChannelOverflow::DropOldest => BackpressurePolicy::DropOldest,
+            ChannelOverflow::Reject => BackpressurePolicy::Reject,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimiterSection {
+    pub kind: LimiterKind,
+    /// Refill, in submissions per second.
+    pub rate: u64,
+    pub burst: u64,
+    /// Per-sender refill, in submissions per second, on top of `rate`.
+    pub per_sender_rate: Option<u64>,
+    /// Per-sender bucket size; defaults to `per_sender_rate`.
+    pub per_sender_burst: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NonceSection {
+    /// Most nonces a sender may have in flight past its confirmed nonce.
+    pub max_inflight: Option<u64>,
+    /// Nonce allocations, releases and resyncs kept per sender for audit.
+    pub audit_capacity: usize,
+    /// JSON file the nonce state is restored from at startup and saved to on
+    /// shutdown.
+    pub state_file: Option<PathBuf>,
+    /// Seconds between nonce stats dumps to the log; 0 disables them.
+    pub stats_interval: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSection {
+    /// Capacity of the event, request and decision channels.
+    pub channel_capacity: usize,
+    /// What a full event channel does with another event: `block` or
+    /// `drop-oldest`.
+    pub event_overflow: ChannelOverflow,
+    /// What a full request channel does with another request: `block`, or
+    /// `reject`, which the HTTP API answers with 503.
+    pub request_overflow: ChannelOverflow,
+    /// What a full decision channel does with another decision: `block` or
+    /// `drop-oldest`.
+    pub decision_overflow: ChannelOverflow,
+    /// Print bare decisions rather than records.
+    pub bare_decisions: bool,
+    /// Where to serve the HTTP API, with the `http-api` feature.
+    pub listen: Option<SocketAddr>,
+    /// Where to serve `/metrics`, with the `metrics` feature.
+    pub metrics_listen: Option<SocketAddr>,
+    /// Unix socket to serve local clients on.
+    pub ipc_socket: Option<PathBuf>,
+    /// Seconds each stage of shutdown may take before it's cut short.
+    pub shutdown_timeout: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeedSection {
+    /// `newHeads` websocket endpoint, with the `ws-feed` feature.
+    pub ws_url: Option<String>,
+    /// More `newHeads` endpoints to fail over to, in order, when `ws_url` goes
+    /// quiet.
+    pub backup_ws_urls: Vec<String>,
+    /// Also take pending txs from `ws_url`'s `newPendingTransactions`.
+    pub mempool: bool,
+    /// Pending tx hashes a second above which only a sample is fetched.
+    pub mempool_max_per_sec: u64,
+    /// `eth_feeHistory` HTTP endpoint, with the `http-feed` feature.
+    pub fee_history_url: Option<String>,
+    /// Seconds between `eth_feeHistory` polls.
+    pub fee_history_interval: u64,
+    /// Blocks each poll asks for.
+    pub fee_history_blocks: u64,
+    /// Tip percentiles each poll asks for.
+    pub fee_history_percentiles: Vec<f64>,
+    /// Gas oracle endpoint, with the `http-feed` feature.
+    pub oracle_url: Option<String>,
+    pub oracle_provider: OracleProvider,
+    /// Seconds between oracle polls.
+    pub oracle_interval: u64,
+    /// Seconds an oracle poll may take.
+    pub oracle_timeout: u64,
+    /// Header the oracle's API key is sent in.
+    pub oracle_api_key_header: String,
+    /// Environment variable holding the oracle's API key, if it needs one. The
+    /// key itself never goes in the file.
+    pub oracle_api_key_env: Option<String>,
+    /// Seconds without events before the block feed in use is failed over from,
+    /// when there are several.
+    pub stale_after: u64,
+    /// How far block feeds' base fees for a block may spread apart, as a
+    /// fraction of their median, before they're flagged and the median used.
+    pub divergence_threshold: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExecutorSection {
+    /// JSON-RPC endpoint to broadcast to, with the `executor` feature.
+    pub rpc_url: Option<String>,
+    /// Environment variable holding the hex private key. The key itself never
+    /// goes in the file.
+    pub private_key_env: String,
+    /// Encrypted keystore to take the key from instead.
+    pub keystore: Option<PathBuf>,
+    /// Environment variable holding the keystore's password.
+    pub keystore_password_env: String,
+    /// HTTP signing service to sign with instead of a local key.
+    pub remote_signer_url: Option<String>,
+    /// The account the signing service signs for.
+    pub remote_signer_address: Option<Address>,
+    /// Seconds a call to the signing service may take.
+    pub remote_signer_timeout: u64,
+    /// Times a failed call to the signing service is tried again.
+    pub remote_signer_retries: u32,
+    /// Seconds between receipt polls.
+    pub receipt_interval: u64,
+    /// Blocks a broadcast tx may go without a receipt before it counts as dropped.
+    pub drop_after_blocks: u64,
+    /// Flashbots-style relay that private requests are sent to instead of
+    /// `rpc_url`.
+    pub private_relay_url: Option<String>,
+    /// Blocks past the head a private tx stays eligible for, unless its deadline
+    /// comes sooner.
+    pub relay_max_blocks: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuditSection {
+    /// JSON lines file every event, request and decision is appended to.
+    pub path: Option<PathBuf>,
+    /// Entries queued for the writer, at most.
+    pub capacity: usize,
+    /// Start a new file before one grows past this many bytes.
+    pub max_bytes: Option<u64>,
+    /// Start a new file once one has been open this many seconds.
+    pub max_age: Option<u64>,
+    pub fsync: AuditFsync,
+    /// Seconds between syncs, with `fsync = "interval"`.
+    pub fsync_interval: u64,
+    pub overflow: AuditOverflow,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthSection {
+    /// Seconds without a base fee after which `/health` reports unhealthy.
+    pub model_stale_after: u64,
+    /// Seconds a polling feed may go without answering before it counts as
+    /// stale.
+    pub feed_stale_after: u64,
+    /// Fraction of a channel's capacity past which it counts as backed up.
+    pub max_channel_fill: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainSection {
+    /// Charges an L1 data fee on top of execution gas, the way OP-stack chains do.
+    pub op_stack: bool,
+    /// L1 gas added to every tx.
+    pub l1_fee_overhead: u64,
+    /// Multiplier on the L1 base fee, scaled by `10^l1_fee_decimals`. Read from
+    /// the chain's `GasPriceOracle` at `rpc_url` while unset.
+    pub l1_fee_scalar: Option<u64>,
+    pub l1_fee_decimals: u32,
+    /// Node of the chain the L1 base fee is polled from, with the `http-feed`
+    /// feature.
+    pub rpc_url: Option<String>,
+    /// Seconds between L1 base fee polls.
+    pub l1_base_fee_interval: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StorageSection {
+    /// SQLite database requests, decisions and outcomes are kept in, with the
+    /// `sqlite` feature.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for AppConfig {
+    fn default() -> Self {
+        Self {
+            chain_id: 1,
+            scheduler: SchedulerSection::default(),
+            model: ModelSection::default(),
+            limiter: LimiterSection::default(),
+            nonces: NonceSection::default(),
+            server: ServerSection::default(),
+            feeds: FeedSection::default(),
+            executor: ExecutorSection::default(),
+            storage: StorageSection::default(),
+            audit: AuditSection::default(),
+            health: HealthSection::default(),
+            chains: BTreeMap::new(),
+        }
+    }
+}
+
+impl Default for HealthSection {
+    fn default() -> Self {
+        Self {
+            model_stale_after: 60,
+            feed_stale_after: 120,
+            max_channel_fill: 0.9,
+        }
+    }
+}
+
+impl Default for ChainSection {
+    fn default() -> Self {
+        Self {
+            op_stack: false,
+            l1_fee_overhead: DEFAULT_OVERHEAD,
+            l1_fee_scalar: None,
+            l1_fee_decimals: DEFAULT_DECIMALS,
+            rpc_url: None,
+            l1_base_fee_interval: 12,
+        }
+    }
+}
+
+impl ChainSection {
+    /// An estimator with the configured L1 fee parameters, if the scalar is set.
+    pub fn l1_data_fee(&self) -> Option<L1DataFeeEstimator> {
+        self.l1_fee_scalar.map(|scalar| {
+            L1DataFeeEstimator::new(L1FeeParams {
+                overhead: self.l1_fee_overhead,
+                scalar,
+                decimals: self.l1_fee_decimals,
+            })
+        })
+    }
+}
+
+impl Default for AuditSection {
+    fn default() -> Self {
+        Self {
+            path: None,
+            capacity: 1024,
+            max_bytes: None,
+            max_age: None,
+            fsync: AuditFsync::Never,
+            fsync_interval: 1,
+            overflow: AuditOverflow::Wait,
+        }
+    }
+}
+
+impl Default for SchedulerSection {
+    fn default() -> Self {
+        Self {
+            target_base_fee: 50,
+            max_priority_fee: 2,
+            spike_threshold_high: 15.0,
+            spike_threshold_low: 10.0,
+            trend_threshold: 1.0,
+            reprice_cooldown_ms: 500,
+            fill_nonce_gaps_after: None,
+            state_file: None,
+        }
+    }
+}
+
+impl Default for ModelSection {
+    fn default() -> Self {
+        Self {
+            window: 100,
+            state_file: None,
+        }
+    }
+}
+
+impl Default for LimiterSection {
+    fn default() -> Self {
+        Self {
+            kind: LimiterKind::TokenBucket,
+            rate: 10,
+            burst: 20,
+            per_sender_rate: None,
+            per_sender_burst: None,
+        }
+    }
+}
+
+impl Default for NonceSection {
+    fn default() -> Self {
+        Self {
+            max_inflight: None,
+            audit_capacity: 0,
+            state_file: None,
+            stats_interval: 60,
+        }
+    }
+}
+
+impl Default for ServerSection {
+    fn default() -> Self {
+        Self {
+            channel_capacity: 100,
+            event_overflow: ChannelOverflow::Block,
+            request_overflow: ChannelOverflow::Block,
+            decision_overflow: ChannelOverflow::Block,
+            bare_decisions: false,
+            listen: None,
+            metrics_listen: None,
+            ipc_socket: None,
+            shutdown_timeout: 10,
+        }
+    }
+}
+
+impl Default for FeedSection {
+    fn default() -> Self {
+        Self {
+            ws_url: None,
+            backup_ws_urls: Vec::new(),
+            mempool: false,
+            mempool_max_per_sec: 100,
+            fee_history_url: None,
+            fee_history_interval: 12,
+            fee_history_blocks: 4,
+            fee_history_percentiles: vec![25.0, 50.0, 75.0],
+            oracle_url: None,
+            oracle_provider: OracleProvider::Etherscan,
+            oracle_interval: 15,
+            oracle_timeout: 10,
+            oracle_api_key_header: "Authorization".into(),
+            oracle_api_key_env: None,
+            stale_after: 30,
+            divergence_threshold: 0.1,
+        }
+    }
+}
+
+impl Default for ExecutorSection {
+    fn default() -> Self {
+        Self {
+            rpc_url: None,
+            private_key_env: "GAS_SAVER_PRIVATE_KEY".into(),
+            keystore: None,
+            keystore_password_env: "GAS_SAVER_KEYSTORE_PASSWORD".into(),
+            remote_signer_url: None,
+            remote_signer_address: None,
+            remote_signer_timeout: 5,
+            remote_signer_retries: 2,
+            receipt_interval: 4,
+            drop_after_blocks: 50,
+            private_relay_url: None,
+            relay_max_blocks: 25,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    /// Not TOML, or a key of the wrong type or unknown; the message names it.
+    Parse(toml::de::Error),
+    /// An override whose value doesn't fit its key, or that names no key.
+    Env { var: String, message: String },
+    /// Parsed, but a value the server can't run with.
+    Invalid { key: &'static str, message: String },
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Io { path, source } => write!(f, "{}: {}", path.display(), source),
+            ConfigError::Parse(e) => write!(f, "{}", e),
+            ConfigError::Env { var, message } => write!(f, "{}: {}", var, message),
+            ConfigError::Invalid { key, message } => write!(f, "{}: {}", key, message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl AppConfig {
+    /// Reads the TOML file at `path` and applies overrides from the process
+    /// environment; see `from_toml`.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path).map_err(|source| ConfigError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        Self::from_toml(&text, std::env::vars())
+    }
+
+    /// Parses `text`, then applies the `ENV_PREFIX` variables among `env` in name
+    /// order, then validates the result. An override value is read as TOML if it
+    /// parses as such and as a string otherwise, so `5`, `[1, 2]` and `http://x`
+    /// all work unquoted.
+    pub fn from_toml(
+        text: &str,
+        env: impl IntoIterator<Item = (String, String)>,
+    ) -> Result<Self, ConfigError> {
+        let mut config: AppConfig = toml::from_str(text).map_err(ConfigError::Parse)?;
+        let mut overrides: Vec<(String, String)> = env
+            .into_iter()
+            .filter(|(var, _)| var.starts_with(ENV_PREFIX))
+            .collect();
+        overrides.sort();
+        for (var, value) in overrides {
+            config = config.overridden(&var, &value)?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// `self` with the key `var` names set to `value`; one at a time, so an
+    /// error points at the variable that caused it.
+    fn overridden(&self, var: &str, value: &str) -> Result<Self, ConfigError> {
+        let env_error = |message: String| ConfigError::Env {
+            var: var.to_string(),
+            message,
+        };
+        let path: Vec<String> = var[ENV_PREFIX.len()..]
+            .split("__")
+            .map(str::to_lowercase)
+            .collect();
+        let (key, sections) = path.split_last().unwrap();
+        if key.is_empty() || sections.iter().any(String::is_empty) {
+            return Err(env_error("empty key".into()));
+        }
+        let mut root = toml::Table::try_from(self).map_err(|e| env_error(e.to_string()))?;
+        let mut table = &mut root;
+        for section in sections {
+            table = match table
+                .entry(section.clone())
+                .or_insert_with(|| toml::Table::new().into())
+            {
+                toml::Value::Table(table) => table,
+                _ => return Err(env_error(format!("`{}` is not a section", section))),
+            };
+        }
+        table.insert(key.clone(), parse_override(value));
+        root.try_into()
+            .map_err(|e: toml::de::Error| env_error(e.message().to_string()))
+    }
+
+    /// Checks the values serde can't, with the scheduler's and limiter's own
+    /// validation.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |key, message: &dyn std::fmt::Display| ConfigError::Invalid {
+            key,
+            message: message.to_string(),
+        };
+        if self.model.window == 0 {
+            return Err(invalid("model.window", &"must be at least 1"));
+        }
+        if self.server.channel_capacity == 0 {
+            return Err(invalid("server.channel_capacity", &"must be at least 1"));
+        }
+        // events and decisions have no sender to hand a rejection back to
+        if self.server.event_overflow == ChannelOverflow::Reject {
+            return Err(invalid(
+                "server.event_overflow",
+                &"must be block or drop-oldest",
+            ));
+        }
+        if self.server.decision_overflow == ChannelOverflow::Reject {
+            return Err(invalid(
+                "server.decision_overflow",
+                &"must be block or drop-oldest",
+            ));
+        }
+        // a dropped request would vanish without its caller hearing of it
+        if self.server.request_overflow == ChannelOverflow::DropOldest {
+            return Err(invalid(
+                "server.request_overflow",
+                &"must be block or reject",
+            ));
+        }
+        if self.server.shutdown_timeout == 0 {
+            return Err(invalid("server.shutdown_timeout", &"must be at least 1"));
+        }
+        if self.feeds.mempool && self.feeds.ws_url.is_none() {
+            return Err(invalid("feeds.mempool", &"needs feeds.ws_url"));
+        }
+        if self.feeds.mempool_max_per_sec == 0 {
+            return Err(invalid("feeds.mempool_max_per_sec", &"must be at least 1"));
+        }
+        if self.feeds.oracle_interval == 0 {
+            return Err(invalid("feeds.oracle_interval", &"must be at least 1"));
+        }
+        if self.feeds.oracle_timeout == 0 {
+            return Err(invalid("feeds.oracle_timeout", &"must be at least 1"));
+        }
+        if self.feeds.stale_after == 0 {
+            return Err(invalid("feeds.stale_after", &"must be at least 1"));
+        }
+        if !(self.feeds.divergence_threshold.is_finite() && self.feeds.divergence_threshold > 0.0) {
+            return Err(invalid(
+                "feeds.divergence_threshold",
+                &"must be a positive fraction",
+            ));
+        }
+        if self.executor.private_relay_url.is_some() && self.executor.rpc_url.is_none() {
+            return Err(invalid(
+                "executor.private_relay_url",
+                &"needs executor.rpc_url",
+            ));
+        }
+        if self.executor.remote_signer_url.is_some() {
+            if self.executor.keystore.is_some() {
+                return Err(invalid(
+                    "executor.remote_signer_url",
+                    &"can't be used with executor.keystore",
+                ));
+            }
+            if self.executor.remote_signer_address.is_none() {
+                return Err(invalid(
+                    "executor.remote_signer_url",
+                    &"needs executor.remote_signer_address",
+                ));
+            }
+        }
+        if self.executor.remote_signer_timeout == 0 {
+            return Err(invalid(
+                "executor.remote_signer_timeout",
+                &"must be at least 1",
+            ));
+        }
+        if self.audit.capacity == 0 {
+            return Err(invalid("audit.capacity", &"must be at least 1"));
+        }
+        if self.audit.max_bytes == Some(0) {
+            return Err(invalid("audit.max_bytes", &"must be at least 1"));
+        }
+        if self.audit.max_age == Some(0) {
+            return Err(invalid("audit.max_age", &"must be at least 1"));
+        }
+        if self.audit.fsync_interval == 0 {
+            return Err(invalid("audit.fsync_interval", &"must be at least 1"));
+        }
+        if self.executor.relay_max_blocks == 0 {
+            return Err(invalid("executor.relay_max_blocks", &"must be at least 1"));
+        }
+        if let Some(percentile) = self
+            .feeds
+            .fee_history_percentiles
+            .iter()
+            .find(|percentile| !(0.0..=100.0).contains(*percentile))
+        {
+            return Err(invalid(
+                "feeds.fee_history_percentiles",
+                &format!("{} is not a percentile", percentile),
+            ));
+        }
+        if self.health.model_stale_after == 0 {
+            return Err(invalid("health.model_stale_after", &"must be at least 1"));
+        }
+        if self.health.feed_stale_after == 0 {
+            return Err(invalid("health.feed_stale_after", &"must be at least 1"));
+        }
+        if !(self.health.max_channel_fill > 0.0 && self.health.max_channel_fill <= 1.0) {
+            return Err(invalid(
+                "health.max_channel_fill",
+                &"must be a fraction above 0 and at most 1",
+            ));
+        }
+        for (chain, section) in &self.chains {
+            let chain_error = |message: &str| invalid("chains", &format!("{}: {}", chain, message));
+            if chain.parse::<u64>().is_err() {
+                return Err(chain_error("not a chain id"));
+            }
+            if section.op_stack && section.l1_fee_scalar.is_none() && section.rpc_url.is_none() {
+                return Err(chain_error("op_stack needs l1_fee_scalar or rpc_url"));
+            }
+            if section.l1_base_fee_interval == 0 {
+                return Err(chain_error("l1_base_fee_interval must be at least 1"));
+            }
+        }
+        if let Some(Err(e)) = self.limiter_config() {
+            let key = match e {
+                LimiterConfigError::ZeroBurst => "limiter.burst",
+                LimiterConfigError::ZeroSustainedRate => "limiter.rate",
+            };
+            return Err(invalid(key, &e));
+        }
+        self.scheduler_config().validate().map_err(|e| match &e {
+            scheduler::ConfigError::SpikeThresholdsInverted { .. } => {
+                invalid("scheduler.spike_threshold_low", &e)
+            }
+            scheduler::ConfigError::PerSenderRate(LimiterConfigError::ZeroBurst) => {
+                invalid("limiter.per_sender_burst", &e)
+            }
+            scheduler::ConfigError::PerSenderRate(LimiterConfigError::ZeroSustainedRate) => {
+                invalid("limiter.per_sender_rate", &e)
+            }
+        })
+    }
+
+    /// The scheduler's share of the settings; everything else keeps
+    /// `SchedulerConfig::default`.
+    pub fn scheduler_config(&self) -> SchedulerConfig {
+        let scheduler = &self.scheduler;
+        SchedulerConfig {
+            target_base_fee: Gwei(scheduler.target_base_fee).into(),
+            max_priority_fee: Gwei(scheduler.max_priority_fee).into(),
+            spike_threshold_high: scheduler.spike_threshold_high * WEI_PER_GWEI as f64,
+            spike_threshold_low: scheduler.spike_threshold_low * WEI_PER_GWEI as f64,
+            trend_threshold: scheduler.trend_threshold * WEI_PER_GWEI as f64,
+            reprice_cooldown: Duration::from_millis(scheduler.reprice_cooldown_ms),
+            chain_id: self.chain_id,
+            auto_fill_nonce_gaps: scheduler.fill_nonce_gaps_after.is_some(),
+            nonce_gap_fill_age: Duration::from_secs(scheduler.fill_nonce_gaps_after.unwrap_or(60)),
+            per_sender_rate: self.limiter.per_sender_rate.map(|rate| RateLimiterConfig {
+                sustained_per_sec: rate,
+                burst: self.limiter.per_sender_burst.unwrap_or(rate),
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// The shared limiter's bucket, or `None` for `noop`.
+    pub fn limiter_config(&self) -> Option<Result<RateLimiterConfig, LimiterConfigError>> {
+        match self.limiter.kind {
+            LimiterKind::TokenBucket => Some(RateLimiterConfig::new(
+                self.limiter.rate,
+                self.limiter.burst,
+            )),
+            LimiterKind::Noop => None,
+        }
+    }
+
+    /// How the audit log at `audit.path` is written.
+    pub fn audit_log_config(&self) -> AuditLogConfig {
+        AuditLogConfig {
+            capacity: self.audit.capacity,
+            max_bytes: self.audit.max_bytes,
+            max_age: self.audit.max_age.map(Duration::from_secs),
+            fsync: match self.audit.fsync {
+                AuditFsync::Always => FsyncPolicy::Always,
+                AuditFsync::Interval => {
+                    FsyncPolicy::Interval(Duration::from_secs(self.audit.fsync_interval))
+                }
+                AuditFsync::Never => FsyncPolicy::Never,
+            },
+            overflow: match self.audit.overflow {
+                AuditOverflow::Wait => OverflowPolicy::Wait,
+                AuditOverflow::Drop => OverflowPolicy::Drop,
+            },
+        }
+    }
+
+    /// When `/health` stops reporting healthy.
+    pub fn health_thresholds(&self) -> HealthThresholds {
+        HealthThresholds {
+            model_stale_after: Duration::from_secs(self.health.model_stale_after),
+            feed_stale_after: Duration::from_secs(self.health.feed_stale_after),
+            max_channel_fill: self.health.max_channel_fill,
+        }
+    }
+
+    /// The OP-stack chains among `chains`, by id; call after `validate`.
+    pub fn op_stack_chains(&self) -> impl Iterator<Item = (u64, &ChainSection)> {
+        self.chains
+            .iter()
+            .filter(|(_, section)| section.op_stack)
+            .filter_map(|(chain, section)| Some((chain.parse().ok()?, section)))
+    }
+
+    pub fn nonce_manager(&self) -> NonceManager {
+        let manager = NonceManager::new().with_audit_capacity(self.nonces.audit_capacity);
+        match self.nonces.max_inflight {
+            Some(max) => manager.with_max_inflight(max),
+            None => manager,
+        }
+    }
+}
+
+fn parse_override(value: &str) -> toml::Value {
+    match format!("value = {}", value).parse::<toml::Table>() {
+        Ok(mut table) => table.remove("value").unwrap(),
+        Err(_) => toml::Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = include_str!("../gas_saver.example.toml");
+
+    fn env(vars: &[(&str, &str)]) -> Vec<(String, String)> {
+        vars.iter()
+            .map(|(var, value)| (var.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn invalid_key(result: Result<AppConfig, ConfigError>) -> &'static str {
+        match result {
+            Err(ConfigError::Invalid { key, .. }) => key,
+            other => panic!("expected a validation error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_example_file_loads() {
+        let config = AppConfig::from_toml(EXAMPLE, []).unwrap();
+        assert_eq!(config.chain_id, 1);
+        assert_eq!(config.limiter.per_sender_rate, Some(2));
+        assert_eq!(config.nonces.state_file, Some("nonces.json".into()));
+        assert_eq!(
+            config.server.metrics_listen,
+            Some("127.0.0.1:9100".parse().unwrap())
+        );
+        assert_eq!(config.executor.private_key_env, "GAS_SAVER_PRIVATE_KEY");
+    }
+
+    #[test]
+    fn test_missing_keys_keep_defaults() {
+        assert_eq!(AppConfig::from_toml("", []).unwrap(), AppConfig::default());
+        let config = AppConfig::from_toml("[limiter]\nrate = 3\n", []).unwrap();
+        assert_eq!(config.limiter.rate, 3);
+        assert_eq!(config.limiter.burst, 20);
+        assert_eq!(config.scheduler, SchedulerSection::default());
+    }
+
+    #[test]
+    fn test_defaults_match_the_scheduler_in_wei() {
+        let config = AppConfig::default().scheduler_config();
+        let defaults = SchedulerConfig::default();
+        assert_eq!(config.spike_threshold_high, defaults.spike_threshold_high);
+        assert_eq!(config.spike_threshold_low, defaults.spike_threshold_low);
+        assert_eq!(config.trend_threshold, defaults.trend_threshold);
+    }
+
+    #[test]
+    fn test_type_errors_name_the_key() {
+        let err = AppConfig::from_toml("[limiter]\nrate = \"fast\"\n", []).unwrap_err();
+        let message = err.to_string();
+        assert!(matches!(err, ConfigError::Parse(_)));
+        assert!(message.contains("line 2"), "{}", message);
+        assert!(message.contains("rate"), "{}", message);
+
+        let err = AppConfig::from_toml("[limiter]\nrat = 3\n", []).unwrap_err();
+        assert!(err.to_string().contains("unknown field `rat`"), "{}", err);
+    }
+
+    #[test]
+    fn test_validation_points_at_the_key() {
+        let bad = |text: &str| invalid_key(AppConfig::from_toml(text, []));
+        assert_eq!(bad("[limiter]\nburst = 0\n"), "limiter.burst");
+        assert_eq!(bad("[limiter]\nrate = 0\n"), "limiter.rate");
+        assert_eq!(
+            bad("[limiter]\nper_sender_rate = 0\nper_sender_burst = 5\n"),
+            "limiter.per_sender_rate"
+        );
+        assert_eq!(
+            bad("[scheduler]\nspike_threshold_low = 20.0\n"),
+            "scheduler.spike_threshold_low"
+        );
+        assert_eq!(bad("[model]\nwindow = 0\n"), "model.window");
+        assert_eq!(
+            bad("[server]\nevent_overflow = \"reject\"\n"),
+            "server.event_overflow"
+        );
+        assert_eq!(
+            bad("[server]\nrequest_overflow = \"drop-oldest\"\n"),
+            "server.request_overflow"
+        );
+        assert_eq!(
+            bad("[executor]\nprivate_relay_url = \"https://relay.example\"\n"),
+            "executor.private_relay_url"
+        );
+        assert_eq!(
+            bad("[executor]\nremote_signer_url = \"https://signer.example\"\n"),
+            "executor.remote_signer_url"
+        );
+        assert_eq!(
+            bad("[feeds]\nfee_history_percentiles = [50.0, 101.0]\n"),
+            "feeds.fee_history_percentiles"
+        );
+        assert_eq!(
+            bad("[feeds]\ndivergence_threshold = 0.0\n"),
+            "feeds.divergence_threshold"
+        );
+        assert_eq!(
+            bad("[health]\nmax_channel_fill = 1.5\n"),
+            "health.max_channel_fill"
+        );
+        assert_eq!(bad("[chains.10]\nop_stack = true\n"), "chains");
+        assert_eq!(bad("[chains.optimism]\nop_stack = false\n"), "chains");
+        // a noop limiter ignores its bucket
+        let noop = "[limiter]\nkind = \"noop\"\nrate = 0\n";
+        assert!(AppConfig::from_toml(noop, []).is_ok());
+    }
+
+    #[test]
+    fn test_env_overrides_the_file() {
+        let vars = env(&[
+            ("GAS_SAVER__LIMITER__RATE", "5"),
+            ("GAS_SAVER__CHAIN_ID", "10"),
+            ("GAS_SAVER__EXECUTOR__RPC_URL", "http://localhost:8545"),
+            ("GAS_SAVER__FEEDS__FEE_HISTORY_PERCENTILES", "[10.0, 90.0]"),
+            // not an override
+            ("GAS_SAVER_PRIVATE_KEY", "0x01"),
+        ]);
+        let config = AppConfig::from_toml(EXAMPLE, vars).unwrap();
+        assert_eq!(config.limiter.rate, 5);
+        assert_eq!(config.limiter.per_sender_rate, Some(2));
+        assert_eq!(config.chain_id, 10);
+        assert_eq!(
+            config.executor.rpc_url.as_deref(),
+            Some("http://localhost:8545")
+        );
+        assert_eq!(config.feeds.fee_history_percentiles, vec![10.0, 90.0]);
+
+        // a chain's section
+        let vars = env(&[
+            ("GAS_SAVER__CHAINS__10__OP_STACK", "true"),
+            ("GAS_SAVER__CHAINS__10__L1_FEE_SCALAR", "1000000"),
+        ]);
+        let config = AppConfig::from_toml("", vars).unwrap();
+        let chains: Vec<_> = config.op_stack_chains().collect();
+        assert_eq!(chains.len(), 1);
+        assert_eq!(chains[0].0, 10);
+        assert_eq!(
+            chains[0].1.l1_data_fee().unwrap().params(),
+            L1FeeParams {
+                scalar: 1_000_000,
+                ..L1FeeParams::default()
+            }
+        );
+
+        let err = AppConfig::from_toml("", env(&[("GAS_SAVER__LIMITER__RATE", "fast")]));
+        assert!(
+            matches!(&err, Err(ConfigError::Env { var, .. }) if var == "GAS_SAVER__LIMITER__RATE"),
+            "{:?}",
+            err
+        );
+        let err = AppConfig::from_toml("", env(&[("GAS_SAVER__CHAIN_ID__X", "1")]));
+        assert!(matches!(err, Err(ConfigError::Env { .. })), "{:?}", err);
+        // overrides are validated like the file
+        let vars = env(&[("GAS_SAVER__LIMITER__BURST", "0")]);
+        assert_eq!(invalid_key(AppConfig::from_toml("", vars)), "limiter.burst");
+    }
+}
@@ -0,0 +1,350 @@
+use crate::channel::InstrumentedSender;
+use crate::events::{GasEvent, hex_hash};
+use crate::limiter::RateLimiter;
+use alloy_network::ReceiptResponse;
+use alloy_primitives::B256;
+use alloy_provider::Provider;
+use lru::LruCache;
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::MissedTickBehavior;
+use tracing::warn;
+
+/// Hashes watched at once unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+/// Watches broadcast hashes for receipts with `eth_getTransactionReceipt` and
+/// turns what it finds into gas events for the scheduler.
+pub struct ReceiptPoller<P> {
+    provider: P,
+    interval: Duration,
+    drop_after_blocks: u64,
+    capacity: NonZeroUsize,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<P: Provider + 'static> ReceiptPoller<P> {
+    /// Polls every `interval`, at most ten calls a second unless given another
+    /// limiter. A hash still without a receipt `drop_after_blocks` blocks after
+    /// the first poll that saw it counts as dropped.
+    pub fn new(provider: P, interval: Duration, drop_after_blocks: u64) -> Self {
+        Self {
+            provider,
+            interval,
+            drop_after_blocks,
+            capacity: NonZeroUsize::new(DEFAULT_CAPACITY).unwrap(),
+            limiter: Arc::new(RateLimiter::new(10, 10)),
+        }
+    }
+
+    /// Calls only with a token from `limiter`, e.g. one shared with other calls
+    /// to the same provider.
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Watches at most `capacity` hashes; registering one more gives up on the
+    /// one registered longest ago. Zero is taken as one.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
+        self
+    }
+
+    /// Watches the hashes sent on `hashes`, e.g. by `Executor::with_broadcasts`,
+    /// and sends each one's outcome to `gas_tx`:
+    ///
+    /// - `TxConfirmed` with the receipt's block, effective gas price and gas used;
+    /// - `TxFailed` if the receipt says it reverted;
+    /// - `TxDropped` if no receipt turned up in time.
+    ///
+    /// A hash is forgotten after its outcome. Runs until `gas_tx`'s receiver is
+    /// dropped, or `hashes` closes with nothing left to watch.
+    pub fn spawn(
+        self,
+        hashes: mpsc::Receiver<[u8; 32]>,
+        gas_tx: impl Into<InstrumentedSender<GasEvent>>,
+    ) -> JoinHandle<()> {
+        tokio::spawn(self.run(hashes, gas_tx.into()))
+    }
+
+    async fn run(self, mut hashes: mpsc::Receiver<[u8; 32]>, gas_tx: InstrumentedSender<GasEvent>) {
+        // each hash with the block it was first polled at
+        let mut tracked: LruCache<[u8; 32], Option<u64>> = LruCache::new(self.capacity);
+        let mut hashes_open = true;
+        let mut ticks = tokio::time::interval(self.interval);
+        ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        while hashes_open || !tracked.is_empty() {
+            tokio::select! {
+                biased;
+                hash = hashes.recv(), if hashes_open => match hash {
+                    Some(hash) => {
+                        if let Some((evicted, _)) = tracked.push(hash, None)
+                            && evicted != hash
+                        {
+                            warn!(
+                                "watching {} hashes; gave up on {}",
+                                self.capacity,
+                                hex_hash(&evicted)
+                            );
+                        }
+                    }
+                    None => hashes_open = false,
+                },
+                _ = ticks.tick() => {
+                    for event in self.poll(&mut tracked).await {
+                        if gas_tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                _ = gas_tx.closed() => return,
+            }
+        }
+    }
+
+    /// One pass over every tracked hash; outcomes found are removed from
+    /// `tracked` and returned.
+    async fn poll(&self, tracked: &mut LruCache<[u8; 32], Option<u64>>) -> Vec<GasEvent> {
+        if tracked.is_empty() {
+            return Vec::new();
+        }
+        self.limiter.acquire().await;
+        let block = match self.provider.get_block_number().await {
+            Ok(block) => block,
+            Err(e) => {
+                warn!("eth_blockNumber failed: {}", e);
+                return Vec::new();
+            }
+        };
+        let hashes: Vec<[u8; 32]> = tracked.iter().map(|(hash, _)| *hash).collect();
+        let mut events = Vec::new();
+        for tx_hash in hashes {
+            self.limiter.acquire().await;
+            let receipt = match self
+                .provider
+                .get_transaction_receipt(B256::from(tx_hash))
+                .await
+            {
+                Ok(receipt) => receipt,
+                Err(e) => {
+                    warn!(
+                        "eth_getTransactionReceipt for {} failed: {}",
+                        hex_hash(&tx_hash),
+                        e
+                    );
+                    continue;
+                }
+            };
+            // some nodes serve receipts of pending txs, without a block
+            let mined = receipt.and_then(|receipt| Some((receipt.block_number()?, receipt)));
+            let event = match mined {
+                Some((block_number, receipt)) => {
+                    if receipt.status() {
+                        GasEvent::TxConfirmed {
+                            tx_hash,
+                            block_number,
+                            effective_gas_price: receipt
+                                .effective_gas_price()
+                                .try_into()
+                                .unwrap_or(u64::MAX),
+                            gas_used: receipt.gas_used(),
+                        }
+                    } else {
+                        GasEvent::TxFailed {
+                            tx_hash,
+                            block_number,
+                        }
+                    }
+                }
+                None => {
+                    let since = tracked
+                        .peek_mut(&tx_hash)
+                        .map(|since| *since.get_or_insert(block));
+                    if since
+                        .is_none_or(|since| block.saturating_sub(since) < self.drop_after_blocks)
+                    {
+                        continue;
+                    }
+                    GasEvent::TxDropped {
+                        tx_hash,
+                        reason: format!("no receipt after {} blocks", self.drop_after_blocks),
+                    }
+                }
+            };
+            tracked.pop(&tx_hash);
+            events.push(event);
+        }
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_provider::RootProvider;
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    const MINED: [u8; 32] = [0x11; 32];
+    const REVERTED: [u8; 32] = [0x22; 32];
+    const LOST: [u8; 32] = [0x33; 32];
+
+    /// A geth receipt for `tx_hash` in block `number`, trimmed of its logs.
+    fn receipt(tx_hash: &[u8; 32], number: u64, status: bool) -> Value {
+        json!({
+            "transactionHash": hex_hash(tx_hash),
+            "transactionIndex": "0x3",
+            "blockHash": format!("0x{}", "44".repeat(32)),
+            "blockNumber": format!("{:#x}", number),
+            "from": "0xf39fd6e51aad88f6f4ce6ab8827279cfffb92266",
+            "to": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "cumulativeGasUsed": "0x1d4c0",
+            "gasUsed": "0x5208",
+            "effectiveGasPrice": "0x2e90edd00",
+            "contractAddress": null,
+            "logs": [],
+            "logsBloom": format!("0x{}", "00".repeat(256)),
+            "type": "0x2",
+            "status": if status { "0x1" } else { "0x0" },
+        })
+    }
+
+    /// Serves JSON-RPC calls on `listener` with `answer`, one connection each,
+    /// until the test ends.
+    fn serve(
+        listener: TcpListener,
+        mut answer: impl FnMut(&str, &Value) -> Value + Send + 'static,
+    ) {
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let call: Value = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((_, body)) = text.split_once("\r\n\r\n")
+                        && let Ok(call) = serde_json::from_str(body)
+                    {
+                        break call;
+                    }
+                };
+                let result = answer(call["method"].as_str().unwrap(), &call["params"]);
+                let body =
+                    json!({"jsonrpc": "2.0", "id": call["id"], "result": result}).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+    }
+
+    /// A node that moves a block per poll. `MINED` shows up in block 102,
+    /// `REVERTED` is already in 100, and nothing is known of any other hash.
+    fn fake_node() -> impl FnMut(&str, &Value) -> Value + Send + 'static {
+        let mut block = 99;
+        move |method, params| match method {
+            "eth_blockNumber" => {
+                block += 1;
+                json!(format!("{:#x}", block))
+            }
+            "eth_getTransactionReceipt" => {
+                let hash = params[0].as_str().unwrap();
+                if hash == hex_hash(&MINED) && block >= 102 {
+                    receipt(&MINED, 102, true)
+                } else if hash == hex_hash(&REVERTED) {
+                    receipt(&REVERTED, 100, false)
+                } else {
+                    Value::Null
+                }
+            }
+            other => panic!("unexpected call {}", other),
+        }
+    }
+
+    /// A poller on `fake_node` already holding `hashes`, dropping after 3 blocks.
+    async fn start(
+        capacity: usize,
+        hashes: &[[u8; 32]],
+    ) -> (
+        JoinHandle<()>,
+        mpsc::Sender<[u8; 32]>,
+        mpsc::Receiver<GasEvent>,
+    ) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        serve(listener, fake_node());
+        let (hash_tx, hash_rx) = mpsc::channel(10);
+        for &hash in hashes {
+            hash_tx.send(hash).await.unwrap();
+        }
+        let (gas_tx, gas_rx) = mpsc::channel(10);
+        let poller = ReceiptPoller::new(
+            RootProvider::new_http(url.parse().unwrap()),
+            Duration::from_millis(5),
+            3,
+        )
+        .with_limiter(Arc::new(RateLimiter::new(1_000, 10)))
+        .with_capacity(capacity)
+        .spawn(hash_rx, gas_tx);
+        (poller, hash_tx, gas_rx)
+    }
+
+    #[tokio::test]
+    async fn test_hashes_end_confirmed_failed_or_dropped() {
+        let (poller, hash_tx, mut gas_rx) = start(10, &[MINED, REVERTED, LOST]).await;
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::TxFailed {
+                tx_hash: REVERTED,
+                block_number: 100,
+            })
+        );
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::TxConfirmed {
+                tx_hash: MINED,
+                block_number: 102,
+                effective_gas_price: 12_500_000_000,
+                gas_used: 21_000,
+            })
+        );
+        // first polled at block 100, given up on at 103
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::TxDropped {
+                tx_hash: LOST,
+                reason: "no receipt after 3 blocks".into(),
+            })
+        );
+
+        // nothing left to watch once no more hashes can come
+        drop(hash_tx);
+        poller.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_oldest_hash_goes_when_full() {
+        let (poller, hash_tx, mut gas_rx) = start(1, &[REVERTED, LOST]).await;
+        // REVERTED was given up on before it could be reported
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::TxDropped {
+                tx_hash: LOST,
+                reason: "no receipt after 3 blocks".into(),
+            })
+        );
+        drop(hash_tx);
+        poller.await.unwrap();
+        assert_eq!(gas_rx.recv().await, None);
+    }
+}
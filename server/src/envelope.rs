@@ -0,0 +1,307 @@
+pub mod legacy;
+
+use crate::events::{
+    DecisionRecord, GasEvent, SchedulerCommand, SchedulerDecision, TransactionRequest,
+};
+use borsh::{BorshDeserialize, BorshSerialize};
+use legacy::{
+    GasEventV3, SchedulerCommandV1, SchedulerCommandV2, TransactionRequestV2, TransactionRequestV3,
+    TransactionRequestV4, TransactionRequestV5, TransactionRequestV6, TransactionRequestV7,
+    TransactionRequestV8,
+};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Schema version written by this build.
+///
+/// 1: before blob requests; `TransactionRequest` had no `blob` and `Submit`/`Reprice`
+///    no `blob_gas_price`.
+/// 2: before `TxConfirmed` carried the receipt's gas figures.
+/// 3: before `Defer` carried a typed reason and retry hint.
+/// 4: before requests and tx decisions carried a `fee_mode`.
+/// 5: before fees and prices in requests, decisions and commands were `Wei`, a
+///    `u128`, rather than a `u64`.
+/// 6: before requests carried an `idempotency_key`.
+/// 7: before requests carried an `access_list`.
+/// 8: before requests carried an `authorization_list`.
+/// 9: before requests and `Submit`/`Reprice` decisions carried a `privacy`.
+/// 10: before `Submit`/`Reprice` decisions carried their clamped tip.
+/// 11: current layouts.
+pub const CURRENT_SCHEMA_VERSION: u16 = 11;
+
+/// A Borsh payload prefixed with the schema version it was written at, so a layout
+/// change shows up as `SchemaError::UnsupportedVersion` rather than garbage.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Envelope<T> {
+    pub schema_version: u16,
+    pub payload: T,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaError {
+    /// Written at a version this build has no decoder for.
+    UnsupportedVersion { found: u16, supported: u16 },
+    /// The version matched but the payload didn't decode.
+    Malformed(String),
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::UnsupportedVersion { found, supported } => write!(
+                f,
+                "schema version {} not supported (current is {})",
+                found, supported
+            ),
+            SchemaError::Malformed(err) => write!(f, "malformed payload: {}", err),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+/// A type carried in an `Envelope`.
+pub trait Schema: BorshSerialize + BorshDeserialize {
+    /// Decodes a payload written at an older `schema_version`. `None` means there is
+    /// no shim for that version.
+    fn migrate(schema_version: u16, payload: &[u8]) -> Option<std::io::Result<Self>> {
+        let _ = (schema_version, payload);
+        None
+    }
+}
+
+impl<T: Schema> Envelope<T> {
+    /// Wraps `payload` at `CURRENT_SCHEMA_VERSION`.
+    pub fn new(payload: T) -> Self {
+        Self {
+            schema_version: CURRENT_SCHEMA_VERSION,
+            payload,
+        }
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        borsh::to_vec(self).expect("borsh encoding into a Vec does not fail")
+    }
+
+    /// Decodes an envelope, upgrading older payloads through `Schema::migrate`. The
+    /// returned `schema_version` is the one the bytes were written at.
+    pub fn decode(bytes: &[u8]) -> Result<Self, SchemaError> {
+        let mut payload = bytes;
+        let schema_version = <u16 as BorshDeserialize>::deserialize(&mut payload)
+            .map_err(|err| SchemaError::Malformed(err.to_string()))?;
+        let decoded = if schema_version == CURRENT_SCHEMA_VERSION {
+            T::try_from_slice(payload)
+        } else {
+            T::migrate(schema_version, payload).ok_or(SchemaError::UnsupportedVersion {
+                found: schema_version,
+                supported: CURRENT_SCHEMA_VERSION,
+            })?
+        };
+        Ok(Self {
+            schema_version,
+            payload: decoded.map_err(|err| SchemaError::Malformed(err.to_string()))?,
+        })
+    }
+}
+
+impl Schema for TransactionRequest {
+    fn migrate(schema_version: u16, payload: &[u8]) -> Option<std::io::Result<Self>> {
+        match schema_version {
+            1 => Some(TransactionRequestV2::try_from_slice(payload).map(Self::from)),
+            2..=4 => Some(TransactionRequestV3::try_from_slice(payload).map(Self::from)),
+            5 => Some(TransactionRequestV4::try_from_slice(payload).map(Self::from)),
+            6 => Some(TransactionRequestV5::try_from_slice(payload).map(Self::from)),
+            7 => Some(TransactionRequestV6::try_from_slice(payload).map(Self::from)),
+            8 => Some(TransactionRequestV7::try_from_slice(payload).map(Self::from)),
+            9 => Some(TransactionRequestV8::try_from_slice(payload).map(Self::from)),
+            10 => Some(Self::try_from_slice(payload)),
+            _ => None,
+        }
+    }
+}
+
+impl Schema for GasEvent {
+    // version 2 only appended variants, so version 1 bytes share its layout
+    fn migrate(schema_version: u16, payload: &[u8]) -> Option<std::io::Result<Self>> {
+        match schema_version {
+            1 | 2 => Some(GasEventV3::try_from_slice(payload).map(Self::from)),
+            3..=10 => Some(Self::try_from_slice(payload)),
+            _ => None,
+        }
+    }
+}
+
+// decisions are a live stream rather than something stored; a free-text defer
+// reason has no faithful typed form anyway, so older ones aren't upgraded
+impl Schema for SchedulerDecision {}
+impl Schema for DecisionRecord {}
+
+impl Schema for SchedulerCommand {
+    fn migrate(schema_version: u16, payload: &[u8]) -> Option<std::io::Result<Self>> {
+        match schema_version {
+            2..=4 => Some(
+                SchedulerCommandV1::<TransactionRequestV3>::try_from_slice(payload).map(Self::from),
+            ),
+            5 => Some(
+                SchedulerCommandV1::<TransactionRequestV4>::try_from_slice(payload).map(Self::from),
+            ),
+            6 => Some(
+                SchedulerCommandV2::<TransactionRequestV5>::try_from_slice(payload).map(Self::from),
+            ),
+            7 => Some(
+                SchedulerCommandV2::<TransactionRequestV6>::try_from_slice(payload).map(Self::from),
+            ),
+            8 => Some(
+                SchedulerCommandV2::<TransactionRequestV7>::try_from_slice(payload).map(Self::from),
+            ),
+            9 => Some(
+                SchedulerCommandV2::<TransactionRequestV8>::try_from_slice(payload).map(Self::from),
+            ),
+            10 => Some(Self::try_from_slice(payload)),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{DeferReason, FeeMode, RetryHint, SubmissionPrivacy, Urgency};
+    use crate::units::Wei;
+    use alloy_primitives::hex;
+
+    fn request() -> TransactionRequest {
+        TransactionRequest {
+            id: 1,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: Some(1),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    #[test]
+    fn test_decodes_stored_v1_request() {
+        // a request stored before blob params existed
+        let stored = hex::decode(concat!(
+            "0100",
+            "0100000000000000",
+            "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+            "01bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "00000000",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0852000000000000",
+            "6400000000000000",
+            "0200000000000000",
+            // deadline, urgency, max_wait_blocks, escalation
+            "00",
+            "01",
+            "00",
+            "00",
+            "010100000000000000",
+        ))
+        .unwrap();
+        let envelope = Envelope::<TransactionRequest>::decode(&stored).unwrap();
+        assert_eq!(envelope.schema_version, 1);
+        assert_eq!(envelope.payload, request());
+
+        // re-encoding writes the current layout
+        let bytes = Envelope::new(envelope.payload).encode();
+        assert_eq!(&bytes[..2], &[11, 0]);
+        // two fee fields widened to u128, plus no blob, fee mode Eip1559, no key,
+        // no access list, no authorizations and public privacy
+        assert_eq!(bytes.len(), stored.len() + 16 + 6);
+        assert_eq!(
+            Envelope::<TransactionRequest>::decode(&bytes).unwrap(),
+            Envelope::new(request())
+        );
+
+        // version 10 only changed decisions
+        let mut bytes = bytes;
+        bytes[..2].copy_from_slice(&10u16.to_le_bytes());
+        assert_eq!(
+            Envelope::<TransactionRequest>::decode(&bytes)
+                .unwrap()
+                .payload,
+            request()
+        );
+    }
+
+    #[test]
+    fn test_unsupported_version_is_typed() {
+        let decision = SchedulerDecision::Defer {
+            tx_id: 1,
+            reason: DeferReason::TrendWait {
+                estimated_blocks: 2,
+            },
+            retry_hint: Some(RetryHint::AfterBlocks(2)),
+        };
+        let mut bytes = Envelope::new(decision.clone()).encode();
+        assert_eq!(
+            Envelope::<SchedulerDecision>::decode(&bytes)
+                .unwrap()
+                .payload,
+            decision
+        );
+
+        for found in [1u16, 10, 12] {
+            bytes[..2].copy_from_slice(&found.to_le_bytes());
+            assert_eq!(
+                Envelope::<SchedulerDecision>::decode(&bytes),
+                Err(SchemaError::UnsupportedVersion {
+                    found,
+                    supported: CURRENT_SCHEMA_VERSION,
+                })
+            );
+        }
+
+        assert!(matches!(
+            Envelope::<SchedulerDecision>::decode(&[10]),
+            Err(SchemaError::Malformed(_))
+        ));
+        assert!(matches!(
+            Envelope::<SchedulerDecision>::decode(&[11, 0, 0xFF]),
+            Err(SchemaError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn test_old_events_upgrade() {
+        for schema_version in [1u16, 2] {
+            let mut bytes = schema_version.to_le_bytes().to_vec();
+            bytes.extend(
+                borsh::to_vec(&GasEventV3::TxConfirmed {
+                    tx_hash: [0x44; 32],
+                    block_number: 7,
+                })
+                .unwrap(),
+            );
+            let envelope = Envelope::<GasEvent>::decode(&bytes).unwrap();
+            assert_eq!(envelope.schema_version, schema_version);
+            assert_eq!(
+                envelope.payload,
+                GasEvent::TxConfirmed {
+                    tx_hash: [0x44; 32],
+                    block_number: 7,
+                    effective_gas_price: 0,
+                    gas_used: 0,
+                }
+            );
+        }
+    }
+}
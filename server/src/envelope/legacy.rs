@@ -0,0 +1,841 @@
+use crate::events::{
+    AccessListItem, BlobParams, Deadline, EscalationStep, FeeMode, GasEvent, RestoredTx,
+    SchedulerCommand, SignedAuthorization, SubmissionPrivacy, TransactionRequest, Urgency,
+};
+use crate::units::Wei;
+use borsh::{BorshDeserialize, BorshSerialize};
+
+/// Layout of `EscalationStep` before fees were `Wei`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct EscalationStepV1 {
+    pub after_secs: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+}
+
+impl From<EscalationStepV1> for EscalationStep {
+    fn from(v1: EscalationStepV1) -> Self {
+        Self {
+            after_secs: v1.after_secs,
+            max_fee_per_gas: v1.max_fee_per_gas.into(),
+            max_priority_fee_per_gas: v1.max_priority_fee_per_gas.into(),
+        }
+    }
+}
+
+/// Layout of `BlobParams` before fees were `Wei`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlobParamsV1 {
+    pub max_fee_per_blob_gas: u64,
+    pub blob_count: u8,
+}
+
+impl From<BlobParamsV1> for BlobParams {
+    fn from(v1: BlobParamsV1) -> Self {
+        Self {
+            max_fee_per_blob_gas: v1.max_fee_per_blob_gas.into(),
+            blob_count: v1.blob_count,
+        }
+    }
+}
+
+/// Layout of `GasEvent` before confirmations carried the receipt's gas figures.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum GasEventV3 {
+    BaseFeeUpdate {
+        base_fee: u64,
+        timestamp: u64,
+    },
+    MempoolTx {
+        tx_hash: [u8; 32],
+        max_fee: u64,
+        max_priority_fee: u64,
+        gas_limit: u64,
+    },
+    NewBlock {
+        number: u64,
+        base_fee: u64,
+        gas_used: u64,
+        gas_limit: u64,
+        block_hash: [u8; 32],
+        parent_hash: [u8; 32],
+    },
+    TxConfirmed {
+        tx_hash: [u8; 32],
+        block_number: u64,
+    },
+    TxDropped {
+        tx_hash: [u8; 32],
+        reason: String,
+    },
+    TxFailed {
+        tx_hash: [u8; 32],
+        block_number: u64,
+    },
+    TxReplaced {
+        old_tx_hash: [u8; 32],
+        new_tx_hash: [u8; 32],
+        nonce: u64,
+    },
+    BlobBaseFeeUpdate {
+        blob_base_fee: u64,
+    },
+}
+
+impl From<GasEventV3> for GasEvent {
+    fn from(v3: GasEventV3) -> Self {
+        match v3 {
+            GasEventV3::BaseFeeUpdate {
+                base_fee,
+                timestamp,
+            } => GasEvent::BaseFeeUpdate {
+                base_fee,
+                timestamp,
+            },
+            GasEventV3::MempoolTx {
+                tx_hash,
+                max_fee,
+                max_priority_fee,
+                gas_limit,
+            } => GasEvent::MempoolTx {
+                tx_hash,
+                max_fee,
+                max_priority_fee,
+                gas_limit,
+            },
+            GasEventV3::NewBlock {
+                number,
+                base_fee,
+                gas_used,
+                gas_limit,
+                block_hash,
+                parent_hash,
+            } => GasEvent::NewBlock {
+                number,
+                base_fee,
+                gas_used,
+                gas_limit,
+                block_hash,
+                parent_hash,
+            },
+            GasEventV3::TxConfirmed {
+                tx_hash,
+                block_number,
+            } => GasEvent::TxConfirmed {
+                tx_hash,
+                block_number,
+                effective_gas_price: 0,
+                gas_used: 0,
+            },
+            GasEventV3::TxDropped { tx_hash, reason } => GasEvent::TxDropped { tx_hash, reason },
+            GasEventV3::TxFailed {
+                tx_hash,
+                block_number,
+            } => GasEvent::TxFailed {
+                tx_hash,
+                block_number,
+            },
+            GasEventV3::TxReplaced {
+                old_tx_hash,
+                new_tx_hash,
+                nonce,
+            } => GasEvent::TxReplaced {
+                old_tx_hash,
+                new_tx_hash,
+                nonce,
+            },
+            GasEventV3::BlobBaseFeeUpdate { blob_base_fee } => {
+                GasEvent::BlobBaseFeeUpdate { blob_base_fee }
+            }
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before blob params.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV2 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStepV1>>,
+    pub chain_id: Option<u64>,
+}
+
+impl From<TransactionRequestV2> for TransactionRequest {
+    fn from(v2: TransactionRequestV2) -> Self {
+        Self {
+            id: v2.id,
+            from: v2.from,
+            to: v2.to,
+            data: v2.data,
+            value: v2.value,
+            gas_limit: v2.gas_limit,
+            max_fee_per_gas: v2.max_fee_per_gas.into(),
+            max_priority_fee_per_gas: v2.max_priority_fee_per_gas.into(),
+            deadline: v2.deadline,
+            urgency: v2.urgency,
+            max_wait_blocks: v2.max_wait_blocks,
+            escalation: v2
+                .escalation
+                .map(|steps| steps.into_iter().map(EscalationStep::from).collect()),
+            chain_id: v2.chain_id,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before fee modes, when every request was type-2.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV3 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStepV1>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParamsV1>,
+}
+
+impl From<TransactionRequestV3> for TransactionRequest {
+    fn from(v3: TransactionRequestV3) -> Self {
+        Self {
+            id: v3.id,
+            from: v3.from,
+            to: v3.to,
+            data: v3.data,
+            value: v3.value,
+            gas_limit: v3.gas_limit,
+            max_fee_per_gas: v3.max_fee_per_gas.into(),
+            max_priority_fee_per_gas: v3.max_priority_fee_per_gas.into(),
+            deadline: v3.deadline,
+            urgency: v3.urgency,
+            max_wait_blocks: v3.max_wait_blocks,
+            escalation: v3
+                .escalation
+                .map(|steps| steps.into_iter().map(EscalationStep::from).collect()),
+            chain_id: v3.chain_id,
+            blob: v3.blob.map(BlobParams::from),
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before fees were `Wei`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV4 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: u64,
+    pub max_priority_fee_per_gas: u64,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStepV1>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParamsV1>,
+    pub fee_mode: FeeMode,
+}
+
+impl From<TransactionRequestV4> for TransactionRequest {
+    fn from(v4: TransactionRequestV4) -> Self {
+        Self {
+            id: v4.id,
+            from: v4.from,
+            to: v4.to,
+            data: v4.data,
+            value: v4.value,
+            gas_limit: v4.gas_limit,
+            max_fee_per_gas: v4.max_fee_per_gas.into(),
+            max_priority_fee_per_gas: v4.max_priority_fee_per_gas.into(),
+            deadline: v4.deadline,
+            urgency: v4.urgency,
+            max_wait_blocks: v4.max_wait_blocks,
+            escalation: v4
+                .escalation
+                .map(|steps| steps.into_iter().map(EscalationStep::from).collect()),
+            chain_id: v4.chain_id,
+            blob: v4.blob.map(BlobParams::from),
+            fee_mode: v4.fee_mode,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before idempotency keys.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV5 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStep>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParams>,
+    pub fee_mode: FeeMode,
+}
+
+impl From<TransactionRequestV5> for TransactionRequest {
+    fn from(v5: TransactionRequestV5) -> Self {
+        Self {
+            id: v5.id,
+            from: v5.from,
+            to: v5.to,
+            data: v5.data,
+            value: v5.value,
+            gas_limit: v5.gas_limit,
+            max_fee_per_gas: v5.max_fee_per_gas,
+            max_priority_fee_per_gas: v5.max_priority_fee_per_gas,
+            deadline: v5.deadline,
+            urgency: v5.urgency,
+            max_wait_blocks: v5.max_wait_blocks,
+            escalation: v5.escalation,
+            chain_id: v5.chain_id,
+            blob: v5.blob,
+            fee_mode: v5.fee_mode,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before access lists.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV6 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStep>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParams>,
+    pub fee_mode: FeeMode,
+    pub idempotency_key: Option<[u8; 16]>,
+}
+
+impl From<TransactionRequestV6> for TransactionRequest {
+    fn from(v6: TransactionRequestV6) -> Self {
+        Self {
+            id: v6.id,
+            from: v6.from,
+            to: v6.to,
+            data: v6.data,
+            value: v6.value,
+            gas_limit: v6.gas_limit,
+            max_fee_per_gas: v6.max_fee_per_gas,
+            max_priority_fee_per_gas: v6.max_priority_fee_per_gas,
+            deadline: v6.deadline,
+            urgency: v6.urgency,
+            max_wait_blocks: v6.max_wait_blocks,
+            escalation: v6.escalation,
+            chain_id: v6.chain_id,
+            blob: v6.blob,
+            fee_mode: v6.fee_mode,
+            idempotency_key: v6.idempotency_key,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before authorization lists.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV7 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStep>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParams>,
+    pub fee_mode: FeeMode,
+    pub idempotency_key: Option<[u8; 16]>,
+    pub access_list: Option<Vec<AccessListItem>>,
+}
+
+impl From<TransactionRequestV7> for TransactionRequest {
+    fn from(v7: TransactionRequestV7) -> Self {
+        Self {
+            id: v7.id,
+            from: v7.from,
+            to: v7.to,
+            data: v7.data,
+            value: v7.value,
+            gas_limit: v7.gas_limit,
+            max_fee_per_gas: v7.max_fee_per_gas,
+            max_priority_fee_per_gas: v7.max_priority_fee_per_gas,
+            deadline: v7.deadline,
+            urgency: v7.urgency,
+            max_wait_blocks: v7.max_wait_blocks,
+            escalation: v7.escalation,
+            chain_id: v7.chain_id,
+            blob: v7.blob,
+            fee_mode: v7.fee_mode,
+            idempotency_key: v7.idempotency_key,
+            access_list: v7.access_list,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `TransactionRequest` before submission privacy.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct TransactionRequestV8 {
+    pub id: u64,
+    pub from: [u8; 20],
+    pub to: Option<[u8; 20]>,
+    pub data: Vec<u8>,
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    pub max_wait_blocks: Option<u32>,
+    pub escalation: Option<Vec<EscalationStep>>,
+    pub chain_id: Option<u64>,
+    pub blob: Option<BlobParams>,
+    pub fee_mode: FeeMode,
+    pub idempotency_key: Option<[u8; 16]>,
+    pub access_list: Option<Vec<AccessListItem>>,
+    pub authorization_list: Option<Vec<SignedAuthorization>>,
+}
+
+impl From<TransactionRequestV8> for TransactionRequest {
+    fn from(v8: TransactionRequestV8) -> Self {
+        Self {
+            id: v8.id,
+            from: v8.from,
+            to: v8.to,
+            data: v8.data,
+            value: v8.value,
+            gas_limit: v8.gas_limit,
+            max_fee_per_gas: v8.max_fee_per_gas,
+            max_priority_fee_per_gas: v8.max_priority_fee_per_gas,
+            deadline: v8.deadline,
+            urgency: v8.urgency,
+            max_wait_blocks: v8.max_wait_blocks,
+            escalation: v8.escalation,
+            chain_id: v8.chain_id,
+            blob: v8.blob,
+            fee_mode: v8.fee_mode,
+            idempotency_key: v8.idempotency_key,
+            access_list: v8.access_list,
+            authorization_list: v8.authorization_list,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+}
+
+/// Layout of `RestoredTx` before fees were `Wei`, with `R` the request layout of
+/// its time.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct RestoredTxV1<R> {
+    pub req: R,
+    pub nonce: u64,
+    pub gas_price: u64,
+    pub tx_hash: Option<[u8; 32]>,
+}
+
+impl<R: Into<TransactionRequest>> From<RestoredTxV1<R>> for RestoredTx {
+    fn from(v1: RestoredTxV1<R>) -> Self {
+        Self {
+            req: v1.req.into(),
+            nonce: v1.nonce,
+            gas_price: v1.gas_price.into(),
+            tx_hash: v1.tx_hash,
+        }
+    }
+}
+
+/// Layout of `SchedulerCommand` before fees were `Wei`. `R` is the request layout
+/// restored txs were written in: `TransactionRequestV3` before fee modes,
+/// `TransactionRequestV4` after.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum SchedulerCommandV1<R> {
+    Resubmit {
+        tx_id: u64,
+        new_max_fee_per_gas: Option<u64>,
+        new_deadline: Option<Deadline>,
+    },
+    Broadcast {
+        tx_id: u64,
+        tx_hash: [u8; 32],
+    },
+    BroadcastFailed {
+        tx_id: u64,
+        reason: String,
+    },
+    InitNonce {
+        chain_id: Option<u64>,
+        address: [u8; 20],
+        nonce: u64,
+        force: bool,
+    },
+    NonceTooLow {
+        chain_id: Option<u64>,
+        address: [u8; 20],
+        network_nonce: u64,
+    },
+    RestoreSubmitted {
+        txs: Vec<RestoredTxV1<R>>,
+    },
+    SetSubmissionRate {
+        tokens_per_sec: u64,
+        burst: Option<u64>,
+    },
+}
+
+impl<R: Into<TransactionRequest>> From<SchedulerCommandV1<R>> for SchedulerCommand {
+    fn from(v1: SchedulerCommandV1<R>) -> Self {
+        match v1 {
+            SchedulerCommandV1::Resubmit {
+                tx_id,
+                new_max_fee_per_gas,
+                new_deadline,
+            } => SchedulerCommand::Resubmit {
+                tx_id,
+                new_max_fee_per_gas: new_max_fee_per_gas.map(Wei::from),
+                new_deadline,
+            },
+            SchedulerCommandV1::Broadcast { tx_id, tx_hash } => {
+                SchedulerCommand::Broadcast { tx_id, tx_hash }
+            }
+            SchedulerCommandV1::BroadcastFailed { tx_id, reason } => {
+                SchedulerCommand::BroadcastFailed { tx_id, reason }
+            }
+            SchedulerCommandV1::InitNonce {
+                chain_id,
+                address,
+                nonce,
+                force,
+            } => SchedulerCommand::InitNonce {
+                chain_id,
+                address,
+                nonce,
+                force,
+            },
+            SchedulerCommandV1::NonceTooLow {
+                chain_id,
+                address,
+                network_nonce,
+            } => SchedulerCommand::NonceTooLow {
+                chain_id,
+                address,
+                network_nonce,
+            },
+            SchedulerCommandV1::RestoreSubmitted { txs } => SchedulerCommand::RestoreSubmitted {
+                txs: txs.into_iter().map(RestoredTx::from).collect(),
+            },
+            SchedulerCommandV1::SetSubmissionRate {
+                tokens_per_sec,
+                burst,
+            } => SchedulerCommand::SetSubmissionRate {
+                tokens_per_sec,
+                burst,
+            },
+        }
+    }
+}
+
+/// Layout of `RestoredTx` once fees were `Wei`, with `R` the request layout of
+/// its time.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub struct RestoredTxV2<R> {
+    pub req: R,
+    pub nonce: u64,
+    pub gas_price: Wei,
+    pub tx_hash: Option<[u8; 32]>,
+}
+
+impl<R: Into<TransactionRequest>> From<RestoredTxV2<R>> for RestoredTx {
+    fn from(v2: RestoredTxV2<R>) -> Self {
+        Self {
+            req: v2.req.into(),
+            nonce: v2.nonce,
+            gas_price: v2.gas_price,
+            tx_hash: v2.tx_hash,
+        }
+    }
+}
+
+/// Layout of `SchedulerCommand` once fees were `Wei`. `R` is the request layout
+/// restored txs were written in: `TransactionRequestV5` before idempotency keys,
+/// `TransactionRequestV6` before access lists, `TransactionRequestV7` before
+/// authorization lists, `TransactionRequestV8` before submission privacy.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum SchedulerCommandV2<R> {
+    Resubmit {
+        tx_id: u64,
+        new_max_fee_per_gas: Option<Wei>,
+        new_deadline: Option<Deadline>,
+    },
+    Broadcast {
+        tx_id: u64,
+        tx_hash: [u8; 32],
+    },
+    BroadcastFailed {
+        tx_id: u64,
+        reason: String,
+    },
+    InitNonce {
+        chain_id: Option<u64>,
+        address: [u8; 20],
+        nonce: u64,
+        force: bool,
+    },
+    NonceTooLow {
+        chain_id: Option<u64>,
+        address: [u8; 20],
+        network_nonce: u64,
+    },
+    RestoreSubmitted {
+        txs: Vec<RestoredTxV2<R>>,
+    },
+    SetSubmissionRate {
+        tokens_per_sec: u64,
+        burst: Option<u64>,
+    },
+}
+
+impl<R: Into<TransactionRequest>> From<SchedulerCommandV2<R>> for SchedulerCommand {
+    fn from(v2: SchedulerCommandV2<R>) -> Self {
+        match v2 {
+            SchedulerCommandV2::Resubmit {
+                tx_id,
+                new_max_fee_per_gas,
+                new_deadline,
+            } => SchedulerCommand::Resubmit {
+                tx_id,
+                new_max_fee_per_gas,
+                new_deadline,
+            },
+            SchedulerCommandV2::Broadcast { tx_id, tx_hash } => {
+                SchedulerCommand::Broadcast { tx_id, tx_hash }
+            }
+            SchedulerCommandV2::BroadcastFailed { tx_id, reason } => {
+                SchedulerCommand::BroadcastFailed { tx_id, reason }
+            }
+            SchedulerCommandV2::InitNonce {
+                chain_id,
+                address,
+                nonce,
+                force,
+            } => SchedulerCommand::InitNonce {
+                chain_id,
+                address,
+                nonce,
+                force,
+            },
+            SchedulerCommandV2::NonceTooLow {
+                chain_id,
+                address,
+                network_nonce,
+            } => SchedulerCommand::NonceTooLow {
+                chain_id,
+                address,
+                network_nonce,
+            },
+            SchedulerCommandV2::RestoreSubmitted { txs } => SchedulerCommand::RestoreSubmitted {
+                txs: txs.into_iter().map(RestoredTx::from).collect(),
+            },
+            SchedulerCommandV2::SetSubmissionRate {
+                tokens_per_sec,
+                burst,
+            } => SchedulerCommand::SetSubmissionRate {
+                tokens_per_sec,
+                burst,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::envelope::Envelope;
+    use alloy_primitives::U256;
+
+    /// `payload` as an envelope written at `schema_version`, decoded by this build.
+    fn stored<T: BorshSerialize>(schema_version: u16, payload: &T) -> TransactionRequest {
+        let mut bytes = schema_version.to_le_bytes().to_vec();
+        bytes.extend(borsh::to_vec(payload).unwrap());
+        let envelope = Envelope::<TransactionRequest>::decode(&bytes).unwrap();
+        assert_eq!(envelope.schema_version, schema_version);
+        envelope.payload
+    }
+
+    fn sample_request() -> TransactionRequest {
+        TransactionRequest {
+            id: 7,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+            value: U256::from(10u64).pow(U256::from(18)).to_be_bytes(),
+            gas_limit: 60_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: Some(1_700_000_000),
+            urgency: Urgency::High,
+            max_wait_blocks: Some(5),
+            escalation: Some(vec![EscalationStep {
+                after_secs: 30,
+                max_fee_per_gas: Wei(150),
+                max_priority_fee_per_gas: Wei(3),
+            }]),
+            chain_id: Some(10),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    /// `sample_request`'s escalation in the layout before fees were `Wei`.
+    fn frozen_escalation() -> Option<Vec<EscalationStepV1>> {
+        Some(vec![EscalationStepV1 {
+            after_secs: 30,
+            max_fee_per_gas: 150,
+            max_priority_fee_per_gas: 3,
+        }])
+    }
+
+    #[test]
+    fn test_v2_request_decodes_without_blob() {
+        let req = sample_request();
+        let v2 = TransactionRequestV2 {
+            id: req.id,
+            from: req.from,
+            to: req.to,
+            data: req.data.clone(),
+            value: req.value,
+            gas_limit: req.gas_limit,
+            max_fee_per_gas: 100,
+            max_priority_fee_per_gas: 2,
+            deadline: req.deadline,
+            urgency: req.urgency,
+            max_wait_blocks: req.max_wait_blocks,
+            escalation: frozen_escalation(),
+            chain_id: req.chain_id,
+        };
+        assert_eq!(stored(1, &v2), req);
+    }
+
+    #[test]
+    fn test_v3_request_decodes_as_eip1559() {
+        let req = sample_request();
+        let v3 = TransactionRequestV3 {
+            id: req.id,
+            from: req.from,
+            to: req.to,
+            data: req.data.clone(),
+            value: req.value,
+            gas_limit: req.gas_limit,
+            max_fee_per_gas: 100,
+            max_priority_fee_per_gas: 2,
+            deadline: req.deadline,
+            urgency: req.urgency,
+            max_wait_blocks: req.max_wait_blocks,
+            escalation: frozen_escalation(),
+            chain_id: req.chain_id,
+            blob: None,
+        };
+        let decoded = stored(3, &v3);
+        assert_eq!(decoded, req);
+        assert_eq!(decoded.fee_mode, FeeMode::Eip1559);
+    }
+
+    #[test]
+    fn test_v4_request_widens_fees() {
+        let req = sample_request();
+        let v4 = TransactionRequestV4 {
+            id: req.id,
+            from: req.from,
+            to: req.to,
+            data: req.data.clone(),
+            value: req.value,
+            gas_limit: req.gas_limit,
+            max_fee_per_gas: u64::MAX,
+            max_priority_fee_per_gas: 2,
+            deadline: req.deadline,
+            urgency: req.urgency,
+            max_wait_blocks: req.max_wait_blocks,
+            escalation: frozen_escalation(),
+            chain_id: req.chain_id,
+            blob: Some(BlobParamsV1 {
+                max_fee_per_blob_gas: 10,
+                blob_count: 1,
+            }),
+            fee_mode: FeeMode::Legacy,
+        };
+        assert_eq!(
+            stored(5, &v4),
+            TransactionRequest {
+                max_fee_per_gas: Wei(u64::MAX as u128),
+                blob: Some(BlobParams {
+                    max_fee_per_blob_gas: Wei(10),
+                    blob_count: 1,
+                }),
+                fee_mode: FeeMode::Legacy,
+                ..req
+            }
+        );
+    }
+}
@@ -1,60 +1,2493 @@
-use alloy_primitives::{Address, B256, U256};
+use crate::units::{Wei, format_ether, format_gwei};
+use alloy_primitives::{Address, U256, hex};
 use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::time::Duration;
 
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum GasEvent {
     BaseFeeUpdate {
         base_fee: u64,
         timestamp: u64,
     },
     MempoolTx {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
         tx_hash: [u8; 32],
         max_fee: u64,
         max_priority_fee: u64,
         gas_limit: u64,
     },
+    /// All-zero hashes mean the feed doesn't know them; such blocks aren't checked
+    /// for reorgs.
     NewBlock {
         number: u64,
         base_fee: u64,
         gas_used: u64,
         gas_limit: u64,
+        #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::array"))]
+        block_hash: [u8; 32],
+        #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::array"))]
+        parent_hash: [u8; 32],
     },
+    /// Zero `gas_used` means the feed didn't report the receipt, as in older
+    /// layouts.
     TxConfirmed {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
         tx_hash: [u8; 32],
         block_number: u64,
+        /// Price per gas actually paid: base fee plus the tip received.
+        #[cfg_attr(feature = "serde", serde(default))]
+        effective_gas_price: u64,
+        #[cfg_attr(feature = "serde", serde(default))]
+        gas_used: u64,
+    },
+    /// The tx left the mempool without being mined, e.g. evicted or expired.
+    TxDropped {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        tx_hash: [u8; 32],
+        reason: String,
+    },
+    /// The tx was included in `block_number` but reverted. Its nonce is used.
+    TxFailed {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        tx_hash: [u8; 32],
+        block_number: u64,
+    },
+    /// A different tx took the sender's `nonce` on chain in place of `old_tx_hash`.
+    TxReplaced {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        old_tx_hash: [u8; 32],
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        new_tx_hash: [u8; 32],
+        nonce: u64,
+    },
+    /// Price of blob gas (EIP-4844) for the next block, in wei.
+    BlobBaseFeeUpdate {
+        blob_base_fee: u64,
+    },
+    /// An `eth_feeHistory` result: one gas used ratio and one row of tip percentiles
+    /// per block from `oldest_block`, and one base fee per block plus the next
+    /// block's. `rewards` is empty when no percentiles were asked for.
+    FeeHistory {
+        oldest_block: u64,
+        base_fees: Vec<u64>,
+        gas_used_ratios: Vec<f64>,
+        rewards: Vec<Vec<u64>>,
+    },
+    /// A gas oracle's estimate for the next block. The tips join the scheduler's
+    /// tip model; the base fee is only reported.
+    OracleEstimate {
+        source: String,
+        base_fee: u64,
+        priority_fee_fast: u64,
+        priority_fee_standard: u64,
+    },
+    /// The L1 base fee an OP-stack chain prices its data fee at, in wei.
+    L1BaseFeeUpdate {
+        base_fee: u64,
     },
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+impl GasEvent {
+    /// The block the event reports on, if it names one; the newest for `FeeHistory`.
+    pub fn block_number(&self) -> Option<u64> {
+        match self {
+            GasEvent::NewBlock { number, .. } => Some(*number),
+            GasEvent::TxConfirmed { block_number, .. }
+            | GasEvent::TxFailed { block_number, .. } => Some(*block_number),
+            GasEvent::FeeHistory {
+                oldest_block,
+                gas_used_ratios,
+                ..
+            } => (gas_used_ratios.len() as u64)
+                .checked_sub(1)
+                .map(|newest| oldest_block + newest),
+            GasEvent::BaseFeeUpdate { .. }
+            | GasEvent::MempoolTx { .. }
+            | GasEvent::TxDropped { .. }
+            | GasEvent::TxReplaced { .. }
+            | GasEvent::BlobBaseFeeUpdate { .. }
+            | GasEvent::OracleEstimate { .. }
+            | GasEvent::L1BaseFeeUpdate { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for GasEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GasEvent::BaseFeeUpdate {
+                base_fee,
+                timestamp,
+            } => write!(
+                f,
+                "base fee {} at {}",
+                format_gwei(*base_fee as u128),
+                timestamp
+            ),
+            GasEvent::MempoolTx {
+                tx_hash,
+                max_fee,
+                max_priority_fee,
+                gas_limit,
+            } => write!(
+                f,
+                "mempool tx {} max fee {} tip {} gas {}",
+                hex_hash(tx_hash),
+                format_gwei(*max_fee as u128),
+                format_gwei(*max_priority_fee as u128),
+                gas_limit
+            ),
+            GasEvent::NewBlock {
+                number,
+                base_fee,
+                gas_used,
+                gas_limit,
+                block_hash,
+                ..
+            } => {
+                write!(f, "block {}", number)?;
+                if *block_hash != [0; 32] {
+                    write!(f, " {}", hex_hash(block_hash))?;
+                }
+                write!(
+                    f,
+                    " base fee {} gas {}/{}",
+                    format_gwei(*base_fee as u128),
+                    gas_used,
+                    gas_limit
+                )
+            }
+            GasEvent::TxConfirmed {
+                tx_hash,
+                block_number,
+                effective_gas_price,
+                gas_used,
+            } => {
+                write!(
+                    f,
+                    "tx {} confirmed in block {}",
+                    hex_hash(tx_hash),
+                    block_number
+                )?;
+                if *gas_used > 0 {
+                    write!(
+                        f,
+                        " at {} for {} gas",
+                        format_gwei(*effective_gas_price as u128),
+                        gas_used
+                    )?;
+                }
+                Ok(())
+            }
+            GasEvent::TxDropped { tx_hash, reason } => {
+                write!(f, "tx {} dropped: {}", hex_hash(tx_hash), reason)
+            }
+            GasEvent::TxFailed {
+                tx_hash,
+                block_number,
+            } => write!(
+                f,
+                "tx {} reverted in block {}",
+                hex_hash(tx_hash),
+                block_number
+            ),
+            GasEvent::TxReplaced {
+                old_tx_hash,
+                new_tx_hash,
+                nonce,
+            } => write!(
+                f,
+                "tx {} replaced by {} at nonce {}",
+                hex_hash(old_tx_hash),
+                hex_hash(new_tx_hash),
+                nonce
+            ),
+            GasEvent::BlobBaseFeeUpdate { blob_base_fee } => {
+                write!(f, "blob base fee {}", format_gwei(*blob_base_fee as u128))
+            }
+            GasEvent::FeeHistory {
+                oldest_block,
+                base_fees,
+                gas_used_ratios,
+                ..
+            } => {
+                write!(
+                    f,
+                    "fee history of {} blocks from {}",
+                    gas_used_ratios.len(),
+                    oldest_block
+                )?;
+                if let (Some(first), Some(last)) = (base_fees.first(), base_fees.last()) {
+                    write!(
+                        f,
+                        ", base fee {} to {}",
+                        format_gwei(*first as u128),
+                        format_gwei(*last as u128)
+                    )?;
+                }
+                Ok(())
+            }
+            GasEvent::OracleEstimate {
+                source,
+                base_fee,
+                priority_fee_fast,
+                priority_fee_standard,
+            } => write!(
+                f,
+                "{} estimate: base fee {}, tip {} standard, {} fast",
+                source,
+                format_gwei(*base_fee as u128),
+                format_gwei(*priority_fee_standard as u128),
+                format_gwei(*priority_fee_fast as u128)
+            ),
+            GasEvent::L1BaseFeeUpdate { base_fee } => {
+                write!(f, "L1 base fee {}", format_gwei(*base_fee as u128))
+            }
+        }
+    }
+}
+
+/// Checksummed address cut to its first and last four digits, for log lines.
+pub(crate) fn short_address(address: &[u8; 20]) -> String {
+    let full = Address::from(*address).to_checksum(None);
+    format!("{}…{}", &full[..6], &full[full.len() - 4..])
+}
+
+pub(crate) fn hex_hash(hash: &[u8; 32]) -> String {
+    format!("0x{}", hex::encode(hash))
+}
+
+/// How much a request cares about timely inclusion versus price.
+#[derive(
+    BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default,
+)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Urgency {
+    Low,
+    #[default]
+    Standard,
+    High,
+}
+
+/// Unix timestamp (seconds) after which a request is no longer worth submitting.
+pub type Deadline = u64;
+
+/// One rung of a caller-supplied price ladder: from `after_secs` past acceptance
+/// the request may pay up to these caps.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct EscalationStep {
+    pub after_secs: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+}
+
+/// Blob gas consumed per blob, fixed by EIP-4844.
+pub const BLOB_GAS_PER_BLOB: u64 = 131_072;
+
+/// What a blob-carrying (type-3) request posts and will pay for it.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct BlobParams {
+    pub max_fee_per_blob_gas: Wei,
+    pub blob_count: u8,
+}
+
+impl BlobParams {
+    pub fn blob_gas(&self) -> u64 {
+        self.blob_count as u64 * BLOB_GAS_PER_BLOB
+    }
+}
+
+/// The transaction type a request is priced and built as.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum FeeMode {
+    /// Type-2: `max_fee_per_gas` caps the fee and `max_priority_fee_per_gas` the tip.
+    #[default]
+    Eip1559,
+    /// Type-0, for chains and tooling without EIP-1559: one gas price, paid in full.
+    /// `max_fee_per_gas` caps it, and `max_priority_fee_per_gas` is what it offers
+    /// above the base fee.
+    Legacy,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct TransactionRequest {
     pub id: u64,
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
     pub from: [u8; 20],
-    pub to: [u8; 20],
+    /// None deploys `data` as a new contract.
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::option_array"))]
+    pub to: Option<[u8; 20]>,
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::vec"))]
     pub data: Vec<u8>,
-    pub value: [u8; 32], // U256 as bytes
-    pub max_fee_per_gas: u64,
-    pub max_priority_fee_per_gas: u64,
-    pub deadline: Option<u64>,
+    /// U256, big-endian.
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::quantity"))]
+    pub value: [u8; 32],
+    pub gas_limit: u64,
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+    pub deadline: Option<Deadline>,
+    pub urgency: Urgency,
+    /// After waiting this many blocks for a better price, price for inclusion instead.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub max_wait_blocks: Option<u32>,
+    /// Caps that replace the ones above as time passes; they only ever rise.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub escalation: Option<Vec<EscalationStep>>,
+    /// Chain the tx is for; None means the scheduler's configured chain.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub chain_id: Option<u64>,
+    /// Set for rollup data postings and other blob txs.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub blob: Option<BlobParams>,
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub fee_mode: FeeMode,
+    /// Client-chosen, so a retried submission is safe: a request whose key the
+    /// scheduler saw within `idempotency_window` is acknowledged, not scheduled.
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::option_array"))]
+    pub idempotency_key: Option<[u8; 16]>,
+    /// EIP-2930 accounts and storage slots to warm up front. Passed through to the
+    /// built tx as is; an empty list is the same as none.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub access_list: Option<Vec<AccessListItem>>,
+    /// Makes the request an EIP-7702 (type-4) tx, delegating each signer's
+    /// account to the code at `address`. Priced like any EIP-1559 request.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub authorization_list: Option<Vec<SignedAuthorization>>,
+    /// Where the executor sends the tx; see `SubmissionPrivacy`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub privacy: SubmissionPrivacy,
+}
+
+/// Whether a request's txs may be seen in the public mempool before they're mined.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SubmissionPrivacy {
+    /// Broadcast over the public RPC.
+    #[default]
+    Public,
+    /// Sent only to a private relay, which hands it to block builders directly.
+    /// Never falls back to the public RPC.
+    PrivateRelay,
+}
+
+/// One EIP-2930 access list entry: an account and the storage slots the tx reads
+/// or writes in it.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AccessListItem {
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+    pub address: [u8; 20],
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::array_vec"))]
+    pub storage_keys: Vec<[u8; 32]>,
+}
+
+/// An EIP-7702 authorization as the account owner signed it. The signature is
+/// checked for shape only; recovering the signer is left to the chain.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SignedAuthorization {
+    /// 0 authorizes on every chain.
+    pub chain_id: u64,
+    /// Contract whose code the signer's account runs.
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+    pub address: [u8; 20],
+    /// The signer's nonce at the time the authorization is applied.
+    pub nonce: u64,
+    pub y_parity: u8,
+    /// Big-endian.
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+    pub r: [u8; 32],
+    /// Big-endian.
+    #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+    pub s: [u8; 32],
+}
+
+impl SignedAuthorization {
+    /// Whether the signature could be valid: `y_parity` is 0 or 1, `r` is
+    /// non-zero, and `s` is non-zero and in the lower half of the curve order, as
+    /// EIP-2 requires.
+    pub fn is_well_formed(&self) -> bool {
+        let s = U256::from_be_bytes(self.s);
+        self.y_parity <= 1 && self.r != [0; 32] && s != U256::ZERO && s <= SECP256K1N_HALF
+    }
 }
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+/// Half the secp256k1 curve order; a larger `s` is a malleated signature.
+const SECP256K1N_HALF: U256 = U256::from_be_bytes(alloy_primitives::hex!(
+    "7fffffffffffffffffffffffffffffff5d576e7357a4501ddfe92f46681b20a0"
+));
+
+/// Most addresses plus storage keys a request's access list may hold.
+pub const MAX_ACCESS_LIST_ENTRIES: usize = 1024;
+
+/// EIP-2930 gas per access list address and per storage key.
+const ACCESS_LIST_ADDRESS_GAS: u64 = 2_400;
+const ACCESS_LIST_STORAGE_KEY_GAS: u64 = 1_900;
+/// EIP-7702 gas per authorization, charged as if its account were empty.
+const AUTHORIZATION_GAS: u64 = 25_000;
+
+impl TransactionRequest {
+    pub fn is_create(&self) -> bool {
+        self.to.is_none()
+    }
+
+    /// Most the tx can cost its sender: `value + max_fee_per_gas * gas_limit`, plus
+    /// `max_fee_per_blob_gas` for each unit of blob gas, in wei.
+    pub fn max_cost(&self) -> Wei {
+        // value is big-endian; anything past 128 bits is unaffordable anyway
+        let value = if self.value[..16].iter().any(|&b| b != 0) {
+            u128::MAX
+        } else {
+            u128::from_be_bytes(self.value[16..].try_into().unwrap())
+        };
+        let blob_cost = self.blob.map_or(Wei::ZERO, |blob| {
+            blob.max_fee_per_blob_gas.saturating_mul(blob.blob_gas())
+        });
+        Wei(value)
+            .saturating_add(self.max_fee_per_gas.saturating_mul(self.gas_limit))
+            .saturating_add(blob_cost)
+    }
+
+    /// Gas the tx burns before executing anything: the base cost, plus contract
+    /// creation and init code word costs for deployments, plus calldata, the
+    /// access list and authorizations.
+    pub fn intrinsic_gas(&self) -> u64 {
+        let zeros = self.data.iter().filter(|&&b| b == 0).count() as u64;
+        let non_zeros = self.data.len() as u64 - zeros;
+        let mut gas = TX_BASE_GAS + zeros * 4 + non_zeros * 16;
+        if self.is_create() {
+            gas += 32_000 + 2 * (self.data.len() as u64).div_ceil(32);
+        }
+        for item in self.access_list.iter().flatten() {
+            gas += ACCESS_LIST_ADDRESS_GAS
+                + ACCESS_LIST_STORAGE_KEY_GAS * item.storage_keys.len() as u64;
+        }
+        let authorizations = self.authorization_list.as_ref().map_or(0, Vec::len);
+        gas + AUTHORIZATION_GAS * authorizations as u64
+    }
+
+    /// Addresses plus storage keys in the access list.
+    pub fn access_list_entries(&self) -> usize {
+        self.access_list
+            .iter()
+            .flatten()
+            .map(|item| 1 + item.storage_keys.len())
+            .sum()
+    }
+
+    /// Checks the request is something an executor could actually build and that
+    /// is still worth scheduling at unix time `now_secs`.
+    pub fn validate(&self, now_secs: u64) -> Result<(), ValidationError> {
+        if self.is_create() && self.data.is_empty() {
+            return Err(ValidationError::EmptyInitCode);
+        }
+        if let Some(blob) = self.blob {
+            if blob.blob_count == 0 {
+                return Err(ValidationError::ZeroBlobCount);
+            }
+            // type-3 txs can't deploy contracts
+            if self.is_create() {
+                return Err(ValidationError::BlobCreate);
+            }
+            if self.fee_mode == FeeMode::Legacy {
+                return Err(ValidationError::LegacyBlob);
+            }
+        }
+        if let Some(items) = self
+            .access_list
+            .as_deref()
+            .filter(|items| !items.is_empty())
+        {
+            // type-0 txs have nowhere to put one
+            if self.fee_mode == FeeMode::Legacy {
+                return Err(ValidationError::LegacyAccessList);
+            }
+            let entries = self.access_list_entries();
+            if entries > MAX_ACCESS_LIST_ENTRIES {
+                return Err(ValidationError::AccessListTooLarge {
+                    entries: entries as u64,
+                    max: MAX_ACCESS_LIST_ENTRIES as u64,
+                });
+            }
+            let mut seen = std::collections::HashSet::new();
+            if let Some(item) = items.iter().find(|item| !seen.insert(item.address)) {
+                return Err(ValidationError::DuplicateAccessListAddress {
+                    address: item.address,
+                });
+            }
+        }
+        if let Some(authorizations) = &self.authorization_list {
+            if authorizations.is_empty() {
+                return Err(ValidationError::EmptyAuthorizationList);
+            }
+            // type-4 txs can't deploy contracts or carry blobs
+            if self.is_create() {
+                return Err(ValidationError::AuthorizationCreate);
+            }
+            if self.blob.is_some() {
+                return Err(ValidationError::BlobAuthorization);
+            }
+            if self.fee_mode == FeeMode::Legacy {
+                return Err(ValidationError::LegacyAuthorization);
+            }
+            if let Some(index) = authorizations
+                .iter()
+                .position(|auth| !auth.is_well_formed())
+            {
+                return Err(ValidationError::MalformedAuthorization {
+                    index: index as u64,
+                });
+            }
+        }
+        let intrinsic = self.intrinsic_gas();
+        if self.gas_limit < intrinsic {
+            return Err(ValidationError::GasLimitBelowIntrinsic {
+                gas_limit: self.gas_limit,
+                intrinsic,
+            });
+        }
+        // the scheduler prices every tx from the request's caps
+        if self.max_fee_per_gas == Wei::ZERO {
+            return Err(ValidationError::ZeroMaxFee);
+        }
+        check_tip(self.max_fee_per_gas, self.max_priority_fee_per_gas)?;
+        if let Some(deadline) = self.deadline
+            && deadline <= now_secs
+        {
+            return Err(ValidationError::DeadlinePassed {
+                deadline,
+                now: now_secs,
+            });
+        }
+        if let Some(steps) = &self.escalation {
+            let mut prev = (None, self.max_fee_per_gas, self.max_priority_fee_per_gas);
+            for step in steps {
+                if prev.0.is_some_and(|after| step.after_secs <= after)
+                    || step.max_fee_per_gas < prev.1
+                    || step.max_priority_fee_per_gas < prev.2
+                {
+                    return Err(ValidationError::EscalationNotMonotonic);
+                }
+                check_tip(step.max_fee_per_gas, step.max_priority_fee_per_gas)?;
+                prev = (
+                    Some(step.after_secs),
+                    step.max_fee_per_gas,
+                    step.max_priority_fee_per_gas,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// The last escalation step reached `elapsed_secs` after acceptance, if any.
+    pub fn escalation_step(&self, elapsed_secs: u64) -> Option<&EscalationStep> {
+        self.escalation
+            .as_deref()?
+            .iter()
+            .rev()
+            .find(|step| step.after_secs <= elapsed_secs)
+    }
+}
+
+/// A one-line summary; calldata and escalation steps are left out.
+impl fmt::Display for TransactionRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tx {} from {} ", self.id, short_address(&self.from))?;
+        match &self.to {
+            Some(to) => write!(f, "to {}", short_address(to))?,
+            None => write!(f, "creating a contract")?,
+        }
+        let value = U256::from_be_bytes(self.value);
+        if value != U256::ZERO {
+            match u128::try_from(value) {
+                Ok(wei) => write!(f, ", value {}", format_ether(wei))?,
+                Err(_) => write!(f, ", value {} wei", value)?,
+            }
+        }
+        match self.fee_mode {
+            FeeMode::Eip1559 => write!(
+                f,
+                ", gas {} at max fee {} tip {}",
+                self.gas_limit, self.max_fee_per_gas, self.max_priority_fee_per_gas
+            )?,
+            FeeMode::Legacy => write!(
+                f,
+                ", gas {} at legacy gas price up to {}",
+                self.gas_limit, self.max_fee_per_gas
+            )?,
+        }
+        if let Some(blob) = &self.blob {
+            write!(
+                f,
+                ", {} blobs at {}",
+                blob.blob_count, blob.max_fee_per_blob_gas
+            )?;
+        }
+        write!(f, ", {:?}", self.urgency)?;
+        if let Some(chain_id) = self.chain_id {
+            write!(f, ", chain {}", chain_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Gas every tx pays before calldata and execution.
+pub const TX_BASE_GAS: u64 = 21_000;
+
+fn check_tip(max_fee_per_gas: Wei, max_priority_fee_per_gas: Wei) -> Result<(), ValidationError> {
+    if max_priority_fee_per_gas > max_fee_per_gas {
+        return Err(ValidationError::PriorityFeeAboveMaxFee {
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+        });
+    }
+    Ok(())
+}
+
+/// Why `TransactionRequest::validate` turned a request away.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ValidationError {
+    /// A deployment (`to` is None) without any init code.
+    EmptyInitCode,
+    /// Escalation steps must come later and pay no less than the caps before them.
+    EscalationNotMonotonic,
+    /// The tip cap exceeds the fee cap, here or in an escalation step.
+    PriorityFeeAboveMaxFee {
+        max_priority_fee_per_gas: Wei,
+        max_fee_per_gas: Wei,
+    },
+    ZeroMaxFee,
+    DeadlinePassed {
+        deadline: Deadline,
+        now: u64,
+    },
+    /// The tx would run out of gas before executing; see
+    /// `TransactionRequest::intrinsic_gas`.
+    GasLimitBelowIntrinsic {
+        gas_limit: u64,
+        intrinsic: u64,
+    },
+    /// Blob params that don't post any blobs.
+    ZeroBlobCount,
+    /// A blob tx without a recipient.
+    BlobCreate,
+    /// Blob txs are type-3 and can't be priced in `FeeMode::Legacy`.
+    LegacyBlob,
+    /// Access lists need a typed tx, so can't go with `FeeMode::Legacy`.
+    LegacyAccessList,
+    /// More addresses plus storage keys than `MAX_ACCESS_LIST_ENTRIES`.
+    AccessListTooLarge {
+        entries: u64,
+        max: u64,
+    },
+    /// Each account goes in the access list once, with all of its storage keys.
+    DuplicateAccessListAddress {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+    },
+    /// An authorization list must authorize something; leave it unset instead.
+    EmptyAuthorizationList,
+    /// A type-4 tx without a recipient.
+    AuthorizationCreate,
+    /// A request can be a blob tx or a type-4 tx, not both.
+    BlobAuthorization,
+    /// Type-4 txs can't be priced in `FeeMode::Legacy`.
+    LegacyAuthorization,
+    /// The authorization at `index` fails `SignedAuthorization::is_well_formed`.
+    MalformedAuthorization {
+        index: u64,
+    },
+    /// Blob txs aren't taken here; see `SchedulerConfig::reject_blob_requests`.
+    BlobUnsupported,
+}
+
+impl std::fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ValidationError::EmptyInitCode => write!(f, "contract creation without init code"),
+            ValidationError::EscalationNotMonotonic => {
+                write!(f, "escalation steps must rise in both time and price")
+            }
+            ValidationError::PriorityFeeAboveMaxFee {
+                max_priority_fee_per_gas,
+                max_fee_per_gas,
+            } => write!(
+                f,
+                "priority fee cap {} above fee cap {}",
+                max_priority_fee_per_gas, max_fee_per_gas
+            ),
+            ValidationError::ZeroMaxFee => write!(f, "zero fee cap"),
+            ValidationError::DeadlinePassed { deadline, now } => {
+                write!(f, "deadline {} already passed at {}", deadline, now)
+            }
+            ValidationError::GasLimitBelowIntrinsic {
+                gas_limit,
+                intrinsic,
+            } => write!(
+                f,
+                "gas limit {} below intrinsic gas {}",
+                gas_limit, intrinsic
+            ),
+            ValidationError::ZeroBlobCount => write!(f, "blob params with zero blobs"),
+            ValidationError::BlobCreate => write!(f, "blob tx without a recipient"),
+            ValidationError::LegacyBlob => write!(f, "blob tx in legacy fee mode"),
+            ValidationError::LegacyAccessList => write!(f, "access list in legacy fee mode"),
+            ValidationError::AccessListTooLarge { entries, max } => {
+                write!(f, "access list has {} entries, more than {}", entries, max)
+            }
+            ValidationError::DuplicateAccessListAddress { address } => write!(
+                f,
+                "{} appears twice in the access list",
+                Address::from(*address)
+            ),
+            ValidationError::EmptyAuthorizationList => write!(f, "empty authorization list"),
+            ValidationError::AuthorizationCreate => {
+                write!(f, "authorization list without a recipient")
+            }
+            ValidationError::BlobAuthorization => {
+                write!(f, "blob tx with an authorization list")
+            }
+            ValidationError::LegacyAuthorization => {
+                write!(f, "authorization list in legacy fee mode")
+            }
+            ValidationError::MalformedAuthorization { index } => {
+                write!(f, "authorization {} has a malformed signature", index)
+            }
+            ValidationError::BlobUnsupported => write!(f, "blob txs are not accepted"),
+        }
+    }
+}
+
+impl std::error::Error for ValidationError {}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub enum SchedulerDecision {
     Submit {
         tx_id: u64,
         nonce: u64,
-        gas_price: u64,
+        gas_price: Wei,
+        /// The tip to sign: the request's, clamped to the scheduler's ceiling for
+        /// the current mode and never above `gas_price`. Unused in `Legacy` mode.
+        #[cfg_attr(feature = "serde", serde(default))]
+        max_priority_fee_per_gas: Wei,
+        /// The executor must build a contract creation tx.
+        create: bool,
+        /// `gas_price * gas_limit`, the most this submission is expected to cost.
+        estimated_cost_wei: Wei,
+        /// What submitting at acceptance-time prices would have cost beyond
+        /// `estimated_cost_wei`, never negative.
+        estimated_savings_wei: Wei,
+        /// `max_fee_per_blob_gas` for a blob request's type-3 tx; None otherwise.
+        #[cfg_attr(feature = "serde", serde(default))]
+        blob_gas_price: Option<Wei>,
+        /// The request's; in `Legacy` mode `gas_price` is the tx's `gasPrice`,
+        /// otherwise its `max_fee_per_gas`.
+        #[cfg_attr(feature = "serde", serde(default))]
+        fee_mode: FeeMode,
+        /// The request's, so the executor knows where to send the tx.
+        #[cfg_attr(feature = "serde", serde(default))]
+        privacy: SubmissionPrivacy,
     },
+    /// The tx stays pending. `retry_hint` says when waiting could end, where the
+    /// scheduler can tell.
     Defer {
         tx_id: u64,
-        reason: String,
+        reason: DeferReason,
+        #[cfg_attr(feature = "serde", serde(default))]
+        retry_hint: Option<RetryHint>,
     },
     Reprice {
         tx_id: u64,
         old_nonce: u64,
-        new_gas_price: u64,
+        new_gas_price: Wei,
+        /// Like `Submit`'s, against `new_gas_price`.
+        #[cfg_attr(feature = "serde", serde(default))]
+        max_priority_fee_per_gas: Wei,
+        /// Like `Submit`'s; never lower than the one sent before.
+        #[cfg_attr(feature = "serde", serde(default))]
+        blob_gas_price: Option<Wei>,
+        #[cfg_attr(feature = "serde", serde(default))]
+        fee_mode: FeeMode,
+        #[cfg_attr(feature = "serde", serde(default))]
+        privacy: SubmissionPrivacy,
     },
     Drop {
         tx_id: u64,
         reason: String,
     },
+    /// The request was refused outright, or a command about it couldn't be carried
+    /// out. `error` is set when the request itself failed validation.
+    Rejected {
+        tx_id: u64,
+        reason: String,
+        #[cfg_attr(feature = "serde", serde(default))]
+        error: Option<ValidationError>,
+    },
+    ModeChanged {
+        spike: bool,
+    },
+    /// Txs from `address` are held until its starting nonce is supplied, e.g. with
+    /// `SchedulerCommand::InitNonce`. Sent once per address.
+    NonceInitRequired {
+        chain_id: u64,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+    },
+    /// `address` has txs in flight above nonces no tx holds; they can't be mined
+    /// until `missing` are filled.
+    NonceGapDetected {
+        chain_id: u64,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+        missing: Vec<u64>,
+    },
+    /// Broadcast a zero-value self-transfer from `address` at `nonce` at `gas_price`,
+    /// so the txs queued above the gap can be mined. Tracked under `tx_id` like a
+    /// Submit.
+    FillNonceGap {
+        tx_id: u64,
+        chain_id: u64,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+        nonce: u64,
+        gas_price: Wei,
+        /// The sender's own tx type, taken from its txs in flight.
+        #[cfg_attr(feature = "serde", serde(default))]
+        fee_mode: FeeMode,
+    },
+    /// The network is already past the submitted tx's nonce: either the tx was mined
+    /// without us seeing it or another tx took the nonce. The tx is no longer tracked.
+    NonceConsumed {
+        tx_id: u64,
+        nonce: u64,
+    },
+    /// A restored tx was left out because its nonce can't be trusted: the chain
+    /// already used it, or another tx claims it.
+    NonceConflict {
+        tx_id: u64,
+        chain_id: u64,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+        nonce: u64,
+        reason: String,
+    },
+    /// The chain switched branches at `fork_block`, discarding `depth` blocks the
+    /// scheduler had seen. Txs in `unconfirmed` lost their confirmation and are being
+    /// tracked as submitted again.
+    Reorg {
+        fork_block: u64,
+        depth: u64,
+        unconfirmed: Vec<u64>,
+    },
+    /// Market context behind the surrounding pricing decisions.
+    MarketUpdate {
+        current_fee: Wei,
+        volatility: f64,
+        trend: f64,
+        spike: bool,
+    },
+    /// What a mined tx actually cost, from its receipt. Savings are never
+    /// negative.
+    Confirmed {
+        tx_id: u64,
+        block_number: u64,
+        effective_gas_price: Wei,
+        /// `effective_gas_price * gas_used`.
+        realized_cost_wei: Wei,
+        /// Below paying the max fee the tx was last sent with for the same gas.
+        savings_vs_max_fee_wei: Wei,
+        /// Below paying the acceptance-time base fee plus the same tip.
+        savings_vs_acceptance_wei: Wei,
+    },
+}
+
+fn legacy_suffix(fee_mode: FeeMode) -> &'static str {
+    match fee_mode {
+        FeeMode::Eip1559 => "",
+        FeeMode::Legacy => " (legacy)",
+    }
+}
+
+fn privacy_suffix(privacy: SubmissionPrivacy) -> &'static str {
+    match privacy {
+        SubmissionPrivacy::Public => "",
+        SubmissionPrivacy::PrivateRelay => " via private relay",
+    }
+}
+
+impl fmt::Display for SchedulerDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SchedulerDecision::Submit {
+                tx_id,
+                nonce,
+                gas_price,
+                create,
+                estimated_cost_wei,
+                estimated_savings_wei,
+                blob_gas_price,
+                fee_mode,
+                privacy,
+                ..
+            } => {
+                write!(
+                    f,
+                    "submit tx {}{} at nonce {} for {}{}{}",
+                    tx_id,
+                    if *create { " (create)" } else { "" },
+                    nonce,
+                    gas_price,
+                    legacy_suffix(*fee_mode),
+                    privacy_suffix(*privacy)
+                )?;
+                if let Some(price) = blob_gas_price {
+                    write!(f, ", blob gas {}", price)?;
+                }
+                write!(
+                    f,
+                    ", up to {}, saving {}",
+                    format_ether(estimated_cost_wei.0),
+                    format_ether(estimated_savings_wei.0)
+                )
+            }
+            SchedulerDecision::Defer {
+                tx_id,
+                reason,
+                retry_hint,
+            } => {
+                write!(f, "defer tx {}: {}", tx_id, reason)?;
+                if let Some(hint) = retry_hint {
+                    write!(f, ", {}", hint)?;
+                }
+                Ok(())
+            }
+            SchedulerDecision::Reprice {
+                tx_id,
+                old_nonce,
+                new_gas_price,
+                blob_gas_price,
+                fee_mode,
+                privacy,
+                ..
+            } => {
+                write!(
+                    f,
+                    "reprice tx {} at nonce {} to {}{}{}",
+                    tx_id,
+                    old_nonce,
+                    new_gas_price,
+                    legacy_suffix(*fee_mode),
+                    privacy_suffix(*privacy)
+                )?;
+                if let Some(price) = blob_gas_price {
+                    write!(f, ", blob gas {}", price)?;
+                }
+                Ok(())
+            }
+            SchedulerDecision::Drop { tx_id, reason } => {
+                write!(f, "drop tx {}: {}", tx_id, reason)
+            }
+            SchedulerDecision::Rejected { tx_id, reason, .. } => {
+                write!(f, "reject tx {}: {}", tx_id, reason)
+            }
+            SchedulerDecision::ModeChanged { spike } => {
+                write!(f, "spike mode {}", if *spike { "on" } else { "off" })
+            }
+            SchedulerDecision::NonceInitRequired { chain_id, address } => write!(
+                f,
+                "sender {} on chain {} needs its starting nonce",
+                short_address(address),
+                chain_id
+            ),
+            SchedulerDecision::NonceGapDetected {
+                chain_id,
+                address,
+                missing,
+            } => write!(
+                f,
+                "sender {} on chain {} is missing nonces {:?}",
+                short_address(address),
+                chain_id,
+                missing
+            ),
+            SchedulerDecision::FillNonceGap {
+                tx_id,
+                chain_id,
+                address,
+                nonce,
+                gas_price,
+                fee_mode,
+            } => write!(
+                f,
+                "fill nonce {} of sender {} on chain {} as tx {} for {}{}",
+                nonce,
+                short_address(address),
+                chain_id,
+                tx_id,
+                gas_price,
+                legacy_suffix(*fee_mode)
+            ),
+            SchedulerDecision::NonceConsumed { tx_id, nonce } => {
+                write!(f, "tx {} lost nonce {} on chain", tx_id, nonce)
+            }
+            SchedulerDecision::NonceConflict {
+                tx_id,
+                chain_id,
+                address,
+                nonce,
+                reason,
+            } => write!(
+                f,
+                "restored tx {} at nonce {} of sender {} on chain {} left out: {}",
+                tx_id,
+                nonce,
+                short_address(address),
+                chain_id,
+                reason
+            ),
+            SchedulerDecision::Reorg {
+                fork_block,
+                depth,
+                unconfirmed,
+            } => write!(
+                f,
+                "reorg at block {} discarded {} blocks, unconfirmed txs {:?}",
+                fork_block, depth, unconfirmed
+            ),
+            SchedulerDecision::MarketUpdate {
+                current_fee,
+                volatility,
+                trend,
+                spike,
+            } => write!(
+                f,
+                "market at {}, volatility {:.2}, trend {:.2}{}",
+                current_fee,
+                volatility,
+                trend,
+                if *spike { ", spike" } else { "" }
+            ),
+            SchedulerDecision::Confirmed {
+                tx_id,
+                block_number,
+                effective_gas_price,
+                realized_cost_wei,
+                savings_vs_max_fee_wei,
+                savings_vs_acceptance_wei,
+            } => write!(
+                f,
+                "tx {} confirmed in block {} at {}, paid {}, saved {} on the max fee and {} on acceptance",
+                tx_id,
+                block_number,
+                effective_gas_price,
+                format_ether(realized_cost_wei.0),
+                format_ether(savings_vs_max_fee_wei.0),
+                format_ether(savings_vs_acceptance_wei.0)
+            ),
+        }
+    }
+}
+
+impl SchedulerDecision {
+    /// The tx the decision is about; None for market-wide and per-sender ones.
+    pub fn tx_id(&self) -> Option<u64> {
+        match *self {
+            SchedulerDecision::Submit { tx_id, .. }
+            | SchedulerDecision::Defer { tx_id, .. }
+            | SchedulerDecision::Reprice { tx_id, .. }
+            | SchedulerDecision::Drop { tx_id, .. }
+            | SchedulerDecision::Rejected { tx_id, .. }
+            | SchedulerDecision::FillNonceGap { tx_id, .. }
+            | SchedulerDecision::NonceConsumed { tx_id, .. }
+            | SchedulerDecision::NonceConflict { tx_id, .. }
+            | SchedulerDecision::Confirmed { tx_id, .. } => Some(tx_id),
+            SchedulerDecision::ModeChanged { .. }
+            | SchedulerDecision::NonceInitRequired { .. }
+            | SchedulerDecision::NonceGapDetected { .. }
+            | SchedulerDecision::Reorg { .. }
+            | SchedulerDecision::MarketUpdate { .. } => None,
+        }
+    }
+
+    /// Snake-case name of the variant, for labels and columns.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            SchedulerDecision::Submit { .. } => "submit",
+            SchedulerDecision::Defer { .. } => "defer",
+            SchedulerDecision::Reprice { .. } => "reprice",
+            SchedulerDecision::Drop { .. } => "drop",
+            SchedulerDecision::Rejected { .. } => "rejected",
+            SchedulerDecision::ModeChanged { .. } => "mode_changed",
+            SchedulerDecision::NonceInitRequired { .. } => "nonce_init_required",
+            SchedulerDecision::NonceGapDetected { .. } => "nonce_gap_detected",
+            SchedulerDecision::FillNonceGap { .. } => "fill_nonce_gap",
+            SchedulerDecision::NonceConsumed { .. } => "nonce_consumed",
+            SchedulerDecision::NonceConflict { .. } => "nonce_conflict",
+            SchedulerDecision::Reorg { .. } => "reorg",
+            SchedulerDecision::MarketUpdate { .. } => "market_update",
+            SchedulerDecision::Confirmed { .. } => "confirmed",
+        }
+    }
+}
+
+/// The request a decision traces back to: its id, and its idempotency key if
+/// it had one.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct CorrelationId {
+    pub tx_id: u64,
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::option_array"))]
+    pub idempotency_key: Option<[u8; 16]>,
+}
+
+/// A decision as the scheduler emitted it, for sinks that take more than the
+/// bare decision; see `DecisionSink::deliver_record`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DecisionRecord {
+    /// Counts up from 1 over everything this scheduler emits, so decisions about
+    /// one tx can be put in order. 0 for a decision delivered without a record.
+    pub seq: u64,
+    /// None for decisions that aren't about one tx.
+    pub correlation_id: Option<CorrelationId>,
+    /// Set when the decision repeats an earlier one, to acknowledge a request
+    /// whose idempotency key was already seen; it calls for no new action.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub replayed: bool,
+    pub meta: DecisionMeta,
+    pub decision: SchedulerDecision,
+}
+
+/// When and on what grounds a decision was made.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct DecisionMeta {
+    /// Wall-clock time of the decision, following tokio's clock like deadlines do.
+    pub decided_at_unix_ms: u64,
+    pub trigger: DecisionTrigger,
+    /// Block of the gas event that triggered the decision; the newest block seen
+    /// when the event names none or something else triggered it.
+    pub block_number: Option<u64>,
+    /// Timestamp of the triggering `BaseFeeUpdate`; otherwise of the newest one.
+    pub timestamp: Option<u64>,
+    pub market: MarketSnapshot,
+}
+
+/// What set off the evaluation pass a decision came out of.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DecisionTrigger {
+    /// Delivered to a sink directly, not emitted by a scheduler.
+    #[default]
+    External,
+    GasEvent,
+    Request,
+    Command,
+    /// The periodic sweep for expired txs.
+    Tick,
+}
+
+/// The fee model's view of the market when a decision was made.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MarketSnapshot {
+    pub current_fee: Wei,
+    /// In wei per block.
+    pub volatility: f64,
+    pub spike: bool,
+}
+
+/// Without a sequence number, correlation id or meta.
+impl From<SchedulerDecision> for DecisionRecord {
+    fn from(decision: SchedulerDecision) -> Self {
+        Self {
+            seq: 0,
+            correlation_id: None,
+            replayed: false,
+            meta: DecisionMeta::default(),
+            decision,
+        }
+    }
+}
+
+impl fmt::Display for DecisionRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{} {}", self.seq, self.decision)?;
+        if self.replayed {
+            write!(f, " (replayed)")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<DecisionRecord> for SchedulerDecision {
+    fn from(record: DecisionRecord) -> Self {
+        record.decision
+    }
+}
+
+/// Why a `Defer` was sent. `Display` gives the wording logs and
+/// `TxStatus::Deferred` carry.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum DeferReason {
+    /// The base fee is above the tx's `max_fee_per_gas`.
+    FeeAboveMax,
+    /// Above the cap but falling; at the current trend it fits in about
+    /// `estimated_blocks`.
+    TrendWait {
+        estimated_blocks: u32,
+    },
+    /// The base fee is above the configured target and the tx's deadline isn't
+    /// within the escalation window yet.
+    OutsideWindow {
+        target_base_fee: Wei,
+    },
+    /// No blob base fee has been seen yet.
+    BlobFeeUnknown,
+    BlobFeeAboveMax {
+        blob_base_fee: Wei,
+        max_fee_per_blob_gas: Wei,
+    },
+    /// The sender's starting nonce hasn't been supplied.
+    NonceUninitialized,
+    /// Fetching the sender's starting nonce failed.
+    NonceUnavailable(String),
+    /// The sender already has `max_inflight` nonces ahead of the chain.
+    NonceWindowFull,
+    NonceReservationExpired,
+    /// The sender's balance couldn't be fetched and the policy is to fail closed.
+    BalanceUnavailable,
+    /// The sender can't cover this tx on top of what it already has in flight.
+    InsufficientBalance,
+}
+
+impl fmt::Display for DeferReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DeferReason::FeeAboveMax => write!(f, "fee above cap"),
+            DeferReason::TrendWait { estimated_blocks } => write!(
+                f,
+                "fee above cap, trending down (~{} blocks)",
+                estimated_blocks
+            ),
+            DeferReason::OutsideWindow { target_base_fee } => {
+                write!(f, "waiting for target base fee {}", target_base_fee)
+            }
+            DeferReason::BlobFeeUnknown => write!(f, "blob base fee unknown"),
+            DeferReason::BlobFeeAboveMax {
+                blob_base_fee,
+                max_fee_per_blob_gas,
+            } => write!(
+                f,
+                "blob base fee {} above cap {}",
+                blob_base_fee, max_fee_per_blob_gas
+            ),
+            DeferReason::NonceUninitialized => write!(f, "nonce not initialized"),
+            DeferReason::NonceUnavailable(e) => write!(f, "nonce initialization failed: {}", e),
+            DeferReason::NonceWindowFull => write!(f, "in-flight nonce window full"),
+            DeferReason::NonceReservationExpired => write!(f, "nonce reservation expired"),
+            DeferReason::BalanceUnavailable => write!(f, "balance unavailable"),
+            DeferReason::InsufficientBalance => write!(f, "insufficient balance"),
+        }
+    }
+}
+
+/// When a deferred tx is next worth checking on.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RetryHint {
+    AfterDuration(
+        #[borsh(
+            serialize_with = "duration_millis::serialize",
+            deserialize_with = "duration_millis::deserialize"
+        )]
+        Duration,
+    ),
+    AfterBlocks(u32),
+    /// Once the base fee is at or below this.
+    WhenFeeBelow(Wei),
+}
+
+impl fmt::Display for RetryHint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RetryHint::AfterDuration(wait) => write!(f, "retry in {}s", wait.as_secs()),
+            RetryHint::AfterBlocks(blocks) => write!(f, "retry in {} blocks", blocks),
+            RetryHint::WhenFeeBelow(fee) => {
+                write!(f, "retry at base fee {}", fee)
+            }
+        }
+    }
+}
+
+/// `Duration` has no borsh impl; it goes on the wire as whole milliseconds.
+mod duration_millis {
+    use borsh::{BorshDeserialize, BorshSerialize};
+    use std::time::Duration;
+
+    pub fn serialize<W: std::io::Write>(wait: &Duration, writer: &mut W) -> std::io::Result<()> {
+        (wait.as_millis() as u64).serialize(writer)
+    }
+
+    pub fn deserialize<R: std::io::Read>(reader: &mut R) -> std::io::Result<Duration> {
+        u64::deserialize_reader(reader).map(Duration::from_millis)
+    }
+}
+
+/// Lifecycle updates pushed to a caller that asked to watch one request.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum TxStatus {
+    Pending,
+    Deferred(String),
+    Submitted {
+        nonce: u64,
+        gas_price: Wei,
+    },
+    Repriced {
+        new_gas_price: Wei,
+    },
+    Broadcast {
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        tx_hash: [u8; 32],
+    },
+    Confirmed {
+        block_number: u64,
+    },
+    /// Confirmed or replaced; see `SchedulerDecision::NonceConsumed`.
+    NonceConsumed {
+        nonce: u64,
+    },
+    Dropped(String),
+    Rejected(String),
+    /// Taken out of pending by `SchedulerCommand::Cancel`.
+    Cancelled,
+}
+
+impl TxStatus {
+    /// No further updates follow a terminal status.
+    pub fn is_terminal(&self) -> bool {
+        matches!(
+            self,
+            TxStatus::Confirmed { .. }
+                | TxStatus::NonceConsumed { .. }
+                | TxStatus::Dropped(_)
+                | TxStatus::Rejected(_)
+                | TxStatus::Cancelled
+        )
+    }
+}
+
+/// Control messages for a running scheduler, delivered alongside gas events and requests.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum SchedulerCommand {
+    /// Put a recently dropped request back into pending under its original id,
+    /// optionally overriding its fee cap and deadline.
+    Resubmit {
+        tx_id: u64,
+        new_max_fee_per_gas: Option<Wei>,
+        new_deadline: Option<Deadline>,
+    },
+    /// The executor broadcast a submitted tx under this hash; lets `TxConfirmed`
+    /// events be matched back to the request.
+    Broadcast {
+        tx_id: u64,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        tx_hash: [u8; 32],
+    },
+    /// The executor could not sign or broadcast a submitted tx. Its nonce is handed
+    /// back and the request is dropped, resubmittable like any other drop.
+    BroadcastFailed { tx_id: u64, reason: String },
+    /// Reports `address`'s on-chain transaction count on `chain_id` (the scheduler's
+    /// configured chain if None); see `NonceManager::update_nonce`. With `force` the
+    /// account is reset to `nonce` even if that lowers it, as an operator override.
+    InitNonce {
+        #[cfg_attr(feature = "serde", serde(default))]
+        chain_id: Option<u64>,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+        nonce: u64,
+        #[cfg_attr(feature = "serde", serde(default))]
+        force: bool,
+    },
+    /// The executor was told "nonce too low": `address` has already sent
+    /// `network_nonce` txs. See `NonceManager::resync`.
+    NonceTooLow {
+        #[cfg_attr(feature = "serde", serde(default))]
+        chain_id: Option<u64>,
+        #[cfg_attr(feature = "serde", serde(with = "hex_serde::array"))]
+        address: [u8; 20],
+        network_nonce: u64,
+    },
+    /// Submitted txs carried over from before a restart, e.g. from the executor's
+    /// records. Each sender's nonces are reconciled with them first (see
+    /// `NonceManager::reconcile`); a tx whose nonce conflicts gets a `NonceConflict`
+    /// decision and is not tracked.
+    RestoreSubmitted { txs: Vec<RestoredTx> },
+    /// Changes the shared submission limiter's refill rate, and its burst capacity
+    /// if given, e.g. to back off while the RPC provider is rate limiting us.
+    SetSubmissionRate {
+        tokens_per_sec: u64,
+        #[cfg_attr(feature = "serde", serde(default))]
+        burst: Option<u64>,
+    },
+    /// Withdraws a pending request, which is dropped with reason `cancelled`.
+    /// Submitted txs hold a nonce the executor may already be using, so they can't
+    /// be cancelled; the command is ignored for them.
+    Cancel { tx_id: u64 },
+}
+
+/// A submitted tx as the executor last knew it, for `RestoreSubmitted`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RestoredTx {
+    pub req: TransactionRequest,
+    pub nonce: u64,
+    pub gas_price: Wei,
+    /// Set if the tx was broadcast.
+    #[cfg_attr(feature = "serde", serde(default, with = "hex_serde::option_array"))]
+    pub tx_hash: Option<[u8; 32]>,
+}
+
+/// JSON forms for the byte fields above: 0x-hex strings out, with plain byte arrays
+/// still accepted in, as inputs were written before. Binary formats such as CBOR and
+/// bincode get the raw bytes.
+#[cfg(feature = "serde")]
+pub(crate) mod hex_serde {
+    use alloy_primitives::{U256, hex};
+    use serde::de::{Error, SeqAccess, Visitor};
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+    use std::fmt;
+
+    #[cfg_attr(feature = "serde", derive(Deserialize), serde(untagged))]
+    enum HexOrBytes {
+        Hex(String),
+        Bytes(Vec<u8>),
+    }
+
+    impl HexOrBytes {
+        fn into_bytes<E: Error>(self) -> Result<Vec<u8>, E> {
+            match self {
+                HexOrBytes::Hex(s) => hex::decode(&s).map_err(E::custom),
+                HexOrBytes::Bytes(bytes) => Ok(bytes),
+            }
+        }
+    }
+
+    /// Hex for human-readable formats, a byte string otherwise.
+    struct Hex<'a>(&'a [u8]);
+
+    impl Serialize for Hex<'_> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if serializer.is_human_readable() {
+                serializer.serialize_str(&hex::encode_prefixed(self.0))
+            } else {
+                serializer.serialize_bytes(self.0)
+            }
+        }
+    }
+
+    /// Reads what `Hex` writes.
+    struct Raw(Vec<u8>);
+
+    impl<'de> Deserialize<'de> for Raw {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            if deserializer.is_human_readable() {
+                HexOrBytes::deserialize(deserializer)?.into_bytes().map(Raw)
+            } else {
+                deserializer.deserialize_bytes(RawVisitor).map(Raw)
+            }
+        }
+    }
+
+    struct RawVisitor;
+
+    impl<'de> Visitor<'de> for RawVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.write_str("a byte string")
+        }
+
+        fn visit_bytes<E: Error>(self, bytes: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(bytes.to_vec())
+        }
+
+        fn visit_byte_buf<E: Error>(self, bytes: Vec<u8>) -> Result<Vec<u8>, E> {
+            Ok(bytes)
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                bytes.push(byte);
+            }
+            Ok(bytes)
+        }
+    }
+
+    fn to_array<const N: usize, E: Error>(bytes: Vec<u8>) -> Result<[u8; N], E> {
+        let len = bytes.len();
+        bytes
+            .try_into()
+            .map_err(|_| E::custom(format_args!("expected {N} bytes, got {len}")))
+    }
+
+    /// Fixed-size arrays: addresses and hashes.
+    pub mod array {
+        use super::*;
+
+        pub fn serialize<S: Serializer, const N: usize>(
+            bytes: &[u8; N],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            Hex(bytes).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+            deserializer: D,
+        ) -> Result<[u8; N], D::Error> {
+            to_array(Raw::deserialize(deserializer)?.0)
+        }
+    }
+
+    pub mod option_array {
+        use super::*;
+
+        pub fn serialize<S: Serializer, const N: usize>(
+            bytes: &Option<[u8; N]>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            bytes.as_ref().map(|bytes| Hex(bytes)).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+            deserializer: D,
+        ) -> Result<Option<[u8; N]>, D::Error> {
+            Option::<Raw>::deserialize(deserializer)?
+                .map(|raw| to_array(raw.0))
+                .transpose()
+        }
+    }
+
+    /// Lists of fixed-size arrays, such as storage keys.
+    pub mod array_vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer, const N: usize>(
+            items: &[[u8; N]],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            serializer.collect_seq(items.iter().map(|item| Hex(item)))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>, const N: usize>(
+            deserializer: D,
+        ) -> Result<Vec<[u8; N]>, D::Error> {
+            Vec::<Raw>::deserialize(deserializer)?
+                .into_iter()
+                .map(|raw| to_array(raw.0))
+                .collect()
+        }
+    }
+
+    /// Calldata; empty is `"0x"`.
+    pub mod vec {
+        use super::*;
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            Hex(bytes).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            Ok(Raw::deserialize(deserializer)?.0)
+        }
+    }
+
+    /// A big-endian U256 as a hex quantity (`"0xde0b6b3a7640000"`); decimal strings and
+    /// numbers are read too. Binary formats get the 32 bytes as they are.
+    pub mod quantity {
+        use super::*;
+
+        #[cfg_attr(feature = "serde", derive(Deserialize), serde(untagged))]
+        enum QuantityOrBytes {
+            Quantity(U256),
+            Bytes([u8; 32]),
+        }
+
+        pub fn serialize<S: Serializer>(
+            value: &[u8; 32],
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            if !serializer.is_human_readable() {
+                return Hex(value).serialize(serializer);
+            }
+            U256::from_be_bytes(*value).serialize(serializer)
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<[u8; 32], D::Error> {
+            if !deserializer.is_human_readable() {
+                return to_array(Raw::deserialize(deserializer)?.0);
+            }
+            Ok(match QuantityOrBytes::deserialize(deserializer)? {
+                QuantityOrBytes::Quantity(value) => value.to_be_bytes(),
+                QuantityOrBytes::Bytes(bytes) => bytes,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::U256;
+
+    #[test]
+    fn test_deploy_request_round_trips() {
+        let deploy = TransactionRequest {
+            to: None,
+            data: vec![0x60],
+            ..sample_request()
+        };
+        assert!(deploy.validate(0).is_ok());
+        assert_eq!(borsh_round_trip(&deploy), deploy);
+        assert!(deploy.is_create());
+    }
+
+    #[test]
+    fn test_legacy_request_round_trips() {
+        let legacy = TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..sample_request()
+        };
+        assert_eq!(borsh_round_trip(&legacy), legacy);
+        #[cfg(feature = "serde")]
+        assert_eq!(round_trip(&legacy)["fee_mode"], "Legacy");
+    }
+
+    #[test]
+    fn test_blob_request_round_trips() {
+        let blob = TransactionRequest {
+            blob: Some(BlobParams {
+                max_fee_per_blob_gas: Wei(10),
+                blob_count: 2,
+            }),
+            ..sample_request()
+        };
+        assert_eq!(borsh_round_trip(&blob), blob);
+        // 2 blobs at 10 wei per blob gas on top of 60k gas at 100 and 1 ether
+        assert_eq!(
+            blob.max_cost(),
+            Wei(10u128.pow(18) + 6_000_000 + 2 * 131_072 * 10)
+        );
+    }
+
+    #[test]
+    fn test_validation_rules() {
+        let now = 1_000;
+        let valid = || TransactionRequest {
+            deadline: Some(now + 1),
+            ..sample_request()
+        };
+        assert_eq!(valid().validate(now), Ok(()));
+        let check = |edit: fn(&mut TransactionRequest)| {
+            let mut req = valid();
+            edit(&mut req);
+            req.validate(now)
+        };
+
+        assert_eq!(
+            check(|r| r.max_priority_fee_per_gas = r.max_fee_per_gas + Wei(1)),
+            Err(ValidationError::PriorityFeeAboveMaxFee {
+                max_priority_fee_per_gas: Wei(101),
+                max_fee_per_gas: Wei(100),
+            })
+        );
+        // equal caps are fine
+        assert_eq!(
+            check(|r| {
+                r.max_priority_fee_per_gas = r.max_fee_per_gas;
+                r.escalation = None;
+            }),
+            Ok(())
+        );
+        assert_eq!(
+            check(|r| {
+                r.max_fee_per_gas = Wei(0);
+                r.max_priority_fee_per_gas = Wei(0);
+            }),
+            Err(ValidationError::ZeroMaxFee)
+        );
+        assert_eq!(
+            check(|r| r.deadline = Some(1_000)),
+            Err(ValidationError::DeadlinePassed {
+                deadline: 1_000,
+                now: 1_000,
+            })
+        );
+        assert_eq!(check(|r| r.deadline = None), Ok(()));
+
+        // calldata costs gas on top of the 21k base: 4 per zero byte, 16 otherwise
+        assert_eq!(
+            check(|r| r.gas_limit = TX_BASE_GAS),
+            Err(ValidationError::GasLimitBelowIntrinsic {
+                gas_limit: 21_000,
+                intrinsic: 21_064,
+            })
+        );
+        assert_eq!(check(|r| r.gas_limit = 21_064), Ok(()));
+        assert_eq!(
+            check(|r| {
+                r.gas_limit = TX_BASE_GAS;
+                r.data = vec![];
+            }),
+            Ok(())
+        );
+        assert_eq!(
+            check(|r| {
+                r.data = vec![0, 0, 1];
+                r.gas_limit = 21_023;
+            }),
+            Err(ValidationError::GasLimitBelowIntrinsic {
+                gas_limit: 21_023,
+                intrinsic: 21_024,
+            })
+        );
+        // deployments add 32k and 2 per init code word
+        assert_eq!(
+            check(|r| {
+                r.to = None;
+                r.data = vec![0x60; 33];
+                r.gas_limit = 53_531;
+            }),
+            Err(ValidationError::GasLimitBelowIntrinsic {
+                gas_limit: 53_531,
+                intrinsic: 53_532,
+            })
+        );
+        assert_eq!(
+            check(|r| r.to = None),
+            Ok(()),
+            "60k covers a 4-byte deployment"
+        );
+        assert_eq!(
+            check(|r| {
+                r.to = None;
+                r.data = vec![];
+            }),
+            Err(ValidationError::EmptyInitCode)
+        );
+
+        assert_eq!(
+            check(|r| r.escalation.as_mut().unwrap()[0].max_priority_fee_per_gas = Wei(151)),
+            Err(ValidationError::PriorityFeeAboveMaxFee {
+                max_priority_fee_per_gas: Wei(151),
+                max_fee_per_gas: Wei(150),
+            })
+        );
+        assert_eq!(
+            check(|r| r.escalation.as_mut().unwrap()[0].max_fee_per_gas = Wei(99)),
+            Err(ValidationError::EscalationNotMonotonic)
+        );
+        assert_eq!(
+            check(|r| r.escalation.as_mut().unwrap().push(EscalationStep {
+                after_secs: 30,
+                max_fee_per_gas: Wei(200),
+                max_priority_fee_per_gas: Wei(3),
+            })),
+            Err(ValidationError::EscalationNotMonotonic)
+        );
+
+        fn blob(blob_count: u8) -> Option<BlobParams> {
+            Some(BlobParams {
+                max_fee_per_blob_gas: Wei(1),
+                blob_count,
+            })
+        }
+        assert_eq!(check(|r| r.blob = blob(1)), Ok(()));
+        assert_eq!(
+            check(|r| r.blob = blob(0)),
+            Err(ValidationError::ZeroBlobCount)
+        );
+        assert_eq!(
+            check(|r| {
+                r.blob = blob(1);
+                r.to = None;
+            }),
+            Err(ValidationError::BlobCreate)
+        );
+        assert_eq!(check(|r| r.fee_mode = FeeMode::Legacy), Ok(()));
+        assert_eq!(
+            check(|r| {
+                r.blob = blob(1);
+                r.fee_mode = FeeMode::Legacy;
+            }),
+            Err(ValidationError::LegacyBlob)
+        );
+    }
+
+    #[test]
+    fn test_access_list_rules() {
+        let item = |address: u8, keys: usize| AccessListItem {
+            address: [address; 20],
+            storage_keys: vec![[0x01; 32]; keys],
+        };
+        let req = |items: Vec<AccessListItem>| TransactionRequest {
+            deadline: None,
+            access_list: Some(items),
+            ..sample_request()
+        };
+
+        let listed = req(vec![item(0xCC, 2), item(0xDD, 0)]);
+        assert_eq!(listed.validate(0), Ok(()));
+        assert_eq!(listed.access_list_entries(), 4);
+        // 4 bytes of calldata, then two addresses and two storage keys
+        assert_eq!(listed.intrinsic_gas(), 21_064 + 2 * 2_400 + 2 * 1_900);
+        assert_eq!(req(vec![]).intrinsic_gas(), 21_064);
+        assert_eq!(
+            TransactionRequest {
+                gas_limit: 21_064,
+                ..listed.clone()
+            }
+            .validate(0),
+            Err(ValidationError::GasLimitBelowIntrinsic {
+                gas_limit: 21_064,
+                intrinsic: 29_664,
+            })
+        );
+
+        assert_eq!(
+            req(vec![item(0xCC, 1), item(0xDD, 1), item(0xCC, 0)]).validate(0),
+            Err(ValidationError::DuplicateAccessListAddress {
+                address: [0xCC; 20],
+            })
+        );
+        assert_eq!(
+            req(vec![item(0xCC, MAX_ACCESS_LIST_ENTRIES)]).validate(0),
+            Err(ValidationError::AccessListTooLarge {
+                entries: MAX_ACCESS_LIST_ENTRIES as u64 + 1,
+                max: MAX_ACCESS_LIST_ENTRIES as u64,
+            })
+        );
+        assert_eq!(
+            TransactionRequest {
+                fee_mode: FeeMode::Legacy,
+                ..listed.clone()
+            }
+            .validate(0),
+            Err(ValidationError::LegacyAccessList)
+        );
+        // an empty list asks for nothing, so legacy requests may send one
+        assert_eq!(
+            TransactionRequest {
+                fee_mode: FeeMode::Legacy,
+                ..req(vec![])
+            }
+            .validate(0),
+            Ok(())
+        );
+
+        assert_eq!(borsh_round_trip(&listed), listed);
+        #[cfg(feature = "serde")]
+        {
+            let json = round_trip(&listed);
+            assert_eq!(
+                json["access_list"][0]["address"],
+                format!("0x{}", "cc".repeat(20))
+            );
+            assert_eq!(
+                json["access_list"][0]["storage_keys"][1],
+                format!("0x{}", "01".repeat(32))
+            );
+        }
+    }
+
+    #[test]
+    fn test_authorization_rules() {
+        let auth = SignedAuthorization {
+            chain_id: 1,
+            address: [0xDD; 20],
+            nonce: 3,
+            y_parity: 1,
+            r: [0x11; 32],
+            s: [0x22; 32],
+        };
+        let req = |auths: Vec<SignedAuthorization>| TransactionRequest {
+            gas_limit: 80_000,
+            deadline: None,
+            authorization_list: Some(auths),
+            ..sample_request()
+        };
+
+        let authorized = req(vec![auth.clone(), auth.clone()]);
+        assert_eq!(authorized.validate(0), Ok(()));
+        assert_eq!(authorized.intrinsic_gas(), 21_064 + 2 * 25_000);
+        assert_eq!(
+            req(vec![]).validate(0),
+            Err(ValidationError::EmptyAuthorizationList)
+        );
+        assert_eq!(
+            TransactionRequest {
+                to: None,
+                ..authorized.clone()
+            }
+            .validate(0),
+            Err(ValidationError::AuthorizationCreate)
+        );
+        assert_eq!(
+            TransactionRequest {
+                fee_mode: FeeMode::Legacy,
+                ..authorized.clone()
+            }
+            .validate(0),
+            Err(ValidationError::LegacyAuthorization)
+        );
+        assert_eq!(
+            TransactionRequest {
+                blob: Some(BlobParams {
+                    max_fee_per_blob_gas: Wei(1),
+                    blob_count: 1,
+                }),
+                ..authorized.clone()
+            }
+            .validate(0),
+            Err(ValidationError::BlobAuthorization)
+        );
+
+        // high-s, zero r and out-of-range parity are all malformed
+        let mut high_s = [0xFF; 32];
+        high_s[0] = 0x7F;
+        for bad in [
+            SignedAuthorization {
+                s: high_s,
+                ..auth.clone()
+            },
+            SignedAuthorization {
+                r: [0; 32],
+                ..auth.clone()
+            },
+            SignedAuthorization {
+                y_parity: 27,
+                ..auth.clone()
+            },
+        ] {
+            assert_eq!(
+                req(vec![auth.clone(), bad]).validate(0),
+                Err(ValidationError::MalformedAuthorization { index: 1 })
+            );
+        }
+
+        assert_eq!(borsh_round_trip(&authorized), authorized);
+        #[cfg(feature = "serde")]
+        {
+            let json = round_trip(&authorized);
+            assert_eq!(
+                json["authorization_list"][0]["r"],
+                format!("0x{}", "11".repeat(32))
+            );
+            assert_eq!(json["authorization_list"][0]["y_parity"], 1);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_confirmation_json_without_receipt() {
+        let json = serde_json::json!({
+            "TxConfirmed": {"tx_hash": format!("0x{}", "44".repeat(32)), "block_number": 7}
+        });
+        assert_eq!(
+            serde_json::from_value::<GasEvent>(json).unwrap(),
+            GasEvent::TxConfirmed {
+                tx_hash: [0x44; 32],
+                block_number: 7,
+                effective_gas_price: 0,
+                gas_used: 0,
+            }
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    fn round_trip<T>(value: &T) -> serde_json::Value
+    where
+        T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+    {
+        let json = serde_json::to_value(value).unwrap();
+        assert_eq!(&serde_json::from_value::<T>(json.clone()).unwrap(), value);
+        json
+    }
+
+    fn borsh_round_trip<T: BorshSerialize + BorshDeserialize>(value: &T) -> T {
+        T::try_from_slice(&borsh::to_vec(value).unwrap()).unwrap()
+    }
+
+    fn sample_request() -> TransactionRequest {
+        TransactionRequest {
+            id: 7,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+            value: U256::from(10u64).pow(U256::from(18)).to_be_bytes(),
+            gas_limit: 60_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: Some(1_700_000_000),
+            urgency: Urgency::High,
+            max_wait_blocks: Some(5),
+            escalation: Some(vec![EscalationStep {
+                after_secs: 30,
+                max_fee_per_gas: Wei(150),
+                max_priority_fee_per_gas: Wei(3),
+            }]),
+            chain_id: Some(10),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_request_json_round_trip() {
+        let req = sample_request();
+        let json = round_trip(&req);
+        assert_eq!(json["from"], format!("0x{}", "aa".repeat(20)));
+        assert_eq!(json["to"], format!("0x{}", "bb".repeat(20)));
+        assert_eq!(json["data"], "0xa9059cbb");
+        assert_eq!(json["value"], "0xde0b6b3a7640000");
+
+        let bare = TransactionRequest {
+            to: None,
+            data: vec![],
+            value: [0; 32],
+            deadline: None,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            ..req
+        };
+        let json = round_trip(&bare);
+        assert_eq!(json["to"], serde_json::Value::Null);
+        assert_eq!(json["data"], "0x");
+        assert_eq!(json["value"], "0x0");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_hand_written_request() {
+        let req: TransactionRequest = serde_json::from_str(
+            r#"{
+                "id": 7,
+                "from": "0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                "to": "0xBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBBB",
+                "data": "0xa9059cbb",
+                "value": "1000000000000000000",
+                "gas_limit": 60000,
+                "max_fee_per_gas": 100,
+                "max_priority_fee_per_gas": 2,
+                "deadline": 1700000000,
+                "urgency": "High",
+                "max_wait_blocks": 5,
+                "escalation": [
+                    {"after_secs": 30, "max_fee_per_gas": 150, "max_priority_fee_per_gas": 3}
+                ],
+                "chain_id": 10
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(req, sample_request());
+
+        // byte arrays, as inputs were written before, and omitted optional fields
+        let legacy: TransactionRequest = serde_json::from_value(serde_json::json!({
+            "id": 1,
+            "from": vec![0xAA_u8; 20],
+            "data": [],
+            "value": vec![0_u8; 32],
+            "gas_limit": 21000,
+            "max_fee_per_gas": 100,
+            "max_priority_fee_per_gas": 2,
+            "deadline": null,
+            "urgency": "Standard",
+        }))
+        .unwrap();
+        assert_eq!(legacy.from, [0xAA; 20]);
+        assert_eq!(legacy.to, None);
+        assert!(legacy.data.is_empty());
+        assert_eq!(legacy.escalation, None);
+
+        let short = serde_json::json!({"InitNonce": {"address": "0xaaaa", "nonce": 1}});
+        let err = serde_json::from_value::<SchedulerCommand>(short).unwrap_err();
+        assert!(
+            err.to_string().contains("expected 20 bytes, got 2"),
+            "{err}"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_gas_event_json_round_trip() {
+        for event in [
+            GasEvent::BaseFeeUpdate {
+                base_fee: 30,
+                timestamp: 1,
+            },
+            GasEvent::MempoolTx {
+                tx_hash: [0x11; 32],
+                max_fee: 40,
+                max_priority_fee: 2,
+                gas_limit: 21_000,
+            },
+            GasEvent::NewBlock {
+                number: 7,
+                base_fee: 30,
+                gas_used: 1,
+                gas_limit: 2,
+                block_hash: [0x22; 32],
+                parent_hash: [0x33; 32],
+            },
+            GasEvent::TxConfirmed {
+                tx_hash: [0x44; 32],
+                block_number: 7,
+                effective_gas_price: 30,
+                gas_used: 21_000,
+            },
+            GasEvent::TxDropped {
+                tx_hash: [0x44; 32],
+                reason: "evicted".to_string(),
+            },
+            GasEvent::TxFailed {
+                tx_hash: [0x44; 32],
+                block_number: 7,
+            },
+            GasEvent::TxReplaced {
+                old_tx_hash: [0x44; 32],
+                new_tx_hash: [0x55; 32],
+                nonce: 3,
+            },
+            GasEvent::FeeHistory {
+                oldest_block: 5,
+                base_fees: vec![30, 31, 29],
+                gas_used_ratios: vec![0.6, 0.25],
+                rewards: vec![vec![1, 2], vec![1, 3]],
+            },
+            GasEvent::OracleEstimate {
+                source: "etherscan".into(),
+                base_fee: 30,
+                priority_fee_fast: 3,
+                priority_fee_standard: 1,
+            },
+            GasEvent::L1BaseFeeUpdate { base_fee: 9 },
+        ] {
+            round_trip(&event);
+        }
+
+        let json = round_trip(&GasEvent::TxConfirmed {
+            tx_hash: [0x44; 32],
+            block_number: 7,
+            effective_gas_price: 30,
+            gas_used: 21_000,
+        });
+        assert_eq!(
+            json["TxConfirmed"]["tx_hash"],
+            format!("0x{}", "44".repeat(32))
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_decision_json_round_trip() {
+        for decision in [
+            SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 2,
+                gas_price: Wei(3),
+                max_priority_fee_per_gas: Wei(2),
+                create: true,
+                estimated_cost_wei: Wei(4),
+                estimated_savings_wei: Wei(5),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::TrendWait {
+                    estimated_blocks: 3,
+                },
+                retry_hint: Some(RetryHint::AfterBlocks(3)),
+            },
+            SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::NonceUnavailable("timeout".to_string()),
+                retry_hint: Some(RetryHint::AfterDuration(Duration::from_millis(1_500))),
+            },
+            SchedulerDecision::Reprice {
+                tx_id: 1,
+                old_nonce: 2,
+                new_gas_price: Wei(3),
+                max_priority_fee_per_gas: Wei(2),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "expired".to_string(),
+            },
+            SchedulerDecision::Rejected {
+                tx_id: 1,
+                reason: "not in dropped archive".to_string(),
+                error: None,
+            },
+            SchedulerDecision::Rejected {
+                tx_id: 1,
+                reason: "zero fee cap".to_string(),
+                error: Some(ValidationError::ZeroMaxFee),
+            },
+            SchedulerDecision::ModeChanged { spike: true },
+            SchedulerDecision::NonceInitRequired {
+                chain_id: 1,
+                address: [0xAA; 20],
+            },
+            SchedulerDecision::NonceGapDetected {
+                chain_id: 1,
+                address: [0xAA; 20],
+                missing: vec![3, 4],
+            },
+            SchedulerDecision::FillNonceGap {
+                tx_id: 1,
+                chain_id: 1,
+                address: [0xAA; 20],
+                nonce: 3,
+                gas_price: Wei(40),
+                fee_mode: FeeMode::Eip1559,
+            },
+            SchedulerDecision::NonceConsumed { tx_id: 1, nonce: 2 },
+            SchedulerDecision::NonceConflict {
+                tx_id: 1,
+                chain_id: 1,
+                address: [0xAA; 20],
+                nonce: 2,
+                reason: "taken".to_string(),
+            },
+            SchedulerDecision::Reorg {
+                fork_block: 6,
+                depth: 2,
+                unconfirmed: vec![],
+            },
+            SchedulerDecision::MarketUpdate {
+                current_fee: Wei(30),
+                volatility: 0.25,
+                trend: -0.5,
+                spike: false,
+            },
+            SchedulerDecision::Confirmed {
+                tx_id: 1,
+                block_number: 7,
+                effective_gas_price: Wei(30),
+                realized_cost_wei: Wei(630_000),
+                savings_vs_max_fee_wei: Wei(42_000),
+                savings_vs_acceptance_wei: Wei(0),
+            },
+        ] {
+            round_trip(&decision);
+        }
+
+        for status in [
+            TxStatus::Pending,
+            TxStatus::Deferred("waiting".to_string()),
+            TxStatus::Submitted {
+                nonce: 2,
+                gas_price: Wei(3),
+            },
+            TxStatus::Repriced {
+                new_gas_price: Wei(4),
+            },
+            TxStatus::Broadcast {
+                tx_hash: [0x55; 32],
+            },
+            TxStatus::Confirmed { block_number: 7 },
+            TxStatus::NonceConsumed { nonce: 2 },
+            TxStatus::Dropped("expired".to_string()),
+            TxStatus::Rejected("duplicate".to_string()),
+            TxStatus::Cancelled,
+        ] {
+            round_trip(&status);
+        }
+    }
+
+    #[test]
+    fn test_display() {
+        let req = TransactionRequest {
+            max_fee_per_gas: Wei(70_000_000_000),
+            max_priority_fee_per_gas: Wei(1_500_000_000),
+            ..sample_request()
+        };
+        assert_eq!(
+            req.to_string(),
+            "tx 7 from 0xaAaA…aaAa to 0xbBbB…BBbB, value 1 ETH, gas 60000 at max fee 70 gwei \
+             tip 1.5 gwei, High, chain 10"
+        );
+        let deploy = TransactionRequest {
+            to: None,
+            value: [0; 32],
+            chain_id: None,
+            blob: Some(BlobParams {
+                max_fee_per_blob_gas: Wei(3),
+                blob_count: 2,
+            }),
+            ..req
+        };
+        assert_eq!(
+            deploy.to_string(),
+            "tx 7 from 0xaAaA…aaAa creating a contract, gas 60000 at max fee 70 gwei \
+             tip 1.5 gwei, 2 blobs at 3 wei, High"
+        );
+
+        let block = GasEvent::NewBlock {
+            number: 7,
+            base_fee: 31_250_000_000,
+            gas_used: 15_000_000,
+            gas_limit: 30_000_000,
+            block_hash: [0; 32],
+            parent_hash: [0; 32],
+        };
+        assert_eq!(
+            block.to_string(),
+            "block 7 base fee 31.25 gwei gas 15000000/30000000"
+        );
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash: [0x44; 32],
+            block_number: 7,
+            effective_gas_price: 30_000_000_000,
+            gas_used: 21_000,
+        };
+        assert_eq!(
+            confirmed.to_string(),
+            format!(
+                "tx 0x{} confirmed in block 7 at 30 gwei for 21000 gas",
+                "44".repeat(32)
+            )
+        );
+
+        let submit = SchedulerDecision::Submit {
+            tx_id: 1,
+            nonce: 4,
+            gas_price: Wei(32_000_000_000),
+            max_priority_fee_per_gas: Wei(2),
+            create: false,
+            estimated_cost_wei: Wei(21_000) * 32_000_000_000,
+            estimated_savings_wei: Wei(21_000) * 8_000_000_000,
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        };
+        assert_eq!(
+            submit.to_string(),
+            "submit tx 1 at nonce 4 for 32 gwei, up to 0.000672 ETH, saving 0.000168 ETH"
+        );
+        let defer = SchedulerDecision::Defer {
+            tx_id: 2,
+            reason: DeferReason::FeeAboveMax,
+            retry_hint: Some(RetryHint::WhenFeeBelow(Wei(40_000_000_000))),
+        };
+        assert_eq!(
+            defer.to_string(),
+            "defer tx 2: fee above cap, retry at base fee 40 gwei"
+        );
+        let gap = SchedulerDecision::NonceGapDetected {
+            chain_id: 1,
+            address: [0xAA; 20],
+            missing: vec![4, 5],
+        };
+        assert_eq!(
+            gap.to_string(),
+            "sender 0xaAaA…aaAa on chain 1 is missing nonces [4, 5]"
+        );
+        let confirmed = SchedulerDecision::Confirmed {
+            tx_id: 1,
+            block_number: 7,
+            effective_gas_price: Wei(41),
+            realized_cost_wei: Wei(861_000),
+            savings_vs_max_fee_wei: Wei(21_000),
+            savings_vs_acceptance_wei: Wei(441_000),
+        };
+        assert_eq!(
+            confirmed.to_string(),
+            "tx 1 confirmed in block 7 at 41 wei, paid 861000 wei, saved 21000 wei on the max fee \
+             and 441000 wei on acceptance"
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_command_json_round_trip() {
+        for command in [
+            SchedulerCommand::Resubmit {
+                tx_id: 1,
+                new_max_fee_per_gas: Some(Wei(200)),
+                new_deadline: None,
+            },
+            SchedulerCommand::Broadcast {
+                tx_id: 1,
+                tx_hash: [0x55; 32],
+            },
+            SchedulerCommand::BroadcastFailed {
+                tx_id: 1,
+                reason: "underpriced".to_string(),
+            },
+            SchedulerCommand::InitNonce {
+                chain_id: None,
+                address: [0xAA; 20],
+                nonce: 12,
+                force: true,
+            },
+            SchedulerCommand::NonceTooLow {
+                chain_id: Some(10),
+                address: [0xAA; 20],
+                network_nonce: 13,
+            },
+            SchedulerCommand::RestoreSubmitted {
+                txs: vec![
+                    RestoredTx {
+                        req: sample_request(),
+                        nonce: 3,
+                        gas_price: Wei(50),
+                        tx_hash: Some([0x66; 32]),
+                    },
+                    RestoredTx {
+                        req: sample_request(),
+                        nonce: 4,
+                        gas_price: Wei(50),
+                        tx_hash: None,
+                    },
+                ],
+            },
+            SchedulerCommand::SetSubmissionRate {
+                tokens_per_sec: 5,
+                burst: None,
+            },
+            SchedulerCommand::Cancel { tx_id: 1 },
+        ] {
+            round_trip(&command);
+        }
+    }
 }
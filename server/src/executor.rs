@@ -0,0 +1,1144 @@
+use crate::events::{
+    Deadline, SchedulerCommand, SchedulerDecision, SubmissionPrivacy, TransactionRequest, hex_hash,
+    short_address,
+};
+use crate::scheduler::{SchedulerHandle, Submission};
+use crate::signer::{SignError, SignedBytes, TxSigner, TypedTx};
+use crate::tx_build;
+use alloy_consensus::Transaction;
+use alloy_primitives::{hex, keccak256};
+use alloy_provider::Provider;
+use alloy_signer::SignerSync;
+use alloy_signer_local::PrivateKeySigner;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc;
+use tracing::{info, warn};
+
+/// Why a broadcast failed, as far as the node's error message tells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum FailureKind {
+    /// The sender has already used the nonce.
+    NonceTooLow,
+    /// The fee is below what the node takes, or not enough above the tx it
+    /// would replace.
+    Underpriced,
+    /// The sender can't cover gas and value.
+    InsufficientFunds,
+    /// The tx couldn't be built or signed, so nothing was sent.
+    Unsendable,
+    Other,
+}
+
+impl FailureKind {
+    /// Classifies a node's error message. Covers the wording of geth, reth,
+    /// erigon, nethermind, besu and anvil.
+    pub fn classify(message: &str) -> Self {
+        let message = message.to_lowercase().replace('_', " ");
+        let has = |needles: &[&str]| needles.iter().any(|needle| message.contains(needle));
+        if has(&["nonce too low", "oldnonce"]) {
+            FailureKind::NonceTooLow
+        } else if has(&[
+            "insufficient funds",
+            "insufficientfunds",
+            "upfront cost exceeds",
+        ]) {
+            FailureKind::InsufficientFunds
+        } else if has(&[
+            "underpriced",
+            "fee too low",
+            "feetoolow",
+            "less than block base fee",
+            "gas price too low",
+            "below configured minimum gas price",
+        ]) {
+            FailureKind::Underpriced
+        } else {
+            FailureKind::Other
+        }
+    }
+}
+
+impl std::fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FailureKind::NonceTooLow => write!(f, "nonce too low"),
+            FailureKind::Underpriced => write!(f, "underpriced"),
+            FailureKind::InsufficientFunds => write!(f, "insufficient funds"),
+            FailureKind::Unsendable => write!(f, "unsendable"),
+            FailureKind::Other => write!(f, "rejected"),
+        }
+    }
+}
+
+/// What came of acting on one decision.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ExecutionReport {
+    Broadcast {
+        tx_id: u64,
+        #[serde(with = "crate::events::hex_serde::array")]
+        tx_hash: [u8; 32],
+    },
+    Failed {
+        tx_id: u64,
+        kind: FailureKind,
+        message: String,
+    },
+}
+
+/// Node error messages meaning it already has the exact tx sent.
+const ALREADY_KNOWN: [&str; 4] = [
+    "already known",
+    "alreadyknown",
+    "known transaction",
+    "already imported",
+];
+
+/// Blocks past the head a private tx stays eligible for, unless its deadline
+/// comes sooner.
+pub const DEFAULT_RELAY_MAX_BLOCKS: u64 = 25;
+/// Post-merge slot time, for turning a deadline into a block number.
+const SECONDS_PER_BLOCK: u64 = 12;
+const RELAY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A Flashbots-style relay, which takes signed txs with
+/// `eth_sendPrivateTransaction` and hands them to block builders without
+/// putting them in the public mempool. Each call is signed by an auth key in an
+/// `X-Flashbots-Signature` header; relays only use it to tell searchers apart,
+/// so it needn't be the key txs are signed with.
+#[derive(Clone)]
+pub struct PrivateRelay {
+    url: String,
+    client: reqwest::Client,
+    auth: PrivateKeySigner,
+    max_blocks: u64,
+    cancel: bool,
+}
+
+impl PrivateRelay {
+    /// A relay at `url`, with a random auth key.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            client: reqwest::Client::new(),
+            auth: PrivateKeySigner::random(),
+            max_blocks: DEFAULT_RELAY_MAX_BLOCKS,
+            cancel: true,
+        }
+    }
+
+    /// Signs calls with `key`, for a reputation that lasts across runs.
+    pub fn with_auth_key(mut self, key: PrivateKeySigner) -> Self {
+        self.auth = key;
+        self
+    }
+
+    /// Blocks past the head a tx stays eligible for, unless its deadline comes
+    /// sooner; `DEFAULT_RELAY_MAX_BLOCKS` by default.
+    pub fn with_max_blocks(mut self, max_blocks: u64) -> Self {
+        self.max_blocks = max_blocks;
+        self
+    }
+
+    /// For relays without `eth_cancelPrivateTransaction`: dropped txs are left
+    /// to lapse at their max block.
+    pub fn without_cancel(mut self) -> Self {
+        self.cancel = false;
+        self
+    }
+
+    /// The last block a tx sent at `head` may be included in: `max_blocks` later,
+    /// or the last block before `deadline` if that's sooner, but at least the next.
+    fn max_block_number(&self, head: u64, deadline: Option<Deadline>, now_secs: u64) -> u64 {
+        let blocks = match deadline {
+            Some(deadline) => {
+                (deadline.saturating_sub(now_secs) / SECONDS_PER_BLOCK).min(self.max_blocks)
+            }
+            None => self.max_blocks,
+        };
+        head + blocks.max(1)
+    }
+
+    async fn call(&self, method: &str, params: Value) -> Result<Value, String> {
+        let body =
+            json!({"jsonrpc": "2.0", "id": 1, "method": method, "params": params}).to_string();
+        let digest = format!("{:#x}", keccak256(body.as_bytes()));
+        let signature = self
+            .auth
+            .sign_message_sync(digest.as_bytes())
+            .map_err(|e| e.to_string())?;
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(RELAY_TIMEOUT)
+            .header("content-type", "application/json")
+            .header(
+                "X-Flashbots-Signature",
+                format!(
+                    "{}:{}",
+                    self.auth.address(),
+                    hex::encode_prefixed(signature.as_bytes())
+                ),
+            )
+            .body(body)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        let reply: Value = response.json().await.map_err(|e| {
+            if status.is_success() {
+                e.to_string()
+            } else {
+                format!("HTTP {}", status)
+            }
+        })?;
+        match reply.get("error") {
+            Some(error) => Err(error["message"]
+                .as_str()
+                .map_or_else(|| error.to_string(), str::to_string)),
+            None => Ok(reply["result"].clone()),
+        }
+    }
+}
+
+struct TrackedTx {
+    req: TransactionRequest,
+    /// The nonce its tx was last broadcast with.
+    nonce: Option<u64>,
+    /// Hash of the tx last sent to the private relay, to cancel it by.
+    relayed: Option<[u8; 32]>,
+}
+
+impl TrackedTx {
+    fn new(mut req: TransactionRequest, default_chain_id: Option<u64>) -> Self {
+        req.chain_id = req.chain_id.or(default_chain_id);
+        Self {
+            req,
+            nonce: None,
+            relayed: None,
+        }
+    }
+}
+
+/// Signs what the scheduler decides with one `TxSigner` and broadcasts it with
+/// `eth_sendRawTransaction`, or sends it to a `PrivateRelay` if its request asks
+/// for `SubmissionPrivacy::PrivateRelay`. Clones share the same tracked requests.
+///
+/// Decisions only name their request, so every request has to be `track`ed
+/// before the scheduler gets it. A request is forgotten once it's dropped,
+/// rejected, confirmed or its nonce consumed.
+#[derive(Clone)]
+pub struct Executor<P> {
+    provider: P,
+    signer: Arc<dyn TxSigner>,
+    feedback: SchedulerHandle,
+    txs: Arc<DashMap<u64, TrackedTx>>,
+    broadcasts: Option<mpsc::Sender<[u8; 32]>>,
+    relay: Option<PrivateRelay>,
+    default_chain_id: Option<u64>,
+}
+
+impl<P: Provider> Executor<P> {
+    /// `feedback` is the handle of the scheduler whose decisions this executes;
+    /// broadcasts and failures are reported to it as commands. Only requests from
+    /// `signer`'s address can be executed.
+    pub fn new(provider: P, signer: impl TxSigner + 'static, feedback: SchedulerHandle) -> Self {
+        Self {
+            provider,
+            signer: Arc::new(signer),
+            feedback,
+            txs: Arc::new(DashMap::new()),
+            broadcasts: None,
+            relay: None,
+            default_chain_id: None,
+        }
+    }
+
+    /// Also sends every hash broadcast to `broadcasts`, e.g. for a
+    /// `confirmation::ReceiptPoller` to watch.
+    pub fn with_broadcasts(mut self, broadcasts: mpsc::Sender<[u8; 32]>) -> Self {
+        self.broadcasts = Some(broadcasts);
+        self
+    }
+
+    /// Sends private requests' txs to `relay`. Without one they fail as
+    /// `Unsendable` rather than going out publicly.
+    pub fn with_private_relay(mut self, relay: PrivateRelay) -> Self {
+        self.relay = Some(relay);
+        self
+    }
+
+    /// Builds requests that don't name a chain for `chain_id`; pass the
+    /// scheduler's `SchedulerConfig::chain_id`, which it prices them on. Without
+    /// one they fail as `Unsendable`.
+    pub fn with_default_chain_id(mut self, chain_id: u64) -> Self {
+        self.default_chain_id = Some(chain_id);
+        self
+    }
+
+    pub fn address(&self) -> [u8; 20] {
+        self.signer.address()
+    }
+
+    /// Remembers `req` so decisions about it can be built.
+    pub fn track(&self, req: TransactionRequest) {
+        self.txs
+            .insert(req.id, TrackedTx::new(req, self.default_chain_id));
+    }
+
+    /// Tracks every request on its way from `submissions` to the scheduler; pass
+    /// the returned receiver to `Scheduler::run` in place of `submissions`.
+    pub fn track_submissions(
+        &self,
+        mut submissions: mpsc::Receiver<Submission>,
+    ) -> mpsc::Receiver<Submission> {
+        let (forward, forwarded) = mpsc::channel(submissions.max_capacity());
+        let txs = self.txs.clone();
+        let default_chain_id = self.default_chain_id;
+        tokio::spawn(async move {
+            while let Some(submission) = submissions.recv().await {
+                let req = submission.req.clone();
+                txs.insert(req.id, TrackedTx::new(req, default_chain_id));
+                if forward.send(submission).await.is_err() {
+                    return;
+                }
+            }
+        });
+        forwarded
+    }
+
+    /// Executes `decisions` in order until the channel closes, reporting each
+    /// outcome to the scheduler:
+    ///
+    /// - a broadcast as `SchedulerCommand::Broadcast`;
+    /// - a failed `Submit` or `FillNonceGap` as `BroadcastFailed`, which hands its
+    ///   nonce back and drops it;
+    /// - a failed `Reprice` not at all, since the tx it would replace is still out
+    ///   there; the scheduler reprices again after its cooldown.
+    ///
+    /// "Nonce too low" also resyncs the sender with `NonceTooLow`, at the
+    /// transaction count the node reports. Decisions still queued when the
+    /// scheduler stops, as at shutdown, are executed all the same, unreported.
+    pub async fn run(self, mut decisions: mpsc::Receiver<SchedulerDecision>) {
+        let mut scheduler_gone = false;
+        while let Some(decision) = decisions.recv().await {
+            let Some(report) = self.execute(&decision).await else {
+                continue;
+            };
+            if !scheduler_gone && self.feed_back(&decision, report).await.is_err() {
+                warn!("Scheduler is gone; executing the decisions left without reporting");
+                scheduler_gone = true;
+            }
+        }
+    }
+
+    /// Signs and broadcasts the tx `decision` describes, if it describes one.
+    /// `Reprice`s go out at the nonce the tx was last broadcast with, and through
+    /// the relay if the tx was private. A `Drop` of a tx last sent to the relay
+    /// cancels it there, if the relay can.
+    pub async fn execute(&self, decision: &SchedulerDecision) -> Option<ExecutionReport> {
+        let privacy = match *decision {
+            SchedulerDecision::Submit { privacy, .. }
+            | SchedulerDecision::Reprice { privacy, .. } => privacy,
+            _ => SubmissionPrivacy::Public,
+        };
+        let (tx_id, built) = match *decision {
+            SchedulerDecision::Submit { tx_id, nonce, .. } => {
+                (tx_id, self.build(tx_id, decision, nonce))
+            }
+            SchedulerDecision::Reprice {
+                tx_id, old_nonce, ..
+            } => {
+                let nonce = self.recorded_nonce(tx_id).unwrap_or(old_nonce);
+                if nonce != old_nonce {
+                    warn!(
+                        "Reprice of tx {} at nonce {}, but it was broadcast at {}",
+                        tx_id, old_nonce, nonce
+                    );
+                }
+                let mut decision = decision.clone();
+                if let SchedulerDecision::Reprice { old_nonce, .. } = &mut decision {
+                    *old_nonce = nonce;
+                }
+                (tx_id, self.build(tx_id, &decision, nonce))
+            }
+            SchedulerDecision::FillNonceGap {
+                tx_id,
+                address,
+                nonce,
+                ..
+            } => {
+                let built = if address == self.address() {
+                    tx_build::build_gap_fill(decision)
+                        .map(|tx| (tx, nonce, address))
+                        .map_err(|e| e.to_string())
+                } else {
+                    Err(format!("no key for {}", short_address(&address)))
+                };
+                (tx_id, built)
+            }
+            SchedulerDecision::Drop { tx_id, .. } => {
+                if let Some((_, tracked)) = self.txs.remove(&tx_id)
+                    && let Some(tx_hash) = tracked.relayed
+                {
+                    self.cancel_private(tx_id, tx_hash).await;
+                }
+                return None;
+            }
+            SchedulerDecision::Rejected { tx_id, .. }
+            | SchedulerDecision::NonceConsumed { tx_id, .. }
+            | SchedulerDecision::Confirmed { tx_id, .. } => {
+                self.txs.remove(&tx_id);
+                return None;
+            }
+            _ => return None,
+        };
+        let report = match built {
+            Ok((tx, nonce, from)) => self.send(tx_id, tx, nonce, from, privacy).await,
+            Err(message) => ExecutionReport::Failed {
+                tx_id,
+                kind: FailureKind::Unsendable,
+                message,
+            },
+        };
+        Some(report)
+    }
+
+    fn recorded_nonce(&self, tx_id: u64) -> Option<u64> {
+        self.txs.get(&tx_id).and_then(|tx| tx.nonce)
+    }
+
+    fn build(
+        &self,
+        tx_id: u64,
+        decision: &SchedulerDecision,
+        nonce: u64,
+    ) -> Result<(TypedTx, u64, [u8; 20]), String> {
+        let tracked = self
+            .txs
+            .get(&tx_id)
+            .ok_or_else(|| format!("tx {} was never tracked", tx_id))?;
+        if tracked.req.from != self.address() {
+            return Err(format!("no key for {}", short_address(&tracked.req.from)));
+        }
+        tx_build::build(&tracked.req, decision)
+            .map(|tx| (tx, nonce, tracked.req.from))
+            .map_err(|e| e.to_string())
+    }
+
+    /// Signs `tx` and sends it, once its signature is checked to recover to
+    /// `from`.
+    async fn send(
+        &self,
+        tx_id: u64,
+        tx: TypedTx,
+        nonce: u64,
+        from: [u8; 20],
+        privacy: SubmissionPrivacy,
+    ) -> ExecutionReport {
+        let relay = match (privacy, &self.relay) {
+            (SubmissionPrivacy::Public, _) => None,
+            (SubmissionPrivacy::PrivateRelay, Some(relay)) => Some(relay),
+            (SubmissionPrivacy::PrivateRelay, None) => {
+                return ExecutionReport::Failed {
+                    tx_id,
+                    kind: FailureKind::Unsendable,
+                    message: "private request, but no private relay is configured".into(),
+                };
+            }
+        };
+        let signed = match self.sign(tx, from).await {
+            Ok(signed) => signed,
+            Err(e) => {
+                return ExecutionReport::Failed {
+                    tx_id,
+                    kind: FailureKind::Unsendable,
+                    message: e.to_string(),
+                };
+            }
+        };
+        let tx_hash = signed.tx_hash();
+        let raw = signed.0;
+        let sent = match relay {
+            Some(relay) => self.send_private(relay, tx_id, &raw).await,
+            None => self
+                .provider
+                .send_raw_transaction(&raw)
+                .await
+                .map(|_| ())
+                .map_err(|e| e.to_string()),
+        };
+        match sent {
+            Ok(()) => {}
+            Err(message)
+                if ALREADY_KNOWN
+                    .iter()
+                    .any(|known| message.to_lowercase().contains(known)) =>
+            {
+                info!("Node already has tx {} as {}", tx_id, hex_hash(&tx_hash));
+            }
+            Err(message) => {
+                return ExecutionReport::Failed {
+                    tx_id,
+                    kind: FailureKind::classify(&message),
+                    message,
+                };
+            }
+        }
+        if let Some(mut tracked) = self.txs.get_mut(&tx_id) {
+            tracked.nonce = Some(nonce);
+            tracked.relayed = relay.map(|_| tx_hash);
+        }
+        ExecutionReport::Broadcast { tx_id, tx_hash }
+    }
+
+    /// Signs `tx` on the chain it names, and checks the signature is `from`'s.
+    async fn sign(&self, tx: TypedTx, from: [u8; 20]) -> Result<SignedBytes, SignError> {
+        let chain_id = tx
+            .chain_id()
+            .ok_or_else(|| SignError::Malformed("tx names no chain".into()))?;
+        let signed = self.signer.sign_typed(tx, chain_id).await?;
+        let recovered = signed.recover_sender()?;
+        if recovered != from {
+            return Err(SignError::WrongSigner {
+                expected: from,
+                recovered,
+            });
+        }
+        Ok(signed)
+    }
+
+    /// Hands a signed tx to `relay`, eligible until the block its request's
+    /// deadline or the relay's `max_blocks` allows.
+    async fn send_private(
+        &self,
+        relay: &PrivateRelay,
+        tx_id: u64,
+        raw: &[u8],
+    ) -> Result<(), String> {
+        let head = self
+            .provider
+            .get_block_number()
+            .await
+            .map_err(|e| format!("could not fetch the head block: {}", e))?;
+        let deadline = self.txs.get(&tx_id).and_then(|tx| tx.req.deadline);
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let max_block = relay.max_block_number(head, deadline, now_secs);
+        let params = json!([{
+            "tx": hex::encode_prefixed(raw),
+            "maxBlockNumber": format!("{:#x}", max_block),
+        }]);
+        relay
+            .call("eth_sendPrivateTransaction", params)
+            .await
+            .map(|_| ())
+    }
+
+    async fn cancel_private(&self, tx_id: u64, tx_hash: [u8; 32]) {
+        let Some(relay) = self.relay.as_ref().filter(|relay| relay.cancel) else {
+            return;
+        };
+        let params = json!([{ "txHash": hex_hash(&tx_hash) }]);
+        match relay.call("eth_cancelPrivateTransaction", params).await {
+            Ok(Value::Bool(true)) => info!("Cancelled tx {} at the relay", tx_id),
+            Ok(_) => info!("Relay no longer had tx {} to cancel", tx_id),
+            Err(e) => warn!("Relay could not cancel tx {}: {}", tx_id, e),
+        }
+    }
+
+    async fn feed_back(
+        &self,
+        decision: &SchedulerDecision,
+        report: ExecutionReport,
+    ) -> Result<(), crate::scheduler::SchedulerClosed> {
+        let (tx_id, kind, message) = match report {
+            ExecutionReport::Broadcast { tx_id, tx_hash } => {
+                info!("Broadcast tx {} as {}", tx_id, hex_hash(&tx_hash));
+                if let Some(broadcasts) = &self.broadcasts
+                    && broadcasts.send(tx_hash).await.is_err()
+                {
+                    warn!("nothing is watching {} for a receipt", hex_hash(&tx_hash));
+                }
+                return self
+                    .feedback
+                    .command(SchedulerCommand::Broadcast { tx_id, tx_hash })
+                    .await;
+            }
+            ExecutionReport::Failed {
+                tx_id,
+                kind,
+                message,
+            } => (tx_id, kind, message),
+        };
+        warn!("Broadcast of tx {} failed ({}): {}", tx_id, kind, message);
+        let chain_id = match *decision {
+            SchedulerDecision::FillNonceGap { chain_id, .. } => Some(chain_id),
+            _ => self.txs.get(&tx_id).and_then(|tx| tx.req.chain_id),
+        };
+        if !matches!(decision, SchedulerDecision::Reprice { .. }) {
+            self.feedback
+                .command(SchedulerCommand::BroadcastFailed {
+                    tx_id,
+                    reason: message,
+                })
+                .await?;
+        }
+        if kind == FailureKind::NonceTooLow {
+            match self
+                .provider
+                .get_transaction_count(self.address().into())
+                .await
+            {
+                Ok(network_nonce) => {
+                    self.feedback
+                        .command(SchedulerCommand::NonceTooLow {
+                            chain_id,
+                            address: self.address(),
+                            network_nonce,
+                        })
+                        .await?;
+                }
+                Err(e) => warn!("could not fetch the transaction count to resync: {}", e),
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{FeeMode, SubmissionPrivacy, Urgency};
+    use crate::signer::LocalSigner;
+    use crate::units::Wei;
+    use alloy_consensus::TxEnvelope;
+    use alloy_eips::eip2718::Decodable2718;
+    use alloy_primitives::Signature;
+    use alloy_provider::RootProvider;
+    use serde_json::{Value, json};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// anvil's first dev account.
+    const DEV_KEY: &str = "0xac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80";
+
+    fn signer() -> PrivateKeySigner {
+        DEV_KEY.parse().unwrap()
+    }
+
+    /// Claims the dev account, but signs with another key.
+    struct Impostor(LocalSigner);
+
+    #[async_trait::async_trait]
+    impl TxSigner for Impostor {
+        async fn sign_typed(&self, tx: TypedTx, chain_id: u64) -> Result<SignedBytes, SignError> {
+            self.0.sign_typed(tx, chain_id).await
+        }
+
+        fn address(&self) -> [u8; 20] {
+            signer().address().0.0
+        }
+    }
+
+    fn request(id: u64, chain_id: u64) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: signer().address().0.0,
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100_000_000_000),
+            max_priority_fee_per_gas: Wei(1_000_000_000),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: Some(chain_id),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn submit(tx_id: u64, nonce: u64, gas_price: u128) -> SchedulerDecision {
+        SchedulerDecision::Submit {
+            tx_id,
+            nonce,
+            gas_price: Wei(gas_price),
+            max_priority_fee_per_gas: Wei(2),
+            create: false,
+            estimated_cost_wei: Wei::ZERO,
+            estimated_savings_wei: Wei::ZERO,
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn reprice(tx_id: u64, old_nonce: u64, new_gas_price: u128) -> SchedulerDecision {
+        SchedulerDecision::Reprice {
+            tx_id,
+            old_nonce,
+            new_gas_price: Wei(new_gas_price),
+            max_priority_fee_per_gas: Wei(2),
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn private(mut decision: SchedulerDecision) -> SchedulerDecision {
+        if let SchedulerDecision::Submit { privacy, .. }
+        | SchedulerDecision::Reprice { privacy, .. } = &mut decision
+        {
+            *privacy = SubmissionPrivacy::PrivateRelay;
+        }
+        decision
+    }
+
+    fn executor(url: &str) -> (Executor<RootProvider>, mpsc::Receiver<SchedulerCommand>) {
+        let (feedback, _req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let provider = RootProvider::new_http(url.parse().unwrap());
+        (
+            Executor::new(provider, LocalSigner::new(signer()), feedback),
+            cmd_rx,
+        )
+    }
+
+    /// Answers one JSON-RPC call with `reply`, a `result` or an `error` member,
+    /// and returns the call.
+    async fn respond(listener: &TcpListener, reply: Value) -> Value {
+        respond_http(listener, reply).await.2
+    }
+
+    /// Like `respond`, but also returns the request's headers and raw body.
+    async fn respond_http(listener: &TcpListener, reply: Value) -> (String, String, Value) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 4096];
+        let (head, body, call) = loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, body)) = text.split_once("\r\n\r\n")
+                && let Ok(call) = serde_json::from_str::<Value>(body)
+            {
+                break (head.to_lowercase(), body.to_string(), call);
+            }
+        };
+        let mut response = json!({"jsonrpc": "2.0", "id": call["id"]});
+        response
+            .as_object_mut()
+            .unwrap()
+            .extend(reply.as_object().unwrap().clone());
+        let reply = response.to_string();
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            reply.len(),
+            reply
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        (head, body, call)
+    }
+
+    /// The signed tx an `eth_sendRawTransaction` call carried, with its hash.
+    fn sent_tx(call: &Value) -> (TxEnvelope, [u8; 32]) {
+        assert_eq!(call["method"], "eth_sendRawTransaction");
+        let raw = hex::decode(call["params"][0].as_str().unwrap()).unwrap();
+        let tx = TxEnvelope::decode_2718(&mut raw.as_slice()).unwrap();
+        (tx, keccak256(&raw).0)
+    }
+
+    /// The signed tx an `eth_sendPrivateTransaction` call carried, with its hash
+    /// and max block number, after checking the call is signed by the executor's
+    /// key.
+    fn relayed_tx((head, body, call): &(String, String, Value)) -> (TxEnvelope, [u8; 32], &str) {
+        assert_eq!(call["method"], "eth_sendPrivateTransaction");
+        assert_signed(head, body);
+        let raw = hex::decode(call["params"][0]["tx"].as_str().unwrap()).unwrap();
+        let tx = TxEnvelope::decode_2718(&mut raw.as_slice()).unwrap();
+        let max_block = call["params"][0]["maxBlockNumber"].as_str().unwrap();
+        (tx, keccak256(&raw).0, max_block)
+    }
+
+    fn assert_signed(head: &str, body: &str) {
+        let header = head
+            .lines()
+            .find_map(|line| line.strip_prefix("x-flashbots-signature: "))
+            .expect("no signature header");
+        let (address, signature) = header.split_once(':').unwrap();
+        let signature = Signature::from_raw(&hex::decode(signature).unwrap()).unwrap();
+        let digest = format!("{:#x}", keccak256(body.as_bytes()));
+        let recovered = signature.recover_address_from_msg(digest).unwrap();
+        assert_eq!(address, recovered.to_string().to_lowercase());
+        assert_eq!(recovered, signer().address());
+    }
+
+    fn node_error(message: &str) -> Value {
+        json!({"error": {"code": -32000, "message": message}})
+    }
+
+    #[test]
+    fn test_classify_node_errors() {
+        let cases = [
+            // geth, reth
+            (
+                "nonce too low: next nonce 5, tx nonce 3",
+                FailureKind::NonceTooLow,
+            ),
+            (
+                "replacement transaction underpriced",
+                FailureKind::Underpriced,
+            ),
+            (
+                "transaction underpriced: tip needed 1, tip permitted 0",
+                FailureKind::Underpriced,
+            ),
+            (
+                "max fee per gas less than block base fee: address 0xf39F…2266, maxFeePerGas: 1, baseFee: 7",
+                FailureKind::Underpriced,
+            ),
+            (
+                "insufficient funds for gas * price + value: balance 0, tx cost 21000",
+                FailureKind::InsufficientFunds,
+            ),
+            // nethermind
+            (
+                "OldNonce, Current nonce: 5, nonce of rejected tx: 3",
+                FailureKind::NonceTooLow,
+            ),
+            (
+                "FeeTooLow, MaxFeePerGas too low. MaxFeePerGas: 1, BaseFee: 7",
+                FailureKind::Underpriced,
+            ),
+            (
+                "InsufficientFunds, Balance is zero, cannot pay gas",
+                FailureKind::InsufficientFunds,
+            ),
+            // besu
+            ("NONCE_TOO_LOW", FailureKind::NonceTooLow),
+            (
+                "Upfront cost exceeds account balance",
+                FailureKind::InsufficientFunds,
+            ),
+            (
+                "Gas price below configured minimum gas price",
+                FailureKind::Underpriced,
+            ),
+            // erigon
+            ("fee too low", FailureKind::Underpriced),
+            // through alloy's transport error
+            (
+                "server returned an error response: error code -32000: nonce too low",
+                FailureKind::NonceTooLow,
+            ),
+            ("execution reverted", FailureKind::Other),
+            ("intrinsic gas too low", FailureKind::Other),
+        ];
+        for (message, kind) in cases {
+            assert_eq!(FailureKind::classify(message), kind, "{}", message);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsendable_decisions_fail_without_sending() {
+        // nothing listens here; none of these get as far as the node
+        let (executor, _cmd_rx) = executor("http://127.0.0.1:1");
+        let unsendable = |report: Option<ExecutionReport>| {
+            matches!(
+                report,
+                Some(ExecutionReport::Failed {
+                    kind: FailureKind::Unsendable,
+                    ..
+                })
+            )
+        };
+
+        assert!(unsendable(executor.execute(&submit(1, 0, 52)).await));
+        executor.track(TransactionRequest {
+            from: [0xAA; 20],
+            ..request(2, 1)
+        });
+        assert!(unsendable(executor.execute(&submit(2, 0, 52)).await));
+        executor.track(TransactionRequest {
+            chain_id: None,
+            ..request(3, 1)
+        });
+        assert!(unsendable(executor.execute(&submit(3, 0, 52)).await));
+        let fill = SchedulerDecision::FillNonceGap {
+            tx_id: 4,
+            chain_id: 1,
+            address: [0xAA; 20],
+            nonce: 0,
+            gas_price: Wei(52),
+            fee_mode: FeeMode::Eip1559,
+        };
+        assert!(unsendable(executor.execute(&fill).await));
+        // without a relay, a private request is never sent publicly instead
+        executor.track(TransactionRequest {
+            privacy: SubmissionPrivacy::PrivateRelay,
+            ..request(5, 1)
+        });
+        assert!(unsendable(
+            executor.execute(&private(submit(5, 0, 52))).await
+        ));
+        // nor is a tx whose signature doesn't recover to its request's sender
+        let (feedback, _req_rx, _cmd_rx) = SchedulerHandle::channel(10);
+        let impostor = Executor::new(
+            executor.provider.clone(),
+            Impostor(LocalSigner::new(PrivateKeySigner::random())),
+            feedback,
+        );
+        impostor.track(request(6, 1));
+        assert!(matches!(
+            impostor.execute(&submit(6, 0, 52)).await,
+            Some(ExecutionReport::Failed {
+                kind: FailureKind::Unsendable,
+                message,
+                ..
+            }) if message.starts_with("signed by")
+        ));
+
+        let drop = SchedulerDecision::Drop {
+            tx_id: 2,
+            reason: "expired".into(),
+        };
+        assert_eq!(executor.execute(&drop).await, None);
+        assert!(!executor.txs.contains_key(&2));
+    }
+
+    #[tokio::test]
+    async fn test_requests_without_a_chain_take_the_default() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (executor, _cmd_rx) = executor(&format!("http://{}", listener.local_addr().unwrap()));
+        let executor = executor.with_default_chain_id(10);
+        executor.track(TransactionRequest {
+            chain_id: None,
+            ..request(1, 1)
+        });
+        let decision = submit(1, 0, 52);
+        let (report, call) = tokio::join!(
+            executor.execute(&decision),
+            respond(
+                &listener,
+                json!({"result": format!("0x{}", "11".repeat(32))}),
+            )
+        );
+        assert!(matches!(
+            report,
+            Some(ExecutionReport::Broadcast { tx_id: 1, .. })
+        ));
+        assert_eq!(sent_tx(&call).0.chain_id(), Some(10));
+    }
+
+    #[tokio::test]
+    async fn test_broadcasts_and_reports_back() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (executor, mut cmd_rx) =
+            executor(&format!("http://{}", listener.local_addr().unwrap()));
+        for id in 1..=4 {
+            executor.track(request(id, 1));
+        }
+        let (decision_tx, decision_rx) = mpsc::channel(10);
+        let task = tokio::spawn(executor.run(decision_rx));
+
+        decision_tx.send(submit(1, 3, 50)).await.unwrap();
+        let call = respond(
+            &listener,
+            json!({"result": format!("0x{}", "11".repeat(32))}),
+        )
+        .await;
+        let (tx, hash) = sent_tx(&call);
+        assert_eq!((tx.nonce(), tx.max_fee_per_gas()), (3, 50));
+        assert_eq!(
+            cmd_rx.recv().await,
+            Some(SchedulerCommand::Broadcast {
+                tx_id: 1,
+                tx_hash: hash
+            })
+        );
+
+        // a stale nonce on the reprice doesn't move the tx off the one it was sent with
+        decision_tx.send(reprice(1, 9, 60)).await.unwrap();
+        let call = respond(
+            &listener,
+            json!({"result": format!("0x{}", "22".repeat(32))}),
+        )
+        .await;
+        let (tx, hash) = sent_tx(&call);
+        assert_eq!((tx.nonce(), tx.max_fee_per_gas()), (3, 60));
+        assert_eq!(
+            cmd_rx.recv().await,
+            Some(SchedulerCommand::Broadcast {
+                tx_id: 1,
+                tx_hash: hash
+            })
+        );
+
+        decision_tx.send(submit(2, 4, 50)).await.unwrap();
+        respond(
+            &listener,
+            node_error("nonce too low: next nonce 5, tx nonce 4"),
+        )
+        .await;
+        assert!(matches!(
+            cmd_rx.recv().await,
+            Some(SchedulerCommand::BroadcastFailed { tx_id: 2, .. })
+        ));
+        let call = respond(&listener, json!({"result": "0x5"})).await;
+        assert_eq!(call["method"], "eth_getTransactionCount");
+        assert_eq!(
+            cmd_rx.recv().await,
+            Some(SchedulerCommand::NonceTooLow {
+                chain_id: Some(1),
+                address: signer().address().0.0,
+                network_nonce: 5,
+            })
+        );
+
+        // the earlier broadcast stands, so a failed reprice isn't reported
+        decision_tx.send(reprice(1, 3, 61)).await.unwrap();
+        respond(&listener, node_error("replacement transaction underpriced")).await;
+        decision_tx.send(submit(7, 0, 50)).await.unwrap();
+        assert!(matches!(
+            cmd_rx.recv().await,
+            Some(SchedulerCommand::BroadcastFailed { tx_id: 7, .. })
+        ));
+
+        // once the scheduler is gone, what's left is still broadcast
+        drop(cmd_rx);
+        decision_tx.send(submit(3, 5, 50)).await.unwrap();
+        decision_tx.send(submit(4, 6, 50)).await.unwrap();
+        drop(decision_tx);
+        for nonce in [5, 6] {
+            let call = respond(
+                &listener,
+                json!({"result": format!("0x{}", "33".repeat(32))}),
+            )
+            .await;
+            assert_eq!(sent_tx(&call).0.nonce(), nonce);
+        }
+        task.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_private_requests_go_to_the_relay() {
+        let node = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let relay = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let (executor, mut cmd_rx) = executor(&format!("http://{}", node.local_addr().unwrap()));
+        let executor = executor.with_private_relay(
+            PrivateRelay::new(format!("http://{}", relay.local_addr().unwrap()))
+                .with_auth_key(signer()),
+        );
+        let now_secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        executor.track(TransactionRequest {
+            privacy: SubmissionPrivacy::PrivateRelay,
+            ..request(1, 1)
+        });
+        executor.track(TransactionRequest {
+            privacy: SubmissionPrivacy::PrivateRelay,
+            deadline: Some(now_secs + 65),
+            ..request(2, 1)
+        });
+        executor.track(request(3, 1));
+        let (decision_tx, decision_rx) = mpsc::channel(10);
+        tokio::spawn(executor.run(decision_rx));
+        let head = json!({"result": "0x64"});
+        let accepted = json!({"result": format!("0x{}", "11".repeat(32))});
+        let broadcast = |tx_id, tx_hash| Some(SchedulerCommand::Broadcast { tx_id, tx_hash });
+
+        // without a deadline the tx stays eligible for the default 25 blocks
+        decision_tx.send(private(submit(1, 3, 50))).await.unwrap();
+        assert_eq!(
+            respond(&node, head.clone()).await["method"],
+            "eth_blockNumber"
+        );
+        let call = respond_http(&relay, accepted.clone()).await;
+        let (tx, hash, max_block) = relayed_tx(&call);
+        assert_eq!(
+            (tx.nonce(), tx.max_fee_per_gas(), max_block),
+            (3, 50, "0x7d")
+        );
+        assert_eq!(cmd_rx.recv().await, broadcast(1, hash));
+
+        // the reprice replaces it at the relay too
+        decision_tx.send(private(reprice(1, 3, 60))).await.unwrap();
+        respond(&node, head.clone()).await;
+        let call = respond_http(&relay, accepted.clone()).await;
+        let (tx, repriced, _) = relayed_tx(&call);
+        assert_eq!((tx.nonce(), tx.max_fee_per_gas()), (3, 60));
+        assert_eq!(cmd_rx.recv().await, broadcast(1, repriced));
+
+        // 65 seconds leave 5 blocks
+        decision_tx.send(private(submit(2, 4, 50))).await.unwrap();
+        respond(&node, head).await;
+        let call = respond_http(&relay, accepted.clone()).await;
+        let (_, hash, max_block) = relayed_tx(&call);
+        assert_eq!(max_block, "0x69");
+        assert_eq!(cmd_rx.recv().await, broadcast(2, hash));
+
+        // a public request goes to the node as before
+        decision_tx.send(submit(3, 5, 50)).await.unwrap();
+        let (tx, hash) = sent_tx(&respond(&node, accepted).await);
+        assert_eq!(tx.nonce(), 5);
+        assert_eq!(cmd_rx.recv().await, broadcast(3, hash));
+
+        // dropping a public tx cancels nothing; dropping a private one cancels
+        // what was last sent for it
+        for tx_id in [3, 1] {
+            let drop = SchedulerDecision::Drop {
+                tx_id,
+                reason: "expired".into(),
+            };
+            decision_tx.send(drop).await.unwrap();
+        }
+        let (head, body, call) = respond_http(&relay, json!({"result": true})).await;
+        assert_signed(&head, &body);
+        assert_eq!(call["method"], "eth_cancelPrivateTransaction");
+        assert_eq!(call["params"][0]["txHash"], hex_hash(&repriced));
+    }
+
+    /// Run with `cargo test --features executor -- --ignored` against a fresh
+    /// `anvil`, or set `ANVIL_URL` to point elsewhere.
+    #[tokio::test]
+    #[ignore = "needs a local anvil node"]
+    async fn test_against_anvil() {
+        let url = std::env::var("ANVIL_URL").unwrap_or("http://127.0.0.1:8545".into());
+        let (executor, _cmd_rx) = executor(&url);
+        let nonce = executor
+            .provider
+            .get_transaction_count(signer().address())
+            .await
+            .unwrap();
+        let chain_id = executor.provider.get_chain_id().await.unwrap();
+        executor.track(request(1, chain_id));
+        executor.track(request(2, chain_id));
+
+        let sent = executor.execute(&submit(1, nonce, 100_000_000_000)).await;
+        assert!(matches!(
+            sent,
+            Some(ExecutionReport::Broadcast { tx_id: 1, .. })
+        ));
+        // anvil mines on arrival, so the nonce is already used
+        assert!(matches!(
+            executor.execute(&submit(2, nonce, 100_000_000_000)).await,
+            Some(ExecutionReport::Failed {
+                kind: FailureKind::NonceTooLow,
+                ..
+            })
+        ));
+    }
+}
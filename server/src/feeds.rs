@@ -0,0 +1,89 @@
+pub mod aggregate;
+#[cfg(feature = "http-feed")]
+pub mod fee_history;
+#[cfg(feature = "http-feed")]
+pub mod l1_fee;
+#[cfg(feature = "ws-feed")]
+pub mod mempool;
+#[cfg(feature = "http-feed")]
+pub mod oracle;
+#[cfg(feature = "ws-feed")]
+pub mod ws;
+
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Why a feed stopped for good. Dropped connections are not errors; feeds
+/// reconnect on their own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FeedError {
+    /// The endpoint can't be connected to as given.
+    InvalidUrl(String),
+    /// The node refused the subscription; retrying won't change its mind.
+    Rejected(String),
+}
+
+impl std::fmt::Display for FeedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeedError::InvalidUrl(reason) => write!(f, "invalid feed url: {}", reason),
+            FeedError::Rejected(reason) => write!(f, "subscription rejected: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for FeedError {}
+
+/// Failures in a row after which a feed counts as unhealthy.
+pub const UNHEALTHY_AFTER: u32 = 3;
+
+/// A feed's view of its endpoint, shared with whoever reports on it. Clones see
+/// the same state.
+#[derive(Debug, Clone, Default)]
+pub struct FeedHealth {
+    inner: Arc<HealthState>,
+}
+
+#[derive(Debug, Default)]
+struct HealthState {
+    failures: AtomicU32,
+    last_error: Mutex<Option<String>>,
+    last_success: Mutex<Option<Instant>>,
+}
+
+impl FeedHealth {
+    /// False once `UNHEALTHY_AFTER` calls in a row have failed, until one succeeds.
+    pub fn is_healthy(&self) -> bool {
+        self.consecutive_failures() < UNHEALTHY_AFTER
+    }
+
+    pub fn consecutive_failures(&self) -> u32 {
+        self.inner.failures.load(Ordering::Relaxed)
+    }
+
+    /// The most recent failure, kept after the feed recovers.
+    pub fn last_error(&self) -> Option<String> {
+        self.inner.last_error.lock().clone()
+    }
+
+    /// Time since the last call that succeeded; None before the first.
+    pub fn since_last_success(&self) -> Option<Duration> {
+        self.inner.last_success.lock().map(|at| at.elapsed())
+    }
+
+    #[cfg(feature = "http-feed")]
+    pub(crate) fn succeeded(&self) {
+        *self.inner.last_success.lock() = Some(Instant::now());
+        self.inner.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Returns the failures in a row, this one included.
+    #[cfg(feature = "http-feed")]
+    pub(crate) fn failed(&self, error: String) -> u32 {
+        *self.inner.last_error.lock() = Some(error);
+        self.inner.failures.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
@@ -0,0 +1,504 @@
+use crate::events::GasEvent;
+use futures::StreamExt;
+use futures::stream::select_all;
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_stream::wrappers::ReceiverStream;
+use tracing::{info, warn};
+
+/// Events each source may have queued before its feed waits.
+const SOURCE_CAPACITY: usize = 100;
+/// Blocks whose reported base fees are kept for cross-checking.
+const BLOCKS_KEPT: usize = 64;
+
+/// Live sources disagreeing on a block's base fee by more than the threshold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub block_number: u64,
+    /// Each source's name and the base fee it reported, in the order they came.
+    pub reports: Vec<(String, u64)>,
+    /// The median, which is what the scheduler got if the block was forwarded
+    /// after the reports diverged.
+    pub median: u64,
+}
+
+/// One source as the aggregator sees it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceStatus {
+    pub name: String,
+    /// Its events are the ones being forwarded.
+    pub active: bool,
+    /// It sent an event within the stale timeout, or the aggregator started that
+    /// recently.
+    pub live: bool,
+    pub events: u64,
+    /// Blocks it reported in that were flagged as divergent.
+    pub divergences: u64,
+    /// Time since its last event; None if it never sent one.
+    pub since_last_event: Option<Duration>,
+}
+
+/// Per-source state of a running aggregator, for health reporting. Clones see
+/// the same state.
+#[derive(Debug, Clone)]
+pub struct AggregateHealth {
+    inner: Arc<Mutex<State>>,
+}
+
+impl AggregateHealth {
+    pub fn sources(&self) -> Vec<SourceStatus> {
+        let state = self.inner.lock();
+        let active = state.active();
+        state
+            .sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| SourceStatus {
+                name: source.name.clone(),
+                active: active == Some(i),
+                live: state.is_live(i),
+                events: source.events,
+                divergences: source.divergences,
+                since_last_event: source.last_event.map(|at| at.elapsed()),
+            })
+            .collect()
+    }
+
+    /// The source being forwarded, if any is live.
+    pub fn active(&self) -> Option<String> {
+        let state = self.inner.lock();
+        state.active().map(|i| state.sources[i].name.clone())
+    }
+
+    /// True while at least one source is live.
+    pub fn is_healthy(&self) -> bool {
+        self.inner.lock().active().is_some()
+    }
+}
+
+#[derive(Debug)]
+struct Source {
+    name: String,
+    /// Its last event, or when the aggregator started.
+    last_seen: Instant,
+    last_event: Option<Instant>,
+    events: u64,
+    divergences: u64,
+}
+
+#[derive(Debug)]
+struct Block {
+    /// Source index and base fee, in the order they came.
+    reports: Vec<(usize, u64)>,
+    flagged: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    sources: Vec<Source>,
+    stale_after: Duration,
+    divergence_threshold: f64,
+    blocks: BTreeMap<u64, Block>,
+    /// The active source as of the last event, for logging switches.
+    forwarding: Option<usize>,
+}
+
+impl State {
+    fn is_live(&self, source: usize) -> bool {
+        self.sources[source].last_seen.elapsed() < self.stale_after
+    }
+
+    /// The most preferred live source.
+    fn active(&self) -> Option<usize> {
+        (0..self.sources.len()).find(|&i| self.is_live(i))
+    }
+
+    /// Records the base fee `source` reported for `number`. Returns the fee to
+    /// forward for it: the median of the reports if they diverge, else the
+    /// source's own.
+    fn report(&mut self, source: usize, number: u64, base_fee: u64) -> (u64, Option<Divergence>) {
+        let block = self.blocks.entry(number).or_insert(Block {
+            reports: Vec::new(),
+            flagged: false,
+        });
+        block.reports.retain(|&(i, _)| i != source);
+        block.reports.push((source, base_fee));
+        let mut fees: Vec<u64> = block.reports.iter().map(|&(_, fee)| fee).collect();
+        fees.sort_unstable();
+        let median = median(&fees);
+        let spread = (fees[fees.len() - 1] - fees[0]) as f64;
+        let diverged = spread > self.divergence_threshold * median as f64;
+        let mut divergence = None;
+        if diverged && !block.flagged {
+            block.flagged = true;
+            for &(i, _) in &block.reports {
+                self.sources[i].divergences += 1;
+            }
+            divergence = Some(Divergence {
+                block_number: number,
+                reports: block
+                    .reports
+                    .iter()
+                    .map(|&(i, fee)| (self.sources[i].name.clone(), fee))
+                    .collect(),
+                median,
+            });
+        } else if diverged {
+            self.sources[source].divergences += 1;
+        }
+        while self.blocks.len() > BLOCKS_KEPT {
+            self.blocks.pop_first();
+        }
+        (if diverged { median } else { base_fee }, divergence)
+    }
+}
+
+/// Of sorted `fees`: the middle one, or the mean of the middle two.
+fn median(fees: &[u64]) -> u64 {
+    let mid = fees.len() / 2;
+    if fees.len() % 2 == 1 {
+        fees[mid]
+    } else {
+        fees[mid - 1] + (fees[mid] - fees[mid - 1]) / 2
+    }
+}
+
+/// Reconciles several gas feeds into one stream. Each feed sends to its own
+/// `add_source` sender. Only the events of the most preferred live source go on
+/// to the scheduler: the primary, the first added, while it's live, else the next
+/// live one in the order added. A source is live until it has gone `stale_after`
+/// without an event.
+///
+/// Base fees every source reports for a block, from `NewBlock`s and
+/// `FeeHistory`s, are cross-checked. When they spread further apart than the
+/// threshold times their median, a `Divergence` is logged and sent, and blocks
+/// forwarded after that carry the median instead. Reports for a block that was
+/// already forwarded can only be flagged.
+pub struct Aggregator {
+    names: Vec<String>,
+    receivers: Vec<mpsc::Receiver<GasEvent>>,
+    stale_after: Duration,
+    divergence_threshold: f64,
+    divergences: Option<mpsc::Sender<Divergence>>,
+}
+
+impl Aggregator {
+    /// Fails over from a source after `stale_after` without events. Base fees
+    /// diverge when they spread more than 10% of their median apart.
+    pub fn new(stale_after: Duration) -> Self {
+        Self {
+            names: Vec::new(),
+            receivers: Vec::new(),
+            stale_after,
+            divergence_threshold: 0.1,
+            divergences: None,
+        }
+    }
+
+    /// The fraction of the median reported base fees may spread apart by.
+    pub fn with_divergence_threshold(mut self, threshold: f64) -> Self {
+        self.divergence_threshold = threshold;
+        self
+    }
+
+    /// Also sends each `Divergence` to `divergences`, if it has room.
+    pub fn with_divergences(mut self, divergences: mpsc::Sender<Divergence>) -> Self {
+        self.divergences = Some(divergences);
+        self
+    }
+
+    /// Adds a source, less preferred than those added before; returns the sender
+    /// its feed should send to. `name` tags it in logs and health.
+    pub fn add_source(&mut self, name: impl Into<String>) -> mpsc::Sender<GasEvent> {
+        let (tx, rx) = mpsc::channel(SOURCE_CAPACITY);
+        self.names.push(name.into());
+        self.receivers.push(rx);
+        tx
+    }
+
+    /// Forwards reconciled events to `gas_tx` until every source's sender is
+    /// dropped or `gas_tx`'s receiver is.
+    pub fn spawn(self, gas_tx: mpsc::Sender<GasEvent>) -> (JoinHandle<()>, AggregateHealth) {
+        let now = Instant::now();
+        let state = State {
+            sources: self
+                .names
+                .into_iter()
+                .map(|name| Source {
+                    name,
+                    last_seen: now,
+                    last_event: None,
+                    events: 0,
+                    divergences: 0,
+                })
+                .collect(),
+            stale_after: self.stale_after,
+            divergence_threshold: self.divergence_threshold,
+            blocks: BTreeMap::new(),
+            forwarding: None,
+        };
+        let health = AggregateHealth {
+            inner: Arc::new(Mutex::new(state)),
+        };
+        let merged = select_all(
+            self.receivers
+                .into_iter()
+                .enumerate()
+                .map(|(i, rx)| ReceiverStream::new(rx).map(move |event| (i, event))),
+        );
+        let task = tokio::spawn(run(merged, health.clone(), self.divergences, gas_tx));
+        (task, health)
+    }
+}
+
+async fn run(
+    mut merged: impl futures::Stream<Item = (usize, GasEvent)> + Unpin,
+    health: AggregateHealth,
+    divergences: Option<mpsc::Sender<Divergence>>,
+    gas_tx: mpsc::Sender<GasEvent>,
+) {
+    loop {
+        let (source, event) = tokio::select! {
+            next = merged.next() => match next {
+                Some(next) => next,
+                None => return,
+            },
+            _ = gas_tx.closed() => return,
+        };
+        let (forward, flagged) = reconcile(&mut health.inner.lock(), source, event);
+        for divergence in flagged {
+            warn!(
+                "FEED DIVERGENCE: block {} base fees {:?}, using {}",
+                divergence.block_number, divergence.reports, divergence.median
+            );
+            if let Some(divergences) = &divergences {
+                let _ = divergences.try_send(divergence);
+            }
+        }
+        if let Some(event) = forward
+            && gas_tx.send(event).await.is_err()
+        {
+            return;
+        }
+    }
+}
+
+/// Records `event` from `source`; returns it, with median base fees where
+/// reports diverged, if `source` is the one to forward.
+fn reconcile(
+    state: &mut State,
+    source: usize,
+    mut event: GasEvent,
+) -> (Option<GasEvent>, Vec<Divergence>) {
+    let now = Instant::now();
+    let entry = &mut state.sources[source];
+    entry.last_seen = now;
+    entry.last_event = Some(now);
+    entry.events += 1;
+
+    let mut flagged = Vec::new();
+    let mut check = |state: &mut State, number, base_fee: &mut u64| {
+        let (fee, divergence) = state.report(source, number, *base_fee);
+        *base_fee = fee;
+        flagged.extend(divergence);
+    };
+    match &mut event {
+        GasEvent::NewBlock {
+            number, base_fee, ..
+        } => check(state, *number, base_fee),
+        GasEvent::FeeHistory {
+            oldest_block,
+            base_fees,
+            ..
+        } => {
+            // the last is the next block's, which no block reports yet
+            let mined = base_fees.len().saturating_sub(1);
+            for (i, base_fee) in base_fees[..mined].iter_mut().enumerate() {
+                check(state, *oldest_block + i as u64, base_fee);
+            }
+        }
+        _ => {}
+    }
+
+    let active = state.active();
+    if active != state.forwarding {
+        match active {
+            Some(i) => info!("FEED: forwarding {}", state.sources[i].name),
+            None => warn!("FEED: every source is stale"),
+        }
+        state.forwarding = active;
+    }
+    ((active == Some(source)).then_some(event), flagged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(number: u64, base_fee: u64) -> GasEvent {
+        GasEvent::NewBlock {
+            number,
+            base_fee,
+            gas_used: 15_000_000,
+            gas_limit: 30_000_000,
+            block_hash: [0; 32],
+            parent_hash: [0; 32],
+        }
+    }
+
+    fn base_fee_of(event: Option<GasEvent>) -> Option<u64> {
+        match event {
+            Some(GasEvent::NewBlock { base_fee, .. }) => Some(base_fee),
+            _ => None,
+        }
+    }
+
+    /// Lets the aggregator task handle everything sent so far.
+    async fn settle() {
+        for _ in 0..10 {
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fails_over_when_the_primary_goes_quiet() {
+        let mut aggregator = Aggregator::new(Duration::from_secs(30));
+        let primary = aggregator.add_source("primary");
+        let backup = aggregator.add_source("backup");
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (task, health) = aggregator.spawn(gas_tx);
+
+        // both are live; only the primary is forwarded
+        primary.send(block(1, 100)).await.unwrap();
+        backup.send(block(1, 100)).await.unwrap();
+        backup.send(block(2, 101)).await.unwrap();
+        primary.send(block(2, 101)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(100));
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(101));
+        settle().await;
+        assert!(gas_rx.try_recv().is_err());
+        assert_eq!(health.active().as_deref(), Some("primary"));
+
+        // the primary falls silent; the backup takes over once it's stale
+        tokio::time::advance(Duration::from_secs(20)).await;
+        backup.send(block(3, 102)).await.unwrap();
+        settle().await;
+        assert!(gas_rx.try_recv().is_err());
+        tokio::time::advance(Duration::from_secs(11)).await;
+        backup.send(block(4, 103)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(103));
+        let sources = health.sources();
+        assert_eq!(
+            sources
+                .iter()
+                .map(|s| (s.name.as_str(), s.active, s.live, s.events))
+                .collect::<Vec<_>>(),
+            vec![("primary", false, false, 2), ("backup", true, true, 4)]
+        );
+        assert_eq!(sources[0].since_last_event, Some(Duration::from_secs(31)));
+
+        // and hands back as soon as the primary speaks again
+        primary.send(block(5, 104)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(104));
+        backup.send(block(5, 104)).await.unwrap();
+        settle().await;
+        assert!(gas_rx.try_recv().is_err());
+        assert_eq!(health.active().as_deref(), Some("primary"));
+
+        // everything stale: nothing is active, but the next event still counts
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(!health.is_healthy());
+        backup.send(block(6, 105)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(105));
+
+        drop((primary, backup));
+        task.await.unwrap();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_divergent_base_fees_take_the_median() {
+        let (divergence_tx, mut divergence_rx) = mpsc::channel(10);
+        let mut aggregator = Aggregator::new(Duration::from_secs(30))
+            .with_divergence_threshold(0.1)
+            .with_divergences(divergence_tx);
+        let primary = aggregator.add_source("primary");
+        let second = aggregator.add_source("second");
+        let third = aggregator.add_source("third");
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (_task, health) = aggregator.spawn(gas_tx);
+
+        // within 10% of each other: the primary's own fee goes through
+        second.send(block(10, 100)).await.unwrap();
+        settle().await;
+        primary.send(block(10, 105)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(105));
+        assert!(divergence_rx.try_recv().is_err());
+
+        // the primary serves garbage; the median of the three wins
+        second.send(block(11, 100)).await.unwrap();
+        third.send(block(11, 102)).await.unwrap();
+        settle().await;
+        primary.send(block(11, 150)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(102));
+        assert_eq!(
+            divergence_rx.recv().await,
+            Some(Divergence {
+                block_number: 11,
+                reports: vec![
+                    ("second".into(), 100),
+                    ("third".into(), 102),
+                    ("primary".into(), 150),
+                ],
+                median: 102,
+            })
+        );
+
+        // a late report against a forwarded block is only flagged, once
+        primary.send(block(12, 100)).await.unwrap();
+        assert_eq!(base_fee_of(gas_rx.recv().await), Some(100));
+        second.send(block(12, 200)).await.unwrap();
+        settle().await;
+        third.send(block(12, 201)).await.unwrap();
+        let late = divergence_rx.recv().await.unwrap();
+        assert_eq!((late.block_number, late.median), (12, 150));
+        settle().await;
+        assert!(divergence_rx.try_recv().is_err());
+        assert!(gas_rx.try_recv().is_err());
+        let divergences: Vec<_> = health.sources().iter().map(|s| s.divergences).collect();
+        assert_eq!(divergences, vec![2, 2, 2]);
+
+        // fee history blocks are checked the same way; the next block's fee isn't
+        second.send(block(13, 100)).await.unwrap();
+        third.send(block(13, 100)).await.unwrap();
+        settle().await;
+        let history = GasEvent::FeeHistory {
+            oldest_block: 13,
+            base_fees: vec![130, 101],
+            gas_used_ratios: vec![0.5],
+            rewards: vec![],
+        };
+        primary.send(history).await.unwrap();
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::FeeHistory {
+                oldest_block: 13,
+                base_fees: vec![100, 101],
+                gas_used_ratios: vec![0.5],
+                rewards: vec![],
+            })
+        );
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median(&[7]), 7);
+        assert_eq!(median(&[1, 2, 9]), 2);
+        assert_eq!(median(&[100, 201]), 150);
+        assert_eq!(median(&[u64::MAX - 1, u64::MAX]), u64::MAX - 1);
+    }
+}
@@ -0,0 +1,424 @@
+use super::{FeedHealth, UNHEALTHY_AFTER};
+use crate::events::GasEvent;
+use crate::limiter::RateLimiter;
+use alloy_primitives::U64;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+/// Longest wait between polls while the endpoint keeps failing.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How a poll's new blocks reach the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Emit {
+    /// One `GasEvent::FeeHistory` per poll, with rewards when percentiles were
+    /// asked for.
+    #[default]
+    FeeHistory,
+    /// A `GasEvent::BaseFeeUpdate` per new block, without a timestamp.
+    BaseFeeUpdates,
+}
+
+/// Polls `eth_feeHistory` over HTTP, for nodes without a websocket endpoint.
+pub struct FeeHistoryFeed {
+    url: String,
+    interval: Duration,
+    block_count: u64,
+    reward_percentiles: Vec<f64>,
+    emit: Emit,
+    limiter: Arc<RateLimiter>,
+}
+
+impl FeeHistoryFeed {
+    /// Asks for the last `block_count` blocks every `interval`, at most once a
+    /// second unless given another limiter.
+    pub fn new(
+        url: impl Into<String>,
+        interval: Duration,
+        block_count: u64,
+        reward_percentiles: Vec<f64>,
+    ) -> Self {
+        Self {
+            url: url.into(),
+            interval,
+            block_count,
+            reward_percentiles,
+            emit: Emit::default(),
+            limiter: Arc::new(RateLimiter::new(1, 1)),
+        }
+    }
+
+    pub fn with_emit(mut self, emit: Emit) -> Self {
+        self.emit = emit;
+        self
+    }
+
+    /// Polls only with a token from `limiter`, e.g. one shared with other calls
+    /// to the same provider.
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Polls until `gas_tx`'s receiver is dropped. Blocks already reported are
+    /// left out of later events. A failed poll is retried after twice the wait of
+    /// the last one, up to a minute, and counts against the returned health.
+    pub fn spawn(self, gas_tx: mpsc::Sender<GasEvent>) -> (JoinHandle<()>, FeedHealth) {
+        let health = FeedHealth::default();
+        (tokio::spawn(self.run(gas_tx, health.clone())), health)
+    }
+
+    async fn run(self, gas_tx: mpsc::Sender<GasEvent>, health: FeedHealth) {
+        let client = match reqwest::Client::builder().timeout(REQUEST_TIMEOUT).build() {
+            Ok(client) => client,
+            Err(e) => {
+                health.failed(e.to_string());
+                warn!("fee history feed can't start: {}", e);
+                return;
+            }
+        };
+        let mut last_reported = None;
+        loop {
+            self.limiter.acquire().await;
+            let delay = match self.poll(&client).await {
+                Ok(history) => {
+                    health.succeeded();
+                    let newest = history.newest();
+                    for event in history.events(self.emit, last_reported) {
+                        if gas_tx.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                    last_reported = last_reported.max(newest);
+                    self.interval
+                }
+                Err(e) => {
+                    let failures = health.failed(e.clone());
+                    let delay = backoff(self.interval, failures);
+                    if failures == UNHEALTHY_AFTER {
+                        warn!("fee history feed is unhealthy after {} failures", failures);
+                    }
+                    warn!("eth_feeHistory failed: {}; retrying in {:?}", e, delay);
+                    delay
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = gas_tx.closed() => return,
+            }
+        }
+    }
+
+    async fn poll(&self, client: &reqwest::Client) -> Result<History, String> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_feeHistory",
+            "params": [format!("{:#x}", self.block_count), "latest", self.reward_percentiles],
+        });
+        let response = client
+            .post(&self.url)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("HTTP {}", status));
+        }
+        parse_response(&response.bytes().await.map_err(|e| e.to_string())?)
+    }
+}
+
+/// `FeeHistoryFeed::new(..).spawn(gas_tx)`.
+pub fn spawn(
+    http_url: impl Into<String>,
+    interval: Duration,
+    block_count: u64,
+    reward_percentiles: Vec<f64>,
+    gas_tx: mpsc::Sender<GasEvent>,
+) -> (JoinHandle<()>, FeedHealth) {
+    FeeHistoryFeed::new(http_url, interval, block_count, reward_percentiles).spawn(gas_tx)
+}
+
+pub(super) fn backoff(interval: Duration, failures: u32) -> Duration {
+    interval
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF.max(interval))
+}
+
+/// An `eth_feeHistory` result, checked for consistent lengths.
+#[derive(Debug, PartialEq)]
+struct History {
+    oldest_block: u64,
+    base_fees: Vec<u64>,
+    gas_used_ratios: Vec<f64>,
+    rewards: Vec<Vec<u64>>,
+}
+
+impl History {
+    fn newest(&self) -> Option<u64> {
+        (self.gas_used_ratios.len() as u64)
+            .checked_sub(1)
+            .map(|newest| self.oldest_block + newest)
+    }
+
+    /// Events for the blocks after `last_reported`; none if there are none.
+    fn events(self, emit: Emit, last_reported: Option<u64>) -> Vec<GasEvent> {
+        let skip = last_reported.map_or(0, |last| {
+            (last + 1).saturating_sub(self.oldest_block) as usize
+        });
+        let blocks = self.gas_used_ratios.len();
+        if skip >= blocks {
+            return Vec::new();
+        }
+        match emit {
+            Emit::FeeHistory => vec![GasEvent::FeeHistory {
+                oldest_block: self.oldest_block + skip as u64,
+                base_fees: self.base_fees[skip..].to_vec(),
+                gas_used_ratios: self.gas_used_ratios[skip..].to_vec(),
+                rewards: self.rewards.get(skip..).unwrap_or_default().to_vec(),
+            }],
+            Emit::BaseFeeUpdates => self.base_fees[skip..blocks]
+                .iter()
+                .map(|&base_fee| GasEvent::BaseFeeUpdate {
+                    base_fee,
+                    timestamp: 0,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FeeHistoryResult {
+    oldest_block: U64,
+    base_fee_per_gas: Vec<U64>,
+    gas_used_ratio: Vec<f64>,
+    /// Left out, or null, by some providers when no percentiles were asked for.
+    #[serde(default)]
+    reward: Option<Vec<Vec<U64>>>,
+}
+
+fn parse_response(body: &[u8]) -> Result<History, String> {
+    let response: Value = serde_json::from_slice(body).map_err(|e| e.to_string())?;
+    if let Some(error) = response.get("error") {
+        return Err(format!("node returned {}", error));
+    }
+    let result = response.get("result").ok_or("response without a result")?;
+    let result = FeeHistoryResult::deserialize(result).map_err(|e| e.to_string())?;
+    let history = History {
+        oldest_block: result.oldest_block.to(),
+        base_fees: result.base_fee_per_gas.iter().map(|fee| fee.to()).collect(),
+        gas_used_ratios: result.gas_used_ratio,
+        rewards: result
+            .reward
+            .unwrap_or_default()
+            .into_iter()
+            .map(|row| row.iter().map(|tip| tip.to()).collect())
+            .collect(),
+    };
+    let blocks = history.gas_used_ratios.len();
+    if history.base_fees.len() != blocks + 1
+        || !(history.rewards.is_empty() || history.rewards.len() == blocks)
+    {
+        return Err(format!(
+            "{} base fees and {} reward rows for {} blocks",
+            history.base_fees.len(),
+            history.rewards.len(),
+            blocks
+        ));
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// geth, asked for the 25th and 75th percentiles over three blocks.
+    const GETH: &str = r#"{
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "oldestBlock": "0x1312cfe",
+            "reward": [
+                ["0x5f5e100", "0x77359400"],
+                ["0x3b9aca00", "0x9502f900"],
+                ["0x2faf080", "0x59682f00"]
+            ],
+            "baseFeePerGas": ["0x2e90edd00", "0x2f34f60c6", "0x2dd8c7d2e", "0x2e18e6d76"],
+            "gasUsedRatio": [0.5621, 0.3712, 0.5346],
+            "baseFeePerBlobGas": ["0x1", "0x1", "0x1", "0x1"],
+            "blobGasUsedRatio": [0, 0.5, 0]
+        }
+    }"#;
+
+    /// A hosted provider asked for no percentiles, which leaves `reward` out.
+    const HOSTED: &str = r#"{"jsonrpc":"2.0","id":1,"result":{"oldestBlock":"0x1312d00","baseFeePerGas":["0x3b9aca00","0x3a699d00"],"gasUsedRatio":[0.4]}}"#;
+
+    fn history() -> History {
+        parse_response(GETH.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_provider_fixtures() {
+        assert_eq!(
+            history(),
+            History {
+                oldest_block: 19_999_998,
+                base_fees: vec![
+                    12_500_000_000,
+                    12_672_000_198,
+                    12_306_906_414,
+                    12_374_142_326
+                ],
+                gas_used_ratios: vec![0.5621, 0.3712, 0.5346],
+                rewards: vec![
+                    vec![100_000_000, 2_000_000_000],
+                    vec![1_000_000_000, 2_500_000_000],
+                    vec![50_000_000, 1_500_000_000],
+                ],
+            }
+        );
+        assert_eq!(
+            parse_response(HOSTED.as_bytes()),
+            Ok(History {
+                oldest_block: 20_000_000,
+                base_fees: vec![1_000_000_000, 980_000_000],
+                gas_used_ratios: vec![0.4],
+                rewards: vec![],
+            })
+        );
+        let null_reward = HOSTED.replace(r#""gasUsedRatio""#, r#""reward":null,"gasUsedRatio""#);
+        assert!(parse_response(null_reward.as_bytes()).is_ok());
+
+        let short = HOSTED.replace(r#","0x3a699d00""#, "");
+        assert!(parse_response(short.as_bytes()).is_err());
+        let refused =
+            r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32005,"message":"rate limited"}}"#;
+        assert!(
+            parse_response(refused.as_bytes())
+                .unwrap_err()
+                .contains("rate limited")
+        );
+    }
+
+    #[test]
+    fn test_events_leave_out_reported_blocks() {
+        assert_eq!(history().newest(), Some(20_000_000));
+        assert_eq!(
+            history().events(Emit::FeeHistory, Some(19_999_998)),
+            vec![GasEvent::FeeHistory {
+                oldest_block: 19_999_999,
+                base_fees: vec![12_672_000_198, 12_306_906_414, 12_374_142_326],
+                gas_used_ratios: vec![0.3712, 0.5346],
+                rewards: vec![
+                    vec![1_000_000_000, 2_500_000_000],
+                    vec![50_000_000, 1_500_000_000],
+                ],
+            }]
+        );
+        assert_eq!(
+            history().events(Emit::BaseFeeUpdates, Some(19_999_999)),
+            vec![GasEvent::BaseFeeUpdate {
+                base_fee: 12_306_906_414,
+                timestamp: 0,
+            }]
+        );
+        assert_eq!(history().events(Emit::BaseFeeUpdates, None).len(), 3);
+        assert!(
+            history()
+                .events(Emit::FeeHistory, Some(20_000_000))
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        let interval = Duration::from_secs(12);
+        assert_eq!(backoff(interval, 1), Duration::from_secs(24));
+        assert_eq!(backoff(interval, 2), Duration::from_secs(48));
+        assert_eq!(backoff(interval, 3), MAX_BACKOFF);
+        assert_eq!(
+            backoff(Duration::from_secs(120), 1),
+            Duration::from_secs(120)
+        );
+    }
+
+    /// Answers one HTTP request with `status` and `body`, returning the request
+    /// body.
+    async fn respond(listener: &TcpListener, status: &str, body: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, sent)) = text.split_once("\r\n\r\n") {
+                let length = head
+                    .lines()
+                    .find_map(|line| {
+                        line.to_ascii_lowercase()
+                            .strip_prefix("content-length: ")
+                            .map(str::to_owned)
+                    })
+                    .and_then(|length| length.parse::<usize>().ok())
+                    .unwrap_or(0);
+                if sent.len() >= length {
+                    let sent = sent.to_owned();
+                    let response = format!(
+                        "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                        status,
+                        body.len(),
+                        body
+                    );
+                    stream.write_all(response.as_bytes()).await.unwrap();
+                    return sent;
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_feed_reports_health_and_recovers() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (feed, health) = FeeHistoryFeed::new(url, Duration::from_millis(5), 1, vec![])
+            .with_limiter(Arc::new(RateLimiter::new(1_000, 10)))
+            .spawn(gas_tx);
+
+        for _ in 0..UNHEALTHY_AFTER {
+            let request = respond(&listener, "503 Service Unavailable", "").await;
+            assert!(request.contains(r#""method":"eth_feeHistory""#));
+            assert!(request.contains(r#""params":["0x1","latest",[]]"#));
+        }
+        // the feed only asks again once it has counted the last failure
+        let (stream, _) = listener.accept().await.unwrap();
+        assert!(!health.is_healthy());
+        assert_eq!(
+            health.last_error().as_deref(),
+            Some("HTTP 503 Service Unavailable")
+        );
+        drop(stream);
+
+        respond(&listener, "200 OK", HOSTED).await;
+        let event = gas_rx.recv().await.unwrap();
+        assert_eq!(event.block_number(), Some(20_000_000));
+        assert!(health.is_healthy());
+        drop(listener);
+        drop(gas_rx);
+        feed.await.unwrap();
+    }
+}
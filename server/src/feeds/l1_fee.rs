@@ -0,0 +1,266 @@
+use super::fee_history::backoff;
+use super::{FeedHealth, UNHEALTHY_AFTER};
+use crate::events::GasEvent;
+use crate::l2::{GAS_PRICE_ORACLE, L1FeeError, L1FeeOracle, L1FeeParams};
+use alloy_primitives::{U256, hex, keccak256};
+use async_trait::async_trait;
+use serde_json::{Value, json};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Reads an OP-stack chain's `GasPriceOracle` predeploy with `eth_call` on one
+/// of its nodes.
+#[derive(Clone)]
+pub struct GasPriceOracleClient {
+    url: String,
+    client: reqwest::Client,
+}
+
+impl GasPriceOracleClient {
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// What the oracle's view `function`, which takes no arguments, returns.
+    async fn call(&self, function: &str) -> Result<U256, L1FeeError> {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "eth_call",
+            "params": [{
+                "to": hex::encode_prefixed(GAS_PRICE_ORACLE),
+                "data": hex::encode_prefixed(&keccak256(function)[..4]),
+            }, "latest"],
+        });
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(REQUEST_TIMEOUT)
+            .json(&request)
+            .send()
+            .await
+            .map_err(|e| L1FeeError::Unavailable(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(L1FeeError::Unavailable(format!("HTTP {}", status)));
+        }
+        let malformed =
+            |message: String| L1FeeError::Malformed(format!("{}: {}", function, message));
+        let reply: Value = response
+            .json()
+            .await
+            .map_err(|e| malformed(e.to_string()))?;
+        if let Some(error) = reply.get("error") {
+            return Err(malformed(
+                error["message"]
+                    .as_str()
+                    .map_or_else(|| error.to_string(), str::to_string),
+            ));
+        }
+        let result = reply["result"]
+            .as_str()
+            .ok_or_else(|| malformed(format!("no result in {}", reply)))?;
+        let word = hex::decode(result).map_err(|e| malformed(e.to_string()))?;
+        if word.len() != 32 {
+            // an empty result is what a chain without the predeploy returns
+            return Err(malformed(format!("{} bytes, not one word", word.len())));
+        }
+        Ok(U256::from_be_slice(&word))
+    }
+
+    async fn call_u64(&self, function: &str) -> Result<u64, L1FeeError> {
+        let value = self.call(function).await?;
+        u64::try_from(value)
+            .map_err(|_| L1FeeError::Malformed(format!("{}: {} is out of range", function, value)))
+    }
+}
+
+#[async_trait]
+impl L1FeeOracle for GasPriceOracleClient {
+    async fn fee_params(&self) -> Result<L1FeeParams, L1FeeError> {
+        let decimals = self.call_u64("decimals()").await?;
+        Ok(L1FeeParams {
+            overhead: self.call_u64("overhead()").await?,
+            scalar: self.call_u64("scalar()").await?,
+            decimals: u32::try_from(decimals)
+                .map_err(|_| L1FeeError::Malformed(format!("decimals(): {}", decimals)))?,
+        })
+    }
+
+    async fn l1_base_fee(&self) -> Result<u64, L1FeeError> {
+        self.call_u64("l1BaseFee()").await
+    }
+}
+
+/// Polls an OP-stack chain's view of the L1 base fee and sends a
+/// `GasEvent::L1BaseFeeUpdate` whenever it changes.
+pub struct L1BaseFeeFeed<O> {
+    oracle: O,
+    interval: Duration,
+}
+
+impl<O: L1FeeOracle + 'static> L1BaseFeeFeed<O> {
+    /// Polls `oracle` every 12 seconds, an L1 block.
+    pub fn new(oracle: O) -> Self {
+        Self {
+            oracle,
+            interval: Duration::from_secs(12),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Polls until `gas_tx`'s receiver is dropped. A failed poll is retried after
+    /// twice the wait of the last one, up to a minute, and counts against the
+    /// returned health.
+    pub fn spawn(self, gas_tx: mpsc::Sender<GasEvent>) -> (JoinHandle<()>, FeedHealth) {
+        let health = FeedHealth::default();
+        (tokio::spawn(self.run(gas_tx, health.clone())), health)
+    }
+
+    async fn run(self, gas_tx: mpsc::Sender<GasEvent>, health: FeedHealth) {
+        let mut last = None;
+        loop {
+            let delay = match self.oracle.l1_base_fee().await {
+                Ok(base_fee) => {
+                    health.succeeded();
+                    if last != Some(base_fee) {
+                        last = Some(base_fee);
+                        if gas_tx
+                            .send(GasEvent::L1BaseFeeUpdate { base_fee })
+                            .await
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                    self.interval
+                }
+                Err(e) => {
+                    let failures = health.failed(e.to_string());
+                    let delay = backoff(self.interval, failures);
+                    if failures == UNHEALTHY_AFTER {
+                        warn!("L1 base fee feed is unhealthy after {} failures", failures);
+                    }
+                    warn!("l1BaseFee() failed: {}; retrying in {:?}", e, delay);
+                    delay
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = gas_tx.closed() => return,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::l2::L1DataFeeEstimator;
+    use std::collections::VecDeque;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    fn word(value: u64) -> String {
+        format!("0x{:064x}", value)
+    }
+
+    /// An OP-stack node whose oracle reports OP mainnet's Bedrock parameters and
+    /// the L1 base fees in `l1_base_fees`, one per call, then errors.
+    async fn node(l1_base_fees: Vec<Value>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let mut l1_base_fees = VecDeque::from(l1_base_fees);
+        let selector = |function: &str| hex::encode_prefixed(&keccak256(function)[..4]);
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                let call = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((_, body)) = text.split_once("\r\n\r\n")
+                        && let Ok(call) = serde_json::from_str::<Value>(body)
+                    {
+                        break call;
+                    }
+                };
+                assert_eq!(
+                    call["params"][0]["to"],
+                    hex::encode_prefixed(GAS_PRICE_ORACLE)
+                );
+                let data = call["params"][0]["data"].as_str().unwrap();
+                let result = if data == selector("overhead()") {
+                    json!(word(188))
+                } else if data == selector("scalar()") {
+                    json!(word(684_000))
+                } else if data == selector("decimals()") {
+                    json!(word(6))
+                } else if data == selector("l1BaseFee()") {
+                    l1_base_fees.pop_front().unwrap_or(json!("0x"))
+                } else {
+                    panic!("unexpected call {}", call)
+                };
+                let body =
+                    json!({"jsonrpc": "2.0", "id": call["id"], "result": result}).to_string();
+                let response = format!(
+                    "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_reads_the_predeploy() {
+        let url = node(vec![
+            json!(word(7_000_000_000)),
+            json!("0x"),
+            json!(word(7_000_000_000)),
+            json!(word(8_000_000_000)),
+        ])
+        .await;
+        let oracle = GasPriceOracleClient::new(url);
+        let estimator = L1DataFeeEstimator::fetch(&oracle).await.unwrap();
+        assert_eq!(estimator.params(), L1FeeParams::default());
+
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (feed, health) = L1BaseFeeFeed::new(oracle)
+            .with_interval(Duration::from_millis(5))
+            .spawn(gas_tx);
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::L1BaseFeeUpdate {
+                base_fee: 7_000_000_000
+            })
+        );
+        // an empty answer is skipped, and an unchanged fee not sent again
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::L1BaseFeeUpdate {
+                base_fee: 8_000_000_000
+            })
+        );
+        assert_eq!(health.consecutive_failures(), 0);
+        assert!(health.last_error().unwrap().contains("0 bytes"));
+
+        drop(gas_rx);
+        feed.await.unwrap();
+    }
+}
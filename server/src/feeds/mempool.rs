@@ -0,0 +1,645 @@
+use super::FeedError;
+use super::ws::{Ended, connect, hang_up, reconnecting};
+use crate::events::GasEvent;
+use crate::limiter::RateLimiter;
+use alloy_primitives::{B256, U64};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, warn};
+
+const SUBSCRIBE_ID: u64 = 1;
+const SUBSCRIBE: &str =
+    r#"{"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newPendingTransactions"]}"#;
+/// How long a fetch may go unanswered before its slot is given to another.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+/// Span the hash rate is measured over.
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+/// What becomes of pending txs that price gas the pre-London way: legacy and
+/// EIP-2930 txs, which have a `gasPrice` and no `maxFeePerGas`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LegacyTxs {
+    /// Left out, as they don't say what tip their senders chose.
+    #[default]
+    Skip,
+    /// Reported with `gasPrice` as both `max_fee` and `max_priority_fee`, which
+    /// is how EIP-1559 itself prices them: all of it above the base fee is tip.
+    AsMaxFee,
+}
+
+/// Subscribes to `newPendingTransactions` and fetches each announced tx with
+/// `eth_getTransactionByHash` over the same connection, for the fees senders
+/// are offering.
+///
+/// Fetches are shed rather than queued when the firehose is more than the node
+/// or the scheduler can take: once hashes arrive faster than
+/// `max_hashes_per_sec`, only every Nth is fetched; a hash is skipped while
+/// `max_in_flight` fetches await replies or the limiter has no token; and an
+/// event is dropped if the scheduler's channel is full.
+pub struct MempoolFeed {
+    ws_url: String,
+    max_in_flight: usize,
+    max_hashes_per_sec: u64,
+    legacy: LegacyTxs,
+    limiter: Arc<RateLimiter>,
+}
+
+impl MempoolFeed {
+    /// Up to 32 fetches in flight, sampling above 100 hashes a second, and at
+    /// most 100 fetches a second unless given another limiter.
+    pub fn new(ws_url: impl Into<String>) -> Self {
+        Self {
+            ws_url: ws_url.into(),
+            max_in_flight: 32,
+            max_hashes_per_sec: 100,
+            legacy: LegacyTxs::default(),
+            limiter: Arc::new(RateLimiter::new(100, 100)),
+        }
+    }
+
+    pub fn with_max_in_flight(mut self, max: usize) -> Self {
+        self.max_in_flight = max;
+        self
+    }
+
+    /// Hash rate above which the feed samples; at least 1.
+    pub fn with_max_hashes_per_sec(mut self, max: u64) -> Self {
+        self.max_hashes_per_sec = max.max(1);
+        self
+    }
+
+    pub fn with_legacy(mut self, legacy: LegacyTxs) -> Self {
+        self.legacy = legacy;
+        self
+    }
+
+    /// Fetches only with a token from `limiter`, e.g. one shared with other
+    /// calls to the same provider.
+    pub fn with_limiter(mut self, limiter: Arc<RateLimiter>) -> Self {
+        self.limiter = limiter;
+        self
+    }
+
+    /// Runs until `gas_tx`'s receiver is dropped, reconnecting like
+    /// `ws::spawn_newheads_feed`. The task only fails if the url is unusable or
+    /// the node refuses the subscription.
+    pub fn spawn(
+        self,
+        gas_tx: mpsc::Sender<GasEvent>,
+    ) -> (JoinHandle<Result<(), FeedError>>, MempoolStats) {
+        let stats = MempoolStats::default();
+        let feed = Arc::new(self);
+        let task = tokio::spawn({
+            let stats = stats.clone();
+            async move {
+                reconnecting("newPendingTransactions", &gas_tx, || {
+                    let (feed, gas_tx, stats) = (feed.clone(), gas_tx.clone(), stats.clone());
+                    async move { feed.session(&gas_tx, &stats).await }
+                })
+                .await
+            }
+        });
+        (task, stats)
+    }
+
+    /// One connection: subscribe, then fetch announced txs until it drops.
+    async fn session(
+        &self,
+        gas_tx: &mpsc::Sender<GasEvent>,
+        stats: &MempoolStats,
+    ) -> Result<Ended, FeedError> {
+        let disconnected =
+            |subscribed, reason: String| Ok(Ended::Disconnected { subscribed, reason });
+        let mut ws = match connect(&self.ws_url).await? {
+            Ok(ws) => ws,
+            Err(reason) => return disconnected(false, reason),
+        };
+        if let Err(e) = ws.send(Message::text(SUBSCRIBE)).await {
+            return disconnected(false, e.to_string());
+        }
+
+        let mut fetches = Fetches::new(self.max_in_flight);
+        let mut sampler = Sampler::new(self.max_hashes_per_sec);
+        let mut subscribed = false;
+        loop {
+            let message = tokio::select! {
+                message = ws.next() => message,
+                _ = gas_tx.closed() => return hang_up(&mut ws).await,
+            };
+            let text = match message {
+                Some(Ok(Message::Text(text))) => text,
+                Some(Ok(Message::Close(_))) | None => {
+                    return disconnected(subscribed, "closed by the node".into());
+                }
+                Some(Ok(_)) => continue,
+                Some(Err(e)) => return disconnected(subscribed, e.to_string()),
+            };
+            let tx = match parse_message(&text) {
+                Ok(Incoming::Subscribed(id)) => {
+                    info!(
+                        "Subscribed to newPendingTransactions on {} as {}",
+                        self.ws_url, id
+                    );
+                    subscribed = true;
+                    continue;
+                }
+                Ok(Incoming::Refused(error)) => return Err(FeedError::Rejected(error)),
+                Ok(Incoming::Pending(hash)) => {
+                    let now = Instant::now();
+                    stats.add(&stats.inner.announced);
+                    if !sampler.admit(now) {
+                        stats.add(&stats.inner.sampled_out);
+                    } else if !fetches.has_room(now) || !self.limiter.check_and_consume() {
+                        stats.add(&stats.inner.shed);
+                    } else {
+                        let request = json!({
+                            "jsonrpc": "2.0",
+                            "id": fetches.start(now),
+                            "method": "eth_getTransactionByHash",
+                            "params": [B256::from(hash)],
+                        });
+                        if let Err(e) = ws.send(Message::text(request.to_string())).await {
+                            return disconnected(subscribed, e.to_string());
+                        }
+                    }
+                    continue;
+                }
+                Ok(Incoming::Fetched { id, tx }) if fetches.finish(id) => tx,
+                Ok(Incoming::FetchFailed { id, error }) if fetches.finish(id) => {
+                    debug!("eth_getTransactionByHash failed: {}", error);
+                    None
+                }
+                // replies to fetches given up on
+                Ok(Incoming::Fetched { .. } | Incoming::FetchFailed { .. }) => continue,
+                Ok(Incoming::Other) => continue,
+                Err(e) => {
+                    warn!("skipping malformed newPendingTransactions message: {}", e);
+                    continue;
+                }
+            };
+
+            // mined or dropped since it was announced
+            let Some(tx) = tx else {
+                stats.add(&stats.inner.missing);
+                continue;
+            };
+            let event = match parse_tx(&tx, self.legacy) {
+                Ok(Some(event)) => event,
+                Ok(None) => {
+                    stats.add(&stats.inner.skipped);
+                    continue;
+                }
+                Err(e) => {
+                    debug!("skipping pending tx that doesn't parse: {}", e);
+                    stats.add(&stats.inner.skipped);
+                    continue;
+                }
+            };
+            match gas_tx.try_send(event) {
+                Ok(()) => stats.add(&stats.inner.emitted),
+                Err(TrySendError::Full(_)) => stats.add(&stats.inner.shed),
+                Err(TrySendError::Closed(_)) => return hang_up(&mut ws).await,
+            }
+        }
+    }
+}
+
+/// What a mempool feed made of the hashes it was told of, shared with whoever
+/// reports on it. Clones see the same counts.
+#[derive(Debug, Clone, Default)]
+pub struct MempoolStats {
+    inner: Arc<MempoolCounters>,
+}
+
+#[derive(Debug, Default)]
+struct MempoolCounters {
+    announced: AtomicU64,
+    sampled_out: AtomicU64,
+    shed: AtomicU64,
+    missing: AtomicU64,
+    skipped: AtomicU64,
+    emitted: AtomicU64,
+}
+
+impl MempoolStats {
+    /// Hashes the node announced.
+    pub fn announced(&self) -> u64 {
+        self.inner.announced.load(Ordering::Relaxed)
+    }
+
+    /// Hashes passed over while sampling.
+    pub fn sampled_out(&self) -> u64 {
+        self.inner.sampled_out.load(Ordering::Relaxed)
+    }
+
+    /// Hashes not fetched for want of a slot or a limiter token, and events the
+    /// scheduler's channel had no room for.
+    pub fn shed(&self) -> u64 {
+        self.inner.shed.load(Ordering::Relaxed)
+    }
+
+    /// Fetches answered with `null` or an error, mostly txs already mined or
+    /// dropped.
+    pub fn missing(&self) -> u64 {
+        self.inner.missing.load(Ordering::Relaxed)
+    }
+
+    /// Fetched txs left out: legacy ones under `LegacyTxs::Skip`, and ones that
+    /// don't parse.
+    pub fn skipped(&self) -> u64 {
+        self.inner.skipped.load(Ordering::Relaxed)
+    }
+
+    /// `GasEvent::MempoolTx`s sent.
+    pub fn emitted(&self) -> u64 {
+        self.inner.emitted.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Lets every hash through while they come at most `max_per_sec`, and every Nth
+/// once they come faster, N being how many times over the rate the last window
+/// was.
+struct Sampler {
+    max_per_sec: u64,
+    window_start: Option<Instant>,
+    seen_in_window: u64,
+    stride: u64,
+    seen: u64,
+}
+
+impl Sampler {
+    fn new(max_per_sec: u64) -> Self {
+        Self {
+            max_per_sec,
+            window_start: None,
+            seen_in_window: 0,
+            stride: 1,
+            seen: 0,
+        }
+    }
+
+    fn admit(&mut self, now: Instant) -> bool {
+        let window_start = *self.window_start.get_or_insert(now);
+        if now.duration_since(window_start) >= SAMPLE_WINDOW {
+            let stride = self.seen_in_window.div_ceil(self.max_per_sec).max(1);
+            if stride != self.stride {
+                info!(
+                    "{} pending txs in the last {:?}; fetching 1 in {}",
+                    self.seen_in_window, SAMPLE_WINDOW, stride
+                );
+            }
+            self.stride = stride;
+            self.window_start = Some(now);
+            self.seen_in_window = 0;
+        }
+        self.seen_in_window += 1;
+        self.seen += 1;
+        self.seen.is_multiple_of(self.stride)
+    }
+}
+
+/// `eth_getTransactionByHash` calls awaiting a reply, by request id.
+struct Fetches {
+    sent: HashMap<u64, Instant>,
+    max: usize,
+    next_id: u64,
+}
+
+impl Fetches {
+    fn new(max: usize) -> Self {
+        Self {
+            sent: HashMap::new(),
+            max,
+            next_id: SUBSCRIBE_ID + 1,
+        }
+    }
+
+    /// Whether another fetch may start, once those unanswered for
+    /// `FETCH_TIMEOUT` are given up on.
+    fn has_room(&mut self, now: Instant) -> bool {
+        if self.sent.len() >= self.max {
+            self.sent
+                .retain(|_, sent_at| now.duration_since(*sent_at) < FETCH_TIMEOUT);
+        }
+        self.sent.len() < self.max
+    }
+
+    /// The id to send the next fetch under.
+    fn start(&mut self, now: Instant) -> u64 {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.sent.insert(id, now);
+        id
+    }
+
+    /// False for replies to fetches given up on, or never sent.
+    fn finish(&mut self, id: u64) -> bool {
+        self.sent.remove(&id).is_some()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    /// The reply to `eth_subscribe`, with the subscription id.
+    Subscribed(String),
+    /// An error reply to `eth_subscribe`.
+    Refused(String),
+    Pending([u8; 32]),
+    /// `None` if the node no longer knows the tx.
+    Fetched {
+        id: u64,
+        tx: Option<Value>,
+    },
+    FetchFailed {
+        id: u64,
+        error: String,
+    },
+    Other,
+}
+
+fn parse_message(text: &str) -> Result<Incoming, String> {
+    let message: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if message.get("method").and_then(Value::as_str) == Some("eth_subscription") {
+        let hash = message
+            .pointer("/params/result")
+            .ok_or("notification without a result")?;
+        let hash = B256::deserialize(hash).map_err(|e| e.to_string())?;
+        return Ok(Incoming::Pending(hash.0));
+    }
+    let error = message.get("error").map(Value::to_string);
+    Ok(match (message.get("id").and_then(Value::as_u64), error) {
+        (Some(SUBSCRIBE_ID), Some(error)) => Incoming::Refused(error),
+        (Some(SUBSCRIBE_ID), None) => match message.get("result").and_then(Value::as_str) {
+            Some(id) => Incoming::Subscribed(id.to_owned()),
+            None => Incoming::Other,
+        },
+        (Some(id), Some(error)) => Incoming::FetchFailed { id, error },
+        (Some(id), None) => Incoming::Fetched {
+            id,
+            tx: message.get("result").filter(|tx| !tx.is_null()).cloned(),
+        },
+        (None, _) => Incoming::Other,
+    })
+}
+
+/// The fields of a pending tx a `GasEvent::MempoolTx` needs.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PendingTx {
+    hash: B256,
+    gas: U64,
+    gas_price: Option<U64>,
+    max_fee_per_gas: Option<U64>,
+    max_priority_fee_per_gas: Option<U64>,
+}
+
+/// `None` for a legacy tx under `LegacyTxs::Skip`.
+fn parse_tx(tx: &Value, legacy: LegacyTxs) -> Result<Option<GasEvent>, String> {
+    let tx = PendingTx::deserialize(tx).map_err(|e| e.to_string())?;
+    // nodes fill in gasPrice for EIP-1559 txs too, so the caps decide
+    let (max_fee, max_priority_fee) = match (tx.max_fee_per_gas, tx.max_priority_fee_per_gas) {
+        (Some(max_fee), Some(max_priority_fee)) => (max_fee, max_priority_fee),
+        (None, None) => match (legacy, tx.gas_price) {
+            (LegacyTxs::Skip, _) => return Ok(None),
+            (LegacyTxs::AsMaxFee, Some(gas_price)) => (gas_price, gas_price),
+            (LegacyTxs::AsMaxFee, None) => return Err("tx has no gasPrice".into()),
+        },
+        _ => return Err("tx has only one of maxFeePerGas and maxPriorityFeePerGas".into()),
+    };
+    Ok(Some(GasEvent::MempoolTx {
+        tx_hash: tx.hash.0,
+        max_fee: max_fee.to(),
+        max_priority_fee: max_priority_fee.to(),
+        gas_limit: tx.gas.to(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    /// An EIP-1559 tx as geth returns it while pending.
+    const DYNAMIC_FEE_TX: &str = r#"{
+        "blockHash": null,
+        "blockNumber": null,
+        "from": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+        "gas": "0x5208",
+        "gasPrice": "0x6fc23ac00",
+        "maxFeePerGas": "0x6fc23ac00",
+        "maxPriorityFeePerGas": "0x3b9aca00",
+        "hash": "0x3333333333333333333333333333333333333333333333333333333333333333",
+        "input": "0x",
+        "nonce": "0x2a",
+        "to": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+        "transactionIndex": null,
+        "value": "0x0",
+        "type": "0x2",
+        "accessList": [],
+        "chainId": "0x1",
+        "v": "0x1",
+        "r": "0x6f1c0b7d1b3f8d1fb1a3f1a2e5b1c0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3",
+        "s": "0x1f2e3d4c5b6a79887766554433221100ffeeddccbbaa99887766554433221100",
+        "yParity": "0x1"
+    }"#;
+
+    /// A legacy tx, also as geth returns it.
+    const LEGACY_TX: &str = r#"{
+        "blockHash": null,
+        "blockNumber": null,
+        "from": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+        "gas": "0x186a0",
+        "gasPrice": "0x4a817c800",
+        "hash": "0x4444444444444444444444444444444444444444444444444444444444444444",
+        "input": "0xa9059cbb",
+        "nonce": "0x7",
+        "to": "0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+        "transactionIndex": null,
+        "value": "0x0",
+        "type": "0x0",
+        "chainId": "0x1",
+        "v": "0x25",
+        "r": "0x6f1c0b7d1b3f8d1fb1a3f1a2e5b1c0d9e8f7a6b5c4d3e2f1a0b9c8d7e6f5a4b3",
+        "s": "0x1f2e3d4c5b6a79887766554433221100ffeeddccbbaa99887766554433221100"
+    }"#;
+
+    fn value(json: &str) -> Value {
+        serde_json::from_str(json).unwrap()
+    }
+
+    fn notification(hash_byte: u8) -> String {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {"subscription": "0x1", "result": B256::repeat_byte(hash_byte)},
+        })
+        .to_string()
+    }
+
+    fn reply(id: &Value, result: Value) -> Message {
+        Message::text(json!({"jsonrpc": "2.0", "id": id, "result": result}).to_string())
+    }
+
+    #[test]
+    fn test_parse_recorded_txs() {
+        let dynamic_fee = GasEvent::MempoolTx {
+            tx_hash: [0x33; 32],
+            max_fee: 30_000_000_000,
+            max_priority_fee: 1_000_000_000,
+            gas_limit: 21_000,
+        };
+        for legacy in [LegacyTxs::Skip, LegacyTxs::AsMaxFee] {
+            assert_eq!(
+                parse_tx(&value(DYNAMIC_FEE_TX), legacy),
+                Ok(Some(dynamic_fee.clone()))
+            );
+        }
+        assert_eq!(parse_tx(&value(LEGACY_TX), LegacyTxs::Skip), Ok(None));
+        assert_eq!(
+            parse_tx(&value(LEGACY_TX), LegacyTxs::AsMaxFee),
+            Ok(Some(GasEvent::MempoolTx {
+                tx_hash: [0x44; 32],
+                max_fee: 20_000_000_000,
+                max_priority_fee: 20_000_000_000,
+                gas_limit: 100_000,
+            }))
+        );
+        let no_tip = DYNAMIC_FEE_TX.replace(r#""maxPriorityFeePerGas": "0x3b9aca00","#, "");
+        assert!(parse_tx(&value(&no_tip), LegacyTxs::AsMaxFee).is_err());
+        assert!(parse_tx(&value(r#"{"hash": "0x33"}"#), LegacyTxs::Skip).is_err());
+
+        assert_eq!(
+            parse_message(&notification(0x33)),
+            Ok(Incoming::Pending([0x33; 32]))
+        );
+        assert_eq!(
+            parse_message(r#"{"jsonrpc":"2.0","id":1,"result":"0x9ce5"}"#),
+            Ok(Incoming::Subscribed("0x9ce5".into()))
+        );
+        assert_eq!(
+            parse_message(r#"{"jsonrpc":"2.0","id":7,"result":null}"#),
+            Ok(Incoming::Fetched { id: 7, tx: None })
+        );
+        let fetched = format!(r#"{{"jsonrpc":"2.0","id":8,"result":{}}}"#, DYNAMIC_FEE_TX);
+        assert_eq!(
+            parse_message(&fetched),
+            Ok(Incoming::Fetched {
+                id: 8,
+                tx: Some(value(DYNAMIC_FEE_TX))
+            })
+        );
+        let refused = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"no"}}"#;
+        assert!(matches!(parse_message(refused), Ok(Incoming::Refused(_))));
+        let failed = refused.replace(r#""id":1"#, r#""id":9"#);
+        assert!(matches!(
+            parse_message(&failed),
+            Ok(Incoming::FetchFailed { id: 9, .. })
+        ));
+    }
+
+    #[test]
+    fn test_sampler_sheds_a_firehose() {
+        let start = Instant::now();
+        let mut sampler = Sampler::new(10);
+        // the first second is taken whole, at 50 hashes
+        let admitted = (0..50).filter(|_| sampler.admit(start)).count();
+        assert_eq!(admitted, 50);
+
+        // five times over the rate: 1 in 5 from then on
+        let next = start + SAMPLE_WINDOW;
+        let admitted = (0..50).filter(|_| sampler.admit(next)).count();
+        assert_eq!(admitted, 10);
+
+        // back under the rate the window after
+        let quiet = next + SAMPLE_WINDOW;
+        let admitted = (0..5).filter(|_| sampler.admit(quiet)).count();
+        assert_eq!(admitted, 1);
+        let calm = quiet + SAMPLE_WINDOW;
+        assert!((0..5).all(|_| sampler.admit(calm)));
+    }
+
+    #[test]
+    fn test_fetches_time_out() {
+        let start = Instant::now();
+        let mut fetches = Fetches::new(2);
+        let first = fetches.start(start);
+        fetches.start(start);
+        assert!(!fetches.has_room(start + FETCH_TIMEOUT / 2));
+        assert!(fetches.finish(first));
+        assert!(fetches.has_room(start));
+
+        fetches.start(start);
+        assert!(fetches.has_room(start + FETCH_TIMEOUT));
+        assert!(!fetches.finish(first));
+    }
+
+    #[tokio::test]
+    async fn test_feed_caps_fetches_in_flight() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (feed, stats) = MempoolFeed::new(url).with_max_in_flight(3).spawn(gas_tx);
+
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let request = ws.next().await.unwrap().unwrap();
+        assert_eq!(request.to_text().unwrap(), SUBSCRIBE);
+        let subscribed = r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#;
+        ws.send(Message::text(subscribed)).await.unwrap();
+        for hash_byte in 1..=10 {
+            ws.send(Message::text(notification(hash_byte)))
+                .await
+                .unwrap();
+        }
+
+        // only the first three are fetched while none is answered
+        let mut fetches = Vec::new();
+        for hash_byte in 1..=3 {
+            let request = value(ws.next().await.unwrap().unwrap().to_text().unwrap());
+            assert_eq!(request["method"], "eth_getTransactionByHash");
+            assert_eq!(request["params"][0], json!(B256::repeat_byte(hash_byte)));
+            fetches.push(request["id"].clone());
+        }
+        ws.send(reply(&fetches[0], Value::Null)).await.unwrap();
+        ws.send(reply(&fetches[1], value(LEGACY_TX))).await.unwrap();
+        ws.send(reply(&fetches[2], value(DYNAMIC_FEE_TX)))
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            gas_rx.recv().await.unwrap(),
+            GasEvent::MempoolTx {
+                tx_hash: [0x33, ..],
+                ..
+            }
+        ));
+        assert_eq!(stats.announced(), 10);
+        assert_eq!(stats.shed(), 7);
+        assert_eq!(stats.sampled_out(), 0);
+        assert_eq!(
+            (stats.missing(), stats.skipped(), stats.emitted()),
+            (1, 1, 1)
+        );
+
+        // a slot is free again
+        ws.send(Message::text(notification(11))).await.unwrap();
+        let request = value(ws.next().await.unwrap().unwrap().to_text().unwrap());
+        assert_eq!(request["params"][0], json!(B256::repeat_byte(11)));
+
+        drop(gas_rx);
+        assert_eq!(feed.await.unwrap(), Ok(()));
+    }
+}
@@ -0,0 +1,493 @@
+use super::fee_history::backoff;
+use super::{FeedHealth, UNHEALTHY_AFTER};
+use crate::events::GasEvent;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use std::marker::PhantomData;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+const WEI_PER_GWEI: u64 = 1_000_000_000;
+
+/// How a poll's estimate reaches the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Emit {
+    /// A `GasEvent::OracleEstimate`, whose tips the scheduler blends into its
+    /// tip model.
+    #[default]
+    Estimate,
+    /// A `GasEvent::BaseFeeUpdate` with the oracle's base fee, without a
+    /// timestamp.
+    BaseFeeUpdate,
+}
+
+/// What one poll of an oracle says, in wei.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OracleReading {
+    /// The block the estimate is for or was made at, if the oracle says.
+    pub block_number: Option<u64>,
+    pub base_fee: u64,
+    pub priority_fee_standard: u64,
+    pub priority_fee_fast: u64,
+}
+
+/// A gas oracle's response body, as the oracle shapes it.
+pub trait OraclePayload: DeserializeOwned {
+    /// Names the oracle in `GasEvent::OracleEstimate`.
+    const SOURCE: &'static str;
+
+    fn reading(self) -> Result<OracleReading, String>;
+}
+
+/// Reads one response body as `P`.
+pub fn parse<P: OraclePayload>(body: &[u8]) -> Result<OracleReading, String> {
+    serde_json::from_slice::<P>(body)
+        .map_err(|e| e.to_string())?
+        .reading()
+}
+
+/// Etherscan's `module=gastracker&action=gasoracle`. Its prices are whole gas
+/// prices in gwei, so the tips are `ProposeGasPrice` and `FastGasPrice` less
+/// `suggestBaseFee`. The API key goes in the URL's `apikey` parameter.
+#[derive(Debug, Deserialize)]
+pub struct EtherscanGasOracle {
+    status: String,
+    message: String,
+    /// The prices, or a string saying what went wrong.
+    result: Value,
+}
+
+#[derive(Deserialize)]
+struct EtherscanPrices {
+    #[serde(rename = "LastBlock")]
+    last_block: String,
+    #[serde(rename = "ProposeGasPrice")]
+    propose_gas_price: String,
+    #[serde(rename = "FastGasPrice")]
+    fast_gas_price: String,
+    #[serde(rename = "suggestBaseFee")]
+    suggest_base_fee: String,
+}
+
+impl OraclePayload for EtherscanGasOracle {
+    const SOURCE: &'static str = "etherscan";
+
+    fn reading(self) -> Result<OracleReading, String> {
+        if self.status != "1" {
+            let reason = self.result.as_str().unwrap_or_default();
+            return Err(format!("{}: {}", self.message, reason));
+        }
+        let prices = EtherscanPrices::deserialize(self.result).map_err(|e| e.to_string())?;
+        let base_fee = parse_gwei(&prices.suggest_base_fee)?;
+        let block_number = prices
+            .last_block
+            .parse()
+            .map_err(|_| format!("{:?} is not a block number", prices.last_block))?;
+        Ok(OracleReading {
+            block_number: Some(block_number),
+            base_fee,
+            priority_fee_standard: parse_gwei(&prices.propose_gas_price)?.saturating_sub(base_fee),
+            priority_fee_fast: parse_gwei(&prices.fast_gas_price)?.saturating_sub(base_fee),
+        })
+    }
+}
+
+/// Blocknative's `gasprices/blockprices`, for the next block. The fast tip is
+/// the one at the highest confidence, the standard tip the one at the middle
+/// confidence, 90% with the default five. The API key goes in the
+/// `Authorization` header.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlocknativeBlockPrices {
+    #[serde(default = "gwei_unit")]
+    unit: String,
+    block_prices: Vec<BlocknativeBlock>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlocknativeBlock {
+    block_number: u64,
+    base_fee_per_gas: f64,
+    estimated_prices: Vec<BlocknativePrice>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BlocknativePrice {
+    confidence: u32,
+    max_priority_fee_per_gas: f64,
+}
+
+fn gwei_unit() -> String {
+    "gwei".into()
+}
+
+impl OraclePayload for BlocknativeBlockPrices {
+    const SOURCE: &'static str = "blocknative";
+
+    fn reading(self) -> Result<OracleReading, String> {
+        if self.unit != "gwei" {
+            return Err(format!("prices in {}, not gwei", self.unit));
+        }
+        let block = self
+            .block_prices
+            .into_iter()
+            .next()
+            .ok_or("no block prices")?;
+        let mut prices = block.estimated_prices;
+        prices.sort_by_key(|price| price.confidence);
+        let (Some(fast), Some(standard)) = (prices.last(), prices.get(prices.len() / 2)) else {
+            return Err("no estimated prices".into());
+        };
+        Ok(OracleReading {
+            block_number: Some(block.block_number),
+            base_fee: float_gwei(block.base_fee_per_gas)?,
+            priority_fee_standard: float_gwei(standard.max_priority_fee_per_gas)?,
+            priority_fee_fast: float_gwei(fast.max_priority_fee_per_gas)?,
+        })
+    }
+}
+
+/// Wei in a decimal gwei amount such as `"19.230609716"`; digits past the ninth
+/// decimal place are dropped.
+fn parse_gwei(gwei: &str) -> Result<u64, String> {
+    let bad = || format!("{:?} is not an amount of gwei", gwei);
+    let (whole, fraction) = gwei.trim().split_once('.').unwrap_or((gwei.trim(), ""));
+    let digits = |part: &str| part.bytes().all(|b| b.is_ascii_digit());
+    if (whole.is_empty() && fraction.is_empty()) || !digits(whole) || !digits(fraction) {
+        return Err(bad());
+    }
+    let whole: u64 = if whole.is_empty() {
+        0
+    } else {
+        whole.parse().map_err(|_| bad())?
+    };
+    let fraction: u64 = format!("{:0<9}", &fraction[..fraction.len().min(9)])
+        .parse()
+        .map_err(|_| bad())?;
+    whole
+        .checked_mul(WEI_PER_GWEI)
+        .and_then(|wei| wei.checked_add(fraction))
+        .ok_or_else(bad)
+}
+
+fn float_gwei(gwei: f64) -> Result<u64, String> {
+    let wei = (gwei * WEI_PER_GWEI as f64).round();
+    if !(0.0..u64::MAX as f64).contains(&wei) {
+        return Err(format!("{} is not an amount of gwei", gwei));
+    }
+    Ok(wei as u64)
+}
+
+/// Polls a commercial gas oracle over HTTP and reads its responses as `P`, e.g.
+/// `EtherscanGasOracle` or `BlocknativeBlockPrices`.
+pub struct OracleFeed<P> {
+    url: String,
+    headers: Vec<(String, String)>,
+    interval: Duration,
+    timeout: Duration,
+    emit: Emit,
+    payload: PhantomData<fn() -> P>,
+}
+
+impl<P: OraclePayload + 'static> OracleFeed<P> {
+    /// Polls `url` every 15 seconds, giving each request 10.
+    pub fn new(url: impl Into<String>) -> Self {
+        Self {
+            url: url.into(),
+            headers: Vec::new(),
+            interval: Duration::from_secs(15),
+            timeout: Duration::from_secs(10),
+            emit: Emit::default(),
+            payload: PhantomData,
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Sends `name: value` with every poll, e.g. an API key.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_emit(mut self, emit: Emit) -> Self {
+        self.emit = emit;
+        self
+    }
+
+    /// Polls until `gas_tx`'s receiver is dropped. A reading for the same block
+    /// as the last one sent is not sent again. A failed poll or a response that
+    /// doesn't parse is logged and retried after twice the wait of the last one,
+    /// up to a minute, and counts against the returned health.
+    pub fn spawn(self, gas_tx: mpsc::Sender<GasEvent>) -> (JoinHandle<()>, FeedHealth) {
+        let health = FeedHealth::default();
+        (tokio::spawn(self.run(gas_tx, health.clone())), health)
+    }
+
+    async fn run(self, gas_tx: mpsc::Sender<GasEvent>, health: FeedHealth) {
+        let client = match reqwest::Client::builder().timeout(self.timeout).build() {
+            Ok(client) => client,
+            Err(e) => {
+                health.failed(e.to_string());
+                warn!("{} oracle feed can't start: {}", P::SOURCE, e);
+                return;
+            }
+        };
+        let mut last_block = None;
+        loop {
+            let delay = match self.poll(&client).await {
+                Ok(reading) => {
+                    health.succeeded();
+                    if reading.block_number.is_none() || reading.block_number != last_block {
+                        last_block = reading.block_number;
+                        if gas_tx.send(self.event(reading)).await.is_err() {
+                            return;
+                        }
+                    }
+                    self.interval
+                }
+                Err(e) => {
+                    let failures = health.failed(e.clone());
+                    let delay = backoff(self.interval, failures);
+                    if failures == UNHEALTHY_AFTER {
+                        warn!(
+                            "{} oracle feed is unhealthy after {} failures",
+                            P::SOURCE,
+                            failures
+                        );
+                    }
+                    warn!(
+                        "{} oracle failed: {}; retrying in {:?}",
+                        P::SOURCE,
+                        e,
+                        delay
+                    );
+                    delay
+                }
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(delay) => {}
+                _ = gas_tx.closed() => return,
+            }
+        }
+    }
+
+    async fn poll(&self, client: &reqwest::Client) -> Result<OracleReading, String> {
+        let mut request = client.get(&self.url);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(|e| e.to_string())?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(format!("HTTP {}", status));
+        }
+        parse::<P>(&response.bytes().await.map_err(|e| e.to_string())?)
+    }
+
+    fn event(&self, reading: OracleReading) -> GasEvent {
+        match self.emit {
+            Emit::Estimate => GasEvent::OracleEstimate {
+                source: P::SOURCE.to_string(),
+                base_fee: reading.base_fee,
+                priority_fee_fast: reading.priority_fee_fast,
+                priority_fee_standard: reading.priority_fee_standard,
+            },
+            Emit::BaseFeeUpdate => GasEvent::BaseFeeUpdate {
+                base_fee: reading.base_fee,
+                timestamp: 0,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// `api.etherscan.io/v2/api?chainid=1&module=gastracker&action=gasoracle`.
+    const ETHERSCAN: &str = r#"{
+        "status": "1",
+        "message": "OK",
+        "result": {
+            "LastBlock": "21000000",
+            "SafeGasPrice": "8.5",
+            "ProposeGasPrice": "9",
+            "FastGasPrice": "10.25",
+            "suggestBaseFee": "8.412345678",
+            "gasUsedRatio": "0.45,0.62,0.38,0.51,0.49"
+        }
+    }"#;
+
+    /// `api.blocknative.com/gasprices/blockprices` with the default confidences.
+    const BLOCKNATIVE: &str = r#"{
+        "system": "ethereum",
+        "network": "main",
+        "unit": "gwei",
+        "maxPrice": 28,
+        "currentBlockNumber": 21000000,
+        "msSinceLastBlock": 4123,
+        "blockPrices": [{
+            "blockNumber": 21000001,
+            "estimatedTransactionCount": 180,
+            "baseFeePerGas": 12.483912345,
+            "estimatedPrices": [
+                {"confidence": 99, "price": 14, "maxPriorityFeePerGas": 1.5, "maxFeePerGas": 26.47},
+                {"confidence": 95, "price": 13, "maxPriorityFeePerGas": 0.62, "maxFeePerGas": 25.59},
+                {"confidence": 90, "price": 13, "maxPriorityFeePerGas": 0.21, "maxFeePerGas": 25.18},
+                {"confidence": 80, "price": 13, "maxPriorityFeePerGas": 0.1, "maxFeePerGas": 25.07},
+                {"confidence": 70, "price": 13, "maxPriorityFeePerGas": 0.05, "maxFeePerGas": 25.02}
+            ]
+        }],
+        "estimatedBaseFees": [{"pending+1": [{"confidence": 99, "baseFee": 13.9}]}]
+    }"#;
+
+    #[test]
+    fn test_parse_etherscan_fixture() {
+        assert_eq!(
+            parse::<EtherscanGasOracle>(ETHERSCAN.as_bytes()),
+            Ok(OracleReading {
+                block_number: Some(21_000_000),
+                base_fee: 8_412_345_678,
+                priority_fee_standard: 587_654_322,
+                priority_fee_fast: 1_837_654_322,
+            })
+        );
+
+        let refused = r#"{"status":"0","message":"NOTOK","result":"Invalid API Key"}"#;
+        assert_eq!(
+            parse::<EtherscanGasOracle>(refused.as_bytes()),
+            Err("NOTOK: Invalid API Key".into())
+        );
+        let garbled = ETHERSCAN.replace(r#""9""#, r#""nine""#);
+        assert!(parse::<EtherscanGasOracle>(garbled.as_bytes()).is_err());
+        // a price under the base fee doesn't make a negative tip
+        let cheap = ETHERSCAN.replace(r#""10.25""#, r#""8""#);
+        let reading = parse::<EtherscanGasOracle>(cheap.as_bytes()).unwrap();
+        assert_eq!(reading.priority_fee_fast, 0);
+    }
+
+    #[test]
+    fn test_parse_blocknative_fixture() {
+        assert_eq!(
+            parse::<BlocknativeBlockPrices>(BLOCKNATIVE.as_bytes()),
+            Ok(OracleReading {
+                block_number: Some(21_000_001),
+                base_fee: 12_483_912_345,
+                priority_fee_standard: 210_000_000,
+                priority_fee_fast: 1_500_000_000,
+            })
+        );
+
+        let no_prices = r#"{"unit":"gwei","blockPrices":[]}"#;
+        assert!(parse::<BlocknativeBlockPrices>(no_prices.as_bytes()).is_err());
+        let in_wei = BLOCKNATIVE.replace(r#""unit": "gwei""#, r#""unit": "wei""#);
+        assert!(parse::<BlocknativeBlockPrices>(in_wei.as_bytes()).is_err());
+        assert!(parse::<BlocknativeBlockPrices>(b"<html>").is_err());
+    }
+
+    #[test]
+    fn test_parse_gwei() {
+        assert_eq!(parse_gwei("19"), Ok(19_000_000_000));
+        assert_eq!(parse_gwei("0.000000001"), Ok(1));
+        assert_eq!(parse_gwei(".5"), Ok(500_000_000));
+        assert_eq!(parse_gwei("1.0000000019"), Ok(1_000_000_001));
+        for bad in ["", ".", "-1", "1e9", "1.2.3", "99999999999"] {
+            assert!(parse_gwei(bad).is_err(), "{}", bad);
+        }
+    }
+
+    /// Answers one HTTP request with `body`, returning the request's headers.
+    async fn respond(listener: &TcpListener, body: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        let head = loop {
+            let n = stream.read(&mut buf).await.unwrap();
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            if let Some((head, _)) = text.split_once("\r\n\r\n") {
+                break head.to_ascii_lowercase();
+            }
+        };
+        let response = format!(
+            "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        );
+        stream.write_all(response.as_bytes()).await.unwrap();
+        head
+    }
+
+    #[tokio::test]
+    async fn test_feed_survives_bad_responses() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let (feed, health) = OracleFeed::<BlocknativeBlockPrices>::new(url)
+            .with_interval(Duration::from_millis(5))
+            .with_header("Authorization", "secret-key")
+            .spawn(gas_tx);
+
+        let head = respond(&listener, BLOCKNATIVE).await;
+        assert!(head.contains("authorization: secret-key"));
+        assert_eq!(
+            gas_rx.recv().await,
+            Some(GasEvent::OracleEstimate {
+                source: "blocknative".into(),
+                base_fee: 12_483_912_345,
+                priority_fee_fast: 1_500_000_000,
+                priority_fee_standard: 210_000_000,
+            })
+        );
+
+        respond(&listener, "not json").await;
+        // the same block isn't reported twice
+        respond(&listener, BLOCKNATIVE).await;
+        let next = BLOCKNATIVE
+            .replace("21000001", "21000002")
+            .replace("12.483912345", "13");
+        respond(&listener, &next).await;
+        assert!(matches!(
+            gas_rx.recv().await,
+            Some(GasEvent::OracleEstimate {
+                base_fee: 13_000_000_000,
+                ..
+            })
+        ));
+        assert_eq!(health.consecutive_failures(), 0);
+        assert!(health.last_error().unwrap().contains("expected"));
+
+        drop(gas_rx);
+        feed.await.unwrap();
+    }
+
+    #[test]
+    fn test_emit_base_fee_updates() {
+        let feed =
+            OracleFeed::<EtherscanGasOracle>::new("http://unused").with_emit(Emit::BaseFeeUpdate);
+        let reading = parse::<EtherscanGasOracle>(ETHERSCAN.as_bytes()).unwrap();
+        assert_eq!(
+            feed.event(reading),
+            GasEvent::BaseFeeUpdate {
+                base_fee: 8_412_345_678,
+                timestamp: 0,
+            }
+        );
+    }
+}
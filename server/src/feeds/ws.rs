@@ -0,0 +1,323 @@
+use super::FeedError;
+use crate::events::GasEvent;
+use alloy_primitives::{B256, U64};
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use serde_json::Value;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::{self, Message};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tracing::{info, warn};
+
+const SUBSCRIBE: &str =
+    r#"{"jsonrpc":"2.0","id":1,"method":"eth_subscribe","params":["newHeads"]}"#;
+/// Wait before the second reconnect attempt in a row; the first is immediate.
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+/// Subscribes to `newHeads` on the node at `ws_url` and sends a
+/// `GasEvent::NewBlock` for every header, until `gas_tx`'s receiver is dropped.
+///
+/// Dropped connections are retried forever, immediately at first and then with
+/// exponential backoff up to 30s, and the subscription is renewed on every new
+/// connection. Headers that don't parse, e.g. pre-London ones without a base fee,
+/// are logged and skipped. The task only fails if the url is unusable or the node
+/// refuses the subscription.
+pub fn spawn_newheads_feed(
+    ws_url: impl Into<String>,
+    gas_tx: mpsc::Sender<GasEvent>,
+) -> JoinHandle<Result<(), FeedError>> {
+    tokio::spawn(run(ws_url.into(), gas_tx))
+}
+
+async fn run(ws_url: String, gas_tx: mpsc::Sender<GasEvent>) -> Result<(), FeedError> {
+    reconnecting("newHeads", &gas_tx, async || {
+        session(&ws_url, &gas_tx).await
+    })
+    .await
+}
+
+/// Runs `session` until it reports the receiver gone or fails for good, waiting
+/// out `backoff` between connections.
+pub(super) async fn reconnecting<F>(
+    subscription: &str,
+    gas_tx: &mpsc::Sender<GasEvent>,
+    mut session: impl FnMut() -> F,
+) -> Result<(), FeedError>
+where
+    F: Future<Output = Result<Ended, FeedError>>,
+{
+    // connection attempts since the last one that got subscribed
+    let mut failures = 0u32;
+    loop {
+        let (subscribed, reason) = match session().await? {
+            Ended::ReceiverGone => return Ok(()),
+            Ended::Disconnected { subscribed, reason } => (subscribed, reason),
+        };
+        if subscribed {
+            failures = 0;
+        }
+        let delay = backoff(failures);
+        failures = failures.saturating_add(1);
+        warn!(
+            "{} feed disconnected: {}; reconnecting in {:?}",
+            subscription, reason, delay
+        );
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = gas_tx.closed() => return Ok(()),
+        }
+    }
+}
+
+pub(super) type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+fn backoff(failures: u32) -> Duration {
+    match failures {
+        0 => Duration::ZERO,
+        n => INITIAL_BACKOFF
+            .saturating_mul(1 << (n - 1).min(16))
+            .min(MAX_BACKOFF),
+    }
+}
+
+/// How a connection ended, short of a `FeedError`.
+pub(super) enum Ended {
+    ReceiverGone,
+    Disconnected { subscribed: bool, reason: String },
+}
+
+/// Closes `ws` cleanly once nothing takes its events any more.
+pub(super) async fn hang_up(ws: &mut WsStream) -> Result<Ended, FeedError> {
+    let _ = ws.close(None).await;
+    Ok(Ended::ReceiverGone)
+}
+
+/// Opens a connection to `ws_url`. The outer error is for good; the inner one is
+/// worth retrying.
+pub(super) async fn connect(ws_url: &str) -> Result<Result<WsStream, String>, FeedError> {
+    match tokio_tungstenite::connect_async(ws_url).await {
+        Ok((ws, _)) => Ok(Ok(ws)),
+        Err(tungstenite::Error::Url(e)) => Err(FeedError::InvalidUrl(e.to_string())),
+        Err(tungstenite::Error::HttpFormat(e)) => Err(FeedError::InvalidUrl(e.to_string())),
+        Err(e) => Ok(Err(e.to_string())),
+    }
+}
+
+/// One connection: subscribe, then forward headers until it drops.
+async fn session(ws_url: &str, gas_tx: &mpsc::Sender<GasEvent>) -> Result<Ended, FeedError> {
+    let disconnected = |subscribed, reason: String| Ok(Ended::Disconnected { subscribed, reason });
+    let mut ws = match connect(ws_url).await? {
+        Ok(ws) => ws,
+        Err(reason) => return disconnected(false, reason),
+    };
+    if let Err(e) = ws.send(Message::text(SUBSCRIBE)).await {
+        return disconnected(false, e.to_string());
+    }
+
+    let mut subscribed = false;
+    loop {
+        let message = tokio::select! {
+            message = ws.next() => message,
+            _ = gas_tx.closed() => return hang_up(&mut ws).await,
+        };
+        let text = match message {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) | None => {
+                return disconnected(subscribed, "closed by the node".into());
+            }
+            // tungstenite answers pings itself
+            Some(Ok(_)) => continue,
+            Some(Err(e)) => return disconnected(subscribed, e.to_string()),
+        };
+        match parse_message(&text) {
+            Ok(Incoming::Subscribed(id)) => {
+                info!("Subscribed to newHeads on {} as {}", ws_url, id);
+                subscribed = true;
+            }
+            Ok(Incoming::Refused(error)) => return Err(FeedError::Rejected(error)),
+            Ok(Incoming::Header(event)) => {
+                if gas_tx.send(event).await.is_err() {
+                    return hang_up(&mut ws).await;
+                }
+            }
+            Ok(Incoming::Other) => {}
+            Err(e) => warn!("skipping malformed newHeads message: {}", e),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Incoming {
+    /// The reply to `eth_subscribe`, with the subscription id.
+    Subscribed(String),
+    /// An error reply to `eth_subscribe`.
+    Refused(String),
+    Header(GasEvent),
+    Other,
+}
+
+/// The fields of a `newHeads` header a `GasEvent::NewBlock` needs.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Header {
+    number: U64,
+    base_fee_per_gas: Option<U64>,
+    gas_used: U64,
+    gas_limit: U64,
+    hash: B256,
+    parent_hash: B256,
+}
+
+fn parse_message(text: &str) -> Result<Incoming, String> {
+    let message: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+    if let Some(error) = message.get("error") {
+        return Ok(Incoming::Refused(error.to_string()));
+    }
+    if message.get("method").and_then(Value::as_str) == Some("eth_subscription") {
+        let header = message
+            .pointer("/params/result")
+            .ok_or("notification without a result")?;
+        let header = Header::deserialize(header).map_err(|e| e.to_string())?;
+        let base_fee = header
+            .base_fee_per_gas
+            .ok_or("header has no baseFeePerGas")?;
+        return Ok(Incoming::Header(GasEvent::NewBlock {
+            number: header.number.to(),
+            base_fee: base_fee.to(),
+            gas_used: header.gas_used.to(),
+            gas_limit: header.gas_limit.to(),
+            block_hash: header.hash.0,
+            parent_hash: header.parent_hash.0,
+        }));
+    }
+    Ok(match message.get("result").and_then(Value::as_str) {
+        Some(id) => Incoming::Subscribed(id.to_owned()),
+        None => Incoming::Other,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::WebSocketStream;
+
+    /// A header as geth sends it, trimmed of the fields nothing reads.
+    const NEW_HEADS: &str = r#"{
+        "jsonrpc": "2.0",
+        "method": "eth_subscription",
+        "params": {
+            "subscription": "0x9ce59a13059e417087c02d3236a0b1cc",
+            "result": {
+                "baseFeePerGas": "0x3b9aca00",
+                "difficulty": "0x0",
+                "gasLimit": "0x1c9c380",
+                "gasUsed": "0xe4e1c0",
+                "hash": "0x1111111111111111111111111111111111111111111111111111111111111111",
+                "miner": "0x95222290dd7278aa3ddd389cc1e1d165cc4bafe5",
+                "number": "0x1312d00",
+                "parentHash": "0x2222222222222222222222222222222222222222222222222222222222222222",
+                "timestamp": "0x65a1b2c3"
+            }
+        }
+    }"#;
+
+    fn header(number: u64) -> String {
+        NEW_HEADS.replace("0x1312d00", &format!("{:#x}", number))
+    }
+
+    fn number(event: GasEvent) -> u64 {
+        match event {
+            GasEvent::NewBlock { number, .. } => number,
+            other => panic!("expected a new block, got {:?}", other),
+        }
+    }
+
+    /// Accepts one connection and answers its `eth_subscribe` with `reply`.
+    async fn accept(listener: &TcpListener, reply: &str) -> WebSocketStream<tokio::net::TcpStream> {
+        let (stream, _) = listener.accept().await.unwrap();
+        let mut ws = tokio_tungstenite::accept_async(stream).await.unwrap();
+        let request = ws.next().await.unwrap().unwrap();
+        assert_eq!(request.to_text().unwrap(), SUBSCRIBE);
+        ws.send(Message::text(reply)).await.unwrap();
+        ws
+    }
+
+    #[test]
+    fn test_parse_recorded_header() {
+        assert_eq!(
+            parse_message(NEW_HEADS),
+            Ok(Incoming::Header(GasEvent::NewBlock {
+                number: 20_000_000,
+                base_fee: 1_000_000_000,
+                gas_used: 15_000_000,
+                gas_limit: 30_000_000,
+                block_hash: [0x11; 32],
+                parent_hash: [0x22; 32],
+            }))
+        );
+        assert_eq!(
+            parse_message(r#"{"jsonrpc":"2.0","id":1,"result":"0x9ce5"}"#),
+            Ok(Incoming::Subscribed("0x9ce5".into()))
+        );
+        let pre_london = NEW_HEADS.replace(r#""baseFeePerGas": "0x3b9aca00","#, "");
+        assert!(parse_message(&pre_london).is_err());
+        assert!(parse_message(&NEW_HEADS.replace("0xe4e1c0", "lots")).is_err());
+        assert!(parse_message("not json").is_err());
+    }
+
+    #[test]
+    fn test_backoff_doubles_up_to_cap() {
+        assert_eq!(backoff(0), Duration::ZERO);
+        assert_eq!(backoff(1), Duration::from_millis(500));
+        assert_eq!(backoff(3), Duration::from_secs(2));
+        assert_eq!(backoff(10), MAX_BACKOFF);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_feed_skips_bad_headers_and_resubscribes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let (gas_tx, mut gas_rx) = mpsc::channel(10);
+        let feed = spawn_newheads_feed(url, gas_tx);
+
+        let subscribed = r#"{"jsonrpc":"2.0","id":1,"result":"0x1"}"#;
+        let mut ws = accept(&listener, subscribed).await;
+        let pre_london = header(16).replace(r#""baseFeePerGas": "0x3b9aca00","#, "");
+        ws.send(Message::text(pre_london)).await.unwrap();
+        ws.send(Message::text(header(17))).await.unwrap();
+        ws.close(None).await.unwrap();
+        assert_eq!(number(gas_rx.recv().await.unwrap()), 17);
+
+        // the feed comes back and subscribes again
+        let mut ws = accept(&listener, subscribed).await;
+        ws.send(Message::text(header(18))).await.unwrap();
+        assert_eq!(number(gas_rx.recv().await.unwrap()), 18);
+
+        // and says goodbye once nothing takes its events
+        drop(gas_rx);
+        assert_eq!(feed.await.unwrap(), Ok(()));
+        assert!(matches!(ws.next().await, Some(Ok(Message::Close(_)))));
+    }
+
+    #[tokio::test]
+    async fn test_feed_gives_up_on_fatal_errors() {
+        let (gas_tx, _gas_rx) = mpsc::channel(10);
+        for url in ["ftp://127.0.0.1", "not a url"] {
+            let feed = spawn_newheads_feed(url, gas_tx.clone());
+            assert!(matches!(feed.await.unwrap(), Err(FeedError::InvalidUrl(_))));
+        }
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let feed = spawn_newheads_feed(url, gas_tx);
+        let refused = r#"{"jsonrpc":"2.0","id":1,"error":{"code":-32601,"message":"notifications not supported"}}"#;
+        let _ws = accept(&listener, refused).await;
+        assert!(matches!(feed.await.unwrap(), Err(FeedError::Rejected(_))));
+    }
+}
@@ -0,0 +1,418 @@
+use crate::envelope::{CURRENT_SCHEMA_VERSION, Envelope, Schema, SchemaError};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    Encode(String),
+    Decode(String),
+    /// An envelope written at another schema version. Only Borsh payloads have
+    /// migration shims, so nothing else decodes.
+    Schema(SchemaError),
+}
+
+impl std::fmt::Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FormatError::Encode(err) => write!(f, "could not encode: {}", err),
+            FormatError::Decode(err) => write!(f, "could not decode: {}", err),
+            FormatError::Schema(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for FormatError {}
+
+impl From<SchemaError> for FormatError {
+    fn from(err: SchemaError) -> Self {
+        FormatError::Schema(err)
+    }
+}
+
+/// Just the version of an encoded envelope, read before the payload is.
+#[derive(Deserialize)]
+struct Header {
+    schema_version: u16,
+}
+
+fn check_version(header: Header) -> Result<(), FormatError> {
+    if header.schema_version != CURRENT_SCHEMA_VERSION {
+        return Err(SchemaError::UnsupportedVersion {
+            found: header.schema_version,
+            supported: CURRENT_SCHEMA_VERSION,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+/// CBOR with field and variant names, so consumers can read it without this crate.
+#[cfg(feature = "cbor")]
+pub fn to_cbor<T: Schema + Serialize>(value: &T) -> Result<Vec<u8>, FormatError> {
+    let mut out = Vec::new();
+    ciborium::into_writer(value, &mut out).map_err(|e| FormatError::Encode(e.to_string()))?;
+    Ok(out)
+}
+
+#[cfg(feature = "cbor")]
+pub fn from_cbor<T: Schema + DeserializeOwned>(bytes: &[u8]) -> Result<T, FormatError> {
+    ciborium::from_reader(bytes).map_err(|e| FormatError::Decode(e.to_string()))
+}
+
+/// bincode's fixed-width little-endian layout, as `bincode::serialize` writes it.
+#[cfg(feature = "bincode")]
+pub fn to_bincode<T: Schema + Serialize>(value: &T) -> Result<Vec<u8>, FormatError> {
+    bincode::serialize(value).map_err(|e| FormatError::Encode(e.to_string()))
+}
+
+/// Bytes left over after the value are an error.
+#[cfg(feature = "bincode")]
+pub fn from_bincode<T: Schema + DeserializeOwned>(bytes: &[u8]) -> Result<T, FormatError> {
+    use bincode::Options;
+
+    bincode::DefaultOptions::new()
+        .with_fixint_encoding()
+        .reject_trailing_bytes()
+        .deserialize(bytes)
+        .map_err(|e| FormatError::Decode(e.to_string()))
+}
+
+impl<T: Schema + Serialize + DeserializeOwned> Envelope<T> {
+    #[cfg(feature = "cbor")]
+    pub fn to_cbor(&self) -> Result<Vec<u8>, FormatError> {
+        let mut out = Vec::new();
+        ciborium::into_writer(self, &mut out).map_err(|e| FormatError::Encode(e.to_string()))?;
+        Ok(out)
+    }
+
+    /// Unlike `Envelope::decode`, refuses anything but `CURRENT_SCHEMA_VERSION`.
+    #[cfg(feature = "cbor")]
+    pub fn from_cbor(bytes: &[u8]) -> Result<Self, FormatError> {
+        check_version(
+            ciborium::from_reader(bytes).map_err(|e| FormatError::Decode(e.to_string()))?,
+        )?;
+        ciborium::from_reader(bytes).map_err(|e| FormatError::Decode(e.to_string()))
+    }
+
+    #[cfg(feature = "bincode")]
+    pub fn to_bincode(&self) -> Result<Vec<u8>, FormatError> {
+        bincode::serialize(self).map_err(|e| FormatError::Encode(e.to_string()))
+    }
+
+    /// Unlike `Envelope::decode`, refuses anything but `CURRENT_SCHEMA_VERSION`.
+    #[cfg(feature = "bincode")]
+    pub fn from_bincode(bytes: &[u8]) -> Result<Self, FormatError> {
+        use bincode::Options;
+
+        // the version leads, so it reads on its own with the payload left over
+        check_version(
+            bincode::deserialize(bytes).map_err(|e| FormatError::Decode(e.to_string()))?,
+        )?;
+        bincode::DefaultOptions::new()
+            .with_fixint_encoding()
+            .reject_trailing_bytes()
+            .deserialize(bytes)
+            .map_err(|e| FormatError::Decode(e.to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        AccessListItem, BlobParams, DeferReason, EscalationStep, FeeMode, GasEvent, RetryHint,
+        SchedulerDecision, SignedAuthorization, SubmissionPrivacy, TransactionRequest, Urgency,
+        ValidationError,
+    };
+    use crate::units::Wei;
+    use std::fmt::Debug;
+    use std::time::Duration;
+
+    fn events() -> Vec<GasEvent> {
+        vec![
+            GasEvent::BaseFeeUpdate {
+                base_fee: 30_000_000_000,
+                timestamp: 1_700_000_000,
+            },
+            GasEvent::MempoolTx {
+                tx_hash: [0x11; 32],
+                max_fee: 40_000_000_000,
+                max_priority_fee: 2_000_000_000,
+                gas_limit: 21_000,
+            },
+            GasEvent::NewBlock {
+                number: 7,
+                base_fee: 30_000_000_000,
+                gas_used: 15_000_000,
+                gas_limit: 30_000_000,
+                block_hash: [0x22; 32],
+                parent_hash: [0x21; 32],
+            },
+            GasEvent::TxConfirmed {
+                tx_hash: [0x33; 32],
+                block_number: 7,
+                effective_gas_price: 31_000_000_000,
+                gas_used: 21_000,
+            },
+            GasEvent::TxDropped {
+                tx_hash: [0x44; 32],
+                reason: "evicted".to_string(),
+            },
+            GasEvent::TxFailed {
+                tx_hash: [0x55; 32],
+                block_number: 8,
+            },
+            GasEvent::TxReplaced {
+                old_tx_hash: [0x66; 32],
+                new_tx_hash: [0x67; 32],
+                nonce: 3,
+            },
+            GasEvent::BlobBaseFeeUpdate { blob_base_fee: 1 },
+            GasEvent::FeeHistory {
+                oldest_block: 5,
+                base_fees: vec![30, 31, 32],
+                gas_used_ratios: vec![0.5, 0.75],
+                rewards: vec![vec![1, 2], vec![3, 4]],
+            },
+            GasEvent::OracleEstimate {
+                source: "etherscan".into(),
+                base_fee: 30,
+                priority_fee_fast: 3,
+                priority_fee_standard: 1,
+            },
+            GasEvent::L1BaseFeeUpdate { base_fee: 9 },
+        ]
+    }
+
+    fn request() -> TransactionRequest {
+        TransactionRequest {
+            id: 1,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![0xa9, 0x05, 0x9c, 0xbb],
+            value: [0xFF; 32],
+            gas_limit: 60_000,
+            max_fee_per_gas: Wei(u128::MAX),
+            max_priority_fee_per_gas: Wei(2_000_000_000),
+            deadline: Some(1_700_000_060),
+            urgency: Urgency::High,
+            max_wait_blocks: Some(4),
+            escalation: Some(vec![EscalationStep {
+                after_secs: 30,
+                max_fee_per_gas: Wei(50_000_000_000),
+                max_priority_fee_per_gas: Wei(3_000_000_000),
+            }]),
+            chain_id: Some(1),
+            blob: Some(BlobParams {
+                max_fee_per_blob_gas: Wei(10),
+                blob_count: 2,
+            }),
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: Some([0x77; 16]),
+            access_list: Some(vec![AccessListItem {
+                address: [0xCC; 20],
+                storage_keys: vec![[0; 32], [0x01; 32]],
+            }]),
+            authorization_list: Some(vec![SignedAuthorization {
+                chain_id: 1,
+                address: [0xDD; 20],
+                nonce: 3,
+                y_parity: 1,
+                r: [0x11; 32],
+                s: [0x22; 32],
+            }]),
+            privacy: SubmissionPrivacy::PrivateRelay,
+        }
+    }
+
+    fn requests() -> Vec<TransactionRequest> {
+        let contract_creation = TransactionRequest {
+            to: None,
+            data: vec![0x60, 0x80],
+            escalation: None,
+            blob: None,
+            fee_mode: FeeMode::Legacy,
+            idempotency_key: None,
+            access_list: None,
+            ..request()
+        };
+        vec![request(), contract_creation]
+    }
+
+    fn decisions() -> Vec<SchedulerDecision> {
+        vec![
+            SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(32_000_000_000),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(672_000_000_000_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: Some(Wei(1)),
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Defer {
+                tx_id: 2,
+                reason: DeferReason::NonceUnavailable("busy".to_string()),
+                retry_hint: Some(RetryHint::AfterDuration(Duration::from_millis(1500))),
+            },
+            SchedulerDecision::Reprice {
+                tx_id: 1,
+                old_nonce: 0,
+                new_gas_price: Wei(40_000_000_000),
+                max_priority_fee_per_gas: Wei(2),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Legacy,
+                privacy: SubmissionPrivacy::Public,
+            },
+            SchedulerDecision::Drop {
+                tx_id: 3,
+                reason: "deadline expired".to_string(),
+            },
+            SchedulerDecision::Rejected {
+                tx_id: 4,
+                reason: "fee cap is zero".to_string(),
+                error: Some(ValidationError::PriorityFeeAboveMaxFee {
+                    max_priority_fee_per_gas: Wei(3),
+                    max_fee_per_gas: Wei(2),
+                }),
+            },
+            SchedulerDecision::ModeChanged { spike: true },
+            SchedulerDecision::NonceInitRequired {
+                chain_id: 1,
+                address: [0xAA; 20],
+            },
+            SchedulerDecision::NonceGapDetected {
+                chain_id: 1,
+                address: [0xAA; 20],
+                missing: vec![3, 5],
+            },
+            SchedulerDecision::FillNonceGap {
+                tx_id: 1 << 63,
+                chain_id: 1,
+                address: [0xAA; 20],
+                nonce: 3,
+                gas_price: Wei(30_000_000_000),
+                fee_mode: FeeMode::Eip1559,
+            },
+            SchedulerDecision::NonceConsumed { tx_id: 5, nonce: 6 },
+            SchedulerDecision::NonceConflict {
+                tx_id: 6,
+                chain_id: 10,
+                address: [0xCC; 20],
+                nonce: 2,
+                reason: "already mined".to_string(),
+            },
+            SchedulerDecision::Reorg {
+                fork_block: 6,
+                depth: 2,
+                unconfirmed: vec![1, 2],
+            },
+            SchedulerDecision::MarketUpdate {
+                current_fee: Wei(30_000_000_000),
+                volatility: 1.25,
+                trend: -0.5,
+                spike: false,
+            },
+            SchedulerDecision::Confirmed {
+                tx_id: 1,
+                block_number: 8,
+                effective_gas_price: Wei(31_000_000_000),
+                realized_cost_wei: Wei(651_000_000_000_000),
+                savings_vs_max_fee_wei: Wei(189_000_000_000_000),
+                savings_vs_acceptance_wei: Wei::ZERO,
+            },
+        ]
+    }
+
+    fn round_trip<T: PartialEq + Debug>(
+        values: Vec<T>,
+        encode: impl Fn(&T) -> Result<Vec<u8>, FormatError>,
+        decode: impl Fn(&[u8]) -> Result<T, FormatError>,
+    ) {
+        for value in values {
+            let bytes = encode(&value).unwrap();
+            assert_eq!(decode(&bytes).unwrap(), value);
+        }
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_round_trips_every_variant() {
+        round_trip(events(), to_cbor, from_cbor);
+        round_trip(requests(), to_cbor, from_cbor);
+        round_trip(decisions(), to_cbor, from_cbor);
+        round_trip(
+            decisions().into_iter().map(Envelope::new).collect(),
+            Envelope::to_cbor,
+            Envelope::from_cbor,
+        );
+
+        // names travel with the values, and byte fields are byte strings
+        let value: ciborium::Value =
+            ciborium::from_reader(&to_cbor(&request()).unwrap()[..]).unwrap();
+        let fields = value.as_map().unwrap();
+        let field = |name: &str| {
+            fields
+                .iter()
+                .find(|(key, _)| key.as_text() == Some(name))
+                .map(|(_, value)| value.clone())
+        };
+        assert_eq!(field("from"), Some(ciborium::Value::Bytes(vec![0xAA; 20])));
+        assert_eq!(field("gas_limit"), Some(ciborium::Value::from(60_000)));
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_round_trips_every_variant() {
+        round_trip(events(), to_bincode, from_bincode);
+        round_trip(requests(), to_bincode, from_bincode);
+        round_trip(decisions(), to_bincode, from_bincode);
+        round_trip(
+            decisions().into_iter().map(Envelope::new).collect(),
+            Envelope::to_bincode,
+            Envelope::from_bincode,
+        );
+
+        let mut bytes = to_bincode(&request()).unwrap();
+        bytes.push(0);
+        assert!(matches!(
+            from_bincode::<TransactionRequest>(&bytes),
+            Err(FormatError::Decode(_))
+        ));
+    }
+
+    #[cfg(feature = "cbor")]
+    #[test]
+    fn test_cbor_envelope_refuses_other_versions() {
+        let envelope = Envelope {
+            schema_version: CURRENT_SCHEMA_VERSION - 1,
+            payload: request(),
+        };
+        assert_eq!(
+            Envelope::<TransactionRequest>::from_cbor(&envelope.to_cbor().unwrap()),
+            Err(FormatError::Schema(SchemaError::UnsupportedVersion {
+                found: CURRENT_SCHEMA_VERSION - 1,
+                supported: CURRENT_SCHEMA_VERSION,
+            }))
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_bincode_envelope_refuses_other_versions() {
+        let envelope = Envelope {
+            schema_version: CURRENT_SCHEMA_VERSION + 1,
+            payload: request(),
+        };
+        assert_eq!(
+            Envelope::<TransactionRequest>::from_bincode(&envelope.to_bincode().unwrap()),
+            Err(FormatError::Schema(SchemaError::UnsupportedVersion {
+                found: CURRENT_SCHEMA_VERSION + 1,
+                supported: CURRENT_SCHEMA_VERSION,
+            }))
+        );
+    }
+}
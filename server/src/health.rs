@@ -0,0 +1,431 @@
+use crate::channel::ChannelStats;
+#[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+use crate::feeds::{FeedHealth, aggregate::AggregateHealth};
+use crate::nonce::NonceManager;
+use crate::scheduler::{Scheduler, SchedulerHandle};
+#[cfg(feature = "serde")]
+use serde::Serialize;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// When a `HealthMonitor` stops reporting healthy.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HealthThresholds {
+    /// The model going this long without a base fee makes the server unhealthy.
+    pub model_stale_after: Duration,
+    /// A feed going this long without a successful call or event counts as
+    /// stale, which degrades the server.
+    pub feed_stale_after: Duration,
+    /// A channel filled past this fraction of its capacity degrades the server.
+    pub max_channel_fill: f64,
+}
+
+impl Default for HealthThresholds {
+    fn default() -> Self {
+        Self {
+            model_stale_after: Duration::from_secs(60),
+            feed_stale_after: Duration::from_secs(120),
+            max_channel_fill: 0.9,
+        }
+    }
+}
+
+/// Worst first, so the overall status is the maximum of its parts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "lowercase"))]
+pub enum HealthStatus {
+    Healthy,
+    /// Working, with something to look at: a feed failing or a channel backing up.
+    Degraded,
+    /// Not getting the data it needs to decide.
+    Unhealthy,
+}
+
+impl std::fmt::Display for HealthStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HealthStatus::Healthy => write!(f, "healthy"),
+            HealthStatus::Degraded => write!(f, "degraded"),
+            HealthStatus::Unhealthy => write!(f, "unhealthy"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize), serde(rename_all = "lowercase"))]
+pub enum FeedState {
+    /// A polling feed whose last call succeeded.
+    Connected,
+    /// A polling feed whose last calls failed, fewer than `UNHEALTHY_AFTER` of
+    /// them.
+    Failing,
+    /// A polling feed past `UNHEALTHY_AFTER` failures in a row.
+    Down,
+    /// The aggregated block feed being forwarded.
+    Active,
+    /// An aggregated block feed that is live but not forwarded.
+    Standby,
+    /// An aggregated block feed gone quiet past the aggregator's limit.
+    Stale,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct FeedReport {
+    pub name: String,
+    pub state: FeedState,
+    /// Seconds since its last event or successful call; None before the first.
+    pub last_event_age_secs: Option<f64>,
+    #[cfg_attr(feature = "serde", serde(skip_serializing_if = "Option::is_none"))]
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ModelReport {
+    pub samples: usize,
+    /// Seconds since the last base fee; None before the first.
+    pub last_update_age_secs: Option<f64>,
+    pub stale: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct QueueReport {
+    pub pending: usize,
+    pub submitted: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct ChannelReport {
+    pub name: String,
+    /// Messages queued, or permits held for them.
+    pub depth: usize,
+    pub capacity: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct LimiterReport {
+    /// Submissions that could go right now.
+    pub available: u64,
+    pub rejection_ratio: f64,
+}
+
+/// Everything a `HealthMonitor` watches, as of `HealthMonitor::report`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize))]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// What made the status worse than healthy, for people.
+    pub problems: Vec<String>,
+    pub uptime_secs: u64,
+    pub feeds: Vec<FeedReport>,
+    pub model: Option<ModelReport>,
+    pub queues: Option<QueueReport>,
+    pub channels: Vec<ChannelReport>,
+    /// Accounts the nonce manager tracks.
+    pub nonce_accounts: Option<usize>,
+    pub limiter: Option<LimiterReport>,
+}
+
+impl HealthReport {
+    fn flag(&mut self, status: HealthStatus, problem: String) {
+        self.status = self.status.max(status);
+        self.problems.push(problem);
+    }
+}
+
+#[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+enum FeedProbe {
+    Polling(String, FeedHealth),
+    Aggregate(AggregateHealth),
+}
+
+struct ChannelProbe {
+    name: String,
+    stats: ChannelStats,
+}
+
+/// Watches the parts of a running server that tell whether it is getting data
+/// and keeping up: feeds, the gas model, the scheduler's queues, the channels
+/// between them, the nonce manager and the limiter. Parts left out are left out
+/// of the report too.
+pub struct HealthMonitor {
+    thresholds: HealthThresholds,
+    started_at: Instant,
+    scheduler: Option<Weak<Scheduler>>,
+    nonce_manager: Option<Arc<NonceManager>>,
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    feeds: Vec<FeedProbe>,
+    channels: Vec<ChannelProbe>,
+}
+
+impl HealthMonitor {
+    pub fn new(thresholds: HealthThresholds) -> Self {
+        Self {
+            thresholds,
+            started_at: Instant::now(),
+            scheduler: None,
+            nonce_manager: None,
+            #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+            feeds: Vec::new(),
+            channels: Vec::new(),
+        }
+    }
+
+    /// Report the scheduler's queues, model and limiter while it runs. It isn't
+    /// kept alive for this, so its sinks still close once it stops.
+    pub fn with_scheduler(mut self, scheduler: &Arc<Scheduler>) -> Self {
+        self.scheduler = Some(Arc::downgrade(scheduler));
+        self
+    }
+
+    pub fn with_nonce_manager(mut self, nonce_manager: Arc<NonceManager>) -> Self {
+        self.nonce_manager = Some(nonce_manager);
+        self
+    }
+
+    /// Report a polling feed by `name`.
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    pub fn with_feed(mut self, name: impl Into<String>, health: FeedHealth) -> Self {
+        self.feeds.push(FeedProbe::Polling(name.into(), health));
+        self
+    }
+
+    /// Report each of an aggregator's block feeds.
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    pub fn with_aggregate(mut self, health: AggregateHealth) -> Self {
+        self.feeds.push(FeedProbe::Aggregate(health));
+        self
+    }
+
+    /// Report how full the channel behind `stats` is. A closed channel drops
+    /// out of the report.
+    pub fn with_channel(mut self, name: impl Into<String>, stats: ChannelStats) -> Self {
+        self.channels.push(ChannelProbe {
+            name: name.into(),
+            stats,
+        });
+        self
+    }
+
+    /// Report the request and command channels behind `handle`.
+    pub fn with_handle(self, handle: &SchedulerHandle) -> Self {
+        self.with_channel("requests", handle.request_stats())
+            .with_channel("commands", ChannelStats::of(handle.commands()))
+    }
+
+    pub fn report(&self) -> HealthReport {
+        let uptime = self.started_at.elapsed();
+        let mut report = HealthReport {
+            status: HealthStatus::Healthy,
+            problems: Vec::new(),
+            uptime_secs: uptime.as_secs(),
+            feeds: Vec::new(),
+            model: None,
+            queues: None,
+            channels: Vec::new(),
+            nonce_accounts: self
+                .nonce_manager
+                .as_ref()
+                .map(|nonce_manager| nonce_manager.accounts().len()),
+            limiter: None,
+        };
+
+        if let Some(scheduler) = self.scheduler.as_ref().and_then(Weak::upgrade) {
+            let model = scheduler.model();
+            let since_update = model.since_last_update();
+            // a model that never got a base fee is as stale as the server is old
+            let stale = since_update.unwrap_or(uptime) > self.thresholds.model_stale_after;
+            if stale {
+                report.flag(
+                    HealthStatus::Unhealthy,
+                    match since_update {
+                        Some(age) => format!("no base fee for {}s", age.as_secs()),
+                        None => format!("no base fee since startup {}s ago", uptime.as_secs()),
+                    },
+                );
+            }
+            report.model = Some(ModelReport {
+                samples: model.sample_count(),
+                last_update_age_secs: since_update.map(|age| age.as_secs_f64()),
+                stale,
+            });
+            report.queues = Some(QueueReport {
+                pending: scheduler.pending_count(),
+                submitted: scheduler.submitted_count(),
+            });
+            let stats = scheduler.limiter_stats();
+            report.limiter = Some(LimiterReport {
+                available: stats.available,
+                rejection_ratio: stats.rejection_ratio(),
+            });
+        }
+
+        #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+        for probe in &self.feeds {
+            self.report_feed(probe, uptime, &mut report);
+        }
+
+        for probe in &self.channels {
+            let Some(depth) = probe.stats.depth() else {
+                continue;
+            };
+            let capacity = probe.stats.capacity();
+            if depth as f64 > capacity as f64 * self.thresholds.max_channel_fill {
+                report.flag(
+                    HealthStatus::Degraded,
+                    format!("{} channel holds {} of {}", probe.name, depth, capacity),
+                );
+            }
+            report.channels.push(ChannelReport {
+                name: probe.name.clone(),
+                depth,
+                capacity,
+            });
+        }
+        report
+    }
+
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    fn report_feed(&self, probe: &FeedProbe, uptime: Duration, report: &mut HealthReport) {
+        match probe {
+            FeedProbe::Polling(name, health) => {
+                let since_success = health.since_last_success();
+                let state = if !health.is_healthy() {
+                    report.flag(
+                        HealthStatus::Degraded,
+                        format!(
+                            "{} feed down after {} failures",
+                            name,
+                            health.consecutive_failures()
+                        ),
+                    );
+                    FeedState::Down
+                } else if health.consecutive_failures() > 0 {
+                    FeedState::Failing
+                } else {
+                    FeedState::Connected
+                };
+                if state != FeedState::Down
+                    && since_success.unwrap_or(uptime) > self.thresholds.feed_stale_after
+                {
+                    report.flag(
+                        HealthStatus::Degraded,
+                        format!(
+                            "{} feed has not answered for {}s",
+                            name,
+                            since_success.unwrap_or(uptime).as_secs()
+                        ),
+                    );
+                }
+                report.feeds.push(FeedReport {
+                    name: name.clone(),
+                    state,
+                    last_event_age_secs: since_success.map(|age| age.as_secs_f64()),
+                    last_error: health.last_error(),
+                });
+            }
+            FeedProbe::Aggregate(health) => {
+                let sources = health.sources();
+                if !sources.iter().any(|source| source.live) {
+                    report.flag(HealthStatus::Unhealthy, "no block feed is live".to_string());
+                }
+                for source in sources {
+                    let state = if source.active {
+                        FeedState::Active
+                    } else if source.live {
+                        FeedState::Standby
+                    } else {
+                        report.flag(
+                            HealthStatus::Degraded,
+                            format!("{} feed is stale", source.name),
+                        );
+                        FeedState::Stale
+                    };
+                    report.feeds.push(FeedReport {
+                        name: source.name,
+                        state,
+                        last_event_age_secs: source.since_last_event.map(|age| age.as_secs_f64()),
+                        last_error: None,
+                    });
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::units::Wei;
+    use tokio::sync::mpsc;
+
+    fn scheduler() -> Arc<Scheduler> {
+        Arc::new(Scheduler::new(
+            Default::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(NonceManager::new()),
+            Arc::new(RateLimiter::new(10, 10)),
+            vec![],
+        ))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_model_and_full_channels_degrade_the_status() {
+        let scheduler = scheduler();
+        let (event_tx, _event_rx) = mpsc::channel::<u64>(10);
+        let monitor = HealthMonitor::new(HealthThresholds::default())
+            .with_scheduler(&scheduler)
+            .with_nonce_manager(Arc::new(NonceManager::new()))
+            .with_channel("events", ChannelStats::of(&event_tx));
+
+        // a fresh server hasn't had the time to miss anything
+        let report = monitor.report();
+        assert_eq!(
+            report.status,
+            HealthStatus::Healthy,
+            "{:?}",
+            report.problems
+        );
+        assert_eq!(report.model.unwrap().last_update_age_secs, None);
+        assert_eq!(report.nonce_accounts, Some(0));
+        assert_eq!(report.limiter.unwrap().available, 10);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let report = monitor.report();
+        assert_eq!(report.status, HealthStatus::Unhealthy);
+        assert!(report.model.unwrap().stale);
+
+        scheduler.model().update(Wei(40));
+        for n in 0..10 {
+            event_tx.send(n).await.unwrap();
+        }
+        let report = monitor.report();
+        assert_eq!(
+            report.status,
+            HealthStatus::Degraded,
+            "{:?}",
+            report.problems
+        );
+        assert_eq!(
+            report.channels,
+            vec![ChannelReport {
+                name: "events".into(),
+                depth: 10,
+                capacity: 10,
+            }]
+        );
+
+        // a closed channel drops out of the report
+        drop(event_tx);
+        let report = monitor.report();
+        assert_eq!(report.status, HealthStatus::Healthy);
+        assert!(report.channels.is_empty());
+    }
+}
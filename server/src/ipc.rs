@@ -0,0 +1,473 @@
+use crate::channel::InstrumentedSender;
+use crate::codec::{FrameDecoder, FrameEncoder, FrameError};
+use crate::envelope::{Envelope, Schema, SchemaError};
+use crate::events::{
+    DecisionRecord, GasEvent, SchedulerCommand, SchedulerDecision, TransactionRequest,
+};
+use crate::scheduler::SchedulerHandle;
+use crate::sink::{DecisionSink, SinkError};
+use async_trait::async_trait;
+use borsh::{BorshDeserialize, BorshSerialize};
+use futures::{SinkExt, StreamExt};
+use parking_lot::Mutex;
+use std::io::{Read, Write};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinSet;
+use tokio_util::codec::{FramedRead, FramedWrite};
+use tokio_util::sync::CancellationToken;
+use tracing::warn;
+
+/// Records a subscribed client may fall behind by before it is disconnected.
+pub const DEFAULT_CLIENT_BUFFER: usize = 256;
+
+/// What a client sends, one per frame in an `Envelope`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+pub enum ClientMessage {
+    Request(Box<TransactionRequest>),
+    Event(GasEvent),
+    Command(SchedulerCommand),
+    /// Stream every `DecisionRecord` from now on back on this connection.
+    Subscribe,
+}
+
+// new at schema version 10, so there are no older frames to upgrade
+impl Schema for ClientMessage {}
+
+#[derive(Debug)]
+pub enum IpcError {
+    Frame(FrameError),
+    /// A frame that isn't an envelope this build can read.
+    Schema(SchemaError),
+}
+
+impl std::fmt::Display for IpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IpcError::Frame(err) => write!(f, "{}", err),
+            IpcError::Schema(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for IpcError {}
+
+impl From<FrameError> for IpcError {
+    fn from(err: FrameError) -> Self {
+        IpcError::Frame(err)
+    }
+}
+
+impl From<SchemaError> for IpcError {
+    fn from(err: SchemaError) -> Self {
+        IpcError::Schema(err)
+    }
+}
+
+impl From<std::io::Error> for IpcError {
+    fn from(err: std::io::Error) -> Self {
+        IpcError::Frame(FrameError::Io(err))
+    }
+}
+
+/// A frame's payload as it is, so it can go through `Envelope::decode` and its
+/// migrations rather than a plain Borsh read.
+struct RawFrame(Vec<u8>);
+
+impl BorshSerialize for RawFrame {
+    fn serialize<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        writer.write_all(&self.0)
+    }
+}
+
+impl BorshDeserialize for RawFrame {
+    fn deserialize_reader<R: Read>(reader: &mut R) -> std::io::Result<Self> {
+        let mut payload = Vec::new();
+        reader.read_to_end(&mut payload)?;
+        Ok(Self(payload))
+    }
+}
+
+type Subscribers = Arc<Mutex<Vec<mpsc::Sender<DecisionRecord>>>>;
+
+/// Serves the scheduler to local processes on a Unix domain socket, in the frames
+/// of `codec`. Each frame a client sends is an `Envelope<ClientMessage>`; requests
+/// and commands go to the scheduler's handle and events to its gas feed. Once a
+/// client has sent `Subscribe`, the server sends it every decision record, each
+/// an `Envelope<DecisionRecord>` frame, from its own buffer. A client that lets
+/// its buffer fill is disconnected, as is one sending a frame that can't be
+/// split off the stream; frames that only fail to decode are logged and skipped.
+///
+/// Records reach the server through `sink`, which has to be among the
+/// scheduler's sinks. The socket file is removed when the server is dropped.
+pub struct IpcServer {
+    listener: UnixListener,
+    path: PathBuf,
+    handle: SchedulerHandle,
+    events: InstrumentedSender<GasEvent>,
+    subscribers: Subscribers,
+    client_buffer: usize,
+    shutdown: CancellationToken,
+}
+
+impl IpcServer {
+    /// Listens on `path`, replacing a socket left there by an earlier run.
+    pub fn bind(
+        path: impl AsRef<Path>,
+        handle: SchedulerHandle,
+        events: impl Into<InstrumentedSender<GasEvent>>,
+    ) -> std::io::Result<Self> {
+        let path = path.as_ref();
+        if std::fs::symlink_metadata(path).is_ok_and(|meta| meta.file_type().is_socket()) {
+            std::fs::remove_file(path)?;
+        }
+        Ok(Self {
+            listener: UnixListener::bind(path)?,
+            path: path.to_path_buf(),
+            handle,
+            events: events.into(),
+            subscribers: Arc::default(),
+            client_buffer: DEFAULT_CLIENT_BUFFER,
+            shutdown: CancellationToken::new(),
+        })
+    }
+
+    /// Records each subscribed client may fall behind by.
+    pub fn with_client_buffer(mut self, records: usize) -> Self {
+        self.client_buffer = records;
+        self
+    }
+
+    /// Stop accepting, and hang up on every client, once `shutdown` is cancelled.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The sink feeding subscribed clients. It never waits: a record goes into
+    /// each client's buffer or the client is dropped.
+    pub fn sink(&self) -> IpcSink {
+        IpcSink {
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    /// Accepts clients until the listener fails or the shutdown token is
+    /// cancelled. Returning, or dropping the future, disconnects every client.
+    pub async fn run(self) -> std::io::Result<()> {
+        let mut clients = JoinSet::new();
+        loop {
+            tokio::select! {
+                accepted = self.listener.accept() => {
+                    let (stream, _) = accepted?;
+                    clients.spawn(serve_client(
+                        stream,
+                        self.handle.clone(),
+                        self.events.clone(),
+                        self.subscribers.clone(),
+                        self.client_buffer,
+                    ));
+                }
+                Some(_) = clients.join_next() => {}
+                _ = self.shutdown.cancelled() => return Ok(()),
+            }
+        }
+    }
+}
+
+impl Drop for IpcServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Reads one client's frames until it hangs up or the scheduler goes away.
+async fn serve_client(
+    stream: UnixStream,
+    handle: SchedulerHandle,
+    events: InstrumentedSender<GasEvent>,
+    subscribers: Subscribers,
+    client_buffer: usize,
+) {
+    let (read, write) = stream.into_split();
+    let mut frames = FramedRead::new(read, FrameDecoder::<RawFrame>::new());
+    let mut write = Some(write);
+    // aborted when this returns or is dropped, which closes the client's buffer
+    // and so drops it from the sink
+    let mut writer = JoinSet::new();
+    while let Some(frame) = frames.next().await {
+        let message = match frame {
+            Ok(RawFrame(bytes)) => match Envelope::<ClientMessage>::decode(&bytes) {
+                Ok(envelope) => envelope.payload,
+                Err(e) => {
+                    warn!("IPC: ignoring frame: {}", e);
+                    continue;
+                }
+            },
+            Err(e) => {
+                warn!("IPC: dropping client: {}", e);
+                break;
+            }
+        };
+        let delivered = match message {
+            ClientMessage::Request(req) => handle.submit_waiting(*req).await.is_ok(),
+            ClientMessage::Event(event) => events.send(event).await.is_ok(),
+            ClientMessage::Command(cmd) => handle.command(cmd).await.is_ok(),
+            ClientMessage::Subscribe => {
+                if let Some(write) = write.take() {
+                    let (tx, rx) = mpsc::channel(client_buffer);
+                    subscribers.lock().push(tx);
+                    writer.spawn(write_records(write, rx));
+                }
+                true
+            }
+        };
+        if !delivered {
+            break;
+        }
+    }
+}
+
+async fn write_records(write: OwnedWriteHalf, mut records: mpsc::Receiver<DecisionRecord>) {
+    let mut frames = FramedWrite::new(write, FrameEncoder::<RawFrame>::new());
+    while let Some(record) = records.recv().await {
+        if let Err(e) = frames.send(RawFrame(Envelope::new(record).encode())).await {
+            warn!("IPC: dropping subscriber: {}", e);
+            return;
+        }
+    }
+}
+
+/// Hands decision records to an `IpcServer`'s subscribed clients.
+#[derive(Clone)]
+pub struct IpcSink {
+    subscribers: Subscribers,
+}
+
+#[async_trait]
+impl DecisionSink for IpcSink {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.deliver_record(decision.into()).await
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.subscribers
+            .lock()
+            .retain(|client| match client.try_send(record.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    warn!("IPC: dropping a subscriber that fell behind");
+                    false
+                }
+                Err(TrySendError::Closed(_)) => false,
+            });
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "ipc"
+    }
+}
+
+/// A connection to an `IpcServer`.
+pub struct GasSaverClient {
+    frames: FramedRead<OwnedReadHalf, FrameDecoder<RawFrame>>,
+    sender: FramedWrite<OwnedWriteHalf, FrameEncoder<RawFrame>>,
+}
+
+impl GasSaverClient {
+    pub async fn connect(path: impl AsRef<Path>) -> std::io::Result<Self> {
+        let (read, write) = UnixStream::connect(path).await?.into_split();
+        Ok(Self {
+            frames: FramedRead::new(read, FrameDecoder::new()),
+            sender: FramedWrite::new(write, FrameEncoder::new()),
+        })
+    }
+
+    pub async fn send(&mut self, message: ClientMessage) -> Result<(), IpcError> {
+        let frame = RawFrame(Envelope::new(message).encode());
+        Ok(self.sender.send(frame).await?)
+    }
+
+    pub async fn submit(&mut self, req: TransactionRequest) -> Result<(), IpcError> {
+        self.send(ClientMessage::Request(Box::new(req))).await
+    }
+
+    pub async fn send_event(&mut self, event: GasEvent) -> Result<(), IpcError> {
+        self.send(ClientMessage::Event(event)).await
+    }
+
+    pub async fn command(&mut self, cmd: SchedulerCommand) -> Result<(), IpcError> {
+        self.send(ClientMessage::Command(cmd)).await
+    }
+
+    /// Has the server stream decision records back; read them with
+    /// `next_record`. Records made before the server saw this are not sent.
+    pub async fn subscribe(&mut self) -> Result<(), IpcError> {
+        self.send(ClientMessage::Subscribe).await
+    }
+
+    /// The next decision record; None once the server has hung up.
+    pub async fn next_record(&mut self) -> Result<Option<DecisionRecord>, IpcError> {
+        match self.frames.next().await.transpose()? {
+            Some(RawFrame(bytes)) => Ok(Some(Envelope::decode(&bytes)?.payload)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{FeeMode, SubmissionPrivacy, Urgency};
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::{NonceAllocator, NonceManager};
+    use crate::scheduler::{Scheduler, SchedulerConfig};
+    use crate::units::Wei;
+    use alloy_primitives::Address;
+
+    fn request(id: u64) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    /// A scheduler served on a fresh socket named after `test`, until the
+    /// returned token is cancelled.
+    fn start(test: &str) -> (PathBuf, CancellationToken) {
+        let path =
+            std::env::temp_dir().join(format!("gas_saver_{}_{}.sock", test, std::process::id()));
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let shutdown = CancellationToken::new();
+        let server = IpcServer::bind(&path, handle, event_tx)
+            .unwrap()
+            .with_shutdown(shutdown.clone());
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Arc::new(Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(server.sink())],
+        ));
+        tokio::spawn(scheduler.run(event_rx, req_rx, cmd_rx));
+        tokio::spawn(server.run());
+        (path, shutdown)
+    }
+
+    async fn next_submit(client: &mut GasSaverClient) -> (u64, u64) {
+        loop {
+            let record = client.next_record().await.unwrap().unwrap();
+            if let SchedulerDecision::Submit { tx_id, nonce, .. } = record.decision {
+                return (tx_id, nonce);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_submit_over_the_socket() {
+        let (path, shutdown) = start("submit");
+        let mut client = GasSaverClient::connect(&path).await.unwrap();
+        let mut watcher = GasSaverClient::connect(&path).await.unwrap();
+        // each subscribes before its own traffic, so both see the decision
+        watcher.subscribe().await.unwrap();
+        watcher.submit(request(1)).await.unwrap();
+        client.subscribe().await.unwrap();
+        client
+            .send_event(GasEvent::BaseFeeUpdate {
+                base_fee: 50,
+                timestamp: 0,
+            })
+            .await
+            .unwrap();
+        assert_eq!(next_submit(&mut client).await, (1, 0));
+        // every subscriber gets the records
+        assert_eq!(next_submit(&mut watcher).await, (1, 0));
+
+        // a client that hung up is forgotten; the rest carry on
+        drop(watcher);
+        let mut late = GasSaverClient::connect(&path).await.unwrap();
+        late.subscribe().await.unwrap();
+        // a frame that isn't ours is skipped without dropping the client
+        late.sender.send(RawFrame(vec![0xFF; 3])).await.unwrap();
+        late.submit(request(2)).await.unwrap();
+        client
+            .send_event(GasEvent::BaseFeeUpdate {
+                base_fee: 50,
+                timestamp: 12,
+            })
+            .await
+            .unwrap();
+        assert_eq!(next_submit(&mut late).await, (2, 1));
+        assert_eq!(next_submit(&mut client).await, (2, 1));
+
+        // shutting down hangs up on everyone and takes the socket away
+        shutdown.cancel();
+        assert_eq!(client.next_record().await.unwrap(), None);
+        assert_eq!(late.next_record().await.unwrap(), None);
+        assert!(GasSaverClient::connect(&path).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_slow_subscribers_are_disconnected() {
+        let (handle, _req_rx, _cmd_rx) = SchedulerHandle::channel(10);
+        let (event_tx, _event_rx) = mpsc::channel(10);
+        let path = std::env::temp_dir().join(format!("gas_saver_slow_{}.sock", std::process::id()));
+        let server = IpcServer::bind(&path, handle, event_tx)
+            .unwrap()
+            .with_client_buffer(1);
+        let sink = server.sink();
+        tokio::spawn(server.run());
+        let mut client = GasSaverClient::connect(&path).await.unwrap();
+        client.subscribe().await.unwrap();
+        while sink.subscribers.lock().is_empty() {
+            tokio::task::yield_now().await;
+        }
+
+        let record = DecisionRecord::from(SchedulerDecision::Drop {
+            tx_id: 1,
+            reason: "cancelled".into(),
+        });
+        // the writer may take the first off the buffer before the rest arrive
+        for _ in 0..100 {
+            sink.deliver_record(record.clone()).await.unwrap();
+        }
+        assert!(sink.subscribers.lock().is_empty());
+        let mut received = 0;
+        while let Some(got) = client.next_record().await.unwrap() {
+            assert_eq!(got, record);
+            received += 1;
+        }
+        assert!(received < 100);
+    }
+}
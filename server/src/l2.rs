@@ -0,0 +1,161 @@
+use crate::units::Wei;
+use async_trait::async_trait;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// The `GasPriceOracle` predeploy every OP-stack chain has, which reports the L1
+/// fee parameters and the L1 base fee the chain last saw.
+pub const GAS_PRICE_ORACLE: [u8; 20] = [
+    0x42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0F,
+];
+/// L1 gas added to every tx; OP mainnet's since Bedrock.
+pub const DEFAULT_OVERHEAD: u64 = 188;
+/// The L1 fee multiplier, in units of `10^-DEFAULT_DECIMALS`; OP mainnet's since
+/// Bedrock.
+pub const DEFAULT_SCALAR: u64 = 684_000;
+pub const DEFAULT_DECIMALS: u32 = 6;
+/// L1 gas per calldata byte.
+const ZERO_BYTE_GAS: u64 = 4;
+const NONZERO_BYTE_GAS: u64 = 16;
+/// The oracle pads the unsigned tx with 68 non-zero bytes to stand for its
+/// signature.
+const SIGNATURE_PADDING_GAS: u64 = 68 * NONZERO_BYTE_GAS;
+
+/// What the chain charges for posting a tx's data to L1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct L1FeeParams {
+    /// L1 gas added to every tx.
+    pub overhead: u64,
+    /// Multiplier on the L1 gas price, scaled by `10^decimals`.
+    pub scalar: u64,
+    pub decimals: u32,
+}
+
+impl Default for L1FeeParams {
+    fn default() -> Self {
+        Self {
+            overhead: DEFAULT_OVERHEAD,
+            scalar: DEFAULT_SCALAR,
+            decimals: DEFAULT_DECIMALS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum L1FeeError {
+    /// The node serving the oracle could not be reached.
+    Unavailable(String),
+    /// The oracle answered with something that isn't a fee parameter.
+    Malformed(String),
+}
+
+impl std::fmt::Display for L1FeeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            L1FeeError::Unavailable(msg) => write!(f, "gas price oracle unavailable: {}", msg),
+            L1FeeError::Malformed(msg) => write!(f, "malformed gas price oracle answer: {}", msg),
+        }
+    }
+}
+
+impl std::error::Error for L1FeeError {}
+
+/// Where the L1 fee parameters of an OP-stack chain come from, usually its
+/// `GasPriceOracle` predeploy.
+#[async_trait]
+pub trait L1FeeOracle: Send + Sync {
+    async fn fee_params(&self) -> Result<L1FeeParams, L1FeeError>;
+
+    /// The L1 base fee the chain last saw, in wei.
+    async fn l1_base_fee(&self) -> Result<u64, L1FeeError>;
+}
+
+/// Estimates the L1 data fee an OP-stack chain charges on top of execution gas,
+/// the way its Bedrock `GasPriceOracle` does:
+///
+/// `(4 * zero bytes + 16 * other bytes + overhead + 68 * 16) * l1_base_fee * scalar / 10^decimals`
+///
+/// The bytes counted are the request's calldata; the tx's fixed fields are left
+/// to `overhead`, which is why it is there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct L1DataFeeEstimator {
+    params: L1FeeParams,
+}
+
+impl L1DataFeeEstimator {
+    pub fn new(params: L1FeeParams) -> Self {
+        Self { params }
+    }
+
+    /// An estimator with the parameters `oracle` reports now.
+    pub async fn fetch(oracle: &dyn L1FeeOracle) -> Result<Self, L1FeeError> {
+        Ok(Self::new(oracle.fee_params().await?))
+    }
+
+    pub fn params(&self) -> L1FeeParams {
+        self.params
+    }
+
+    /// L1 gas charged for posting a tx with `calldata`.
+    pub fn l1_gas(&self, calldata: &[u8]) -> u64 {
+        let zeros = calldata.iter().filter(|&&b| b == 0).count() as u64;
+        let nonzeros = calldata.len() as u64 - zeros;
+        zeros * ZERO_BYTE_GAS
+            + nonzeros * NONZERO_BYTE_GAS
+            + self.params.overhead
+            + SIGNATURE_PADDING_GAS
+    }
+
+    /// The data fee for a tx with `calldata` at `l1_base_fee`, in wei.
+    pub fn data_fee(&self, calldata: &[u8], l1_base_fee: u64) -> Wei {
+        let scaled = u128::from(self.l1_gas(calldata))
+            .saturating_mul(u128::from(l1_base_fee))
+            .saturating_mul(u128::from(self.params.scalar));
+        Wei(scaled / 10u128.saturating_pow(self.params.decimals))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_primitives::hex;
+
+    #[test]
+    fn test_data_fees_match_hand_computed_ones() {
+        let estimator = L1DataFeeEstimator::default();
+
+        // a plain transfer: overhead and signature padding only
+        assert_eq!(estimator.l1_gas(&[]), 188 + 1088);
+        assert_eq!(
+            estimator.data_fee(&[], 10_000_000_000),
+            Wei(1276 * 10_000_000_000 * 684_000 / 1_000_000)
+        );
+
+        // transfer(0x1111…11, 1 ether): 30 non-zero bytes and 38 zero ones
+        let transfer = hex::decode(concat!(
+            "a9059cbb",
+            "0000000000000000000000001111111111111111111111111111111111111111",
+            "0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+        ))
+        .unwrap();
+        assert_eq!(estimator.l1_gas(&transfer), 30 * 16 + 38 * 4 + 188 + 1088);
+        // 1908 L1 gas at 20 gwei, times 0.684
+        assert_eq!(
+            estimator.data_fee(&transfer, 20_000_000_000),
+            Wei(26_101_440_000_000)
+        );
+
+        // another chain's scalar, at a sub-gwei L1 base fee
+        let estimator = L1DataFeeEstimator::new(L1FeeParams {
+            overhead: 2100,
+            scalar: 1_000_000,
+            decimals: 6,
+        });
+        assert_eq!(
+            estimator.data_fee(&transfer, 500_000_000),
+            Wei((632 + 2100 + 1088) * 500_000_000)
+        );
+        assert_eq!(estimator.data_fee(&transfer, 0), Wei::ZERO);
+    }
+}
@@ -1,5 +1,43 @@
+#[cfg(feature = "http-api")]
+pub mod api;
+#[cfg(feature = "serde")]
+pub mod audit;
+pub mod balance;
+pub mod channel;
+pub mod codec;
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "executor")]
+pub mod confirmation;
+pub mod envelope;
 pub mod events;
+#[cfg(feature = "executor")]
+pub mod executor;
+#[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+pub mod feeds;
+#[cfg(any(feature = "cbor", feature = "bincode"))]
+pub mod formats;
+pub mod health;
+#[cfg(unix)]
+pub mod ipc;
+pub mod l2;
 pub mod limiter;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod model;
 pub mod nonce;
+#[cfg(feature = "serde")]
+pub mod replay;
+#[cfg(feature = "alloy-rpc")]
+pub mod rpc;
 pub mod scheduler;
+#[cfg(feature = "executor")]
+pub mod signer;
+pub mod sink;
+pub mod source;
+#[cfg(feature = "sqlite")]
+pub mod storage;
+pub mod testkit;
+#[cfg(feature = "tx-build")]
+pub mod tx_build;
+pub mod units;
@@ -1,70 +1,1439 @@
-use std::sync::atomic::{AtomicU64, Ordering};
-use std::time::{Duration, Instant};
+use dashmap::DashMap;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::Mutex;
+use tokio::time::Instant;
 
-pub struct RateLimiter {
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Stands in for "never" where an `Instant` is needed; far enough out to be past
+/// any deadline, near enough not to overflow.
+const FAR_FUTURE: Duration = Duration::from_secs(86_400 * 365 * 30);
+
+/// What `RateLimiter::try_acquire_until` got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcquireOutcome {
+    Acquired,
+    /// No token before the deadline; `earliest` is when one is expected. Nothing
+    /// was taken.
+    WouldMiss {
+        earliest: Instant,
+    },
+}
+
+/// Sustained refill rate and burst capacity of a token bucket.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RateLimiterConfig {
+    /// Tokens added per second, long-run.
+    pub sustained_per_sec: u64,
+    /// Tokens the bucket holds, i.e. how many can go at once after a quiet spell.
+    pub burst: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimiterConfigError {
+    /// The bucket could never hold a token, so every request would be refused.
+    ZeroBurst,
+    /// The bucket would never refill after its first burst.
+    ZeroSustainedRate,
+}
+
+impl std::fmt::Display for LimiterConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LimiterConfigError::ZeroBurst => write!(f, "burst must be at least 1"),
+            LimiterConfigError::ZeroSustainedRate => write!(
+                f,
+                "sustained rate must be at least 1 per second; use RateLimiterConfig::unlimited() for no limit"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for LimiterConfigError {}
+
+impl RateLimiterConfig {
+    pub fn new(sustained_per_sec: u64, burst: u64) -> Result<Self, LimiterConfigError> {
+        let config = Self {
+            sustained_per_sec,
+            burst,
+        };
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// No limit: every request goes through.
+    pub fn unlimited() -> Self {
+        Self {
+            sustained_per_sec: u64::MAX,
+            burst: u64::MAX,
+        }
+    }
+
+    pub fn is_unlimited(&self) -> bool {
+        self.sustained_per_sec == u64::MAX
+    }
+
+    pub fn validate(&self) -> Result<(), LimiterConfigError> {
+        if self.burst == 0 {
+            return Err(LimiterConfigError::ZeroBurst);
+        }
+        if self.sustained_per_sec == 0 {
+            return Err(LimiterConfigError::ZeroSustainedRate);
+        }
+        Ok(())
+    }
+}
+
+/// Time source for a `RateLimiter`, in nanoseconds from any fixed epoch.
+pub trait Clock: Send + Sync {
+    fn now_nanos(&self) -> u64;
+}
+
+/// Tokio's clock, so paused time in tests drives the refill. The default.
+pub struct MonotonicClock {
+    start: Instant,
+}
+
+impl Default for MonotonicClock {
+    fn default() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for MonotonicClock {
+    fn now_nanos(&self) -> u64 {
+        self.start.elapsed().as_nanos() as u64
+    }
+}
+
+/// A clock that only moves when told to. Clones share the same time, so a test
+/// can keep one and hand the other to the limiter.
+#[derive(Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(by.as_nanos() as u64, Ordering::SeqCst);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now_nanos(&self) -> u64 {
+        self.nanos.load(Ordering::SeqCst)
+    }
+}
+
+/// Token bucket. Refill is measured on `C`; the async waits (`acquire`,
+/// `try_acquire_until`) sleep on tokio's timer, so they only make progress with a
+/// clock that moves along with it.
+pub struct RateLimiter<C = MonotonicClock> {
     tokens: AtomicU64,
-    max_tokens: u64,
-    refill_rate: u64,       // tokens per second
-    last_refill: AtomicU64, // Instant as nanos
+    max_tokens: AtomicU64,
+    refill_rate: AtomicU64, // tokens per second
+    clock: C,
+    /// Clock reading up to which tokens have been credited.
+    last_refill: AtomicU64,
+    /// Queues `acquire` callers; tokio's mutex hands itself over in FIFO order, so
+    /// only the waiter at the front sleeps on the clock. A waiter that is dropped
+    /// leaves the queue with its lock future.
+    waiters: Mutex<()>,
+    /// `acquire` callers queued or being served; others take nothing meanwhile.
+    queued: AtomicUsize,
+    counters: Counters,
+}
+
+/// Bookkeeping for `stats`; relaxed, as nothing is ordered by them.
+struct Counters {
+    acquired: AtomicU64,
+    rejected: AtomicU64,
+    refunded: AtomicU64,
+    high_water: AtomicU64,
+    low_water: AtomicU64,
+}
+
+/// From `RateLimiter::stats`; counts since the limiter was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RateLimiterStats {
+    /// Requests granted, whatever their weight.
+    pub acquired: u64,
+    /// Requests refused.
+    pub rejected: u64,
+    /// Tokens handed back through `refund`, including by dropped permits.
+    pub refunded: u64,
+    pub available: u64,
+    /// Most tokens the bucket has held.
+    pub high_water: u64,
+    /// Fewest tokens the bucket has held.
+    pub low_water: u64,
+}
+
+impl RateLimiterStats {
+    /// Share of requests refused, 0.0 if there were none.
+    pub fn rejection_ratio(&self) -> f64 {
+        let total = self.acquired + self.rejected;
+        if total == 0 {
+            0.0
+        } else {
+            self.rejected as f64 / total as f64
+        }
+    }
+
+    /// What happened between `earlier` and this snapshot of the same limiter.
+    pub fn since(&self, earlier: &RateLimiterStats) -> RateLimiterStats {
+        RateLimiterStats {
+            acquired: self.acquired.saturating_sub(earlier.acquired),
+            rejected: self.rejected.saturating_sub(earlier.rejected),
+            refunded: self.refunded.saturating_sub(earlier.refunded),
+            ..*self
+        }
+    }
 }
 
 impl RateLimiter {
+    /// Shorthand for `from_config` that panics on an invalid rate or burst; meant
+    /// for values fixed in code. Anything read from config should go through
+    /// `from_config`.
     pub fn new(rate: u64, max: u64) -> Self {
+        let config = RateLimiterConfig {
+            sustained_per_sec: rate,
+            burst: max,
+        };
+        Self::from_config(config).unwrap_or_else(|e| panic!("invalid rate limiter: {e}"))
+    }
+
+    pub fn from_config(config: RateLimiterConfig) -> Result<Self, LimiterConfigError> {
+        Self::with_clock(config, MonotonicClock::default())
+    }
+
+    /// A bucket that never refills, for tests that run out of tokens on purpose.
+    #[cfg(test)]
+    pub(crate) fn without_refill(max: u64) -> Self {
+        Self::build(0, max, MonotonicClock::default())
+    }
+}
+
+impl<C: Clock> RateLimiter<C> {
+    /// `from_config` on a clock of the caller's choosing.
+    pub fn with_clock(config: RateLimiterConfig, clock: C) -> Result<Self, LimiterConfigError> {
+        config.validate()?;
+        Ok(Self::build(config.sustained_per_sec, config.burst, clock))
+    }
+
+    fn build(rate: u64, max: u64, clock: C) -> Self {
         Self {
             tokens: AtomicU64::new(max),
-            max_tokens: max,
-            refill_rate: rate,
-            last_refill: AtomicU64::new(Instant::now().elapsed().as_nanos() as u64),
+            max_tokens: AtomicU64::new(max),
+            refill_rate: AtomicU64::new(rate),
+            last_refill: AtomicU64::new(clock.now_nanos()),
+            clock,
+            waiters: Mutex::new(()),
+            queued: AtomicUsize::new(0),
+            counters: Counters {
+                acquired: AtomicU64::new(0),
+                rejected: AtomicU64::new(0),
+                refunded: AtomicU64::new(0),
+                high_water: AtomicU64::new(max),
+                low_water: AtomicU64::new(max),
+            },
+        }
+    }
+
+    pub fn stats(&self) -> RateLimiterStats {
+        let counters = &self.counters;
+        RateLimiterStats {
+            acquired: counters.acquired.load(Ordering::Relaxed),
+            rejected: counters.rejected.load(Ordering::Relaxed),
+            refunded: counters.refunded.load(Ordering::Relaxed),
+            available: self.available(),
+            high_water: counters.high_water.load(Ordering::Relaxed),
+            low_water: counters.low_water.load(Ordering::Relaxed),
         }
     }
 
     pub fn check_and_consume(&self) -> bool {
+        self.check_and_consume_weight(1)
+    }
+
+    /// Tokens per second; `u64::MAX` is no limit.
+    pub fn current_rate(&self) -> u64 {
+        self.refill_rate.load(Ordering::SeqCst)
+    }
+
+    pub fn capacity(&self) -> u64 {
+        self.max_tokens.load(Ordering::SeqCst)
+    }
+
+    /// Changes the refill rate from now on. Time up to now is credited at the old
+    /// rate first; time toward a token that hadn't accrued yet is dropped.
+    pub fn set_rate(&self, tokens_per_sec: u64) {
+        loop {
+            // read before the rate: a refill that sees the new rate also sees the
+            // moved `last_refill`, so none credits old time at the new rate
+            let last = self.last_refill.load(Ordering::SeqCst);
+            let rate = self.current_rate() as u128;
+            let now = self.clock.now_nanos();
+            let owed = ((now.saturating_sub(last) as u128).saturating_mul(rate)
+                / NANOS_PER_SEC as u128)
+                .min(self.capacity() as u128) as u64;
+            if self
+                .last_refill
+                .compare_exchange(last, now, Ordering::SeqCst, Ordering::SeqCst)
+                .is_ok()
+            {
+                self.add_tokens(owed);
+                self.refill_rate.store(tokens_per_sec, Ordering::SeqCst);
+                return;
+            }
+        }
+    }
+
+    /// Changes how many tokens the bucket holds. A bucket holding more than `max`
+    /// is cut down to it; a larger one fills up at the refill rate.
+    pub fn set_capacity(&self, max: u64) {
+        self.max_tokens.store(max, Ordering::SeqCst);
+        if let Ok(previous) =
+            self.tokens
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                    Some(current.min(max))
+                })
+        {
+            self.counters
+                .low_water
+                .fetch_min(previous.min(max), Ordering::Relaxed);
+        }
+    }
+
+    /// Waits until a token is available and takes it. Waiters are served strictly
+    /// in the order they arrived, and while any are waiting the non-waiting calls
+    /// (`check_and_consume` and friends) are refused, so a caller polling in a loop
+    /// can't starve them. Never completes if the bucket stays empty for good: a
+    /// zero refill rate or capacity.
+    pub async fn acquire(&self) {
+        self.queued.fetch_add(1, Ordering::SeqCst);
+        let _slot = QueueSlot(&self.queued);
+        let _turn = self.waiters.lock().await;
+        while !self.take(1) {
+            match self.until_next_token() {
+                Some(wait) => tokio::time::sleep(wait).await,
+                None => std::future::pending().await,
+            }
+        }
+    }
+
+    /// `acquire`, giving up after `timeout`. Returns false without taking a token
+    /// if none came in time.
+    pub async fn acquire_timeout(&self, timeout: Duration) -> bool {
+        tokio::time::timeout(timeout, self.acquire()).await.is_ok()
+    }
+
+    /// Takes a token if one accrues by `deadline`, waiting for it if need be. If
+    /// none is expected in time it returns at once with the estimate instead, so the
+    /// caller can plan around the miss. The estimate assumes nobody else takes
+    /// tokens first; if someone does while this waits, it re-estimates. It doesn't
+    /// queue: `acquire` callers waiting at the same time go first.
+    pub async fn try_acquire_until(&self, deadline: Instant) -> AcquireOutcome {
+        loop {
+            if self.check_and_consume() {
+                return AcquireOutcome::Acquired;
+            }
+            let earliest = Instant::now() + self.until_next_token().unwrap_or(FAR_FUTURE);
+            if earliest > deadline {
+                return AcquireOutcome::WouldMiss { earliest };
+            }
+            tokio::time::sleep_until(earliest).await;
+        }
+    }
+
+    /// How long until the next token accrues; None if none ever will.
+    fn until_next_token(&self) -> Option<Duration> {
+        let rate = self.current_rate();
+        if rate == 0 || self.capacity() == 0 {
+            return None;
+        }
+        let per_token_ns = NANOS_PER_SEC.div_ceil(rate);
+        let accrued_ns = self
+            .clock
+            .now_nanos()
+            .saturating_sub(self.last_refill.load(Ordering::SeqCst));
+        Some(Duration::from_nanos(
+            per_token_ns.saturating_sub(accrued_ns).max(1),
+        ))
+    }
+
+    /// Tokens that could be taken right now, for planning a batch before taking it
+    /// with `check_and_consume_weight`. Other users may take them in between.
+    pub fn available(&self) -> u64 {
+        if self.is_unlimited() {
+            return u64::MAX;
+        }
         self.refill();
+        self.tokens.load(Ordering::SeqCst)
+    }
+
+    /// Takes `weight` tokens at once or none at all. A weight above `max_tokens`
+    /// could never be paid in full, so it goes through only when the bucket is full
+    /// and then drains it; until then it is refused like any other. Refused while
+    /// `acquire` callers are waiting.
+    pub fn check_and_consume_weight(&self, weight: u64) -> bool {
+        if self.queued.load(Ordering::SeqCst) > 0 && !self.is_unlimited() {
+            self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        self.take(weight)
+    }
 
+    fn take(&self, weight: u64) -> bool {
+        if self.is_unlimited() {
+            self.counters.acquired.fetch_add(1, Ordering::Relaxed);
+            return true;
+        }
+        self.refill();
+        let max = self.capacity();
+        let take = |current: u64| {
+            if current >= weight {
+                Some(current - weight)
+            } else if weight > max && max > 0 && current == max {
+                Some(0)
+            } else {
+                None
+            }
+        };
+        // each attempt decides on the exact count it replaces, so concurrent
+        // consumers of any weight never see a half-applied update
+        match self
+            .tokens
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, take)
+        {
+            Ok(previous) => {
+                self.counters.acquired.fetch_add(1, Ordering::Relaxed);
+                let left = take(previous).unwrap_or(0);
+                self.counters.low_water.fetch_min(left, Ordering::Relaxed);
+                true
+            }
+            Err(_) => {
+                self.counters.rejected.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+        }
+    }
+
+    /// Returns `n` tokens taken for something that then didn't happen. The bucket
+    /// still never holds more than `max_tokens`.
+    pub fn refund(&self, n: u64) {
+        self.counters.refunded.fetch_add(n, Ordering::Relaxed);
+        self.add_tokens(n);
+    }
+
+    /// Takes a token now if there is one. It goes back to the bucket when the
+    /// permit is dropped, unless the permit was committed first.
+    pub fn try_acquire(&self) -> Option<Permit<'_, C>> {
+        // built only on success: dropping a permit refunds
+        if self.check_and_consume() {
+            Some(Permit {
+                limiter: self,
+                committed: false,
+            })
+        } else {
+            None
+        }
+    }
+
+    fn is_unlimited(&self) -> bool {
+        self.current_rate() == u64::MAX
+    }
+
+    /// Adds `n` tokens, never past `max_tokens`.
+    fn add_tokens(&self, n: u64) {
+        let max = self.capacity();
+        let add = |current: u64| current.saturating_add(n).min(max);
+        if let Ok(previous) =
+            self.tokens
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+                    Some(add(current))
+                })
+        {
+            self.counters
+                .high_water
+                .fetch_max(add(previous), Ordering::Relaxed);
+        }
+    }
+
+    /// Credits the tokens earned since the last refill. Accrual is continuous: the
+    /// time left over after the last whole token counts toward the next one.
+    fn refill(&self) {
+        let now = self.clock.now_nanos();
         loop {
-            let current = self.tokens.load(Ordering::SeqCst);
-            if current == 0 {
-                return false;
+            // `last_refill` before the rate; see `set_rate`
+            let last = self.last_refill.load(Ordering::SeqCst);
+            let rate = self.current_rate() as u128;
+            if rate == 0 {
+                return;
             }
+            let max = self.capacity();
+            let owed =
+                (now.saturating_sub(last) as u128).saturating_mul(rate) / NANOS_PER_SEC as u128;
+            if owed == 0 {
+                return;
+            }
+            // a full bucket's worth leaves nothing to carry over
+            let (tokens_to_add, credited) = if owed >= max as u128 {
+                (max, now)
+            } else {
+                let spent_ns = (owed * NANOS_PER_SEC as u128).div_ceil(rate) as u64;
+                (owed as u64, last + spent_ns)
+            };
             if self
-                .tokens
-                .compare_exchange(current, current - 1, Ordering::SeqCst, Ordering::SeqCst)
+                .last_refill
+                .compare_exchange(last, credited, Ordering::SeqCst, Ordering::SeqCst)
                 .is_ok()
             {
-                return true;
+                self.add_tokens(tokens_to_add);
+                return;
             }
+            // someone else credited part of the interval; go again from where they
+            // stopped, so time up to `now` is in the bucket before the caller looks
         }
     }
+}
 
-    fn refill(&self) {
-        let now = Instant::now().elapsed().as_nanos() as u64;
-        let last = self.last_refill.load(Ordering::SeqCst);
-        let elapsed_ns = now.saturating_sub(last);
-
-        if elapsed_ns > 1_000_000_000 {
-            // at least 1 second
-            let tokens_to_add = (elapsed_ns / 1_000_000_000) * self.refill_rate;
-            if tokens_to_add > 0 {
-                if self
-                    .last_refill
-                    .compare_exchange(last, now, Ordering::SeqCst, Ordering::SeqCst)
-                    .is_ok()
-                {
-                    let mut current = self.tokens.load(Ordering::SeqCst);
-                    loop {
-                        let next = (current + tokens_to_add).min(self.max_tokens);
-                        match self.tokens.compare_exchange(
-                            current,
-                            next,
-                            Ordering::SeqCst,
-                            Ordering::SeqCst,
-                        ) {
-                            Ok(_) => break,
-                            Err(actual) => current = actual,
+/// An `acquire` caller's place in the count of queued waiters, given up on drop so
+/// a cancelled waiter stops holding others off.
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A token taken with `RateLimiter::try_acquire`, refunded on drop unless committed.
+#[must_use = "dropping a permit refunds its token"]
+pub struct Permit<'a, C: Clock = MonotonicClock> {
+    limiter: &'a RateLimiter<C>,
+    committed: bool,
+}
+
+impl<C: Clock> Permit<'_, C> {
+    /// Keeps the token spent: the operation it paid for went ahead.
+    pub fn commit(mut self) {
+        self.committed = true;
+    }
+}
+
+impl<C: Clock> Drop for Permit<'_, C> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.limiter.refund(1);
+        }
+    }
+}
+
+/// What the scheduler needs from a submission limiter. `RateLimiter` is the real
+/// one; `NoopLimiter` and `ScriptedLimiter` stand in for simulations and tests.
+pub trait Limiter: Send + Sync {
+    fn check_and_consume(&self) -> bool {
+        self.check_and_consume_weight(1)
+    }
+
+    /// Takes `weight` tokens or nothing.
+    fn check_and_consume_weight(&self, weight: u64) -> bool;
+
+    /// Returns tokens taken for something that didn't happen.
+    fn refund(&self, n: u64);
+
+    /// Tokens that could be taken right now.
+    fn available(&self) -> u64;
+
+    fn stats(&self) -> RateLimiterStats;
+
+    /// Limiters without a rate ignore this.
+    fn set_rate(&self, _tokens_per_sec: u64) {}
+
+    /// Limiters without a capacity ignore this.
+    fn set_capacity(&self, _max: u64) {}
+}
+
+impl<C: Clock> Limiter for RateLimiter<C> {
+    fn check_and_consume_weight(&self, weight: u64) -> bool {
+        RateLimiter::check_and_consume_weight(self, weight)
+    }
+
+    fn refund(&self, n: u64) {
+        RateLimiter::refund(self, n)
+    }
+
+    fn available(&self) -> u64 {
+        RateLimiter::available(self)
+    }
+
+    fn stats(&self) -> RateLimiterStats {
+        RateLimiter::stats(self)
+    }
+
+    fn set_rate(&self, tokens_per_sec: u64) {
+        RateLimiter::set_rate(self, tokens_per_sec)
+    }
+
+    fn set_capacity(&self, max: u64) {
+        RateLimiter::set_capacity(self, max)
+    }
+}
+
+/// Allows everything and only counts, for simulations and backtests where
+/// submission pacing doesn't matter.
+#[derive(Default)]
+pub struct NoopLimiter {
+    acquired: AtomicU64,
+    refunded: AtomicU64,
+}
+
+impl NoopLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Limiter for NoopLimiter {
+    fn check_and_consume_weight(&self, _weight: u64) -> bool {
+        self.acquired.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    fn refund(&self, n: u64) {
+        self.refunded.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn available(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn stats(&self) -> RateLimiterStats {
+        RateLimiterStats {
+            acquired: self.acquired.load(Ordering::Relaxed),
+            refunded: self.refunded.load(Ordering::Relaxed),
+            available: u64::MAX,
+            high_water: u64::MAX,
+            low_water: u64::MAX,
+            ..Default::default()
+        }
+    }
+}
+
+/// Answers consume calls from a script, in order, whatever their weight; allows
+/// everything once the script runs out. For tests that need a given call refused
+/// without depending on timing.
+pub struct ScriptedLimiter {
+    script: parking_lot::Mutex<std::collections::VecDeque<bool>>,
+    acquired: AtomicU64,
+    rejected: AtomicU64,
+    refunded: AtomicU64,
+}
+
+impl ScriptedLimiter {
+    pub fn new(script: impl IntoIterator<Item = bool>) -> Self {
+        Self {
+            script: parking_lot::Mutex::new(script.into_iter().collect()),
+            acquired: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            refunded: AtomicU64::new(0),
+        }
+    }
+
+    /// Allows every call but the `n`th (counting from 1).
+    pub fn deny_call(n: usize) -> Self {
+        Self::new((1..=n).map(|call| call != n))
+    }
+
+    /// Consume calls answered so far.
+    pub fn calls(&self) -> u64 {
+        self.acquired.load(Ordering::Relaxed) + self.rejected.load(Ordering::Relaxed)
+    }
+}
+
+impl Limiter for ScriptedLimiter {
+    fn check_and_consume_weight(&self, _weight: u64) -> bool {
+        let allowed = self.script.lock().pop_front().unwrap_or(true);
+        let counter = if allowed {
+            &self.acquired
+        } else {
+            &self.rejected
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        allowed
+    }
+
+    fn refund(&self, n: u64) {
+        self.refunded.fetch_add(n, Ordering::Relaxed);
+    }
+
+    fn available(&self) -> u64 {
+        u64::MAX
+    }
+
+    fn stats(&self) -> RateLimiterStats {
+        RateLimiterStats {
+            acquired: self.acquired.load(Ordering::Relaxed),
+            rejected: self.rejected.load(Ordering::Relaxed),
+            refunded: self.refunded.load(Ordering::Relaxed),
+            available: u64::MAX,
+            high_water: u64::MAX,
+            low_water: u64::MAX,
+        }
+    }
+}
+
+/// One token bucket per key, e.g. per sender, so a busy key can only spend its own
+/// budget. Buckets are created on first use.
+pub struct KeyedRateLimiter<K> {
+    buckets: DashMap<K, RateLimiter>,
+    limit: RateLimiterConfig,
+}
+
+impl<K: Eq + Hash + Clone> KeyedRateLimiter<K> {
+    /// Panics if `limit` is invalid; see `RateLimiterConfig::validate`.
+    pub fn new(limit: RateLimiterConfig) -> Self {
+        if let Err(e) = limit.validate() {
+            panic!("invalid per-key rate limit: {e}");
+        }
+        Self {
+            buckets: DashMap::new(),
+            limit,
+        }
+    }
+
+    pub fn check_and_consume(&self, key: &K) -> bool {
+        self.check_and_consume_weight(key, 1)
+    }
+
+    /// Takes `weight` tokens from `key`'s bucket, or none at all.
+    pub fn check_and_consume_weight(&self, key: &K, weight: u64) -> bool {
+        self.bucket(key).check_and_consume_weight(weight)
+    }
+
+    /// Returns `weight` tokens to `key`'s bucket.
+    pub fn refund(&self, key: &K, weight: u64) {
+        if let Some(bucket) = self.buckets.get(key) {
+            bucket.refund(weight);
+        }
+    }
+
+    /// Tokens `key` could take right now.
+    pub fn available(&self, key: &K) -> u64 {
+        self.buckets
+            .get(key)
+            .map_or(self.limit.burst, |bucket| bucket.available())
+    }
+
+    /// Forgets keys whose bucket has refilled completely; a fresh bucket behaves
+    /// the same, so nothing is lost. Returns how many were dropped.
+    pub fn remove_idle(&self) -> usize {
+        let before = self.buckets.len();
+        self.buckets
+            .retain(|_, bucket| bucket.available() < self.limit.burst);
+        before - self.buckets.len()
+    }
+
+    /// Keys with a bucket.
+    pub fn len(&self) -> usize {
+        self.buckets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buckets.is_empty()
+    }
+
+    fn bucket(&self, key: &K) -> dashmap::mapref::one::RefMut<'_, K, RateLimiter> {
+        self.buckets.entry(key.clone()).or_insert_with(|| {
+            let limit = self.limit;
+            RateLimiter::build(
+                limit.sustained_per_sec,
+                limit.burst,
+                MonotonicClock::default(),
+            )
+        })
+    }
+}
+
+/// Per-key buckets under one shared parent bucket: a key is held to its own limit
+/// and all keys together to the parent's. A token is only spent if both grant it.
+pub struct HierarchicalLimiter<K> {
+    parent: Arc<dyn Limiter>,
+    children: KeyedRateLimiter<K>,
+}
+
+impl<K: Eq + Hash + Clone> HierarchicalLimiter<K> {
+    /// Panics if `child` is invalid; see `RateLimiterConfig::validate`.
+    pub fn new(parent: Arc<dyn Limiter>, child: RateLimiterConfig) -> Self {
+        Self {
+            parent,
+            children: KeyedRateLimiter::new(child),
+        }
+    }
+
+    pub fn parent(&self) -> &Arc<dyn Limiter> {
+        &self.parent
+    }
+
+    pub fn check_and_consume(&self, key: &K) -> bool {
+        self.check_and_consume_weight(key, 1)
+    }
+
+    /// Takes `weight` tokens from `key`'s bucket and then the parent, or from
+    /// neither: the child's tokens go back if the parent refuses.
+    pub fn check_and_consume_weight(&self, key: &K, weight: u64) -> bool {
+        let child = self.children.bucket(key);
+        if !child.check_and_consume_weight(weight) {
+            return false;
+        }
+        if !self.parent.check_and_consume_weight(weight) {
+            child.refund(weight);
+            return false;
+        }
+        true
+    }
+
+    /// Returns `weight` tokens to `key`'s bucket and the parent.
+    pub fn refund(&self, key: &K, weight: u64) {
+        self.children.refund(key, weight);
+        self.parent.refund(weight);
+    }
+
+    /// Tokens `key` could take right now, the parent's limit included.
+    pub fn available(&self, key: &K) -> u64 {
+        self.children.available(key).min(self.parent.available())
+    }
+
+    /// See `KeyedRateLimiter::remove_idle`.
+    pub fn remove_idle(&self) -> usize {
+        self.children.remove_idle()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_weighted_consume_is_all_or_nothing() {
+        let limiter = RateLimiter::without_refill(10);
+        assert!(limiter.check_and_consume_weight(4));
+        assert!(!limiter.check_and_consume_weight(7));
+        assert!(limiter.check_and_consume_weight(6));
+        assert!(!limiter.check_and_consume_weight(1));
+        assert!(limiter.check_and_consume_weight(0));
+    }
+
+    #[test]
+    fn test_oversized_cost_needs_full_bucket() {
+        let limiter = RateLimiter::without_refill(10);
+        assert!(limiter.check_and_consume_weight(1));
+        assert!(!limiter.check_and_consume_weight(50));
+
+        let limiter = RateLimiter::without_refill(10);
+        assert!(limiter.check_and_consume_weight(50));
+        assert!(!limiter.check_and_consume());
+    }
+
+    #[test]
+    fn test_concurrent_weights_never_overspend() {
+        let started = std::time::Instant::now();
+        let limiter = Arc::new(RateLimiter::new(10_000, 100));
+        let consumed = Arc::new(AtomicU64::new(0));
+        let threads: Vec<_> = (1..=8u64)
+            .map(|t| {
+                let limiter = limiter.clone();
+                let consumed = consumed.clone();
+                std::thread::spawn(move || {
+                    for i in 0..20_000u64 {
+                        let weight = (t + i) % 7 + 1;
+                        if limiter.check_and_consume_weight(weight) {
+                            consumed.fetch_add(weight, Ordering::SeqCst);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let refilled = (started.elapsed().as_secs_f64() * 10_000.0).ceil() as u64;
+        let consumed = consumed.load(Ordering::SeqCst);
+        assert!(consumed > 0);
+        assert!(
+            consumed <= 100 + refilled,
+            "consumed {consumed} of at most {}",
+            100 + refilled
+        );
+        assert!(limiter.available() <= 100);
+    }
+
+    #[test]
+    fn test_available_tracks_consumption() {
+        let limiter = RateLimiter::without_refill(10);
+        assert_eq!(limiter.available(), 10);
+        assert!(limiter.check_and_consume_weight(7));
+        assert_eq!(limiter.available(), 3);
+        assert!(!limiter.check_and_consume_weight(4));
+        assert_eq!(limiter.available(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tokens_accrue_continuously() {
+        let limiter = RateLimiter::new(10, 20);
+        assert!(limiter.check_and_consume_weight(20));
+        assert!(!limiter.check_and_consume());
+
+        for (advance_ms, expected) in [(100, 1), (450, 5), (650, 12)] {
+            tokio::time::advance(Duration::from_millis(advance_ms)).await;
+            assert_eq!(limiter.available(), expected);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_partial_token_time_carries_over() {
+        let limiter = RateLimiter::new(3, 3);
+        assert!(limiter.check_and_consume_weight(3));
+
+        // one token every 333ms: 500ms buys one with 167ms toward the next
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(limiter.check_and_consume());
+        assert!(!limiter.check_and_consume());
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert!(limiter.check_and_consume());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_sleeps_until_a_token_accrues() {
+        let limiter = RateLimiter::new(10, 1);
+        let started = Instant::now();
+        limiter.acquire().await;
+        assert_eq!(started.elapsed(), Duration::ZERO);
+        limiter.acquire().await;
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
+        assert!(!limiter.check_and_consume());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_wakes_waiters_in_order() {
+        let limiter = Arc::new(RateLimiter::new(1, 1));
+        assert!(limiter.check_and_consume());
+        let started = Instant::now();
+        let served = Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        let mut waiters = Vec::new();
+        for id in 0..3 {
+            let (limiter, served) = (limiter.clone(), served.clone());
+            waiters.push(tokio::spawn(async move {
+                limiter.acquire().await;
+                served.lock().push((id, started.elapsed().as_secs()));
+            }));
+            // let it join the queue before the next one
+            tokio::task::yield_now().await;
+        }
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*served.lock(), vec![(0, 1), (1, 2), (2, 3)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waiters_served_in_order_despite_hot_polling() {
+        let limiter = Arc::new(RateLimiter::new(1, 1));
+        assert!(limiter.check_and_consume());
+        let started = Instant::now();
+        let served = Arc::new(parking_lot::Mutex::new(Vec::new()));
+
+        let mut waiters = Vec::new();
+        for id in 0..3 {
+            let (limiter, served) = (limiter.clone(), served.clone());
+            waiters.push(tokio::spawn(async move {
+                limiter.acquire().await;
+                served.lock().push((id, started.elapsed().as_secs()));
+            }));
+            tokio::task::yield_now().await;
+        }
+        // a caller that never waits, retrying as fast as it can
+        let poller = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                let mut taken = 0;
+                for _ in 0..10_000 {
+                    if limiter.check_and_consume() {
+                        taken += 1;
+                    }
+                    tokio::time::sleep(Duration::from_millis(1)).await;
+                }
+                taken
+            })
+        };
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*served.lock(), vec![(0, 1), (1, 2), (2, 3)]);
+        // once the queue is empty the poller gets its turn
+        assert!(poller.await.unwrap() > 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancelled_waiter_absorbs_nothing() {
+        let limiter = Arc::new(RateLimiter::new(1, 1));
+        assert!(limiter.check_and_consume());
+        let started = Instant::now();
+
+        let spawn_waiter = |limiter: Arc<RateLimiter>| {
+            tokio::spawn(async move {
+                limiter.acquire().await;
+                started.elapsed()
+            })
+        };
+        let first = spawn_waiter(limiter.clone());
+        tokio::task::yield_now().await;
+        let cancelled = spawn_waiter(limiter.clone());
+        tokio::task::yield_now().await;
+        let last = spawn_waiter(limiter.clone());
+        tokio::task::yield_now().await;
+
+        cancelled.abort();
+        assert!(cancelled.await.unwrap_err().is_cancelled());
+        assert_eq!(first.await.unwrap(), Duration::from_secs(1));
+        assert_eq!(last.await.unwrap(), Duration::from_secs(2));
+        assert_eq!(limiter.queued.load(Ordering::SeqCst), 0);
+        assert!(!limiter.check_and_consume());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_acquire_timeout_takes_nothing() {
+        let limiter = RateLimiter::new(1, 1);
+        assert!(limiter.check_and_consume());
+
+        assert!(!limiter.acquire_timeout(Duration::from_millis(500)).await);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(limiter.available(), 1);
+        assert!(limiter.acquire_timeout(Duration::from_millis(500)).await);
+        assert_eq!(limiter.available(), 0);
+    }
+
+    #[test]
+    fn test_config_rejects_zero_rate_or_burst() {
+        assert_eq!(
+            RateLimiterConfig::new(10, 0),
+            Err(LimiterConfigError::ZeroBurst)
+        );
+        assert_eq!(
+            RateLimiterConfig::new(0, 20),
+            Err(LimiterConfigError::ZeroSustainedRate)
+        );
+        assert_eq!(
+            RateLimiterConfig::new(0, 0),
+            Err(LimiterConfigError::ZeroBurst)
+        );
+        // fields read from a file skip `new`, so `from_config` checks again
+        #[cfg(feature = "serde")]
+        {
+            let typo: RateLimiterConfig =
+                serde_json::from_str(r#"{"sustained_per_sec":10,"burst":0}"#).unwrap();
+            assert!(matches!(
+                RateLimiter::from_config(typo),
+                Err(LimiterConfigError::ZeroBurst)
+            ));
+        }
+
+        let config = RateLimiterConfig::new(1, 1).unwrap();
+        assert_eq!(RateLimiter::from_config(config).unwrap().capacity(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "invalid rate limiter: burst must be at least 1")]
+    fn test_new_panics_on_zero_burst() {
+        RateLimiter::new(10, 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unlimited_never_refuses() {
+        let config = RateLimiterConfig::unlimited();
+        assert!(config.is_unlimited());
+        let limiter = RateLimiter::from_config(config).unwrap();
+        for _ in 0..3 {
+            assert!(limiter.check_and_consume_weight(u64::MAX));
+        }
+        assert!(limiter.try_acquire().is_some());
+        assert_eq!(limiter.available(), u64::MAX);
+        assert!(limiter.acquire_timeout(Duration::ZERO).await);
+    }
+
+    fn manual(rate: u64, burst: u64) -> (RateLimiter<ManualClock>, ManualClock) {
+        let clock = ManualClock::new();
+        let config = RateLimiterConfig::new(rate, burst).unwrap();
+        (
+            RateLimiter::with_clock(config, clock.clone()).unwrap(),
+            clock,
+        )
+    }
+
+    #[test]
+    fn test_manual_clock_drives_refill() {
+        let (limiter, clock) = manual(10, 20);
+        assert!(limiter.check_and_consume_weight(20));
+        clock.advance(Duration::from_millis(250));
+        assert_eq!(limiter.available(), 2);
+        assert_eq!(limiter.until_next_token(), Some(Duration::from_millis(50)));
+        clock.advance(Duration::from_millis(50));
+        assert_eq!(limiter.available(), 3);
+        clock.advance(Duration::from_secs(10));
+        assert_eq!(limiter.available(), 20);
+    }
+
+    #[test]
+    fn test_contended_refill_mints_every_token() {
+        let (limiter, clock) = manual(100, 10_000);
+        assert!(limiter.check_and_consume_weight(10_000));
+        let limiter = Arc::new(limiter);
+        // a simulated minute in 1ms steps, shared between eight threads that
+        // each move the clock and then race for a token
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let (limiter, clock) = (limiter.clone(), clock.clone());
+                std::thread::spawn(move || {
+                    let mut granted = 0u64;
+                    for _ in 0..7_500 {
+                        clock.advance(Duration::from_millis(1));
+                        if limiter.check_and_consume() {
+                            granted += 1;
                         }
                     }
+                    granted
+                })
+            })
+            .collect();
+        let granted: u64 = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        let minted = 100 * 60;
+        assert!(
+            (granted + limiter.available()).abs_diff(minted) <= 1,
+            "granted {granted}, {} left, {minted} minted",
+            limiter.available()
+        );
+    }
+
+    #[test]
+    fn test_manual_clock_burst() {
+        let (limiter, clock) = manual(1, 5);
+        for _ in 0..5 {
+            assert!(limiter.check_and_consume());
+        }
+        assert!(!limiter.check_and_consume());
+        clock.advance(Duration::from_secs(1));
+        assert!(limiter.check_and_consume());
+        assert!(!limiter.check_and_consume());
+
+        // however long it idles, one burst is all it saves up
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(limiter.available(), 5);
+        assert!(limiter.check_and_consume_weight(5));
+        assert!(!limiter.check_and_consume());
+    }
+
+    #[test]
+    fn test_manual_clock_capacity_clamp() {
+        let (limiter, clock) = manual(10, 10);
+        limiter.set_capacity(3);
+        assert_eq!(limiter.available(), 3);
+        clock.advance(Duration::from_secs(1));
+        assert_eq!(limiter.available(), 3);
+
+        limiter.set_capacity(10);
+        clock.advance(Duration::from_millis(500));
+        assert_eq!(limiter.available(), 8);
+    }
+
+    #[test]
+    fn test_stats_count_a_scripted_sequence() {
+        let (limiter, clock) = manual(1, 5);
+        assert!(limiter.check_and_consume_weight(3));
+        assert!(!limiter.check_and_consume_weight(4));
+        assert!(limiter.check_and_consume_weight(2));
+        assert!(!limiter.check_and_consume());
+        limiter.refund(1);
+        drop(limiter.try_acquire().unwrap());
+        clock.advance(Duration::from_secs(10));
+
+        assert_eq!(
+            limiter.stats(),
+            RateLimiterStats {
+                acquired: 3,
+                rejected: 2,
+                refunded: 2,
+                available: 5,
+                high_water: 5,
+                low_water: 0,
+            }
+        );
+        assert_eq!(limiter.stats().rejection_ratio(), 0.4);
+    }
+
+    #[test]
+    fn test_scripted_limiter_answers_in_order() {
+        let limiter = ScriptedLimiter::deny_call(3);
+        let answers: Vec<bool> = (0..5).map(|_| limiter.check_and_consume()).collect();
+        assert_eq!(answers, vec![true, true, false, true, true]);
+        assert_eq!(limiter.calls(), 5);
+        assert_eq!(limiter.stats().rejected, 1);
+
+        let noop = NoopLimiter::new();
+        assert!(noop.check_and_consume_weight(u64::MAX));
+        noop.refund(3);
+        assert_eq!((noop.stats().acquired, noop.stats().refunded), (1, 3));
+    }
+
+    #[test]
+    fn test_refund_caps_at_max_tokens() {
+        let limiter = RateLimiter::without_refill(10);
+        assert!(limiter.check_and_consume_weight(4));
+        limiter.refund(3);
+        assert_eq!(limiter.available(), 9);
+        limiter.refund(u64::MAX);
+        assert_eq!(limiter.available(), 10);
+    }
+
+    #[test]
+    fn test_permit_refunds_unless_committed() {
+        let limiter = RateLimiter::without_refill(2);
+        let kept = limiter.try_acquire().unwrap();
+        let abandoned = limiter.try_acquire().unwrap();
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.available(), 0);
+
+        drop(abandoned);
+        assert_eq!(limiter.available(), 1);
+        kept.commit();
+        assert_eq!(limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refunds_and_refills_stay_within_capacity() {
+        let limiter = RateLimiter::new(10, 10);
+        assert!(limiter.check_and_consume_weight(6));
+        tokio::time::advance(Duration::from_millis(300)).await;
+        limiter.refund(6);
+        assert_eq!(limiter.available(), 10);
+
+        assert!(limiter.check_and_consume_weight(2));
+        limiter.refund(2);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(limiter.available(), 10);
+        // time spent full isn't banked for later
+        assert!(limiter.check_and_consume_weight(10));
+        assert!(!limiter.check_and_consume());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_try_acquire_until_waits_when_in_time() {
+        let limiter = RateLimiter::new(10, 1);
+        assert!(limiter.check_and_consume());
+        let started = Instant::now();
+
+        let outcome = limiter
+            .try_acquire_until(started + Duration::from_millis(100))
+            .await;
+        assert_eq!(outcome, AcquireOutcome::Acquired);
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
+        assert_eq!(limiter.available(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_try_acquire_until_reports_a_miss_at_once() {
+        let limiter = RateLimiter::new(10, 1);
+        assert!(limiter.check_and_consume());
+        tokio::time::advance(Duration::from_millis(30)).await;
+        let now = Instant::now();
+
+        let outcome = limiter
+            .try_acquire_until(now + Duration::from_millis(50))
+            .await;
+        assert_eq!(
+            outcome,
+            AcquireOutcome::WouldMiss {
+                earliest: now + Duration::from_millis(70)
+            }
+        );
+        assert_eq!(now.elapsed(), Duration::ZERO);
+        // the token that accrues later is still there for someone else
+        tokio::time::advance(Duration::from_millis(70)).await;
+        assert_eq!(limiter.available(), 1);
+
+        let stopped = RateLimiter::without_refill(0);
+        let deadline = Instant::now() + Duration::from_secs(3600);
+        assert!(matches!(
+            stopped.try_acquire_until(deadline).await,
+            AcquireOutcome::WouldMiss { earliest } if earliest > deadline
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_change_switches_accrual_slope() {
+        let limiter = RateLimiter::new(10, 100);
+        assert!(limiter.check_and_consume_weight(100));
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limiter.available(), 10);
+
+        limiter.set_rate(30);
+        assert_eq!(limiter.current_rate(), 30);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(limiter.available(), 25);
+
+        // 50ms at 30/s is 1.5 tokens: the whole one is credited before the switch
+        tokio::time::advance(Duration::from_millis(50)).await;
+        limiter.set_rate(2);
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert_eq!(limiter.available(), 27);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_raising_a_stopped_rate_mints_no_burst() {
+        let limiter = RateLimiter::without_refill(10);
+        assert!(limiter.check_and_consume_weight(10));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        limiter.set_rate(10);
+        assert_eq!(limiter.available(), 0);
+        tokio::time::advance(Duration::from_millis(300)).await;
+        assert_eq!(limiter.available(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_capacity_change_clamps_tokens() {
+        let limiter = RateLimiter::new(10, 10);
+        limiter.set_capacity(4);
+        assert_eq!(limiter.capacity(), 4);
+        assert_eq!(limiter.available(), 4);
+
+        limiter.set_capacity(8);
+        assert_eq!(limiter.available(), 4);
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert_eq!(limiter.available(), 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_busy_key_spends_only_its_own_budget() {
+        let limiter = KeyedRateLimiter::new(RateLimiterConfig {
+            sustained_per_sec: 1,
+            burst: 2,
+        });
+        let (a, b) = ([0xAA; 20], [0xBB; 20]);
+        assert!(limiter.check_and_consume(&a));
+        assert!(limiter.check_and_consume(&a));
+        assert!(!limiter.check_and_consume(&a));
+        assert_eq!(limiter.available(&b), 2);
+        assert!(limiter.check_and_consume(&b));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        assert!(limiter.check_and_consume(&a));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parent_refusal_refunds_the_child() {
+        let parent = Arc::new(RateLimiter::without_refill(3));
+        let limiter = HierarchicalLimiter::new(
+            parent.clone(),
+            RateLimiterConfig {
+                sustained_per_sec: 1,
+                burst: 2,
+            },
+        );
+        assert!(limiter.check_and_consume_weight(&1u8, 2));
+        assert!(limiter.check_and_consume(&2u8));
+        // key 3 has tokens of its own, but the aggregate is spent
+        assert!(!limiter.check_and_consume(&3u8));
+        assert_eq!(limiter.available(&3u8), 0);
+        assert_eq!(limiter.children.available(&3u8), 2);
+
+        parent.refund(1);
+        assert!(limiter.check_and_consume(&3u8));
+        assert_eq!(limiter.children.available(&3u8), 1);
+        assert_eq!(parent.available(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_parent_and_children_limit_throughput() {
+        let parent = Arc::new(RateLimiter::new(10, 10));
+        let limiter = HierarchicalLimiter::new(
+            parent,
+            RateLimiterConfig {
+                sustained_per_sec: 2,
+                burst: 2,
+            },
+        );
+        // eight keys asking every 10ms for 10s
+        let mut granted = [0u64; 8];
+        for _ in 0..1_000 {
+            for (key, count) in granted.iter_mut().enumerate() {
+                if limiter.check_and_consume(&key) {
+                    *count += 1;
                 }
             }
+            tokio::time::advance(Duration::from_millis(10)).await;
         }
+        let total: u64 = granted.iter().sum();
+        assert!(total <= 10 * 10 + 10, "{total} granted in all");
+        assert!(total >= 10 * 10, "{total} granted in all");
+        for count in granted {
+            assert!(count <= 2 * 10 + 2, "{count} granted to one key");
+        }
+
+        // two keys alone are held to their own limits, well under the parent's
+        let mut granted = [0u64; 2];
+        tokio::time::advance(Duration::from_secs(5)).await;
+        for _ in 0..1_000 {
+            for (key, count) in granted.iter_mut().enumerate() {
+                if limiter.check_and_consume(&key) {
+                    *count += 1;
+                }
+            }
+            tokio::time::advance(Duration::from_millis(10)).await;
+        }
+        assert!(granted.iter().all(|&count| (20..=22).contains(&count)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_keys_are_forgotten() {
+        let limiter = KeyedRateLimiter::new(RateLimiterConfig {
+            sustained_per_sec: 10,
+            burst: 10,
+        });
+        for key in 0..100u32 {
+            assert!(limiter.check_and_consume(&key));
+        }
+        assert_eq!(limiter.len(), 100);
+        assert_eq!(limiter.remove_idle(), 0);
+
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(limiter.check_and_consume_weight(&7, 2));
+        assert_eq!(limiter.remove_idle(), 99);
+        assert_eq!(limiter.len(), 1);
+        assert_eq!(limiter.available(&7), 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_refill_caps_at_max_tokens() {
+        let limiter = RateLimiter::new(5, 10);
+        assert!(limiter.check_and_consume_weight(3));
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert_eq!(limiter.available(), 10);
+        assert!(limiter.check_and_consume_weight(10));
+        assert!(!limiter.check_and_consume());
     }
 }
@@ -1,111 +1,1640 @@
-use gas_saver_eth::events::{GasEvent, TransactionRequest};
-use gas_saver_eth::limiter::RateLimiter;
-use gas_saver_eth::model::GasModel;
-use gas_saver_eth::nonce::NonceManager;
-use gas_saver_eth::scheduler::{Scheduler, SchedulerConfig};
+use clap::parser::ValueSource;
+use clap::{Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+use gas_saver_eth::audit::AuditLog;
+use gas_saver_eth::channel::{InstrumentedSender, instrumented_channel};
+use gas_saver_eth::config::{self, AppConfig};
+use gas_saver_eth::events::{
+    DecisionRecord, GasEvent, SchedulerCommand, SchedulerDecision, TransactionRequest, Urgency,
+};
+use gas_saver_eth::limiter::{Limiter, NoopLimiter, RateLimiter};
+use gas_saver_eth::model::{GasModel, ModelSnapshot};
+use gas_saver_eth::nonce::{ImportPolicy, NonceAllocator, NonceManager, NonceSnapshot};
+use gas_saver_eth::replay;
+use gas_saver_eth::scheduler::{
+    MarketUpdatePolicy, Scheduler, SchedulerConfig, SchedulerHandle, SchedulerSnapshot,
+};
+use gas_saver_eth::sink::{ChannelSink, DecisionSink, RequiredSink};
+use gas_saver_eth::source::{ChannelSource, FeePattern, SyntheticSource};
+use gas_saver_eth::testkit::scripted_request;
+use gas_saver_eth::units::{Gwei, Wei};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, BufReader};
 use tokio::sync::mpsc;
-use tracing::{Level, info};
-use tracing_subscriber;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+use tokio_util::sync::CancellationToken;
+use tracing::{Level, info, warn};
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt().with_max_level(Level::INFO).init();
+#[derive(Parser)]
+#[command(about = "Gas-aware transaction scheduler")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the scheduler against a synthetic fee feed and a scripted set of requests.
+    Simulate(SimulateArgs),
+    /// Run the scheduler on JSON lines read from stdin; decisions go to stdout.
+    Serve(Box<ServeArgs>),
+    /// Backtest the scheduler on recorded base fees and requests, on a virtual
+    /// clock, and print what each request paid against sending it at once.
+    Replay(ReplayArgs),
+}
+
+/// Knobs shared by every mode.
+#[derive(Args)]
+struct SchedulerArgs {
+    /// In gwei.
+    #[arg(long, default_value_t = 50)]
+    target_base_fee: u64,
+    /// Tip ceiling, in gwei.
+    #[arg(long, default_value_t = 2)]
+    max_priority_fee: u64,
+    /// Volatility at which the scheduler enters spike mode, in gwei per block.
+    #[arg(long, default_value_t = 15.0)]
+    spike_threshold_high: f64,
+    /// Volatility at which it leaves spike mode again, in gwei per block.
+    #[arg(long, default_value_t = 10.0)]
+    spike_threshold_low: f64,
+    /// Fall in the base fee, in gwei per block, past which a tx over its fee cap
+    /// waits for the trend.
+    #[arg(long, default_value_t = 1.0)]
+    trend_threshold: f64,
+    /// How submissions are paced.
+    #[arg(long, value_enum, default_value_t = LimiterKind::TokenBucket)]
+    limiter: LimiterKind,
+    /// Limiter refill, in submissions per second.
+    #[arg(long, default_value_t = 10)]
+    rate: u64,
+    #[arg(long, default_value_t = 20)]
+    burst: u64,
+    /// Per-sender limiter refill, in submissions per second, on top of `--rate`.
+    #[arg(long)]
+    per_sender_rate: Option<u64>,
+    /// Per-sender bucket size; defaults to `--per-sender-rate`.
+    #[arg(long)]
+    per_sender_burst: Option<u64>,
+    /// Chain of requests that don't name one.
+    #[arg(long, default_value_t = 1)]
+    chain_id: u64,
+    /// Most nonces a sender may have in flight past its confirmed nonce.
+    #[arg(long)]
+    max_inflight: Option<u64>,
+    /// Fill nonce gaps with self-transfers once they stand this many seconds.
+    #[arg(long)]
+    fill_nonce_gaps_after: Option<u64>,
+    /// Keep this many nonce allocations, releases and resyncs per sender for audit.
+    #[arg(long, default_value_t = 0)]
+    nonce_audit_capacity: usize,
+}
+
+/// Sets each config key whose flag `given` accepts to the flag's value.
+macro_rules! apply_flags {
+    ($given:expr, $args:expr, { $($flag:ident => $key:expr),* $(,)? }) => {
+        $(if $given(stringify!($flag)) {
+            $key = $args.$flag.clone();
+        })*
+    };
+}
+
+impl SchedulerArgs {
+    /// Copies the flags `given` accepts into `config`.
+    fn apply(&self, config: &mut AppConfig, given: &dyn Fn(&str) -> bool) {
+        apply_flags!(given, self, {
+            target_base_fee => config.scheduler.target_base_fee,
+            max_priority_fee => config.scheduler.max_priority_fee,
+            spike_threshold_high => config.scheduler.spike_threshold_high,
+            spike_threshold_low => config.scheduler.spike_threshold_low,
+            trend_threshold => config.scheduler.trend_threshold,
+            fill_nonce_gaps_after => config.scheduler.fill_nonce_gaps_after,
+            rate => config.limiter.rate,
+            burst => config.limiter.burst,
+            per_sender_rate => config.limiter.per_sender_rate,
+            per_sender_burst => config.limiter.per_sender_burst,
+            chain_id => config.chain_id,
+            max_inflight => config.nonces.max_inflight,
+            nonce_audit_capacity => config.nonces.audit_capacity,
+        });
+        if given("limiter") {
+            config.limiter.kind = match self.limiter {
+                LimiterKind::TokenBucket => config::LimiterKind::TokenBucket,
+                LimiterKind::Noop => config::LimiterKind::Noop,
+            };
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum LimiterKind {
+    /// `--rate` and `--burst` apply.
+    TokenBucket,
+    /// No pacing at all, e.g. for simulations.
+    Noop,
+}
+
+#[cfg(feature = "http-feed")]
+#[derive(Clone, Copy, ValueEnum)]
+enum OracleProvider {
+    /// Etherscan's gastracker `gasoracle`.
+    Etherscan,
+    /// Blocknative's `blockprices`.
+    Blocknative,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AuditFsync {
+    /// After every batch of entries.
+    Always,
+    /// Every `--audit-fsync-interval` seconds at most.
+    Interval,
+    /// Only on rotation and shutdown.
+    Never,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum AuditOverflow {
+    /// Wait for room, slowing the scheduler down.
+    Wait,
+    /// Leave the entry out and count it.
+    Drop,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ChannelOverflow {
+    /// Wait for room.
+    Block,
+    /// Drop the oldest queued message and count it.
+    DropOldest,
+    /// Fail the send and count it.
+    Reject,
+}
+
+impl From<ChannelOverflow> for config::ChannelOverflow {
+    fn from(overflow: ChannelOverflow) -> Self {
+        match overflow {
+            ChannelOverflow::Block => config::ChannelOverflow::Block,
+            ChannelOverflow::DropOldest => config::ChannelOverflow::DropOldest,
+            ChannelOverflow::Reject => config::ChannelOverflow::Reject,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Pattern {
+    Flat,
+    Ramp,
+    Spike,
+    RandomWalk,
+}
 
-    let (gas_tx, gas_rx) = mpsc::channel(100);
-    let (req_tx, req_rx) = mpsc::channel(100);
-    let (decision_tx, mut decision_rx) = mpsc::channel(100);
+#[derive(Args)]
+struct SimulateArgs {
+    #[arg(long, value_enum, default_value_t = Pattern::Spike)]
+    pattern: Pattern,
+    /// Seed for the random-walk pattern.
+    #[arg(long, default_value_t = 42)]
+    seed: u64,
+    /// Number of requests to submit.
+    #[arg(long, default_value_t = 2)]
+    txs: u64,
+    #[arg(long, default_value_t = 20)]
+    blocks: u64,
+    #[arg(long, default_value_t = 100)]
+    block_ms: u64,
+    /// Gap between consecutive request submissions.
+    #[arg(long, default_value_t = 500)]
+    tx_interval_ms: u64,
+    /// Run on the wall clock instead of virtual time.
+    #[arg(long)]
+    realtime: bool,
+    #[command(flatten)]
+    scheduler: SchedulerArgs,
+}
+
+#[derive(Args)]
+struct ReplayArgs {
+    /// CSV of `block_number,timestamp,base_fee[,gas_used,gas_limit]` rows.
+    #[arg(long)]
+    base_fees: PathBuf,
+    /// JSON lines of `{"timestamp": .., "event": ..}` to replay alongside the
+    /// base fees.
+    #[arg(long)]
+    events: Option<PathBuf>,
+    /// JSON lines of `{"offset_ms": .., "request": ..}`, offsets counted from
+    /// the first block.
+    #[arg(long)]
+    requests: PathBuf,
+    /// TOML file with scheduler settings, as for `serve`; flags override it.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    #[command(flatten)]
+    scheduler: SchedulerArgs,
+}
+
+impl ReplayArgs {
+    fn config(&self, given: &dyn Fn(&str) -> bool) -> anyhow::Result<AppConfig> {
+        let mut config = load_config(self.config.as_deref())?;
+        self.scheduler.apply(&mut config, given);
+        Ok(config)
+    }
+}
+
+#[derive(Args)]
+struct ServeArgs {
+    /// TOML file with settings for everything below; see `gas_saver.example.toml`.
+    /// Flags given on the command line override it.
+    #[arg(long)]
+    config: Option<PathBuf>,
+    /// Capacity of the event, request and decision channels.
+    #[arg(long, default_value_t = 100)]
+    channel_capacity: usize,
+    /// What a full event channel does with another event: block or drop-oldest.
+    #[arg(long, value_enum, default_value_t = ChannelOverflow::Block)]
+    event_overflow: ChannelOverflow,
+    /// What a full request channel does with another request: block, or reject,
+    /// which the HTTP API answers with 503.
+    #[arg(long, value_enum, default_value_t = ChannelOverflow::Block)]
+    request_overflow: ChannelOverflow,
+    /// What a full decision channel does with another decision: block or
+    /// drop-oldest.
+    #[arg(long, value_enum, default_value_t = ChannelOverflow::Block)]
+    decision_overflow: ChannelOverflow,
+    /// JSON file the nonce state is restored from at startup, if present, and saved
+    /// to on shutdown.
+    #[arg(long)]
+    nonce_state: Option<PathBuf>,
+    /// JSON file the requests still queued or in flight are restored from at
+    /// startup, if present, and saved to on shutdown.
+    #[arg(long)]
+    scheduler_state: Option<PathBuf>,
+    /// JSON file the fee history is restored from at startup, if present, and
+    /// saved to on shutdown.
+    #[arg(long)]
+    model_state: Option<PathBuf>,
+    /// Seconds each stage of shutdown may take before it's cut short, which
+    /// makes the exit status non-zero.
+    #[arg(long, default_value_t = 10)]
+    shutdown_timeout: u64,
+    /// Seconds between nonce stats dumps to the log; 0 disables them.
+    #[arg(long, default_value_t = 60)]
+    nonce_stats_interval: u64,
+    /// Print bare decisions, as before they were wrapped in records with their
+    /// sequence number, correlation id and meta.
+    #[arg(long)]
+    bare_decisions: bool,
+    /// Websocket endpoint of a node to take gas events from, via its `newHeads`
+    /// subscription, alongside any events on stdin.
+    #[cfg(feature = "ws-feed")]
+    #[arg(long)]
+    ws_url: Option<String>,
+    /// Another `newHeads` endpoint to fail over to when `--ws-url` goes quiet;
+    /// repeat for more, in order of preference.
+    #[cfg(feature = "ws-feed")]
+    #[arg(long = "backup-ws-url")]
+    backup_ws_urls: Vec<String>,
+    /// Also subscribe to `newPendingTransactions` on `--ws-url`, for the fees
+    /// pending txs offer.
+    #[cfg(feature = "ws-feed")]
+    #[arg(long)]
+    mempool: bool,
+    /// Pending tx hashes a second above which only a sample of them is fetched.
+    #[cfg(feature = "ws-feed")]
+    #[arg(long, default_value_t = 100)]
+    mempool_max_per_sec: u64,
+    /// HTTP endpoint of a node to poll `eth_feeHistory` on, for gas events from
+    /// providers without websockets.
+    #[cfg(feature = "http-feed")]
+    #[arg(long)]
+    fee_history_url: Option<String>,
+    /// Seconds between `eth_feeHistory` polls.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, default_value_t = 12)]
+    fee_history_interval: u64,
+    /// Blocks each poll asks for.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, default_value_t = 4)]
+    fee_history_blocks: u64,
+    /// Tip percentiles each poll asks for, comma separated.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, value_delimiter = ',', default_values_t = [25.0, 50.0, 75.0])]
+    fee_history_percentiles: Vec<f64>,
+    /// Gas oracle endpoint to poll; its tips are blended into the tip model.
+    #[cfg(feature = "http-feed")]
+    #[arg(long)]
+    oracle_url: Option<String>,
+    /// Response shape of `--oracle-url`.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, value_enum, default_value_t = OracleProvider::Etherscan)]
+    oracle_provider: OracleProvider,
+    /// Seconds between oracle polls.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, default_value_t = 15)]
+    oracle_interval: u64,
+    /// Seconds an oracle poll may take.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, default_value_t = 10)]
+    oracle_timeout: u64,
+    /// Environment variable holding the oracle's API key, if it needs one.
+    #[cfg(feature = "http-feed")]
+    #[arg(long)]
+    oracle_api_key_env: Option<String>,
+    /// Header the oracle's API key is sent in.
+    #[cfg(feature = "http-feed")]
+    #[arg(long, default_value = "Authorization")]
+    oracle_api_key_header: String,
+    /// Seconds without events before the block feed in use is failed over
+    /// from, when there are several.
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    #[arg(long = "feed-stale-after", default_value_t = 30)]
+    stale_after: u64,
+    /// How far block feeds' base fees for a block may spread apart, as a
+    /// fraction of their median, before the median is used instead.
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    #[arg(long, default_value_t = 0.1)]
+    divergence_threshold: f64,
+    /// Address to serve the HTTP API on, for submitting, cancelling and watching
+    /// requests. Stdin closing no longer stops the server then; SIGINT or SIGTERM
+    /// does.
+    #[cfg(feature = "http-api")]
+    #[arg(long)]
+    listen: Option<std::net::SocketAddr>,
+    /// Seconds without a base fee after which `/health` answers 503.
+    #[cfg(feature = "http-api")]
+    #[arg(long, default_value_t = 60)]
+    health_model_stale_after: u64,
+    /// Seconds a polling feed may go unanswered before `/health` reports degraded.
+    #[cfg(feature = "http-api")]
+    #[arg(long, default_value_t = 120)]
+    health_feed_stale_after: u64,
+    /// Fraction of a channel's capacity past which `/health` reports degraded.
+    #[cfg(feature = "http-api")]
+    #[arg(long, default_value_t = 0.9)]
+    health_max_channel_fill: f64,
+    /// Unix socket to serve co-located processes on, in Borsh frames: requests,
+    /// events and commands in, decision records out to clients that subscribe.
+    /// Stdin closing no longer stops the server then; SIGINT or SIGTERM does.
+    #[cfg(unix)]
+    #[arg(long)]
+    ipc_socket: Option<PathBuf>,
+    /// JSON-RPC endpoint to broadcast `Submit`, `Reprice` and `FillNonceGap`
+    /// decisions to, signed with the key in `--private-key-env`, `--keystore` or
+    /// at `--remote-signer-url`. Without it decisions are only printed.
+    #[cfg(feature = "executor")]
+    #[arg(long)]
+    rpc_url: Option<String>,
+    /// Environment variable holding the hex private key requests are signed with.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value = "GAS_SAVER_PRIVATE_KEY")]
+    private_key_env: String,
+    /// Web3 keystore file to take the key from instead.
+    #[cfg(feature = "executor")]
+    #[arg(long)]
+    keystore: Option<PathBuf>,
+    /// Environment variable holding the keystore's password.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value = "GAS_SAVER_KEYSTORE_PASSWORD")]
+    keystore_password_env: String,
+    /// HTTP signing service to sign with instead of a local key; see
+    /// `signer::RemoteSigner` for what it's sent.
+    #[cfg(feature = "executor")]
+    #[arg(long, requires = "remote_signer_address")]
+    remote_signer_url: Option<String>,
+    /// The account the signing service signs for.
+    #[cfg(feature = "executor")]
+    #[arg(long)]
+    remote_signer_address: Option<alloy_primitives::Address>,
+    /// Seconds a call to the signing service may take.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value_t = 5)]
+    remote_signer_timeout: u64,
+    /// Times a failed call to the signing service is tried again.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value_t = 2)]
+    remote_signer_retries: u32,
+    /// Seconds between receipt polls for broadcast txs.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value_t = 4)]
+    receipt_interval: u64,
+    /// Blocks a broadcast tx may go without a receipt before it counts as dropped.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value_t = 50)]
+    drop_after_blocks: u64,
+    /// Flashbots-style relay to send requests marked `PrivateRelay` to, with
+    /// `eth_sendPrivateTransaction`. Without it they fail rather than going out
+    /// through `--rpc-url`.
+    #[cfg(feature = "executor")]
+    #[arg(long)]
+    private_relay_url: Option<String>,
+    /// Blocks past the head a private tx stays eligible for, unless its deadline
+    /// comes sooner.
+    #[cfg(feature = "executor")]
+    #[arg(long, default_value_t = 25)]
+    relay_max_blocks: u64,
+    /// SQLite database to keep every request, decision and outcome in.
+    #[cfg(feature = "sqlite")]
+    #[arg(long)]
+    storage: Option<PathBuf>,
+    /// JSON lines file to append every event, request and decision to, apart
+    /// from `--storage`.
+    #[arg(long)]
+    audit_log: Option<PathBuf>,
+    /// Audit entries queued for the writer.
+    #[arg(long, default_value_t = 1024)]
+    audit_capacity: usize,
+    /// Start a new audit file before one grows past this many bytes.
+    #[arg(long)]
+    audit_max_bytes: Option<u64>,
+    /// Start a new audit file once one has been open this many seconds.
+    #[arg(long)]
+    audit_max_age: Option<u64>,
+    /// When the audit log is synced to disk.
+    #[arg(long, value_enum, default_value_t = AuditFsync::Never)]
+    audit_fsync: AuditFsync,
+    /// Seconds between syncs with `--audit-fsync interval`.
+    #[arg(long, default_value_t = 1)]
+    audit_fsync_interval: u64,
+    /// What happens to audit entries the writer has no room for.
+    #[arg(long, value_enum, default_value_t = AuditOverflow::Wait)]
+    audit_overflow: AuditOverflow,
+    /// Address to serve Prometheus metrics on, at `/metrics`.
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics_listen: Option<std::net::SocketAddr>,
+    #[command(flatten)]
+    scheduler: SchedulerArgs,
+}
+
+impl ServeArgs {
+    /// The settings to serve with: built-in defaults, then the `--config` file
+    /// and environment overrides, then the flags `given` accepts.
+    fn config(&self, given: &dyn Fn(&str) -> bool) -> anyhow::Result<AppConfig> {
+        let mut config = load_config(self.config.as_deref())?;
+        self.scheduler.apply(&mut config, given);
+        apply_flags!(given, self, {
+            channel_capacity => config.server.channel_capacity,
+            nonce_state => config.nonces.state_file,
+            scheduler_state => config.scheduler.state_file,
+            model_state => config.model.state_file,
+            shutdown_timeout => config.server.shutdown_timeout,
+            nonce_stats_interval => config.nonces.stats_interval,
+            bare_decisions => config.server.bare_decisions,
+            audit_log => config.audit.path,
+            audit_capacity => config.audit.capacity,
+            audit_max_bytes => config.audit.max_bytes,
+            audit_max_age => config.audit.max_age,
+            audit_fsync_interval => config.audit.fsync_interval,
+        });
+        if given("audit_fsync") {
+            config.audit.fsync = match self.audit_fsync {
+                AuditFsync::Always => config::AuditFsync::Always,
+                AuditFsync::Interval => config::AuditFsync::Interval,
+                AuditFsync::Never => config::AuditFsync::Never,
+            };
+        }
+        if given("event_overflow") {
+            config.server.event_overflow = self.event_overflow.into();
+        }
+        if given("request_overflow") {
+            config.server.request_overflow = self.request_overflow.into();
+        }
+        if given("decision_overflow") {
+            config.server.decision_overflow = self.decision_overflow.into();
+        }
+        if given("audit_overflow") {
+            config.audit.overflow = match self.audit_overflow {
+                AuditOverflow::Wait => config::AuditOverflow::Wait,
+                AuditOverflow::Drop => config::AuditOverflow::Drop,
+            };
+        }
+        #[cfg(feature = "ws-feed")]
+        apply_flags!(given, self, {
+            ws_url => config.feeds.ws_url,
+            backup_ws_urls => config.feeds.backup_ws_urls,
+            mempool => config.feeds.mempool,
+            mempool_max_per_sec => config.feeds.mempool_max_per_sec,
+        });
+        #[cfg(feature = "http-feed")]
+        apply_flags!(given, self, {
+            fee_history_url => config.feeds.fee_history_url,
+            fee_history_interval => config.feeds.fee_history_interval,
+            fee_history_blocks => config.feeds.fee_history_blocks,
+            fee_history_percentiles => config.feeds.fee_history_percentiles,
+            oracle_url => config.feeds.oracle_url,
+            oracle_interval => config.feeds.oracle_interval,
+            oracle_timeout => config.feeds.oracle_timeout,
+            oracle_api_key_env => config.feeds.oracle_api_key_env,
+            oracle_api_key_header => config.feeds.oracle_api_key_header,
+        });
+        #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+        apply_flags!(given, self, {
+            stale_after => config.feeds.stale_after,
+            divergence_threshold => config.feeds.divergence_threshold,
+        });
+        #[cfg(feature = "http-feed")]
+        if given("oracle_provider") {
+            config.feeds.oracle_provider = match self.oracle_provider {
+                OracleProvider::Etherscan => config::OracleProvider::Etherscan,
+                OracleProvider::Blocknative => config::OracleProvider::Blocknative,
+            };
+        }
+        #[cfg(feature = "http-api")]
+        apply_flags!(given, self, {
+            listen => config.server.listen,
+            health_model_stale_after => config.health.model_stale_after,
+            health_feed_stale_after => config.health.feed_stale_after,
+            health_max_channel_fill => config.health.max_channel_fill,
+        });
+        #[cfg(unix)]
+        apply_flags!(given, self, { ipc_socket => config.server.ipc_socket });
+        #[cfg(feature = "executor")]
+        apply_flags!(given, self, {
+            rpc_url => config.executor.rpc_url,
+            private_key_env => config.executor.private_key_env,
+            keystore => config.executor.keystore,
+            keystore_password_env => config.executor.keystore_password_env,
+            remote_signer_url => config.executor.remote_signer_url,
+            remote_signer_address => config.executor.remote_signer_address,
+            remote_signer_timeout => config.executor.remote_signer_timeout,
+            remote_signer_retries => config.executor.remote_signer_retries,
+            receipt_interval => config.executor.receipt_interval,
+            drop_after_blocks => config.executor.drop_after_blocks,
+            private_relay_url => config.executor.private_relay_url,
+            relay_max_blocks => config.executor.relay_max_blocks,
+        });
+        #[cfg(feature = "metrics")]
+        apply_flags!(given, self, { metrics_listen => config.server.metrics_listen });
+        #[cfg(feature = "sqlite")]
+        apply_flags!(given, self, { storage => config.storage.path });
+        Ok(config)
+    }
+}
 
-    let model = Arc::new(GasModel::new(100));
-    let nonce_manager = Arc::new(NonceManager::new());
-    let limiter = Arc::new(RateLimiter::new(10, 20));
+/// Built-in defaults, then the file at `path` if any, then environment
+/// overrides.
+fn load_config(path: Option<&Path>) -> anyhow::Result<AppConfig> {
+    Ok(match path {
+        Some(path) => AppConfig::load(path)?,
+        None => AppConfig::from_toml("", std::env::vars())?,
+    })
+}
 
-    let config = SchedulerConfig {
-        target_base_fee: 50,
-        max_priority_fee: 2,
-        spike_threshold: 15.0,
-        reprice_cooldown: tokio::time::Duration::from_millis(500),
+/// One line of `serve` input.
+#[derive(Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Input {
+    Event(GasEvent),
+    Request(Box<TransactionRequest>),
+    Command(SchedulerCommand),
+}
+
+fn main() -> anyhow::Result<()> {
+    let matches = Cli::command().get_matches();
+    let cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    let virtual_time = match &cli.command {
+        Command::Simulate(args) => !args.realtime,
+        Command::Serve(_) => false,
+        Command::Replay(_) => true,
     };
 
-    let scheduler = Arc::new(Scheduler::new(
-        config,
-        model,
+    // stdout carries decisions in serve mode
+    let logger = tracing_subscriber::fmt()
+        .with_max_level(Level::INFO)
+        .with_writer(std::io::stderr);
+    if virtual_time {
+        // wall-clock stamps would make identical runs diff
+        logger.without_time().init();
+    } else {
+        logger.init();
+    }
+
+    match cli.command {
+        Command::Simulate(args) => {
+            // Virtual time makes the decision log depend only on the arguments
+            let mut runtime = tokio::runtime::Builder::new_current_thread();
+            runtime.enable_all().start_paused(virtual_time);
+            runtime.build()?.block_on(simulate(args))
+        }
+        Command::Serve(args) => {
+            let flags = matches.subcommand_matches("serve").unwrap();
+            let config =
+                args.config(&|id| flags.value_source(id) == Some(ValueSource::CommandLine))?;
+            let runtime = tokio::runtime::Runtime::new()?;
+            let shutdown = CancellationToken::new();
+            runtime.spawn(cancel_on_signal(shutdown.clone()));
+            let served =
+                runtime.block_on(serve(config, BufReader::new(tokio::io::stdin()), shutdown));
+            // a read of stdin that is still blocked would hold the exit up
+            runtime.shutdown_background();
+            served
+        }
+        Command::Replay(args) => {
+            let flags = matches.subcommand_matches("replay").unwrap();
+            let config =
+                args.config(&|id| flags.value_source(id) == Some(ValueSource::CommandLine))?;
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .start_paused(true)
+                .build()?;
+            runtime.block_on(replay(args, config))
+        }
+    }
+}
+
+/// A scheduler on `scheduler_config`, with the limiter, model and L1 data fees
+/// `config` describes.
+fn build_scheduler(
+    config: &AppConfig,
+    scheduler_config: SchedulerConfig,
+    nonce_manager: Arc<NonceManager>,
+    sinks: Vec<Box<dyn DecisionSink>>,
+) -> anyhow::Result<Scheduler> {
+    config.validate()?;
+    let limiter: Arc<dyn Limiter> = match config.limiter_config() {
+        Some(bucket) => Arc::new(RateLimiter::from_config(bucket?)?),
+        None => Arc::new(NoopLimiter::new()),
+    };
+
+    let scheduler = Scheduler::new(
+        scheduler_config,
+        Arc::new(GasModel::new(config.model.window)),
         nonce_manager,
         limiter,
-        decision_tx,
-    ));
+        sinks,
+    );
+    Ok(config
+        .op_stack_chains()
+        .filter_map(|(chain, section)| Some((chain, section.l1_data_fee()?)))
+        .fold(scheduler, |scheduler, (chain, estimator)| {
+            scheduler.with_l1_data_fee(chain, estimator)
+        }))
+}
 
-    let _scheduler_handle = tokio::spawn(async move {
-        scheduler.run(gas_rx, req_rx).await;
-    });
+/// The synthetic feed around `--target-base-fee`. Feeds deliver wei, and
+/// `FeePattern` takes it as a `u64`.
+fn fee_pattern(args: &SimulateArgs) -> anyhow::Result<FeePattern> {
+    let base_fee = u64::try_from(Wei::from(Gwei(args.scheduler.target_base_fee)))?;
+    Ok(match args.pattern {
+        Pattern::Flat => FeePattern::Flat { base_fee },
+        Pattern::Ramp => FeePattern::Ramp {
+            start: base_fee,
+            step: Gwei(5).to_wei().0 as i64,
+        },
+        // Fee jumps to five times the target for blocks 6-10, then settles back
+        Pattern::Spike => FeePattern::Spike {
+            base_fee,
+            peak: base_fee * 5,
+            at_block: 5,
+            blocks: 5,
+        },
+        Pattern::RandomWalk => FeePattern::RandomWalk {
+            start: base_fee,
+            max_step: (base_fee / 5).max(1),
+            seed: args.seed,
+        },
+    })
+}
+
+async fn simulate(args: SimulateArgs) -> anyhow::Result<()> {
+    let block_time = Duration::from_millis(args.block_ms);
+    let source = SyntheticSource::new(fee_pattern(&args)?, block_time).with_max_blocks(args.blocks);
+    let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(100);
+    let (decision_tx, mut decision_rx) = mpsc::channel::<SchedulerDecision>(100);
+
+    // The scripted senders are fresh accounts
+    let mut config = AppConfig::default();
+    args.scheduler.apply(&mut config, &|_| true);
+    let nonce_manager = config.nonce_manager();
+    for index in 0..args.txs.min(4) {
+        let sender = scripted_request(index, Wei::ZERO).from;
+        nonce_manager.update_nonce(args.scheduler.chain_id, sender.into(), 0);
+    }
+    let scheduler = build_scheduler(
+        &config,
+        SchedulerConfig {
+            market_updates: MarketUpdatePolicy::Every(Duration::from_millis(500)),
+            ..config.scheduler_config()
+        },
+        Arc::new(nonce_manager),
+        vec![Box::new(ChannelSink::new(decision_tx))],
+    )?;
+    let suggestions = tokio::spawn(log_fee_suggestions(scheduler.model()));
+    let scheduler_task = tokio::spawn(Arc::new(scheduler).run_with_source(source, req_rx, cmd_rx));
 
     // Decision consumer
-    tokio::spawn(async move {
+    let consumer = tokio::spawn(async move {
         while let Some(decision) = decision_rx.recv().await {
-            info!("CORE DECISION: {:?}", decision);
+            info!("CORE DECISION: {}", decision);
         }
     });
 
     info!("Starting GasSaver Simulation...");
 
-    // 1. Initial stable state
-    gas_tx
-        .send(GasEvent::BaseFeeUpdate {
-            base_fee: 50,
-            timestamp: 1000,
-        })
-        .await?;
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-    // 2. Submit transaction
-    let tx1 = TransactionRequest {
-        id: 1,
-        from: [0xAA; 20],
-        to: [0xBB; 20],
-        data: vec![],
-        value: [0; 32],
-        max_fee_per_gas: 100,
-        max_priority_fee_per_gas: 2,
-        deadline: None,
-    };
-    req_tx.send(tx1).await?;
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-    // 3. Gas rises - triggering reprice
-    info!("Triggering gas rise for reprice...");
-    gas_tx
-        .send(GasEvent::BaseFeeUpdate {
-            base_fee: 70,
-            timestamp: 1100,
+    // Requests land mid-block so they never tie with a block on the clock
+    let start = tokio::time::Instant::now() + block_time / 2;
+    for index in 0..args.txs {
+        tokio::time::sleep_until(start + Duration::from_millis(args.tx_interval_ms) * index as u32)
+            .await;
+        handle
+            .submit(scripted_request(
+                index,
+                Gwei(args.scheduler.target_base_fee).into(),
+            ))
+            .await?;
+    }
+
+    // The scheduler stops once the feed runs out and the handle is gone
+    drop(handle);
+    scheduler_task.await?;
+    consumer.await?;
+    suggestions.abort();
+    info!("Simulation finished.");
+
+    Ok(())
+}
+
+async fn replay(args: ReplayArgs, config: AppConfig) -> anyhow::Result<()> {
+    let read = |path: &Path| {
+        std::fs::read_to_string(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+    };
+    let in_file = |path: &Path| {
+        let path = path.display().to_string();
+        move |e: replay::ReplayError| anyhow::anyhow!("{}: {}", path, e)
+    };
+    let mut events =
+        replay::parse_base_fee_csv(&read(&args.base_fees)?).map_err(in_file(&args.base_fees))?;
+    if let Some(path) = &args.events {
+        events.extend(replay::parse_jsonl(&read(path)?).map_err(in_file(path))?);
+    }
+    let requests = replay::parse_jsonl(&read(&args.requests)?).map_err(in_file(&args.requests))?;
+
+    let report = replay::Replay::new(events, requests)?
+        .run(|sink| {
+            build_scheduler(
+                &config,
+                SchedulerConfig {
+                    market_updates: MarketUpdatePolicy::Disabled,
+                    ..config.scheduler_config()
+                },
+                Arc::new(config.nonce_manager()),
+                vec![sink],
+            )
         })
         .await?;
-    tokio::time::sleep(tokio::time::Duration::from_millis(100)).await;
-
-    // 4. Gas Spike - triggering inclusion-first for new tx
-    info!("Triggering gas spike...");
-    for i in 0..10 {
-        gas_tx
-            .send(GasEvent::BaseFeeUpdate {
-                base_fee: 70 + (i * 20),
-                timestamp: 1200 + i,
+    println!("{}", report);
+    Ok(())
+}
+
+/// An oracle feed for `url` with `feeds`' interval, timeout and API key.
+#[cfg(feature = "http-feed")]
+fn oracle_feed<P: gas_saver_eth::feeds::oracle::OraclePayload + 'static>(
+    url: &str,
+    feeds: &config::FeedSection,
+) -> anyhow::Result<gas_saver_eth::feeds::oracle::OracleFeed<P>> {
+    let mut feed = gas_saver_eth::feeds::oracle::OracleFeed::<P>::new(url)
+        .with_interval(Duration::from_secs(feeds.oracle_interval))
+        .with_timeout(Duration::from_secs(feeds.oracle_timeout));
+    if let Some(var) = &feeds.oracle_api_key_env {
+        let key = std::env::var(var).map_err(|e| anyhow::anyhow!("{}: {}", var, e))?;
+        feed = feed.with_header(&feeds.oracle_api_key_header, key.trim());
+    }
+    Ok(feed)
+}
+
+/// The L1 fee parameters `section`'s chain reports at its `rpc_url`.
+#[cfg(feature = "http-feed")]
+async fn oracle_l1_data_fee(
+    chain: u64,
+    section: &config::ChainSection,
+) -> anyhow::Result<gas_saver_eth::l2::L1DataFeeEstimator> {
+    let url = section.rpc_url.as_deref().unwrap_or_default();
+    let oracle = gas_saver_eth::feeds::l1_fee::GasPriceOracleClient::new(url);
+    gas_saver_eth::l2::L1DataFeeEstimator::fetch(&oracle)
+        .await
+        .map_err(|e| anyhow::anyhow!("chain {}: {}", chain, e))
+}
+
+#[cfg(not(feature = "http-feed"))]
+async fn oracle_l1_data_fee(
+    chain: u64,
+    _section: &config::ChainSection,
+) -> anyhow::Result<gas_saver_eth::l2::L1DataFeeEstimator> {
+    anyhow::bail!(
+        "chain {}: reading the L1 fee scalar from rpc_url needs the http-feed feature",
+        chain
+    )
+}
+
+/// The signer `executor` asks for, with the local key to authenticate at a
+/// private relay with, if there is one.
+#[cfg(feature = "executor")]
+fn tx_signer(
+    executor: &config::ExecutorSection,
+) -> anyhow::Result<(
+    Box<dyn gas_saver_eth::signer::TxSigner>,
+    Option<alloy_signer_local::PrivateKeySigner>,
+)> {
+    use gas_saver_eth::signer::{LocalSigner, RemoteSigner};
+    if let (Some(url), Some(address)) =
+        (&executor.remote_signer_url, executor.remote_signer_address)
+    {
+        let signer = RemoteSigner::new(url.clone(), address.0.0)
+            .with_timeout(Duration::from_secs(executor.remote_signer_timeout))
+            .with_retries(executor.remote_signer_retries, Duration::from_millis(200));
+        return Ok((Box::new(signer), None));
+    }
+    let signer = match &executor.keystore {
+        Some(path) => {
+            let var = &executor.keystore_password_env;
+            let password = std::env::var(var).map_err(|e| anyhow::anyhow!("{}: {}", var, e))?;
+            LocalSigner::from_keystore(path, &password)
+                .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?
+        }
+        None => LocalSigner::from_env(&executor.private_key_env)?,
+    };
+    let key = signer.key().clone();
+    Ok((Box::new(signer), Some(key)))
+}
+
+async fn serve(
+    config: AppConfig,
+    input: impl AsyncBufRead + Unpin,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let (event_tx, event_rx) = instrumented_channel(
+        config.server.channel_capacity,
+        config.server.event_overflow.into(),
+    );
+    let (handle, req_rx, cmd_rx) = SchedulerHandle::channel_with_policy(
+        config.server.channel_capacity,
+        config.server.request_overflow.into(),
+    );
+    let (decision_tx, mut decision_rx) = instrumented_channel::<DecisionRecord>(
+        config.server.channel_capacity,
+        config.server.decision_overflow.into(),
+    );
+    #[cfg(feature = "http-api")]
+    let monitor = gas_saver_eth::health::HealthMonitor::new(config.health_thresholds())
+        .with_channel("events", event_tx.stats())
+        .with_handle(&handle)
+        .with_channel("decisions", decision_tx.stats());
+
+    #[cfg(feature = "executor")]
+    let (req_rx, executor_tx, executor) = match &config.executor.rpc_url {
+        Some(url) => {
+            let (signer, relay_key) = tx_signer(&config.executor)?;
+            info!(
+                "Broadcasting to {} as {}",
+                url,
+                alloy_primitives::Address::from(signer.address())
+            );
+            let provider = alloy_provider::RootProvider::new_http(url.parse()?);
+            let (hash_tx, hash_rx) = mpsc::channel(config.server.channel_capacity);
+            let poller = gas_saver_eth::confirmation::ReceiptPoller::new(
+                provider.clone(),
+                Duration::from_secs(config.executor.receipt_interval),
+                config.executor.drop_after_blocks,
+            )
+            .spawn(hash_rx, event_tx.clone());
+            let mut executor =
+                gas_saver_eth::executor::Executor::new(provider, signer, handle.clone())
+                    .with_broadcasts(hash_tx)
+                    .with_default_chain_id(config.chain_id);
+            if let Some(relay_url) = &config.executor.private_relay_url {
+                info!("Sending private requests to {}", relay_url);
+                let mut relay = gas_saver_eth::executor::PrivateRelay::new(relay_url.clone())
+                    .with_max_blocks(config.executor.relay_max_blocks);
+                if let Some(key) = relay_key {
+                    relay = relay.with_auth_key(key);
+                }
+                executor = executor.with_private_relay(relay);
+            }
+            let req_rx = executor.track_submissions(req_rx);
+            let (executor_tx, executor_rx) = mpsc::channel(config.server.channel_capacity);
+            let executor = tokio::spawn(executor.run(executor_rx));
+            (req_rx, Some(executor_tx), Some((executor, poller)))
+        }
+        None => (req_rx, None, None),
+    };
+
+    #[cfg(feature = "sqlite")]
+    let (req_rx, storage) = match &config.storage.path {
+        Some(path) => {
+            let storage = gas_saver_eth::storage::Storage::open(path)?;
+            info!("Keeping history in {}", path.display());
+            let sink = storage.sink(config.server.channel_capacity);
+            (sink.track_submissions(req_rx), Some(sink))
+        }
+        None => (req_rx, None),
+    };
+
+    let (event_rx, req_rx, audit) = match &config.audit.path {
+        Some(path) => {
+            let audit = AuditLog::open(path, config.audit_log_config())?;
+            info!("Keeping an audit log in {}", path.display());
+            let event_rx = audit.track_events(event_rx);
+            (event_rx, audit.track_submissions(req_rx), Some(audit))
+        }
+        None => (event_rx, req_rx, None),
+    };
+
+    let nonce_manager = Arc::new(config.nonce_manager());
+    if let Some(path) = existing(&config.nonces.state_file) {
+        let snapshot: NonceSnapshot = read_state(path)?;
+        info!(
+            "Restored nonces of {} accounts from {}",
+            snapshot.accounts.len(),
+            path.display()
+        );
+        nonce_manager.import(snapshot, ImportPolicy::RaiseOnly);
+    }
+    #[cfg(unix)]
+    let ipc = match &config.server.ipc_socket {
+        Some(path) => Some(
+            gas_saver_eth::ipc::IpcServer::bind(path, handle.clone(), event_tx.clone())?
+                .with_shutdown(shutdown.clone()),
+        ),
+        None => None,
+    };
+    #[cfg(feature = "metrics")]
+    let decision_stats = decision_tx.stats();
+    // the executor broadcasts from this channel, so a Submit it never got stays pending
+    let mut sinks: Vec<Box<dyn DecisionSink>> =
+        vec![Box::new(RequiredSink::new(ChannelSink::new(decision_tx)))];
+    if let Some(audit) = &audit {
+        sinks.push(Box::new(audit.clone()));
+    }
+    #[cfg(unix)]
+    if let Some(ipc) = &ipc {
+        sinks.push(Box::new(ipc.sink()));
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(storage) = &storage {
+        sinks.push(Box::new(storage.clone()));
+    }
+    let mut scheduler = build_scheduler(
+        &config,
+        SchedulerConfig {
+            market_updates: MarketUpdatePolicy::Disabled,
+            // the executor can't build type-3 txs, and would only find out after
+            // a nonce was taken for one
+            reject_blob_requests: cfg!(feature = "executor") && config.executor.rpc_url.is_some(),
+            ..config.scheduler_config()
+        },
+        nonce_manager.clone(),
+        sinks,
+    )?;
+    // OP-stack chains without a configured scalar take the one their oracle reports
+    for (chain, section) in config.op_stack_chains() {
+        if section.l1_fee_scalar.is_some() {
+            continue;
+        }
+        let estimator = oracle_l1_data_fee(chain, section).await?;
+        info!(
+            "Chain {} prices L1 data with {:?}",
+            chain,
+            estimator.params()
+        );
+        scheduler = scheduler.with_l1_data_fee(chain, estimator);
+    }
+    #[cfg(feature = "metrics")]
+    let (scheduler, metrics_server) = match config.server.metrics_listen {
+        Some(addr) => {
+            let metrics = Arc::new(gas_saver_eth::metrics::Metrics::new());
+            metrics.watch_nonces(nonce_manager.clone());
+            metrics.watch_channel("events", event_tx.stats());
+            metrics.watch_channel("requests", handle.request_stats());
+            metrics.watch_channel("decisions", decision_stats);
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!("Serving metrics on {}", listener.local_addr()?);
+            let scheduler = scheduler.with_metrics(metrics.clone());
+            let server = tokio::spawn(async move {
+                if let Err(e) = gas_saver_eth::metrics::serve(listener, metrics).await {
+                    warn!("metrics endpoint stopped: {}", e);
+                }
+            });
+            (scheduler, Some(server))
+        }
+        None => (scheduler, None),
+    };
+    let scheduler = scheduler.with_shutdown(shutdown.clone());
+    let model = scheduler.model();
+    if let Some(path) = existing(&config.model.state_file) {
+        let snapshot: ModelSnapshot = read_state(path)?;
+        info!(
+            "Restored {} blocks of fee history from {}",
+            snapshot.base_fees.len(),
+            path.display()
+        );
+        model.import(snapshot);
+    }
+    let scheduler = Arc::new(scheduler);
+    #[cfg(feature = "http-api")]
+    let monitor = monitor
+        .with_scheduler(&scheduler)
+        .with_nonce_manager(nonce_manager.clone());
+    let scheduler_task =
+        tokio::spawn(scheduler.run_with_source(ChannelSource::new(event_rx), req_rx, cmd_rx));
+    if let Some(path) = existing(&config.scheduler.state_file) {
+        let snapshot: SchedulerSnapshot = read_state(path)?;
+        info!(
+            "Restored {} pending and {} submitted txs from {}",
+            snapshot.pending.len(),
+            snapshot.submitted.len(),
+            path.display()
+        );
+        handle
+            .command(SchedulerCommand::RestoreSubmitted {
+                txs: snapshot.submitted,
             })
             .await?;
+        for req in snapshot.pending {
+            handle.submit_waiting(req).await?;
+        }
     }
 
-    let tx2 = TransactionRequest {
-        id: 2,
-        from: [0xCC; 20],
-        to: [0xDD; 20],
-        data: vec![],
-        value: [0; 32],
-        max_fee_per_gas: 500,
-        max_priority_fee_per_gas: 10,
-        deadline: None,
+    // Feeds send through an intake that stops taking their events at shutdown,
+    // which is what makes them hang up
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    let (feed_tx, feed_rx) = mpsc::channel(config.server.channel_capacity);
+    #[cfg(all(feature = "http-api", any(feature = "ws-feed", feature = "http-feed")))]
+    let monitor = monitor.with_channel(
+        "feed intake",
+        gas_saver_eth::channel::ChannelStats::of(&feed_tx),
+    );
+
+    // several block feeds are reconciled into one stream, failing over between them
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    let mut aggregator = {
+        let mut block_feeds = 0;
+        #[cfg(feature = "ws-feed")]
+        {
+            block_feeds += config.feeds.ws_url.iter().count() + config.feeds.backup_ws_urls.len();
+        }
+        #[cfg(feature = "http-feed")]
+        {
+            block_feeds += config.feeds.fee_history_url.iter().count();
+        }
+        (block_feeds > 1).then(|| {
+            gas_saver_eth::feeds::aggregate::Aggregator::new(Duration::from_secs(
+                config.feeds.stale_after,
+            ))
+            .with_divergence_threshold(config.feeds.divergence_threshold)
+        })
+    };
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    let mut block_tx = |name: &str| match aggregator.as_mut() {
+        Some(aggregator) => aggregator.add_source(name),
+        None => feed_tx.clone(),
+    };
+    #[cfg(feature = "ws-feed")]
+    let ws_feeds: Vec<_> = (config
+        .feeds
+        .ws_url
+        .iter()
+        .map(|url| ("ws".to_string(), url)))
+    .chain(
+        (config.feeds.backup_ws_urls.iter().enumerate())
+            .map(|(i, url)| (format!("ws-backup-{}", i + 1), url)),
+    )
+    .map(|(name, url)| {
+        info!("Taking gas events from {}", url);
+        let feed = gas_saver_eth::feeds::ws::spawn_newheads_feed(url.clone(), block_tx(&name));
+        tokio::spawn(async move {
+            match feed.await {
+                Ok(Err(e)) => warn!("{} newHeads feed stopped: {}", name, e),
+                Err(e) if e.is_panic() => warn!("{} newHeads feed panicked: {}", name, e),
+                _ => {}
+            }
+        })
+    })
+    .collect();
+    #[cfg(feature = "ws-feed")]
+    let mempool = match &config.feeds.ws_url {
+        Some(url) if config.feeds.mempool => {
+            info!("Taking pending txs from {}", url);
+            let (feed, _) = gas_saver_eth::feeds::mempool::MempoolFeed::new(url.clone())
+                .with_max_hashes_per_sec(config.feeds.mempool_max_per_sec)
+                .spawn(feed_tx.clone());
+            Some(tokio::spawn(async move {
+                match feed.await {
+                    Ok(Err(e)) => warn!("mempool feed stopped: {}", e),
+                    Err(e) if e.is_panic() => warn!("mempool feed panicked: {}", e),
+                    _ => {}
+                }
+            }))
+        }
+        _ => None,
+    };
+    #[cfg(feature = "http-feed")]
+    let fee_history = config.feeds.fee_history_url.as_ref().map(|url| {
+        info!("Polling eth_feeHistory on {}", url);
+        gas_saver_eth::feeds::fee_history::spawn(
+            url.clone(),
+            Duration::from_secs(config.feeds.fee_history_interval),
+            config.feeds.fee_history_blocks,
+            config.feeds.fee_history_percentiles.clone(),
+            block_tx("fee-history"),
+        )
+    });
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    let aggregate = aggregator.map(|aggregator| {
+        info!(
+            "Failing over between block feeds after {}s of silence",
+            config.feeds.stale_after
+        );
+        aggregator.spawn(feed_tx.clone())
+    });
+    #[cfg(feature = "http-feed")]
+    let oracle = match &config.feeds.oracle_url {
+        Some(url) => {
+            use gas_saver_eth::feeds::oracle::{BlocknativeBlockPrices, EtherscanGasOracle};
+            info!("Polling the gas oracle at {}", url);
+            Some(match config.feeds.oracle_provider {
+                config::OracleProvider::Etherscan => {
+                    oracle_feed::<EtherscanGasOracle>(url, &config.feeds)?.spawn(feed_tx.clone())
+                }
+                config::OracleProvider::Blocknative => {
+                    oracle_feed::<BlocknativeBlockPrices>(url, &config.feeds)?
+                        .spawn(feed_tx.clone())
+                }
+            })
+        }
+        None => None,
+    };
+    #[cfg(feature = "http-feed")]
+    let l1_base_fees: Vec<_> = config
+        .op_stack_chains()
+        .filter_map(|(chain, section)| Some((chain, section, section.rpc_url.as_ref()?)))
+        .map(|(chain, section, url)| {
+            use gas_saver_eth::feeds::l1_fee::{GasPriceOracleClient, L1BaseFeeFeed};
+            info!("Polling chain {}'s L1 base fee on {}", chain, url);
+            let feed = L1BaseFeeFeed::new(GasPriceOracleClient::new(url.clone()))
+                .with_interval(Duration::from_secs(section.l1_base_fee_interval))
+                .spawn(feed_tx.clone());
+            (format!("l1-base-fee-{}", chain), feed)
+        })
+        .collect();
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    let intake = {
+        drop(feed_tx);
+        tokio::spawn(forward_feed_events(
+            feed_rx,
+            event_tx.clone(),
+            shutdown.clone(),
+        ))
+    };
+
+    #[cfg(all(feature = "http-api", feature = "http-feed"))]
+    let monitor = [("fee-history", &fee_history), ("oracle", &oracle)]
+        .into_iter()
+        .filter_map(|(name, feed)| Some((name.to_string(), feed.as_ref()?)))
+        .chain(l1_base_fees.iter().map(|(name, feed)| (name.clone(), feed)))
+        .fold(monitor, |monitor, (name, (_, health))| {
+            monitor.with_feed(name, health.clone())
+        });
+    #[cfg(all(feature = "http-api", any(feature = "ws-feed", feature = "http-feed")))]
+    let monitor = match &aggregate {
+        Some((_, health)) => monitor.with_aggregate(health.clone()),
+        None => monitor,
+    };
+    #[cfg(feature = "http-api")]
+    let api = match config.server.listen {
+        Some(addr) => {
+            let listener = tokio::net::TcpListener::bind(addr).await?;
+            info!("Serving the HTTP API on {}", listener.local_addr()?);
+            let app = gas_saver_eth::api::router(handle.clone())
+                .merge(gas_saver_eth::api::health_router(Arc::new(monitor)));
+            let api_shutdown = shutdown.clone();
+            Some(tokio::spawn(async move {
+                if let Err(e) = gas_saver_eth::api::serve(listener, app, api_shutdown).await {
+                    warn!("HTTP API stopped: {}", e);
+                }
+            }))
+        }
+        None => None,
     };
-    req_tx.send(tx2).await?;
 
-    tokio::time::sleep(tokio::time::Duration::from_secs(1)).await;
-    info!("Simulation finished.");
+    #[cfg(unix)]
+    let ipc = ipc.map(|server| {
+        info!("Serving local clients on {}", server.path().display());
+        tokio::spawn(async move {
+            if let Err(e) = server.run().await {
+                warn!("IPC server stopped: {}", e);
+            }
+        })
+    });
+
+    let suggestions = tokio::spawn(log_fee_suggestions(model.clone()));
+    let stats_dump = (config.nonces.stats_interval > 0).then(|| {
+        let period = Duration::from_secs(config.nonces.stats_interval);
+        tokio::spawn(dump_nonce_stats(nonce_manager.clone(), period))
+    });
+
+    let bare = config.server.bare_decisions;
+    let writer = tokio::spawn(async move {
+        while let Some(record) = decision_rx.recv().await {
+            #[cfg(feature = "executor")]
+            if let Some(executor_tx) = &executor_tx {
+                // the executor only stops once the decisions run out, at shutdown
+                let _ = executor_tx.send(record.decision.clone()).await;
+            }
+            let line = if bare {
+                serde_json::to_string(&record.decision)
+            } else {
+                serde_json::to_string(&record)
+            };
+            match line {
+                Ok(line) => println!("{}", line),
+                Err(e) => warn!("could not encode decision {:?}: {}", record.decision, e),
+            }
+        }
+    });
+
+    info!("Serving; reading JSON lines from stdin");
+    let input = shutdown
+        .run_until_cancelled(read_input(input, &event_tx, &handle))
+        .await;
+    if let Some(Ok(())) = input {
+        let mut serving = false;
+        #[cfg(unix)]
+        {
+            serving |= ipc.is_some();
+        }
+        #[cfg(feature = "http-api")]
+        {
+            serving |= api.is_some();
+        }
+        if serving {
+            info!("Stdin closed; serving clients until signalled");
+            shutdown.cancelled().await;
+        }
+    }
+
+    info!("Shutting down");
+    shutdown.cancel();
+    let mut drain = Drain::new(Duration::from_secs(config.server.shutdown_timeout));
+    // the intake stops taking events, so every feed sees its receiver go
+    #[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+    {
+        drain.join("feed intake", intake).await;
+        #[cfg(feature = "ws-feed")]
+        for feed in ws_feeds.into_iter().chain(mempool) {
+            drain.join("websocket feed", feed).await;
+        }
+        #[cfg(feature = "http-feed")]
+        for (feed, _health) in [fee_history, oracle]
+            .into_iter()
+            .flatten()
+            .chain(l1_base_fees.into_iter().map(|(_, feed)| feed))
+        {
+            drain.join("polling feed", feed).await;
+        }
+        if let Some((aggregate, _health)) = aggregate {
+            drain.join("feed aggregator", aggregate).await;
+        }
+    }
+    // answers what is in progress; the scheduler takes nothing new anyway
+    drain.stage();
+    #[cfg(feature = "http-api")]
+    if let Some(api) = api {
+        drain.join("HTTP API", api).await;
+    }
+    #[cfg(unix)]
+    if let Some(ipc) = ipc {
+        drain.join("IPC server", ipc).await;
+    }
+    drain.stage();
+    let snapshot = drain.join("scheduler", scheduler_task).await;
+    drain.join("decision writer", writer).await;
+    #[cfg(feature = "executor")]
+    if let Some((executor, poller)) = executor {
+        drain.stage();
+        drain.join("executor", executor).await;
+        poller.abort();
+    }
+    if let Some(audit) = audit {
+        drain.stage();
+        if let Some(flushed) = drain.finish("audit log", audit.flush()).await {
+            flushed?;
+        }
+        let stats = audit.stats();
+        info!(
+            "Audit log: {} entries written, {} dropped, {} failed",
+            stats.written(),
+            stats.dropped(),
+            stats.failed()
+        );
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(storage) = storage {
+        drain.stage();
+        if let Some(flushed) = drain.finish("storage", storage.flush()).await {
+            flushed?;
+        }
+    }
+    suggestions.abort();
+    if let Some(stats_dump) = stats_dump {
+        stats_dump.abort();
+    }
+    #[cfg(feature = "metrics")]
+    if let Some(metrics_server) = metrics_server {
+        metrics_server.abort();
+    }
+
+    // after the scheduler is gone, so txs it never handed out release their nonces
+    if let Some(path) = &config.nonces.state_file {
+        write_state(path, &nonce_manager.export())?;
+        info!("Saved nonce state to {}", path.display());
+    }
+    // a scheduler that was cut short leaves the last snapshot in place
+    if let (Some(path), Some(snapshot)) = (&config.scheduler.state_file, snapshot) {
+        write_state(path, &snapshot)?;
+        info!(
+            "Saved {} pending and {} submitted txs to {}",
+            snapshot.pending.len(),
+            snapshot.submitted.len(),
+            path.display()
+        );
+    }
+    if let Some(path) = &config.model.state_file {
+        write_state(path, &model.export())?;
+        info!("Saved fee history to {}", path.display());
+    }
+
+    if let Some(input) = input {
+        input?;
+    }
+    if !drain.failed.is_empty() {
+        anyhow::bail!("shutdown cut short by: {}", drain.failed.join(", "));
+    }
+    Ok(())
+}
 
+/// Hands each line of `input` to the scheduler until the input runs out.
+async fn read_input(
+    input: impl AsyncBufRead + Unpin,
+    event_tx: &InstrumentedSender<GasEvent>,
+    handle: &SchedulerHandle,
+) -> anyhow::Result<()> {
+    let mut lines = input.lines();
+    while let Some(line) = lines.next_line().await? {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(Input::Event(event)) => event_tx.send(event).await?,
+            Ok(Input::Request(req)) => handle.submit_waiting(*req).await?,
+            Ok(Input::Command(cmd)) => handle.command(cmd).await?,
+            Err(e) => warn!("ignoring malformed input line: {}", e),
+        }
+    }
     Ok(())
 }
+
+/// Passes the feeds' events on to the scheduler until `shutdown` is cancelled.
+/// Dropping `feed_rx` then is what tells the feeds to stop.
+#[cfg(any(feature = "ws-feed", feature = "http-feed"))]
+async fn forward_feed_events(
+    mut feed_rx: mpsc::Receiver<GasEvent>,
+    event_tx: InstrumentedSender<GasEvent>,
+    shutdown: CancellationToken,
+) {
+    shutdown
+        .run_until_cancelled(async move {
+            while let Some(event) = feed_rx.recv().await {
+                if event_tx.send(event).await.is_err() {
+                    break;
+                }
+            }
+        })
+        .await;
+}
+
+/// Cancels `shutdown` on SIGINT, or SIGTERM on Unix.
+async fn cancel_on_signal(shutdown: CancellationToken) {
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => signal.recv().await,
+            Err(e) => {
+                warn!("cannot listen for SIGTERM: {}", e);
+                std::future::pending().await
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<Option<()>>();
+    tokio::select! {
+        interrupted = tokio::signal::ctrl_c() => {
+            if let Err(e) = interrupted {
+                warn!("cannot listen for SIGINT: {}", e);
+                return;
+            }
+            info!("Interrupted");
+        }
+        _ = terminate => info!("Terminated"),
+    }
+    shutdown.cancel();
+}
+
+/// Waits on the server's tasks at shutdown, a stage at a time, each stage
+/// bounded by the shutdown timeout.
+struct Drain {
+    timeout: Duration,
+    deadline: Instant,
+    /// What failed or had to be given up on, by name.
+    failed: Vec<String>,
+}
+
+impl Drain {
+    fn new(timeout: Duration) -> Self {
+        Self {
+            timeout,
+            deadline: Instant::now() + timeout,
+            failed: Vec::new(),
+        }
+    }
+
+    /// Starts the next stage, with a deadline of its own.
+    fn stage(&mut self) {
+        self.deadline = Instant::now() + self.timeout;
+    }
+
+    /// `task`'s output, or `None` if it failed or had to be aborted.
+    async fn join<T>(&mut self, name: &str, task: JoinHandle<T>) -> Option<T> {
+        let abort = task.abort_handle();
+        match self.finish(name, task).await {
+            Some(Ok(output)) => Some(output),
+            Some(Err(e)) => {
+                warn!("{} failed: {}", name, e);
+                self.failed.push(name.to_string());
+                None
+            }
+            None => {
+                abort.abort();
+                None
+            }
+        }
+    }
+
+    /// `work`'s output, or `None` if the stage's deadline passes first.
+    async fn finish<T>(&mut self, name: &str, work: impl Future<Output = T>) -> Option<T> {
+        match tokio::time::timeout_at(self.deadline, work).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                warn!("{} did not stop within {:?}", name, self.timeout);
+                self.failed.push(name.to_string());
+                None
+            }
+        }
+    }
+}
+
+/// `path`, if it's set and there is a file there.
+fn existing(path: &Option<PathBuf>) -> Option<&Path> {
+    path.as_deref().filter(|path| path.exists())
+}
+
+fn read_state<T: DeserializeOwned>(path: &Path) -> anyhow::Result<T> {
+    let bytes = std::fs::read(path).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))?;
+    serde_json::from_slice(&bytes).map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+fn write_state(path: &Path, state: &impl Serialize) -> anyhow::Result<()> {
+    std::fs::write(path, serde_json::to_vec(state)?)
+        .map_err(|e| anyhow::anyhow!("{}: {}", path.display(), e))
+}
+
+/// Logs a standard-urgency fee suggestion for each new block the model records,
+/// to eyeball against public fee oracles.
+async fn log_fee_suggestions(model: Arc<GasModel>) {
+    let mut blocks = model.subscribe_blocks();
+    while blocks.changed().await.is_ok() {
+        let Some(number) = *blocks.borrow_and_update() else {
+            continue;
+        };
+        if let Some(suggestion) = model.suggest_fees(Urgency::Standard) {
+            info!(
+                "FEE SUGGESTION after block {} at base fee {}: max fee {} tip {}",
+                number,
+                model.current_fee(),
+                suggestion.max_fee_per_gas,
+                suggestion.max_priority_fee_per_gas
+            );
+        }
+    }
+}
+
+/// Logs every account's nonce stats and the manager's totals each `period`.
+async fn dump_nonce_stats(nonce_manager: Arc<NonceManager>, period: Duration) {
+    let mut ticker = tokio::time::interval(period);
+    // the first tick is immediate and there is nothing to show yet
+    ticker.tick().await;
+    loop {
+        ticker.tick().await;
+        for (chain_id, address) in nonce_manager.accounts() {
+            if let Some(stats) = nonce_manager.stats(chain_id, address) {
+                info!(
+                    "NONCE STATS: sender {} on chain {}: {:?}",
+                    address, chain_id, stats
+                );
+            }
+        }
+        info!("NONCE STATS: {:?}", nonce_manager.counters());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    fn line(input: serde_json::Value) -> Vec<u8> {
+        format!("{}\n", input).into_bytes()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_saves_state_and_restores_it() {
+        let dir = std::env::temp_dir().join(format!("gas_saver_shutdown_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = AppConfig::default();
+        config.nonces.state_file = Some(dir.join("nonces.json"));
+        config.scheduler.state_file = Some(dir.join("scheduler.json"));
+        config.model.state_file = Some(dir.join("model.json"));
+        config.audit.path = Some(dir.join("audit.jsonl"));
+        for path in [
+            &config.nonces.state_file,
+            &config.scheduler.state_file,
+            &config.model.state_file,
+            &config.audit.path,
+        ] {
+            let _ = std::fs::remove_file(path.as_ref().unwrap());
+        }
+
+        // stdin stands in for the feeds: a fee far above what the request bids
+        let fee = Wei::from(Gwei(200)).0 as u64;
+        let (mut feed, input) = tokio::io::duplex(4096);
+        let shutdown = CancellationToken::new();
+        let (served, ()) = tokio::join!(
+            serve(config.clone(), BufReader::new(input), shutdown.clone()),
+            async {
+                let event = GasEvent::BaseFeeUpdate {
+                    base_fee: fee,
+                    timestamp: 1,
+                };
+                feed.write_all(&line(serde_json::json!({ "event": event })))
+                    .await
+                    .unwrap();
+                let req = scripted_request(0, Gwei(10).into());
+                feed.write_all(&line(serde_json::json!({ "request": req })))
+                    .await
+                    .unwrap();
+                // time only moves on once every task has handled what it could
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                // the audit writer is a thread of its own, on the wall clock
+                tokio::time::resume();
+                shutdown.cancel();
+            }
+        );
+        served.unwrap();
+
+        let scheduler: SchedulerSnapshot = read_state(&dir.join("scheduler.json")).unwrap();
+        let pending: Vec<_> = scheduler.pending.iter().map(|req| req.id).collect();
+        assert_eq!(pending, vec![1]);
+        let model: ModelSnapshot = read_state(&dir.join("model.json")).unwrap();
+        assert_eq!(model.base_fees, vec![Wei::from(fee)]);
+        let _: NonceSnapshot = read_state(&dir.join("nonces.json")).unwrap();
+        // flushed before serve returned
+        let audited: Vec<_> = std::fs::read_to_string(dir.join("audit.jsonl"))
+            .unwrap()
+            .lines()
+            .map(|line| {
+                let entry: gas_saver_eth::audit::AuditEntry = serde_json::from_str(line).unwrap();
+                entry.payload.kind()
+            })
+            .collect();
+        assert_eq!(audited, ["event", "request", "decision"]);
+
+        // stdin closing shuts down too; what was restored is saved again
+        drop(feed);
+        let (feed, input) = tokio::io::duplex(4096);
+        drop(feed);
+        let shutdown = CancellationToken::new();
+        serve(config, BufReader::new(input), shutdown.clone())
+            .await
+            .unwrap();
+        assert!(shutdown.is_cancelled());
+        let restored: SchedulerSnapshot = read_state(&dir.join("scheduler.json")).unwrap();
+        assert_eq!(restored, scheduler);
+        let model: ModelSnapshot = read_state(&dir.join("model.json")).unwrap();
+        assert_eq!(model.base_fees, vec![Wei::from(fee)]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
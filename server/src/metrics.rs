@@ -0,0 +1,464 @@
+use crate::channel::ChannelStats;
+use crate::events::SchedulerDecision;
+use crate::limiter::RateLimiterStats;
+use crate::nonce::NonceManager;
+use crate::units::Wei;
+use axum::Router;
+use axum::http::header::CONTENT_TYPE;
+use axum::routing::get;
+use parking_lot::Mutex;
+use prometheus::{
+    Counter, CounterVec, Encoder, Gauge, Histogram, HistogramOpts, IntCounter, IntCounterVec,
+    IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Bucket bounds of the latency histograms, in seconds: from a block to an hour.
+const LATENCY_BUCKETS: [f64; 10] = [
+    1.0, 5.0, 12.0, 30.0, 60.0, 120.0, 300.0, 600.0, 1800.0, 3600.0,
+];
+
+/// The scheduler's Prometheus metrics, under their own registry. Attach with
+/// `Scheduler::with_metrics` and serve with `router`.
+pub struct Metrics {
+    registry: Registry,
+    decisions: IntCounterVec,
+    pending: IntGauge,
+    submitted: IntGauge,
+    base_fee: Gauge,
+    volatility: Gauge,
+    limiter_acquired: IntCounter,
+    limiter_rejected: IntCounter,
+    limiter_rejection_ratio: Gauge,
+    nonce_allocations: IntCounter,
+    nonce_releases: IntCounter,
+    nonce_resyncs: IntCounter,
+    time_to_submit: Histogram,
+    time_to_confirm: Histogram,
+    nonce_manager: Mutex<Option<Arc<NonceManager>>>,
+    channel_depth: IntGaugeVec,
+    channel_high_water: IntGaugeVec,
+    channel_dropped: IntCounterVec,
+    channel_rejected: IntCounterVec,
+    channel_blocked_sends: IntCounterVec,
+    channel_send_wait: CounterVec,
+    channels: Mutex<Vec<(String, ChannelStats)>>,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::new(name, help).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let int_gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let gauge = |name: &str, help: &str| {
+            let gauge = Gauge::new(name, help).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let histogram = |name: &str, help: &str| {
+            let opts = HistogramOpts::new(name, help).buckets(LATENCY_BUCKETS.to_vec());
+            let histogram = Histogram::with_opts(opts).unwrap();
+            registry.register(Box::new(histogram.clone())).unwrap();
+            histogram
+        };
+        let decisions = IntCounterVec::new(
+            Opts::new("gas_saver_decisions_total", "Decisions emitted, by type."),
+            &["decision"],
+        )
+        .unwrap();
+        registry.register(Box::new(decisions.clone())).unwrap();
+        let per_channel = |name: &str, help: &str| {
+            let opts = Opts::new(name, help);
+            let counter = IntCounterVec::new(opts, &["channel"]).unwrap();
+            registry.register(Box::new(counter.clone())).unwrap();
+            counter
+        };
+        let per_channel_gauge = |name: &str, help: &str| {
+            let opts = Opts::new(name, help);
+            let gauge = IntGaugeVec::new(opts, &["channel"]).unwrap();
+            registry.register(Box::new(gauge.clone())).unwrap();
+            gauge
+        };
+        let channel_send_wait = CounterVec::new(
+            Opts::new(
+                "gas_saver_channel_send_wait_seconds_total",
+                "Time senders spent waiting on a full channel.",
+            ),
+            &["channel"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(channel_send_wait.clone()))
+            .unwrap();
+
+        Self {
+            decisions,
+            pending: int_gauge("gas_saver_pending_txs", "Requests waiting to be submitted."),
+            submitted: int_gauge(
+                "gas_saver_submitted_txs",
+                "Submitted txs not yet confirmed or given up on.",
+            ),
+            base_fee: gauge("gas_saver_base_fee_wei", "Latest base fee sample."),
+            volatility: gauge(
+                "gas_saver_fee_volatility_wei",
+                "Spread of block-to-block base fee changes.",
+            ),
+            limiter_acquired: counter(
+                "gas_saver_limiter_acquired_total",
+                "Submissions the shared limiter let through.",
+            ),
+            limiter_rejected: counter(
+                "gas_saver_limiter_rejected_total",
+                "Submissions the shared limiter held back.",
+            ),
+            limiter_rejection_ratio: gauge(
+                "gas_saver_limiter_rejection_ratio",
+                "Share of limiter requests refused since start.",
+            ),
+            nonce_allocations: counter(
+                "gas_saver_nonce_allocations_total",
+                "Nonces handed out, reused ones included.",
+            ),
+            nonce_releases: counter(
+                "gas_saver_nonce_releases_total",
+                "Nonces handed back unused.",
+            ),
+            nonce_resyncs: counter(
+                "gas_saver_nonce_resyncs_total",
+                "Network nonces applied to tracked accounts.",
+            ),
+            time_to_submit: histogram(
+                "gas_saver_time_to_submit_seconds",
+                "From a request's acceptance to its Submit.",
+            ),
+            time_to_confirm: histogram(
+                "gas_saver_time_to_confirm_seconds",
+                "From a request's acceptance to its confirmation.",
+            ),
+            nonce_manager: Mutex::new(None),
+            channel_depth: per_channel_gauge(
+                "gas_saver_channel_depth",
+                "Messages queued in a channel.",
+            ),
+            channel_high_water: per_channel_gauge(
+                "gas_saver_channel_high_water",
+                "Most messages seen queued in a channel.",
+            ),
+            channel_dropped: per_channel(
+                "gas_saver_channel_dropped_total",
+                "Queued messages a full channel dropped to make room.",
+            ),
+            channel_rejected: per_channel(
+                "gas_saver_channel_rejected_total",
+                "Sends a full channel turned away.",
+            ),
+            channel_blocked_sends: per_channel(
+                "gas_saver_channel_blocked_sends_total",
+                "Sends that waited on a full channel.",
+            ),
+            channel_send_wait,
+            channels: Mutex::new(Vec::new()),
+            registry,
+        }
+    }
+
+    /// For registering further collectors next to these.
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Reports `nonce_manager`'s counters from now on, read at each scrape.
+    pub fn watch_nonces(&self, nonce_manager: Arc<NonceManager>) {
+        *self.nonce_manager.lock() = Some(nonce_manager);
+    }
+
+    /// Reports the channel behind `stats` by `name`, read at each scrape.
+    pub fn watch_channel(&self, name: impl Into<String>, stats: ChannelStats) {
+        self.channels.lock().push((name.into(), stats));
+    }
+
+    /// Everything registered, in the Prometheus text format.
+    pub fn render(&self) -> String {
+        if let Some(nonce_manager) = &*self.nonce_manager.lock() {
+            let counters = nonce_manager.counters();
+            catch_up(&self.nonce_allocations, counters.allocations);
+            catch_up(&self.nonce_releases, counters.releases);
+            catch_up(&self.nonce_resyncs, counters.resyncs);
+        }
+        for (name, stats) in &*self.channels.lock() {
+            let label = [name.as_str()];
+            self.channel_depth
+                .with_label_values(&label)
+                .set(stats.depth().unwrap_or(0) as i64);
+            self.channel_high_water
+                .with_label_values(&label)
+                .set(stats.high_water() as i64);
+            let counters = [
+                (&self.channel_dropped, stats.dropped()),
+                (&self.channel_rejected, stats.rejected()),
+                (&self.channel_blocked_sends, stats.blocked_sends()),
+            ];
+            for (counter, total) in counters {
+                catch_up(&counter.with_label_values(&label), total);
+            }
+            let send_wait = self.channel_send_wait.with_label_values(&label);
+            catch_up_secs(&send_wait, stats.send_wait());
+        }
+        let mut out = Vec::new();
+        // only fails on a writer that does
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut out)
+            .unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    pub(crate) fn record_decision(&self, decision: &SchedulerDecision) {
+        self.decisions.with_label_values(&[decision.kind()]).inc();
+    }
+
+    pub(crate) fn observe_submit(&self, since_acceptance: Duration) {
+        self.time_to_submit.observe(since_acceptance.as_secs_f64());
+    }
+
+    pub(crate) fn observe_confirm(&self, since_acceptance: Duration) {
+        self.time_to_confirm.observe(since_acceptance.as_secs_f64());
+    }
+
+    pub(crate) fn set_queues(&self, pending: usize, submitted: usize) {
+        self.pending.set(pending as i64);
+        self.submitted.set(submitted as i64);
+    }
+
+    pub(crate) fn set_market(&self, current_fee: Wei, volatility: f64) {
+        self.base_fee.set(current_fee.0 as f64);
+        self.volatility.set(volatility);
+    }
+
+    pub(crate) fn set_limiter(&self, stats: RateLimiterStats) {
+        catch_up(&self.limiter_acquired, stats.acquired);
+        catch_up(&self.limiter_rejected, stats.rejected);
+        self.limiter_rejection_ratio.set(stats.rejection_ratio());
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Moves a counter mirroring a running total up to it.
+fn catch_up(counter: &IntCounter, total: u64) {
+    counter.inc_by(total.saturating_sub(counter.get()));
+}
+
+/// `catch_up` for a running total of time.
+fn catch_up_secs(counter: &Counter, total: Duration) {
+    counter.inc_by((total.as_secs_f64() - counter.get()).max(0.0));
+}
+
+/// `GET /metrics`, rendering `metrics` on every scrape.
+pub fn router(metrics: Arc<Metrics>) -> Router {
+    Router::new().route(
+        "/metrics",
+        get(move || {
+            let metrics = metrics.clone();
+            async move { ([(CONTENT_TYPE, prometheus::TEXT_FORMAT)], metrics.render()) }
+        }),
+    )
+}
+
+/// Serves `router(metrics)` on `listener` until the listener fails.
+pub async fn serve(
+    listener: tokio::net::TcpListener,
+    metrics: Arc<Metrics>,
+) -> std::io::Result<()> {
+    axum::serve(listener, router(metrics)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::{BackpressurePolicy, instrumented_channel};
+    use crate::events::{
+        FeeMode, GasEvent, SchedulerCommand, SubmissionPrivacy, TransactionRequest, TxStatus,
+        Urgency,
+    };
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::NonceAllocator;
+    use crate::scheduler::{Scheduler, SchedulerConfig, SchedulerHandle};
+    use crate::sink::ChannelSink;
+    use alloy_primitives::Address;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio::sync::mpsc;
+
+    fn request(id: u64) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    /// `GET /metrics` over a fresh connection; the body of a 200 response.
+    async fn scrape(addr: std::net::SocketAddr) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        let request = "GET /metrics HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n";
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200"), "{}", head);
+        assert!(head.contains(prometheus::TEXT_FORMAT), "{}", head);
+        body.to_string()
+    }
+
+    /// The value of the sample named exactly `series`, labels included.
+    fn sample(text: &str, series: &str) -> Option<f64> {
+        text.lines()
+            .find_map(|line| line.strip_prefix(series)?.strip_prefix(' '))
+            .map(|value| value.parse().unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_scrape_after_a_confirmed_tx() {
+        let metrics = Arc::new(Metrics::new());
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (decision_tx, _decision_rx) = mpsc::channel::<SchedulerDecision>(100);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let nonce_manager = Arc::new(NonceManager::new());
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        metrics.watch_nonces(nonce_manager.clone());
+        let scheduler = Arc::new(
+            Scheduler::new(
+                SchedulerConfig::default(),
+                Arc::new(GasModel::new(10)),
+                nonce_manager,
+                Arc::new(RateLimiter::new(100, 100)),
+                vec![Box::new(ChannelSink::new(decision_tx))],
+            )
+            .with_metrics(metrics.clone()),
+        );
+        let scheduler_task = tokio::spawn(scheduler.run(event_rx, req_rx, cmd_rx));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, metrics));
+
+        for (n, base_fee) in [40, 44, 42].into_iter().enumerate() {
+            let timestamp = n as u64;
+            let event = GasEvent::BaseFeeUpdate {
+                base_fee,
+                timestamp,
+            };
+            event_tx.send(event).await.unwrap();
+        }
+        let mut statuses = handle.submit_with_status(request(1)).await.unwrap();
+        // commands and events go ahead of each other, so each waits on the last
+        let mut wait_for = async |wanted: fn(&TxStatus) -> bool| {
+            while !wanted(&statuses.recv().await.unwrap()) {}
+        };
+        wait_for(|status| matches!(status, TxStatus::Submitted { .. })).await;
+        let tx_hash = [0x11; 32];
+        let cmd = SchedulerCommand::Broadcast { tx_id: 1, tx_hash };
+        handle.command(cmd).await.unwrap();
+        wait_for(|status| matches!(status, TxStatus::Broadcast { .. })).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash,
+            block_number: 7,
+            effective_gas_price: 44,
+            gas_used: 21_000,
+        };
+        event_tx.send(confirmed).await.unwrap();
+        wait_for(|status| matches!(status, TxStatus::Confirmed { .. })).await;
+        drop((event_tx, handle));
+        scheduler_task.await.unwrap();
+
+        let text = scrape(addr).await;
+        server.abort();
+        assert_eq!(
+            sample(&text, r#"gas_saver_decisions_total{decision="submit"}"#),
+            Some(1.0)
+        );
+        assert_eq!(
+            sample(&text, r#"gas_saver_decisions_total{decision="confirmed"}"#),
+            Some(1.0)
+        );
+        assert_eq!(sample(&text, "gas_saver_base_fee_wei"), Some(42.0));
+        assert!(sample(&text, "gas_saver_fee_volatility_wei").unwrap() > 0.0);
+        assert_eq!(sample(&text, "gas_saver_pending_txs"), Some(0.0));
+        assert_eq!(sample(&text, "gas_saver_submitted_txs"), Some(0.0));
+        assert_eq!(sample(&text, "gas_saver_limiter_acquired_total"), Some(1.0));
+        assert_eq!(
+            sample(&text, "gas_saver_limiter_rejection_ratio"),
+            Some(0.0)
+        );
+        assert_eq!(
+            sample(&text, "gas_saver_nonce_allocations_total"),
+            Some(1.0)
+        );
+        assert_eq!(
+            sample(&text, "gas_saver_time_to_submit_seconds_count"),
+            Some(1.0)
+        );
+        assert_eq!(
+            sample(&text, "gas_saver_time_to_confirm_seconds_count"),
+            Some(1.0)
+        );
+        assert!(sample(&text, "gas_saver_time_to_confirm_seconds_sum").unwrap() < 1.0);
+    }
+
+    #[tokio::test]
+    async fn test_channel_lag_is_exposed() {
+        let metrics = Metrics::new();
+        let (tx, mut rx) = instrumented_channel(2, BackpressurePolicy::Reject);
+        metrics.watch_channel("requests", tx.stats());
+        for n in 0..3 {
+            let _ = tx.send(n).await;
+        }
+
+        let text = metrics.render();
+        let requests =
+            |series: &str| sample(&text, &format!(r#"{}{{channel="requests"}}"#, series));
+        assert_eq!(requests("gas_saver_channel_depth"), Some(2.0));
+        assert_eq!(requests("gas_saver_channel_high_water"), Some(2.0));
+        assert_eq!(requests("gas_saver_channel_rejected_total"), Some(1.0));
+        assert_eq!(requests("gas_saver_channel_dropped_total"), Some(0.0));
+        assert_eq!(requests("gas_saver_channel_blocked_sends_total"), Some(0.0));
+
+        // counters only ever move up to the channel's totals
+        rx.recv().await.unwrap();
+        let _ = tx.send(3).await;
+        let text = metrics.render();
+        let requests =
+            |series: &str| sample(&text, &format!(r#"{}{{channel="requests"}}"#, series));
+        assert_eq!(requests("gas_saver_channel_depth"), Some(2.0));
+        assert_eq!(requests("gas_saver_channel_rejected_total"), Some(1.0));
+    }
+}
@@ -1,25 +1,76 @@
+use crate::events::Urgency;
+use crate::units::{Gwei, Wei};
 use parking_lot::RwLock;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::time::Instant;
+
+/// A (max fee, tip) pair suitable for filling in a `TransactionRequest`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeSuggestion {
+    pub max_fee_per_gas: Wei,
+    pub max_priority_fee_per_gas: Wei,
+}
+
+/// A `GasModel`'s samples, oldest first, to carry them across a restart.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ModelSnapshot {
+    pub base_fees: Vec<Wei>,
+    pub rewards: Vec<Vec<Wei>>,
+}
 
 pub struct GasModel {
-    history: RwLock<VecDeque<u64>>,
+    history: RwLock<VecDeque<Wei>>,
+    /// Per-block tip percentiles, ascending, as `eth_feeHistory` reports them.
+    rewards: RwLock<VecDeque<Vec<Wei>>>,
     max_history: usize,
+    /// When the last base fee came in.
+    last_update: RwLock<Option<Instant>>,
+    /// Number of the last block recorded with `update_block`.
+    blocks: watch::Sender<Option<u64>>,
 }
 
 impl GasModel {
     pub fn new(max_history: usize) -> Self {
         Self {
             history: RwLock::new(VecDeque::with_capacity(max_history)),
+            rewards: RwLock::new(VecDeque::with_capacity(max_history)),
             max_history,
+            last_update: RwLock::new(None),
+            blocks: watch::Sender::new(None),
         }
     }
 
-    pub fn update(&self, base_fee: u64) {
+    pub fn update(&self, base_fee: Wei) {
         let mut history = self.history.write();
         if history.len() >= self.max_history {
             history.pop_front();
         }
         history.push_back(base_fee);
+        *self.last_update.write() = Some(Instant::now());
+    }
+
+    /// Records a mined block's base fee, as `update` does, and announces the block
+    /// to `subscribe_blocks` receivers.
+    pub fn update_block(&self, number: u64, base_fee: Wei) {
+        self.update(base_fee);
+        self.blocks.send_replace(Some(number));
+    }
+
+    /// Watches the number of the last block given to `update_block`; None before
+    /// the first. A slow receiver sees only the latest block.
+    pub fn subscribe_blocks(&self) -> watch::Receiver<Option<u64>> {
+        self.blocks.subscribe()
+    }
+
+    /// Time since the last `update`; None before the first, snapshots imported
+    /// with `import` included.
+    pub fn since_last_update(&self) -> Option<Duration> {
+        self.last_update.read().map(|at| at.elapsed())
     }
 
     // this is used to determine if the gas price should be increased or decreased
@@ -29,34 +80,128 @@ impl GasModel {
             return 0.0;
         }
 
-        let first = *history.front().unwrap() as f64;
-        let last = *history.back().unwrap() as f64;
+        let first = history.front().unwrap().0 as f64;
+        let last = history.back().unwrap().0 as f64;
 
         (last - first) / history.len() as f64
     }
 
+    // standard deviation of block-to-block fee changes; a steady ramp is not volatile,
+    // as `test_model` has always expected. Two changes, so three fees, are the least
+    // that can disagree.
     pub fn get_volatility(&self) -> f64 {
         let history = self.history.read();
-        if history.len() < 2 {
+        if history.len() < 3 {
             return 0.0;
         }
 
-        let mean = history.iter().sum::<u64>() as f64 / history.len() as f64;
-        let variance = history
+        let deltas: Vec<f64> = history
+            .iter()
+            .zip(history.iter().skip(1))
+            .map(|(a, b)| b.0 as f64 - a.0 as f64)
+            .collect();
+        let mean = deltas.iter().sum::<f64>() / deltas.len() as f64;
+        let variance = deltas
             .iter()
             .map(|&x| {
-                let diff = x as f64 - mean;
+                let diff = x - mean;
                 diff * diff
             })
             .sum::<f64>()
-            / history.len() as f64;
+            / deltas.len() as f64;
 
         variance.sqrt()
     }
 
+    /// Records one block's tip percentiles, lowest percentile first.
+    pub fn update_rewards(&self, rewards: Vec<Wei>) {
+        let mut history = self.rewards.write();
+        if history.len() >= self.max_history {
+            history.pop_front();
+        }
+        history.push_back(rewards);
+    }
+
+    /// Records a gas oracle's tips as one more block's percentiles: the standard
+    /// tip for Low and Standard, the fast one for High. `suggest_tip` then takes the
+    /// median over the oracle's estimates and the chain's blocks alike.
+    pub fn update_oracle_tips(&self, standard: Wei, fast: Wei) {
+        self.update_rewards(vec![standard, standard, fast]);
+    }
+
+    /// Median over the recorded blocks of the tip percentile matching `urgency`: the
+    /// lowest reported for Low, the middle for Standard, the highest for High. None
+    /// until a block with percentiles has been recorded.
+    pub fn suggest_tip(&self, urgency: Urgency) -> Option<Wei> {
+        let history = self.rewards.read();
+        let mut tips: Vec<Wei> = history
+            .iter()
+            .filter(|rewards| !rewards.is_empty())
+            .map(|rewards| match urgency {
+                Urgency::Low => rewards[0],
+                Urgency::Standard => rewards[(rewards.len() - 1) / 2],
+                Urgency::High => rewards[rewards.len() - 1],
+            })
+            .collect();
+        if tips.is_empty() {
+            return None;
+        }
+        tips.sort_unstable();
+        Some(tips[tips.len() / 2])
+    }
+
+    pub fn export(&self) -> ModelSnapshot {
+        ModelSnapshot {
+            base_fees: self.history.read().iter().copied().collect(),
+            rewards: self.rewards.read().iter().cloned().collect(),
+        }
+    }
+
+    /// Replaces the samples with `snapshot`'s, keeping the newest `max_history`
+    /// of each.
+    pub fn import(&self, snapshot: ModelSnapshot) {
+        let newest = |len: usize| len.saturating_sub(self.max_history);
+        let skip = newest(snapshot.base_fees.len());
+        *self.history.write() = snapshot.base_fees.into_iter().skip(skip).collect();
+        let skip = newest(snapshot.rewards.len());
+        *self.rewards.write() = snapshot.rewards.into_iter().skip(skip).collect();
+    }
+
+    /// Samples currently held, at most `max_history`.
+    pub fn sample_count(&self) -> usize {
+        self.history.read().len()
+    }
+
     // latest fee at tail of queue
-    pub fn current_fee(&self) -> u64 {
-        self.history.read().back().copied().unwrap_or(0)
+    pub fn current_fee(&self) -> Wei {
+        self.history.read().back().copied().unwrap_or_default()
+    }
+
+    /// Suggests fees from the next-block base fee prediction plus headroom. More urgent
+    /// requests budget for more consecutive full blocks (12.5% each under EIP-1559) and
+    /// more volatility. The tip comes from `suggest_tip` once rewards are known.
+    /// Returns None until there are enough samples to predict.
+    pub fn suggest_fees(&self, urgency: Urgency) -> Option<FeeSuggestion> {
+        if self.history.read().len() < 2 {
+            return None;
+        }
+
+        let current = self.current_fee().0 as f64;
+        let predicted = current + self.get_trend().max(0.0);
+        let (blocks, tip) = match urgency {
+            Urgency::Low => (1, Gwei(1)),
+            Urgency::Standard => (2, Gwei(2)),
+            Urgency::High => (4, Gwei(5)),
+        };
+        let headroom =
+            predicted * (1.125f64.powi(blocks) - 1.0) + self.get_volatility() * blocks as f64;
+        let base = Wei((predicted + headroom).ceil().max(current) as u128);
+        let tip = self.suggest_tip(urgency).unwrap_or(tip.into());
+
+        Some(FeeSuggestion {
+            max_fee_per_gas: base + tip,
+            max_priority_fee_per_gas: tip,
+        })
     }
 }
 
@@ -64,17 +209,144 @@ impl GasModel {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_export_and_import() {
+        let model = GasModel::new(3);
+        for fee in [10, 20, 30, 40] {
+            model.update(Wei(fee));
+        }
+        model.update_rewards(vec![Wei(1), Wei(2)]);
+        let snapshot = model.export();
+        assert_eq!(snapshot.base_fees, vec![Wei(20), Wei(30), Wei(40)]);
+
+        let restored = GasModel::new(2);
+        restored.import(snapshot);
+        assert_eq!(restored.sample_count(), 2);
+        assert_eq!(restored.current_fee(), Wei(40));
+        assert_eq!(restored.get_trend(), 5.0);
+        assert_eq!(restored.suggest_tip(Urgency::High), Some(Wei(2)));
+    }
+
+    #[test]
+    fn test_blocks_are_announced() {
+        let model = GasModel::new(10);
+        let mut blocks = model.subscribe_blocks();
+        model.update(Wei(10));
+        assert!(!blocks.has_changed().unwrap());
+
+        model.update_block(7, Wei(20));
+        assert!(blocks.has_changed().unwrap());
+        assert_eq!(*blocks.borrow_and_update(), Some(7));
+        assert_eq!(model.current_fee(), Wei(20));
+        assert_eq!(model.sample_count(), 2);
+    }
+
     #[test]
     fn test_model() {
         let model = GasModel::new(10);
-        model.update(10);
-        model.update(20);
-        model.update(30);
+        model.update(Wei(10));
+        model.update(Wei(20));
+        model.update(Wei(30));
         let gas_price = model.current_fee();
         let trend = model.get_trend();
 
-        assert_eq!(gas_price, 30);
+        assert_eq!(gas_price, Wei(30));
         assert_eq!(trend, 6.666666666666667);
         assert_eq!(model.get_volatility(), 0.0);
     }
+
+    #[test]
+    fn test_volatility_measures_changes_not_levels() {
+        let ramp = GasModel::new(10);
+        let choppy = GasModel::new(10);
+        for (i, fee) in [10, 40, 10, 40, 10].into_iter().enumerate() {
+            ramp.update(Wei(100 * (i as u128 + 1)));
+            choppy.update(Wei(fee));
+        }
+        assert_eq!(ramp.get_volatility(), 0.0);
+        assert_eq!(choppy.get_volatility(), 30.0);
+
+        let two = GasModel::new(10);
+        two.update(Wei(10));
+        two.update(Wei(90));
+        assert_eq!(two.get_volatility(), 0.0);
+    }
+
+    #[test]
+    fn test_suggest_fees_cold_model() {
+        let model = GasModel::new(10);
+        assert_eq!(model.suggest_fees(Urgency::Standard), None);
+        model.update(Wei(10));
+        assert_eq!(model.suggest_fees(Urgency::Standard), None);
+    }
+
+    #[test]
+    fn test_suggest_fees_headroom_grows_with_volatility() {
+        let calm = GasModel::new(10);
+        let choppy = GasModel::new(10);
+        for fee in [100, 100, 100, 100] {
+            calm.update(Wei(fee));
+        }
+        for fee in [100, 60, 140, 100] {
+            choppy.update(Wei(fee));
+        }
+        assert_eq!(calm.current_fee(), choppy.current_fee());
+
+        let calm_fee = calm.suggest_fees(Urgency::Standard).unwrap();
+        let choppy_fee = choppy.suggest_fees(Urgency::Standard).unwrap();
+        assert_eq!(calm_fee.max_fee_per_gas, Wei(127) + Gwei(2).into());
+        assert!(choppy_fee.max_fee_per_gas > calm_fee.max_fee_per_gas);
+        assert_eq!(
+            calm_fee.max_priority_fee_per_gas,
+            choppy_fee.max_priority_fee_per_gas
+        );
+    }
+
+    #[test]
+    fn test_suggest_fees_never_below_base_fee() {
+        let model = GasModel::new(10);
+        for fee in [200, 150, 100, 50] {
+            model.update(Wei(fee));
+        }
+        for urgency in [Urgency::Low, Urgency::Standard, Urgency::High] {
+            let s = model.suggest_fees(urgency).unwrap();
+            assert!(s.max_fee_per_gas - s.max_priority_fee_per_gas >= Wei(50));
+        }
+        let low = model.suggest_fees(Urgency::Low).unwrap();
+        let high = model.suggest_fees(Urgency::High).unwrap();
+        assert!(high.max_fee_per_gas > low.max_fee_per_gas);
+        assert!(high.max_priority_fee_per_gas > low.max_priority_fee_per_gas);
+    }
+
+    #[test]
+    fn test_suggest_tip_from_rewards() {
+        let model = GasModel::new(3);
+        model.update(Wei(100));
+        model.update(Wei(100));
+        assert_eq!(model.suggest_tip(Urgency::Standard), None);
+        assert_eq!(
+            model
+                .suggest_fees(Urgency::Standard)
+                .unwrap()
+                .max_priority_fee_per_gas,
+            Gwei(2).into()
+        );
+
+        // 10th, 50th and 90th percentiles of four blocks; the oldest falls out
+        for rewards in [[1, 1, 1], [2, 5, 9], [1, 3, 20], [3, 4, 8]] {
+            model.update_rewards(rewards.map(Wei).to_vec());
+        }
+        assert_eq!(model.suggest_tip(Urgency::Low), Some(Wei(2)));
+        assert_eq!(model.suggest_tip(Urgency::Standard), Some(Wei(4)));
+        assert_eq!(model.suggest_tip(Urgency::High), Some(Wei(9)));
+        let standard = model.suggest_fees(Urgency::Standard).unwrap();
+        assert_eq!(standard.max_priority_fee_per_gas, Wei(4));
+        assert_eq!(standard.max_fee_per_gas, Wei(127) + Wei(4));
+
+        // an oracle estimate counts as one more block, pushing out the oldest
+        model.update_oracle_tips(Wei(6), Wei(30));
+        assert_eq!(model.suggest_tip(Urgency::Low), Some(Wei(3)));
+        assert_eq!(model.suggest_tip(Urgency::Standard), Some(Wei(4)));
+        assert_eq!(model.suggest_tip(Urgency::High), Some(Wei(20)));
+    }
 }
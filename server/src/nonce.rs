@@ -1,44 +1,2574 @@
+use crate::events::DeferReason;
+use alloy_primitives::Address;
+use async_trait::async_trait;
+use borsh::{BorshDeserialize, BorshSerialize};
 use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use parking_lot::{Mutex, RwLock};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::ops::Range;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{OnceCell, broadcast};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProviderError {
+    /// The backing node could not be reached.
+    Unavailable(String),
+    Other(String),
+}
+
+impl std::fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProviderError::Unavailable(msg) => write!(f, "nonce source unavailable: {}", msg),
+            ProviderError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NonceError {
+    /// The address's starting nonce was never set from the network.
+    Uninitialized,
+    /// Fetching the starting nonce failed.
+    Provider(ProviderError),
+    /// The reservation outlived its TTL or a resync, and its nonce was reclaimed.
+    ReservationExpired,
+    /// The nonce would sit more than `max_inflight` ahead of the chain.
+    WindowFull,
+}
+
+impl std::fmt::Display for NonceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            NonceError::Uninitialized => write!(f, "nonce not initialized"),
+            NonceError::Provider(e) => write!(f, "nonce initialization failed: {}", e),
+            NonceError::ReservationExpired => write!(f, "nonce reservation expired"),
+            NonceError::WindowFull => write!(f, "in-flight nonce window full"),
+        }
+    }
+}
+
+impl std::error::Error for NonceError {}
+
+impl From<NonceError> for DeferReason {
+    fn from(e: NonceError) -> Self {
+        match e {
+            NonceError::Uninitialized => DeferReason::NonceUninitialized,
+            NonceError::Provider(e) => DeferReason::NonceUnavailable(e.to_string()),
+            NonceError::ReservationExpired => DeferReason::NonceReservationExpired,
+            NonceError::WindowFull => DeferReason::NonceWindowFull,
+        }
+    }
+}
+
+/// Where starting nonces come from, i.e. `eth_getTransactionCount`.
+#[async_trait]
+pub trait NonceProvider: Send + Sync {
+    async fn transaction_count(
+        &self,
+        chain_id: u64,
+        address: Address,
+    ) -> Result<u64, ProviderError>;
+}
+
+/// Fixed in-memory transaction counts; unknown addresses are fresh accounts.
+#[derive(Default)]
+pub struct StaticNonces {
+    counts: RwLock<HashMap<AccountKey, u64>>,
+}
+
+impl StaticNonces {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, chain_id: u64, address: impl Into<Address>, count: u64) {
+        let address = address.into();
+        self.counts.write().insert((chain_id, address), count);
+    }
+}
+
+#[async_trait]
+impl NonceProvider for StaticNonces {
+    async fn transaction_count(
+        &self,
+        chain_id: u64,
+        address: Address,
+    ) -> Result<u64, ProviderError> {
+        Ok(self
+            .counts
+            .read()
+            .get(&(chain_id, address))
+            .copied()
+            .unwrap_or(0))
+    }
+}
+
+/// Released nonces kept per address; anything past this is left as a gap.
+const MAX_FREE_NONCES: usize = 64;
+
+/// Events buffered per `NonceManager::subscribe` receiver. One that falls further
+/// behind gets `RecvError::Lagged` and skips ahead to the oldest buffered event.
+pub const NONCE_EVENT_BUFFER: usize = 1024;
+
+/// Nonce sequences are per chain: (chain id, address).
+pub type AccountKey = (u64, Address);
+
+/// (account, nonce) of a reservation.
+pub type ReservationKey = (AccountKey, u64);
+
+/// An uncommitted reservation: when it lapses, if ever, and who took it.
+#[derive(Debug, Clone, Copy)]
+struct Hold {
+    expires_at: Option<Instant>,
+    origin: NonceOrigin,
+}
+
+#[derive(Debug, Default)]
+struct AccountNonces {
+    /// Next never-allocated nonce.
+    next: u64,
+    /// Released nonces below `next`, handed out again lowest first.
+    free: BTreeSet<u64>,
+    /// Allocated and neither released nor confirmed.
+    outstanding: BTreeSet<u64>,
+    /// Lowest nonce not known to be used on chain.
+    confirmed_next: u64,
+    /// Nonce the account was first initialized at; nothing below it was ours, so
+    /// a rollback never goes there.
+    floor: u64,
+    /// Oldest first, at most `NonceManager::audit_capacity` entries.
+    audit: VecDeque<AuditEntry>,
+}
+
+impl AccountNonces {
+    fn starting_at(next: u64) -> Self {
+        Self {
+            next,
+            confirmed_next: next,
+            floor: next,
+            ..Default::default()
+        }
+    }
+
+    fn audit(&mut self, capacity: usize, nonce: u64, action: AuditAction, origin: NonceOrigin) {
+        if capacity == 0 {
+            return;
+        }
+        if self.audit.len() >= capacity {
+            self.audit.pop_front();
+        }
+        self.audit.push_back(AuditEntry {
+            nonce,
+            at_ms: unix_millis(),
+            action,
+            origin,
+        });
+    }
+
+    fn allocate(&mut self) -> u64 {
+        let nonce = self.free.pop_first().unwrap_or_else(|| {
+            self.next += 1;
+            self.next - 1
+        });
+        self.outstanding.insert(nonce);
+        nonce
+    }
+
+    /// Whether nonces below `end` stay within `max_inflight` of the chain.
+    fn within_window(&self, end: u64, max_inflight: Option<u64>) -> bool {
+        max_inflight.is_none_or(|max| end <= self.confirmed_next.saturating_add(max))
+    }
+
+    /// One past the highest nonce that allocating `count` nonces would hand out: a
+    /// lone allocation reuses a released nonce first, a range is always fresh.
+    fn allocation_end(&self, count: u64) -> u64 {
+        match (count, self.free.first()) {
+            (1, Some(&free)) => free + 1,
+            _ => self.next + count,
+        }
+    }
+
+    fn allocate_within(&mut self, max_inflight: Option<u64>) -> Result<u64, NonceError> {
+        if !self.within_window(self.allocation_end(1), max_inflight) {
+            return Err(NonceError::WindowFull);
+        }
+        Ok(self.allocate())
+    }
+
+    /// Nonces nobody holds between the chain's next nonce and the highest
+    /// outstanding one; txs above them can't be mined.
+    fn gaps(&self) -> Vec<u64> {
+        let Some(&highest) = self.outstanding.last() else {
+            return Vec::new();
+        };
+        (self.confirmed_next..highest)
+            .filter(|nonce| !self.outstanding.contains(nonce))
+            .collect()
+    }
+}
+
+/// Nonce lifecycle changes, from `NonceManager::subscribe`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceEvent {
+    Allocated {
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+    },
+    /// `nonce` was mined, and with it every nonce below.
+    Confirmed {
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+    },
+    Released {
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+    },
+    /// A network nonce was applied; `nonce` is the network's next one.
+    Resynced {
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+    },
+}
+
+/// One account's state, from `NonceManager::stats`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NonceStats {
+    pub next_to_allocate: u64,
+    pub highest_confirmed: Option<u64>,
+    /// Released nonces waiting to be handed out again.
+    pub free_list_len: usize,
+    /// Allocated nonces neither released nor confirmed.
+    pub inflight: usize,
+}
+
+/// From `NonceManager::counters`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NonceCounters {
+    /// Nonces handed out, including ones reused from the free list.
+    pub allocations: u64,
+    pub releases: u64,
+    /// Network nonces applied: `update_nonce`, `resync` and `force_set_nonce`.
+    pub resyncs: u64,
+}
+
+/// What `NonceManager::update_nonce` did with a network nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NonceUpdate {
+    /// The account was unknown and now starts at the network nonce.
+    Initialized,
+    /// The confirmed nonce, and the allocation counter if it was behind, moved up.
+    Raised,
+    /// Already in step with the network.
+    Unchanged,
+    /// The network nonce is below one already known; ignored.
+    Stale,
+}
+
+/// What `NonceManager::resync` changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResyncOutcome {
+    /// Allocation counter before the resync; None if the account was unknown.
+    pub previous_next: Option<u64>,
+    pub next: u64,
+    /// Nonces we had allocated that the network has already used.
+    pub consumed: Vec<u64>,
+}
+
+/// What `NonceManager::reconcile` found and changed.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ReconcileReport {
+    /// Allocation counter before; None if the account was unknown.
+    pub previous_next: Option<u64>,
+    pub next: u64,
+    /// Observed nonces that weren't outstanding and now are.
+    pub adopted: Vec<u64>,
+    /// Observed nonces claimed more than once, or also held by a live reservation.
+    pub duplicates: Vec<u64>,
+    /// Observed nonces the chain has already used; left alone.
+    pub confirmed: Vec<u64>,
+}
+
+impl ReconcileReport {
+    pub fn has_conflicts(&self) -> bool {
+        !self.duplicates.is_empty() || !self.confirmed.is_empty()
+    }
+}
+
+/// Who asked for a nonce operation, as kept in the audit log.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum NonceOrigin {
+    /// A lone tx submitted by the scheduler.
+    Submit,
+    /// One of several txs of a sender submitted as a contiguous range.
+    Batch,
+    /// A self-transfer filling a stale gap.
+    GapFill,
+    /// A tx carried over from before a restart, adopted by `reconcile`.
+    Restore,
+    /// A network nonce: `update_nonce`, `resync`, `force_set_nonce` or a fetch by
+    /// `ensure_initialized`.
+    Sync,
+    /// `NonceManager`'s own allocation and release methods, called directly.
+    Direct,
+}
+
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AuditAction {
+    Allocated,
+    Released,
+    /// `nonce` is the network's next one.
+    Resynced,
+}
+
+/// One line of an account's audit log, from `NonceManager::audit_log`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuditEntry {
+    pub nonce: u64,
+    /// Unix time in milliseconds.
+    pub at_ms: u64,
+    pub action: AuditAction,
+    pub origin: NonceOrigin,
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/// One account's counters as saved by `NonceManager::export`.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AccountSnapshot {
+    pub chain_id: u64,
+    #[borsh(
+        serialize_with = "address_bytes::serialize",
+        deserialize_with = "address_bytes::deserialize"
+    )]
+    #[cfg_attr(
+        feature = "serde",
+        serde(deserialize_with = "address_bytes::hex_or_bytes")
+    )]
+    pub address: Address,
+    pub next: u64,
+    pub confirmed_next: u64,
+    pub floor: u64,
+    /// Released nonces, plus nonces reserved but never committed at export time.
+    pub free: Vec<u64>,
+    pub outstanding: Vec<u64>,
+    /// Oldest first; empty unless the manager keeps an audit log.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub audit: Vec<AuditEntry>,
+}
+
+/// `Address` has no borsh impl of its own; it goes on the wire as its 20 bytes.
+mod address_bytes {
+    use alloy_primitives::Address;
+    use borsh::{BorshDeserialize, BorshSerialize};
+    #[cfg(feature = "serde")]
+    use serde::{Deserialize, Deserializer};
+
+    pub fn serialize<W: std::io::Write>(address: &Address, writer: &mut W) -> std::io::Result<()> {
+        address.into_array().serialize(writer)
+    }
+
+    pub fn deserialize<R: std::io::Read>(reader: &mut R) -> std::io::Result<Address> {
+        <[u8; 20]>::deserialize_reader(reader).map(Address::from)
+    }
+
+    /// JSON snapshots saved before addresses were hex strings hold byte arrays.
+    #[cfg(feature = "serde")]
+    pub fn hex_or_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Address, D::Error> {
+        #[cfg_attr(feature = "serde", derive(Deserialize), serde(untagged))]
+        enum Either {
+            Hex(Address),
+            Bytes([u8; 20]),
+        }
+        Ok(match Either::deserialize(deserializer)? {
+            Either::Hex(address) => address,
+            Either::Bytes(bytes) => Address::from(bytes),
+        })
+    }
+}
+
+/// Everything a restarted `NonceManager` needs to carry on without refetching.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NonceSnapshot {
+    /// Sorted by chain id, then address.
+    pub accounts: Vec<AccountSnapshot>,
+}
+
+/// How `NonceManager::import` treats accounts it already tracks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportPolicy {
+    /// Counters only move up; free and outstanding nonces are merged.
+    #[default]
+    RaiseOnly,
+    /// The snapshot replaces the account, even if that lowers its counters.
+    Overwrite,
+}
+
+/// Nonce bookkeeping the scheduler runs on. `NonceManager` keeps it in process;
+/// scheduler instances signing for the same wallet need one implementation they
+/// all go through, or they hand out the same nonces.
+#[async_trait]
+pub trait NonceAllocator: Send + Sync {
+    fn is_initialized(&self, chain_id: u64, address: Address) -> bool;
+
+    /// Fetches `address`'s starting nonce from `provider` unless it is already
+    /// known, and returns the next nonce to allocate. A failed fetch is retried
+    /// by the next caller.
+    async fn ensure_initialized(
+        &self,
+        chain_id: u64,
+        address: Address,
+        provider: &dyn NonceProvider,
+    ) -> Result<u64, NonceError>;
+
+    /// Peek at the current nonce without incrementing. Released nonces waiting
+    /// on the free list are not considered.
+    fn peek_nonce(&self, chain_id: u64, address: Address) -> u64;
+
+    /// Highest nonce known to be used on chain, by confirmation or resync.
+    fn highest_confirmed(&self, chain_id: u64, address: Address) -> Option<u64>;
+
+    fn lowest_unconfirmed(&self, chain_id: u64, address: Address) -> Option<u64>;
+
+    /// Nonces that were skipped or released below the highest outstanding one and
+    /// are held by no tx, lowest first. Everything above the first of them is stuck.
+    fn detect_gaps(&self, chain_id: u64, address: Address) -> Vec<u64>;
+
+    /// Allocates a nonce, reusing released ones first, and holds it until
+    /// `commit_reservation` or `cancel_reservation`. Usually called through
+    /// `reserve_nonce`, which wraps the hold in a `Reservation`.
+    fn reserve(
+        &self,
+        chain_id: u64,
+        address: Address,
+        origin: NonceOrigin,
+    ) -> Result<u64, NonceError>;
+
+    /// Holds `count` consecutive fresh nonces, allocated in one step so concurrent
+    /// allocations can't interleave with them.
+    fn reserve_contiguous(
+        &self,
+        chain_id: u64,
+        address: Address,
+        count: u64,
+        origin: NonceOrigin,
+    ) -> Result<Range<u64>, NonceError>;
+
+    /// Whether `count` nonces could be reserved now, by `reserve` for one or
+    /// `reserve_contiguous` for several, without allocating anything. Concurrent
+    /// allocations may still take the room before the reservation is made.
+    fn can_allocate(&self, chain_id: u64, address: Address, count: u64) -> Result<(), NonceError>;
+
+    /// Holds one specific nonce out of a gap, e.g. for a filler tx. False unless
+    /// `nonce` is below the allocation counter, unconfirmed and not outstanding.
+    fn reserve_exact(
+        &self,
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+        origin: NonceOrigin,
+    ) -> bool;
+
+    /// Marks a held nonce as used for good. False if the hold had already lapsed.
+    fn commit_reservation(&self, chain_id: u64, address: Address, nonce: u64) -> bool;
+
+    /// Hands a held nonce back, unless the hold already lapsed.
+    fn cancel_reservation(&self, chain_id: u64, address: Address, nonce: u64);
+
+    /// Hands back a committed nonce whose tx left the mempool without being mined,
+    /// so the next allocation can reuse it. False if it wasn't outstanding.
+    fn release_unmined(&self, chain_id: u64, address: Address, nonce: u64) -> bool;
+
+    /// Releases every reservation past its TTL and returns them, oldest nonce
+    /// first per address. Their guards become inert.
+    fn release_expired_reservations(&self) -> Vec<ReservationKey>;
+
+    /// Records that `nonce` was mined, which settles every nonce below it too.
+    fn record_confirmed(&self, chain_id: u64, address: Address, nonce: u64);
+
+    /// Un-confirms `from_nonce` and everything above it after a reorg, never below
+    /// the nonce the account was initialized at. Returns the nonces that are
+    /// pending again; the allocation counter is left alone.
+    fn rollback_confirmed(&self, chain_id: u64, address: Address, from_nonce: u64) -> Vec<u64>;
+
+    /// Takes the network's transaction count `network_next` (e.g. on startup):
+    /// everything below it counts as confirmed, and allocation resumes at whichever
+    /// of it and the local counter is higher. Nothing moves down, so a stale count
+    /// is ignored; see `force_set_nonce` for deliberate lowering. Released nonces
+    /// and reservations below it are forgotten.
+    fn update_nonce(&self, chain_id: u64, address: Address, network_next: u64) -> NonceUpdate;
+
+    /// Resets an account to start over at `next`, even below nonces already handed
+    /// out, e.g. after a deep reorg or an operator override. Every reservation of
+    /// the account is dropped. Returns the allocation counter it replaced.
+    fn force_set_nonce(&self, chain_id: u64, address: Address, next: u64) -> Option<u64>;
+
+    /// Catches up with a network that is ahead of us, typically after the executor
+    /// hit "nonce too low". Like `update_nonce` nothing ever moves down, but it
+    /// reports which allocated nonces the network has used, by our tx or another one.
+    fn resync(&self, chain_id: u64, address: Address, network_next: u64) -> ResyncOutcome;
+
+    /// Squares the account with nonces `observed` in use elsewhere, e.g. by
+    /// submitted txs restored after a restart: every unconfirmed one becomes
+    /// outstanding and the counter moves past the highest, so nothing allocated
+    /// later can collide with them. An unknown account starts at the lowest.
+    /// Nonces claimed twice or already confirmed are reported, not resolved.
+    fn reconcile(&self, chain_id: u64, address: Address, observed: &[u64]) -> ReconcileReport;
+}
+
+impl dyn NonceAllocator {
+    /// Allocates a nonce that goes back to the pool unless committed, either when
+    /// the guard is dropped or once it outlives the reservation TTL, if set.
+    pub fn reserve_nonce(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        origin: NonceOrigin,
+    ) -> Result<Reservation, NonceError> {
+        let address = address.into();
+        let nonce = self.reserve(chain_id, address, origin)?;
+        Ok(Reservation::new(self.clone(), chain_id, address, nonce))
+    }
+
+    /// `reserve_contiguous` with each nonce held by its own reservation.
+    pub fn reserve_range(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        count: u64,
+        origin: NonceOrigin,
+    ) -> Result<Vec<Reservation>, NonceError> {
+        let address = address.into();
+        Ok(self
+            .reserve_contiguous(chain_id, address, count, origin)?
+            .map(|nonce| Reservation::new(self.clone(), chain_id, address, nonce))
+            .collect())
+    }
+
+    /// `reserve_exact` held by a reservation.
+    pub fn reserve_gap(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        nonce: u64,
+        origin: NonceOrigin,
+    ) -> Option<Reservation> {
+        let address = address.into();
+        self.reserve_exact(chain_id, address, nonce, origin)
+            .then(|| Reservation::new(self.clone(), chain_id, address, nonce))
+    }
+}
 
 pub struct NonceManager {
-    /// Maps Address to its next expected nonce
-    nonces: DashMap<[u8; 20], Arc<AtomicU64>>,
+    /// Maps (chain id, address) to its nonce sequence
+    nonces: DashMap<AccountKey, AccountNonces>,
+    /// One fetch per account in `ensure_initialized`; concurrent callers share it.
+    fetches: DashMap<AccountKey, Arc<OnceCell<()>>>,
+    /// Uncommitted reservations.
+    reservations: Mutex<HashMap<ReservationKey, Hold>>,
+    reservation_ttl: Option<Duration>,
+    max_inflight: Option<u64>,
+    /// Audit entries kept per account; 0 keeps none.
+    audit_capacity: usize,
+    allocations: AtomicU64,
+    releases: AtomicU64,
+    resyncs: AtomicU64,
+    /// Only sent to while someone is subscribed; never while holding an entry.
+    events: broadcast::Sender<NonceEvent>,
+}
+
+impl Default for NonceManager {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl NonceManager {
     pub fn new() -> Self {
         Self {
             nonces: DashMap::new(),
+            fetches: DashMap::new(),
+            reservations: Mutex::new(HashMap::new()),
+            reservation_ttl: None,
+            max_inflight: None,
+            audit_capacity: 0,
+            allocations: AtomicU64::new(0),
+            releases: AtomicU64::new(0),
+            resyncs: AtomicU64::new(0),
+            events: broadcast::channel(NONCE_EVENT_BUFFER).0,
+        }
+    }
+
+    /// How long a reservation may go uncommitted before
+    /// `release_expired_reservations` takes its nonce back. Without one,
+    /// reservations only end by commit or drop.
+    pub fn with_reservation_ttl(mut self, ttl: Duration) -> Self {
+        self.reservation_ttl = Some(ttl);
+        self
+    }
+
+    /// Caps how far past an account's confirmed nonce allocations may go, since
+    /// nodes only queue so many future-nonce txs per sender and silently drop the
+    /// rest. Allocations beyond it fail with `NonceError::WindowFull` until
+    /// confirmations catch up.
+    pub fn with_max_inflight(mut self, max_inflight: u64) -> Self {
+        self.max_inflight = Some(max_inflight);
+        self
+    }
+
+    /// Keeps the last `capacity` allocations, releases and resyncs of each account,
+    /// with when and on whose behalf they happened, for `audit_log`. Off by default.
+    pub fn with_audit_capacity(mut self, capacity: usize) -> Self {
+        self.audit_capacity = capacity;
+        self
+    }
+
+    /// Allocates a nonce for an account whose starting nonce has been set with
+    /// `update_nonce`. Released nonces are reused before new ones.
+    pub fn try_next_nonce(
+        &self,
+        chain_id: u64,
+        address: impl Into<Address>,
+    ) -> Result<u64, NonceError> {
+        self.allocate_one(chain_id, address.into(), NonceOrigin::Direct)
+    }
+
+    fn allocate_one(
+        &self,
+        chain_id: u64,
+        address: Address,
+        origin: NonceOrigin,
+    ) -> Result<u64, NonceError> {
+        let nonce = {
+            let mut account = self
+                .nonces
+                .get_mut(&(chain_id, address))
+                .ok_or(NonceError::Uninitialized)?;
+            let nonce = account.allocate_within(self.max_inflight)?;
+            account.audit(self.audit_capacity, nonce, AuditAction::Allocated, origin);
+            nonce
+        };
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.notify(NonceEvent::Allocated {
+            chain_id,
+            address,
+            nonce,
+        });
+        Ok(nonce)
+    }
+
+    /// Allocates a nonce, starting unknown addresses at zero. Only right for fresh
+    /// accounts, e.g. on a local or synthetic chain. Ignores `max_inflight`.
+    pub fn next_nonce_or_zero(&self, chain_id: u64, address: impl Into<Address>) -> u64 {
+        let address = address.into();
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        let nonce = {
+            let mut account = self.nonces.entry((chain_id, address)).or_default();
+            let nonce = account.allocate();
+            let origin = NonceOrigin::Direct;
+            account.audit(self.audit_capacity, nonce, AuditAction::Allocated, origin);
+            nonce
+        };
+        self.notify(NonceEvent::Allocated {
+            chain_id,
+            address,
+            nonce,
+        });
+        nonce
+    }
+
+    /// Allocates `count` consecutive nonces in one step, so concurrent allocations
+    /// can't interleave with them. Always fresh ones: released nonces aren't
+    /// contiguous. All or nothing: unless the whole range fits in the
+    /// `max_inflight` window, nothing is allocated.
+    pub fn try_allocate_range(
+        &self,
+        chain_id: u64,
+        address: impl Into<Address>,
+        count: u64,
+    ) -> Result<Range<u64>, NonceError> {
+        self.allocate_contiguous(chain_id, address.into(), count, NonceOrigin::Direct)
+    }
+
+    fn allocate_contiguous(
+        &self,
+        chain_id: u64,
+        address: Address,
+        count: u64,
+        origin: NonceOrigin,
+    ) -> Result<Range<u64>, NonceError> {
+        let mut account = self
+            .nonces
+            .get_mut(&(chain_id, address))
+            .ok_or(NonceError::Uninitialized)?;
+        let range = account.next..account.next + count;
+        if !account.within_window(range.end, self.max_inflight) {
+            return Err(NonceError::WindowFull);
+        }
+        account.next = range.end;
+        account.outstanding.extend(range.clone());
+        for nonce in range.clone() {
+            account.audit(self.audit_capacity, nonce, AuditAction::Allocated, origin);
+        }
+        drop(account);
+        self.allocations.fetch_add(count, Ordering::Relaxed);
+        for nonce in range.clone() {
+            self.notify(NonceEvent::Allocated {
+                chain_id,
+                address,
+                nonce,
+            });
+        }
+        Ok(range)
+    }
+
+    /// Releases every nonce of a range from `try_allocate_range`, highest first so the
+    /// counter rewinds as far as it can. Returns how many were released.
+    pub fn release_range(
+        &self,
+        chain_id: u64,
+        address: impl Into<Address>,
+        range: Range<u64>,
+    ) -> usize {
+        let address = address.into();
+        range
+            .rev()
+            .filter(|&nonce| self.release_nonce(chain_id, address, nonce))
+            .count()
+    }
+
+    /// Hands back a nonce that was allocated but never broadcast. The most recent
+    /// allocation simply rewinds the counter; an older one goes on a free list for
+    /// the next allocation. Returns false if the nonce was not outstanding or the
+    /// free list is full.
+    pub fn release_nonce(&self, chain_id: u64, address: impl Into<Address>, nonce: u64) -> bool {
+        self.release_one(chain_id, address.into(), nonce, NonceOrigin::Direct)
+    }
+
+    fn release_one(
+        &self,
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+        origin: NonceOrigin,
+    ) -> bool {
+        {
+            let Some(mut entry) = self.nonces.get_mut(&(chain_id, address)) else {
+                return false;
+            };
+            let account = entry.value_mut();
+            if !account.outstanding.contains(&nonce) {
+                return false;
+            }
+            if nonce + 1 == account.next {
+                account.next = nonce;
+                // released nonces right below the new top rewind the counter too
+                while account.next > 0 && account.free.remove(&(account.next - 1)) {
+                    account.next -= 1;
+                }
+            } else if account.free.len() < MAX_FREE_NONCES {
+                account.free.insert(nonce);
+            } else {
+                return false;
+            }
+            account.outstanding.remove(&nonce);
+            account.audit(self.audit_capacity, nonce, AuditAction::Released, origin);
+        }
+        self.releases.fetch_add(1, Ordering::Relaxed);
+        self.notify(NonceEvent::Released {
+            chain_id,
+            address,
+            nonce,
+        });
+        true
+    }
+
+    /// Nonces allocated past the highest confirmed one.
+    pub fn pending_count(&self, chain_id: u64, address: impl Into<Address>) -> u64 {
+        let address = address.into();
+        self.nonces
+            .get(&(chain_id, address))
+            .map(|account| account.next.saturating_sub(account.confirmed_next))
+            .unwrap_or(0)
+    }
+
+    /// Every tracked account, sorted.
+    pub fn accounts(&self) -> Vec<AccountKey> {
+        let mut accounts: Vec<AccountKey> = self.nonces.iter().map(|entry| *entry.key()).collect();
+        accounts.sort_unstable();
+        accounts
+    }
+
+    pub fn stats(&self, chain_id: u64, address: impl Into<Address>) -> Option<NonceStats> {
+        let address = address.into();
+        self.nonces
+            .get(&(chain_id, address))
+            .map(|account| NonceStats {
+                next_to_allocate: account.next,
+                highest_confirmed: account.confirmed_next.checked_sub(1),
+                free_list_len: account.free.len(),
+                inflight: account.outstanding.len(),
+            })
+    }
+
+    /// The account's audit log, oldest first. Empty unless enabled with
+    /// `with_audit_capacity`.
+    pub fn audit_log(&self, chain_id: u64, address: impl Into<Address>) -> Vec<AuditEntry> {
+        let address = address.into();
+        self.nonces
+            .get(&(chain_id, address))
+            .map(|account| account.audit.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Totals across all accounts since the manager was created.
+    pub fn counters(&self) -> NonceCounters {
+        NonceCounters {
+            allocations: self.allocations.load(Ordering::Relaxed),
+            releases: self.releases.load(Ordering::Relaxed),
+            resyncs: self.resyncs.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Receives every nonce event from now on, in the order they happened. See
+    /// `NONCE_EVENT_BUFFER` for receivers that fall behind.
+    pub fn subscribe(&self) -> broadcast::Receiver<NonceEvent> {
+        self.events.subscribe()
+    }
+
+    fn notify(&self, event: NonceEvent) {
+        // fails only when nobody is subscribed
+        let _ = self.events.send(event);
+    }
+
+    fn hold(&self, origin: NonceOrigin) -> Hold {
+        Hold {
+            expires_at: self.reservation_ttl.map(|ttl| Instant::now() + ttl),
+            origin,
         }
     }
 
-    /// Allocates a nonce for a given address.
-    /// If the address is unknown, it should ideally be initialized from the network first.
-    /// For this implementation, we'll assume initialization happens separately.
-    pub fn next_nonce(&self, address: &[u8; 20]) -> u64 {
-        let entry = self
+    /// See `NonceAllocator`'s `reserve_nonce`.
+    pub fn reserve_nonce(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        origin: NonceOrigin,
+    ) -> Result<Reservation, NonceError> {
+        let address = address.into();
+        let allocator: Arc<dyn NonceAllocator> = self.clone();
+        allocator.reserve_nonce(chain_id, address, origin)
+    }
+
+    /// See `NonceAllocator`'s `reserve_range`.
+    pub fn reserve_range(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        count: u64,
+        origin: NonceOrigin,
+    ) -> Result<Vec<Reservation>, NonceError> {
+        let address = address.into();
+        let allocator: Arc<dyn NonceAllocator> = self.clone();
+        allocator.reserve_range(chain_id, address, count, origin)
+    }
+
+    /// See `NonceAllocator`'s `reserve_gap`.
+    pub fn reserve_gap(
+        self: &Arc<Self>,
+        chain_id: u64,
+        address: impl Into<Address>,
+        nonce: u64,
+        origin: NonceOrigin,
+    ) -> Option<Reservation> {
+        let address = address.into();
+        let allocator: Arc<dyn NonceAllocator> = self.clone();
+        allocator.reserve_gap(chain_id, address, nonce, origin)
+    }
+
+    /// Saves every account. Uncommitted reservations die with the process that
+    /// holds them, so their nonces are saved as free.
+    pub fn export(&self) -> NonceSnapshot {
+        let reserved: Vec<ReservationKey> = self.reservations.lock().keys().copied().collect();
+        let mut accounts: Vec<AccountSnapshot> = self
             .nonces
-            .entry(*address)
-            .or_insert_with(|| Arc::new(AtomicU64::new(0)));
-        entry.fetch_add(1, Ordering::SeqCst)
+            .iter()
+            .map(|entry| {
+                let (chain_id, address) = *entry.key();
+                let account = entry.value();
+                let mut free = account.free.clone();
+                let mut outstanding = account.outstanding.clone();
+                for &(key, nonce) in &reserved {
+                    if key == (chain_id, address) && outstanding.remove(&nonce) {
+                        free.insert(nonce);
+                    }
+                }
+                AccountSnapshot {
+                    chain_id,
+                    address,
+                    next: account.next,
+                    confirmed_next: account.confirmed_next,
+                    floor: account.floor,
+                    free: free.into_iter().collect(),
+                    outstanding: outstanding.into_iter().collect(),
+                    audit: account.audit.iter().copied().collect(),
+                }
+            })
+            .collect();
+        accounts.sort_by_key(|account| (account.chain_id, account.address));
+        NonceSnapshot { accounts }
+    }
+
+    /// Restores accounts saved by `export`; `policy` decides what happens to
+    /// accounts that are already tracked. Audit logs are merged by time and cut
+    /// to this manager's capacity.
+    pub fn import(&self, snapshot: NonceSnapshot, policy: ImportPolicy) {
+        for saved in snapshot.accounts {
+            let mut restored = AccountNonces {
+                next: saved.next,
+                free: saved.free.into_iter().collect(),
+                outstanding: saved.outstanding.into_iter().collect(),
+                confirmed_next: saved.confirmed_next,
+                floor: saved.floor,
+                audit: saved.audit.into_iter().collect(),
+            };
+            let excess = restored.audit.len().saturating_sub(self.audit_capacity);
+            restored.audit.drain(..excess);
+            let key = (saved.chain_id, saved.address);
+            match (self.nonces.get_mut(&key), policy) {
+                (Some(mut entry), ImportPolicy::RaiseOnly) => {
+                    let account = entry.value_mut();
+                    account.next = account.next.max(restored.next);
+                    account.confirmed_next = account.confirmed_next.max(restored.confirmed_next);
+                    account.floor = account.floor.min(restored.floor);
+                    account.outstanding.extend(restored.outstanding);
+                    account.free.extend(restored.free);
+                    let confirmed_next = account.confirmed_next;
+                    account.outstanding.retain(|&n| n >= confirmed_next);
+                    let outstanding = &account.outstanding;
+                    account
+                        .free
+                        .retain(|n| *n >= confirmed_next && !outstanding.contains(n));
+                    account.audit.extend(restored.audit);
+                    // stable, so entries of the same millisecond keep their order
+                    account
+                        .audit
+                        .make_contiguous()
+                        .sort_by_key(|entry| entry.at_ms);
+                    let excess = account.audit.len().saturating_sub(self.audit_capacity);
+                    account.audit.drain(..excess);
+                }
+                (entry, _) => {
+                    // release the shard lock before inserting
+                    drop(entry);
+                    self.nonces.insert(key, restored);
+                }
+            }
+        }
+    }
+
+    /// Shared by `update_nonce` and `resync`; also returns the confirmed nonce
+    /// before the raise, None for a new account.
+    fn raise_to(&self, key: AccountKey, network_next: u64) -> (Option<u64>, ResyncOutcome) {
+        self.resyncs.fetch_add(1, Ordering::Relaxed);
+        let raised = match self.nonces.get_mut(&key) {
+            Some(mut entry) => {
+                let account = entry.value_mut();
+                let previous_confirmed = account.confirmed_next;
+                let previous_next = account.next;
+                account.next = account.next.max(network_next);
+                account.confirmed_next = account.confirmed_next.max(network_next);
+                let consumed: Vec<u64> = account
+                    .outstanding
+                    .iter()
+                    .copied()
+                    .filter(|&n| n < network_next)
+                    .collect();
+                account.outstanding.retain(|&n| n >= network_next);
+                account.free.retain(|&n| n >= network_next);
+                let (action, origin) = (AuditAction::Resynced, NonceOrigin::Sync);
+                account.audit(self.audit_capacity, network_next, action, origin);
+                let outcome = ResyncOutcome {
+                    previous_next: Some(previous_next),
+                    next: account.next,
+                    consumed,
+                };
+                (Some(previous_confirmed), outcome)
+            }
+            None => {
+                // entry() rather than insert(): another caller may have just created it
+                let mut entry = self
+                    .nonces
+                    .entry(key)
+                    .or_insert_with(|| AccountNonces::starting_at(network_next));
+                let account = entry.value_mut();
+                account.next = account.next.max(network_next);
+                account.confirmed_next = account.confirmed_next.max(network_next);
+                let (action, origin) = (AuditAction::Resynced, NonceOrigin::Sync);
+                account.audit(self.audit_capacity, network_next, action, origin);
+                let outcome = ResyncOutcome {
+                    previous_next: None,
+                    next: account.next,
+                    consumed: Vec::new(),
+                };
+                (None, outcome)
+            }
+        };
+        self.reservations
+            .lock()
+            .retain(|&(reserved, nonce), _| reserved != key || nonce >= network_next);
+        self.notify(NonceEvent::Resynced {
+            chain_id: key.0,
+            address: key.1,
+            nonce: network_next,
+        });
+        raised
     }
+}
 
-    /// Peek at the current nonce without incrementing
-    pub fn peek_nonce(&self, address: &[u8; 20]) -> u64 {
+#[async_trait]
+impl NonceAllocator for NonceManager {
+    fn is_initialized(&self, chain_id: u64, address: Address) -> bool {
+        self.nonces.contains_key(&(chain_id, address))
+    }
+
+    async fn ensure_initialized(
+        &self,
+        chain_id: u64,
+        address: Address,
+        provider: &dyn NonceProvider,
+    ) -> Result<u64, NonceError> {
+        if !self.is_initialized(chain_id, address) {
+            let fetch = self.fetches.entry((chain_id, address)).or_default().clone();
+            fetch
+                .get_or_try_init(|| async {
+                    let count = provider.transaction_count(chain_id, address).await?;
+                    // allocations made meanwhile may already be past the chain's count
+                    let mut account = self
+                        .nonces
+                        .entry((chain_id, address))
+                        .or_insert_with(|| AccountNonces::starting_at(count));
+                    account.next = account.next.max(count);
+                    let (action, origin) = (AuditAction::Resynced, NonceOrigin::Sync);
+                    account.audit(self.audit_capacity, count, action, origin);
+                    drop(account);
+                    self.notify(NonceEvent::Resynced {
+                        chain_id,
+                        address,
+                        nonce: count,
+                    });
+                    Ok(())
+                })
+                .await
+                .map_err(NonceError::Provider)?;
+        }
+        Ok(self.peek_nonce(chain_id, address))
+    }
+
+    fn peek_nonce(&self, chain_id: u64, address: Address) -> u64 {
         self.nonces
-            .get(address)
-            .map(|v| v.load(Ordering::SeqCst))
+            .get(&(chain_id, address))
+            .map(|account| account.next)
             .unwrap_or(0)
     }
 
-    /// Update the nonce (e.g., if a transaction fails with "nonce too low" or on startup)
-    pub fn update_nonce(&self, address: [u8; 20], new_nonce: u64) {
-        let entry = self
+    fn highest_confirmed(&self, chain_id: u64, address: Address) -> Option<u64> {
+        self.nonces
+            .get(&(chain_id, address))
+            .and_then(|account| account.confirmed_next.checked_sub(1))
+    }
+
+    fn lowest_unconfirmed(&self, chain_id: u64, address: Address) -> Option<u64> {
+        self.nonces
+            .get(&(chain_id, address))
+            .and_then(|account| account.outstanding.first().copied())
+    }
+
+    fn detect_gaps(&self, chain_id: u64, address: Address) -> Vec<u64> {
+        self.nonces
+            .get(&(chain_id, address))
+            .map(|account| account.gaps())
+            .unwrap_or_default()
+    }
+
+    fn reserve(
+        &self,
+        chain_id: u64,
+        address: Address,
+        origin: NonceOrigin,
+    ) -> Result<u64, NonceError> {
+        let nonce = self.allocate_one(chain_id, address, origin)?;
+        self.reservations
+            .lock()
+            .insert(((chain_id, address), nonce), self.hold(origin));
+        Ok(nonce)
+    }
+
+    fn reserve_contiguous(
+        &self,
+        chain_id: u64,
+        address: Address,
+        count: u64,
+        origin: NonceOrigin,
+    ) -> Result<Range<u64>, NonceError> {
+        let range = self.allocate_contiguous(chain_id, address, count, origin)?;
+        let hold = self.hold(origin);
+        let mut reservations = self.reservations.lock();
+        for nonce in range.clone() {
+            reservations.insert(((chain_id, address), nonce), hold);
+        }
+        Ok(range)
+    }
+
+    fn can_allocate(&self, chain_id: u64, address: Address, count: u64) -> Result<(), NonceError> {
+        let account = self
             .nonces
-            .entry(address)
-            .or_insert_with(|| Arc::new(AtomicU64::new(new_nonce)));
-        entry.store(new_nonce, Ordering::SeqCst);
+            .get(&(chain_id, address))
+            .ok_or(NonceError::Uninitialized)?;
+        if account.within_window(account.allocation_end(count), self.max_inflight) {
+            Ok(())
+        } else {
+            Err(NonceError::WindowFull)
+        }
+    }
+
+    fn reserve_exact(
+        &self,
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+        origin: NonceOrigin,
+    ) -> bool {
+        {
+            let Some(mut entry) = self.nonces.get_mut(&(chain_id, address)) else {
+                return false;
+            };
+            let account = entry.value_mut();
+            if nonce < account.confirmed_next
+                || nonce >= account.next
+                || !account.outstanding.insert(nonce)
+            {
+                return false;
+            }
+            account.free.remove(&nonce);
+            account.audit(self.audit_capacity, nonce, AuditAction::Allocated, origin);
+        }
+        self.allocations.fetch_add(1, Ordering::Relaxed);
+        self.notify(NonceEvent::Allocated {
+            chain_id,
+            address,
+            nonce,
+        });
+        self.reservations
+            .lock()
+            .insert(((chain_id, address), nonce), self.hold(origin));
+        true
+    }
+
+    fn commit_reservation(&self, chain_id: u64, address: Address, nonce: u64) -> bool {
+        self.reservations
+            .lock()
+            .remove(&((chain_id, address), nonce))
+            .is_some()
+    }
+
+    fn cancel_reservation(&self, chain_id: u64, address: Address, nonce: u64) {
+        // a lapsed reservation was released by the sweep already
+        let held = self
+            .reservations
+            .lock()
+            .remove(&((chain_id, address), nonce));
+        if let Some(hold) = held {
+            self.release_one(chain_id, address, nonce, hold.origin);
+        }
+    }
+
+    fn release_unmined(&self, chain_id: u64, address: Address, nonce: u64) -> bool {
+        self.release_one(chain_id, address, nonce, NonceOrigin::Submit)
+    }
+
+    fn release_expired_reservations(&self) -> Vec<ReservationKey> {
+        let now = Instant::now();
+        let mut expired = Vec::new();
+        self.reservations.lock().retain(|&key, hold| {
+            let live = hold.expires_at.is_none_or(|at| at > now);
+            if !live {
+                expired.push((key, hold.origin));
+            }
+            live
+        });
+        expired.sort_unstable_by_key(|&(key, _)| key);
+        for &(((chain_id, address), nonce), origin) in &expired {
+            self.release_one(chain_id, address, nonce, origin);
+        }
+        expired.into_iter().map(|(key, _)| key).collect()
+    }
+
+    fn record_confirmed(&self, chain_id: u64, address: Address, nonce: u64) {
+        {
+            let Some(mut entry) = self.nonces.get_mut(&(chain_id, address)) else {
+                return;
+            };
+            let account = entry.value_mut();
+            account.confirmed_next = account.confirmed_next.max(nonce + 1);
+            // a confirmation above the counter, e.g. after `force_set_nonce` lowered it
+            account.next = account.next.max(nonce + 1);
+            account.outstanding.retain(|&n| n > nonce);
+            // reusing these would only earn "nonce too low"
+            account.free.retain(|&n| n > nonce);
+        }
+        self.notify(NonceEvent::Confirmed {
+            chain_id,
+            address,
+            nonce,
+        });
+    }
+
+    fn rollback_confirmed(&self, chain_id: u64, address: Address, from_nonce: u64) -> Vec<u64> {
+        let Some(mut entry) = self.nonces.get_mut(&(chain_id, address)) else {
+            return Vec::new();
+        };
+        let account = entry.value_mut();
+        let from_nonce = from_nonce.max(account.floor);
+        if from_nonce >= account.confirmed_next {
+            return Vec::new();
+        }
+        let reverted: Vec<u64> = (from_nonce..account.confirmed_next).collect();
+        account.confirmed_next = from_nonce;
+        account.outstanding.extend(&reverted);
+        reverted
+    }
+
+    fn update_nonce(&self, chain_id: u64, address: Address, network_next: u64) -> NonceUpdate {
+        match self.raise_to((chain_id, address), network_next) {
+            (None, _) => NonceUpdate::Initialized,
+            (Some(confirmed_next), _) if confirmed_next < network_next => NonceUpdate::Raised,
+            (Some(confirmed_next), _) if confirmed_next == network_next => NonceUpdate::Unchanged,
+            (Some(_), _) => NonceUpdate::Stale,
+        }
+    }
+
+    fn force_set_nonce(&self, chain_id: u64, address: Address, next: u64) -> Option<u64> {
+        self.resyncs.fetch_add(1, Ordering::Relaxed);
+        let key = (chain_id, address);
+        let mut reset = AccountNonces::starting_at(next);
+        let (action, origin) = (AuditAction::Resynced, NonceOrigin::Sync);
+        let previous = match self.nonces.entry(key) {
+            Entry::Occupied(mut entry) => {
+                reset.floor = reset.floor.min(entry.get().floor);
+                reset.audit = std::mem::take(&mut entry.get_mut().audit);
+                reset.audit(self.audit_capacity, next, action, origin);
+                Some(std::mem::replace(entry.get_mut(), reset).next)
+            }
+            Entry::Vacant(entry) => {
+                reset.audit(self.audit_capacity, next, action, origin);
+                entry.insert(reset);
+                None
+            }
+        };
+        self.reservations
+            .lock()
+            .retain(|&(reserved, _), _| reserved != key);
+        self.notify(NonceEvent::Resynced {
+            chain_id,
+            address,
+            nonce: next,
+        });
+        previous
+    }
+
+    fn resync(&self, chain_id: u64, address: Address, network_next: u64) -> ResyncOutcome {
+        self.raise_to((chain_id, address), network_next).1
+    }
+
+    fn reconcile(&self, chain_id: u64, address: Address, observed: &[u64]) -> ReconcileReport {
+        let key = (chain_id, address);
+        let mut claimed = BTreeSet::new();
+        let mut duplicates = BTreeSet::new();
+        for &nonce in observed {
+            if !claimed.insert(nonce) {
+                duplicates.insert(nonce);
+            }
+        }
+        {
+            let reservations = self.reservations.lock();
+            duplicates.extend(
+                claimed
+                    .iter()
+                    .filter(|&&nonce| reservations.contains_key(&(key, nonce))),
+            );
+        }
+        let Some(&lowest) = claimed.first() else {
+            return ReconcileReport {
+                previous_next: self.nonces.get(&key).map(|account| account.next),
+                next: self.peek_nonce(chain_id, address),
+                ..Default::default()
+            };
+        };
+
+        let mut report = ReconcileReport::default();
+        {
+            let mut account = match self.nonces.entry(key) {
+                Entry::Occupied(entry) => {
+                    report.previous_next = Some(entry.get().next);
+                    entry.into_ref()
+                }
+                Entry::Vacant(entry) => entry.insert(AccountNonces::starting_at(lowest)),
+            };
+            for nonce in claimed {
+                if nonce < account.confirmed_next {
+                    report.confirmed.push(nonce);
+                    continue;
+                }
+                account.free.remove(&nonce);
+                account.next = account.next.max(nonce + 1);
+                if account.outstanding.insert(nonce) {
+                    let (action, origin) = (AuditAction::Allocated, NonceOrigin::Restore);
+                    account.audit(self.audit_capacity, nonce, action, origin);
+                    report.adopted.push(nonce);
+                }
+            }
+            report.next = account.next;
+            let confirmed_next = account.confirmed_next;
+            report.duplicates = duplicates
+                .into_iter()
+                .filter(|&nonce| nonce >= confirmed_next)
+                .collect();
+        }
+        self.allocations
+            .fetch_add(report.adopted.len() as u64, Ordering::Relaxed);
+        for &nonce in &report.adopted {
+            self.notify(NonceEvent::Allocated {
+                chain_id,
+                address,
+                nonce,
+            });
+        }
+        report
+    }
+}
+
+/// A nonce held for a tx that hasn't been broadcast yet. Dropping it without
+/// `commit` hands the nonce back.
+pub struct Reservation {
+    allocator: Arc<dyn NonceAllocator>,
+    chain_id: u64,
+    address: Address,
+    nonce: u64,
+    settled: bool,
+}
+
+impl Reservation {
+    fn new(
+        allocator: Arc<dyn NonceAllocator>,
+        chain_id: u64,
+        address: Address,
+        nonce: u64,
+    ) -> Self {
+        Self {
+            allocator,
+            chain_id,
+            address,
+            nonce,
+            settled: false,
+        }
+    }
+
+    pub fn nonce(&self) -> u64 {
+        self.nonce
+    }
+
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    pub fn address(&self) -> Address {
+        self.address
+    }
+
+    /// Marks the nonce as used for good. Fails if it was already reclaimed.
+    pub fn commit(mut self) -> Result<u64, NonceError> {
+        self.settled = true;
+        if self
+            .allocator
+            .commit_reservation(self.chain_id, self.address, self.nonce)
+        {
+            Ok(self.nonce)
+        } else {
+            Err(NonceError::ReservationExpired)
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.settled {
+            self.allocator
+                .cancel_reservation(self.chain_id, self.address, self.nonce);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// Answers slowly and counts how often it was asked.
+    struct SlowProvider {
+        count: u64,
+        calls: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl NonceProvider for SlowProvider {
+        async fn transaction_count(
+            &self,
+            _chain_id: u64,
+            _address: Address,
+        ) -> Result<u64, ProviderError> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            Ok(self.count)
+        }
+    }
+
+    struct DownProvider;
+
+    #[async_trait]
+    impl NonceProvider for DownProvider {
+        async fn transaction_count(
+            &self,
+            _chain_id: u64,
+            _address: Address,
+        ) -> Result<u64, ProviderError> {
+            Err(ProviderError::Unavailable("node offline".to_string()))
+        }
+    }
+
+    #[test]
+    fn test_allocation_requires_initialization() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        assert!(!manager.is_initialized(1, address));
+        assert_eq!(
+            manager.try_next_nonce(1, address),
+            Err(NonceError::Uninitialized)
+        );
+
+        manager.update_nonce(1, address, 7);
+        assert!(manager.is_initialized(1, address));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(7));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(8));
+        assert_eq!(manager.peek_nonce(1, address), 9);
+    }
+
+    #[test]
+    fn test_next_nonce_or_zero_initializes() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xBB);
+        assert_eq!(manager.next_nonce_or_zero(1, address), 0);
+        assert!(manager.is_initialized(1, address));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_concurrent_initialization_fetches_once() {
+        let manager = Arc::new(NonceManager::new());
+        let provider = Arc::new(SlowProvider {
+            count: 12,
+            calls: AtomicUsize::new(0),
+        });
+        let address = Address::repeat_byte(0xAA);
+
+        let tasks: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = manager.clone();
+                let provider = provider.clone();
+                tokio::spawn(async move {
+                    manager
+                        .ensure_initialized(1, address, provider.as_ref())
+                        .await
+                })
+            })
+            .collect();
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), Ok(12));
+        }
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+
+        // known addresses never go back to the provider
+        assert_eq!(manager.try_next_nonce(1, address), Ok(12));
+        assert_eq!(
+            manager
+                .ensure_initialized(1, address, provider.as_ref())
+                .await,
+            Ok(13)
+        );
+        assert_eq!(provider.calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_initialization_keeps_higher_local_nonce() {
+        let manager = Arc::new(NonceManager::new());
+        let provider = Arc::new(SlowProvider {
+            count: 5,
+            calls: AtomicUsize::new(0),
+        });
+        let address = Address::repeat_byte(0xAA);
+
+        let fetch = {
+            let manager = manager.clone();
+            let provider = provider.clone();
+            tokio::spawn(async move {
+                manager
+                    .ensure_initialized(1, address, provider.as_ref())
+                    .await
+            })
+        };
+        tokio::task::yield_now().await;
+        // set locally while the fetch is in flight
+        manager.update_nonce(1, address, 8);
+        assert_eq!(fetch.await.unwrap(), Ok(8));
+    }
+
+    #[tokio::test]
+    async fn test_provider_failure_leaves_address_uninitialized() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            manager.ensure_initialized(1, address, &DownProvider).await,
+            Err(NonceError::Provider(ProviderError::Unavailable(
+                "node offline".to_string()
+            )))
+        );
+        assert!(!manager.is_initialized(1, address));
+
+        let provider = StaticNonces::new();
+        provider.set(1, address, 3);
+        assert_eq!(
+            manager.ensure_initialized(1, address, &provider).await,
+            Ok(3)
+        );
+    }
+
+    #[test]
+    fn test_release_latest_nonce_rewinds() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 5);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(5));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(6));
+
+        assert!(manager.release_nonce(1, address, 6));
+        assert_eq!(manager.peek_nonce(1, address), 6);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(6));
+        // never allocated, or already handed back
+        assert!(!manager.release_nonce(1, address, 7));
+        assert!(!manager.release_nonce(1, Address::repeat_byte(0xBB), 0));
+    }
+
+    #[test]
+    fn test_out_of_order_release_uses_free_list() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for expected in 0..4 {
+            assert_eq!(manager.try_next_nonce(1, address), Ok(expected));
+        }
+
+        assert!(manager.release_nonce(1, address, 1));
+        assert!(!manager.release_nonce(1, address, 1));
+        assert!(manager.release_nonce(1, address, 2));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(1));
+
+        // releasing the top also reclaims the free nonce right below it
+        assert!(manager.release_nonce(1, address, 3));
+        assert_eq!(manager.peek_nonce(1, address), 2);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(2));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(3));
+    }
+
+    #[test]
+    fn test_free_list_is_bounded_and_trimmed_by_resync() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        let top = MAX_FREE_NONCES as u64 + 2;
+        for _ in 0..top {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        for nonce in 0..MAX_FREE_NONCES as u64 {
+            assert!(manager.release_nonce(1, address, nonce));
+        }
+        assert!(!manager.release_nonce(1, address, MAX_FREE_NONCES as u64));
+
+        manager.update_nonce(1, address, 40);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(40));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(41));
+    }
+
+    fn initialized(ttl: Duration) -> Arc<NonceManager> {
+        let manager = NonceManager::new().with_reservation_ttl(ttl);
+        manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        Arc::new(manager)
+    }
+
+    #[test]
+    fn test_reservation_commit_and_drop() {
+        let manager = initialized(Duration::from_secs(60));
+        let address = Address::repeat_byte(0xAA);
+
+        let first = manager
+            .reserve_nonce(1, address, NonceOrigin::Submit)
+            .unwrap();
+        let second = manager
+            .reserve_nonce(1, address, NonceOrigin::Submit)
+            .unwrap();
+        assert_eq!((first.nonce(), second.nonce()), (0, 1));
+        assert_eq!(first.commit(), Ok(0));
+
+        drop(second);
+        assert_eq!(manager.peek_nonce(1, address), 1);
+        assert_eq!(
+            manager
+                .reserve_nonce(1, address, NonceOrigin::Submit)
+                .unwrap()
+                .commit(),
+            Ok(1)
+        );
+        assert!(
+            manager
+                .reserve_nonce(1, Address::repeat_byte(0xBB), NonceOrigin::Submit)
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reservation_expires_after_ttl() {
+        let manager = initialized(Duration::from_secs(60));
+        let address = Address::repeat_byte(0xAA);
+        let stale = manager
+            .reserve_nonce(1, address, NonceOrigin::Submit)
+            .unwrap();
+        tokio::time::advance(Duration::from_secs(30)).await;
+        let fresh = manager
+            .reserve_nonce(1, address, NonceOrigin::Submit)
+            .unwrap();
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(
+            manager.release_expired_reservations(),
+            vec![((1, address), 0)]
+        );
+        assert_eq!(stale.commit(), Err(NonceError::ReservationExpired));
+        assert_eq!(fresh.commit(), Ok(1));
+
+        // the reclaimed nonce is handed out again
+        assert_eq!(manager.try_next_nonce(1, address), Ok(0));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(2));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_interleaved_reservations_are_distinct() {
+        let manager = initialized(Duration::from_secs(60));
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let mut kept = Vec::new();
+                    for round in 0..50 {
+                        let reservation = manager
+                            .reserve_nonce(1, Address::repeat_byte(0xAA), NonceOrigin::Submit)
+                            .unwrap();
+                        tokio::task::yield_now().await;
+                        // every other reservation is abandoned and reused
+                        if (task + round) % 2 == 0 {
+                            kept.push(reservation.commit().unwrap());
+                        }
+                    }
+                    kept
+                })
+            })
+            .collect();
+
+        let mut committed = Vec::new();
+        for task in tasks {
+            committed.extend(task.await.unwrap());
+        }
+        committed.sort_unstable();
+        let count = committed.len();
+        committed.dedup();
+        assert_eq!(committed.len(), count);
+    }
+
+    #[test]
+    fn test_gap_after_out_of_order_release() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 10);
+        for expected in 10..16 {
+            assert_eq!(manager.try_next_nonce(1, address), Ok(expected));
+        }
+        assert!(manager.detect_gaps(1, address).is_empty());
+
+        manager.record_confirmed(1, address, 10);
+        manager.record_confirmed(1, address, 11);
+        // 12 and 14 were never broadcast; 15 rewinds the counter instead
+        assert!(manager.release_nonce(1, address, 12));
+        assert!(manager.release_nonce(1, address, 14));
+        assert!(manager.release_nonce(1, address, 15));
+        assert_eq!(manager.detect_gaps(1, address), vec![12]);
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(13));
+
+        // the next allocation fills the hole
+        assert_eq!(manager.try_next_nonce(1, address), Ok(12));
+        assert!(manager.detect_gaps(1, address).is_empty());
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(12));
+    }
+
+    #[test]
+    fn test_reserve_gap() {
+        let manager = initialized(Duration::from_secs(60));
+        let address = Address::repeat_byte(0xAA);
+        for _ in 0..4 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        manager.release_nonce(1, address, 1);
+        manager.record_confirmed(1, address, 0);
+
+        let filler = manager
+            .reserve_gap(1, address, 1, NonceOrigin::GapFill)
+            .unwrap();
+        assert_eq!(filler.nonce(), 1);
+        assert!(manager.detect_gaps(1, address).is_empty());
+        // confirmed, outstanding and never allocated nonces aren't gaps
+        for nonce in [0, 2, 4] {
+            assert!(
+                manager
+                    .reserve_gap(1, address, nonce, NonceOrigin::GapFill)
+                    .is_none()
+            );
+        }
+        drop(filler);
+        assert_eq!(manager.detect_gaps(1, address), vec![1]);
+    }
+
+    #[test]
+    fn test_confirmation_settles_lower_nonces() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..4 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        assert!(manager.release_nonce(1, address, 1));
+        assert_eq!(manager.detect_gaps(1, address), vec![1]);
+
+        // mined through 2, so 1 was used by someone else
+        manager.record_confirmed(1, address, 2);
+        assert!(manager.detect_gaps(1, address).is_empty());
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(3));
+        assert!(!manager.release_nonce(1, address, 0));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(4));
+        assert_eq!(
+            manager.detect_gaps(1, Address::repeat_byte(0xBB)),
+            Vec::<u64>::new()
+        );
+    }
+
+    #[test]
+    fn test_confirmation_above_the_counter_raises_it() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..6 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        manager.force_set_nonce(1, address, 2);
+        assert_eq!(manager.peek_nonce(1, address), 2);
+
+        // the tx at 5 was still in flight and got mined
+        manager.record_confirmed(1, address, 5);
+        assert_eq!(manager.peek_nonce(1, address), 6);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(6));
+    }
+
+    #[test]
+    fn test_chains_have_independent_sequences() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        manager.update_nonce(10, address, 50);
+        assert!(!manager.is_initialized(137, address));
+
+        assert_eq!(manager.try_next_nonce(1, address), Ok(0));
+        assert_eq!(manager.try_next_nonce(10, address), Ok(50));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(1));
+
+        manager.update_nonce(10, address, 70);
+        assert_eq!(manager.peek_nonce(1, address), 2);
+        assert_eq!(manager.try_next_nonce(10, address), Ok(70));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(2));
+    }
+
+    #[test]
+    fn test_pending_and_confirmed_counters() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        let counters = || {
+            (
+                manager.peek_nonce(1, address),
+                manager.highest_confirmed(1, address),
+                manager.pending_count(1, address),
+            )
+        };
+        assert_eq!(counters(), (0, None, 0));
+
+        for _ in 0..3 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        assert_eq!(counters(), (3, None, 3));
+
+        manager.record_confirmed(1, address, 0);
+        assert_eq!(counters(), (3, Some(0), 2));
+        manager.record_confirmed(1, address, 1);
+        assert_eq!(counters(), (3, Some(1), 1));
+
+        // the network saw one more of ours than was confirmed here
+        manager.update_nonce(1, address, 3);
+        assert_eq!(counters(), (3, Some(2), 0));
+        assert_eq!(manager.lowest_unconfirmed(1, address), None);
+
+        // and then a tx sent from elsewhere
+        manager.update_nonce(1, address, 5);
+        assert_eq!(counters(), (5, Some(4), 0));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(5));
+    }
+
+    #[test]
+    fn test_rollback_confirmed() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 2);
+        for _ in 0..6 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        manager.record_confirmed(1, address, 5);
+        assert_eq!(manager.highest_confirmed(1, address), Some(5));
+
+        assert_eq!(manager.rollback_confirmed(1, address, 4), vec![4, 5]);
+        assert_eq!(manager.highest_confirmed(1, address), Some(3));
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(4));
+        assert_eq!(manager.pending_count(1, address), 4);
+        // nothing left to roll back, and allocation carries on where it was
+        assert!(manager.rollback_confirmed(1, address, 4).is_empty());
+        assert_eq!(manager.peek_nonce(1, address), 8);
+
+        manager.record_confirmed(1, address, 5);
+        assert_eq!(manager.highest_confirmed(1, address), Some(5));
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(6));
+
+        // 0 and 1 predate initialization
+        assert_eq!(manager.rollback_confirmed(1, address, 0), vec![2, 3, 4, 5]);
+        assert_eq!(manager.highest_confirmed(1, address), Some(1));
+        assert!(
+            manager
+                .rollback_confirmed(1, Address::repeat_byte(0xBB), 0)
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_rollback_under_concurrent_reads() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..10 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..1_000 {
+                        let confirmed = manager.highest_confirmed(1, address);
+                        assert!(confirmed.is_none_or(|nonce| nonce < 10));
+                        assert!(manager.pending_count(1, address) <= 10);
+                    }
+                });
+            }
+            for round in 0..1_000 {
+                manager.record_confirmed(1, address, 9);
+                manager.rollback_confirmed(1, address, round % 10);
+            }
+        });
+        manager.record_confirmed(1, address, 9);
+        assert_eq!(manager.pending_count(1, address), 0);
+    }
+
+    fn populated() -> Arc<NonceManager> {
+        let manager = Arc::new(NonceManager::new());
+        manager.update_nonce(1, Address::repeat_byte(0xAA), 10);
+        manager.update_nonce(10, Address::repeat_byte(0xAA), 3);
+        for _ in 0..5 {
+            manager
+                .try_next_nonce(1, Address::repeat_byte(0xAA))
+                .unwrap();
+        }
+        manager.record_confirmed(1, Address::repeat_byte(0xAA), 10);
+        assert!(manager.release_nonce(1, Address::repeat_byte(0xAA), 12));
+        manager
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let manager = populated();
+        // reserved at shutdown and never committed
+        let reservation = manager
+            .reserve_nonce(1, Address::repeat_byte(0xAA), NonceOrigin::Submit)
+            .unwrap();
+        assert_eq!(reservation.nonce(), 12);
+        let snapshot = manager.export();
+        std::mem::forget(reservation);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&snapshot).unwrap();
+            assert_eq!(
+                serde_json::from_str::<NonceSnapshot>(&json).unwrap(),
+                snapshot
+            );
+        }
+        let bytes = borsh::to_vec(&snapshot).unwrap();
+        let decoded = NonceSnapshot::try_from_slice(&bytes).unwrap();
+        assert_eq!(decoded, snapshot);
+
+        let restored = NonceManager::new();
+        restored.import(decoded, ImportPolicy::RaiseOnly);
+        assert_eq!(restored.export(), snapshot);
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(restored.highest_confirmed(1, address), Some(10));
+        assert_eq!(restored.lowest_unconfirmed(1, address), Some(11));
+        assert_eq!(restored.try_next_nonce(1, address), Ok(12));
+        assert_eq!(restored.try_next_nonce(1, address), Ok(15));
+        assert_eq!(restored.try_next_nonce(10, address), Ok(3));
+    }
+
+    #[test]
+    fn test_snapshot_reads_legacy_formats() {
+        let account = AccountSnapshot {
+            chain_id: 1,
+            address: Address::repeat_byte(0xAA),
+            next: 7,
+            confirmed_next: 5,
+            floor: 0,
+            free: vec![6],
+            outstanding: vec![5],
+            audit: Vec::new(),
+        };
+
+        // borsh keeps the raw 20 bytes it always wrote
+        let bytes = borsh::to_vec(&account).unwrap();
+        assert_eq!(&bytes[8..28], &[0xAA; 20]);
+        assert_eq!(AccountSnapshot::try_from_slice(&bytes).unwrap(), account);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_value(&account).unwrap();
+            assert_eq!(json["address"], format!("0x{}", "aa".repeat(20)));
+            let mut legacy = json.clone();
+            legacy["address"] = serde_json::json!([0xAA_u8; 20].to_vec());
+            legacy.as_object_mut().unwrap().remove("audit");
+            assert_eq!(
+                serde_json::from_value::<AccountSnapshot>(legacy).unwrap(),
+                account
+            );
+        }
+    }
+
+    #[test]
+    fn test_byte_array_and_address_share_an_entry() {
+        let manager = NonceManager::new();
+        manager.update_nonce(1, Address::repeat_byte(0xAA), 4);
+
+        assert_eq!(manager.try_next_nonce(1, [0xAA; 20]), Ok(4));
+        assert_eq!(manager.try_next_nonce(1, Address::repeat_byte(0xAA)), Ok(5));
+        assert_eq!(
+            manager.stats(1, [0xAA; 20]),
+            manager.stats(1, Address::repeat_byte(0xAA))
+        );
+        assert_eq!(manager.accounts(), vec![(1, Address::repeat_byte(0xAA))]);
+    }
+
+    #[test]
+    fn test_import_policy() {
+        let snapshot = populated().export();
+        let address = Address::repeat_byte(0xAA);
+
+        let ahead = NonceManager::new();
+        ahead.update_nonce(1, address, 20);
+        ahead.import(snapshot.clone(), ImportPolicy::RaiseOnly);
+        assert_eq!(ahead.peek_nonce(1, address), 20);
+        assert_eq!(ahead.highest_confirmed(1, address), Some(19));
+        // the free nonce is already confirmed here
+        assert_eq!(ahead.try_next_nonce(1, address), Ok(20));
+
+        ahead.import(snapshot, ImportPolicy::Overwrite);
+        assert_eq!(ahead.peek_nonce(1, address), 15);
+        assert_eq!(ahead.try_next_nonce(1, address), Ok(12));
+    }
+
+    #[test]
+    fn test_range_allocation_and_release() {
+        let manager = Arc::new(NonceManager::new());
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            manager.try_allocate_range(1, address, 3),
+            Err(NonceError::Uninitialized)
+        );
+        manager.update_nonce(1, address, 4);
+
+        assert_eq!(manager.try_allocate_range(1, address, 3), Ok(4..7));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(7));
+        // 4..7 sits below 7, so it goes to the free list
+        assert_eq!(manager.release_range(1, address, 4..7), 3);
+        assert_eq!(manager.detect_gaps(1, address), vec![4, 5, 6]);
+        assert_eq!(manager.release_range(1, address, 4..7), 0);
+
+        // ranges skip the free list and stay contiguous
+        let batch = manager
+            .reserve_range(1, address, 2, NonceOrigin::Batch)
+            .unwrap();
+        let nonces: Vec<u64> = batch.iter().map(Reservation::nonce).collect();
+        assert_eq!(nonces, vec![8, 9]);
+        drop(batch);
+        assert_eq!(manager.peek_nonce(1, address), 8);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_ranges_are_disjoint_and_contiguous() {
+        let manager = Arc::new(NonceManager::new());
+        manager.update_nonce(1, Address::repeat_byte(0xAA), 100);
+        let tasks: Vec<_> = (0..8)
+            .map(|task| {
+                let manager = manager.clone();
+                tokio::spawn(async move {
+                    let mut ranges = Vec::new();
+                    for round in 0..100 {
+                        let count = 1 + (task + round) % 4;
+                        ranges.push(
+                            manager
+                                .try_allocate_range(1, Address::repeat_byte(0xAA), count)
+                                .unwrap(),
+                        );
+                        tokio::task::yield_now().await;
+                    }
+                    ranges
+                })
+            })
+            .collect();
+
+        let mut ranges = Vec::new();
+        for task in tasks {
+            ranges.extend(task.await.unwrap());
+        }
+        ranges.sort_by_key(|range| range.start);
+        // each range starts where the previous one ended: no overlap, no hole
+        assert_eq!(ranges[0].start, 100);
+        assert!(ranges.windows(2).all(|w| w[0].end == w[1].start));
+        assert_eq!(
+            ranges.last().unwrap().end,
+            manager.peek_nonce(1, Address::repeat_byte(0xAA))
+        );
+    }
+
+    #[test]
+    fn test_resync_when_network_is_ahead() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..3 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        // someone else sent three txs from this account, consuming 0..5
+        assert_eq!(
+            manager.resync(1, address, 5),
+            ResyncOutcome {
+                previous_next: Some(3),
+                next: 5,
+                consumed: vec![0, 1, 2],
+            }
+        );
+        assert_eq!(manager.highest_confirmed(1, address), Some(4));
+        assert_eq!(manager.lowest_unconfirmed(1, address), None);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(5));
+
+        let fresh = manager.resync(1, Address::repeat_byte(0xBB), 9);
+        assert_eq!(fresh.previous_next, None);
+        assert_eq!(manager.try_next_nonce(1, Address::repeat_byte(0xBB)), Ok(9));
+    }
+
+    #[test]
+    fn test_resync_never_lowers() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..4 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        manager.record_confirmed(1, address, 2);
+
+        // the network has only seen 0 and 1 so far
+        assert_eq!(
+            manager.resync(1, address, 2),
+            ResyncOutcome {
+                previous_next: Some(4),
+                next: 4,
+                consumed: vec![],
+            }
+        );
+        assert_eq!(manager.highest_confirmed(1, address), Some(2));
+        assert_eq!(manager.lowest_unconfirmed(1, address), Some(3));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(4));
+    }
+
+    #[test]
+    fn test_reconcile_covers_restored_nonces() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+
+        // counter behind: the restored txs went out after the nonce state was saved
+        manager.update_nonce(1, address, 5);
+        assert_eq!(
+            manager.reconcile(1, address, &[7, 5]),
+            ReconcileReport {
+                previous_next: Some(5),
+                next: 8,
+                adopted: vec![5, 7],
+                ..Default::default()
+            }
+        );
+        assert_eq!(manager.detect_gaps(1, address), vec![6]);
+        assert_eq!(manager.try_allocate_range(1, address, 3), Ok(8..11));
+
+        // counter ahead: a restored nonce had been released meanwhile
+        assert!(manager.release_nonce(1, address, 9));
+        let report = manager.reconcile(1, address, &[9, 10]);
+        assert_eq!((report.next, report.adopted.clone()), (11, vec![9]));
+        assert!(!report.has_conflicts());
+        assert_eq!(manager.try_next_nonce(1, address), Ok(11));
+
+        // unknown account: starts at the lowest restored nonce
+        let other = Address::repeat_byte(0xBB);
+        let report = manager.reconcile(1, other, &[6, 4]);
+        assert_eq!((report.previous_next, report.next), (None, 7));
+        assert_eq!(manager.highest_confirmed(1, other), Some(3));
+        // the gap is left for a filler rather than reused blindly
+        assert_eq!(manager.detect_gaps(1, other), vec![5]);
+        assert_eq!(manager.try_next_nonce(1, other), Ok(7));
+    }
+
+    #[test]
+    fn test_reconcile_flags_duplicate_claims() {
+        let manager = Arc::new(NonceManager::new());
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 3);
+        let held = manager
+            .reserve_nonce(1, address, NonceOrigin::Submit)
+            .unwrap();
+        assert_eq!(held.nonce(), 3);
+
+        let report = manager.reconcile(1, address, &[1, 3, 4, 4]);
+        assert_eq!(
+            report,
+            ReconcileReport {
+                previous_next: Some(4),
+                next: 5,
+                adopted: vec![4],
+                duplicates: vec![3, 4],
+                confirmed: vec![1],
+            }
+        );
+        assert!(report.has_conflicts());
+        // nothing handed out later lands on a claimed nonce
+        assert_eq!(manager.try_allocate_range(1, address, 2), Ok(5..7));
+        assert_eq!(manager.highest_confirmed(1, address), Some(2));
+    }
+
+    #[test]
+    fn test_inflight_window() {
+        let manager = NonceManager::new().with_max_inflight(3);
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 10);
+        assert_eq!(
+            manager.try_allocate_range(1, address, 4),
+            Err(NonceError::WindowFull)
+        );
+        for expected in 10..13 {
+            assert_eq!(manager.try_next_nonce(1, address), Ok(expected));
+        }
+        assert_eq!(
+            manager.try_next_nonce(1, address),
+            Err(NonceError::WindowFull)
+        );
+
+        manager.record_confirmed(1, address, 10);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(13));
+        assert_eq!(
+            manager.try_next_nonce(1, address),
+            Err(NonceError::WindowFull)
+        );
+        // a released nonce inside the window is still handed out
+        assert!(manager.release_nonce(1, address, 12));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(12));
+    }
+
+    #[test]
+    fn test_can_allocate_leaves_state_alone() {
+        let manager = NonceManager::new().with_max_inflight(4);
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            manager.can_allocate(1, address, 1),
+            Err(NonceError::Uninitialized)
+        );
+        manager.update_nonce(1, address, 0);
+        manager.try_allocate_range(1, address, 2).unwrap();
+
+        // room for 2, the batch wants 3
+        let before = manager.stats(1, address);
+        assert_eq!(manager.can_allocate(1, address, 2), Ok(()));
+        assert_eq!(
+            manager.can_allocate(1, address, 3),
+            Err(NonceError::WindowFull)
+        );
+        assert_eq!(
+            manager.try_allocate_range(1, address, 3),
+            Err(NonceError::WindowFull)
+        );
+        assert_eq!(manager.stats(1, address), before);
+        assert_eq!(manager.counters().allocations, 2);
+
+        // a released nonce fits a lone allocation even when a fresh one wouldn't
+        manager.try_allocate_range(1, address, 2).unwrap();
+        assert!(manager.release_nonce(1, address, 1));
+        assert_eq!(manager.can_allocate(1, address, 1), Ok(()));
+        assert_eq!(
+            manager.can_allocate(1, address, 2),
+            Err(NonceError::WindowFull)
+        );
+    }
+
+    #[test]
+    fn test_partial_ranges_never_allocate_under_confirmations() {
+        let manager = NonceManager::new().with_max_inflight(4);
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        manager.try_allocate_range(1, address, 2).unwrap();
+
+        let barrier = std::sync::Barrier::new(5);
+        let ranges: Vec<Range<u64>> = std::thread::scope(|scope| {
+            let workers: Vec<_> = (0..4)
+                .map(|_| {
+                    scope.spawn(|| {
+                        barrier.wait();
+                        let mut ranges = Vec::new();
+                        for _ in 0..200 {
+                            if manager.can_allocate(1, address, 3).is_err() {
+                                continue;
+                            }
+                            // the window may have filled since the check
+                            match manager.try_allocate_range(1, address, 3) {
+                                Ok(range) => ranges.push(range),
+                                Err(e) => assert_eq!(e, NonceError::WindowFull),
+                            }
+                        }
+                        ranges
+                    })
+                })
+                .collect();
+            barrier.wait();
+            manager.record_confirmed(1, address, 0);
+            manager.record_confirmed(1, address, 1);
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().unwrap())
+                .collect()
+        });
+
+        // once both confirmations land the window has room for exactly one batch
+        assert!(ranges.len() <= 1);
+        for range in &ranges {
+            assert_eq!(range.end - range.start, 3);
+            assert!(range.end <= 2 + 4);
+        }
+        assert_eq!(manager.counters().allocations, 2 + 3 * ranges.len() as u64);
+        assert_eq!(
+            manager.stats(1, address).unwrap().inflight,
+            3 * ranges.len()
+        );
+    }
+
+    #[test]
+    fn test_update_nonce_only_raises() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            manager.update_nonce(1, address, 5),
+            NonceUpdate::Initialized
+        );
+        assert_eq!(manager.update_nonce(1, address, 5), NonceUpdate::Unchanged);
+        assert_eq!(manager.update_nonce(1, address, 8), NonceUpdate::Raised);
+        for expected in 8..11 {
+            assert_eq!(manager.try_next_nonce(1, address), Ok(expected));
+        }
+
+        // a stale count leaves both counters alone
+        assert_eq!(manager.update_nonce(1, address, 6), NonceUpdate::Stale);
+        assert_eq!(manager.highest_confirmed(1, address), Some(7));
+        assert_eq!(manager.try_next_nonce(1, address), Ok(11));
+
+        assert_eq!(manager.force_set_nonce(1, address, 6), Some(12));
+        assert_eq!(manager.highest_confirmed(1, address), Some(5));
+        assert_eq!(manager.lowest_unconfirmed(1, address), None);
+        assert_eq!(manager.try_next_nonce(1, address), Ok(6));
+        assert_eq!(
+            manager.force_set_nonce(1, Address::repeat_byte(0xBB), 3),
+            None
+        );
+    }
+
+    #[test]
+    fn test_stale_update_races_allocation() {
+        let manager = Arc::new(NonceManager::new());
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 5);
+
+        let allocators: Vec<_> = (0..4)
+            .map(|_| {
+                let manager = manager.clone();
+                std::thread::spawn(move || {
+                    (0..500)
+                        .map(|_| manager.try_next_nonce(1, address).unwrap())
+                        .collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        // a poller that keeps reporting the count it fetched before allocation began
+        let poller = {
+            let manager = manager.clone();
+            std::thread::spawn(move || {
+                for _ in 0..2_000 {
+                    manager.update_nonce(1, address, 5);
+                }
+            })
+        };
+
+        let mut nonces: Vec<u64> = allocators
+            .into_iter()
+            .flat_map(|thread| thread.join().unwrap())
+            .collect();
+        poller.join().unwrap();
+        nonces.sort_unstable();
+        assert_eq!(nonces, (5..2_005).collect::<Vec<_>>());
+        assert_eq!(manager.peek_nonce(1, address), 2_005);
+    }
+
+    #[test]
+    fn test_stats_follow_operations() {
+        let manager = NonceManager::new();
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(manager.stats(1, address), None);
+        manager.update_nonce(1, address, 10);
+        manager.update_nonce(5, Address::repeat_byte(0xBB), 0);
+        assert_eq!(
+            manager.accounts(),
+            vec![(1, address), (5, Address::repeat_byte(0xBB))]
+        );
+
+        for _ in 0..4 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        manager.try_allocate_range(1, address, 3).unwrap();
+        manager.record_confirmed(1, address, 11);
+        assert!(manager.release_nonce(1, address, 13));
+        assert!(manager.release_nonce(1, address, 16));
+        assert!(!manager.release_nonce(1, address, 10));
+        assert_eq!(
+            manager.stats(1, address),
+            Some(NonceStats {
+                next_to_allocate: 16,
+                highest_confirmed: Some(11),
+                free_list_len: 1,
+                inflight: 3,
+            })
+        );
+
+        manager.try_next_nonce(1, address).unwrap();
+        manager.resync(1, address, 15);
+        assert_eq!(
+            manager.stats(1, address),
+            Some(NonceStats {
+                next_to_allocate: 16,
+                highest_confirmed: Some(14),
+                free_list_len: 0,
+                inflight: 1,
+            })
+        );
+        assert_eq!(
+            manager.counters(),
+            NonceCounters {
+                allocations: 8,
+                releases: 2,
+                resyncs: 3,
+            }
+        );
+    }
+
+    #[test]
+    fn test_audit_log_evicts_oldest_first() {
+        let manager = Arc::new(NonceManager::new().with_audit_capacity(4));
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 10);
+        for reservation in manager
+            .reserve_range(1, address, 2, NonceOrigin::Batch)
+            .unwrap()
+        {
+            reservation.commit().unwrap();
+        }
+        drop(
+            manager
+                .reserve_nonce(1, address, NonceOrigin::Submit)
+                .unwrap(),
+        );
+        manager.try_next_nonce(1, address).unwrap();
+
+        let log = manager.audit_log(1, address);
+        let lines: Vec<_> = log
+            .iter()
+            .map(|entry| (entry.nonce, entry.action, entry.origin))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (11, AuditAction::Allocated, NonceOrigin::Batch),
+                (12, AuditAction::Allocated, NonceOrigin::Submit),
+                (12, AuditAction::Released, NonceOrigin::Submit),
+                (12, AuditAction::Allocated, NonceOrigin::Direct),
+            ]
+        );
+        assert!(log.windows(2).all(|w| w[0].at_ms <= w[1].at_ms));
+
+        manager.force_set_nonce(1, address, 3);
+        let last = *manager.audit_log(1, address).last().unwrap();
+        assert_eq!(
+            (last.nonce, last.action, last.origin),
+            (3, AuditAction::Resynced, NonceOrigin::Sync)
+        );
+
+        let untracked = NonceManager::new();
+        untracked.update_nonce(1, address, 0);
+        untracked.try_next_nonce(1, address).unwrap();
+        assert!(untracked.audit_log(1, address).is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_audit_log_is_saved_with_snapshot() {
+        let manager = NonceManager::new().with_audit_capacity(8);
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 0);
+        for _ in 0..3 {
+            manager.try_next_nonce(1, address).unwrap();
+        }
+        let snapshot = manager.export();
+        assert_eq!(snapshot.accounts[0].audit, manager.audit_log(1, address));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        let restored = NonceManager::new().with_audit_capacity(2);
+        restored.import(
+            serde_json::from_str(&json).unwrap(),
+            ImportPolicy::RaiseOnly,
+        );
+        let nonces: Vec<u64> = restored
+            .audit_log(1, address)
+            .iter()
+            .map(|entry| entry.nonce)
+            .collect();
+        assert_eq!(nonces, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_events_arrive_in_order() {
+        let manager = NonceManager::new();
+        let mut events = manager.subscribe();
+        let address = Address::repeat_byte(0xAA);
+        manager.update_nonce(1, address, 3);
+        manager.try_next_nonce(1, address).unwrap();
+        manager.try_allocate_range(1, address, 2).unwrap();
+        manager.release_nonce(1, address, 4);
+        manager.record_confirmed(1, address, 3);
+        manager.resync(1, address, 6);
+
+        let allocated = |nonce| NonceEvent::Allocated {
+            chain_id: 1,
+            address,
+            nonce,
+        };
+        let mut received = Vec::new();
+        while let Ok(event) = events.try_recv() {
+            received.push(event);
+        }
+        assert_eq!(
+            received,
+            vec![
+                NonceEvent::Resynced {
+                    chain_id: 1,
+                    address,
+                    nonce: 3,
+                },
+                allocated(3),
+                allocated(4),
+                allocated(5),
+                NonceEvent::Released {
+                    chain_id: 1,
+                    address,
+                    nonce: 4,
+                },
+                NonceEvent::Confirmed {
+                    chain_id: 1,
+                    address,
+                    nonce: 3,
+                },
+                NonceEvent::Resynced {
+                    chain_id: 1,
+                    address,
+                    nonce: 6,
+                },
+            ]
+        );
     }
 }
@@ -0,0 +1,711 @@
+use crate::events::{FeeMode, GasEvent, SchedulerCommand, SchedulerDecision, TransactionRequest};
+use crate::scheduler::{Scheduler, SchedulerHandle};
+use crate::sink::{ChannelSink, DecisionSink};
+use crate::source::synthetic_hash;
+use crate::units::{Wei, format_ether, format_gwei};
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+/// Header of the base fee CSV; `gas_used` and `gas_limit` may be left off.
+pub const CSV_HEADER: &str = "block_number,timestamp,base_fee,gas_used,gas_limit";
+
+const DEFAULT_GAS_LIMIT: u64 = 30_000_000;
+
+/// A recorded gas event and its unix time in seconds; one line of an events
+/// JSONL file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TimedEvent {
+    pub timestamp: u64,
+    pub event: GasEvent,
+}
+
+/// A request and when it arrived, in milliseconds after the first event; one
+/// line of a requests JSONL file.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct TimedRequest {
+    pub offset_ms: u64,
+    pub request: TransactionRequest,
+}
+
+#[derive(Debug)]
+pub enum ReplayError {
+    Csv {
+        line: usize,
+        message: String,
+    },
+    Json {
+        line: usize,
+        error: serde_json::Error,
+    },
+    /// Nothing to replay the requests against.
+    NoEvents,
+}
+
+impl fmt::Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ReplayError::Csv { line, message } => write!(f, "line {}: {}", line, message),
+            ReplayError::Json { line, error } => write!(f, "line {}: {}", line, error),
+            ReplayError::NoEvents => write!(f, "no gas events to replay"),
+        }
+    }
+}
+
+impl std::error::Error for ReplayError {}
+
+/// Reads base fees as `NewBlock` events, one row per block under `CSV_HEADER`
+/// (or its first three columns), fees in wei. Blank lines and lines starting
+/// with `#` are skipped. Blocks are chained with made-up hashes, so a number
+/// going backwards replays as a reorg.
+pub fn parse_base_fee_csv(text: &str) -> Result<Vec<TimedEvent>, ReplayError> {
+    let mut rows = text
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+    let columns = match rows.next() {
+        Some((_, header)) if CSV_HEADER.starts_with(header) => header.split(',').count(),
+        Some((line, _)) => {
+            return Err(ReplayError::Csv {
+                line,
+                message: format!("expected a `{}` header", CSV_HEADER),
+            });
+        }
+        None => return Ok(Vec::new()),
+    };
+    rows.map(|(line, row)| {
+        let error = |message: String| ReplayError::Csv { line, message };
+        let fields = row
+            .split(',')
+            .map(|field| field.trim().parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| error(e.to_string()))?;
+        if fields.len() != columns {
+            return Err(error(format!(
+                "{} fields where the header has {}",
+                fields.len(),
+                columns
+            )));
+        }
+        let gas_limit = fields.get(4).copied().unwrap_or(DEFAULT_GAS_LIMIT);
+        let number = fields[0];
+        Ok(TimedEvent {
+            timestamp: fields[1],
+            event: GasEvent::NewBlock {
+                number,
+                base_fee: fields[2],
+                gas_used: fields.get(3).copied().unwrap_or(gas_limit / 2),
+                gas_limit,
+                block_hash: synthetic_hash(number),
+                parent_hash: synthetic_hash(number.saturating_sub(1)),
+            },
+        })
+    })
+    .collect()
+}
+
+/// Reads one `T` per non-blank line.
+pub fn parse_jsonl<T: for<'de> Deserialize<'de>>(text: &str) -> Result<Vec<T>, ReplayError> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            serde_json::from_str(line).map_err(|error| ReplayError::Json {
+                line: index + 1,
+                error,
+            })
+        })
+        .collect()
+}
+
+/// Recorded events and requests, ready to be run through a scheduler on
+/// tokio's clock. Run under paused time, e.g. a runtime built with
+/// `start_paused(true)`, a replay takes no real time and two runs over the same
+/// inputs give the same report.
+pub struct Replay {
+    events: Vec<TimedEvent>,
+    requests: Vec<TimedRequest>,
+}
+
+/// An input due at some point of the replay.
+enum Step<'a> {
+    Event(&'a GasEvent),
+    Request(&'a TransactionRequest),
+}
+
+impl Replay {
+    /// Events are put in timestamp order and requests in arrival order; ties
+    /// keep the order given.
+    pub fn new(
+        mut events: Vec<TimedEvent>,
+        mut requests: Vec<TimedRequest>,
+    ) -> Result<Self, ReplayError> {
+        if events.is_empty() {
+            return Err(ReplayError::NoEvents);
+        }
+        events.sort_by_key(|event| event.timestamp);
+        requests.sort_by_key(|request| request.offset_ms);
+        Ok(Self { events, requests })
+    }
+
+    /// Feeds everything to the scheduler `build` returns, which must deliver
+    /// to the sink it is given, and reports what came of each request. Fails
+    /// only if `build` does.
+    ///
+    /// The scheduler's clock starts at the first event's timestamp, and every
+    /// sender starts at nonce 0. Each `Submit` and `Reprice` counts as
+    /// broadcast at once. A broadcast tx is included by the first later block
+    /// whose base fee it covers, at that base fee plus its tip, and confirmed
+    /// to the scheduler with its whole gas limit used. Inputs due at the same
+    /// time go a millisecond apart, events first, so that each is handled
+    /// before the next.
+    pub async fn run<E>(
+        &self,
+        build: impl FnOnce(Box<dyn DecisionSink>) -> Result<Scheduler, E>,
+    ) -> Result<ReplayReport, E> {
+        let first = self.events[0].timestamp;
+        let (decision_tx, mut decision_rx) = mpsc::channel(256);
+        let scheduler =
+            build(Box::new(ChannelSink::new(decision_tx)))?.with_start_time(first * 1000);
+        let (event_tx, event_rx) = mpsc::channel(16);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(16);
+        let scheduler = tokio::spawn(Arc::new(scheduler).run(event_rx, req_rx, cmd_rx));
+
+        let requests: BTreeMap<u64, &TransactionRequest> = self
+            .requests
+            .iter()
+            .map(|timed| (timed.request.id, &timed.request))
+            .collect();
+        let broadcast = Arc::new(Mutex::new(BTreeMap::<u64, Broadcast>::new()));
+        // Broadcasts reach the scheduler through a queue of their own, so the
+        // recorder never waits on the scheduler while the scheduler waits on it.
+        let (broadcast_tx, mut broadcast_rx) = mpsc::unbounded_channel();
+        let forwarder = tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                while let Some(cmd) = broadcast_rx.recv().await {
+                    let _ = handle.command(cmd).await;
+                }
+            }
+        });
+        let (done_tx, mut done_rx) = oneshot::channel::<()>();
+        let recorder = tokio::spawn({
+            let broadcast = broadcast.clone();
+            async move {
+                let mut broadcast_tx = Some(broadcast_tx);
+                let mut decisions = Vec::new();
+                loop {
+                    tokio::select! {
+                        biased;
+                        decision = decision_rx.recv() => {
+                            let Some(decision) = decision else { break };
+                            let cmd = track_broadcasts(&decision, &broadcast);
+                            if let (Some(cmd), Some(broadcast_tx)) = (cmd, &broadcast_tx) {
+                                let _ = broadcast_tx.send(cmd);
+                            }
+                            decisions.push(decision);
+                        }
+                        // the scheduler stops once nothing can send it anything
+                        _ = &mut done_rx, if broadcast_tx.is_some() => broadcast_tx = None,
+                    }
+                }
+                decisions
+            }
+        });
+
+        let senders: BTreeSet<_> = self
+            .requests
+            .iter()
+            .map(|timed| (timed.request.chain_id, timed.request.from))
+            .collect();
+        for (chain_id, address) in senders {
+            let cmd = SchedulerCommand::InitNonce {
+                chain_id,
+                address,
+                nonce: 0,
+                force: false,
+            };
+            // can't fail: the scheduler runs until the handle is dropped
+            let _ = handle.command(cmd).await;
+        }
+
+        let start = Instant::now();
+        let mut last_due = None;
+        for (due, step) in self.timeline(first) {
+            let due = match last_due {
+                Some(last) if due <= last => last + Duration::from_millis(1),
+                _ => due,
+            };
+            last_due = Some(due);
+            tokio::time::sleep_until(start + due).await;
+            match step {
+                Step::Event(event) => {
+                    let included = match event {
+                        GasEvent::NewBlock { base_fee, .. } => {
+                            included(&broadcast, &requests, Wei::from(*base_fee))
+                        }
+                        _ => Vec::new(),
+                    };
+                    let _ = event_tx.send(event.clone()).await;
+                    if let GasEvent::NewBlock { number, .. } = event {
+                        for (tx_hash, effective_gas_price, gas_used) in included {
+                            let confirmed = GasEvent::TxConfirmed {
+                                tx_hash,
+                                block_number: *number,
+                                effective_gas_price,
+                                gas_used,
+                            };
+                            let _ = event_tx.send(confirmed).await;
+                        }
+                    }
+                }
+                Step::Request(request) => {
+                    let _ = handle.submit_waiting(request.clone()).await;
+                }
+            }
+        }
+        drop((event_tx, handle, done_tx));
+        let _ = forwarder.await;
+        let _ = scheduler.await;
+        let decisions = recorder.await.unwrap_or_default();
+        Ok(ReplayReport::new(
+            &self.requests,
+            &self.events,
+            first,
+            &decisions,
+        ))
+    }
+
+    /// Every input with when it is due after the first event.
+    fn timeline(&self, first: u64) -> Vec<(Duration, Step<'_>)> {
+        let events = self.events.iter().map(|timed| {
+            let due = Duration::from_secs(timed.timestamp - first);
+            (due, Step::Event(&timed.event))
+        });
+        let requests = self.requests.iter().map(|timed| {
+            let due = Duration::from_millis(timed.offset_ms);
+            (due, Step::Request(&timed.request))
+        });
+        let mut timeline: Vec<_> = events.chain(requests).collect();
+        // stable, so events stay ahead of requests due at the same time
+        timeline.sort_by_key(|(due, _)| *due);
+        timeline
+    }
+}
+
+/// The tx a replayed broadcast stands for.
+struct Broadcast {
+    tx_hash: [u8; 32],
+    gas_price: Wei,
+    /// Replacements sent before this one.
+    attempts: u64,
+}
+
+/// The `Broadcast` of each `Submit` and `Reprice`, under a hash of its own;
+/// forgets txs the scheduler is done with.
+fn track_broadcasts(
+    decision: &SchedulerDecision,
+    broadcast: &Mutex<BTreeMap<u64, Broadcast>>,
+) -> Option<SchedulerCommand> {
+    let (tx_id, gas_price) = match *decision {
+        SchedulerDecision::Submit {
+            tx_id, gas_price, ..
+        } => (tx_id, gas_price),
+        SchedulerDecision::Reprice {
+            tx_id,
+            new_gas_price,
+            ..
+        } => (tx_id, new_gas_price),
+        SchedulerDecision::Drop { tx_id, .. } | SchedulerDecision::Confirmed { tx_id, .. } => {
+            broadcast.lock().remove(&tx_id);
+            return None;
+        }
+        _ => return None,
+    };
+    let mut broadcast = broadcast.lock();
+    let attempts = broadcast.get(&tx_id).map_or(0, |tx| tx.attempts + 1);
+    let mut tx_hash = [0xDD; 32];
+    tx_hash[..8].copy_from_slice(&tx_id.to_be_bytes());
+    tx_hash[24..].copy_from_slice(&attempts.to_be_bytes());
+    broadcast.insert(
+        tx_id,
+        Broadcast {
+            tx_hash,
+            gas_price,
+            attempts,
+        },
+    );
+    Some(SchedulerCommand::Broadcast { tx_id, tx_hash })
+}
+
+/// Takes out the broadcast txs a block at `base_fee` includes, with the price
+/// and gas each pays.
+fn included(
+    broadcast: &Mutex<BTreeMap<u64, Broadcast>>,
+    requests: &BTreeMap<u64, &TransactionRequest>,
+    base_fee: Wei,
+) -> Vec<([u8; 32], u64, u64)> {
+    let mut broadcast = broadcast.lock();
+    let ids: Vec<u64> = broadcast
+        .iter()
+        .filter(|(_, tx)| tx.gas_price >= base_fee)
+        .map(|(tx_id, _)| *tx_id)
+        .collect();
+    ids.into_iter()
+        .filter_map(|tx_id| {
+            let tx = broadcast.remove(&tx_id)?;
+            let request = requests.get(&tx_id)?;
+            let price = paid_price(request, tx.gas_price, base_fee);
+            Some((
+                tx.tx_hash,
+                u64::try_from(price.0).unwrap_or(u64::MAX),
+                request.gas_limit,
+            ))
+        })
+        .collect()
+}
+
+/// What a tx priced at `gas_price` pays per gas in a block at `base_fee`.
+fn paid_price(request: &TransactionRequest, gas_price: Wei, base_fee: Wei) -> Wei {
+    match request.fee_mode {
+        FeeMode::Legacy => gas_price,
+        FeeMode::Eip1559 => gas_price.min(Wei(base_fee.0 + request.max_priority_fee_per_gas.0)),
+    }
+}
+
+/// How one replayed request ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TxFate {
+    Confirmed {
+        block_number: u64,
+        effective_gas_price: Wei,
+    },
+    /// Sent, but no later block covered its price.
+    InFlight {
+        gas_price: Wei,
+    },
+    Dropped {
+        reason: String,
+    },
+    Rejected {
+        reason: String,
+    },
+    /// Still queued when the data ran out.
+    Pending,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TxReplay {
+    pub tx_id: u64,
+    /// Had it gone out on arrival: the next block's base fee plus its tip,
+    /// within its fee cap.
+    pub naive_price: Wei,
+    pub reprices: u32,
+    pub fate: TxFate,
+}
+
+impl TxReplay {
+    /// `(naive price - effective price) * gas limit` for a confirmed tx;
+    /// negative where waiting cost more.
+    pub fn savings_wei(&self, gas_limit: u64) -> Option<i128> {
+        match self.fate {
+            TxFate::Confirmed {
+                effective_gas_price,
+                ..
+            } => Some(
+                (self.naive_price.0 as i128 - effective_gas_price.0 as i128) * gas_limit as i128,
+            ),
+            _ => None,
+        }
+    }
+}
+
+/// What a replay came to, in request id order; its `Display` is the summary
+/// the `replay` command prints.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReplayReport {
+    pub txs: Vec<TxReplay>,
+    pub reprices: u32,
+    pub drops: u32,
+    /// Drops for a passed deadline.
+    pub missed_deadlines: u32,
+    /// Over confirmed txs; negative if waiting cost more than it saved.
+    pub total_savings_wei: i128,
+}
+
+impl ReplayReport {
+    fn new(
+        requests: &[TimedRequest],
+        events: &[TimedEvent],
+        first: u64,
+        decisions: &[SchedulerDecision],
+    ) -> Self {
+        let blocks: Vec<(u64, Wei)> = events
+            .iter()
+            .filter_map(|timed| match timed.event {
+                GasEvent::NewBlock { base_fee, .. } => {
+                    Some(((timed.timestamp - first) * 1000, Wei::from(base_fee)))
+                }
+                _ => None,
+            })
+            .collect();
+        let mut txs: BTreeMap<u64, (TxReplay, u64)> = requests
+            .iter()
+            .map(|timed| {
+                let request = &timed.request;
+                // the first block after arrival, or the last one there was
+                let base_fee = blocks
+                    .iter()
+                    .find(|(at, _)| *at > timed.offset_ms)
+                    .or(blocks.last())
+                    .map_or(Wei::ZERO, |(_, base_fee)| *base_fee);
+                let naive_price = Wei((base_fee.0 + request.max_priority_fee_per_gas.0)
+                    .min(request.max_fee_per_gas.0));
+                let tx = TxReplay {
+                    tx_id: request.id,
+                    naive_price,
+                    reprices: 0,
+                    fate: TxFate::Pending,
+                };
+                (request.id, (tx, request.gas_limit))
+            })
+            .collect();
+
+        let (mut reprices, mut drops, mut missed_deadlines) = (0, 0, 0);
+        for decision in decisions {
+            let Some((tx, _)) = decision.tx_id().and_then(|tx_id| txs.get_mut(&tx_id)) else {
+                continue;
+            };
+            match decision {
+                SchedulerDecision::Submit { gas_price, .. } => {
+                    tx.fate = TxFate::InFlight {
+                        gas_price: *gas_price,
+                    };
+                }
+                SchedulerDecision::Reprice { new_gas_price, .. } => {
+                    tx.reprices += 1;
+                    reprices += 1;
+                    tx.fate = TxFate::InFlight {
+                        gas_price: *new_gas_price,
+                    };
+                }
+                SchedulerDecision::Drop { reason, .. } => {
+                    drops += 1;
+                    if reason.starts_with("deadline expired") {
+                        missed_deadlines += 1;
+                    }
+                    tx.fate = TxFate::Dropped {
+                        reason: reason.clone(),
+                    };
+                }
+                SchedulerDecision::Rejected { reason, .. } => {
+                    tx.fate = TxFate::Rejected {
+                        reason: reason.clone(),
+                    };
+                }
+                SchedulerDecision::Confirmed {
+                    block_number,
+                    effective_gas_price,
+                    ..
+                } => {
+                    tx.fate = TxFate::Confirmed {
+                        block_number: *block_number,
+                        effective_gas_price: *effective_gas_price,
+                    };
+                }
+                _ => {}
+            }
+        }
+
+        let total_savings_wei = txs
+            .values()
+            .filter_map(|(tx, gas_limit)| tx.savings_wei(*gas_limit))
+            .sum();
+        Self {
+            txs: txs.into_values().map(|(tx, _)| tx).collect(),
+            reprices,
+            drops,
+            missed_deadlines,
+            total_savings_wei,
+        }
+    }
+
+    fn count(&self, matches: impl Fn(&TxFate) -> bool) -> usize {
+        self.txs.iter().filter(|tx| matches(&tx.fate)).count()
+    }
+}
+
+/// `wei` in ether, with a sign.
+fn format_signed_ether(wei: i128) -> String {
+    let sign = if wei < 0 { "-" } else { "" };
+    format!("{}{}", sign, format_ether(wei.unsigned_abs()))
+}
+
+impl fmt::Display for ReplayReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for tx in &self.txs {
+            write!(f, "tx {}: ", tx.tx_id)?;
+            match &tx.fate {
+                TxFate::Confirmed {
+                    block_number,
+                    effective_gas_price,
+                } => write!(
+                    f,
+                    "paid {} in block {} vs {} naive",
+                    format_gwei(effective_gas_price.0),
+                    block_number,
+                    format_gwei(tx.naive_price.0)
+                )?,
+                TxFate::InFlight { gas_price } => write!(
+                    f,
+                    "sent at {}, never included, vs {} naive",
+                    format_gwei(gas_price.0),
+                    format_gwei(tx.naive_price.0)
+                )?,
+                TxFate::Dropped { reason } => write!(f, "dropped: {}", reason)?,
+                TxFate::Rejected { reason } => write!(f, "rejected: {}", reason)?,
+                TxFate::Pending => write!(f, "still pending")?,
+            }
+            if tx.reprices > 0 {
+                write!(f, " ({} reprices)", tx.reprices)?;
+            }
+            writeln!(f)?;
+        }
+        let confirmed = self.count(|fate| matches!(fate, TxFate::Confirmed { .. }));
+        writeln!(
+            f,
+            "txs: {}, confirmed {}, in flight {}, dropped {}, rejected {}, pending {}",
+            self.txs.len(),
+            confirmed,
+            self.count(|fate| matches!(fate, TxFate::InFlight { .. })),
+            self.count(|fate| matches!(fate, TxFate::Dropped { .. })),
+            self.count(|fate| matches!(fate, TxFate::Rejected { .. })),
+            self.count(|fate| matches!(fate, TxFate::Pending)),
+        )?;
+        writeln!(
+            f,
+            "reprices: {}, drops: {}, missed deadlines: {}",
+            self.reprices, self.drops, self.missed_deadlines
+        )?;
+        write!(
+            f,
+            "estimated savings over {} confirmed txs: {}",
+            confirmed,
+            format_signed_ether(self.total_savings_wei)
+        )
+    }
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::limiter::NoopLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::NonceManager;
+    use crate::units::Gwei;
+    use std::convert::Infallible;
+
+    const BASE_FEES: &str = include_str!("../fixtures/replay/base_fees.csv");
+    const REQUESTS: &str = include_str!("../fixtures/replay/requests.jsonl");
+
+    fn fixture() -> Replay {
+        let events = parse_base_fee_csv(BASE_FEES).unwrap();
+        Replay::new(events, parse_jsonl(REQUESTS).unwrap()).unwrap()
+    }
+
+    async fn run(replay: &Replay) -> ReplayReport {
+        let report = replay
+            .run(|sink| {
+                Ok::<_, Infallible>(Scheduler::new(
+                    AppConfig::default().scheduler_config(),
+                    Arc::new(GasModel::new(100)),
+                    Arc::new(NonceManager::new()),
+                    Arc::new(NoopLimiter::default()),
+                    vec![sink],
+                ))
+            })
+            .await;
+        report.unwrap()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fixture_replays_the_same_each_time() {
+        let replay = fixture();
+        let report = run(&replay).await;
+        assert_eq!(run(&replay).await, report);
+        assert_eq!(report.to_string(), run(&replay).await.to_string());
+
+        let fates: Vec<_> = report.txs.iter().map(|tx| &tx.fate).collect();
+        assert!(matches!(
+            fates.as_slice(),
+            [
+                TxFate::Confirmed {
+                    block_number: 19000001,
+                    ..
+                },
+                TxFate::Confirmed {
+                    block_number: 19000003,
+                    ..
+                },
+                TxFate::Confirmed {
+                    block_number: 19000022,
+                    ..
+                },
+                TxFate::Dropped { .. },
+                TxFate::Pending,
+            ]
+        ));
+        // tx 3 went out just before the spike and followed it up
+        assert_eq!(report.txs[2].naive_price, Wei::from(Gwei(44)));
+        assert_eq!(report.txs[2].reprices, 5);
+        assert_eq!(
+            (report.reprices, report.drops, report.missed_deadlines),
+            (5, 1, 1)
+        );
+        assert_eq!(
+            report.total_savings_wei,
+            -((Gwei(120).to_wei().0 - Gwei(44).to_wei().0) as i128) * 65_000
+        );
+        assert!(report.to_string().ends_with(
+            "reprices: 5, drops: 1, missed deadlines: 1\n\
+             estimated savings over 3 confirmed txs: -0.00494 ETH"
+        ));
+    }
+
+    #[test]
+    fn test_base_fee_csv() {
+        let events = parse_base_fee_csv(
+            "# three columns are enough\n\
+             block_number,timestamp,base_fee\n\
+             \n\
+             7,1700000000,30000000000\n",
+        )
+        .unwrap();
+        assert_eq!(
+            events,
+            [TimedEvent {
+                timestamp: 1700000000,
+                event: GasEvent::NewBlock {
+                    number: 7,
+                    base_fee: 30_000_000_000,
+                    gas_used: 15_000_000,
+                    gas_limit: 30_000_000,
+                    block_hash: synthetic_hash(7),
+                    parent_hash: synthetic_hash(6),
+                },
+            }]
+        );
+
+        let short_row = parse_base_fee_csv(&format!("{}\n7,1700000000,30\n", CSV_HEADER));
+        assert!(matches!(short_row, Err(ReplayError::Csv { line: 2, .. })));
+        let no_header = parse_base_fee_csv("7,1700000000,30\n");
+        assert!(matches!(no_header, Err(ReplayError::Csv { line: 1, .. })));
+    }
+}
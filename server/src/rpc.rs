@@ -0,0 +1,535 @@
+use crate::events::{
+    AccessListItem, FeeMode, SignedAuthorization, SubmissionPrivacy, TransactionRequest, Urgency,
+};
+use crate::units::Wei;
+use alloy_eips::eip7702::{
+    Authorization as RpcAuthorization, SignedAuthorization as RpcSignedAuthorization,
+};
+use alloy_primitives::{Address, B256, Bytes, TxKind, U256};
+use alloy_rpc_types_eth::{
+    AccessList, AccessListItem as RpcAccessListItem, TransactionInput,
+    TransactionRequest as RpcTransactionRequest,
+};
+
+/// Why a request didn't convert; each variant names the field, as it is spelled
+/// on the side being converted from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConversionError {
+    /// A field this side needs was unset.
+    Missing(&'static str),
+    /// A field with no counterpart on the other side was set.
+    Unsupported(&'static str),
+    /// The field was set to something the other side can't express.
+    Invalid {
+        field: &'static str,
+        reason: &'static str,
+    },
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::Missing(field) => write!(f, "`{}` is required", field),
+            ConversionError::Unsupported(field) => write!(f, "`{}` is not supported", field),
+            ConversionError::Invalid { field, reason } => write!(f, "`{}` {}", field, reason),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
+
+/// Takes an alloy request as built for `eth_sendTransaction`, with `id` 0 and the
+/// scheduling fields (deadline, urgency, ...) at their defaults; set those before
+/// submitting.
+///
+/// - `nonce` must be unset: the scheduler assigns nonces when it submits.
+/// - `chain_id` may be unset, leaving the chain to the scheduler's config.
+/// - `access_list` carries over, unless the request is legacy; an empty one is
+///   the same as none.
+/// - A `gas_price` makes a `FeeMode::Legacy` request with that price as both fee
+///   cap and tip cap; the configured tip ceiling still applies. Otherwise
+///   `max_fee_per_gas` and `max_priority_fee_per_gas` are both required.
+/// - `authorization_list` carries over on EIP-1559 and type-4 requests, and must
+///   not be empty; each chain id must fit in a u64.
+/// - Blob fields are rejected.
+impl TryFrom<RpcTransactionRequest> for TransactionRequest {
+    type Error = ConversionError;
+
+    fn try_from(rpc: RpcTransactionRequest) -> Result<Self, Self::Error> {
+        if rpc.nonce.is_some() {
+            return Err(ConversionError::Unsupported("nonce"));
+        }
+        if rpc.max_fee_per_blob_gas.is_some() {
+            return Err(ConversionError::Unsupported("max_fee_per_blob_gas"));
+        }
+        if rpc.blob_versioned_hashes.is_some() {
+            return Err(ConversionError::Unsupported("blob_versioned_hashes"));
+        }
+        if rpc.sidecar.is_some() {
+            return Err(ConversionError::Unsupported("sidecar"));
+        }
+        let (fee_mode, max_fee_per_gas, max_priority_fee_per_gas) = rpc_fees(&rpc)?;
+        let authorization_list = match rpc.authorization_list {
+            Some(list) if list.is_empty() => {
+                return Err(ConversionError::Invalid {
+                    field: "authorization_list",
+                    reason: "is empty",
+                });
+            }
+            Some(_) if fee_mode == FeeMode::Legacy => {
+                return Err(ConversionError::Invalid {
+                    field: "authorization_list",
+                    reason: "is set on a legacy request",
+                });
+            }
+            Some(list) => Some(
+                list.iter()
+                    .map(|auth| {
+                        Ok(SignedAuthorization {
+                            chain_id: auth.chain_id.try_into().map_err(|_| {
+                                ConversionError::Invalid {
+                                    field: "authorization_list",
+                                    reason: "has a chain id above u64",
+                                }
+                            })?,
+                            address: auth.address.into_array(),
+                            nonce: auth.nonce,
+                            y_parity: auth.y_parity(),
+                            r: auth.r().to_be_bytes(),
+                            s: auth.s().to_be_bytes(),
+                        })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+            None => None,
+        };
+        let access_list = rpc.access_list.filter(|list| !list.is_empty()).map(|list| {
+            list.0
+                .into_iter()
+                .map(|item| AccessListItem {
+                    address: item.address.into_array(),
+                    storage_keys: item.storage_keys.into_iter().map(|key| key.0).collect(),
+                })
+                .collect::<Vec<_>>()
+        });
+        if access_list.is_some() && fee_mode == FeeMode::Legacy {
+            return Err(ConversionError::Invalid {
+                field: "access_list",
+                reason: "is set on a legacy request",
+            });
+        }
+        let from = rpc.from.ok_or(ConversionError::Missing("from"))?;
+        let gas_limit = rpc.gas.ok_or(ConversionError::Missing("gas"))?;
+        let data = rpc
+            .input
+            .try_into_unique_input()
+            .map_err(|_| ConversionError::Invalid {
+                field: "input",
+                reason: "differs from `data`",
+            })?
+            .unwrap_or_default();
+        Ok(TransactionRequest {
+            id: 0,
+            from: from.into_array(),
+            to: match rpc.to {
+                Some(TxKind::Call(to)) => Some(to.into_array()),
+                Some(TxKind::Create) | None => None,
+            },
+            data: data.to_vec(),
+            value: rpc.value.unwrap_or_default().to_be_bytes(),
+            gas_limit,
+            max_fee_per_gas: Wei(max_fee_per_gas),
+            max_priority_fee_per_gas: Wei(max_priority_fee_per_gas),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: rpc.chain_id,
+            blob: None,
+            fee_mode,
+            idempotency_key: None,
+            access_list,
+            authorization_list,
+            privacy: SubmissionPrivacy::Public,
+        })
+    }
+}
+
+/// Fee mode, fee cap and tip cap of an alloy request, from whichever fee fields
+/// it sets.
+fn rpc_fees(rpc: &RpcTransactionRequest) -> Result<(FeeMode, u128, u128), ConversionError> {
+    let legacy = match rpc.transaction_type {
+        None => rpc.gas_price.is_some(),
+        Some(0 | 1) => true,
+        Some(2 | 4) => false,
+        Some(_) => {
+            return Err(ConversionError::Invalid {
+                field: "transaction_type",
+                reason: "is neither legacy, EIP-1559 nor EIP-7702",
+            });
+        }
+    };
+    if !legacy {
+        if rpc.gas_price.is_some() {
+            return Err(ConversionError::Invalid {
+                field: "gas_price",
+                reason: "is set on an EIP-1559 request",
+            });
+        }
+        let max_fee = rpc
+            .max_fee_per_gas
+            .ok_or(ConversionError::Missing("max_fee_per_gas"))?;
+        let tip = rpc
+            .max_priority_fee_per_gas
+            .ok_or(ConversionError::Missing("max_priority_fee_per_gas"))?;
+        return Ok((FeeMode::Eip1559, max_fee, tip));
+    }
+    if rpc.max_fee_per_gas.is_some() {
+        return Err(ConversionError::Invalid {
+            field: "max_fee_per_gas",
+            reason: "is set on a legacy request",
+        });
+    }
+    if rpc.max_priority_fee_per_gas.is_some() {
+        return Err(ConversionError::Invalid {
+            field: "max_priority_fee_per_gas",
+            reason: "is set on a legacy request",
+        });
+    }
+    let gas_price = rpc.gas_price.ok_or(ConversionError::Missing("gas_price"))?;
+    Ok((FeeMode::Legacy, gas_price, gas_price))
+}
+
+/// The alloy request for the same transaction, without a nonce; the executor
+/// fills it in from the decision.
+///
+/// A `FeeMode::Legacy` request becomes a type-0 request priced at its fee cap,
+/// so its tip cap doesn't survive a round trip. A request with authorizations
+/// becomes a type-4 request. Blob requests are rejected, as the blob hashes and
+/// sidecar aren't part of the request.
+impl TryFrom<TransactionRequest> for RpcTransactionRequest {
+    type Error = ConversionError;
+
+    fn try_from(req: TransactionRequest) -> Result<Self, Self::Error> {
+        if req.blob.is_some() {
+            return Err(ConversionError::Unsupported("blob"));
+        }
+        if req.fee_mode == FeeMode::Legacy && req.access_list_entries() > 0 {
+            return Err(ConversionError::Invalid {
+                field: "access_list",
+                reason: "is set on a legacy request",
+            });
+        }
+        if req.fee_mode == FeeMode::Legacy && req.authorization_list.is_some() {
+            return Err(ConversionError::Invalid {
+                field: "authorization_list",
+                reason: "is set on a legacy request",
+            });
+        }
+        let mut rpc = RpcTransactionRequest {
+            from: Some(Address::from(req.from)),
+            to: Some(match req.to {
+                Some(to) => TxKind::Call(Address::from(to)),
+                None => TxKind::Create,
+            }),
+            gas: Some(req.gas_limit),
+            value: Some(U256::from_be_bytes(req.value)),
+            input: TransactionInput::new(Bytes::from(req.data)),
+            chain_id: req.chain_id,
+            access_list: req
+                .access_list
+                .filter(|items| !items.is_empty())
+                .map(|items| {
+                    AccessList(
+                        items
+                            .into_iter()
+                            .map(|item| RpcAccessListItem {
+                                address: Address::from(item.address),
+                                storage_keys: item
+                                    .storage_keys
+                                    .into_iter()
+                                    .map(B256::from)
+                                    .collect(),
+                            })
+                            .collect(),
+                    )
+                }),
+            authorization_list: req.authorization_list.as_ref().map(|list| {
+                list.iter()
+                    .map(|auth| {
+                        RpcSignedAuthorization::new_unchecked(
+                            RpcAuthorization {
+                                chain_id: U256::from(auth.chain_id),
+                                address: Address::from(auth.address),
+                                nonce: auth.nonce,
+                            },
+                            auth.y_parity,
+                            U256::from_be_bytes(auth.r),
+                            U256::from_be_bytes(auth.s),
+                        )
+                    })
+                    .collect()
+            }),
+            ..Default::default()
+        };
+        match req.fee_mode {
+            FeeMode::Eip1559 => {
+                rpc.transaction_type = Some(if req.authorization_list.is_some() {
+                    4
+                } else {
+                    2
+                });
+                rpc.max_fee_per_gas = Some(req.max_fee_per_gas.0);
+                rpc.max_priority_fee_per_gas = Some(req.max_priority_fee_per_gas.0);
+            }
+            FeeMode::Legacy => {
+                rpc.transaction_type = Some(0);
+                rpc.gas_price = Some(req.max_fee_per_gas.0);
+            }
+        }
+        Ok(rpc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::units::Gwei;
+    use alloy_primitives::hex;
+    use alloy_rpc_types_eth::AccessList;
+
+    fn request() -> TransactionRequest {
+        TransactionRequest {
+            id: 0,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            // 1 ether
+            value: U256::from(1_000_000_000_000_000_000u64).to_be_bytes(),
+            gas_limit: 21_000,
+            max_fee_per_gas: Gwei(40).into(),
+            max_priority_fee_per_gas: Gwei(2).into(),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: Some(1),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn round_trip(req: TransactionRequest) -> RpcTransactionRequest {
+        let rpc = RpcTransactionRequest::try_from(req.clone()).unwrap();
+        assert_eq!(TransactionRequest::try_from(rpc.clone()), Ok(req));
+        rpc
+    }
+
+    #[test]
+    fn test_transfer_round_trip() {
+        let rpc = round_trip(request());
+        assert_eq!(rpc.to, Some(TxKind::Call(Address::repeat_byte(0xBB))));
+        assert_eq!(rpc.value, Some(U256::from(10u64).pow(U256::from(18))));
+        assert_eq!(rpc.max_fee_per_gas, Some(40_000_000_000));
+        assert_eq!(rpc.max_priority_fee_per_gas, Some(2_000_000_000));
+        assert_eq!(rpc.nonce, None);
+    }
+
+    #[test]
+    fn test_contract_call_round_trip() {
+        // transfer(address,uint256)
+        let data = hex::decode(concat!(
+            "a9059cbb",
+            "000000000000000000000000cccccccccccccccccccccccccccccccccccccccc",
+            "00000000000000000000000000000000000000000000000000000000000003e8",
+        ))
+        .unwrap();
+        let req = TransactionRequest {
+            data: data.clone(),
+            value: [0; 32],
+            gas_limit: 60_000,
+            chain_id: None,
+            ..request()
+        };
+        let rpc = round_trip(req);
+        assert_eq!(rpc.input.input(), Some(&Bytes::from(data)));
+        assert_eq!(rpc.chain_id, None);
+    }
+
+    #[test]
+    fn test_access_list_round_trip() {
+        let req = TransactionRequest {
+            gas_limit: 30_000,
+            access_list: Some(vec![AccessListItem {
+                address: [0xCC; 20],
+                storage_keys: vec![[0; 32], [0x01; 32]],
+            }]),
+            ..request()
+        };
+        let rpc = round_trip(req);
+        let list = rpc.access_list.unwrap();
+        assert_eq!(list.0.len(), 1);
+        assert_eq!(list.0[0].address, Address::repeat_byte(0xCC));
+        assert_eq!(list.0[0].storage_keys[1], B256::repeat_byte(0x01));
+    }
+
+    #[test]
+    fn test_authorization_round_trip() {
+        let req = TransactionRequest {
+            gas_limit: 46_000,
+            authorization_list: Some(vec![SignedAuthorization {
+                chain_id: 0,
+                address: [0xDD; 20],
+                nonce: 3,
+                y_parity: 1,
+                r: [0x11; 32],
+                s: [0x22; 32],
+            }]),
+            ..request()
+        };
+        let rpc = round_trip(req.clone());
+        assert_eq!(rpc.transaction_type, Some(4));
+        let list = rpc.authorization_list.clone().unwrap();
+        assert_eq!(list[0].address, Address::repeat_byte(0xDD));
+        assert_eq!(list[0].s(), U256::from_be_bytes([0x22; 32]));
+
+        let mut empty = rpc.clone();
+        empty.authorization_list = Some(vec![]);
+        assert!(matches!(
+            TransactionRequest::try_from(empty),
+            Err(ConversionError::Invalid {
+                field: "authorization_list",
+                reason: "is empty",
+            })
+        ));
+        let legacy = TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..req
+        };
+        assert!(matches!(
+            RpcTransactionRequest::try_from(legacy),
+            Err(ConversionError::Invalid {
+                field: "authorization_list",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_creation_round_trip() {
+        let req = TransactionRequest {
+            to: None,
+            data: vec![0x60, 0x80, 0x60, 0x40, 0x52],
+            value: [0; 32],
+            gas_limit: 100_000,
+            ..request()
+        };
+        let rpc = round_trip(req.clone());
+        assert_eq!(rpc.to, Some(TxKind::Create));
+
+        // alloy also leaves `to` unset for a creation
+        let unset = RpcTransactionRequest { to: None, ..rpc };
+        assert_eq!(TransactionRequest::try_from(unset), Ok(req));
+    }
+
+    #[test]
+    fn test_legacy_fees() {
+        let rpc = RpcTransactionRequest {
+            from: Some(Address::repeat_byte(0xAA)),
+            to: Some(TxKind::Call(Address::repeat_byte(0xBB))),
+            gas: Some(21_000),
+            gas_price: Some(30_000_000_000),
+            ..Default::default()
+        };
+        let req = TransactionRequest::try_from(rpc).unwrap();
+        assert_eq!(req.fee_mode, FeeMode::Legacy);
+        assert_eq!(req.max_fee_per_gas, Gwei(30).into());
+        assert_eq!(req.max_priority_fee_per_gas, Gwei(30).into());
+
+        let back = RpcTransactionRequest::try_from(req).unwrap();
+        assert_eq!(back.transaction_type, Some(0));
+        assert_eq!(back.gas_price, Some(30_000_000_000));
+        assert_eq!(back.max_fee_per_gas, None);
+    }
+
+    #[test]
+    fn test_conversion_errors_name_the_field() {
+        let rpc = RpcTransactionRequest::try_from(request()).unwrap();
+        let fails = |edit: fn(&mut RpcTransactionRequest)| {
+            let mut rpc = rpc.clone();
+            edit(&mut rpc);
+            TransactionRequest::try_from(rpc).unwrap_err()
+        };
+        assert_eq!(
+            fails(|rpc| rpc.nonce = Some(3)),
+            ConversionError::Unsupported("nonce")
+        );
+        assert_eq!(
+            fails(|rpc| rpc.from = None),
+            ConversionError::Missing("from")
+        );
+        assert_eq!(fails(|rpc| rpc.gas = None), ConversionError::Missing("gas"));
+        assert_eq!(
+            fails(|rpc| rpc.max_priority_fee_per_gas = None),
+            ConversionError::Missing("max_priority_fee_per_gas")
+        );
+        assert!(matches!(
+            fails(|rpc| rpc.gas_price = Some(1)),
+            ConversionError::Invalid {
+                field: "gas_price",
+                ..
+            }
+        ));
+        assert!(matches!(
+            fails(|rpc| rpc.transaction_type = Some(3)),
+            ConversionError::Invalid {
+                field: "transaction_type",
+                ..
+            }
+        ));
+        assert!(matches!(
+            fails(|rpc| rpc.input.data = Some(Bytes::from_static(&[1]))),
+            ConversionError::Invalid { field: "input", .. }
+        ));
+        assert_eq!(
+            fails(|rpc| rpc.max_fee_per_blob_gas = Some(1)),
+            ConversionError::Unsupported("max_fee_per_blob_gas")
+        );
+        assert!(matches!(
+            fails(|rpc| {
+                rpc.access_list = Some(AccessList(vec![Default::default()]));
+                rpc.transaction_type = Some(1);
+                rpc.gas_price = rpc.max_fee_per_gas.take();
+                rpc.max_priority_fee_per_gas = None;
+            }),
+            ConversionError::Invalid {
+                field: "access_list",
+                ..
+            }
+        ));
+        // an empty access list is the same as none
+        let mut empty = rpc.clone();
+        empty.access_list = Some(AccessList::default());
+        assert!(TransactionRequest::try_from(empty).is_ok());
+        assert_eq!(
+            ConversionError::Missing("from").to_string(),
+            "`from` is required"
+        );
+
+        let blob = TransactionRequest {
+            blob: Some(crate::events::BlobParams {
+                max_fee_per_blob_gas: Wei(1),
+                blob_count: 1,
+            }),
+            ..request()
+        };
+        assert_eq!(
+            RpcTransactionRequest::try_from(blob),
+            Err(ConversionError::Unsupported("blob"))
+        );
+    }
+}
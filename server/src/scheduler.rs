@@ -1,199 +1,6564 @@
-use crate::events::{GasEvent, SchedulerDecision, TransactionRequest};
-use crate::limiter::RateLimiter;
+use crate::balance::BalanceProvider;
+use crate::channel::{BackpressurePolicy, ChannelStats, InstrumentedSender, SendError};
+use crate::events::{
+    CorrelationId, DecisionMeta, DecisionRecord, DecisionTrigger, DeferReason, EscalationStep,
+    FeeMode, GasEvent, MarketSnapshot, RestoredTx, RetryHint, SchedulerCommand, SchedulerDecision,
+    SubmissionPrivacy, TransactionRequest, TxStatus, Urgency, ValidationError, hex_hash,
+};
+use crate::l2::L1DataFeeEstimator;
+use crate::limiter::{
+    HierarchicalLimiter, Limiter, LimiterConfigError, RateLimiterConfig, RateLimiterStats,
+};
 use crate::model::GasModel;
-use crate::nonce::NonceManager;
-use std::collections::HashMap;
+use crate::nonce::{
+    AccountKey, NonceAllocator, NonceError, NonceOrigin, NonceProvider, Reservation,
+};
+use crate::sink::{DecisionSink, SinkFailurePolicy, SinkSet};
+use crate::source::{ChannelSource, GasEventSource};
+use crate::units::{Gwei, Wei, gwei_to_wei};
+use alloy_primitives::{Address, keccak256};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::Arc;
-use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tokio::sync::mpsc;
-use tracing::{info, warn};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::time::{Instant, MissedTickBehavior};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
 
+/// How many limiter tokens a submission costs.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LimiterWeighting {
+    /// One token per transaction.
+    #[default]
+    PerTx,
+    /// `ceil(gas_limit / gas_per_token)` tokens per transaction.
+    PerGas { gas_per_token: u64 },
+}
+
+impl LimiterWeighting {
+    pub fn cost(&self, req: &TransactionRequest) -> u64 {
+        match *self {
+            LimiterWeighting::PerTx => 1,
+            LimiterWeighting::PerGas { gas_per_token } => {
+                req.gas_limit.div_ceil(gas_per_token.max(1)).max(1)
+            }
+        }
+    }
+}
+
+/// How often `SchedulerDecision::MarketUpdate` telemetry is emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MarketUpdatePolicy {
+    #[default]
+    Disabled,
+    /// After every `NewBlock`.
+    EveryBlock,
+    /// At most once per interval, after any base fee sample.
+    Every(Duration),
+}
+
+/// What to do with a tx when the balance provider can't answer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BalanceErrorPolicy {
+    /// Defer the tx until a balance is known.
+    #[default]
+    FailClosed,
+    /// Submit as if the balance were sufficient.
+    FailOpen,
+}
+
+/// What to do with a submitted tx reported dropped from the mempool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DroppedTxPolicy {
+    /// Give its nonce back and return it to pending, to be priced and submitted
+    /// like a new tx.
+    #[default]
+    Requeue,
+    /// Keep its nonce and send a Reprice straight away, at the current price or
+    /// its last one if that was higher.
+    Resubmit,
+}
+
+#[derive(Debug, Clone)]
 pub struct SchedulerConfig {
-    pub target_base_fee: u64,
-    pub max_priority_fee: u64,
-    pub spike_threshold: f64,
+    pub target_base_fee: Wei,
+    /// Hold low/standard urgency txs while the base fee is above `target_base_fee`.
+    pub honor_target_base_fee: bool,
+    /// Within this long of its deadline a tx stops waiting for the target, and a
+    /// submitted tx reprices on the base cooldown regardless of backoff.
+    pub target_escalation_window: Duration,
+    /// Ceiling on the tip used in Submit/Reprice, whatever the request asks for.
+    pub max_priority_fee: Wei,
+    /// Tip ceiling while in inclusion-first (spike) mode.
+    pub spike_max_priority_fee: Wei,
+    /// Volatility, in wei per block, above which spike mode is entered.
+    pub spike_threshold_high: f64,
+    /// Volatility below which spike mode is left again; must not exceed the high threshold.
+    pub spike_threshold_low: f64,
+    /// Fall in the base fee, in wei per block, past which a tx over its fee cap
+    /// is deferred as `TrendWait` rather than `FeeAboveMax`.
+    pub trend_threshold: f64,
+    /// Consecutive samples beyond a threshold required before the mode flips.
+    pub spike_confirm_samples: u32,
+    /// Minimum gap between a tx's Submit and its first Reprice.
     pub reprice_cooldown: Duration,
+    /// Each Reprice multiplies that tx's cooldown by this factor; 1.0 keeps it fixed.
+    pub reprice_backoff_factor: f64,
+    /// Ceiling on a tx's backed-off cooldown.
+    pub max_reprice_cooldown: Duration,
+    /// Stop repricing a tx after this many Reprices. Takes precedence over everything,
+    /// including deadline pressure.
+    pub max_reprices: Option<u32>,
+    pub limiter_weighting: LimiterWeighting,
+    /// Give each sender a bucket of its own, spent before the shared one, so one
+    /// busy sender can't take the whole submission budget.
+    pub per_sender_rate: Option<RateLimiterConfig>,
+    /// Warn when the shared limiter refuses more than this share of requests over
+    /// a minute.
+    pub limiter_warn_rejection_ratio: Option<f64>,
+    /// How long a dropped request can still be brought back with `SchedulerCommand::Resubmit`.
+    pub dropped_retention: Duration,
+    /// Maximum number of dropped requests kept for resubmission; oldest are evicted first.
+    pub dropped_archive_capacity: usize,
+    pub dropped_tx_policy: DroppedTxPolicy,
+    /// How often pending txs are swept for expiry between gas events.
+    pub sweep_interval: Duration,
+    /// Drop pending txs this long after acceptance.
+    pub pending_max_age: Option<Duration>,
+    /// Drop pending txs after this many evaluations in which they didn't qualify.
+    pub pending_max_evaluations: Option<u32>,
+    pub market_updates: MarketUpdatePolicy,
+    pub sink_failure_policy: SinkFailurePolicy,
+    /// Longest a single sink may take to accept a decision before it counts as failed.
+    /// Sinks marked required are waited on regardless.
+    pub sink_timeout: Duration,
+    /// How long a fetched sender balance is trusted before it is queried again.
+    pub balance_cache_ttl: Duration,
+    pub balance_error_policy: BalanceErrorPolicy,
+    /// Recent blocks remembered to link new blocks to their parents; also how long
+    /// confirmations are kept around in case a reorg reverts them.
+    pub block_history: usize,
+    /// Drop a request identical in sender, recipient, calldata and value to one
+    /// accepted within this window that is still pending or submitted.
+    pub dedupe_window: Option<Duration>,
+    /// How long a request's `idempotency_key` is remembered; a repeat within it
+    /// re-acknowledges the original instead of creating a new tx.
+    pub idempotency_window: Duration,
+    /// Maximum number of idempotency keys remembered; oldest are evicted first.
+    pub idempotency_capacity: usize,
+    /// Chain of requests that don't name one.
+    pub chain_id: u64,
+    /// Refuse blob requests on arrival, as when the executor can't build type-3 txs.
+    pub reject_blob_requests: bool,
+    /// Fill nonce gaps older than `nonce_gap_fill_age` with self-transfers.
+    pub auto_fill_nonce_gaps: bool,
+    /// How long a gap may stand before it is filled; the tx that held the nonce
+    /// may just be slow to broadcast.
+    pub nonce_gap_fill_age: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    SpikeThresholdsInverted { low: f64, high: f64 },
+    PerSenderRate(LimiterConfigError),
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::SpikeThresholdsInverted { low, high } => write!(
+                f,
+                "spike_threshold_low ({}) is above spike_threshold_high ({})",
+                low, high
+            ),
+            ConfigError::PerSenderRate(e) => write!(f, "per_sender_rate: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl SchedulerConfig {
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.spike_threshold_low > self.spike_threshold_high {
+            return Err(ConfigError::SpikeThresholdsInverted {
+                low: self.spike_threshold_low,
+                high: self.spike_threshold_high,
+            });
+        }
+        if let Some(limit) = &self.per_sender_rate {
+            limit.validate().map_err(ConfigError::PerSenderRate)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for SchedulerConfig {
+    fn default() -> Self {
+        Self {
+            target_base_fee: Gwei(50).into(),
+            honor_target_base_fee: false,
+            target_escalation_window: Duration::from_secs(60),
+            max_priority_fee: Gwei(2).into(),
+            spike_max_priority_fee: Gwei(10).into(),
+            spike_threshold_high: gwei_to_wei(15) as f64,
+            spike_threshold_low: gwei_to_wei(10) as f64,
+            trend_threshold: gwei_to_wei(1) as f64,
+            spike_confirm_samples: 1,
+            reprice_cooldown: Duration::from_millis(500),
+            reprice_backoff_factor: 1.0,
+            max_reprice_cooldown: Duration::from_secs(60),
+            max_reprices: None,
+            limiter_weighting: LimiterWeighting::PerTx,
+            per_sender_rate: None,
+            limiter_warn_rejection_ratio: Some(0.5),
+            dropped_retention: Duration::from_secs(600),
+            dropped_archive_capacity: 1024,
+            dropped_tx_policy: DroppedTxPolicy::Requeue,
+            sweep_interval: Duration::from_secs(1),
+            pending_max_age: None,
+            pending_max_evaluations: None,
+            market_updates: MarketUpdatePolicy::Disabled,
+            sink_failure_policy: SinkFailurePolicy::Log,
+            sink_timeout: Duration::from_secs(1),
+            balance_cache_ttl: Duration::from_secs(12),
+            balance_error_policy: BalanceErrorPolicy::FailClosed,
+            block_history: 64,
+            dedupe_window: None,
+            idempotency_window: Duration::from_secs(600),
+            idempotency_capacity: 10_000,
+            chain_id: 1,
+            reject_blob_requests: false,
+            auto_fill_nonce_gaps: false,
+            nonce_gap_fill_age: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Receiving end of a per-request status stream; closes after a terminal status.
+pub type StatusReceiver = mpsc::Receiver<TxStatus>;
+
+/// Updates buffered per watcher; a watcher that falls this far behind misses updates
+/// rather than stalling the scheduler, all but the terminal one.
+const STATUS_BUFFER: usize = 16;
+
+/// A request on its way into the scheduler, optionally with a status watcher attached.
+pub struct Submission {
+    pub req: TransactionRequest,
+    pub status: Option<mpsc::Sender<TxStatus>>,
+}
+
+impl From<TransactionRequest> for Submission {
+    fn from(req: TransactionRequest) -> Self {
+        Self { req, status: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SchedulerClosed;
+
+impl std::fmt::Display for SchedulerClosed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "scheduler is no longer running")
+    }
+}
+
+impl std::error::Error for SchedulerClosed {}
+
+/// Why a request didn't reach the scheduler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubmitError {
+    /// The scheduler is no longer running.
+    Closed,
+    /// Its request queue is full and rejects rather than waits; try again later.
+    Full,
+}
+
+impl std::fmt::Display for SubmitError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SubmitError::Closed => write!(f, "{}", SchedulerClosed),
+            SubmitError::Full => write!(f, "scheduler's request queue is full"),
+        }
+    }
+}
+
+impl std::error::Error for SubmitError {}
+
+impl<T> From<SendError<T>> for SubmitError {
+    fn from(e: SendError<T>) -> Self {
+        match e {
+            SendError::Full(_) => SubmitError::Full,
+            SendError::Closed(_) => SubmitError::Closed,
+        }
+    }
+}
+
+/// Cloneable front door to a running scheduler.
+#[derive(Clone)]
+pub struct SchedulerHandle {
+    requests: InstrumentedSender<Submission>,
+    commands: mpsc::Sender<SchedulerCommand>,
+}
+
+impl SchedulerHandle {
+    /// Creates a handle plus the receivers to pass to `Scheduler::run`.
+    pub fn channel(
+        capacity: usize,
+    ) -> (
+        Self,
+        mpsc::Receiver<Submission>,
+        mpsc::Receiver<SchedulerCommand>,
+    ) {
+        Self::channel_with_policy(capacity, BackpressurePolicy::Block)
+    }
+
+    /// As `channel`, with requests sent by `policy` once `capacity` are queued:
+    /// under `Reject`, `submit` fails with `SubmitError::Full`.
+    pub fn channel_with_policy(
+        capacity: usize,
+        policy: BackpressurePolicy,
+    ) -> (
+        Self,
+        mpsc::Receiver<Submission>,
+        mpsc::Receiver<SchedulerCommand>,
+    ) {
+        let (requests, req_rx) = crate::channel::instrumented_channel(capacity, policy);
+        let (commands, cmd_rx) = mpsc::channel(capacity);
+        (Self { requests, commands }, req_rx, cmd_rx)
+    }
+
+    /// The request queue's counters.
+    pub fn request_stats(&self) -> ChannelStats {
+        self.requests.stats()
+    }
+
+    /// The channel commands go to the scheduler on.
+    pub(crate) fn commands(&self) -> &mpsc::Sender<SchedulerCommand> {
+        &self.commands
+    }
+
+    pub async fn submit(&self, req: TransactionRequest) -> Result<(), SubmitError> {
+        Ok(self.requests.send(req.into()).await?)
+    }
+
+    /// As `submit`, but waits for room even if the request queue would reject;
+    /// for requests with no caller to tell, like restored ones.
+    pub async fn submit_waiting(&self, req: TransactionRequest) -> Result<(), SchedulerClosed> {
+        self.requests
+            .send_waiting(req.into())
+            .await
+            .map_err(|_| SchedulerClosed)
+    }
+
+    /// Submits `req` and returns a stream of its lifecycle updates.
+    pub async fn submit_with_status(
+        &self,
+        req: TransactionRequest,
+    ) -> Result<StatusReceiver, SubmitError> {
+        let (status_tx, status_rx) = mpsc::channel(STATUS_BUFFER);
+        let submission = Submission {
+            req,
+            status: Some(status_tx),
+        };
+        self.requests.send(submission).await?;
+        Ok(status_rx)
+    }
+
+    pub async fn command(&self, cmd: SchedulerCommand) -> Result<(), SchedulerClosed> {
+        self.commands.send(cmd).await.map_err(|_| SchedulerClosed)
+    }
 }
 
 struct SubmittedTx {
     req: TransactionRequest,
+    /// When the request was accepted; escalation steps count from here.
+    accepted_at: Instant,
     nonce: u64,
-    last_gas_price: u64,
+    last_gas_price: Wei,
     last_action_at: Instant,
+    /// Current gap required before the next Reprice; grows with backoff and starts
+    /// over when the tx is resubmitted.
+    cooldown: Duration,
+    reprices: u32,
+    /// Set once the executor reports the broadcast hash.
+    tx_hash: Option<[u8; 32]>,
+    /// Blob fee cap last sent for a blob request.
+    blob_gas_price: Option<Wei>,
+    /// Acceptance-time base fee plus the tip it was submitted with, the baseline for
+    /// realized savings; 0 when unknown, as for restored txs.
+    accepted_price: Wei,
+    /// Holds `nonce` until the broadcast is reported; dropping it releases the nonce.
+    reservation: Option<Reservation>,
+}
+
+struct PendingTx {
+    req: TransactionRequest,
+    /// Reason of the last Defer sent for this tx, so the same kind of wait isn't
+    /// re-announced when only its figures move.
+    last_defer: Option<DeferReason>,
+    accepted_at: Instant,
+    /// Evaluation passes in which the tx didn't qualify for submission.
+    evaluations: u32,
+    /// `NewBlock` events seen since acceptance.
+    blocks_waited: u32,
+    /// Base fee when the tx was accepted, the baseline for reported savings.
+    accepted_fee: Wei,
+}
+
+impl PendingTx {
+    /// The tx has waited out its `max_wait_blocks` and must now price for inclusion.
+    fn wait_budget_exhausted(&self) -> bool {
+        self.req
+            .max_wait_blocks
+            .is_some_and(|max| self.blocks_waited > max)
+    }
+
+    /// Counts a deferred evaluation; true when `reason` is a new kind of wait that
+    /// should be announced.
+    fn defer(&mut self, reason: &DeferReason) -> bool {
+        self.evaluations += 1;
+        let announce = self
+            .last_defer
+            .as_ref()
+            .is_none_or(|last| std::mem::discriminant(last) != std::mem::discriminant(reason));
+        self.last_defer = Some(reason.clone());
+        announce
+    }
+
+    fn new(req: TransactionRequest, accepted_fee: Wei) -> Self {
+        Self {
+            req,
+            last_defer: None,
+            accepted_at: Instant::now(),
+            evaluations: 0,
+            blocks_waited: 0,
+            accepted_fee,
+        }
+    }
+}
+
+/// Why a pending tx was retired without being submitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpiryReason {
+    DeadlineExpired,
+    DeadlineExpiredBelowMarket {
+        max_fee_per_gas: Wei,
+        base_fee: Wei,
+    },
+    MaxAge,
+    MaxEvaluations,
+    /// `max_wait_blocks` ran out and even inclusion-first pricing is above the cap.
+    WaitBudgetExhausted {
+        price: Wei,
+        max_fee_per_gas: Wei,
+    },
+}
+
+impl std::fmt::Display for ExpiryReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpiryReason::DeadlineExpired => write!(f, "deadline expired"),
+            ExpiryReason::DeadlineExpiredBelowMarket {
+                max_fee_per_gas,
+                base_fee,
+            } => write!(
+                f,
+                "deadline expired with fee cap {} below base fee {}",
+                max_fee_per_gas, base_fee
+            ),
+            ExpiryReason::MaxAge => write!(f, "exceeded max pending age"),
+            ExpiryReason::MaxEvaluations => {
+                write!(f, "exceeded max evaluations without qualifying")
+            }
+            ExpiryReason::WaitBudgetExhausted {
+                price,
+                max_fee_per_gas,
+            } => write!(
+                f,
+                "block wait budget exhausted with inclusion price {} above fee cap {}",
+                price, max_fee_per_gas
+            ),
+        }
+    }
+}
+
+/// `eth_feeHistory` shape: at least one block, one more base fee than blocks, and
+/// either no rewards or one equally long row per block.
+fn check_fee_history(
+    base_fees: &[u64],
+    gas_used_ratios: &[f64],
+    rewards: &[Vec<u64>],
+) -> Result<(), String> {
+    let blocks = gas_used_ratios.len();
+    if blocks == 0 {
+        return Err("no blocks".to_string());
+    }
+    if base_fees.len() != blocks + 1 {
+        return Err(format!(
+            "{} base fees for {} blocks, expected {}",
+            base_fees.len(),
+            blocks,
+            blocks + 1
+        ));
+    }
+    if !rewards.is_empty() {
+        if rewards.len() != blocks {
+            return Err(format!(
+                "{} reward rows for {} blocks",
+                rewards.len(),
+                blocks
+            ));
+        }
+        if rewards.iter().any(|row| row.len() != rewards[0].len()) {
+            return Err("reward rows differ in length".to_string());
+        }
+    }
+    Ok(())
+}
+
+/// What makes two requests the same on-chain transaction, ignoring id and pricing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Fingerprint {
+    chain_id: Option<u64>,
+    from: [u8; 20],
+    to: Option<[u8; 20]>,
+    data_hash: [u8; 32],
+    value: [u8; 32],
+}
+
+impl Fingerprint {
+    fn of(req: &TransactionRequest) -> Self {
+        Self {
+            chain_id: req.chain_id,
+            from: req.from,
+            to: req.to,
+            data_hash: keccak256(&req.data).0,
+            value: req.value,
+        }
+    }
+}
+
+struct ConfirmedTx {
+    tx: SubmittedTx,
+    block_number: u64,
+}
+
+struct CachedBalance {
+    balance: u128,
+    fetched_at: Instant,
+}
+
+struct DroppedTx {
+    req: TransactionRequest,
+    dropped_at: Instant,
+}
+
+/// The tx an idempotency key was first seen with, and what was last said about it.
+struct KeyedTx {
+    tx_id: u64,
+    last_decision: Option<SchedulerDecision>,
+}
+
+/// Idempotency keys seen within `idempotency_window`, oldest first.
+#[derive(Default)]
+struct IdempotencyKeys {
+    entries: HashMap<[u8; 16], KeyedTx>,
+    by_tx: HashMap<u64, [u8; 16]>,
+    order: VecDeque<([u8; 16], Instant)>,
 }
 
+/// Everything the run loop owns between events.
+#[derive(Default)]
+struct SchedulerState {
+    pending: Vec<PendingTx>,
+    submitted: HashMap<u64, SubmittedTx>,
+    /// Recently dropped requests, oldest first, kept around for resubmission.
+    dropped: VecDeque<DroppedTx>,
+    /// Inclusion-first mode, switched with hysteresis in `update_spike_mode`.
+    spike_mode: bool,
+    /// Consecutive samples that argued for leaving the current mode.
+    spike_streak: u32,
+    last_market_update: Option<Instant>,
+    /// Callers watching individual requests, removed on terminal status or hang-up.
+    watchers: HashMap<u64, mpsc::Sender<TxStatus>>,
+    /// Sender balances, refreshed after `balance_cache_ttl`.
+    balances: HashMap<[u8; 20], CachedBalance>,
+    /// Last `L1BaseFeeUpdate`; L1 data fees count as nothing until one arrives.
+    l1_base_fee: Option<u64>,
+    /// Highest block accepted on the current branch.
+    head_block: Option<u64>,
+    /// Newest `BaseFeeUpdate` timestamp accepted.
+    last_fee_timestamp: u64,
+    /// (number, hash) of the last `block_history` blocks on the current branch.
+    recent_blocks: VecDeque<(u64, [u8; 32])>,
+    /// Confirmed txs still within `block_history` blocks of the head.
+    confirmed: Vec<ConfirmedTx>,
+    /// Live requests by fingerprint with their acceptance time, when deduplicating.
+    fingerprints: HashMap<Fingerprint, (u64, Instant)>,
+    /// Accounts already announced with `NonceInitRequired`.
+    nonce_init_requested: HashSet<AccountKey>,
+    /// Last `NonceGapDetected` report per account, so a standing gap isn't repeated.
+    reported_gaps: HashMap<AccountKey, Vec<u64>>,
+    /// When each missing nonce was first seen.
+    gaps_seen_at: HashMap<(AccountKey, u64), Instant>,
+    /// Gap fillers created so far; their ids count up from `GAP_FILLER_ID_BASE`.
+    gap_fillers: u64,
+    /// Start of the current rejection-ratio window and the limiter's stats then.
+    limiter_window: Option<(Instant, RateLimiterStats)>,
+    idempotency: IdempotencyKeys,
+    /// Sequence number of the last emitted decision.
+    decision_seq: u64,
+    /// What the current pass is handling, and the block and fee timestamp it is
+    /// about, for the meta of the decisions it emits.
+    trigger: (DecisionTrigger, Option<u64>, Option<u64>),
+}
+
+/// Gap fillers get ids from the top half of the id space, away from request ids.
+const GAP_FILLER_ID_BASE: u64 = 1 << 63;
+
+/// Span over which the limiter's rejection ratio is judged.
+const LIMITER_WINDOW: Duration = Duration::from_secs(60);
+
+/// Blob base fee samples kept by the blob fee model.
+const BLOB_FEE_HISTORY: usize = 20;
+
 pub struct Scheduler {
     config: SchedulerConfig,
     model: Arc<GasModel>,
-    nonce_manager: Arc<NonceManager>,
-    limiter: Arc<RateLimiter>,
-    decision_tx: mpsc::Sender<SchedulerDecision>,
+    /// Fed by `BlobBaseFeeUpdate`; gates and prices blob requests.
+    blob_model: GasModel,
+    nonce_manager: Arc<dyn NonceAllocator>,
+    limiter: Arc<dyn Limiter>,
+    sender_limiter: Option<HierarchicalLimiter<[u8; 20]>>,
+    sinks: SinkSet,
+    balances: Option<Arc<dyn BalanceProvider>>,
+    nonces: Option<Arc<dyn NonceProvider>>,
+    /// OP-stack chains, whose txs also pay for posting their data to L1.
+    l1_data_fees: HashMap<u64, L1DataFeeEstimator>,
+    /// Replayed or out-of-order gas events ignored so far.
+    stale_events: AtomicU64,
+    /// Lengths of the pending and submitted queues after the last input.
+    pending_len: AtomicUsize,
+    submitted_len: AtomicUsize,
+    started_at: Instant,
+    started_at_unix_ms: u64,
+    shutdown: CancellationToken,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Arc<crate::metrics::Metrics>>,
+}
+
+/// What a scheduler still tracked when it stopped, to take up again after a
+/// restart: pending requests are submitted anew and submitted txs restored with
+/// `SchedulerCommand::RestoreSubmitted`.
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct SchedulerSnapshot {
+    pub pending: Vec<TransactionRequest>,
+    pub submitted: Vec<RestoredTx>,
 }
 
 impl Scheduler {
     pub fn new(
         config: SchedulerConfig,
         model: Arc<GasModel>,
-        nonce_manager: Arc<NonceManager>,
-        limiter: Arc<RateLimiter>,
-        decision_tx: mpsc::Sender<SchedulerDecision>,
+        nonce_manager: Arc<dyn NonceAllocator>,
+        limiter: Arc<dyn Limiter>,
+        sinks: Vec<Box<dyn DecisionSink>>,
     ) -> Self {
+        let sinks = SinkSet::new(sinks, config.sink_failure_policy, config.sink_timeout);
+        let sender_limiter = config
+            .per_sender_rate
+            .map(|limit| HierarchicalLimiter::new(limiter.clone(), limit));
         Self {
             config,
             model,
+            blob_model: GasModel::new(BLOB_FEE_HISTORY),
             nonce_manager,
             limiter,
-            decision_tx,
+            sender_limiter,
+            sinks,
+            balances: None,
+            nonces: None,
+            l1_data_fees: HashMap::new(),
+            stale_events: AtomicU64::new(0),
+            pending_len: AtomicUsize::new(0),
+            submitted_len: AtomicUsize::new(0),
+            shutdown: CancellationToken::new(),
+            #[cfg(feature = "metrics")]
+            metrics: None,
+            started_at: Instant::now(),
+            started_at_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
         }
     }
 
+    /// Defer txs whose worst-case cost exceeds what their sender can pay.
+    pub fn with_balance_provider(mut self, provider: Arc<dyn BalanceProvider>) -> Self {
+        self.balances = Some(provider);
+        self
+    }
+
+    /// Treat `chain_id` as an OP-stack chain: its txs' L1 data fee, at the last
+    /// `L1BaseFeeUpdate`, counts against their senders' balances and in the
+    /// `estimated_cost_wei` of their submits.
+    pub fn with_l1_data_fee(mut self, chain_id: u64, estimator: L1DataFeeEstimator) -> Self {
+        self.l1_data_fees.insert(chain_id, estimator);
+        self
+    }
+
+    /// Fetch each sender's starting nonce on first use instead of waiting for
+    /// `SchedulerCommand::InitNonce`.
+    pub fn with_nonce_provider(mut self, provider: Arc<dyn NonceProvider>) -> Self {
+        self.nonces = Some(provider);
+        self
+    }
+
+    /// Start the clock behind `now_millis` at `unix_ms` rather than the wall
+    /// clock, e.g. to replay recorded data with its own deadlines.
+    pub fn with_start_time(mut self, unix_ms: u64) -> Self {
+        self.started_at_unix_ms = unix_ms;
+        self
+    }
+
+    /// Stop once `shutdown` is cancelled: gas events are no longer taken, and
+    /// requests and commands already queued are handled before `run` returns.
+    pub fn with_shutdown(mut self, shutdown: CancellationToken) -> Self {
+        self.shutdown = shutdown;
+        self
+    }
+
+    /// The base fee and tip model decisions are priced from.
+    pub fn model(&self) -> Arc<GasModel> {
+        self.model.clone()
+    }
+
+    /// Keep `metrics` up to date: decisions and latencies as they happen, queue
+    /// depths, market and limiter figures after every input.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Arc<crate::metrics::Metrics>) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Gas events ignored because they were no newer than what was already seen.
+    pub fn stale_events(&self) -> u64 {
+        self.stale_events.load(Ordering::Relaxed)
+    }
+
+    /// Requests waiting to be submitted, as of the last input handled.
+    pub fn pending_count(&self) -> usize {
+        self.pending_len.load(Ordering::Relaxed)
+    }
+
+    /// Txs submitted and not yet settled, as of the last input handled.
+    pub fn submitted_count(&self) -> usize {
+        self.submitted_len.load(Ordering::Relaxed)
+    }
+
+    /// The shared submission limiter's counters.
+    pub fn limiter_stats(&self) -> RateLimiterStats {
+        self.limiter.stats()
+    }
+
+    /// Current unix time in seconds, advanced from a monotonic anchor so that
+    /// deadlines follow tokio's clock (and therefore paused time in tests).
+    pub fn now_secs(&self) -> u64 {
+        self.now_millis() / 1000
+    }
+
+    /// As `now_secs`, in milliseconds.
+    pub fn now_millis(&self) -> u64 {
+        self.started_at_unix_ms + self.started_at.elapsed().as_millis() as u64
+    }
+
     pub async fn run(
         self: Arc<Self>,
-        mut gas_events: mpsc::Receiver<GasEvent>,
-        mut tx_requests: mpsc::Receiver<TransactionRequest>,
-    ) {
-        let mut pending_txs: Vec<TransactionRequest> = Vec::new();
-        let mut submitted_txs: HashMap<u64, SubmittedTx> = HashMap::new();
+        gas_events: mpsc::Receiver<GasEvent>,
+        tx_requests: mpsc::Receiver<Submission>,
+        commands: mpsc::Receiver<SchedulerCommand>,
+    ) -> SchedulerSnapshot {
+        self.run_with_source(ChannelSource::new(gas_events), tx_requests, commands)
+            .await
+    }
+
+    /// Runs until the source and both channels are exhausted, or until the
+    /// shutdown token is cancelled and the channels are drained. Inputs that are
+    /// ready at the same time are taken in a fixed order (gas events, commands,
+    /// requests) so that replaying the same inputs yields the same decisions.
+    /// Returns the txs still pending or submitted.
+    pub async fn run_with_source<S: GasEventSource>(
+        self: Arc<Self>,
+        mut source: S,
+        mut tx_requests: mpsc::Receiver<Submission>,
+        mut commands: mpsc::Receiver<SchedulerCommand>,
+    ) -> SchedulerSnapshot {
+        let mut state = SchedulerState::default();
+        let mut sweep = tokio::time::interval(self.config.sweep_interval);
+        sweep.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let (mut source_open, mut commands_open, mut requests_open) = (true, true, true);
+        let mut stopping = false;
 
-        loop {
+        while source_open || commands_open || requests_open {
             tokio::select! {
-                Some(event) = gas_events.recv() => {
-                    self.handle_gas_event(event, &mut pending_txs, &mut submitted_txs).await;
-                }
-                Some(req) = tx_requests.recv() => {
-                    self.handle_tx_request(req, &mut pending_txs, &mut submitted_txs).await;
+                biased;
+                _ = self.shutdown.cancelled(), if !stopping => {
+                    info!("Scheduler stopping; handling what is already queued");
+                    stopping = true;
+                    source_open = false;
+                    tx_requests.close();
+                    commands.close();
                 }
-                else => break,
+                event = source.next(), if source_open => match event {
+                    Some(event) => self.handle_gas_event(event, &mut state).await,
+                    None => source_open = false,
+                },
+                cmd = commands.recv(), if commands_open => match cmd {
+                    Some(cmd) => self.handle_command(cmd, &mut state).await,
+                    None => commands_open = false,
+                },
+                submission = tx_requests.recv(), if requests_open => match submission {
+                    Some(submission) => self.handle_submission(submission, &mut state).await,
+                    None => requests_open = false,
+                },
+                _ = sweep.tick() => self.handle_tick(&mut state).await,
+            }
+            self.pending_len
+                .store(state.pending.len(), Ordering::Relaxed);
+            self.submitted_len
+                .store(state.submitted.len(), Ordering::Relaxed);
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.set_queues(state.pending.len(), state.submitted.len());
+                // read locks only, so updates to the model never wait on a scrape
+                metrics.set_market(self.model.current_fee(), self.model.get_volatility());
+                metrics.set_limiter(self.limiter.stats());
             }
         }
+        Self::snapshot(state)
     }
 
-    async fn handle_gas_event(
-        &self,
-        event: GasEvent,
-        pending_txs: &mut Vec<TransactionRequest>,
-        submitted_txs: &mut HashMap<u64, SubmittedTx>,
-    ) {
-        match event {
-            GasEvent::BaseFeeUpdate { base_fee, .. } | GasEvent::NewBlock { base_fee, .. } => {
-                self.model.update(base_fee);
-                self.re_evaluate_pending(pending_txs, submitted_txs).await;
-            }
-            GasEvent::TxConfirmed { tx_hash, .. } => {
-                info!("Inclusion event for tx hash: {:?}", tx_hash);
-                // Real-world: map hash to id and remove from submitted_txs
-            }
-            _ => {}
+    fn snapshot(state: SchedulerState) -> SchedulerSnapshot {
+        let mut submitted: Vec<RestoredTx> = state
+            .submitted
+            .into_values()
+            .map(|tx| RestoredTx {
+                req: tx.req,
+                nonce: tx.nonce,
+                gas_price: tx.last_gas_price,
+                tx_hash: tx.tx_hash,
+            })
+            .collect();
+        submitted.sort_by_key(|tx| tx.req.id);
+        SchedulerSnapshot {
+            pending: state.pending.into_iter().map(|tx| tx.req).collect(),
+            submitted,
         }
     }
 
-    async fn handle_tx_request(
+    /// Records what the decisions of the current pass are responding to. An
+    /// event's own block and timestamp win over the newest ones seen.
+    fn set_trigger(
         &self,
-        req: TransactionRequest,
-        pending_txs: &mut Vec<TransactionRequest>,
-        submitted_txs: &mut HashMap<u64, SubmittedTx>,
+        state: &mut SchedulerState,
+        trigger: DecisionTrigger,
+        event: Option<&GasEvent>,
     ) {
-        pending_txs.push(req);
-        self.re_evaluate_pending(pending_txs, submitted_txs).await;
+        let block_number = event.and_then(GasEvent::block_number).or(state.head_block);
+        let timestamp = match event {
+            Some(GasEvent::BaseFeeUpdate { timestamp, .. }) => Some(*timestamp),
+            _ => Some(state.last_fee_timestamp).filter(|&t| t != 0),
+        };
+        state.trigger = (trigger, block_number, timestamp);
     }
 
-    async fn re_evaluate_pending(
-        &self,
-        pending_txs: &mut Vec<TransactionRequest>,
-        submitted_txs: &mut HashMap<u64, SubmittedTx>,
-    ) {
-        let current_fee = self.model.current_fee();
-        let volatility = self.model.get_volatility();
-        let trend = self.model.get_trend();
-        let is_spike = volatility > self.config.spike_threshold;
+    async fn handle_tick(&self, state: &mut SchedulerState) {
+        self.set_trigger(state, DecisionTrigger::Tick, None);
+        self.sweep_pending(state).await;
+        self.check_limiter_rejections(state);
+    }
 
-        // 1. Repricing with cooldown
-        for tx in submitted_txs.values_mut() {
-            if tx.last_action_at.elapsed() < self.config.reprice_cooldown {
-                continue;
+    async fn handle_gas_event(&self, event: GasEvent, state: &mut SchedulerState) {
+        self.set_trigger(state, DecisionTrigger::GasEvent, Some(&event));
+        match event {
+            GasEvent::NewBlock {
+                number,
+                base_fee,
+                block_hash,
+                parent_hash,
+                ..
+            } => {
+                if self.is_stale_block(state, number, block_hash) {
+                    debug!("STALE BLOCK: ignoring replayed block {}", number);
+                    self.stale_events.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                state.head_block = Some(number);
+                if let Some((fork_block, depth)) =
+                    self.link_block(state, number, block_hash, parent_hash)
+                {
+                    self.handle_reorg(state, fork_block, depth).await;
+                }
+                let history = self.config.block_history as u64;
+                state
+                    .confirmed
+                    .retain(|c| c.block_number + history > number);
+
+                self.model.update_block(number, Wei::from(base_fee));
+                for p in &mut state.pending {
+                    p.blocks_waited += 1;
+                }
+                self.on_base_fee_sample(state, true).await;
+                self.check_nonce_gaps(state).await;
+            }
+            GasEvent::BaseFeeUpdate {
+                base_fee,
+                timestamp,
+            } => {
+                // a zero timestamp is unknown and always accepted
+                if timestamp != 0 && timestamp <= state.last_fee_timestamp {
+                    debug!("STALE BASE FEE: ignoring update at {}", timestamp);
+                    self.stale_events.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                state.last_fee_timestamp = state.last_fee_timestamp.max(timestamp);
+                self.model.update(Wei::from(base_fee));
+                self.on_base_fee_sample(state, false).await;
+            }
+            GasEvent::TxConfirmed {
+                tx_hash,
+                block_number,
+                effective_gas_price,
+                gas_used,
+            } => match Self::submitted_by_hash(state, tx_hash) {
+                Some(tx_id) => {
+                    info!("CONFIRMED: tx {} in block {}", tx_id, block_number);
+                    let tx = state.submitted.remove(&tx_id).unwrap();
+                    #[cfg(feature = "metrics")]
+                    if let Some(metrics) = &self.metrics {
+                        metrics.observe_confirm(tx.accepted_at.elapsed());
+                    }
+                    let (chain_id, address) = self.account_of(&tx.req);
+                    self.nonce_manager
+                        .record_confirmed(chain_id, address, tx.nonce);
+                    let decision = (gas_used > 0).then(|| {
+                        Self::realized(
+                            &tx,
+                            tx_id,
+                            block_number,
+                            Wei::from(effective_gas_price),
+                            gas_used,
+                        )
+                    });
+                    state.confirmed.push(ConfirmedTx { tx, block_number });
+                    self.notify(state, tx_id, TxStatus::Confirmed { block_number });
+                    if let Some(decision) = decision {
+                        self.emit(state, decision).await;
+                    }
+                }
+                None => info!("Inclusion event for tx hash: {}", hex_hash(&tx_hash)),
+            },
+            GasEvent::TxDropped { tx_hash, reason } => {
+                match Self::submitted_by_hash(state, tx_hash) {
+                    Some(tx_id) => self.handle_mempool_drop(state, tx_id, reason).await,
+                    None => info!("Drop event for tx hash: {}", hex_hash(&tx_hash)),
+                }
+            }
+            GasEvent::TxFailed {
+                tx_hash,
+                block_number,
+            } => {
+                let Some(tx_id) = Self::submitted_by_hash(state, tx_hash) else {
+                    info!("Revert event for tx hash: {}", hex_hash(&tx_hash));
+                    return;
+                };
+                warn!("REVERTED: tx {} in block {}", tx_id, block_number);
+                let tx = state.submitted.remove(&tx_id).unwrap();
+                let (chain_id, address) = self.account_of(&tx.req);
+                // the revert still used the nonce
+                self.nonce_manager
+                    .record_confirmed(chain_id, address, tx.nonce);
+                self.drop_tx(tx.req, "reverted".to_string(), state).await;
+            }
+            GasEvent::TxReplaced {
+                old_tx_hash,
+                new_tx_hash,
+                nonce,
+            } => {
+                let Some(tx_id) = Self::submitted_by_hash(state, old_tx_hash) else {
+                    info!("Replacement event for tx hash: {}", hex_hash(&old_tx_hash));
+                    return;
+                };
+                let tx = state.submitted.remove(&tx_id).unwrap();
+                if tx.nonce != nonce {
+                    warn!(
+                        "REPLACED: tx {} holds nonce {}, replacement reported at {}",
+                        tx_id, tx.nonce, nonce
+                    );
+                }
+                warn!(
+                    "NONCE CONSUMED: tx {} nonce {} taken by {}",
+                    tx_id,
+                    tx.nonce,
+                    hex_hash(&new_tx_hash)
+                );
+                let (chain_id, address) = self.account_of(&tx.req);
+                self.nonce_manager
+                    .record_confirmed(chain_id, address, tx.nonce);
+                let decision = SchedulerDecision::NonceConsumed {
+                    tx_id,
+                    nonce: tx.nonce,
+                };
+                self.emit(state, decision).await;
+            }
+            GasEvent::L1BaseFeeUpdate { base_fee } => {
+                debug!("L1 BASE FEE: {}", Wei::from(base_fee));
+                state.l1_base_fee = Some(base_fee);
+            }
+            GasEvent::BlobBaseFeeUpdate { blob_base_fee } => {
+                self.blob_model.update(Wei::from(blob_base_fee));
+                self.re_evaluate_pending(state).await;
+            }
+            GasEvent::FeeHistory {
+                oldest_block,
+                base_fees,
+                gas_used_ratios,
+                rewards,
+            } => {
+                if let Err(e) = check_fee_history(&base_fees, &gas_used_ratios, &rewards) {
+                    warn!("FEE HISTORY from block {} ignored: {}", oldest_block, e);
+                    return;
+                }
+                let (next_base_fee, mined) = base_fees.split_last().unwrap();
+                let mut fresh = 0;
+                for (i, &base_fee) in mined.iter().enumerate() {
+                    let number = oldest_block + i as u64;
+                    if state.head_block.is_some_and(|head| number <= head) {
+                        continue;
+                    }
+                    state.head_block = Some(number);
+                    self.model.update_block(number, Wei::from(base_fee));
+                    if let Some(rewards) = rewards.get(i) {
+                        self.model
+                            .update_rewards(rewards.iter().copied().map(Wei::from).collect());
+                    }
+                    fresh += 1;
+                }
+                if fresh < mined.len() {
+                    debug!(
+                        "STALE BLOCK: fee history replayed {} seen blocks",
+                        mined.len() - fresh
+                    );
+                    self.stale_events.fetch_add(1, Ordering::Relaxed);
+                }
+                // the next block's base fee, as a BaseFeeUpdate would carry it
+                self.model.update(Wei::from(*next_base_fee));
+                for p in &mut state.pending {
+                    p.blocks_waited += fresh as u32;
+                }
+                self.on_base_fee_sample(state, fresh > 0).await;
+            }
+            GasEvent::OracleEstimate {
+                source,
+                priority_fee_fast,
+                priority_fee_standard,
+                ..
+            } => {
+                debug!(
+                    "ORACLE: {} suggests tips of {} and {}",
+                    source, priority_fee_standard, priority_fee_fast
+                );
+                self.model.update_oracle_tips(
+                    Wei::from(priority_fee_standard),
+                    Wei::from(priority_fee_fast),
+                );
             }
+            GasEvent::MempoolTx { .. } => {}
+        }
+    }
 
-            let min_new_price = (tx.last_gas_price * 110) / 100;
-            let desired_price = current_fee + tx.req.max_priority_fee_per_gas;
+    fn submitted_by_hash(state: &SchedulerState, tx_hash: [u8; 32]) -> Option<u64> {
+        state
+            .submitted
+            .values()
+            .find(|tx| tx.tx_hash == Some(tx_hash))
+            .map(|tx| tx.req.id)
+    }
 
-            if desired_price > min_new_price && desired_price <= tx.req.max_fee_per_gas {
+    /// A broadcast tx fell out of the mempool unmined; see `DroppedTxPolicy`.
+    async fn handle_mempool_drop(&self, state: &mut SchedulerState, tx_id: u64, reason: String) {
+        match self.config.dropped_tx_policy {
+            DroppedTxPolicy::Requeue => {
+                let tx = state.submitted.remove(&tx_id).unwrap();
+                let (chain_id, address) = self.account_of(&tx.req);
+                let released = self
+                    .nonce_manager
+                    .release_unmined(chain_id, address, tx.nonce);
                 warn!(
-                    "REPRICING: tx {} from {} to {} (volatility: {:.2})",
-                    tx.req.id, tx.last_gas_price, desired_price, volatility
+                    "MEMPOOL DROP: tx {} ({}) back to pending, nonce {} released: {}",
+                    tx_id, reason, tx.nonce, released
                 );
-
+                let mut pending = PendingTx::new(tx.req, self.model.current_fee());
+                pending.accepted_at = tx.accepted_at;
+                state.pending.push(pending);
+                self.notify(state, tx_id, TxStatus::Pending);
+                self.re_evaluate_pending(state).await;
+            }
+            DroppedTxPolicy::Resubmit => {
+                let tip = self.effective_tip(&state.submitted[&tx_id].req, state.spike_mode);
+                let tx = state.submitted.get_mut(&tx_id).unwrap();
+                let price = (self.model.current_fee() + tip)
+                    .min(tx.req.max_fee_per_gas)
+                    .max(tx.last_gas_price);
+                warn!(
+                    "MEMPOOL DROP: tx {} ({}) resubmitted at nonce {} for {}",
+                    tx_id, reason, tx.nonce, price
+                );
+                tx.tx_hash = None;
+                tx.last_gas_price = price;
+                tx.last_action_at = Instant::now();
+                tx.blob_gas_price = self.blob_gas_price(&tx.req).max(tx.blob_gas_price);
                 let decision = SchedulerDecision::Reprice {
-                    tx_id: tx.req.id,
+                    tx_id,
                     old_nonce: tx.nonce,
-                    new_gas_price: desired_price,
+                    new_gas_price: price,
+                    max_priority_fee_per_gas: tip.min(price),
+                    blob_gas_price: tx.blob_gas_price,
+                    fee_mode: tx.req.fee_mode,
+                    privacy: tx.req.privacy,
                 };
-                let _ = self.decision_tx.send(decision).await;
-                tx.last_gas_price = desired_price;
-                tx.last_action_at = Instant::now();
+                self.emit(state, decision).await;
             }
         }
+    }
 
-        // 2. Pending submission
-        pending_txs.sort_by_key(|t| t.id);
-        let mut to_remove = Vec::new();
-
-        for (idx, tx) in pending_txs.iter().enumerate() {
-            if !self.limiter.check_and_consume() {
-                break;
-            }
+    /// A block at or below the head is a replay, unless it carries a different hash
+    /// than the one recorded for its height; that is a reorg and goes through
+    /// `link_block`.
+    fn is_stale_block(&self, state: &SchedulerState, number: u64, block_hash: [u8; 32]) -> bool {
+        if state.head_block.is_none_or(|head| number > head) {
+            return false;
+        }
+        let replaced = block_hash != [0; 32]
+            && state
+                .recent_blocks
+                .iter()
+                .any(|&(n, hash)| n == number && hash != block_hash);
+        !replaced
+    }
 
-            let decision = if is_spike {
-                info!("DEGRADATION MODE: Inclusion-first for tx {}", tx.id);
-                let gas_price = current_fee + tx.max_priority_fee_per_gas;
-                let nonce = self.nonce_manager.next_nonce(&tx.from);
-                Some(SchedulerDecision::Submit {
-                    tx_id: tx.id,
-                    nonce,
-                    gas_price,
-                })
-            } else if current_fee <= tx.max_fee_per_gas {
-                let gas_price = current_fee + tx.max_priority_fee_per_gas;
-                let nonce = self.nonce_manager.next_nonce(&tx.from);
-                Some(SchedulerDecision::Submit {
-                    tx_id: tx.id,
-                    nonce,
-                    gas_price,
-                })
-            } else if trend < -1.0 {
-                // Significant downward trend
-                info!(
-                    "FEE HIGH but trending down ({:.2}). Deferring tx {}",
-                    trend, tx.id
-                );
-                None
-            } else {
-                None
-            };
+    /// Records a block on the current branch and returns `(fork_block, depth)` if it
+    /// doesn't extend what was seen before. Only the block and its parent can be
+    /// compared, so a head-only feed that skips ahead of a deeper reorg is reported
+    /// as forking at the parent's height; feeds that replay the new branch from the
+    /// fork point are reported exactly.
+    fn link_block(
+        &self,
+        state: &mut SchedulerState,
+        number: u64,
+        block_hash: [u8; 32],
+        parent_hash: [u8; 32],
+    ) -> Option<(u64, u64)> {
+        const UNKNOWN: [u8; 32] = [0; 32];
+        if block_hash == UNKNOWN {
+            return None;
+        }
+        let recorded = |n: u64| {
+            state
+                .recent_blocks
+                .iter()
+                .find(|(number, _)| *number == n)
+                .map(|(_, hash)| *hash)
+        };
 
-            if let Some(d) = decision {
-                if let SchedulerDecision::Submit {
-                    tx_id,
-                    nonce,
-                    gas_price,
-                } = d
-                {
-                    submitted_txs.insert(
-                        tx_id,
-                        SubmittedTx {
-                            req: tx.clone(),
-                            nonce,
-                            last_gas_price: gas_price,
-                            last_action_at: Instant::now(),
-                        },
-                    );
-                    let _ = self.decision_tx.send(d).await;
-                }
-                to_remove.push(idx);
-            }
+        let mut fork_block = match recorded(number) {
+            Some(hash) if hash == block_hash => return None,
+            Some(_) => Some(number),
+            None => None,
+        };
+        if let Some(parent) = number.checked_sub(1)
+            && parent_hash != UNKNOWN
+            && recorded(parent).is_some_and(|hash| hash != parent_hash)
+        {
+            fork_block = Some(parent);
         }
+        let reorg = fork_block.map(|fork_block| {
+            let depth = state
+                .recent_blocks
+                .iter()
+                .filter(|(number, _)| *number >= fork_block)
+                .count() as u64;
+            state
+                .recent_blocks
+                .retain(|(number, _)| *number < fork_block);
+            (fork_block, depth)
+        });
 
-        for &idx in to_remove.iter().rev() {
-            pending_txs.remove(idx);
+        state.recent_blocks.push_back((number, block_hash));
+        while state.recent_blocks.len() > self.config.block_history {
+            state.recent_blocks.pop_front();
         }
+        reorg
+    }
+
+    /// Puts txs confirmed on the abandoned branch back among the submitted ones.
+    async fn handle_reorg(&self, state: &mut SchedulerState, fork_block: u64, depth: u64) {
+        let mut unconfirmed = Vec::new();
+        // lowest reverted nonce per account
+        let mut reverted: HashMap<AccountKey, u64> = HashMap::new();
+        let mut idx = 0;
+        while idx < state.confirmed.len() {
+            if state.confirmed[idx].block_number >= fork_block {
+                let tx = state.confirmed.swap_remove(idx).tx;
+                unconfirmed.push(tx.req.id);
+                let lowest = reverted.entry(self.account_of(&tx.req)).or_insert(tx.nonce);
+                *lowest = (*lowest).min(tx.nonce);
+                state.submitted.insert(tx.req.id, tx);
+            } else {
+                idx += 1;
+            }
+        }
+        unconfirmed.sort_unstable();
+        for ((chain_id, address), from_nonce) in reverted {
+            let nonces = self
+                .nonce_manager
+                .rollback_confirmed(chain_id, address, from_nonce);
+            info!(
+                "NONCE: sender {} on chain {} has nonces {:?} pending again",
+                address, chain_id, nonces
+            );
+        }
+        warn!(
+            "REORG: fork at block {} discarded {} blocks, unconfirmed txs {:?}",
+            fork_block, depth, unconfirmed
+        );
+        let decision = SchedulerDecision::Reorg {
+            fork_block,
+            depth,
+            unconfirmed,
+        };
+        self.emit(state, decision).await;
+    }
+
+    /// Reacts to a fresh base fee sample already recorded in the model.
+    async fn on_base_fee_sample(&self, state: &mut SchedulerState, new_block: bool) {
+        let volatility = self.model.get_volatility();
+        if let Some(spike) = self.update_spike_mode(state, volatility) {
+            self.emit(state, SchedulerDecision::ModeChanged { spike })
+                .await;
+        }
+        if self.market_update_due(state, new_block) {
+            state.last_market_update = Some(Instant::now());
+            let update = SchedulerDecision::MarketUpdate {
+                current_fee: self.model.current_fee(),
+                volatility,
+                trend: self.model.get_trend(),
+                spike: state.spike_mode,
+            };
+            self.emit(state, update).await;
+        }
+        self.re_evaluate_pending(state).await;
+    }
+
+    fn market_update_due(&self, state: &SchedulerState, new_block: bool) -> bool {
+        match self.config.market_updates {
+            MarketUpdatePolicy::Disabled => false,
+            MarketUpdatePolicy::EveryBlock => new_block,
+            MarketUpdatePolicy::Every(interval) => state
+                .last_market_update
+                .is_none_or(|last| last.elapsed() >= interval),
+        }
+    }
+
+    async fn handle_submission(&self, submission: Submission, state: &mut SchedulerState) {
+        self.set_trigger(state, DecisionTrigger::Request, None);
+        // a repeat's watcher is dropped; the original's watcher carries on
+        if self.acknowledge_repeat(&submission.req, state).await {
+            return;
+        }
+        if let Some(status) = submission.status {
+            state.watchers.insert(submission.req.id, status);
+        }
+        self.handle_tx_request(submission.req, state).await;
+    }
+
+    async fn handle_tx_request(&self, req: TransactionRequest, state: &mut SchedulerState) {
+        self.set_trigger(state, DecisionTrigger::Request, None);
+        // before anything is reserved for it, and before dedupe remembers it
+        let checked = req.validate(self.now_secs()).and_then(|()| {
+            if self.config.reject_blob_requests && req.blob.is_some() {
+                Err(ValidationError::BlobUnsupported)
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(e) = checked {
+            warn!("REJECTED: {} ({})", req, e);
+            let decision = SchedulerDecision::Rejected {
+                tx_id: req.id,
+                reason: e.to_string(),
+                error: Some(e),
+            };
+            self.emit(state, decision).await;
+            return;
+        }
+        if let Some(original) = self.duplicate_of(&req, state) {
+            warn!("DUPLICATE: tx {} repeats tx {}", req.id, original);
+            let decision = SchedulerDecision::Drop {
+                tx_id: req.id,
+                reason: format!("duplicate of #{}", original),
+            };
+            self.emit(state, decision).await;
+            return;
+        }
+        self.notify(state, req.id, TxStatus::Pending);
+        state
+            .pending
+            .push(PendingTx::new(req, self.model.current_fee()));
+        self.re_evaluate_pending(state).await;
+    }
+
+    /// If `req`'s idempotency key was seen within `idempotency_window`, re-emits the
+    /// latest decision about the original and returns true. Otherwise remembers the
+    /// key for `req`.
+    async fn acknowledge_repeat(
+        &self,
+        req: &TransactionRequest,
+        state: &mut SchedulerState,
+    ) -> bool {
+        let Some(key) = req.idempotency_key else {
+            return false;
+        };
+        self.evict_idempotency_keys(state);
+        let Some(original) = state.idempotency.entries.get(&key) else {
+            state.idempotency.entries.insert(
+                key,
+                KeyedTx {
+                    tx_id: req.id,
+                    last_decision: None,
+                },
+            );
+            state.idempotency.by_tx.insert(req.id, key);
+            state.idempotency.order.push_back((key, Instant::now()));
+            return false;
+        };
+
+        info!(
+            "IDEMPOTENT REPEAT: tx {} repeats tx {} (key {})",
+            req.id,
+            original.tx_id,
+            alloy_primitives::hex::encode(key)
+        );
+        if let Some(decision) = original.last_decision.clone() {
+            let record = self.record(state, decision, true);
+            self.sinks.deliver(record).await;
+        }
+        true
+    }
+
+    /// Forgets idempotency keys that are past `idempotency_window` or over capacity.
+    fn evict_idempotency_keys(&self, state: &mut SchedulerState) {
+        let keys = &mut state.idempotency;
+        while let Some(&(key, seen_at)) = keys.order.front() {
+            if seen_at.elapsed() < self.config.idempotency_window
+                && keys.order.len() <= self.config.idempotency_capacity
+            {
+                break;
+            }
+            keys.order.pop_front();
+            if let Some(keyed) = keys.entries.remove(&key) {
+                keys.by_tx.remove(&keyed.tx_id);
+            }
+        }
+    }
+
+    /// Id of a live request `req` duplicates; otherwise registers `req` as the original.
+    fn duplicate_of(&self, req: &TransactionRequest, state: &mut SchedulerState) -> Option<u64> {
+        let window = self.config.dedupe_window?;
+        state
+            .fingerprints
+            .retain(|_, (_, accepted_at)| accepted_at.elapsed() < window);
+
+        let fingerprint = Fingerprint::of(req);
+        if let Some(&(original, _)) = state.fingerprints.get(&fingerprint) {
+            return Some(original);
+        }
+        state
+            .fingerprints
+            .insert(fingerprint, (req.id, Instant::now()));
+        None
+    }
+
+    async fn handle_command(&self, cmd: SchedulerCommand, state: &mut SchedulerState) {
+        self.set_trigger(state, DecisionTrigger::Command, None);
+        match cmd {
+            SchedulerCommand::Resubmit {
+                tx_id,
+                new_max_fee_per_gas,
+                new_deadline,
+            } => {
+                self.evict_dropped(state);
+                let Some(pos) = state.dropped.iter().position(|d| d.req.id == tx_id) else {
+                    let decision = SchedulerDecision::Rejected {
+                        tx_id,
+                        reason: "not in dropped archive".to_string(),
+                        error: None,
+                    };
+                    self.emit(state, decision).await;
+                    return;
+                };
+
+                // The request keeps its original id so callers can correlate the
+                // new decisions with the earlier Drop.
+                let mut req = state.dropped.remove(pos).unwrap().req;
+                if let Some(max_fee) = new_max_fee_per_gas {
+                    req.max_fee_per_gas = max_fee;
+                }
+                if let Some(deadline) = new_deadline {
+                    req.deadline = Some(deadline);
+                }
+                info!(
+                    "RESUBMIT: tx {} with max fee {} and deadline {:?}",
+                    req.id, req.max_fee_per_gas, req.deadline
+                );
+                state
+                    .pending
+                    .push(PendingTx::new(req, self.model.current_fee()));
+                self.re_evaluate_pending(state).await;
+            }
+            SchedulerCommand::Broadcast { tx_id, tx_hash } => {
+                let Some(tx) = state.submitted.get_mut(&tx_id) else {
+                    warn!("Broadcast report for unknown tx {}", tx_id);
+                    return;
+                };
+                tx.tx_hash = Some(tx_hash);
+                if let Some(reservation) = tx.reservation.take()
+                    && let Err(e) = reservation.commit()
+                {
+                    // the nonce may be handed out again; nothing to do but shout
+                    warn!(
+                        "NONCE: tx {} broadcast with nonce {}: {}",
+                        tx_id, tx.nonce, e
+                    );
+                }
+                self.notify(state, tx_id, TxStatus::Broadcast { tx_hash });
+            }
+            SchedulerCommand::BroadcastFailed { tx_id, reason } => {
+                let Some(tx) = state.submitted.remove(&tx_id) else {
+                    warn!("Broadcast failure for unknown tx {}", tx_id);
+                    return;
+                };
+                if tx.reservation.is_none() {
+                    warn!(
+                        "NONCE: tx {} already broadcast with nonce {}",
+                        tx_id, tx.nonce
+                    );
+                }
+                // releases the nonce
+                drop(tx.reservation);
+                self.drop_tx(tx.req, format!("broadcast failed: {}", reason), state)
+                    .await;
+            }
+            SchedulerCommand::InitNonce {
+                chain_id,
+                address,
+                nonce,
+                force,
+            } => {
+                let chain_id = chain_id.unwrap_or(self.config.chain_id);
+                let address = Address::from(address);
+                if force {
+                    let previous = self.nonce_manager.force_set_nonce(chain_id, address, nonce);
+                    warn!(
+                        "NONCE: sender {} on chain {} forced to {} (was {:?})",
+                        address, chain_id, nonce, previous
+                    );
+                } else {
+                    let update = self.nonce_manager.update_nonce(chain_id, address, nonce);
+                    info!(
+                        "NONCE: sender {} on chain {} reported at {}: {:?}",
+                        address, chain_id, nonce, update
+                    );
+                }
+                state.nonce_init_requested.remove(&(chain_id, address));
+                self.re_evaluate_pending(state).await;
+            }
+            SchedulerCommand::NonceTooLow {
+                chain_id,
+                address,
+                network_nonce,
+            } => {
+                let chain_id = chain_id.unwrap_or(self.config.chain_id);
+                let address = Address::from(address);
+                let outcome = self.nonce_manager.resync(chain_id, address, network_nonce);
+                info!(
+                    "NONCE: sender {} on chain {} resynced to {} (was {:?}), consumed {:?}",
+                    address, chain_id, outcome.next, outcome.previous_next, outcome.consumed
+                );
+                state.nonce_init_requested.remove(&(chain_id, address));
+
+                let mut superseded: Vec<(u64, u64)> = state
+                    .submitted
+                    .values()
+                    .filter(|tx| {
+                        self.account_of(&tx.req) == (chain_id, address) && tx.nonce < network_nonce
+                    })
+                    .map(|tx| (tx.req.id, tx.nonce))
+                    .collect();
+                superseded.sort_unstable_by_key(|&(_, nonce)| nonce);
+                for (tx_id, nonce) in superseded {
+                    // the resync already forgot its reservation, so this releases nothing
+                    state.submitted.remove(&tx_id);
+                    warn!("NONCE CONSUMED: tx {} nonce {} used on chain", tx_id, nonce);
+                    self.emit(state, SchedulerDecision::NonceConsumed { tx_id, nonce })
+                        .await;
+                }
+                self.re_evaluate_pending(state).await;
+            }
+            SchedulerCommand::RestoreSubmitted { txs } => {
+                self.restore_submitted(txs, state).await;
+                self.re_evaluate_pending(state).await;
+            }
+            SchedulerCommand::SetSubmissionRate {
+                tokens_per_sec,
+                burst,
+            } => {
+                self.limiter.set_rate(tokens_per_sec);
+                if let Some(burst) = burst {
+                    self.limiter.set_capacity(burst);
+                }
+                info!("SUBMISSION RATE: {}/s, burst {:?}", tokens_per_sec, burst);
+            }
+            SchedulerCommand::Cancel { tx_id } => {
+                let Some(idx) = state.pending.iter().position(|p| p.req.id == tx_id) else {
+                    warn!("Cancel for tx {}, which is not pending", tx_id);
+                    return;
+                };
+                let pending = state.pending.swap_remove(idx);
+                // ahead of the drop's own status, which then has no watcher left
+                self.notify(state, tx_id, TxStatus::Cancelled);
+                self.drop_tx(pending.req, "cancelled".to_string(), state)
+                    .await;
+            }
+        }
+    }
+
+    /// Tracks txs submitted before a restart again once their senders' nonces are
+    /// reconciled with them. Of several restored txs claiming one nonce, the
+    /// broadcast one is kept, then the lowest id; the rest, and txs whose nonce
+    /// the chain already used or another tracked tx holds, become `NonceConflict`s.
+    async fn restore_submitted(&self, txs: Vec<RestoredTx>, state: &mut SchedulerState) {
+        let mut conflicts = Vec::new();
+        let mut by_account: HashMap<AccountKey, Vec<RestoredTx>> = HashMap::new();
+        for tx in txs {
+            let account = self.account_of(&tx.req);
+            let tracked = state.submitted.contains_key(&tx.req.id)
+                || state.pending.iter().any(|p| p.req.id == tx.req.id);
+            let holder = state
+                .submitted
+                .values()
+                .find(|s| s.nonce == tx.nonce && self.account_of(&s.req) == account)
+                .map(|s| s.req.id);
+            if tracked {
+                conflicts.push((account, tx, "tx id already tracked".to_string()));
+            } else if let Some(holder) = holder {
+                conflicts.push((account, tx, format!("nonce held by tx {}", holder)));
+            } else {
+                by_account.entry(account).or_default().push(tx);
+            }
+        }
+
+        let mut accounts: Vec<_> = by_account.into_iter().collect();
+        accounts.sort_unstable_by_key(|(account, _)| *account);
+        for ((chain_id, address), mut txs) in accounts {
+            let nonces: Vec<u64> = txs.iter().map(|tx| tx.nonce).collect();
+            let report = self.nonce_manager.reconcile(chain_id, address, &nonces);
+            info!(
+                "NONCE: sender {} on chain {} reconciled with {} restored txs: {:?}",
+                address,
+                chain_id,
+                txs.len(),
+                report
+            );
+            txs.sort_by_key(|tx| (tx.tx_hash.is_none(), tx.req.id));
+            let mut kept = HashSet::new();
+            for tx in txs {
+                let claims = nonces.iter().filter(|&&n| n == tx.nonce).count();
+                let reason = if report.confirmed.contains(&tx.nonce) {
+                    Some("nonce already confirmed".to_string())
+                } else if !report.duplicates.contains(&tx.nonce) {
+                    None
+                } else if claims == 1 {
+                    Some("nonce held by a reservation".to_string())
+                } else if !kept.insert(tx.nonce) {
+                    Some("nonce claimed by another restored tx".to_string())
+                } else {
+                    None
+                };
+                if let Some(reason) = reason {
+                    conflicts.push(((chain_id, address), tx, reason));
+                    continue;
+                }
+                info!("RESTORED: tx {} at nonce {}", tx.req.id, tx.nonce);
+                state.submitted.insert(
+                    tx.req.id,
+                    SubmittedTx {
+                        req: tx.req,
+                        accepted_at: Instant::now(),
+                        nonce: tx.nonce,
+                        last_gas_price: tx.gas_price,
+                        last_action_at: Instant::now(),
+                        cooldown: self.config.reprice_cooldown,
+                        reprices: 0,
+                        tx_hash: tx.tx_hash,
+                        blob_gas_price: None,
+                        accepted_price: Wei::ZERO,
+                        reservation: None,
+                    },
+                );
+            }
+        }
+
+        for ((chain_id, address), tx, reason) in conflicts {
+            warn!(
+                "NONCE CONFLICT: restored tx {} at nonce {} of sender {}: {}",
+                tx.req.id, tx.nonce, address, reason
+            );
+            let decision = SchedulerDecision::NonceConflict {
+                tx_id: tx.req.id,
+                chain_id,
+                address: address.into_array(),
+                nonce: tx.nonce,
+                reason,
+            };
+            self.emit(state, decision).await;
+        }
+    }
+
+    /// The `Confirmed` decision for a mined tx: what it paid against what it offered
+    /// and against its acceptance-time price.
+    fn realized(
+        tx: &SubmittedTx,
+        tx_id: u64,
+        block_number: u64,
+        effective_gas_price: Wei,
+        gas_used: u64,
+    ) -> SchedulerDecision {
+        let realized_cost_wei = effective_gas_price * gas_used;
+        SchedulerDecision::Confirmed {
+            tx_id,
+            block_number,
+            effective_gas_price,
+            realized_cost_wei,
+            savings_vs_max_fee_wei: (tx.last_gas_price * gas_used)
+                .saturating_sub(realized_cost_wei),
+            savings_vs_acceptance_wei: (tx.accepted_price * gas_used)
+                .saturating_sub(realized_cost_wei),
+        }
+    }
+
+    /// Numbers `decision`, tags it with the tx and idempotency key it is about and
+    /// stamps it with the current pass's trigger and market.
+    fn record(
+        &self,
+        state: &mut SchedulerState,
+        decision: SchedulerDecision,
+        replayed: bool,
+    ) -> DecisionRecord {
+        state.decision_seq += 1;
+        let correlation_id = decision.tx_id().map(|tx_id| CorrelationId {
+            tx_id,
+            idempotency_key: state.idempotency.by_tx.get(&tx_id).copied(),
+        });
+        let (trigger, block_number, timestamp) = state.trigger;
+        let meta = DecisionMeta {
+            decided_at_unix_ms: self.now_millis(),
+            trigger,
+            block_number,
+            timestamp,
+            market: MarketSnapshot {
+                current_fee: self.model.current_fee(),
+                volatility: self.model.get_volatility(),
+                spike: state.spike_mode,
+            },
+        };
+        DecisionRecord {
+            seq: state.decision_seq,
+            correlation_id,
+            replayed,
+            meta,
+            decision,
+        }
+    }
+
+    /// Delivers a decision to every sink and mirrors it to the tx's watcher, if any.
+    /// Returns whether any sink took it.
+    async fn emit(&self, state: &mut SchedulerState, decision: SchedulerDecision) -> bool {
+        let status = match &decision {
+            SchedulerDecision::Submit {
+                tx_id,
+                nonce,
+                gas_price,
+                ..
+            } => Some((
+                *tx_id,
+                TxStatus::Submitted {
+                    nonce: *nonce,
+                    gas_price: *gas_price,
+                },
+            )),
+            SchedulerDecision::Defer { tx_id, reason, .. } => {
+                Some((*tx_id, TxStatus::Deferred(reason.to_string())))
+            }
+            SchedulerDecision::Reprice {
+                tx_id,
+                new_gas_price,
+                ..
+            } => Some((
+                *tx_id,
+                TxStatus::Repriced {
+                    new_gas_price: *new_gas_price,
+                },
+            )),
+            SchedulerDecision::Drop { tx_id, reason } => {
+                Some((*tx_id, TxStatus::Dropped(reason.clone())))
+            }
+            SchedulerDecision::Rejected { tx_id, reason, .. } => {
+                Some((*tx_id, TxStatus::Rejected(reason.clone())))
+            }
+            SchedulerDecision::NonceConsumed { tx_id, nonce } => {
+                Some((*tx_id, TxStatus::NonceConsumed { nonce: *nonce }))
+            }
+            SchedulerDecision::ModeChanged { .. }
+            | SchedulerDecision::FillNonceGap { .. }
+            | SchedulerDecision::NonceInitRequired { .. }
+            | SchedulerDecision::NonceGapDetected { .. }
+            | SchedulerDecision::NonceConflict { .. }
+            | SchedulerDecision::Reorg { .. }
+            | SchedulerDecision::MarketUpdate { .. }
+            // the watcher already got `TxStatus::Confirmed`
+            | SchedulerDecision::Confirmed { .. } => None,
+        };
+        if let Some((tx_id, status)) = status {
+            self.notify(state, tx_id, status);
+        }
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.record_decision(&decision);
+            if let SchedulerDecision::Submit { tx_id, .. } = &decision
+                && let Some(tx) = state.submitted.get(tx_id)
+            {
+                metrics.observe_submit(tx.accepted_at.elapsed());
+            }
+        }
+        let record = self.record(state, decision, false);
+        if let Some(key) = record.correlation_id.and_then(|c| c.idempotency_key)
+            && let Some(keyed) = state.idempotency.entries.get_mut(&key)
+        {
+            keyed.last_decision = Some(record.decision.clone());
+        }
+        self.sinks.deliver(record).await
+    }
+
+    /// Pushes a status to the tx's watcher without ever waiting on it. A full
+    /// watcher misses the update, except a terminal one, which is handed to a task
+    /// that waits for room or for the watcher to hang up. Every terminal status
+    /// passes through here, so it also retires the tx's fingerprint.
+    fn notify(&self, state: &mut SchedulerState, tx_id: u64, status: TxStatus) {
+        let terminal = status.is_terminal();
+        if terminal && !state.fingerprints.is_empty() {
+            state.fingerprints.retain(|_, (id, _)| *id != tx_id);
+        }
+        let Some(watcher) = state.watchers.get(&tx_id) else {
+            return;
+        };
+        match watcher.try_send(status) {
+            Err(TrySendError::Full(status)) if terminal => {
+                let watcher = watcher.clone();
+                tokio::spawn(async move {
+                    let _ = watcher.send(status).await;
+                });
+            }
+            Err(TrySendError::Closed(_)) => {
+                state.watchers.remove(&tx_id);
+            }
+            _ => {}
+        }
+        if terminal {
+            state.watchers.remove(&tx_id);
+        }
+    }
+
+    /// Forgets archived drops that are past retention or over capacity.
+    fn evict_dropped(&self, state: &mut SchedulerState) {
+        while let Some(front) = state.dropped.front() {
+            if front.dropped_at.elapsed() < self.config.dropped_retention
+                && state.dropped.len() <= self.config.dropped_archive_capacity
+            {
+                break;
+            }
+            state.dropped.pop_front();
+        }
+    }
+
+    /// Feeds one volatility sample into the spike-mode state machine and returns
+    /// the new mode if it flipped.
+    fn update_spike_mode(&self, state: &mut SchedulerState, volatility: f64) -> Option<bool> {
+        let beyond = if state.spike_mode {
+            volatility < self.config.spike_threshold_low
+        } else {
+            volatility > self.config.spike_threshold_high
+        };
+        if !beyond {
+            state.spike_streak = 0;
+            return None;
+        }
+
+        state.spike_streak += 1;
+        if state.spike_streak < self.config.spike_confirm_samples.max(1) {
+            return None;
+        }
+        state.spike_streak = 0;
+        state.spike_mode = !state.spike_mode;
+        warn!(
+            "MODE CHANGE: spike={} (volatility: {:.2})",
+            state.spike_mode, volatility
+        );
+        Some(state.spike_mode)
+    }
+
+    /// Whether `req` should keep waiting for the base fee to come down to target.
+    fn waits_for_target(&self, req: &TransactionRequest, current_fee: Wei, now_secs: u64) -> bool {
+        if !self.config.honor_target_base_fee
+            || current_fee <= self.config.target_base_fee
+            || req.urgency == Urgency::High
+        {
+            return false;
+        }
+        let escalation = self.config.target_escalation_window.as_secs();
+        req.deadline
+            .is_none_or(|deadline| deadline.saturating_sub(now_secs) > escalation)
+    }
+
+    /// When a tx waiting for the target base fee goes anyway: once its deadline is
+    /// within the escalation window, or for a tx without one, once the fee is down.
+    fn target_retry_hint(&self, req: &TransactionRequest, now_secs: u64) -> RetryHint {
+        let escalation = self.config.target_escalation_window.as_secs();
+        match req.deadline {
+            Some(deadline) => RetryHint::AfterDuration(Duration::from_secs(
+                deadline.saturating_sub(escalation).saturating_sub(now_secs),
+            )),
+            None => RetryHint::WhenFeeBelow(self.config.target_base_fee),
+        }
+    }
+
+    /// Why a blob request can't go out yet: the blob fee is unknown or above its cap.
+    fn blob_fee_wait(&self, req: &TransactionRequest) -> Option<DeferReason> {
+        let blob = req.blob?;
+        if self.blob_model.sample_count() == 0 {
+            return Some(DeferReason::BlobFeeUnknown);
+        }
+        let blob_base_fee = self.blob_model.current_fee();
+        (blob_base_fee > blob.max_fee_per_blob_gas).then_some(DeferReason::BlobFeeAboveMax {
+            blob_base_fee,
+            max_fee_per_blob_gas: blob.max_fee_per_blob_gas,
+        })
+    }
+
+    /// Blob fee cap for a blob request: the current blob base fee plus the most it
+    /// can rise in one block (12.5%), within the request's cap.
+    fn blob_gas_price(&self, req: &TransactionRequest) -> Option<Wei> {
+        let blob = req.blob?;
+        let blob_base_fee = self.blob_model.current_fee();
+        Some((blob_base_fee + blob_base_fee.div_ceil(8)).min(blob.max_fee_per_blob_gas))
+    }
+
+    /// Tip for `req`, clamped to the configured ceiling for the current mode.
+    fn effective_tip(&self, req: &TransactionRequest, inclusion_first: bool) -> Wei {
+        let ceiling = if inclusion_first {
+            self.config.spike_max_priority_fee
+        } else {
+            self.config.max_priority_fee
+        };
+        req.max_priority_fee_per_gas.min(ceiling)
+    }
+
+    fn log_tip_clamp(&self, req: &TransactionRequest, tip: Wei) {
+        if tip < req.max_priority_fee_per_gas {
+            warn!(
+                "TIP CLAMPED: tx {} requested {} but ceiling is {}",
+                req.id, req.max_priority_fee_per_gas, tip
+            );
+        }
+    }
+
+    async fn drop_tx(&self, req: TransactionRequest, reason: String, state: &mut SchedulerState) {
+        warn!("DROPPING: tx {} ({})", req.id, reason);
+        let decision = SchedulerDecision::Drop {
+            tx_id: req.id,
+            reason,
+        };
+        self.emit(state, decision).await;
+
+        state.dropped.push_back(DroppedTx {
+            req,
+            dropped_at: Instant::now(),
+        });
+        self.evict_dropped(state);
+    }
+
+    fn expiry_reason(&self, p: &PendingTx, now_secs: u64, base_fee: Wei) -> Option<ExpiryReason> {
+        if p.req.deadline.is_some_and(|deadline| now_secs >= deadline) {
+            if base_fee > p.req.max_fee_per_gas {
+                return Some(ExpiryReason::DeadlineExpiredBelowMarket {
+                    max_fee_per_gas: p.req.max_fee_per_gas,
+                    base_fee,
+                });
+            }
+            return Some(ExpiryReason::DeadlineExpired);
+        }
+        if self
+            .config
+            .pending_max_age
+            .is_some_and(|max_age| p.accepted_at.elapsed() >= max_age)
+        {
+            return Some(ExpiryReason::MaxAge);
+        }
+        if self
+            .config
+            .pending_max_evaluations
+            .is_some_and(|max| p.evaluations >= max)
+        {
+            return Some(ExpiryReason::MaxEvaluations);
+        }
+        if p.wait_budget_exhausted() {
+            let price = base_fee + self.effective_tip(&p.req, true);
+            if price > p.req.max_fee_per_gas {
+                return Some(ExpiryReason::WaitBudgetExhausted {
+                    price,
+                    max_fee_per_gas: p.req.max_fee_per_gas,
+                });
+            }
+        }
+        None
+    }
+
+    /// Drops every pending tx that has outlived its retention rules. Runs on the
+    /// sweep tick and before each evaluation; never consumes limiter tokens.
+    /// Once a `LIMITER_WINDOW` has passed, warns if the limiter refused too many
+    /// requests in it and starts the next window. Returns the ratio it warned about.
+    fn check_limiter_rejections(&self, state: &mut SchedulerState) -> Option<f64> {
+        let threshold = self.config.limiter_warn_rejection_ratio?;
+        let now = Instant::now();
+        let stats = self.limiter.stats();
+        let Some((started, earlier)) = state.limiter_window else {
+            state.limiter_window = Some((now, stats));
+            return None;
+        };
+        if now.duration_since(started) < LIMITER_WINDOW {
+            return None;
+        }
+        state.limiter_window = Some((now, stats));
+        let window = stats.since(&earlier);
+        let ratio = window.rejection_ratio();
+        if ratio <= threshold {
+            return None;
+        }
+        warn!(
+            "RATE LIMITED: {} of {} submission attempts refused in the last {:?}",
+            window.rejected,
+            window.acquired + window.rejected,
+            LIMITER_WINDOW
+        );
+        Some(ratio)
+    }
+
+    async fn sweep_pending(&self, state: &mut SchedulerState) {
+        let now_secs = self.now_secs();
+        let base_fee = self.model.current_fee();
+        let mut expired = Vec::new();
+        let mut idx = 0;
+        // pending is re-sorted before submission, so swap_remove keeps this O(pending)
+        while idx < state.pending.len() {
+            match self.expiry_reason(&state.pending[idx], now_secs, base_fee) {
+                Some(reason) => expired.push((state.pending.swap_remove(idx), reason)),
+                None => idx += 1,
+            }
+        }
+        expired.sort_by_key(|(p, _)| p.req.id);
+        for (p, reason) in expired {
+            self.drop_tx(p.req, reason.to_string(), state).await;
+        }
+        if let Some(senders) = &self.sender_limiter {
+            senders.remove_idle();
+        }
+    }
+
+    /// Why `req` can't be paid for right now, if a balance provider is attached. Txs
+    /// already submitted by the same sender count against its balance until they
+    /// leave `submitted`, as does `committed`, spend planned earlier in this pass.
+    async fn balance_shortfall(
+        &self,
+        req: &TransactionRequest,
+        committed: Wei,
+        state: &mut SchedulerState,
+    ) -> Option<DeferReason> {
+        let provider = self.balances.as_ref()?;
+        let cached = state
+            .balances
+            .get(&req.from)
+            .filter(|c| c.fetched_at.elapsed() < self.config.balance_cache_ttl);
+        let balance = match cached {
+            Some(c) => c.balance,
+            None => match provider.balance_of(&req.from).await {
+                Ok(balance) => {
+                    state.balances.insert(
+                        req.from,
+                        CachedBalance {
+                            balance,
+                            fetched_at: Instant::now(),
+                        },
+                    );
+                    balance
+                }
+                Err(e) => {
+                    warn!("BALANCE LOOKUP FAILED: tx {} ({})", req.id, e);
+                    return match self.config.balance_error_policy {
+                        BalanceErrorPolicy::FailClosed => Some(DeferReason::BalanceUnavailable),
+                        BalanceErrorPolicy::FailOpen => None,
+                    };
+                }
+            },
+        };
+
+        let in_flight = state
+            .submitted
+            .values()
+            .filter(|tx| tx.req.from == req.from)
+            .map(|tx| self.max_total_cost(&tx.req, state.l1_base_fee))
+            .fold(Wei::ZERO, Wei::saturating_add);
+        if in_flight
+            .saturating_add(committed)
+            .saturating_add(self.max_total_cost(req, state.l1_base_fee))
+            > Wei(balance)
+        {
+            return Some(DeferReason::InsufficientBalance);
+        }
+        None
+    }
+
+    /// The L1 data fee `req` pays at `l1_base_fee` if its chain is an OP-stack one.
+    fn l1_data_fee(&self, req: &TransactionRequest, l1_base_fee: Option<u64>) -> Wei {
+        let chain_id = req.chain_id.unwrap_or(self.config.chain_id);
+        match (self.l1_data_fees.get(&chain_id), l1_base_fee) {
+            (Some(estimator), Some(l1_base_fee)) => estimator.data_fee(&req.data, l1_base_fee),
+            _ => Wei::ZERO,
+        }
+    }
+
+    /// `req.max_cost()` plus its L1 data fee.
+    fn max_total_cost(&self, req: &TransactionRequest, l1_base_fee: Option<u64>) -> Wei {
+        req.max_cost()
+            .saturating_add(self.l1_data_fee(req, l1_base_fee))
+    }
+
+    /// Takes tokens for the longest prefix of `costs` the limiter covers, all at once,
+    /// and returns its length. Stopping at the first tx that doesn't fit rather than
+    /// letting cheaper ones behind it through means an expensive tx still sees a full
+    /// bucket eventually. Losing a race for the tokens to another limiter user plans
+    /// nothing; the next pass tries again.
+    fn reserve_tokens(&self, costs: &[u64]) -> usize {
+        let available = self.limiter.available();
+        let mut total = 0;
+        let mut count = 0;
+        for &cost in costs {
+            if total + cost > available {
+                break;
+            }
+            total += cost;
+            count += 1;
+        }
+        if count == 0 {
+            // a cost above the bucket's capacity can only be paid from a full bucket
+            return match costs.first() {
+                Some(&cost) if self.limiter.check_and_consume_weight(cost) => 1,
+                _ => 0,
+            };
+        }
+        if self.limiter.check_and_consume_weight(total) {
+            count
+        } else {
+            0
+        }
+    }
+
+    /// Gives back the tokens taken for `tx` when its submission didn't go out.
+    fn refund_tokens(&self, tx: &TransactionRequest) {
+        let cost = self.config.limiter_weighting.cost(tx);
+        match &self.sender_limiter {
+            Some(senders) => senders.refund(&tx.from, cost),
+            None => self.limiter.refund(cost),
+        }
+    }
+
+    /// Raises each tx's caps to those of its current escalation step, so every check
+    /// after this sees the caps the caller allows right now.
+    fn apply_escalation(state: &mut SchedulerState) {
+        let pending = state
+            .pending
+            .iter_mut()
+            .map(|p| (&mut p.req, p.accepted_at));
+        let submitted = state
+            .submitted
+            .values_mut()
+            .map(|tx| (&mut tx.req, tx.accepted_at));
+        for (req, accepted_at) in pending.chain(submitted) {
+            let Some(&EscalationStep {
+                max_fee_per_gas,
+                max_priority_fee_per_gas,
+                ..
+            }) = req.escalation_step(accepted_at.elapsed().as_secs())
+            else {
+                continue;
+            };
+            if max_fee_per_gas > req.max_fee_per_gas
+                || max_priority_fee_per_gas > req.max_priority_fee_per_gas
+            {
+                req.max_fee_per_gas = req.max_fee_per_gas.max(max_fee_per_gas);
+                req.max_priority_fee_per_gas =
+                    req.max_priority_fee_per_gas.max(max_priority_fee_per_gas);
+                info!(
+                    "ESCALATION: tx {} caps raised to {} / {}",
+                    req.id, req.max_fee_per_gas, req.max_priority_fee_per_gas
+                );
+            }
+        }
+    }
+
+    /// Defers a pending tx, telling the sinks only when the kind of wait changed.
+    async fn defer_pending(&self, state: &mut SchedulerState, idx: usize, reason: DeferReason) {
+        let p = &mut state.pending[idx];
+        if p.defer(&reason) {
+            let decision = SchedulerDecision::Defer {
+                tx_id: p.req.id,
+                reason,
+                retry_hint: None,
+            };
+            self.emit(state, decision).await;
+        }
+    }
+
+    /// The nonce sequence a request draws from.
+    fn account_of(&self, req: &TransactionRequest) -> AccountKey {
+        (
+            req.chain_id.unwrap_or(self.config.chain_id),
+            Address::from(req.from),
+        )
+    }
+
+    /// Why `account` can't be given a nonce yet, if it can't. Fetches the starting
+    /// nonce when a provider is configured; otherwise asks for it, once until it is
+    /// supplied.
+    async fn uninitialized_nonce(
+        &self,
+        account: AccountKey,
+        state: &mut SchedulerState,
+    ) -> Option<DeferReason> {
+        let (chain_id, address) = account;
+        if self.nonce_manager.is_initialized(chain_id, address) {
+            return None;
+        }
+        let Some(provider) = &self.nonces else {
+            if state.nonce_init_requested.insert(account) {
+                warn!(
+                    "NONCE: sender {} on chain {} needs its starting nonce",
+                    address, chain_id
+                );
+                let decision = SchedulerDecision::NonceInitRequired {
+                    chain_id,
+                    address: address.into_array(),
+                };
+                self.emit(state, decision).await;
+            }
+            return Some(DeferReason::NonceUninitialized);
+        };
+        match self
+            .nonce_manager
+            .ensure_initialized(chain_id, address, provider.as_ref())
+            .await
+        {
+            Ok(nonce) => {
+                info!(
+                    "NONCE: sender {} on chain {} starts at {}",
+                    address, chain_id, nonce
+                );
+                None
+            }
+            Err(e) => {
+                warn!("NONCE: sender {} on chain {}: {}", address, chain_id, e);
+                Some(e.into())
+            }
+        }
+    }
+
+    /// Reports nonce gaps of accounts with txs in flight, once per distinct gap.
+    async fn check_nonce_gaps(&self, state: &mut SchedulerState) {
+        let mut accounts: Vec<AccountKey> = state
+            .submitted
+            .values()
+            .map(|tx| self.account_of(&tx.req))
+            .collect();
+        accounts.sort_unstable();
+        accounts.dedup();
+        state
+            .reported_gaps
+            .retain(|account, _| accounts.contains(account));
+        let now = Instant::now();
+        let mut stale = Vec::new();
+        let mut seen_at = HashMap::new();
+        for account in accounts {
+            let (chain_id, address) = account;
+            let missing = self.nonce_manager.detect_gaps(chain_id, address);
+            for &nonce in &missing {
+                let key = (account, nonce);
+                let first_seen = state.gaps_seen_at.get(&key).copied().unwrap_or(now);
+                seen_at.insert(key, first_seen);
+                if now.duration_since(first_seen) >= self.config.nonce_gap_fill_age {
+                    stale.push(key);
+                }
+            }
+            if missing.is_empty() {
+                state.reported_gaps.remove(&account);
+                continue;
+            }
+            if state.reported_gaps.get(&account) == Some(&missing) {
+                continue;
+            }
+            warn!(
+                "NONCE GAP: sender {} on chain {} is missing nonces {:?}",
+                address, chain_id, missing
+            );
+            state.reported_gaps.insert(account, missing.clone());
+            let decision = SchedulerDecision::NonceGapDetected {
+                chain_id,
+                address: address.into_array(),
+                missing,
+            };
+            self.emit(state, decision).await;
+        }
+        state.gaps_seen_at = seen_at;
+
+        if self.config.auto_fill_nonce_gaps {
+            stale.sort_unstable();
+            for (account, nonce) in stale {
+                self.fill_nonce_gap(state, account, nonce).await;
+            }
+        }
+    }
+
+    /// Takes a missing nonce with a zero-value self-transfer at a competitive price
+    /// and tracks it as a submitted tx.
+    async fn fill_nonce_gap(&self, state: &mut SchedulerState, account: AccountKey, nonce: u64) {
+        let (chain_id, address) = account;
+        let Some(reservation) =
+            self.nonce_manager
+                .reserve_gap(chain_id, address, nonce, NonceOrigin::GapFill)
+        else {
+            return;
+        };
+        let tip = if state.spike_mode {
+            self.config.spike_max_priority_fee
+        } else {
+            self.config.max_priority_fee
+        };
+        let gas_price = self.model.current_fee() + tip;
+        let fee_mode = state
+            .submitted
+            .values()
+            .find(|tx| self.account_of(&tx.req) == account)
+            .map_or(FeeMode::Eip1559, |tx| tx.req.fee_mode);
+        let tx_id = GAP_FILLER_ID_BASE + state.gap_fillers;
+        state.gap_fillers += 1;
+        let req = TransactionRequest {
+            id: tx_id,
+            from: address.into_array(),
+            to: Some(address.into_array()),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: gas_price,
+            max_priority_fee_per_gas: tip,
+            deadline: None,
+            urgency: Urgency::High,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: Some(chain_id),
+            blob: None,
+            fee_mode,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        };
+        warn!(
+            "GAP FILL: tx {} takes nonce {} of sender {} on chain {} at {}",
+            tx_id, nonce, address, chain_id, gas_price
+        );
+        state.submitted.insert(
+            tx_id,
+            SubmittedTx {
+                req,
+                accepted_at: Instant::now(),
+                nonce,
+                last_gas_price: gas_price,
+                last_action_at: Instant::now(),
+                cooldown: self.config.reprice_cooldown,
+                reprices: 0,
+                tx_hash: None,
+                blob_gas_price: None,
+                accepted_price: Wei::ZERO,
+                reservation: Some(reservation),
+            },
+        );
+        let decision = SchedulerDecision::FillNonceGap {
+            tx_id,
+            chain_id,
+            address: address.into_array(),
+            nonce,
+            gas_price,
+            fee_mode,
+        };
+        if !self.emit(state, decision).await {
+            // frees the nonce; the gap is filled on a later block
+            warn!("GAP FILL UNDELIVERED: tx {}", tx_id);
+            state.submitted.remove(&tx_id);
+        }
+    }
+
+    /// Drops submitted txs whose nonce reservation lapsed before the executor
+    /// reported a broadcast; their nonces are already back in the pool.
+    async fn reclaim_expired_nonces(&self, state: &mut SchedulerState) {
+        for ((chain_id, address), nonce) in self.nonce_manager.release_expired_reservations() {
+            let tx_id = state
+                .submitted
+                .values()
+                .find(|tx| {
+                    tx.reservation.as_ref().is_some_and(|r| {
+                        (r.chain_id(), r.address(), r.nonce()) == (chain_id, address, nonce)
+                    })
+                })
+                .map(|tx| tx.req.id);
+            if let Some(tx_id) = tx_id {
+                let tx = state.submitted.remove(&tx_id).unwrap();
+                let reason = NonceError::ReservationExpired.to_string();
+                self.drop_tx(tx.req, reason, state).await;
+            }
+        }
+    }
+
+    /// Keeps each sender's most urgent txs that fit in its in-flight nonce window and
+    /// defers the rest, without allocating: nonces are only reserved once the batch
+    /// is final, so a batch that doesn't fit never holds part of the window.
+    async fn fit_nonce_windows(
+        &self,
+        state: &mut SchedulerState,
+        batch: Vec<(usize, bool)>,
+    ) -> Vec<(usize, bool)> {
+        let mut by_account: HashMap<AccountKey, Vec<usize>> = HashMap::new();
+        for &(idx, _) in &batch {
+            let account = self.account_of(&state.pending[idx].req);
+            by_account.entry(account).or_default().push(idx);
+        }
+
+        let mut rejected = Vec::new();
+        for ((chain_id, address), mut indices) in by_account {
+            indices.sort_by_key(|&idx| (Reverse(state.pending[idx].req.urgency), idx));
+            let mut fits = indices.len();
+            while fits > 0 {
+                match self
+                    .nonce_manager
+                    .can_allocate(chain_id, address, fits as u64)
+                {
+                    Ok(()) => break,
+                    Err(e) => rejected.push((indices[fits - 1], e)),
+                }
+                fits -= 1;
+            }
+        }
+        rejected.sort_by_key(|&(idx, _)| idx);
+        for (idx, e) in &rejected {
+            self.defer_pending(state, *idx, e.clone().into()).await;
+        }
+        batch
+            .into_iter()
+            .filter(|(idx, _)| !rejected.iter().any(|(r, _)| r == idx))
+            .collect()
+    }
+
+    /// Nonces for the txs about to be submitted, by pending index. A sender with
+    /// several txs in the pass gets one contiguous range, handed out in request
+    /// priority order (urgency, then arrival); a lone tx may reuse a released nonce.
+    fn reserve_nonces(
+        &self,
+        state: &SchedulerState,
+        batch: &[(usize, bool)],
+    ) -> HashMap<usize, Result<Reservation, NonceError>> {
+        let mut by_account: HashMap<AccountKey, Vec<usize>> = HashMap::new();
+        for &(idx, _) in batch {
+            let account = self.account_of(&state.pending[idx].req);
+            by_account.entry(account).or_default().push(idx);
+        }
+
+        let mut reservations = HashMap::new();
+        for ((chain_id, address), mut indices) in by_account {
+            indices.sort_by_key(|&idx| (Reverse(state.pending[idx].req.urgency), idx));
+            if let [idx] = indices[..] {
+                let reservation =
+                    self.nonce_manager
+                        .reserve_nonce(chain_id, address, NonceOrigin::Submit);
+                reservations.insert(idx, reservation);
+                continue;
+            }
+            let count = indices.len() as u64;
+            match self
+                .nonce_manager
+                .reserve_range(chain_id, address, count, NonceOrigin::Batch)
+            {
+                Ok(range) => {
+                    reservations.extend(indices.into_iter().zip(range.into_iter().map(Ok)))
+                }
+                // fill what is left of the window, most urgent first
+                Err(NonceError::WindowFull) => {
+                    reservations.extend(indices.into_iter().map(|idx| {
+                        (
+                            idx,
+                            self.nonce_manager
+                                .reserve_nonce(chain_id, address, NonceOrigin::Batch),
+                        )
+                    }))
+                }
+                Err(e) => reservations.extend(indices.into_iter().map(|idx| (idx, Err(e.clone())))),
+            }
+        }
+        reservations
+    }
+
+    async fn re_evaluate_pending(&self, state: &mut SchedulerState) {
+        self.reclaim_expired_nonces(state).await;
+        Self::apply_escalation(state);
+        let current_fee = self.model.current_fee();
+        let volatility = self.model.get_volatility();
+        let trend = self.model.get_trend();
+        let is_spike = state.spike_mode;
+
+        // 1. Repricing with per-tx backoff. A tx within `target_escalation_window` of
+        // its deadline falls back to the base cooldown; `max_reprices` still applies.
+        let now_secs = self.now_secs();
+        let escalation = self.config.target_escalation_window.as_secs();
+        let mut repriced = Vec::new();
+        for tx in state.submitted.values_mut() {
+            if self
+                .config
+                .max_reprices
+                .is_some_and(|max| tx.reprices >= max)
+            {
+                continue;
+            }
+            let deadline_pressure = tx
+                .req
+                .deadline
+                .is_some_and(|deadline| deadline.saturating_sub(now_secs) <= escalation);
+            let cooldown = if deadline_pressure {
+                self.config.reprice_cooldown
+            } else {
+                tx.cooldown
+            };
+            if tx.last_action_at.elapsed() < cooldown {
+                continue;
+            }
+
+            let min_new_price = (tx.last_gas_price * 110) / 100;
+            let tip = self.effective_tip(&tx.req, is_spike);
+            let desired_price = match tx.req.fee_mode {
+                FeeMode::Eip1559 => Some(current_fee + tip).filter(|&p| p > min_new_price),
+                // a legacy tx priced under the market is bumped by at least the
+                // replacement minimum
+                FeeMode::Legacy => Some(current_fee + tip)
+                    .filter(|&p| p > tx.last_gas_price)
+                    .map(|p| p.max(min_new_price)),
+            };
+
+            if let Some(desired_price) = desired_price.filter(|&p| p <= tx.req.max_fee_per_gas) {
+                self.log_tip_clamp(&tx.req, tip);
+                warn!(
+                    "REPRICING: tx {} from {} to {} (volatility: {:.2})",
+                    tx.req.id, tx.last_gas_price, desired_price, volatility
+                );
+
+                tx.blob_gas_price = self.blob_gas_price(&tx.req).max(tx.blob_gas_price);
+                let decision = SchedulerDecision::Reprice {
+                    tx_id: tx.req.id,
+                    old_nonce: tx.nonce,
+                    new_gas_price: desired_price,
+                    max_priority_fee_per_gas: tip.min(desired_price),
+                    blob_gas_price: tx.blob_gas_price,
+                    fee_mode: tx.req.fee_mode,
+                    privacy: tx.req.privacy,
+                };
+                repriced.push((tx.req.id, decision));
+                tx.last_gas_price = desired_price;
+                tx.last_action_at = Instant::now();
+                tx.reprices += 1;
+                tx.cooldown = tx
+                    .cooldown
+                    .mul_f64(self.config.reprice_backoff_factor)
+                    .min(self.config.max_reprice_cooldown);
+            }
+        }
+        // submitted is a HashMap; keep the emitted order stable
+        repriced.sort_by_key(|(tx_id, _)| *tx_id);
+        for (_, d) in repriced {
+            self.emit(state, d).await;
+        }
+
+        // 2. Bookkeeping over all pending txs; never touches the limiter
+        self.sweep_pending(state).await;
+        state.pending.sort_by_key(|p| p.req.id);
+        let now_secs = self.now_secs();
+
+        let mut eligible = Vec::new();
+        let mut deferred = Vec::new();
+        for (idx, p) in state.pending.iter_mut().enumerate() {
+            // The sweep already dropped any tx whose forced price would exceed its cap
+            let (reason, retry_hint) = if let Some(reason) = self.blob_fee_wait(&p.req) {
+                (reason, None)
+            } else if p.wait_budget_exhausted() {
+                info!(
+                    "WAIT BUDGET EXHAUSTED: tx {} after {} blocks",
+                    p.req.id, p.blocks_waited
+                );
+                eligible.push((idx, true));
+                continue;
+            } else if self.waits_for_target(&p.req, current_fee, now_secs) {
+                let reason = DeferReason::OutsideWindow {
+                    target_base_fee: self.config.target_base_fee,
+                };
+                (reason, Some(self.target_retry_hint(&p.req, now_secs)))
+            } else if is_spike || current_fee <= p.req.max_fee_per_gas {
+                eligible.push((idx, is_spike));
+                continue;
+            } else if trend < -self.config.trend_threshold {
+                info!(
+                    "FEE HIGH but trending down ({:.2}). Deferring tx {}",
+                    trend, p.req.id
+                );
+                // blocks until the fee is under the cap if the trend holds
+                let gap = (current_fee - p.req.max_fee_per_gas).0 as f64;
+                let estimated_blocks = (gap / -trend).ceil().min(u32::MAX as f64) as u32;
+                (
+                    DeferReason::TrendWait { estimated_blocks },
+                    Some(RetryHint::AfterBlocks(estimated_blocks)),
+                )
+            } else {
+                (
+                    DeferReason::FeeAboveMax,
+                    Some(RetryHint::WhenFeeBelow(p.req.max_fee_per_gas)),
+                )
+            };
+            if p.defer(&reason) {
+                deferred.push(SchedulerDecision::Defer {
+                    tx_id: p.req.id,
+                    reason,
+                    retry_hint,
+                });
+            }
+        }
+        for d in deferred {
+            self.emit(state, d).await;
+        }
+
+        // 3. Nonce and balance gates, before the limiter so txs that can't go out
+        // don't spend tokens
+        let mut affordable = Vec::new();
+        let mut committed: HashMap<[u8; 20], Wei> = HashMap::new();
+        for (idx, inclusion_first) in eligible {
+            let tx = state.pending[idx].req.clone();
+            let blocked = match self.uninitialized_nonce(self.account_of(&tx), state).await {
+                Some(reason) => Some(reason),
+                None => {
+                    let sender_committed = committed.get(&tx.from).copied().unwrap_or_default();
+                    self.balance_shortfall(&tx, sender_committed, state).await
+                }
+            };
+            match blocked {
+                None => {
+                    *committed.entry(tx.from).or_default() +=
+                        self.max_total_cost(&tx, state.l1_base_fee);
+                    affordable.push((idx, inclusion_first));
+                }
+                Some(reason) => self.defer_pending(state, idx, reason).await,
+            }
+        }
+        let mut affordable = self.fit_nonce_windows(state, affordable).await;
+
+        // 4. Submission of as many txs as the tokens reserved for this pass cover
+        let costs: Vec<u64> = affordable
+            .iter()
+            .map(|&(idx, _)| self.config.limiter_weighting.cost(&state.pending[idx].req))
+            .collect();
+        match &self.sender_limiter {
+            Some(senders) => {
+                // a sender out of tokens waits without holding up the others
+                let mut costs = costs.into_iter();
+                affordable.retain(|&(idx, _)| {
+                    let cost = costs.next().unwrap_or(0);
+                    senders.check_and_consume_weight(&state.pending[idx].req.from, cost)
+                });
+            }
+            None => {
+                let planned = self.reserve_tokens(&costs);
+                affordable.truncate(planned);
+            }
+        }
+        let mut reservations = self.reserve_nonces(state, &affordable);
+        let mut to_remove = Vec::new();
+        for (idx, inclusion_first) in affordable {
+            let tx = state.pending[idx].req.clone();
+            if inclusion_first {
+                info!("DEGRADATION MODE: Inclusion-first for tx {}", tx.id);
+            }
+            let tip = self.effective_tip(&tx, inclusion_first);
+            self.log_tip_clamp(&tx, tip);
+            // a legacy tx pays its gas price in full, so it stays within the cap
+            let gas_price = match tx.fee_mode {
+                FeeMode::Eip1559 => current_fee + tip,
+                FeeMode::Legacy => (current_fee + tip).min(tx.max_fee_per_gas),
+            };
+            // Savings compare like for like: the same tip on the acceptance-time base fee
+            let blob_gas_price = self.blob_gas_price(&tx);
+            let blob_cost = blob_gas_price
+                .zip(tx.blob)
+                .map_or(Wei::ZERO, |(price, blob)| price * blob.blob_gas());
+            let l1_data_fee = self.l1_data_fee(&tx, state.l1_base_fee);
+            let estimated_cost_wei = gas_price * tx.gas_limit + blob_cost + l1_data_fee;
+            let accepted_price = state.pending[idx].accepted_fee.saturating_add(tip);
+            // waiting doesn't move the L1 data fee, so it's on both sides
+            let estimated_savings_wei =
+                (accepted_price * tx.gas_limit + l1_data_fee).saturating_sub(estimated_cost_wei);
+            let reservation = match reservations.remove(&idx).unwrap() {
+                Ok(reservation) => reservation,
+                Err(e) => {
+                    // a full in-flight window, or a concurrent reset since the gate above
+                    self.refund_tokens(&tx);
+                    self.defer_pending(state, idx, e.into()).await;
+                    continue;
+                }
+            };
+            let nonce = reservation.nonce();
+            state.submitted.insert(
+                tx.id,
+                SubmittedTx {
+                    req: tx.clone(),
+                    accepted_at: state.pending[idx].accepted_at,
+                    nonce,
+                    last_gas_price: gas_price,
+                    last_action_at: Instant::now(),
+                    cooldown: self.config.reprice_cooldown,
+                    reprices: 0,
+                    tx_hash: None,
+                    blob_gas_price,
+                    accepted_price,
+                    reservation: Some(reservation),
+                },
+            );
+            let decision = SchedulerDecision::Submit {
+                tx_id: tx.id,
+                nonce,
+                gas_price,
+                max_priority_fee_per_gas: tip.min(gas_price),
+                create: tx.is_create(),
+                estimated_cost_wei,
+                estimated_savings_wei,
+                blob_gas_price,
+                fee_mode: tx.fee_mode,
+                privacy: tx.privacy,
+            };
+            if !self.emit(state, decision).await {
+                // nobody will broadcast it; dropping the reservation frees the nonce
+                warn!("SUBMIT UNDELIVERED: tx {} stays pending", tx.id);
+                state.submitted.remove(&tx.id);
+                self.refund_tokens(&tx);
+                continue;
+            }
+            to_remove.push(idx);
+        }
+
+        for &idx in to_remove.iter().rev() {
+            state.pending.remove(idx);
+        }
+    }
+}
+
+/// Scheduler state built directly, for the benchmarks under `benches/`.
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+pub struct BenchState(SchedulerState);
+
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+impl BenchState {
+    pub fn pending_count(&self) -> usize {
+        self.0.pending.len()
+    }
+
+    pub fn submitted_count(&self) -> usize {
+        self.0.submitted.len()
+    }
+}
+
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+impl Scheduler {
+    /// State holding `pending` as just accepted at the current base fee, and
+    /// `submitted` as broadcast now at their `gas_price` without reservations, so
+    /// a benchmark skips the decisions that would put them there.
+    pub fn bench_state(
+        &self,
+        pending: Vec<TransactionRequest>,
+        submitted: Vec<RestoredTx>,
+    ) -> BenchState {
+        let now = Instant::now();
+        let current_fee = self.model.current_fee();
+        let pending = pending
+            .into_iter()
+            .map(|req| PendingTx::new(req, current_fee))
+            .collect();
+        let submitted = submitted
+            .into_iter()
+            .map(|tx| {
+                let submitted = SubmittedTx {
+                    req: tx.req,
+                    accepted_at: now,
+                    nonce: tx.nonce,
+                    last_gas_price: tx.gas_price,
+                    last_action_at: now,
+                    cooldown: self.config.reprice_cooldown,
+                    reprices: 0,
+                    tx_hash: tx.tx_hash,
+                    blob_gas_price: None,
+                    accepted_price: Wei::ZERO,
+                    reservation: None,
+                };
+                (submitted.req.id, submitted)
+            })
+            .collect();
+        BenchState(SchedulerState {
+            pending,
+            submitted,
+            ..SchedulerState::default()
+        })
+    }
+
+    /// One `re_evaluate_pending` pass, as after a gas event.
+    pub async fn bench_re_evaluate_pending(&self, state: &mut BenchState) {
+        self.re_evaluate_pending(&mut state.0).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::balance::{BalanceError, StaticBalances};
+    use crate::events::{BlobParams, SignedAuthorization};
+    use crate::l2::L1FeeParams;
+    use crate::limiter::{RateLimiter, ScriptedLimiter};
+    use crate::nonce::{
+        AuditAction, NonceManager, NonceUpdate, ProviderError, ReconcileReport, ReservationKey,
+        ResyncOutcome, StaticNonces,
+    };
+    use crate::sink::{ChannelSink, RequiredSink};
+    use crate::units::WEI_PER_GWEI;
+
+    fn scheduler(config: SchedulerConfig) -> (Scheduler, mpsc::Receiver<SchedulerDecision>) {
+        scheduler_with_limiter(config, RateLimiter::new(100, 100))
+    }
+
+    fn scheduler_with_limiter(
+        config: SchedulerConfig,
+        limiter: impl Limiter + 'static,
+    ) -> (Scheduler, mpsc::Receiver<SchedulerDecision>) {
+        let (decision_tx, decision_rx) = mpsc::channel(100);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Scheduler::new(
+            config,
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(limiter),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        (scheduler, decision_rx)
+    }
+
+    fn request(id: u64, max_fee_per_gas: u128, deadline: Option<u64>) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(max_fee_per_gas),
+            max_priority_fee_per_gas: Wei(2),
+            deadline,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn base_fee(base_fee: u64) -> GasEvent {
+        GasEvent::BaseFeeUpdate {
+            base_fee,
+            timestamp: 0,
+        }
+    }
+
+    fn drain_records(rx: &mut mpsc::Receiver<DecisionRecord>) -> Vec<DecisionRecord> {
+        let mut records = Vec::new();
+        while let Ok(r) = rx.try_recv() {
+            records.push(r);
+        }
+        records
+    }
+
+    fn drain(rx: &mut mpsc::Receiver<SchedulerDecision>) -> Vec<SchedulerDecision> {
+        let mut decisions = Vec::new();
+        while let Ok(d) = rx.try_recv() {
+            decisions.push(d);
+        }
+        decisions
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resubmit_after_low_cap_drop() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let deadline = scheduler.now_secs() + 10;
+        scheduler
+            .handle_tx_request(request(1, 40, Some(deadline)), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::FeeAboveMax,
+                retry_hint: Some(RetryHint::WhenFeeBelow(Wei(40))),
+            }]
+        );
+
+        tokio::time::advance(Duration::from_secs(11)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "deadline expired with fee cap 40 wei below base fee 50 wei".to_string(),
+            }]
+        );
+        assert!(state.pending.is_empty());
+
+        let cmd = SchedulerCommand::Resubmit {
+            tx_id: 1,
+            new_max_fee_per_gas: Some(Wei(100)),
+            new_deadline: Some(scheduler.now_secs() + 60),
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(1_092_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+        assert!(state.dropped.is_empty());
+        assert_eq!(state.submitted[&1].req.max_fee_per_gas, Wei(100));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_resubmit_unknown_id_is_rejected() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        let cmd = SchedulerCommand::Resubmit {
+            tx_id: 7,
+            new_max_fee_per_gas: Some(Wei(100)),
+            new_deadline: None,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Rejected { tx_id: 7, .. }]
+        ));
+        assert!(state.pending.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_drops_only_pending_txs() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 40, None), &mut state)
+            .await;
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        drain(&mut rx);
+        assert!(state.submitted.contains_key(&2));
+
+        let cancel = |tx_id| SchedulerCommand::Cancel { tx_id };
+        scheduler.handle_command(cancel(1), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "cancelled".to_string(),
+            }]
+        );
+        assert!(state.pending.is_empty());
+        assert_eq!(state.dropped.len(), 1);
+
+        // the submitted tx keeps its nonce, and unknown ids are ignored
+        scheduler.handle_command(cancel(2), &mut state).await;
+        scheduler.handle_command(cancel(7), &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+        assert!(state.submitted.contains_key(&2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_archive_eviction() {
+        let config = SchedulerConfig {
+            dropped_retention: Duration::from_secs(30),
+            dropped_archive_capacity: 2,
+            ..Default::default()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let deadline = scheduler.now_secs() + 1;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 40, Some(deadline)), &mut state)
+                .await;
+        }
+        tokio::time::advance(Duration::from_secs(2)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let drops = drain(&mut rx)
+            .into_iter()
+            .filter(|d| matches!(d, SchedulerDecision::Drop { .. }))
+            .count();
+        assert_eq!(drops, 3);
+
+        // capacity keeps only the two most recent drops
+        let ids: Vec<u64> = state.dropped.iter().map(|d| d.req.id).collect();
+        assert_eq!(ids, vec![2, 3]);
+
+        // retention expiry forgets the rest
+        tokio::time::advance(Duration::from_secs(31)).await;
+        let cmd = SchedulerCommand::Resubmit {
+            tx_id: 3,
+            new_max_fee_per_gas: Some(Wei(100)),
+            new_deadline: None,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Rejected { tx_id: 3, .. }]
+        ));
+        assert!(state.dropped.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_exhausted_limiter_still_drops_expired() {
+        let (scheduler, mut rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::without_refill(0));
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let deadline = scheduler.now_secs() + 5;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        scheduler
+            .handle_tx_request(request(2, 100, Some(deadline)), &mut state)
+            .await;
+        assert!(drain(&mut rx).is_empty());
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 2,
+                reason: "deadline expired".to_string(),
+            }]
+        );
+        let pending: Vec<u64> = state.pending.iter().map(|p| p.req.id).collect();
+        assert_eq!(pending, vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_defer_emitted_without_tokens_and_only_once() {
+        let (scheduler, mut rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::without_refill(0));
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        scheduler
+            .handle_tx_request(request(2, 40, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 2,
+                reason: DeferReason::FeeAboveMax,
+                retry_hint: Some(RetryHint::WhenFeeBelow(Wei(40))),
+            }]
+        );
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_falling_fee_defers_with_block_estimate() {
+        let gwei = |n: u64| n * WEI_PER_GWEI as u64;
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        for fee in [100, 90, 80, 70, 60] {
+            scheduler
+                .handle_gas_event(base_fee(gwei(fee)), &mut state)
+                .await;
+        }
+        drain(&mut rx);
+        // 20 gwei above the cap, falling 8 gwei per block
+        scheduler
+            .handle_tx_request(request(1, gwei(40).into(), None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::TrendWait {
+                    estimated_blocks: 3,
+                },
+                retry_hint: Some(RetryHint::AfterBlocks(3)),
+            }]
+        );
+
+        // 10 to go at 50/6 per block: a revised estimate, but the same wait
+        scheduler
+            .handle_gas_event(base_fee(gwei(50)), &mut state)
+            .await;
+        assert!(drain(&mut rx).is_empty());
+        assert_eq!(
+            state.pending[0].last_defer,
+            Some(DeferReason::TrendWait {
+                estimated_blocks: 2,
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fall_of_a_few_wei_is_no_trend() {
+        let gwei = |n: u64| n * WEI_PER_GWEI as u64;
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        for fee in [100, 99, 98, 97, 96] {
+            scheduler
+                .handle_gas_event(base_fee(gwei(60) + fee), &mut state)
+                .await;
+        }
+        drain(&mut rx);
+        scheduler
+            .handle_tx_request(request(1, gwei(40).into(), None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::FeeAboveMax,
+                retry_hint: Some(RetryHint::WhenFeeBelow(Gwei(40).into())),
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_submissions_resume_after_refill() {
+        let (scheduler, mut rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::new(1, 1));
+        let mut state = SchedulerState::default();
+        let submitted = |decisions: Vec<SchedulerDecision>| -> Vec<u64> {
+            decisions
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::Submit { tx_id, .. } => Some(tx_id),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=2 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        assert_eq!(submitted(drain(&mut rx)), vec![1]);
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert_eq!(submitted(drain(&mut rx)), vec![2]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_per_sender_rate_limit() {
+        let config = SchedulerConfig {
+            per_sender_rate: Some(RateLimiterConfig {
+                sustained_per_sec: 1,
+                burst: 2,
+            }),
+            ..SchedulerConfig::default()
+        };
+        let (scheduler, mut rx) = scheduler_with_limiter(config, RateLimiter::without_refill(3));
+        let mut state = SchedulerState::default();
+        let other = [0xCC; 20];
+        let cmd = SchedulerCommand::InitNonce {
+            chain_id: None,
+            address: other,
+            nonce: 0,
+            force: false,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        for id in 1..=5 {
+            let mut req = request(id, 100, None);
+            if id > 3 {
+                req.from = other;
+            }
+            scheduler.handle_tx_request(req, &mut state).await;
+        }
+        let submitted: Vec<u64> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, .. } => Some(tx_id),
+                _ => None,
+            })
+            .collect();
+        // 0xAA spends its own two tokens; 0xCC still gets in until the shared three run out
+        assert_eq!(submitted, vec![1, 2, 4]);
+        assert_eq!(state.pending.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_submission_rate() {
+        let limiter = Arc::new(RateLimiter::new(1, 1));
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let (decision_tx, mut rx) = mpsc::channel(100);
+        let scheduler = Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            limiter.clone(),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        drain(&mut rx);
+
+        let cmd = SchedulerCommand::SetSubmissionRate {
+            tokens_per_sec: 4,
+            burst: Some(2),
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert_eq!(limiter.current_rate(), 4);
+        assert_eq!(limiter.capacity(), 2);
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let submitted = drain(&mut rx)
+            .into_iter()
+            .filter(|d| matches!(d, SchedulerDecision::Submit { .. }))
+            .count();
+        assert_eq!(submitted, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scripted_limiter_denial_keeps_tx_pending() {
+        let (scheduler, mut rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), ScriptedLimiter::deny_call(3));
+        let mut state = SchedulerState::default();
+        let submitted = |rx: &mut mpsc::Receiver<SchedulerDecision>| -> Vec<u64> {
+            drain(rx)
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::Submit { tx_id, .. } => Some(tx_id),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        assert_eq!(submitted(&mut rx), vec![1, 2]);
+        assert_eq!(state.pending.len(), 1);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert_eq!(submitted(&mut rx), vec![3]);
+        let stats = scheduler.limiter_stats();
+        assert_eq!((stats.acquired, stats.rejected), (3, 1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_rejections_checked_per_window() {
+        let (scheduler, _rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::without_refill(1));
+        let mut state = SchedulerState::default();
+        assert_eq!(scheduler.check_limiter_rejections(&mut state), None);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        let stats = scheduler.limiter_stats();
+        assert_eq!((stats.acquired, stats.rejected), (1, 2));
+        tokio::time::advance(Duration::from_secs(30)).await;
+        assert_eq!(scheduler.check_limiter_rejections(&mut state), None);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        let ratio = scheduler.check_limiter_rejections(&mut state).unwrap();
+        assert!((ratio - 2.0 / 3.0).abs() < 1e-9);
+        // a quiet window after that stays quiet
+        tokio::time::advance(LIMITER_WINDOW).await;
+        assert_eq!(scheduler.check_limiter_rejections(&mut state), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_deployment_request() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let mut deploy = request(1, 100, None);
+        deploy.to = None;
+        deploy.data = vec![0x60, 0x80, 0x60, 0x40];
+        deploy.gas_limit = 60_000;
+        scheduler.handle_tx_request(deploy, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: true,
+                estimated_cost_wei: Wei(3_120_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+
+        let mut empty = request(2, 100, None);
+        empty.to = None;
+        scheduler.handle_tx_request(empty, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Rejected { tx_id: 2, .. }]
+        ));
+        assert!(state.pending.is_empty());
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test(start_paused = true)]
+    async fn test_hand_written_json_request() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let req: TransactionRequest = serde_json::from_str(
+            r#"{"id":1,"from":"0xaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa",
+                "to":"0xbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb","data":"0x","value":"0x0",
+                "gas_limit":21000,"max_fee_per_gas":100,"max_priority_fee_per_gas":2,
+                "deadline":null,"urgency":"Standard"}"#,
+        )
+        .unwrap();
+        assert_eq!(req, request(1, 100, None));
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 1,
+                create: false,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_invalid_request_has_no_side_effects() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let stale = request(1, 100, Some(scheduler.now_secs()));
+        scheduler.handle_tx_request(stale, &mut state).await;
+        let decisions = drain(&mut rx);
+        assert_eq!(decisions.len(), 1);
+        assert!(matches!(
+            decisions[0],
+            SchedulerDecision::Rejected {
+                tx_id: 1,
+                error: Some(ValidationError::DeadlinePassed { .. }),
+                ..
+            }
+        ));
+        assert!(state.pending.is_empty());
+        assert!(state.submitted.is_empty());
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(scheduler.nonce_manager.peek_nonce(1, address), 0);
+        assert_eq!(scheduler.limiter_stats().acquired, 0);
+        assert_eq!(scheduler.limiter_stats().rejected, 0);
+
+        // the next valid request gets the first nonce
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 2,
+                nonce: 0,
+                ..
+            }]
+        ));
+    }
+
+    struct DownProvider;
+
+    #[async_trait::async_trait]
+    impl BalanceProvider for DownProvider {
+        async fn balance_of(&self, _address: &[u8; 20]) -> Result<u128, BalanceError> {
+            Err(BalanceError::Unavailable("node offline".to_string()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_balance_refreshes_after_deposit() {
+        let balances = Arc::new(StaticBalances::new());
+        // 100 gwei * 21_000 gas needs 2_100_000
+        balances.set([0xAA; 20], 1_000_000);
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let scheduler = scheduler.with_balance_provider(balances.clone());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::InsufficientBalance,
+                retry_hint: None,
+            }]
+        );
+
+        // the stale cached balance still applies until the TTL passes
+        balances.deposit([0xAA; 20], 5_000_000);
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+
+        tokio::time::advance(SchedulerConfig::default().balance_cache_ttl).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit { tx_id: 1, .. }]
+        ));
+
+        // the in-flight tx counts against the sender's 6_000_000
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit { tx_id: 2, .. }]
+        ));
+        scheduler
+            .handle_tx_request(request(3, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Defer { tx_id: 3, .. }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_op_stack_txs_budget_for_their_l1_data_fee() {
+        let balances = Arc::new(StaticBalances::new());
+        // 100 wei * 21_000 gas of execution, and 1276 L1 gas for a plain transfer
+        balances.set([0xAA; 20], 3_000_000);
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let params = L1FeeParams {
+            overhead: 188,
+            scalar: 1_000_000,
+            decimals: 6,
+        };
+        let scheduler = scheduler
+            .with_balance_provider(balances)
+            .with_l1_data_fee(1, L1DataFeeEstimator::new(params));
+        let mut state = SchedulerState::default();
+        let l1_base_fee = |base_fee| GasEvent::L1BaseFeeUpdate { base_fee };
+
+        // 2_100_000 + 1_276_000 is more than the sender has
+        scheduler
+            .handle_gas_event(l1_base_fee(1_000), &mut state)
+            .await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::InsufficientBalance,
+                retry_hint: None,
+            }]
+        );
+
+        // at half the L1 base fee it fits, and the data fee is in the estimate
+        scheduler
+            .handle_gas_event(l1_base_fee(500), &mut state)
+            .await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 1,
+                estimated_cost_wei: Wei(1_730_000),
+                estimated_savings_wei: Wei::ZERO,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_pass_plans_tokens_and_balance_together() {
+        let balances = Arc::new(StaticBalances::new());
+        balances.set([0xAA; 20], 5_000_000);
+        let (scheduler, mut rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::without_refill(10));
+        let scheduler = scheduler.with_balance_provider(balances);
+        let mut state = SchedulerState::default();
+
+        for id in 1..=3 {
+            state
+                .pending
+                .push(PendingTx::new(request(id, 100, None), Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let decisions = drain(&mut rx);
+        assert!(matches!(
+            decisions.as_slice(),
+            [
+                SchedulerDecision::Defer { tx_id: 3, .. },
+                SchedulerDecision::Submit { tx_id: 1, .. },
+                SchedulerDecision::Submit { tx_id: 2, .. },
+            ]
+        ));
+        // only the two submissions were paid for
+        assert_eq!(scheduler.limiter.available(), 8);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_balance_error_policy() {
+        for (policy, submits) in [
+            (BalanceErrorPolicy::FailClosed, false),
+            (BalanceErrorPolicy::FailOpen, true),
+        ] {
+            let config = SchedulerConfig {
+                balance_error_policy: policy,
+                ..Default::default()
+            };
+            let (scheduler, mut rx) = scheduler(config);
+            let scheduler = scheduler.with_balance_provider(Arc::new(DownProvider));
+            let mut state = SchedulerState::default();
+
+            scheduler.handle_gas_event(base_fee(50), &mut state).await;
+            scheduler
+                .handle_tx_request(request(1, 100, None), &mut state)
+                .await;
+            let decisions = drain(&mut rx);
+            if submits {
+                assert!(matches!(
+                    decisions.as_slice(),
+                    [SchedulerDecision::Submit { tx_id: 1, .. }]
+                ));
+            } else {
+                assert_eq!(
+                    decisions,
+                    vec![SchedulerDecision::Defer {
+                        tx_id: 1,
+                        reason: DeferReason::BalanceUnavailable,
+                        retry_hint: None,
+                    }]
+                );
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_uninitialized_sender_is_deferred() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let sender = [0xCC; 20];
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        for id in 1..=2 {
+            let mut req = request(id, 100, None);
+            req.from = sender;
+            scheduler.handle_tx_request(req, &mut state).await;
+        }
+        let defer = |tx_id| SchedulerDecision::Defer {
+            tx_id,
+            reason: DeferReason::NonceUninitialized,
+            retry_hint: None,
+        };
+        assert_eq!(
+            drain(&mut rx),
+            vec![
+                SchedulerDecision::NonceInitRequired {
+                    chain_id: 1,
+                    address: sender,
+                },
+                defer(1),
+                defer(2),
+            ]
+        );
+
+        let cmd = SchedulerCommand::InitNonce {
+            chain_id: None,
+            address: sender,
+            nonce: 9,
+            force: false,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        let nonces: Vec<_> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, nonce, .. } => Some((tx_id, nonce)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nonces, vec![(1, 9), (2, 10)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonces_follow_request_chain() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let mut optimism = request(1, 100, None);
+        optimism.chain_id = Some(10);
+        scheduler.handle_tx_request(optimism, &mut state).await;
+        assert_eq!(
+            drain(&mut rx)[0],
+            SchedulerDecision::NonceInitRequired {
+                chain_id: 10,
+                address: [0xAA; 20],
+            }
+        );
+
+        let cmd = SchedulerCommand::InitNonce {
+            chain_id: Some(10),
+            address: [0xAA; 20],
+            nonce: 5,
+            force: false,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        let nonces: Vec<_> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, nonce, .. } => Some((tx_id, nonce)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nonces, vec![(1, 5), (2, 0)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_nonces_follow_priority() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        for (id, urgency) in [
+            (1, Urgency::Low),
+            (2, Urgency::High),
+            (3, Urgency::Standard),
+        ] {
+            let mut req = request(id, 100, None);
+            req.urgency = urgency;
+            state.pending.push(PendingTx::new(req, Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let nonces: Vec<_> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, nonce, .. } => Some((tx_id, nonce)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(nonces, vec![(1, 2), (2, 0), (3, 1)]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_full_nonce_window_defers() {
+        let (decision_tx, mut rx) = mpsc::channel(100);
+        let nonce_manager = NonceManager::new().with_max_inflight(2);
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        let mut state = SchedulerState::default();
+        for (id, urgency) in [
+            (1, Urgency::Low),
+            (2, Urgency::High),
+            (3, Urgency::Standard),
+        ] {
+            let mut req = request(id, 100, None);
+            req.urgency = urgency;
+            state.pending.push(PendingTx::new(req, Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let decisions = drain(&mut rx);
+        let submitted: Vec<_> = decisions
+            .iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, nonce, .. } => Some((*tx_id, *nonce)),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(submitted, vec![(2, 0), (3, 1)]);
+        assert!(decisions.contains(&SchedulerDecision::Defer {
+            tx_id: 1,
+            reason: DeferReason::NonceWindowFull,
+            retry_hint: None,
+        }));
+
+        // confirming nonce 0 frees a slot
+        let tx_hash = [0x22; 32];
+        let cmd = SchedulerCommand::Broadcast { tx_id: 2, tx_hash };
+        scheduler.handle_command(cmd, &mut state).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash,
+            block_number: 1,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler.handle_gas_event(confirmed, &mut state).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(drain(&mut rx).iter().any(|d| matches!(
+            d,
+            SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 2,
+                ..
+            }
+        )));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_batch_is_fitted_to_window_before_reserving() {
+        let (decision_tx, mut rx) = mpsc::channel(100);
+        let nonce_manager = Arc::new(NonceManager::new().with_max_inflight(2));
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            nonce_manager.clone(),
+            Arc::new(RateLimiter::without_refill(3)),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        let mut state = SchedulerState::default();
+        for id in 1..=3 {
+            state
+                .pending
+                .push(PendingTx::new(request(id, 100, None), Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let decisions = drain(&mut rx);
+        let submitted = decisions
+            .iter()
+            .filter(|d| matches!(d, SchedulerDecision::Submit { .. }))
+            .count();
+        assert_eq!(submitted, 2);
+        assert!(decisions.contains(&SchedulerDecision::Defer {
+            tx_id: 3,
+            reason: DeferReason::NonceWindowFull,
+            retry_hint: None,
+        }));
+        // the deferred tx took neither a token nor a nonce
+        assert_eq!(scheduler.limiter.available(), 1);
+        let counters = nonce_manager.counters();
+        assert_eq!((counters.allocations, counters.releases), (2, 0));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_allocations_are_audited_by_origin() {
+        let (decision_tx, mut rx) = mpsc::channel(100);
+        let nonce_manager = Arc::new(NonceManager::new().with_audit_capacity(8));
+        let (lone, batched) = (Address::repeat_byte(0xAA), Address::repeat_byte(0xBB));
+        nonce_manager.update_nonce(1, lone, 0);
+        nonce_manager.update_nonce(1, batched, 7);
+        let scheduler = Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            nonce_manager.clone(),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        let mut state = SchedulerState::default();
+        state
+            .pending
+            .push(PendingTx::new(request(1, 100, None), Wei(50)));
+        for id in 2..=3 {
+            let mut req = request(id, 100, None);
+            req.from = batched.into_array();
+            state.pending.push(PendingTx::new(req, Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        drain(&mut rx);
+
+        let allocations = |address| -> Vec<(u64, NonceOrigin)> {
+            nonce_manager
+                .audit_log(1, address)
+                .into_iter()
+                .filter(|entry| entry.action == AuditAction::Allocated)
+                .map(|entry| (entry.nonce, entry.origin))
+                .collect()
+        };
+        assert_eq!(allocations(lone), vec![(0, NonceOrigin::Submit)]);
+        assert_eq!(
+            allocations(batched),
+            vec![(7, NonceOrigin::Batch), (8, NonceOrigin::Batch)]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_broadcast_failure_releases_nonce() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=2 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        drain(&mut rx);
+
+        let cmd = SchedulerCommand::BroadcastFailed {
+            tx_id: 1,
+            reason: "signer locked".to_string(),
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "broadcast failed: signer locked".to_string(),
+            }]
+        );
+        assert!(!state.submitted.contains_key(&1));
+
+        // the next tx fills the hole tx 1 left
+        scheduler
+            .handle_tx_request(request(3, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 3,
+                nonce: 0,
+                ..
+            }]
+        ));
+    }
+
+    /// Stand-in for a coordination service several schedulers share: one ledger
+    /// behind a lock. Deliberately minimal; released nonces below the top leak.
+    #[derive(Default)]
+    struct Ledger {
+        next: HashMap<AccountKey, u64>,
+        confirmed_next: HashMap<AccountKey, u64>,
+        held: HashSet<ReservationKey>,
+    }
+
+    struct SharedNonces(Arc<parking_lot::Mutex<Ledger>>);
+
+    #[async_trait::async_trait]
+    impl NonceAllocator for SharedNonces {
+        fn is_initialized(&self, chain_id: u64, address: Address) -> bool {
+            self.0.lock().next.contains_key(&(chain_id, address))
+        }
+
+        async fn ensure_initialized(
+            &self,
+            chain_id: u64,
+            address: Address,
+            provider: &dyn NonceProvider,
+        ) -> Result<u64, NonceError> {
+            if !self.is_initialized(chain_id, address) {
+                let count = provider
+                    .transaction_count(chain_id, address)
+                    .await
+                    .map_err(NonceError::Provider)?;
+                self.update_nonce(chain_id, address, count);
+            }
+            Ok(self.peek_nonce(chain_id, address))
+        }
+
+        fn peek_nonce(&self, chain_id: u64, address: Address) -> u64 {
+            let ledger = self.0.lock();
+            ledger.next.get(&(chain_id, address)).copied().unwrap_or(0)
+        }
+
+        fn highest_confirmed(&self, chain_id: u64, address: Address) -> Option<u64> {
+            let ledger = self.0.lock();
+            let confirmed_next = ledger.confirmed_next.get(&(chain_id, address))?;
+            confirmed_next.checked_sub(1)
+        }
+
+        fn lowest_unconfirmed(&self, chain_id: u64, address: Address) -> Option<u64> {
+            let ledger = self.0.lock();
+            let held = ledger.held.iter();
+            held.filter(|(account, _)| *account == (chain_id, address))
+                .map(|&(_, nonce)| nonce)
+                .min()
+        }
+
+        fn detect_gaps(&self, _chain_id: u64, _address: Address) -> Vec<u64> {
+            Vec::new()
+        }
+
+        fn reserve(
+            &self,
+            chain_id: u64,
+            address: Address,
+            origin: NonceOrigin,
+        ) -> Result<u64, NonceError> {
+            self.reserve_contiguous(chain_id, address, 1, origin)
+                .map(|range| range.start)
+        }
+
+        fn reserve_contiguous(
+            &self,
+            chain_id: u64,
+            address: Address,
+            count: u64,
+            _origin: NonceOrigin,
+        ) -> Result<std::ops::Range<u64>, NonceError> {
+            let mut ledger = self.0.lock();
+            let account = (chain_id, address);
+            let next = ledger
+                .next
+                .get_mut(&account)
+                .ok_or(NonceError::Uninitialized)?;
+            let range = *next..*next + count;
+            *next = range.end;
+            ledger
+                .held
+                .extend(range.clone().map(|nonce| (account, nonce)));
+            Ok(range)
+        }
+
+        fn can_allocate(
+            &self,
+            chain_id: u64,
+            address: Address,
+            _count: u64,
+        ) -> Result<(), NonceError> {
+            if self.0.lock().next.contains_key(&(chain_id, address)) {
+                Ok(())
+            } else {
+                Err(NonceError::Uninitialized)
+            }
+        }
+
+        fn reserve_exact(
+            &self,
+            _chain_id: u64,
+            _address: Address,
+            _nonce: u64,
+            _origin: NonceOrigin,
+        ) -> bool {
+            false
+        }
+
+        fn commit_reservation(&self, chain_id: u64, address: Address, nonce: u64) -> bool {
+            self.0.lock().held.remove(&((chain_id, address), nonce))
+        }
+
+        fn cancel_reservation(&self, chain_id: u64, address: Address, nonce: u64) {
+            let mut ledger = self.0.lock();
+            let account = (chain_id, address);
+            if ledger.held.remove(&(account, nonce))
+                && let Some(next) = ledger.next.get_mut(&account)
+                && *next == nonce + 1
+            {
+                *next = nonce;
+            }
+        }
+
+        fn release_unmined(&self, chain_id: u64, address: Address, nonce: u64) -> bool {
+            let mut ledger = self.0.lock();
+            match ledger.next.get_mut(&(chain_id, address)) {
+                Some(next) if *next == nonce + 1 => {
+                    *next = nonce;
+                    true
+                }
+                _ => false,
+            }
+        }
+
+        fn release_expired_reservations(&self) -> Vec<ReservationKey> {
+            Vec::new()
+        }
+
+        fn record_confirmed(&self, chain_id: u64, address: Address, nonce: u64) {
+            let mut ledger = self.0.lock();
+            let confirmed_next = ledger
+                .confirmed_next
+                .entry((chain_id, address))
+                .or_default();
+            *confirmed_next = (*confirmed_next).max(nonce + 1);
+        }
+
+        fn rollback_confirmed(&self, chain_id: u64, address: Address, from_nonce: u64) -> Vec<u64> {
+            let mut ledger = self.0.lock();
+            let Some(confirmed_next) = ledger.confirmed_next.get_mut(&(chain_id, address)) else {
+                return Vec::new();
+            };
+            let reverted = (from_nonce..*confirmed_next).collect();
+            *confirmed_next = (*confirmed_next).min(from_nonce);
+            reverted
+        }
+
+        fn update_nonce(&self, chain_id: u64, address: Address, network_next: u64) -> NonceUpdate {
+            let mut ledger = self.0.lock();
+            let account = (chain_id, address);
+            let previous = ledger.confirmed_next.insert(account, network_next);
+            let next = ledger.next.entry(account).or_default();
+            *next = (*next).max(network_next);
+            match previous {
+                None => NonceUpdate::Initialized,
+                Some(previous) if previous < network_next => NonceUpdate::Raised,
+                Some(previous) => {
+                    ledger.confirmed_next.insert(account, previous);
+                    if previous == network_next {
+                        NonceUpdate::Unchanged
+                    } else {
+                        NonceUpdate::Stale
+                    }
+                }
+            }
+        }
+
+        fn force_set_nonce(&self, chain_id: u64, address: Address, next: u64) -> Option<u64> {
+            let mut ledger = self.0.lock();
+            ledger.confirmed_next.insert((chain_id, address), next);
+            ledger.next.insert((chain_id, address), next)
+        }
+
+        fn resync(&self, chain_id: u64, address: Address, network_next: u64) -> ResyncOutcome {
+            let previous_next = self.0.lock().next.get(&(chain_id, address)).copied();
+            self.update_nonce(chain_id, address, network_next);
+            ResyncOutcome {
+                previous_next,
+                next: self.peek_nonce(chain_id, address),
+                consumed: Vec::new(),
+            }
+        }
+
+        fn reconcile(&self, chain_id: u64, address: Address, observed: &[u64]) -> ReconcileReport {
+            let mut ledger = self.0.lock();
+            let next = ledger.next.entry((chain_id, address)).or_default();
+            let previous_next = *next;
+            *next = observed.iter().map(|&n| n + 1).fold(*next, u64::max);
+            ReconcileReport {
+                previous_next: Some(previous_next),
+                next: *next,
+                ..Default::default()
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_schedulers_sharing_an_allocator_get_disjoint_nonces() {
+        let ledger = Arc::new(parking_lot::Mutex::new(Ledger::default()));
+        let mut instances = Vec::new();
+        for _ in 0..2 {
+            let (decision_tx, rx) = mpsc::channel(100);
+            let scheduler = Scheduler::new(
+                SchedulerConfig::default(),
+                Arc::new(GasModel::new(10)),
+                Arc::new(SharedNonces(ledger.clone())),
+                Arc::new(RateLimiter::new(100, 100)),
+                vec![Box::new(ChannelSink::new(decision_tx))],
+            );
+            instances.push((scheduler, SchedulerState::default(), rx));
+        }
+        instances[0]
+            .0
+            .nonce_manager
+            .update_nonce(1, Address::repeat_byte(0xAA), 0);
+
+        for id in 1..=3 {
+            for (scheduler, state, _) in &mut instances {
+                scheduler.handle_gas_event(base_fee(50), state).await;
+                scheduler
+                    .handle_tx_request(request(id, 100, None), state)
+                    .await;
+            }
+        }
+
+        let mut nonces = Vec::new();
+        for (_, _, rx) in &mut instances {
+            let mine: Vec<u64> = drain(rx)
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::Submit { nonce, .. } => Some(nonce),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(mine.len(), 3);
+            nonces.extend(mine);
+        }
+        nonces.sort_unstable();
+        assert_eq!(nonces, (0..6).collect::<Vec<_>>());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_restored_submissions_are_reconciled() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let sender = Address::repeat_byte(0xAA);
+        scheduler.nonce_manager.update_nonce(1, sender, 2);
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        drain(&mut rx);
+
+        let restored = |id, nonce, tx_hash| RestoredTx {
+            req: request(id, 100, None),
+            nonce,
+            gas_price: Wei(60),
+            tx_hash,
+        };
+        let txs = vec![
+            restored(10, 1, None),
+            restored(11, 4, None),
+            restored(12, 4, Some([0x12; 32])),
+            restored(13, 3, None),
+        ];
+        let cmd = SchedulerCommand::RestoreSubmitted { txs };
+        scheduler.handle_command(cmd, &mut state).await;
+
+        let conflict = |tx_id, nonce, reason: &str| SchedulerDecision::NonceConflict {
+            tx_id,
+            chain_id: 1,
+            address: [0xAA; 20],
+            nonce,
+            reason: reason.to_string(),
+        };
+        assert_eq!(
+            drain(&mut rx),
+            vec![
+                conflict(10, 1, "nonce already confirmed"),
+                conflict(11, 4, "nonce claimed by another restored tx"),
+            ]
+        );
+        let mut tracked: Vec<_> = state.submitted.keys().copied().collect();
+        tracked.sort_unstable();
+        assert_eq!(tracked, vec![12, 13]);
+
+        // new submissions start above every restored nonce
+        scheduler
+            .handle_tx_request(request(20, 100, None), &mut state)
+            .await;
+        assert!(drain(&mut rx).iter().any(|d| matches!(
+            d,
+            SchedulerDecision::Submit {
+                tx_id: 20,
+                nonce: 5,
+                ..
+            }
+        )));
+
+        // the restored broadcast hash still matches its confirmation
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash: [0x12; 32],
+            block_number: 1,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler.handle_gas_event(confirmed, &mut state).await;
+        assert!(!state.submitted.contains_key(&12));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonce_too_low_retires_superseded_submissions() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        drain(&mut rx);
+
+        // another wallet on the same key sent two txs
+        let cmd = SchedulerCommand::NonceTooLow {
+            chain_id: None,
+            address: [0xAA; 20],
+            network_nonce: 2,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![
+                SchedulerDecision::NonceConsumed { tx_id: 1, nonce: 0 },
+                SchedulerDecision::NonceConsumed { tx_id: 2, nonce: 1 },
+            ]
+        );
+        assert_eq!(state.submitted.keys().copied().collect::<Vec<_>>(), vec![3]);
+
+        // already in step: nothing to retire and the counter stays put
+        let cmd = SchedulerCommand::NonceTooLow {
+            chain_id: Some(1),
+            address: [0xAA; 20],
+            network_nonce: 2,
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+        assert_eq!(
+            scheduler
+                .nonce_manager
+                .peek_nonce(1, Address::repeat_byte(0xAA)),
+            3
+        );
+    }
+
+    /// Submits and broadcasts `request(1, 100, None)` under `tx_hash`.
+    async fn broadcast_one(
+        config: SchedulerConfig,
+        tx_hash: [u8; 32],
+    ) -> (Scheduler, mpsc::Receiver<SchedulerDecision>, SchedulerState) {
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        let cmd = SchedulerCommand::Broadcast { tx_id: 1, tx_hash };
+        scheduler.handle_command(cmd, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                ..
+            }]
+        ));
+        (scheduler, rx, state)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mempool_drop_requeues_with_same_nonce() {
+        let tx_hash = [0x11; 32];
+        let (scheduler, mut rx, mut state) =
+            broadcast_one(SchedulerConfig::default(), tx_hash).await;
+
+        let dropped = GasEvent::TxDropped {
+            tx_hash,
+            reason: "evicted".to_string(),
+        };
+        scheduler.handle_gas_event(dropped, &mut state).await;
+        // back through pending, and the released nonce is the next one out
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                ..
+            }]
+        ));
+        assert!(state.pending.is_empty());
+        assert_eq!(state.submitted[&1].tx_hash, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_mempool_drop_resubmits_at_nonce() {
+        let config = SchedulerConfig {
+            dropped_tx_policy: DroppedTxPolicy::Resubmit,
+            ..SchedulerConfig::default()
+        };
+        let tx_hash = [0x11; 32];
+        let (scheduler, mut rx, mut state) = broadcast_one(config, tx_hash).await;
+        scheduler.model.update(Wei(40));
+
+        let dropped = GasEvent::TxDropped {
+            tx_hash,
+            reason: "evicted".to_string(),
+        };
+        scheduler.handle_gas_event(dropped, &mut state).await;
+        // never below the price it was first sent at
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Reprice {
+                tx_id: 1,
+                old_nonce: 0,
+                new_gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+        assert_eq!(state.submitted[&1].tx_hash, None);
+        assert_eq!(state.submitted[&1].reprices, 0);
+
+        // an unknown hash changes nothing
+        let dropped = GasEvent::TxDropped {
+            tx_hash,
+            reason: "evicted".to_string(),
+        };
+        scheduler.handle_gas_event(dropped, &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reverted_tx_is_dropped() {
+        let tx_hash = [0x11; 32];
+        let (scheduler, mut rx, mut state) =
+            broadcast_one(SchedulerConfig::default(), tx_hash).await;
+
+        let failed = GasEvent::TxFailed {
+            tx_hash,
+            block_number: 3,
+        };
+        scheduler.handle_gas_event(failed, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "reverted".to_string(),
+            }]
+        );
+        assert!(state.submitted.is_empty());
+        assert_eq!(state.dropped.len(), 1);
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            scheduler.nonce_manager.highest_confirmed(1, address),
+            Some(0)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replaced_tx_consumes_nonce() {
+        let tx_hash = [0x11; 32];
+        let (scheduler, mut rx, mut state) =
+            broadcast_one(SchedulerConfig::default(), tx_hash).await;
+
+        let replaced = GasEvent::TxReplaced {
+            old_tx_hash: tx_hash,
+            new_tx_hash: [0x22; 32],
+            nonce: 0,
+        };
+        scheduler.handle_gas_event(replaced, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::NonceConsumed { tx_id: 1, nonce: 0 }]
+        );
+        assert!(state.submitted.is_empty());
+        let address = Address::repeat_byte(0xAA);
+        assert_eq!(
+            scheduler.nonce_manager.highest_confirmed(1, address),
+            Some(0)
+        );
+
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 2,
+                nonce: 1,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_undelivered_submit_keeps_nonce_and_tx() {
+        let (scheduler, rx) = scheduler(SchedulerConfig::default());
+        drop(rx);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+
+        assert_eq!(state.pending.len(), 1);
+        assert!(state.submitted.is_empty());
+        assert_eq!(
+            scheduler
+                .nonce_manager
+                .peek_nonce(1, Address::repeat_byte(0xAA)),
+            0
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_submit_waits_for_a_full_required_sink() {
+        let (executor_tx, mut executor_rx) = mpsc::channel(1);
+        let (audit_tx, mut audit_rx) = mpsc::channel(100);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let config = SchedulerConfig {
+            sink_timeout: Duration::from_millis(100),
+            ..SchedulerConfig::default()
+        };
+        let scheduler = Scheduler::new(
+            config,
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![
+                Box::new(RequiredSink::new(ChannelSink::new(executor_tx.clone()))),
+                Box::new(ChannelSink::new(audit_tx)),
+            ],
+        );
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        drain(&mut executor_rx);
+        let backlog = SchedulerDecision::Drop {
+            tx_id: 0,
+            reason: "backlog".to_string(),
+        };
+        executor_tx.send(backlog).await.unwrap();
+
+        // the executor only gets to its backlog long after the sink timeout
+        let executor = async {
+            tokio::time::sleep(Duration::from_secs(5)).await;
+            executor_rx.recv().await
+        };
+        tokio::join!(
+            scheduler.handle_tx_request(request(1, 100, None), &mut state),
+            executor
+        );
+        assert!(matches!(
+            executor_rx.try_recv(),
+            Ok(SchedulerDecision::Submit { tx_id: 1, .. })
+        ));
+        assert!(state.pending.is_empty());
+        assert!(state.submitted.contains_key(&1));
+        assert!(
+            drain(&mut audit_rx)
+                .iter()
+                .any(|d| matches!(d, SchedulerDecision::Submit { tx_id: 1, .. }))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_undelivered_submit_refunds_tokens() {
+        let (scheduler, rx) =
+            scheduler_with_limiter(SchedulerConfig::default(), RateLimiter::without_refill(1));
+        drop(rx);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=2 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+
+        assert_eq!(state.pending.len(), 2);
+        assert_eq!(scheduler.limiter.available(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_unbroadcast_reservation_expires() {
+        let nonce_manager = NonceManager::new().with_reservation_ttl(Duration::from_secs(30));
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let (decision_tx, mut rx) = mpsc::channel(100);
+        let scheduler = Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(ChannelSink::new(decision_tx))],
+        );
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for id in 1..=2 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        let cmd = SchedulerCommand::Broadcast {
+            tx_id: 1,
+            tx_hash: [0x11; 32],
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        drain(&mut rx);
+
+        tokio::time::advance(Duration::from_secs(30)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 2,
+                reason: "nonce reservation expired".to_string(),
+            }]
+        );
+        assert!(state.submitted.contains_key(&1));
+
+        scheduler
+            .handle_tx_request(request(3, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 3,
+                nonce: 1,
+                ..
+            }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonce_gap_is_reported_once() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let gaps = |decisions: Vec<SchedulerDecision>| -> Vec<Vec<u64>> {
+            decisions
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::NonceGapDetected { missing, .. } => Some(missing),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        scheduler.handle_gas_event(block(1, 50), &mut state).await;
+        for id in 1..=3 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        let cmd = SchedulerCommand::BroadcastFailed {
+            tx_id: 2,
+            reason: "rejected by node".to_string(),
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        drain(&mut rx);
+
+        scheduler.handle_gas_event(block(2, 50), &mut state).await;
+        assert_eq!(gaps(drain(&mut rx)), vec![vec![1]]);
+        scheduler.handle_gas_event(block(3, 50), &mut state).await;
+        assert!(gaps(drain(&mut rx)).is_empty());
+
+        // a new tx takes nonce 1 and closes the gap
+        scheduler
+            .handle_tx_request(request(4, 100, None), &mut state)
+            .await;
+        assert_eq!(state.submitted[&4].nonce, 1);
+        scheduler.handle_gas_event(block(4, 50), &mut state).await;
+        assert!(gaps(drain(&mut rx)).is_empty());
+        assert!(state.reported_gaps.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_nonce_gaps_are_filled() {
+        let config = SchedulerConfig {
+            auto_fill_nonce_gaps: true,
+            nonce_gap_fill_age: Duration::from_secs(30),
+            ..Default::default()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+        let fillers = |decisions: Vec<SchedulerDecision>| -> Vec<(u64, u64)> {
+            decisions
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::FillNonceGap { tx_id, nonce, .. } => Some((tx_id, nonce)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        scheduler.handle_gas_event(block(1, 50), &mut state).await;
+        for id in 1..=4 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+        }
+        for tx_id in [2, 3] {
+            let cmd = SchedulerCommand::BroadcastFailed {
+                tx_id,
+                reason: "rejected by node".to_string(),
+            };
+            scheduler.handle_command(cmd, &mut state).await;
+        }
+        drain(&mut rx);
+
+        // too young: the original txs might still turn up
+        scheduler.handle_gas_event(block(2, 50), &mut state).await;
+        assert!(fillers(drain(&mut rx)).is_empty());
+        tokio::time::advance(Duration::from_secs(20)).await;
+        scheduler.handle_gas_event(block(3, 50), &mut state).await;
+        assert!(fillers(drain(&mut rx)).is_empty());
+
+        tokio::time::advance(Duration::from_secs(15)).await;
+        scheduler.handle_gas_event(block(4, 50), &mut state).await;
+        let filled = fillers(drain(&mut rx));
+        assert_eq!(
+            filled,
+            vec![(GAP_FILLER_ID_BASE, 1), (GAP_FILLER_ID_BASE + 1, 2)]
+        );
+        let filler = &state.submitted[&GAP_FILLER_ID_BASE];
+        assert_eq!(filler.req.to, Some([0xAA; 20]));
+        assert_eq!(filler.req.value, [0; 32]);
+
+        scheduler.handle_gas_event(block(5, 50), &mut state).await;
+        assert!(fillers(drain(&mut rx)).is_empty());
+        assert!(
+            scheduler
+                .nonce_manager
+                .detect_gaps(1, Address::repeat_byte(0xAA))
+                .is_empty()
+        );
+    }
+
+    struct NonceSourceDown;
+
+    #[async_trait::async_trait]
+    impl NonceProvider for NonceSourceDown {
+        async fn transaction_count(
+            &self,
+            _chain_id: u64,
+            _address: Address,
+        ) -> Result<u64, ProviderError> {
+            Err(ProviderError::Unavailable("node offline".to_string()))
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_nonce_provider_initializes_on_first_use() {
+        let sender = [0xCC; 20];
+        let nonces = Arc::new(StaticNonces::new());
+        nonces.set(1, sender, 4);
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let scheduler = scheduler.with_nonce_provider(nonces);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let mut req = request(1, 100, None);
+        req.from = sender;
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 4,
+                ..
+            }]
+        ));
+
+        // a failed fetch defers the tx and leaves the sender for the next pass
+        let (failing, mut rx) = self::scheduler(SchedulerConfig::default());
+        let failing = failing.with_nonce_provider(Arc::new(NonceSourceDown));
+        let mut state = SchedulerState::default();
+        failing.handle_gas_event(base_fee(50), &mut state).await;
+        let mut req = request(2, 100, None);
+        req.from = sender;
+        failing.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 2,
+                reason: DeferReason::NonceUnavailable(
+                    "nonce source unavailable: node offline".to_string()
+                ),
+                retry_hint: None,
+            }]
+        );
+        assert!(!failing.nonce_manager.is_initialized(1, sender.into()));
+    }
+
+    /// Seconds since start at which tx 1 was repriced while the base fee climbs 20%
+    /// every second for `seconds`.
+    async fn reprice_times(
+        config: SchedulerConfig,
+        deadline_in: Option<u64>,
+        seconds: u64,
+    ) -> Vec<u64> {
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+        let started = Instant::now();
+
+        let mut fee = 100;
+        scheduler.handle_gas_event(base_fee(fee), &mut state).await;
+        let deadline = deadline_in.map(|secs| scheduler.now_secs() + secs);
+        scheduler
+            .handle_tx_request(request(1, u128::MAX / 2, deadline), &mut state)
+            .await;
+        drain(&mut rx);
+
+        let mut times = Vec::new();
+        for _ in 0..seconds {
+            tokio::time::advance(Duration::from_secs(1)).await;
+            fee = fee * 6 / 5;
+            scheduler.handle_gas_event(base_fee(fee), &mut state).await;
+            for d in drain(&mut rx) {
+                if let SchedulerDecision::Reprice { tx_id: 1, .. } = d {
+                    times.push(started.elapsed().as_secs());
+                }
+            }
+        }
+        times
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_reprice_backoff_is_geometric() {
+        let config = SchedulerConfig {
+            reprice_cooldown: Duration::from_secs(1),
+            reprice_backoff_factor: 2.0,
+            max_reprice_cooldown: Duration::from_secs(8),
+            // keep deadline pressure out of the way
+            target_escalation_window: Duration::ZERO,
+            ..Default::default()
+        };
+        assert_eq!(
+            reprice_times(config.clone(), None, 40).await,
+            vec![1, 3, 7, 15, 23, 31, 39]
+        );
+
+        let capped = SchedulerConfig {
+            max_reprices: Some(3),
+            ..config.clone()
+        };
+        assert_eq!(reprice_times(capped, None, 40).await, vec![1, 3, 7]);
+
+        // from t=10 the deadline is inside the escalation window, so the base cooldown
+        // applies again, but the cap still holds
+        let pressured = SchedulerConfig {
+            target_escalation_window: Duration::from_secs(60),
+            max_reprices: Some(5),
+            ..config
+        };
+        assert_eq!(
+            reprice_times(pressured, Some(70), 20).await,
+            vec![1, 3, 7, 10, 11]
+        );
+    }
+
+    fn block(number: u64, base_fee: u64) -> GasEvent {
+        GasEvent::NewBlock {
+            number,
+            base_fee,
+            gas_used: 15_000_000,
+            gas_limit: 30_000_000,
+            block_hash: [0; 32],
+            parent_hash: [0; 32],
+        }
+    }
+
+    fn hash(number: u64, branch: u8) -> [u8; 32] {
+        let mut hash = [branch; 32];
+        hash[24..].copy_from_slice(&number.to_be_bytes());
+        hash
+    }
+
+    /// Block `number` of `branch`, whose parent is on `parent_branch`.
+    fn chain_block(number: u64, branch: u8, parent_branch: u8) -> GasEvent {
+        GasEvent::NewBlock {
+            number,
+            base_fee: 50,
+            gas_used: 15_000_000,
+            gas_limit: 30_000_000,
+            block_hash: hash(number, branch),
+            parent_hash: hash(number - 1, parent_branch),
+        }
+    }
+
+    /// Scheduler with tx 1 confirmed in block 101 and tx 2 in block 100 of branch 1,
+    /// whose head is `head`.
+    async fn confirmed_chain(
+        head: u64,
+    ) -> (Scheduler, mpsc::Receiver<SchedulerDecision>, SchedulerState) {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler
+            .handle_gas_event(chain_block(100, 1, 1), &mut state)
+            .await;
+        for id in 1..=2 {
+            scheduler
+                .handle_tx_request(request(id, 100, None), &mut state)
+                .await;
+            let cmd = SchedulerCommand::Broadcast {
+                tx_id: id,
+                tx_hash: [id as u8; 32],
+            };
+            scheduler.handle_command(cmd, &mut state).await;
+        }
+        let confirm = |id: u64, block_number| GasEvent::TxConfirmed {
+            tx_hash: [id as u8; 32],
+            block_number,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler
+            .handle_gas_event(confirm(2, 100), &mut state)
+            .await;
+        for number in 101..=head {
+            scheduler
+                .handle_gas_event(chain_block(number, 1, 1), &mut state)
+                .await;
+        }
+        scheduler
+            .handle_gas_event(confirm(1, 101), &mut state)
+            .await;
+        drain(&mut rx);
+        (scheduler, rx, state)
+    }
+
+    fn reorgs(decisions: Vec<SchedulerDecision>) -> Vec<SchedulerDecision> {
+        decisions
+            .into_iter()
+            .filter(|d| matches!(d, SchedulerDecision::Reorg { .. }))
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_linear_chain_has_no_reorg() {
+        let (scheduler, mut rx, mut state) = confirmed_chain(101).await;
+        for number in 102..=105 {
+            scheduler
+                .handle_gas_event(chain_block(number, 1, 1), &mut state)
+                .await;
+        }
+        // a repeated block is not a reorg either
+        scheduler
+            .handle_gas_event(chain_block(105, 1, 1), &mut state)
+            .await;
+        assert!(reorgs(drain(&mut rx)).is_empty());
+        assert!(state.submitted.is_empty());
+        assert_eq!(state.confirmed.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_one_block_reorg() {
+        let (scheduler, mut rx, mut state) = confirmed_chain(101).await;
+        scheduler
+            .handle_gas_event(chain_block(101, 2, 1), &mut state)
+            .await;
+        assert_eq!(
+            reorgs(drain(&mut rx)),
+            vec![SchedulerDecision::Reorg {
+                fork_block: 101,
+                depth: 1,
+                unconfirmed: vec![1],
+            }]
+        );
+        assert!(state.submitted.contains_key(&1));
+        assert_eq!(state.confirmed.len(), 1);
+        // tx 1 holds nonce 0, which is pending again
+        let nonces = &scheduler.nonce_manager;
+        assert_eq!(
+            nonces.highest_confirmed(1, Address::repeat_byte(0xAA)),
+            None
+        );
+        assert_eq!(
+            nonces.lowest_unconfirmed(1, Address::repeat_byte(0xAA)),
+            Some(0)
+        );
+
+        // the new branch then extends normally
+        scheduler
+            .handle_gas_event(chain_block(102, 2, 2), &mut state)
+            .await;
+        assert!(reorgs(drain(&mut rx)).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_two_block_reorg() {
+        let (scheduler, mut rx, mut state) = confirmed_chain(102).await;
+        // new head at 102 whose parent is a 101 we never saw
+        scheduler
+            .handle_gas_event(chain_block(102, 2, 2), &mut state)
+            .await;
+        assert_eq!(
+            reorgs(drain(&mut rx)),
+            vec![SchedulerDecision::Reorg {
+                fork_block: 101,
+                depth: 2,
+                unconfirmed: vec![1],
+            }]
+        );
+        assert!(state.submitted.contains_key(&1));
+        assert!(!state.submitted.contains_key(&2));
+    }
+
+    fn dedupe_config() -> SchedulerConfig {
+        SchedulerConfig {
+            dedupe_window: Some(Duration::from_secs(60)),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_duplicate_while_pending_is_dropped() {
+        let (scheduler, mut rx) = scheduler(dedupe_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(80), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 50, None), &mut state)
+            .await;
+        // same transaction under a new id and a different fee cap
+        scheduler
+            .handle_tx_request(request(2, 60, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx)[1..],
+            [SchedulerDecision::Drop {
+                tx_id: 2,
+                reason: "duplicate of #1".to_string(),
+            }]
+        );
+        assert_eq!(state.pending.len(), 1);
+
+        // outside the window the original no longer shields itself
+        tokio::time::advance(Duration::from_secs(60)).await;
+        scheduler
+            .handle_tx_request(request(3, 60, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Defer { tx_id: 3, .. }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_duplicate_after_confirmation_is_allowed() {
+        let (scheduler, mut rx) = scheduler(dedupe_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        let broadcast = SchedulerCommand::Broadcast {
+            tx_id: 1,
+            tx_hash: [1; 32],
+        };
+        scheduler.handle_command(broadcast, &mut state).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash: [1; 32],
+            block_number: 10,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler.handle_gas_event(confirmed, &mut state).await;
+        drain(&mut rx);
+
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit { tx_id: 2, .. }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_confirmation_reports_realized_cost() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        // accepted at 60 + 2, submitted once the fee fell to 40
+        scheduler.handle_gas_event(base_fee(60), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 55, None), &mut state)
+            .await;
+        scheduler.handle_gas_event(base_fee(40), &mut state).await;
+        assert_eq!(
+            drain(&mut rx).last(),
+            Some(&SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(42),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(882_000),
+                estimated_savings_wei: Wei(420_000),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            })
+        );
+        let broadcast = SchedulerCommand::Broadcast {
+            tx_id: 1,
+            tx_hash: [1; 32],
+        };
+        scheduler.handle_command(broadcast, &mut state).await;
+
+        let confirmed = |tx_hash| GasEvent::TxConfirmed {
+            tx_hash,
+            block_number: 10,
+            effective_gas_price: 41,
+            gas_used: 21_000,
+        };
+        scheduler
+            .handle_gas_event(confirmed([9; 32]), &mut state)
+            .await;
+        assert!(drain(&mut rx).is_empty(), "unknown hashes are only logged");
+
+        scheduler
+            .handle_gas_event(confirmed([1; 32]), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Confirmed {
+                tx_id: 1,
+                block_number: 10,
+                effective_gas_price: Wei(41),
+                realized_cost_wei: Wei(861_000),
+                // 42 offered, 62 at acceptance
+                savings_vs_max_fee_wei: Wei(21_000),
+                savings_vs_acceptance_wei: Wei(441_000),
+            }]
+        );
+        assert!(state.submitted.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dedupe_compares_calldata_hashes() {
+        let (scheduler, mut rx) = scheduler(dedupe_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(80), &mut state).await;
+        let mut first = request(1, 50, None);
+        first.data = vec![0xA9, 0x05, 0x9C, 0xBB, 0x00, 0x01];
+        let mut second = request(2, 50, None);
+        second.data = vec![0xA9, 0x05, 0x9C, 0xBB, 0x00, 0x02];
+        let mut third = request(3, 50, None);
+        third.data = first.data.clone();
+        for mut req in [first, second, third] {
+            req.gas_limit = 30_000;
+            scheduler.handle_tx_request(req, &mut state).await;
+        }
+
+        let drops: Vec<_> = drain(&mut rx)
+            .into_iter()
+            .filter(|d| matches!(d, SchedulerDecision::Drop { .. }))
+            .collect();
+        assert_eq!(
+            drops,
+            vec![SchedulerDecision::Drop {
+                tx_id: 3,
+                reason: "duplicate of #1".to_string(),
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_submit_reports_cost_and_savings() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(80), &mut state).await;
+        let mut req = request(1, 60, None);
+        req.gas_limit = 100_000;
+        scheduler.handle_tx_request(req, &mut state).await;
+        drain(&mut rx);
+
+        scheduler.handle_gas_event(base_fee(45), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(47),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(4_700_000),
+                // (80 + 2 - 47) * 100_000
+                estimated_savings_wei: Wei(3_500_000),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_savings_floored_when_fees_rose() {
+        let (scheduler, mut rx) = scheduler(target_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(block(1, 80), &mut state).await;
+        let mut req = request(1, 200, None);
+        req.max_wait_blocks = Some(1);
+        scheduler.handle_tx_request(req, &mut state).await;
+        scheduler.handle_gas_event(block(2, 85), &mut state).await;
+        scheduler.handle_gas_event(block(3, 90), &mut state).await;
+        assert!(drain(&mut rx).contains(&SchedulerDecision::Submit {
+            tx_id: 1,
+            nonce: 0,
+            gas_price: Wei(92),
+            max_priority_fee_per_gas: Wei(2),
+            create: false,
+            estimated_cost_wei: Wei(1_932_000),
+            estimated_savings_wei: Wei(0),
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_escalation_ladder_caps_reprices() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let step = |after_secs, max_fee_per_gas| EscalationStep {
+            after_secs,
+            max_fee_per_gas,
+            max_priority_fee_per_gas: Wei(2),
+        };
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let mut req = request(1, 60, None);
+        req.escalation = Some(vec![
+            step(0, Wei(60)),
+            step(120, Wei(90)),
+            step(300, Wei(150)),
+        ]);
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit {
+                gas_price: Wei(52),
+                ..
+            }]
+        ));
+
+        // (seconds since acceptance, base fee, expected reprice)
+        let script = [
+            (60, 80, None),
+            (130, 80, Some(82)),
+            (200, 120, None),
+            (310, 120, Some(122)),
+            (400, 200, None),
+        ];
+        let started = Instant::now();
+        for (at, fee, expected) in script {
+            tokio::time::advance(Duration::from_secs(at) - started.elapsed()).await;
+            scheduler.handle_gas_event(base_fee(fee), &mut state).await;
+            let reprices: Vec<Wei> = drain(&mut rx)
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::Reprice { new_gas_price, .. } => Some(new_gas_price),
+                    _ => None,
+                })
+                .collect();
+            assert_eq!(
+                reprices,
+                expected.into_iter().map(Wei).collect::<Vec<_>>(),
+                "t={}",
+                at
+            );
+        }
+        assert_eq!(state.submitted[&1].req.max_fee_per_gas, Wei(150));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_escalation_must_be_monotonic() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        let mut req = request(1, 60, None);
+        req.escalation = Some(vec![
+            EscalationStep {
+                after_secs: 60,
+                max_fee_per_gas: Wei(90),
+                max_priority_fee_per_gas: Wei(2),
+            },
+            EscalationStep {
+                after_secs: 120,
+                max_fee_per_gas: Wei(80),
+                max_priority_fee_per_gas: Wei(2),
+            },
+        ]);
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Rejected {
+                tx_id: 1,
+                reason: "escalation steps must rise in both time and price".to_string(),
+                error: Some(ValidationError::EscalationNotMonotonic),
+            }]
+        );
+    }
+
+    /// Decisions and model samples after feeding `blocks` of a rising chain to a
+    /// scheduler holding one cheap tx.
+    async fn burst(blocks: &[u64]) -> (Vec<SchedulerDecision>, usize, u64) {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler
+            .handle_tx_request(request(1, 60, None), &mut state)
+            .await;
+        for &number in blocks {
+            let mut event = chain_block(number, 1, 1);
+            if let GasEvent::NewBlock { base_fee, .. } = &mut event {
+                *base_fee = 40 + number * 5;
+            }
+            scheduler.handle_gas_event(event, &mut state).await;
+        }
+        (
+            drain(&mut rx),
+            scheduler.model.sample_count(),
+            scheduler.stale_events(),
+        )
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_replayed_blocks_are_ignored() {
+        let (once, samples, stale) = burst(&[1, 2, 3, 4, 5]).await;
+        assert_eq!((samples, stale), (5, 0));
+
+        let (twice, replay_samples, replay_stale) = burst(&[1, 2, 3, 4, 5, 1, 2, 3, 4, 5]).await;
+        assert_eq!(twice, once);
+        assert_eq!((replay_samples, replay_stale), (5, 5));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stale_base_fee_updates_are_ignored() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        for timestamp in [10, 20, 20, 15, 30] {
+            let event = GasEvent::BaseFeeUpdate {
+                base_fee: 50,
+                timestamp,
+            };
+            scheduler.handle_gas_event(event, &mut state).await;
+        }
+        assert_eq!(scheduler.model.sample_count(), 3);
+        assert_eq!(scheduler.stale_events(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fee_history_matches_individual_updates() {
+        let fees = [40, 44, 39, 47, 52, 50];
+        let (bulk, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let history = GasEvent::FeeHistory {
+            oldest_block: 100,
+            base_fees: fees.to_vec(),
+            gas_used_ratios: vec![0.9, 0.1, 0.95, 0.9, 0.4],
+            rewards: vec![vec![1, 2, 6]; 5],
+        };
+        bulk.handle_gas_event(history, &mut state).await;
+        assert_eq!(state.head_block, Some(104));
+
+        let (single, _rx) = scheduler(SchedulerConfig::default());
+        let mut single_state = SchedulerState::default();
+        for fee in fees {
+            single
+                .handle_gas_event(base_fee(fee), &mut single_state)
+                .await;
+        }
+        assert_eq!(bulk.model.sample_count(), fees.len());
+        assert_eq!(bulk.model.current_fee(), single.model.current_fee());
+        assert_eq!(bulk.model.get_trend(), single.model.get_trend());
+        assert_eq!(bulk.model.get_volatility(), single.model.get_volatility());
+        assert_eq!(bulk.model.suggest_tip(Urgency::High), Some(Wei(6)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_new_blocks_are_announced_by_the_model() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let mut blocks = scheduler.model.subscribe_blocks();
+        scheduler.handle_gas_event(base_fee(40), &mut state).await;
+        assert!(!blocks.has_changed().unwrap());
+
+        scheduler.handle_gas_event(block(101, 40), &mut state).await;
+        assert_eq!(*blocks.borrow_and_update(), Some(101));
+        // blocks 100 and 101 are old news; only 102 is announced
+        let history = GasEvent::FeeHistory {
+            oldest_block: 100,
+            base_fees: vec![41, 42, 43, 44],
+            gas_used_ratios: vec![0.5; 3],
+            rewards: vec![],
+        };
+        scheduler.handle_gas_event(history, &mut state).await;
+        assert_eq!(*blocks.borrow_and_update(), Some(102));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_oracle_estimates_feed_tips_not_base_fees() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let estimate = GasEvent::OracleEstimate {
+            source: "etherscan".into(),
+            base_fee: 90,
+            priority_fee_fast: 7,
+            priority_fee_standard: 3,
+        };
+
+        scheduler.handle_gas_event(estimate, &mut state).await;
+        assert_eq!(scheduler.model.suggest_tip(Urgency::Standard), Some(Wei(3)));
+        assert_eq!(scheduler.model.suggest_tip(Urgency::High), Some(Wei(7)));
+        assert_eq!(scheduler.model.sample_count(), 0);
+        assert!(drain(&mut rx).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_fee_history_skips_seen_blocks_and_bad_shapes() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(block(101, 40), &mut state).await;
+
+        let history =
+            |base_fees: Vec<u64>, gas_used_ratios: Vec<f64>, rewards| GasEvent::FeeHistory {
+                oldest_block: 100,
+                base_fees,
+                gas_used_ratios,
+                rewards,
+            };
+        for malformed in [
+            history(vec![45], vec![], vec![]),
+            history(vec![45, 46], vec![0.5, 0.5], vec![]),
+            history(vec![45, 46, 47], vec![0.5, 0.5], vec![vec![1]]),
+            history(vec![45, 46, 47], vec![0.5, 0.5], vec![vec![1], vec![1, 2]]),
+        ] {
+            scheduler.handle_gas_event(malformed, &mut state).await;
+        }
+        assert_eq!(
+            scheduler.model.sample_count(),
+            1,
+            "nothing partially applied"
+        );
+        assert_eq!(scheduler.model.suggest_tip(Urgency::Low), None);
+
+        // blocks 100 and 101 were seen; 102 and the next block's fee are new
+        let overlap = history(vec![35, 40, 44, 48], vec![0.5; 3], vec![]);
+        scheduler.handle_gas_event(overlap, &mut state).await;
+        assert_eq!(scheduler.model.sample_count(), 3);
+        assert_eq!(scheduler.model.current_fee(), Wei(48));
+        assert_eq!(state.head_block, Some(102));
+        assert_eq!(scheduler.stale_events(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_budget_forces_inclusion_pricing() {
+        let (scheduler, mut rx) = scheduler(target_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(block(1, 80), &mut state).await;
+        let mut req = request(1, 200, None);
+        req.max_wait_blocks = Some(3);
+        req.max_priority_fee_per_gas = Wei(8);
+        scheduler.handle_tx_request(req, &mut state).await;
+
+        for number in 2..=4 {
+            scheduler
+                .handle_gas_event(block(number, 80), &mut state)
+                .await;
+        }
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Defer { tx_id: 1, .. }]
+        ));
+
+        // block N+1: inclusion-first tip ceiling instead of the normal one
+        scheduler.handle_gas_event(block(5, 80), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(88),
+                max_priority_fee_per_gas: Wei(8),
+                create: false,
+                estimated_cost_wei: Wei(1_848_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_wait_budget_drops_when_cap_too_low() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(block(1, 100), &mut state).await;
+        let mut req = request(1, 90, None);
+        req.max_wait_blocks = Some(2);
+        scheduler.handle_tx_request(req, &mut state).await;
+        for number in 2..=3 {
+            scheduler
+                .handle_gas_event(block(number, 100), &mut state)
+                .await;
+        }
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Defer { tx_id: 1, .. }]
+        ));
+
+        scheduler.handle_gas_event(block(4, 100), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason:
+                    "block wait budget exhausted with inclusion price 102 wei above fee cap 90 wei"
+                        .to_string(),
+            }]
+        );
+    }
+
+    /// The default tip ceilings are whole gwei, far above these tests' wei-sized tips.
+    fn wei_tip_ceilings() -> SchedulerConfig {
+        SchedulerConfig {
+            max_priority_fee: Wei(2),
+            spike_max_priority_fee: Wei(10),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tip_clamped_in_normal_mode() {
+        let (scheduler, mut rx) = scheduler(wei_tip_ceilings());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let mut req = request(1, 100, None);
+        req.max_priority_fee_per_gas = Wei(90);
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(1_092_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_tip_clamped_in_spike_mode() {
+        // thresholds in wei per block, to match the fees
+        let config = SchedulerConfig {
+            spike_threshold_high: 15.0,
+            spike_threshold_low: 10.0,
+            ..wei_tip_ceilings()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        for fee in [50, 100, 50, 100] {
+            scheduler.handle_gas_event(base_fee(fee), &mut state).await;
+        }
+        assert!(state.spike_mode);
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::ModeChanged { spike: true }]
+        );
+
+        let mut req = request(1, 1000, None);
+        req.max_priority_fee_per_gas = Wei(900);
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(110),
+                max_priority_fee_per_gas: Wei(10),
+                create: false,
+                estimated_cost_wei: Wei(2_310_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+
+        // a modest tip below both ceilings is left alone
+        let mut req = request(2, 1000, None);
+        req.max_priority_fee_per_gas = Wei(4);
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 2,
+                nonce: 1,
+                gas_price: Wei(104),
+                max_priority_fee_per_gas: Wei(4),
+                create: false,
+                estimated_cost_wei: Wei(2_184_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_legacy_requests_price_one_gas_price() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let legacy = |id, max_fee_per_gas| TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..request(id, max_fee_per_gas, None)
+        };
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for req in [request(1, 100, None), legacy(2, 100), legacy(3, 51)] {
+            scheduler.handle_tx_request(req, &mut state).await;
+        }
+        let submits: Vec<_> = drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit {
+                    tx_id,
+                    gas_price,
+                    fee_mode,
+                    ..
+                } => Some((tx_id, gas_price, fee_mode)),
+                _ => None,
+            })
+            .collect();
+        // a legacy price is paid in full, so it stops at the cap
+        assert_eq!(
+            submits,
+            vec![
+                (1, Wei(52), FeeMode::Eip1559),
+                (2, Wei(52), FeeMode::Legacy),
+                (3, Wei(51), FeeMode::Legacy),
+            ]
+        );
+
+        // 57 is no more than the 10% replacement minimum: only the legacy tx, now
+        // underpriced, is bumped
+        tokio::time::advance(SchedulerConfig::default().reprice_cooldown).await;
+        scheduler.handle_gas_event(base_fee(55), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Reprice {
+                tx_id: 2,
+                old_nonce: 1,
+                new_gas_price: Wei(57),
+                max_priority_fee_per_gas: Wei(2),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Legacy,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+
+        // a small rise still bumps it by the full replacement minimum
+        tokio::time::advance(Duration::from_secs(60)).await;
+        scheduler.handle_gas_event(base_fee(56), &mut state).await;
+        assert!(drain(&mut rx).contains(&SchedulerDecision::Reprice {
+            tx_id: 2,
+            old_nonce: 1,
+            new_gas_price: Wei(62),
+            max_priority_fee_per_gas: Wei(2),
+            blob_gas_price: None,
+            fee_mode: FeeMode::Legacy,
+            privacy: SubmissionPrivacy::Public,
+        }));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_privacy_carries_to_submit_and_reprice() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let private = TransactionRequest {
+            privacy: SubmissionPrivacy::PrivateRelay,
+            ..request(1, 100, None)
+        };
+        let privacy_of = |decisions: Vec<SchedulerDecision>| -> Vec<_> {
+            decisions
+                .into_iter()
+                .filter_map(|d| match d {
+                    SchedulerDecision::Submit { tx_id, privacy, .. }
+                    | SchedulerDecision::Reprice { tx_id, privacy, .. } => Some((tx_id, privacy)),
+                    _ => None,
+                })
+                .collect()
+        };
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for req in [private, request(2, 100, None)] {
+            scheduler.handle_tx_request(req, &mut state).await;
+        }
+        let expected = vec![
+            (1, SubmissionPrivacy::PrivateRelay),
+            (2, SubmissionPrivacy::Public),
+        ];
+        assert_eq!(privacy_of(drain(&mut rx)), expected);
+
+        tokio::time::advance(SchedulerConfig::default().reprice_cooldown).await;
+        scheduler.handle_gas_event(base_fee(70), &mut state).await;
+        assert_eq!(privacy_of(drain(&mut rx)), expected);
+    }
+
+    fn target_config() -> SchedulerConfig {
+        SchedulerConfig {
+            target_base_fee: Wei(30),
+            honor_target_base_fee: true,
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_waits_for_target_base_fee() {
+        let (scheduler, mut rx) = scheduler(target_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::OutsideWindow {
+                    target_base_fee: Wei(30),
+                },
+                retry_hint: Some(RetryHint::WhenFeeBelow(Wei(30))),
+            }]
+        );
+
+        scheduler.handle_gas_event(base_fee(40), &mut state).await;
+        assert!(drain(&mut rx).is_empty());
+
+        scheduler.handle_gas_event(base_fee(30), &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(32),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(672_000),
+                estimated_savings_wei: Wei(420_000),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_target_already_met_submits_immediately() {
+        let (scheduler, mut rx) = scheduler(target_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(25), &mut state).await;
+        scheduler
+            .handle_tx_request(request(1, 100, None), &mut state)
+            .await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(27),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(567_000),
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }]
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_target_yields_to_deadline_and_urgency() {
+        let (scheduler, mut rx) = scheduler(target_config());
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let deadline = scheduler.now_secs() + 120;
+        scheduler
+            .handle_tx_request(request(1, 100, Some(deadline)), &mut state)
+            .await;
+        let mut urgent = request(2, 100, None);
+        urgent.urgency = Urgency::High;
+        scheduler.handle_tx_request(urgent, &mut state).await;
+        let decisions = drain(&mut rx);
+        assert!(matches!(
+            decisions.as_slice(),
+            [
+                SchedulerDecision::Defer { tx_id: 1, .. },
+                SchedulerDecision::Submit { tx_id: 2, .. }
+            ]
+        ));
+        // the 60s escalation window opens a minute before the deadline
+        assert_eq!(
+            decisions[0],
+            SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::OutsideWindow {
+                    target_base_fee: Wei(30),
+                },
+                retry_hint: Some(RetryHint::AfterDuration(Duration::from_secs(60))),
+            }
+        );
+
+        // inside the escalation window the target no longer holds tx 1 back
+        tokio::time::advance(Duration::from_secs(61)).await;
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit { tx_id: 1, .. }]
+        ));
+    }
+
+    #[test]
+    fn test_spike_hysteresis_bounds_mode_changes() {
+        let gwei = |v: f64| v * WEI_PER_GWEI as f64;
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+
+        // wei-sized jitter is nowhere near the 15 gwei threshold
+        assert_eq!(scheduler.update_spike_mode(&mut state, 16.0), None);
+
+        // hovering around the high threshold only enters spike mode once
+        let series = [14.0, 16.0, 14.0, 16.0, 13.0, 17.0, 11.0, 15.5, 12.0];
+        let changes: Vec<bool> = series
+            .iter()
+            .filter_map(|&v| scheduler.update_spike_mode(&mut state, gwei(v)))
+            .collect();
+        assert_eq!(changes, vec![true]);
+
+        let changes: Vec<bool> = [9.0, 14.0, 16.0]
+            .iter()
+            .filter_map(|&v| scheduler.update_spike_mode(&mut state, gwei(v)))
+            .collect();
+        assert_eq!(changes, vec![false, true]);
+    }
+
+    #[test]
+    fn test_spike_mode_requires_consecutive_samples() {
+        let gwei = |v: f64| v * WEI_PER_GWEI as f64;
+        let config = SchedulerConfig {
+            spike_threshold_low: gwei(15.0),
+            spike_confirm_samples: 3,
+            ..Default::default()
+        };
+        let (scheduler, _rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        let series = [16.0, 14.0, 16.0, 16.0, 14.0, 16.0, 16.0, 14.0, 16.0, 16.0];
+        let changes = series
+            .iter()
+            .filter(|&&v| scheduler.update_spike_mode(&mut state, gwei(v)).is_some())
+            .count();
+        assert_eq!(changes, 0);
+
+        let changes: Vec<bool> = [16.0, 16.0, 16.0, 14.0, 14.0, 14.0]
+            .iter()
+            .filter_map(|&v| scheduler.update_spike_mode(&mut state, gwei(v)))
+            .collect();
+        assert_eq!(changes, vec![true, false]);
+    }
+
+    #[test]
+    fn test_config_rejects_inverted_spike_thresholds() {
+        let config = SchedulerConfig {
+            spike_threshold_low: gwei_to_wei(20) as f64,
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::SpikeThresholdsInverted {
+                low: 20e9,
+                high: 15e9
+            })
+        );
+        assert!(SchedulerConfig::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_config_rejects_invalid_per_sender_rate() {
+        let config = SchedulerConfig {
+            per_sender_rate: Some(RateLimiterConfig {
+                sustained_per_sec: 0,
+                burst: 5,
+            }),
+            ..Default::default()
+        };
+        assert_eq!(
+            config.validate(),
+            Err(ConfigError::PerSenderRate(
+                LimiterConfigError::ZeroSustainedRate
+            ))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_status_stream_through_lifecycle() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let (status_tx, mut status_rx) = mpsc::channel(STATUS_BUFFER);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let submission = Submission {
+            req: request(1, 100, None),
+            status: Some(status_tx),
+        };
+        scheduler.handle_submission(submission, &mut state).await;
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        scheduler.handle_gas_event(base_fee(70), &mut state).await;
+        let cmd = SchedulerCommand::Broadcast {
+            tx_id: 1,
+            tx_hash: [0x11; 32],
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash: [0x11; 32],
+            block_number: 42,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler.handle_gas_event(confirmed, &mut state).await;
+
+        let mut statuses = Vec::new();
+        while let Some(status) = status_rx.recv().await {
+            statuses.push(status);
+        }
+        assert_eq!(
+            statuses,
+            vec![
+                TxStatus::Pending,
+                TxStatus::Submitted {
+                    nonce: 0,
+                    gas_price: Wei(52)
+                },
+                TxStatus::Repriced {
+                    new_gas_price: Wei(72)
+                },
+                TxStatus::Broadcast {
+                    tx_hash: [0x11; 32]
+                },
+                TxStatus::Confirmed { block_number: 42 },
+            ]
+        );
+        assert!(state.submitted.is_empty());
+        assert!(state.watchers.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_slow_watcher_still_gets_the_terminal_status() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let (status_tx, mut status_rx) = mpsc::channel(1);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let submission = Submission {
+            req: request(1, 100, None),
+            status: Some(status_tx),
+        };
+        scheduler.handle_submission(submission, &mut state).await;
+        let cmd = SchedulerCommand::Broadcast {
+            tx_id: 1,
+            tx_hash: [0x11; 32],
+        };
+        scheduler.handle_command(cmd, &mut state).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash: [0x11; 32],
+            block_number: 42,
+            effective_gas_price: 0,
+            gas_used: 0,
+        };
+        scheduler.handle_gas_event(confirmed, &mut state).await;
+        assert!(state.watchers.is_empty());
+
+        // only Pending fit; what came after it is lost, but not the end
+        assert_eq!(status_rx.recv().await, Some(TxStatus::Pending));
+        assert_eq!(
+            status_rx.recv().await,
+            Some(TxStatus::Confirmed { block_number: 42 })
+        );
+        assert_eq!(status_rx.recv().await, None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_cancel_ends_the_status_stream_with_cancelled() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let (status_tx, mut status_rx) = mpsc::channel(STATUS_BUFFER);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let submission = Submission {
+            req: request(1, 40, None),
+            status: Some(status_tx),
+        };
+        scheduler.handle_submission(submission, &mut state).await;
+        let cancel = SchedulerCommand::Cancel { tx_id: 1 };
+        scheduler.handle_command(cancel, &mut state).await;
+
+        let mut statuses = Vec::new();
+        while let Some(status) = status_rx.recv().await {
+            statuses.push(status);
+        }
+        assert!(matches!(
+            statuses.as_slice(),
+            [
+                TxStatus::Pending,
+                TxStatus::Deferred(_),
+                TxStatus::Cancelled
+            ]
+        ));
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [
+                SchedulerDecision::Defer { tx_id: 1, .. },
+                SchedulerDecision::Drop { tx_id: 1, .. }
+            ]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_dropped_status_receiver_is_forgotten() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        let (status_tx, status_rx) = mpsc::channel(1);
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let submission = Submission {
+            req: request(1, 40, None),
+            status: Some(status_tx),
+        };
+        drop(status_rx);
+        scheduler.handle_submission(submission, &mut state).await;
+
+        assert!(state.watchers.is_empty());
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Defer { tx_id: 1, .. }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_handle_submit_with_status() {
+        let (scheduler, _rx) = scheduler(SchedulerConfig::default());
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(8);
+        let (_gas_tx, gas_rx) = mpsc::channel(8);
+        tokio::spawn(Arc::new(scheduler).run(gas_rx, req_rx, cmd_rx));
+
+        let mut status = handle
+            .submit_with_status(request(1, 100, None))
+            .await
+            .unwrap();
+        assert_eq!(status.recv().await, Some(TxStatus::Pending));
+        assert_eq!(
+            status.recv().await,
+            Some(TxStatus::Submitted {
+                nonce: 0,
+                gas_price: Wei(2)
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_shutdown_drains_queued_requests_into_the_snapshot() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let shutdown = CancellationToken::new();
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(8);
+        let (gas_tx, gas_rx) = mpsc::channel(8);
+        let task = tokio::spawn(
+            Arc::new(scheduler.with_shutdown(shutdown.clone())).run(gas_rx, req_rx, cmd_rx),
+        );
+
+        gas_tx.send(base_fee(50)).await.unwrap();
+        handle.submit(request(1, 100, None)).await.unwrap();
+        while !matches!(rx.recv().await, Some(SchedulerDecision::Submit { .. })) {}
+        // queued before the shutdown, so still handled; it can't go out at this fee
+        handle.submit(request(2, 40, None)).await.unwrap();
+        shutdown.cancel();
+
+        let snapshot = task.await.unwrap();
+        let pending: Vec<_> = snapshot.pending.iter().map(|req| req.id).collect();
+        assert_eq!(pending, vec![2]);
+        let submitted: Vec<_> = (snapshot.submitted.iter())
+            .map(|tx| (tx.req.id, tx.nonce, tx.tx_hash))
+            .collect();
+        assert_eq!(submitted, vec![(1, 0, None)]);
+        // nothing more is taken, though the handle is still around
+        assert!(handle.submit(request(3, 100, None)).await.is_err());
+        assert!(gas_tx.send(base_fee(60)).await.is_err());
+    }
+
+    async fn replay(seed: u64) -> Vec<SchedulerDecision> {
+        use crate::source::{FeePattern, SyntheticSource};
+
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(16);
+        let source = SyntheticSource::new(
+            FeePattern::RandomWalk {
+                start: 50,
+                max_step: 15,
+                seed,
+            },
+            Duration::from_secs(12),
+        )
+        .with_max_blocks(40);
+
+        for id in 1..=5 {
+            handle
+                .submit(request(id, 40 + id as u128 * 5, None))
+                .await
+                .unwrap();
+        }
+        drop(handle);
+        Arc::new(scheduler)
+            .run_with_source(source, req_rx, cmd_rx)
+            .await;
+        drain(&mut rx)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_synthetic_replay_is_deterministic() {
+        let first = replay(42).await;
+        assert!(!first.is_empty());
+        assert_eq!(first, replay(42).await);
+        assert_ne!(first, replay(43).await);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweep_retention_rules() {
+        let config = SchedulerConfig {
+            pending_max_age: Some(Duration::from_secs(60)),
+            pending_max_evaluations: Some(3),
+            ..Default::default()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        let deadline = scheduler.now_secs() + 10;
+        scheduler
+            .handle_tx_request(request(1, 40, Some(deadline)), &mut state)
+            .await;
+        scheduler
+            .handle_tx_request(request(2, 40, None), &mut state)
+            .await;
+        state.pending[1].evaluations = 0;
+        let mut old = PendingTx::new(request(3, 40, None), Wei(50));
+        old.accepted_at -= Duration::from_secs(61);
+        state.pending.push(old);
+        drain(&mut rx);
+
+        // tx 1 has been evaluated twice, tx 2 once since the reset
+        scheduler.sweep_pending(&mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 3,
+                reason: "exceeded max pending age".to_string(),
+            }]
+        );
+
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler.sweep_pending(&mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "exceeded max evaluations without qualifying".to_string(),
+            }]
+        );
+
+        state.pending[0].req.deadline = Some(scheduler.now_secs());
+        scheduler.sweep_pending(&mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Drop {
+                tx_id: 2,
+                reason: "deadline expired with fee cap 40 wei below base fee 50 wei".to_string(),
+            }]
+        );
+        assert!(state.pending.is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_sweep_runs_on_tick_without_gas_events() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(8);
+        let (gas_tx, gas_rx) = mpsc::channel(8);
+        let deadline = scheduler.now_secs() + 5;
+        tokio::spawn(Arc::new(scheduler).run(gas_rx, req_rx, cmd_rx));
+
+        gas_tx.send(base_fee(50)).await.unwrap();
+        handle.submit(request(1, 40, Some(deadline))).await.unwrap();
+        assert!(matches!(
+            rx.recv().await,
+            Some(SchedulerDecision::Defer { tx_id: 1, .. })
+        ));
+
+        // nothing else arrives; the sweep tick alone notices the deadline
+        let started = Instant::now();
+        assert_eq!(
+            rx.recv().await,
+            Some(SchedulerDecision::Drop {
+                tx_id: 1,
+                reason: "deadline expired with fee cap 40 wei below base fee 50 wei".to_string(),
+            })
+        );
+        assert!(started.elapsed() <= Duration::from_secs(6));
+    }
+
+    async fn submitted_ids(config: SchedulerConfig, gas_limits: &[u64]) -> Vec<u64> {
+        let (scheduler, mut rx) = scheduler_with_limiter(config, RateLimiter::without_refill(10));
+        let mut state = SchedulerState::default();
+
+        for (i, &gas_limit) in gas_limits.iter().enumerate() {
+            let mut req = request(i as u64 + 1, 100, None);
+            req.gas_limit = gas_limit;
+            state.pending.push(PendingTx::new(req, Wei(50)));
+        }
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        drain(&mut rx)
+            .into_iter()
+            .filter_map(|d| match d {
+                SchedulerDecision::Submit { tx_id, .. } => Some(tx_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_limiter_weighting_throughput() {
+        let per_gas = SchedulerConfig {
+            limiter_weighting: LimiterWeighting::PerGas {
+                gas_per_token: 21_000,
+            },
+            ..Default::default()
+        };
+        let small = [21_000; 12];
+        let large = [5_000_000; 3];
+
+        // per tx: a 10-token bucket admits 10 of anything
+        assert_eq!(
+            submitted_ids(SchedulerConfig::default(), &small)
+                .await
+                .len(),
+            10
+        );
+        assert_eq!(
+            submitted_ids(SchedulerConfig::default(), &large)
+                .await
+                .len(),
+            3
+        );
+
+        // per gas: small transfers still cost one token, deployments drain the bucket
+        assert_eq!(submitted_ids(per_gas.clone(), &small).await.len(), 10);
+        assert_eq!(submitted_ids(per_gas.clone(), &large).await, vec![1]);
+
+        // mixed queue: a 3-token call after two transfers waits its turn
+        assert_eq!(
+            submitted_ids(per_gas, &[21_000, 21_000, 63_000, 21_000]).await,
+            vec![1, 2, 3, 4]
+        );
+    }
+
+    fn market_updates(decisions: Vec<SchedulerDecision>) -> Vec<SchedulerDecision> {
+        decisions
+            .into_iter()
+            .filter(|d| matches!(d, SchedulerDecision::MarketUpdate { .. }))
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_market_updates_are_throttled() {
+        let config = SchedulerConfig {
+            market_updates: MarketUpdatePolicy::Every(Duration::from_secs(1)),
+            ..Default::default()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        for fee in 50..60 {
+            scheduler.handle_gas_event(base_fee(fee), &mut state).await;
+        }
+        assert_eq!(
+            market_updates(drain(&mut rx)),
+            vec![SchedulerDecision::MarketUpdate {
+                current_fee: Wei(50),
+                volatility: 0.0,
+                trend: 0.0,
+                spike: false,
+            }]
+        );
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        scheduler.handle_gas_event(base_fee(70), &mut state).await;
+        let updates = market_updates(drain(&mut rx));
+        let [
+            SchedulerDecision::MarketUpdate {
+                current_fee,
+                volatility,
+                trend,
+                spike,
+            },
+        ] = updates.as_slice()
+        else {
+            panic!("expected one market update, got {:?}", updates);
+        };
+        assert_eq!(*current_fee, Wei(70));
+        assert_eq!(*volatility, scheduler.model.get_volatility());
+        assert_eq!(*trend, scheduler.model.get_trend());
+        assert_eq!(*spike, state.spike_mode);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_market_updates_per_block_and_disabled() {
+        let config = SchedulerConfig {
+            market_updates: MarketUpdatePolicy::EveryBlock,
+            ..Default::default()
+        };
+        let (per_block, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+
+        per_block.handle_gas_event(base_fee(50), &mut state).await;
+        per_block.handle_gas_event(block(1, 50), &mut state).await;
+        per_block.handle_gas_event(block(2, 50), &mut state).await;
+        assert_eq!(market_updates(drain(&mut rx)).len(), 2);
+
+        let (quiet, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        quiet.handle_gas_event(block(1, 50), &mut state).await;
+        quiet.handle_gas_event(base_fee(50), &mut state).await;
+        assert!(market_updates(drain(&mut rx)).is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blob_request_waits_for_blob_fee() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let req = TransactionRequest {
+            blob: Some(BlobParams {
+                max_fee_per_blob_gas: Wei(10),
+                blob_count: 1,
+            }),
+            ..request(1, 100, None)
+        };
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Defer {
+                tx_id: 1,
+                reason: DeferReason::BlobFeeUnknown,
+                retry_hint: None,
+            }]
+        );
+
+        let blob_fee = |blob_base_fee| GasEvent::BlobBaseFeeUpdate { blob_base_fee };
+        scheduler.handle_gas_event(blob_fee(12), &mut state).await;
+        assert_eq!(state.pending.len(), 1);
+        assert!(state.submitted.is_empty());
+
+        // 8 plus one block of headroom, well inside the cap of 10
+        scheduler.handle_gas_event(blob_fee(8), &mut state).await;
+        assert_eq!(
+            drain(&mut rx).last(),
+            Some(&SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(52) * 21_000 + Wei(131_072) * 9,
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: Some(Wei(9)),
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_blob_request_refused_when_configured() {
+        let config = SchedulerConfig {
+            reject_blob_requests: true,
+            ..SchedulerConfig::default()
+        };
+        let (scheduler, mut rx) = scheduler(config);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let req = TransactionRequest {
+            blob: Some(BlobParams {
+                max_fee_per_blob_gas: Wei(10),
+                blob_count: 1,
+            }),
+            ..request(1, 100, None)
+        };
+        scheduler.handle_tx_request(req, &mut state).await;
+        assert_eq!(
+            drain(&mut rx),
+            vec![SchedulerDecision::Rejected {
+                tx_id: 1,
+                reason: "blob txs are not accepted".to_string(),
+                error: Some(ValidationError::BlobUnsupported),
+            }]
+        );
+        assert!(state.pending.is_empty());
+
+        // other requests go through as before
+        scheduler
+            .handle_tx_request(request(2, 100, None), &mut state)
+            .await;
+        assert!(matches!(
+            drain(&mut rx).as_slice(),
+            [SchedulerDecision::Submit { tx_id: 2, .. }]
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_authorized_request_submits_as_eip1559() {
+        let (scheduler, mut rx) = scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+
+        let req = TransactionRequest {
+            gas_limit: 46_000,
+            chain_id: Some(1),
+            authorization_list: Some(vec![SignedAuthorization {
+                chain_id: 1,
+                address: [0xDD; 20],
+                nonce: 0,
+                y_parity: 0,
+                r: [0x11; 32],
+                s: [0x22; 32],
+            }]),
+            ..request(1, 100, None)
+        };
+        scheduler.handle_tx_request(req.clone(), &mut state).await;
+        let decision = drain(&mut rx).pop().unwrap();
+        assert_eq!(
+            decision,
+            SchedulerDecision::Submit {
+                tx_id: 1,
+                nonce: 0,
+                gas_price: Wei(52),
+                max_priority_fee_per_gas: Wei(2),
+                create: false,
+                estimated_cost_wei: Wei(52) * 46_000,
+                estimated_savings_wei: Wei(0),
+                blob_gas_price: None,
+                fee_mode: FeeMode::Eip1559,
+                privacy: SubmissionPrivacy::Public,
+            }
+        );
+
+        #[cfg(feature = "tx-build")]
+        {
+            let tx = crate::tx_build::build_eip7702(&req, &decision).unwrap();
+            assert_eq!(tx.max_fee_per_gas, 52);
+            assert_eq!(tx.authorization_list.len(), 1);
+            assert_eq!(tx.authorization_list[0].address, Address::repeat_byte(0xDD));
+        }
+    }
+
+    fn record_scheduler(config: SchedulerConfig) -> (Scheduler, mpsc::Receiver<DecisionRecord>) {
+        let (decision_tx, decision_rx) = mpsc::channel(100);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Scheduler::new(
+            config,
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(ChannelSink::<DecisionRecord>::new(decision_tx))],
+        );
+        (scheduler, decision_rx)
+    }
+
+    fn keyed(id: u64, key: u8) -> Submission {
+        TransactionRequest {
+            idempotency_key: Some([key; 16]),
+            ..request(id, 100, None)
+        }
+        .into()
+    }
+
+    fn submits(records: &[DecisionRecord]) -> Vec<(u64, bool)> {
+        records
+            .iter()
+            .filter_map(|r| match r.decision {
+                SchedulerDecision::Submit { tx_id, .. } => Some((tx_id, r.replayed)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_repeated_idempotency_key_reacknowledges() {
+        let (scheduler, mut rx) = record_scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_submission(keyed(1, 0x11), &mut state)
+            .await;
+        let first = drain_records(&mut rx);
+        assert_eq!(first.len(), 1);
+        assert!(matches!(
+            first[0].decision,
+            SchedulerDecision::Submit { tx_id: 1, .. }
+        ));
+        assert_eq!(
+            first[0].correlation_id,
+            Some(CorrelationId {
+                tx_id: 1,
+                idempotency_key: Some([0x11; 16]),
+            })
+        );
+
+        // the gateway retried with a fresh id: nothing new is scheduled
+        let (watcher, mut status_rx) = mpsc::channel(STATUS_BUFFER);
+        let retry = Submission {
+            status: Some(watcher),
+            ..keyed(2, 0x11)
+        };
+        scheduler.handle_submission(retry, &mut state).await;
+        let repeat = drain_records(&mut rx);
+        assert_eq!(
+            repeat,
+            vec![DecisionRecord {
+                seq: first[0].seq + 1,
+                replayed: true,
+                ..first[0].clone()
+            }]
+        );
+        assert_eq!(state.submitted.len(), 1);
+        assert!(state.pending.is_empty());
+        assert!(!state.watchers.contains_key(&2));
+        assert_eq!(status_rx.recv().await, None);
+
+        // requests without a key are correlated by id alone
+        scheduler
+            .handle_submission(request(3, 100, None).into(), &mut state)
+            .await;
+        let unkeyed = drain_records(&mut rx);
+        assert_eq!(unkeyed[0].seq, first[0].seq + 2);
+        assert_eq!(
+            unkeyed[0].correlation_id,
+            Some(CorrelationId {
+                tx_id: 3,
+                idempotency_key: None,
+            })
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idempotency_key_expires_after_window() {
+        let config = SchedulerConfig {
+            idempotency_window: Duration::from_secs(60),
+            ..SchedulerConfig::default()
+        };
+        let (scheduler, mut rx) = record_scheduler(config);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        scheduler
+            .handle_submission(keyed(1, 0x11), &mut state)
+            .await;
+
+        tokio::time::advance(Duration::from_secs(59)).await;
+        scheduler
+            .handle_submission(keyed(2, 0x11), &mut state)
+            .await;
+        tokio::time::advance(Duration::from_secs(1)).await;
+        scheduler
+            .handle_submission(keyed(3, 0x11), &mut state)
+            .await;
+
+        let records = drain_records(&mut rx);
+        assert_eq!(submits(&records), vec![(1, false), (1, true), (3, false)]);
+        let seqs: Vec<u64> = records.iter().map(|r| r.seq).collect();
+        assert!(seqs.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(state.idempotency.by_tx.get(&3), Some(&[0x11; 16]));
+        assert!(!state.idempotency.by_tx.contains_key(&1));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idempotency_keys_evicted_over_capacity() {
+        let config = SchedulerConfig {
+            idempotency_capacity: 2,
+            ..SchedulerConfig::default()
+        };
+        let (scheduler, mut rx) = record_scheduler(config);
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(base_fee(50), &mut state).await;
+        for (id, key) in [(1, 0x11), (2, 0x22), (3, 0x33)] {
+            scheduler
+                .handle_submission(keyed(id, key), &mut state)
+                .await;
+        }
+        // the oldest key made way for the third
+        scheduler
+            .handle_submission(keyed(4, 0x11), &mut state)
+            .await;
+        scheduler
+            .handle_submission(keyed(5, 0x33), &mut state)
+            .await;
+
+        assert_eq!(
+            submits(&drain_records(&mut rx)),
+            vec![(1, false), (2, false), (3, false), (4, false), (3, true)]
+        );
+        assert_eq!(state.idempotency.entries.len(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_decision_meta_carries_triggering_block() {
+        let (scheduler, mut rx) = record_scheduler(SchedulerConfig::default());
+        let mut state = SchedulerState::default();
+        scheduler.handle_gas_event(block(7, 50), &mut state).await;
+        let deadline = scheduler.now_secs() + 5;
+        scheduler
+            .handle_submission(request(1, 40, None).into(), &mut state)
+            .await;
+        scheduler
+            .handle_submission(request(2, 10, Some(deadline)).into(), &mut state)
+            .await;
+        let requested = drain_records(&mut rx);
+        assert_eq!(requested.len(), 2);
+        for record in &requested {
+            assert_eq!(record.meta.trigger, DecisionTrigger::Request);
+            assert_eq!(record.meta.block_number, Some(7));
+            assert_eq!(record.meta.market.current_fee, Wei(50));
+        }
+
+        scheduler.handle_gas_event(block(8, 30), &mut state).await;
+        let evented = drain_records(&mut rx);
+        let submit = evented
+            .iter()
+            .find(|r| matches!(r.decision, SchedulerDecision::Submit { tx_id: 1, .. }))
+            .unwrap();
+        assert_eq!(submit.meta.trigger, DecisionTrigger::GasEvent);
+        assert_eq!(submit.meta.block_number, Some(8));
+        assert_eq!(submit.meta.market.current_fee, Wei(30));
+
+        tokio::time::advance(Duration::from_secs(6)).await;
+        scheduler.handle_tick(&mut state).await;
+        let swept = drain_records(&mut rx);
+        assert!(matches!(
+            swept[..],
+            [DecisionRecord {
+                decision: SchedulerDecision::Drop { tx_id: 2, .. },
+                ..
+            }]
+        ));
+        assert_eq!(swept[0].meta.trigger, DecisionTrigger::Tick);
+        assert_eq!(swept[0].meta.block_number, Some(8));
+        assert_eq!(
+            swept[0].meta.decided_at_unix_ms - requested[0].meta.decided_at_unix_ms,
+            6_000
+        );
     }
 }
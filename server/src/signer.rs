@@ -0,0 +1,768 @@
+use crate::events::short_address;
+use alloy_consensus::{SignableTransaction, Transaction, TxEnvelope, TypedTransaction};
+use alloy_eips::eip2718::{Decodable2718, Encodable2718};
+use alloy_network::TxSignerSync;
+use alloy_primitives::{Signature, hex, keccak256};
+use alloy_signer_local::PrivateKeySigner;
+use async_trait::async_trait;
+use aws_lc_rs::cipher::{AES_128, DecryptingKey, DecryptionContext, UnboundCipherKey};
+use aws_lc_rs::iv::FixedLength;
+use aws_lc_rs::pbkdf2;
+use serde::Deserialize;
+use serde_json::{Value, json};
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::time::Duration;
+use tracing::warn;
+
+/// An unsigned tx, as `tx_build` makes them.
+pub type TypedTx = TypedTransaction;
+
+/// Seconds a `RemoteSigner` call may take.
+pub const DEFAULT_REMOTE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Times a failed `RemoteSigner` call is tried again.
+pub const DEFAULT_REMOTE_RETRIES: u32 = 2;
+const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(200);
+/// Most memory scrypt parameters in a keystore may ask for, in bytes.
+const MAX_SCRYPT_MEMORY: u64 = 1 << 30;
+
+/// A signed tx, EIP-2718 encoded as `eth_sendRawTransaction` takes it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedBytes(pub Vec<u8>);
+
+impl SignedBytes {
+    fn new(tx: TypedTx, signature: Signature) -> Self {
+        let envelope: TxEnvelope = tx.into_signed(signature).into();
+        Self(envelope.encoded_2718())
+    }
+
+    pub fn tx_hash(&self) -> [u8; 32] {
+        keccak256(&self.0).0
+    }
+
+    /// The address the signature recovers to.
+    pub fn recover_sender(&self) -> Result<[u8; 20], SignError> {
+        let envelope = TxEnvelope::decode_2718(&mut self.0.as_slice())
+            .map_err(|e| SignError::Malformed(e.to_string()))?;
+        envelope
+            .signature()
+            .recover_address_from_prehash(&envelope.signature_hash())
+            .map(|address| address.0.0)
+            .map_err(|e| SignError::Malformed(e.to_string()))
+    }
+}
+
+impl AsRef<[u8]> for SignedBytes {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignError {
+    /// The tx names another chain than the one it was to be signed for.
+    ChainMismatch { tx: u64, requested: u64 },
+    /// The key refused to sign.
+    Key(String),
+    /// The signing service couldn't be reached, or turned the tx down.
+    Remote(String),
+    /// What was signed doesn't decode, or its signature recovers to no address.
+    Malformed(String),
+    /// The signature recovers to another address than the signer's.
+    WrongSigner {
+        expected: [u8; 20],
+        recovered: [u8; 20],
+    },
+}
+
+impl std::fmt::Display for SignError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignError::ChainMismatch { tx, requested } => {
+                write!(f, "tx is for chain {}, not {}", tx, requested)
+            }
+            SignError::Key(e) => write!(f, "signing failed: {}", e),
+            SignError::Remote(e) => write!(f, "remote signer: {}", e),
+            SignError::Malformed(e) => write!(f, "malformed signed tx: {}", e),
+            SignError::WrongSigner {
+                expected,
+                recovered,
+            } => write!(
+                f,
+                "signed by {} rather than {}",
+                short_address(recovered),
+                short_address(expected)
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignError {}
+
+/// Signs the txs the executor builds. `address` is the one account it signs
+/// for; the executor only hands it that account's txs.
+#[async_trait]
+pub trait TxSigner: Send + Sync {
+    /// Signs `tx` for `chain_id`, which it takes on if it names no chain.
+    async fn sign_typed(&self, tx: TypedTx, chain_id: u64) -> Result<SignedBytes, SignError>;
+
+    fn address(&self) -> [u8; 20];
+}
+
+#[async_trait]
+impl<S: TxSigner + ?Sized> TxSigner for Box<S> {
+    async fn sign_typed(&self, tx: TypedTx, chain_id: u64) -> Result<SignedBytes, SignError> {
+        (**self).sign_typed(tx, chain_id).await
+    }
+
+    fn address(&self) -> [u8; 20] {
+        (**self).address()
+    }
+}
+
+/// `tx`, on `chain_id` unless it names another.
+fn on_chain(mut tx: TypedTx, chain_id: u64) -> Result<TypedTx, SignError> {
+    if tx.set_chain_id_checked(chain_id) {
+        Ok(tx)
+    } else {
+        Err(SignError::ChainMismatch {
+            tx: tx.chain_id().unwrap_or_default(),
+            requested: chain_id,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum KeyError {
+    /// The environment variable isn't set, or isn't unicode.
+    Env {
+        var: String,
+        error: std::env::VarError,
+    },
+    Io(std::io::Error),
+    /// The keystore isn't one this can read.
+    Keystore(String),
+    /// The keystore's MAC doesn't match: the password is wrong.
+    WrongPassword,
+    /// What was read isn't a secp256k1 private key.
+    Key(String),
+}
+
+impl std::fmt::Display for KeyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KeyError::Env { var, error } => write!(f, "{}: {}", var, error),
+            KeyError::Io(e) => write!(f, "{}", e),
+            KeyError::Keystore(e) => write!(f, "unreadable keystore: {}", e),
+            KeyError::WrongPassword => write!(f, "wrong keystore password"),
+            KeyError::Key(e) => write!(f, "not a private key: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+impl From<std::io::Error> for KeyError {
+    fn from(err: std::io::Error) -> Self {
+        KeyError::Io(err)
+    }
+}
+
+/// Signs with a key held in memory, read from an environment variable or an
+/// encrypted keystore file.
+#[derive(Clone)]
+pub struct LocalSigner {
+    key: PrivateKeySigner,
+}
+
+impl LocalSigner {
+    pub fn new(key: PrivateKeySigner) -> Self {
+        Self { key }
+    }
+
+    /// The hex private key in the environment variable `var`.
+    pub fn from_env(var: &str) -> Result<Self, KeyError> {
+        let key = std::env::var(var).map_err(|error| KeyError::Env {
+            var: var.to_string(),
+            error,
+        })?;
+        key.trim()
+            .parse()
+            .map(Self::new)
+            .map_err(|e: alloy_signer_local::LocalSignerError| KeyError::Key(e.to_string()))
+    }
+
+    /// The key in a Web3 Secret Storage (version 3) keystore, as geth, `cast
+    /// wallet` and most wallets write them: scrypt or PBKDF2-SHA256 and
+    /// AES-128-CTR.
+    pub fn from_keystore(path: impl AsRef<Path>, password: &str) -> Result<Self, KeyError> {
+        let key = decrypt_keystore(&std::fs::read(path)?, password)?;
+        PrivateKeySigner::from_slice(&key)
+            .map(Self::new)
+            .map_err(|e| KeyError::Key(e.to_string()))
+    }
+
+    /// The key, for what needs to sign other than txs, such as a relay's
+    /// `X-Flashbots-Signature`.
+    pub fn key(&self) -> &PrivateKeySigner {
+        &self.key
+    }
+}
+
+impl From<PrivateKeySigner> for LocalSigner {
+    fn from(key: PrivateKeySigner) -> Self {
+        Self::new(key)
+    }
+}
+
+#[async_trait]
+impl TxSigner for LocalSigner {
+    async fn sign_typed(&self, tx: TypedTx, chain_id: u64) -> Result<SignedBytes, SignError> {
+        let mut tx = on_chain(tx, chain_id)?;
+        let signature = self
+            .key
+            .sign_transaction_sync(&mut tx)
+            .map_err(|e| SignError::Key(e.to_string()))?;
+        Ok(SignedBytes::new(tx, signature))
+    }
+
+    fn address(&self) -> [u8; 20] {
+        self.key.address().0.0
+    }
+}
+
+#[derive(Deserialize)]
+struct Keystore {
+    version: u32,
+    #[serde(alias = "Crypto")]
+    crypto: KeystoreCrypto,
+}
+
+#[derive(Deserialize)]
+struct KeystoreCrypto {
+    cipher: String,
+    cipherparams: CipherParams,
+    ciphertext: String,
+    kdf: String,
+    kdfparams: Value,
+    mac: String,
+}
+
+#[derive(Deserialize)]
+struct CipherParams {
+    iv: String,
+}
+
+#[derive(Deserialize)]
+struct ScryptParams {
+    dklen: usize,
+    n: u64,
+    r: u32,
+    p: u32,
+    salt: String,
+}
+
+#[derive(Deserialize)]
+struct Pbkdf2Params {
+    dklen: usize,
+    c: u32,
+    prf: String,
+    salt: String,
+}
+
+fn unhex(field: &str, value: &str) -> Result<Vec<u8>, KeyError> {
+    hex::decode(value).map_err(|e| KeyError::Keystore(format!("{}: {}", field, e)))
+}
+
+fn params<T: for<'de> Deserialize<'de>>(kdfparams: Value) -> Result<T, KeyError> {
+    serde_json::from_value(kdfparams).map_err(|e| KeyError::Keystore(format!("kdfparams: {}", e)))
+}
+
+fn decrypt_keystore(json: &[u8], password: &str) -> Result<Vec<u8>, KeyError> {
+    let keystore: Keystore =
+        serde_json::from_slice(json).map_err(|e| KeyError::Keystore(e.to_string()))?;
+    if keystore.version != 3 {
+        return Err(KeyError::Keystore(format!(
+            "version {} isn't supported",
+            keystore.version
+        )));
+    }
+    let crypto = keystore.crypto;
+    if crypto.cipher != "aes-128-ctr" {
+        return Err(KeyError::Keystore(format!(
+            "cipher {} isn't supported",
+            crypto.cipher
+        )));
+    }
+    let password = password.as_bytes();
+    let derived = match crypto.kdf.as_str() {
+        "scrypt" => {
+            let p: ScryptParams = params(crypto.kdfparams)?;
+            let salt = unhex("salt", &p.salt)?;
+            scrypt(password, &salt, p.n, p.r, p.p, p.dklen)?
+        }
+        "pbkdf2" => {
+            let p: Pbkdf2Params = params(crypto.kdfparams)?;
+            if p.prf != "hmac-sha256" {
+                return Err(KeyError::Keystore(format!("prf {} isn't supported", p.prf)));
+            }
+            let iterations = NonZeroU32::new(p.c)
+                .ok_or_else(|| KeyError::Keystore("pbkdf2 needs an iteration".into()))?;
+            let mut derived = vec![0; p.dklen];
+            pbkdf2::derive(
+                pbkdf2::PBKDF2_HMAC_SHA256,
+                iterations,
+                &unhex("salt", &p.salt)?,
+                password,
+                &mut derived,
+            );
+            derived
+        }
+        other => {
+            return Err(KeyError::Keystore(format!("kdf {} isn't supported", other)));
+        }
+    };
+    if derived.len() < 32 {
+        return Err(KeyError::Keystore("dklen is below 32".into()));
+    }
+    let mut ciphertext = unhex("ciphertext", &crypto.ciphertext)?;
+    let mac = keccak256([&derived[16..32], &ciphertext].concat());
+    if mac.0[..] != unhex("mac", &crypto.mac)?[..] {
+        return Err(KeyError::WrongPassword);
+    }
+    let iv: [u8; 16] = unhex("iv", &crypto.cipherparams.iv)?
+        .try_into()
+        .map_err(|_| KeyError::Keystore("iv isn't 16 bytes".into()))?;
+    let cipher = UnboundCipherKey::new(&AES_128, &derived[..16])
+        .and_then(DecryptingKey::ctr)
+        .map_err(|e| KeyError::Keystore(e.to_string()))?;
+    cipher
+        .decrypt(
+            &mut ciphertext,
+            DecryptionContext::Iv128(FixedLength::from(iv)),
+        )
+        .map_err(|e| KeyError::Keystore(e.to_string()))?;
+    Ok(ciphertext)
+}
+
+/// scrypt, as in RFC 7914, over aws-lc's PBKDF2.
+fn scrypt(
+    password: &[u8],
+    salt: &[u8],
+    n: u64,
+    r: u32,
+    p: u32,
+    dklen: usize,
+) -> Result<Vec<u8>, KeyError> {
+    if n < 2 || !n.is_power_of_two() || n > u64::from(u32::MAX) || r == 0 || p == 0 {
+        return Err(KeyError::Keystore("bad scrypt parameters".into()));
+    }
+    let memory = 128 * u64::from(r) * n;
+    if memory > MAX_SCRYPT_MEMORY {
+        return Err(KeyError::Keystore(format!(
+            "scrypt parameters want {} bytes",
+            memory
+        )));
+    }
+    let (n, r) = (n as usize, r as usize);
+    let once = NonZeroU32::MIN;
+    let mut blocks = vec![0; 128 * r * p as usize];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        once,
+        salt,
+        password,
+        &mut blocks,
+    );
+    for block in blocks.chunks_mut(128 * r) {
+        romix(block, n, r);
+    }
+    let mut derived = vec![0; dklen];
+    pbkdf2::derive(
+        pbkdf2::PBKDF2_HMAC_SHA256,
+        once,
+        &blocks,
+        password,
+        &mut derived,
+    );
+    Ok(derived)
+}
+
+fn romix(block: &mut [u8], n: usize, r: usize) {
+    let words = 32 * r;
+    let mut x: Vec<u32> = block
+        .chunks_exact(4)
+        .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]))
+        .collect();
+    let mut v = vec![0; words * n];
+    let mut scratch = vec![0; words];
+    for chunk in v.chunks_exact_mut(words) {
+        chunk.copy_from_slice(&x);
+        block_mix(&mut x, &mut scratch, r);
+    }
+    for _ in 0..n {
+        // Integerify: the last 64-byte block's first word, as n <= 2^32
+        let j = x[words - 16] as usize & (n - 1);
+        for (word, other) in x.iter_mut().zip(&v[j * words..(j + 1) * words]) {
+            *word ^= other;
+        }
+        block_mix(&mut x, &mut scratch, r);
+    }
+    for (bytes, word) in block.chunks_exact_mut(4).zip(&x) {
+        bytes.copy_from_slice(&word.to_le_bytes());
+    }
+}
+
+fn block_mix(b: &mut [u32], y: &mut [u32], r: usize) {
+    let mut x = [0; 16];
+    x.copy_from_slice(&b[(2 * r - 1) * 16..]);
+    for i in 0..2 * r {
+        for (word, other) in x.iter_mut().zip(&b[i * 16..(i + 1) * 16]) {
+            *word ^= other;
+        }
+        salsa20_8(&mut x);
+        // even blocks to the first half, odd ones to the second
+        let at = (i / 2 + (i % 2) * r) * 16;
+        y[at..at + 16].copy_from_slice(&x);
+    }
+    b.copy_from_slice(y);
+}
+
+fn salsa20_8(b: &mut [u32; 16]) {
+    fn quarter(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+        x[b] ^= x[a].wrapping_add(x[d]).rotate_left(7);
+        x[c] ^= x[b].wrapping_add(x[a]).rotate_left(9);
+        x[d] ^= x[c].wrapping_add(x[b]).rotate_left(13);
+        x[a] ^= x[d].wrapping_add(x[c]).rotate_left(18);
+    }
+    let mut x = *b;
+    for _ in 0..4 {
+        quarter(&mut x, 0, 4, 8, 12);
+        quarter(&mut x, 5, 9, 13, 1);
+        quarter(&mut x, 10, 14, 2, 6);
+        quarter(&mut x, 15, 3, 7, 11);
+        quarter(&mut x, 0, 1, 2, 3);
+        quarter(&mut x, 5, 6, 7, 4);
+        quarter(&mut x, 10, 11, 8, 9);
+        quarter(&mut x, 15, 12, 13, 14);
+    }
+    for (word, mixed) in b.iter_mut().zip(x) {
+        *word = word.wrapping_add(mixed);
+    }
+}
+
+/// Signs through an HTTP signing service, such as a KMS or HSM proxy, that
+/// holds the key. Each tx is POSTed as
+///
+/// `{"address": "0x…", "chain_id": 1, "tx": "0x<unsigned tx>", "hash": "0x<signing hash>"}`
+///
+/// and the service answers `{"signature": "0x<r, s and v, 65 bytes>"}`, or
+/// `{"error": "…"}` to refuse. Calls that time out, can't connect or get a 5xx
+/// or 429 are tried again, waiting twice as long each time; a signature for
+/// another address than `address` is refused.
+#[derive(Clone)]
+pub struct RemoteSigner {
+    url: String,
+    address: [u8; 20],
+    client: reqwest::Client,
+    timeout: Duration,
+    retries: u32,
+    retry_delay: Duration,
+    headers: Vec<(String, String)>,
+}
+
+/// A failed call, and whether trying again may help.
+struct CallError {
+    message: String,
+    retry: bool,
+}
+
+impl RemoteSigner {
+    /// A signer for `address` at `url`.
+    pub fn new(url: impl Into<String>, address: [u8; 20]) -> Self {
+        Self {
+            url: url.into(),
+            address,
+            client: reqwest::Client::new(),
+            timeout: DEFAULT_REMOTE_TIMEOUT,
+            retries: DEFAULT_REMOTE_RETRIES,
+            retry_delay: DEFAULT_RETRY_DELAY,
+            headers: Vec::new(),
+        }
+    }
+
+    /// How long each call may take; `DEFAULT_REMOTE_TIMEOUT` by default.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Times a failed call is tried again, `delay` after the first failure and
+    /// twice as long after each one since.
+    pub fn with_retries(mut self, retries: u32, delay: Duration) -> Self {
+        self.retries = retries;
+        self.retry_delay = delay;
+        self
+    }
+
+    /// Sends `name: value` with every call, e.g. to authenticate.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    async fn call(&self, body: &Value) -> Result<Signature, CallError> {
+        let mut request = self.client.post(&self.url).timeout(self.timeout).json(body);
+        for (name, value) in &self.headers {
+            request = request.header(name, value);
+        }
+        let response = request.send().await.map_err(|e| CallError {
+            message: e.to_string(),
+            retry: e.is_timeout() || e.is_connect() || e.is_request(),
+        })?;
+        let status = response.status();
+        let reply: Value = response.json().await.unwrap_or_default();
+        if let Some(error) = reply.get("error") {
+            return Err(CallError {
+                message: error
+                    .as_str()
+                    .map_or_else(|| error.to_string(), str::to_string),
+                retry: status.is_server_error(),
+            });
+        }
+        if !status.is_success() {
+            return Err(CallError {
+                message: format!("HTTP {}", status),
+                retry: status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS,
+            });
+        }
+        let malformed = |message: String| CallError {
+            message,
+            retry: false,
+        };
+        let signature = reply["signature"]
+            .as_str()
+            .ok_or_else(|| malformed(format!("no signature in {}", reply)))?;
+        let bytes = hex::decode(signature).map_err(|e| malformed(e.to_string()))?;
+        Signature::from_raw(&bytes).map_err(|e| malformed(e.to_string()))
+    }
+}
+
+#[async_trait]
+impl TxSigner for RemoteSigner {
+    async fn sign_typed(&self, tx: TypedTx, chain_id: u64) -> Result<SignedBytes, SignError> {
+        let tx = on_chain(tx, chain_id)?;
+        let body = json!({
+            "address": hex::encode_prefixed(self.address),
+            "chain_id": chain_id,
+            "tx": hex::encode_prefixed(tx.encoded_for_signing()),
+            "hash": tx.signature_hash(),
+        });
+        let mut delay = self.retry_delay;
+        let mut attempt = 0;
+        let signature = loop {
+            match self.call(&body).await {
+                Ok(signature) => break signature,
+                Err(e) if e.retry && attempt < self.retries => {
+                    warn!(
+                        "Remote signer failed, retrying in {:?}: {}",
+                        delay, e.message
+                    );
+                    tokio::time::sleep(delay).await;
+                    delay *= 2;
+                    attempt += 1;
+                }
+                Err(e) => return Err(SignError::Remote(e.message)),
+            }
+        };
+        let signed = SignedBytes::new(tx, signature);
+        let recovered = signed.recover_sender()?;
+        if recovered != self.address {
+            return Err(SignError::WrongSigner {
+                expected: self.address,
+                recovered,
+            });
+        }
+        Ok(signed)
+    }
+
+    fn address(&self) -> [u8; 20] {
+        self.address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloy_consensus::TxLegacy;
+    use alloy_primitives::{Address, B256, TxKind, U256};
+    use alloy_signer::SignerSync;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// The EIP-155 example tx: nonce 9, 20 gwei, 1 ether to 0x3535…35.
+    fn eip155_tx() -> TypedTx {
+        TypedTx::Legacy(TxLegacy {
+            chain_id: None,
+            nonce: 9,
+            gas_price: 20_000_000_000,
+            gas_limit: 21_000,
+            to: TxKind::Call(Address::repeat_byte(0x35)),
+            value: U256::from(10u128.pow(18)),
+            input: Default::default(),
+        })
+    }
+
+    fn eip155_key() -> PrivateKeySigner {
+        PrivateKeySigner::from_bytes(&B256::repeat_byte(0x46)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_local_signatures_match_known_vectors() {
+        let signer = LocalSigner::new(eip155_key());
+        assert_eq!(
+            hex::encode(signer.address()),
+            "9d8a62f656a8d1615c1294fd71e9cfb3e4855a4f"
+        );
+        let signed = signer.sign_typed(eip155_tx(), 1).await.unwrap();
+        assert_eq!(
+            hex::encode(&signed.0),
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a7640000\
+             8025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d899\
+             7f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83"
+        );
+        assert_eq!(signed.recover_sender().unwrap(), signer.address());
+        // a tx that names its chain isn't signed for another
+        let mut tx = eip155_tx();
+        tx.set_chain_id(10);
+        assert_eq!(
+            signer.sign_typed(tx, 1).await,
+            Err(SignError::ChainMismatch {
+                tx: 10,
+                requested: 1
+            })
+        );
+
+        // RFC 7914's scrypt vectors, the second at the cost of a light keystore
+        assert_eq!(
+            hex::encode(scrypt(b"", b"", 16, 1, 1, 64).unwrap()),
+            "77d6576238657b203b19ca42c18a0497f16b4844e3074ae8dfdffa3fede21442\
+             fcd0069ded0948f8326a753a0fc81f17e8d3e0fb2e0d3628cf35e20c38d18906"
+        );
+        assert_eq!(
+            hex::encode(scrypt(b"password", b"NaCl", 1024, 8, 16, 64).unwrap()),
+            "fdbabe1c9d3472007856e7190d01e9fe7c6ad7cbc8237830e77376634b373162\
+             2eaf30d92e22a3886ff109279d9830dac727afb94a83ee6d8360cbdfa2cc0640"
+        );
+
+        // the Web3 Secret Storage PBKDF2 test vector
+        let keystore = json!({
+            "version": 3,
+            "id": "3198bc9c-6672-5ab3-d995-4942343ae5b6",
+            "crypto": {
+                "cipher": "aes-128-ctr",
+                "cipherparams": {"iv": "6087dab2f9fdbbfaddc31a909735c1e6"},
+                "ciphertext": "5318b4d5bcd28de64ee5559e671353e16f075ecae9f99c7a79a38af5f869aa46",
+                "kdf": "pbkdf2",
+                "kdfparams": {
+                    "c": 262144,
+                    "dklen": 32,
+                    "prf": "hmac-sha256",
+                    "salt": "ae3cd4e7013836a3df6bd7241b12db061dbe2c6785853cce422d148a624ce0bd"
+                },
+                "mac": "517ead924a9d0dc3124507e3393d175ce3ff7c1e96529c6c555ce9e51205e9b2"
+            }
+        });
+        let path =
+            std::env::temp_dir().join(format!("gas_saver_keystore_{}.json", std::process::id()));
+        std::fs::write(&path, keystore.to_string()).unwrap();
+        let signer = LocalSigner::from_keystore(&path, "testpassword").unwrap();
+        assert_eq!(
+            hex::encode(signer.key().to_bytes()),
+            "7a28b5ba57c53603b0b07b56bba752f7784bf506fa95edc395f5cf6c7514fe9d"
+        );
+        assert!(matches!(
+            LocalSigner::from_keystore(&path, "wrong"),
+            Err(KeyError::WrongPassword)
+        ));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// A signing service for `key` that fails its first `failures` calls with a
+    /// 503, and returns how many calls it has had.
+    async fn signing_service(key: PrivateKeySigner, failures: usize) -> (String, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                let call = loop {
+                    let n = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some((_, body)) = text.split_once("\r\n\r\n")
+                        && let Ok(call) = serde_json::from_str::<Value>(body)
+                    {
+                        break call;
+                    }
+                };
+                let (status, reply) = if counted.fetch_add(1, Ordering::SeqCst) < failures {
+                    ("503 Service Unavailable", json!({"error": "busy"}))
+                } else {
+                    let hash: B256 = call["hash"].as_str().unwrap().parse().unwrap();
+                    let signature = key.sign_hash_sync(&hash).unwrap();
+                    let signature = hex::encode_prefixed(signature.as_bytes());
+                    ("200 OK", json!({ "signature": signature }))
+                };
+                let reply = reply.to_string();
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    reply.len(),
+                    reply
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+        (url, calls)
+    }
+
+    #[tokio::test]
+    async fn test_remote_signer_retries_and_checks_the_signer() {
+        let address = eip155_key().address().0.0;
+        let (url, calls) = signing_service(eip155_key(), 1).await;
+        let signer = RemoteSigner::new(url, address).with_retries(1, Duration::from_millis(1));
+        let signed = signer.sign_typed(eip155_tx(), 1).await.unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+        // the same signature the key gives locally
+        let local = LocalSigner::new(eip155_key());
+        assert_eq!(signed, local.sign_typed(eip155_tx(), 1).await.unwrap());
+
+        // out of retries
+        let (url, calls) = signing_service(eip155_key(), 2).await;
+        let signer = RemoteSigner::new(url, address).with_retries(1, Duration::from_millis(1));
+        assert_eq!(
+            signer.sign_typed(eip155_tx(), 1).await,
+            Err(SignError::Remote("busy".into()))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        // a service signing with another key is caught
+        let other = PrivateKeySigner::random();
+        let (url, _) = signing_service(other.clone(), 0).await;
+        let signer = RemoteSigner::new(url, address);
+        assert_eq!(
+            signer.sign_typed(eip155_tx(), 1).await,
+            Err(SignError::WrongSigner {
+                expected: address,
+                recovered: other.address().0.0
+            })
+        );
+    }
+}
@@ -0,0 +1,409 @@
+use crate::channel::InstrumentedSender;
+use crate::events::{DecisionRecord, SchedulerDecision};
+use async_trait::async_trait;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tracing::{info, warn};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SinkError {
+    /// The consumer behind the sink has gone away.
+    Closed,
+    /// The sink's own buffer is full.
+    Full,
+    /// Delivery took longer than the scheduler is willing to wait.
+    Timeout,
+    Other(String),
+}
+
+impl std::fmt::Display for SinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SinkError::Closed => write!(f, "sink closed"),
+            SinkError::Full => write!(f, "sink buffer full"),
+            SinkError::Timeout => write!(f, "sink delivery timed out"),
+            SinkError::Other(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl std::error::Error for SinkError {}
+
+/// Somewhere scheduler decisions go: an executor, an audit log, a metrics tap.
+#[async_trait]
+pub trait DecisionSink: Send + Sync {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError>;
+
+    /// What the scheduler calls: the decision with its sequence number and
+    /// correlation id. Sinks with no use for those only implement `deliver`.
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.deliver(record.decision).await
+    }
+
+    /// Short label used in logs.
+    fn name(&self) -> &str {
+        "sink"
+    }
+
+    /// Whether a decision only counts as delivered once this sink has it, as for
+    /// the channel an executor broadcasts from. Wrap a sink in `RequiredSink` to
+    /// set it.
+    fn required(&self) -> bool {
+        false
+    }
+}
+
+/// Forwards decisions into a channel by its backpressure policy: a plain mpsc
+/// sender waits for capacity. A `ChannelSink<DecisionRecord>` forwards whole
+/// records.
+pub struct ChannelSink<T = SchedulerDecision> {
+    tx: InstrumentedSender<T>,
+}
+
+impl<T> ChannelSink<T> {
+    pub fn new(tx: impl Into<InstrumentedSender<T>>) -> Self {
+        Self { tx: tx.into() }
+    }
+}
+
+#[async_trait]
+impl<T> DecisionSink for ChannelSink<T>
+where
+    T: From<SchedulerDecision> + From<DecisionRecord> + Send + 'static,
+{
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.tx
+            .send(decision.into())
+            .await
+            .map_err(|_| SinkError::Closed)
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.tx
+            .send(record.into())
+            .await
+            .map_err(|_| SinkError::Closed)
+    }
+
+    fn name(&self) -> &str {
+        "channel"
+    }
+}
+
+/// Marks `S` as required: the scheduler waits on it without `sink_timeout`,
+/// never detaches it, and keeps a Submit pending if it refused one.
+pub struct RequiredSink<S>(S);
+
+impl<S: DecisionSink> RequiredSink<S> {
+    pub fn new(sink: S) -> Self {
+        Self(sink)
+    }
+}
+
+#[async_trait]
+impl<S: DecisionSink> DecisionSink for RequiredSink<S> {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.0.deliver(decision).await
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.0.deliver_record(record).await
+    }
+
+    fn name(&self) -> &str {
+        self.0.name()
+    }
+
+    fn required(&self) -> bool {
+        true
+    }
+}
+
+/// Logs every decision.
+pub struct TracingSink;
+
+#[async_trait]
+impl DecisionSink for TracingSink {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        info!("DECISION: {}", decision);
+        Ok(())
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        info!("DECISION: {}", record);
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "tracing"
+    }
+}
+
+/// Gives a slow sink its own bounded queue drained by a background task, so the
+/// scheduler only ever pays for a `try_send`. A full queue reports `SinkError::Full`.
+pub struct BufferedSink {
+    tx: mpsc::Sender<DecisionRecord>,
+    name: String,
+}
+
+impl BufferedSink {
+    /// Must be called from within a tokio runtime.
+    pub fn spawn(inner: Box<dyn DecisionSink>, capacity: usize) -> Self {
+        let name = format!("buffered({})", inner.name());
+        let (tx, mut rx) = mpsc::channel(capacity);
+        tokio::spawn(async move {
+            while let Some(record) = rx.recv().await {
+                if let Err(e) = inner.deliver_record(record).await {
+                    warn!("SINK FAILURE: {} failed to deliver ({})", inner.name(), e);
+                }
+            }
+        });
+        Self { tx, name }
+    }
+}
+
+#[async_trait]
+impl DecisionSink for BufferedSink {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.deliver_record(decision.into()).await
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.tx.try_send(record).map_err(|e| match e {
+            TrySendError::Full(_) => SinkError::Full,
+            TrySendError::Closed(_) => SinkError::Closed,
+        })
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// What the scheduler does with a sink whose delivery failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SinkFailurePolicy {
+    /// Log the failure and keep delivering to the sink.
+    #[default]
+    Log,
+    /// Log the failure and stop delivering to the sink.
+    Detach,
+}
+
+struct SinkSlot {
+    sink: Box<dyn DecisionSink>,
+    detached: AtomicBool,
+}
+
+/// Delivers each decision to every attached sink in order. Each delivery to an
+/// optional sink is bounded by `timeout`, so a stuck one delays the others by at
+/// most that much; required sinks are waited on for as long as they take.
+pub struct SinkSet {
+    slots: Vec<SinkSlot>,
+    policy: SinkFailurePolicy,
+    timeout: Duration,
+}
+
+impl SinkSet {
+    pub fn new(
+        sinks: Vec<Box<dyn DecisionSink>>,
+        policy: SinkFailurePolicy,
+        timeout: Duration,
+    ) -> Self {
+        let slots = sinks
+            .into_iter()
+            .map(|sink| SinkSlot {
+                sink,
+                detached: AtomicBool::new(false),
+            })
+            .collect();
+        Self {
+            slots,
+            policy,
+            timeout,
+        }
+    }
+
+    /// Returns whether every required sink took the record, and at least one sink
+    /// did.
+    pub async fn deliver(&self, record: DecisionRecord) -> bool {
+        let (mut delivered, mut required_failed) = (false, false);
+        for slot in &self.slots {
+            if slot.detached.load(Ordering::Relaxed) {
+                continue;
+            }
+
+            let required = slot.sink.required();
+            let result = if required {
+                slot.sink.deliver_record(record.clone()).await
+            } else {
+                match tokio::time::timeout(self.timeout, slot.sink.deliver_record(record.clone()))
+                    .await
+                {
+                    Ok(result) => result,
+                    Err(_) => Err(SinkError::Timeout),
+                }
+            };
+            if let Err(e) = result {
+                warn!(
+                    "SINK FAILURE: {} failed to deliver ({})",
+                    slot.sink.name(),
+                    e
+                );
+                required_failed |= required;
+                if self.policy == SinkFailurePolicy::Detach && !required {
+                    warn!("SINK DETACHED: {}", slot.sink.name());
+                    slot.detached.store(true, Ordering::Relaxed);
+                }
+            } else {
+                delivered = true;
+            }
+        }
+        delivered && !required_failed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::sync::atomic::AtomicUsize;
+
+    struct FailingSink {
+        attempts: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl DecisionSink for FailingSink {
+        async fn deliver(&self, _decision: SchedulerDecision) -> Result<(), SinkError> {
+            self.attempts.fetch_add(1, Ordering::SeqCst);
+            Err(SinkError::Other("disk on fire".to_string()))
+        }
+    }
+
+    struct StuckSink;
+
+    #[async_trait]
+    impl DecisionSink for StuckSink {
+        async fn deliver(&self, _decision: SchedulerDecision) -> Result<(), SinkError> {
+            std::future::pending().await
+        }
+    }
+
+    fn decision(tx_id: u64) -> SchedulerDecision {
+        SchedulerDecision::Drop {
+            tx_id,
+            reason: "test".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failing_sink_does_not_starve_healthy_sink() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = mpsc::channel(10);
+        let sinks: Vec<Box<dyn DecisionSink>> = vec![
+            Box::new(FailingSink {
+                attempts: attempts.clone(),
+            }),
+            Box::new(ChannelSink::new(tx)),
+        ];
+        let set = SinkSet::new(sinks, SinkFailurePolicy::Log, Duration::from_secs(1));
+
+        set.deliver(decision(1).into()).await;
+        set.deliver(decision(2).into()).await;
+
+        assert_eq!(rx.try_recv(), Ok(decision(1)));
+        assert_eq!(rx.try_recv(), Ok(decision(2)));
+        assert_eq!(attempts.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_detach_policy_stops_delivering_to_failed_sink() {
+        let attempts = Arc::new(AtomicUsize::new(0));
+        let (tx, mut rx) = mpsc::channel(10);
+        let sinks: Vec<Box<dyn DecisionSink>> = vec![
+            Box::new(FailingSink {
+                attempts: attempts.clone(),
+            }),
+            Box::new(ChannelSink::new(tx)),
+        ];
+        let set = SinkSet::new(sinks, SinkFailurePolicy::Detach, Duration::from_secs(1));
+
+        assert!(set.deliver(decision(1).into()).await);
+        assert!(set.deliver(decision(2).into()).await);
+
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+        assert_eq!(rx.try_recv(), Ok(decision(1)));
+        assert_eq!(rx.try_recv(), Ok(decision(2)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stuck_sink_is_bounded_by_timeout() {
+        let (tx, mut rx) = mpsc::channel(10);
+        let sinks: Vec<Box<dyn DecisionSink>> =
+            vec![Box::new(StuckSink), Box::new(ChannelSink::new(tx))];
+        let set = SinkSet::new(sinks, SinkFailurePolicy::Log, Duration::from_millis(100));
+
+        let started = tokio::time::Instant::now();
+        set.deliver(decision(1).into()).await;
+        assert_eq!(started.elapsed(), Duration::from_millis(100));
+        assert_eq!(rx.try_recv(), Ok(decision(1)));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_required_sink_is_waited_on_past_the_timeout() {
+        let (executor_tx, mut executor_rx) = mpsc::channel(1);
+        let (audit_tx, mut audit_rx) = mpsc::channel(10);
+        executor_tx.send(decision(0)).await.unwrap();
+        let sinks: Vec<Box<dyn DecisionSink>> = vec![
+            Box::new(RequiredSink::new(ChannelSink::new(executor_tx))),
+            Box::new(ChannelSink::new(audit_tx)),
+        ];
+        let set = Arc::new(SinkSet::new(
+            sinks,
+            SinkFailurePolicy::Detach,
+            Duration::from_millis(100),
+        ));
+
+        let delivery = tokio::spawn({
+            let set = set.clone();
+            async move { set.deliver(decision(1).into()).await }
+        });
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert!(!delivery.is_finished());
+        // the executor catches up and the decision goes through to both
+        assert_eq!(executor_rx.recv().await, Some(decision(0)));
+        assert!(delivery.await.unwrap());
+        assert_eq!(executor_rx.try_recv(), Ok(decision(1)));
+        assert_eq!(audit_rx.try_recv(), Ok(decision(1)));
+    }
+
+    #[tokio::test]
+    async fn test_refused_by_a_required_sink_is_undelivered() {
+        let (executor_tx, executor_rx) = mpsc::channel::<SchedulerDecision>(1);
+        let (audit_tx, mut audit_rx) = mpsc::channel(10);
+        drop(executor_rx);
+        let sinks: Vec<Box<dyn DecisionSink>> = vec![
+            Box::new(RequiredSink::new(ChannelSink::new(executor_tx))),
+            Box::new(ChannelSink::new(audit_tx)),
+        ];
+        let set = SinkSet::new(sinks, SinkFailurePolicy::Detach, Duration::from_secs(1));
+
+        assert!(!set.deliver(decision(1).into()).await);
+        assert!(!set.deliver(decision(2).into()).await);
+        assert_eq!(audit_rx.try_recv(), Ok(decision(1)));
+        assert_eq!(audit_rx.try_recv(), Ok(decision(2)));
+    }
+
+    #[tokio::test]
+    async fn test_buffered_sink_reports_full() {
+        let sink = BufferedSink::spawn(Box::new(StuckSink), 1);
+        // the worker takes the first decision and gets stuck on it
+        assert_eq!(sink.deliver(decision(1)).await, Ok(()));
+        tokio::task::yield_now().await;
+        assert_eq!(sink.deliver(decision(2)).await, Ok(()));
+        assert_eq!(sink.deliver(decision(3)).await, Err(SinkError::Full));
+    }
+}
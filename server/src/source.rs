@@ -0,0 +1,294 @@
+use crate::events::GasEvent;
+use async_trait::async_trait;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::{Interval, MissedTickBehavior};
+
+const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// Anything that can feed gas events to the scheduler.
+///
+/// `next` is polled inside `tokio::select!`, so implementations must be
+/// cancel-safe: dropping an unfinished `next` must not lose an event.
+#[async_trait]
+pub trait GasEventSource: Send {
+    /// Next event, or None once the source is exhausted.
+    async fn next(&mut self) -> Option<GasEvent>;
+}
+
+/// Events pushed by hand through an mpsc channel.
+pub struct ChannelSource {
+    rx: mpsc::Receiver<GasEvent>,
+}
+
+impl ChannelSource {
+    pub fn new(rx: mpsc::Receiver<GasEvent>) -> Self {
+        Self { rx }
+    }
+}
+
+#[async_trait]
+impl GasEventSource for ChannelSource {
+    async fn next(&mut self) -> Option<GasEvent> {
+        self.rx.recv().await
+    }
+}
+
+/// Shape of the base fee produced by a `SyntheticSource`, one value per block.
+#[derive(Debug, Clone, PartialEq)]
+pub enum FeePattern {
+    Flat {
+        base_fee: u64,
+    },
+    /// Starts at `start` and moves by `step` every block, never below 1.
+    Ramp {
+        start: u64,
+        step: i64,
+    },
+    /// Sits at `base_fee`, jumps to `peak` at block `at_block` for `blocks` blocks.
+    Spike {
+        base_fee: u64,
+        peak: u64,
+        at_block: u64,
+        blocks: u64,
+    },
+    /// Moves by a uniformly random amount in `[-max_step, max_step]` each block.
+    RandomWalk {
+        start: u64,
+        max_step: u64,
+        seed: u64,
+    },
+}
+
+/// Generates `NewBlock` events from a `FeePattern` at a fixed block cadence on
+/// tokio's clock, so it runs in real time normally and instantly under paused time.
+pub struct SyntheticSource {
+    blocks: SyntheticBlocks,
+    interval: Interval,
+    max_blocks: Option<u64>,
+}
+
+impl SyntheticSource {
+    pub fn new(pattern: FeePattern, block_time: Duration) -> Self {
+        let mut interval = tokio::time::interval(block_time);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        Self {
+            blocks: SyntheticBlocks::new(pattern),
+            interval,
+            max_blocks: None,
+        }
+    }
+
+    /// Stop after `blocks` events.
+    pub fn with_max_blocks(mut self, blocks: u64) -> Self {
+        self.max_blocks = Some(blocks);
+        self
+    }
+
+    pub fn with_first_block(mut self, number: u64) -> Self {
+        self.blocks = self.blocks.with_first_block(number);
+        self
+    }
+}
+
+#[async_trait]
+impl GasEventSource for SyntheticSource {
+    async fn next(&mut self) -> Option<GasEvent> {
+        if self
+            .max_blocks
+            .is_some_and(|max| self.blocks.emitted >= max)
+        {
+            return None;
+        }
+        self.interval.tick().await;
+        self.blocks.next()
+    }
+}
+
+/// The endless run of `NewBlock` events a `FeePattern` describes, off the clock;
+/// what a `SyntheticSource` emits, one per tick.
+pub(crate) struct SyntheticBlocks {
+    pattern: FeePattern,
+    /// Offset of the emitted block numbers.
+    first_block: u64,
+    /// Blocks emitted so far.
+    emitted: u64,
+    last_fee: Option<u64>,
+    rng: SplitMix64,
+}
+
+impl SyntheticBlocks {
+    pub(crate) fn new(pattern: FeePattern) -> Self {
+        let seed = match pattern {
+            FeePattern::RandomWalk { seed, .. } => seed,
+            _ => 0,
+        };
+        Self {
+            pattern,
+            first_block: 1,
+            emitted: 0,
+            last_fee: None,
+            rng: SplitMix64(seed),
+        }
+    }
+
+    pub(crate) fn with_first_block(mut self, number: u64) -> Self {
+        self.first_block = number;
+        self
+    }
+
+    fn fee_at(&mut self, index: u64) -> u64 {
+        match self.pattern {
+            FeePattern::Flat { base_fee } => base_fee,
+            FeePattern::Ramp { start, step } => (start as i64 + step * index as i64).max(1) as u64,
+            FeePattern::Spike {
+                base_fee,
+                peak,
+                at_block,
+                blocks,
+            } => {
+                if index >= at_block && index < at_block + blocks {
+                    peak
+                } else {
+                    base_fee
+                }
+            }
+            FeePattern::RandomWalk {
+                start, max_step, ..
+            } => match self.last_fee {
+                None => start,
+                Some(last) => {
+                    let span = max_step * 2 + 1;
+                    let step = (self.rng.next() % span) as i64 - max_step as i64;
+                    (last as i64 + step).max(1) as u64
+                }
+            },
+        }
+    }
+}
+
+impl Iterator for SyntheticBlocks {
+    type Item = GasEvent;
+
+    fn next(&mut self) -> Option<GasEvent> {
+        let base_fee = self.fee_at(self.emitted);
+        // EIP-1559 raises the fee after full blocks and lowers it after empty ones
+        let gas_used = match self.last_fee {
+            Some(last) if base_fee > last => BLOCK_GAS_LIMIT,
+            Some(last) if base_fee < last => BLOCK_GAS_LIMIT / 4,
+            _ => BLOCK_GAS_LIMIT / 2,
+        };
+        let number = self.first_block + self.emitted;
+        let event = GasEvent::NewBlock {
+            number,
+            base_fee,
+            gas_used,
+            gas_limit: BLOCK_GAS_LIMIT,
+            block_hash: synthetic_hash(number),
+            parent_hash: synthetic_hash(number.saturating_sub(1)),
+        };
+        self.last_fee = Some(base_fee);
+        self.emitted += 1;
+        Some(event)
+    }
+}
+
+/// Stand-in hash for a block on the single synthetic chain; never all zeros.
+pub(crate) fn synthetic_hash(number: u64) -> [u8; 32] {
+    let mut hash = [0xEE; 32];
+    hash[24..].copy_from_slice(&number.to_be_bytes());
+    hash
+}
+
+/// Small, dependency-free PRNG; the same seed yields the same sequence everywhere.
+struct SplitMix64(u64);
+
+impl SplitMix64 {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn fees(mut source: SyntheticSource) -> Vec<u64> {
+        let mut fees = Vec::new();
+        while let Some(event) = source.next().await {
+            if let GasEvent::NewBlock { base_fee, .. } = event {
+                fees.push(base_fee);
+            }
+        }
+        fees
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_patterns() {
+        let block = Duration::from_secs(12);
+        let flat = SyntheticSource::new(FeePattern::Flat { base_fee: 30 }, block);
+        assert_eq!(fees(flat.with_max_blocks(3)).await, vec![30, 30, 30]);
+
+        let ramp = SyntheticSource::new(
+            FeePattern::Ramp {
+                start: 20,
+                step: -8,
+            },
+            block,
+        );
+        assert_eq!(fees(ramp.with_max_blocks(4)).await, vec![20, 12, 4, 1]);
+
+        let spike = SyntheticSource::new(
+            FeePattern::Spike {
+                base_fee: 50,
+                peak: 200,
+                at_block: 1,
+                blocks: 2,
+            },
+            block,
+        );
+        assert_eq!(fees(spike.with_max_blocks(4)).await, vec![50, 200, 200, 50]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_block_cadence_and_numbering() {
+        let started = tokio::time::Instant::now();
+        let mut source =
+            SyntheticSource::new(FeePattern::Flat { base_fee: 30 }, Duration::from_secs(12))
+                .with_first_block(100)
+                .with_max_blocks(3);
+
+        let mut numbers = Vec::new();
+        while let Some(GasEvent::NewBlock { number, .. }) = source.next().await {
+            numbers.push(number);
+        }
+        assert_eq!(numbers, vec![100, 101, 102]);
+        // first tick is immediate
+        assert_eq!(started.elapsed(), Duration::from_secs(24));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_random_walk_is_seeded() {
+        let walk = |seed| {
+            SyntheticSource::new(
+                FeePattern::RandomWalk {
+                    start: 100,
+                    max_step: 10,
+                    seed,
+                },
+                Duration::from_secs(1),
+            )
+            .with_max_blocks(50)
+        };
+        let a = fees(walk(42)).await;
+        let b = fees(walk(42)).await;
+        let c = fees(walk(7)).await;
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert!(a.windows(2).all(|w| w[0].abs_diff(w[1]) <= 10));
+    }
+}
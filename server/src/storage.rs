@@ -0,0 +1,520 @@
+use crate::events::{DecisionRecord, SchedulerDecision, TransactionRequest};
+use crate::scheduler::Submission;
+use crate::sink::{DecisionSink, SinkError};
+use crate::units::Wei;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use rusqlite::{Connection, OptionalExtension, params};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::sync::{mpsc, oneshot};
+use tracing::warn;
+
+/// Writes the writer thread commits together, at most.
+const BATCH: usize = 256;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS requests (
+    tx_id INTEGER PRIMARY KEY,
+    payload TEXT NOT NULL,
+    received_at_unix_ms INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS decisions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    seq INTEGER NOT NULL,
+    tx_id INTEGER,
+    kind TEXT NOT NULL,
+    payload TEXT NOT NULL,
+    decided_at_unix_ms INTEGER NOT NULL
+);
+CREATE INDEX IF NOT EXISTS decisions_by_tx ON decisions (tx_id, id);
+CREATE TABLE IF NOT EXISTS outcomes (
+    tx_id INTEGER PRIMARY KEY,
+    block_number INTEGER NOT NULL,
+    effective_gas_price_wei TEXT NOT NULL,
+    realized_cost_wei TEXT NOT NULL,
+    savings_vs_max_fee_wei TEXT NOT NULL,
+    savings_vs_acceptance_wei TEXT NOT NULL,
+    confirmed_at_unix_ms INTEGER NOT NULL
+);
+";
+
+#[derive(Debug)]
+pub enum StorageError {
+    Sqlite(rusqlite::Error),
+    /// A stored payload that no longer decodes, e.g. written by a newer version.
+    Payload(serde_json::Error),
+}
+
+impl std::fmt::Display for StorageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StorageError::Sqlite(e) => write!(f, "sqlite: {}", e),
+            StorageError::Payload(e) => write!(f, "bad stored payload: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+impl From<rusqlite::Error> for StorageError {
+    fn from(e: rusqlite::Error) -> Self {
+        StorageError::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for StorageError {
+    fn from(e: serde_json::Error) -> Self {
+        StorageError::Payload(e)
+    }
+}
+
+/// A request as it was received.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoredRequest {
+    pub request: TransactionRequest,
+    pub received_at_unix_ms: u64,
+}
+
+/// What a confirmed tx cost, from its `Confirmed` decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Outcome {
+    pub block_number: u64,
+    pub effective_gas_price: Wei,
+    pub realized_cost_wei: Wei,
+    pub savings_vs_max_fee_wei: Wei,
+    pub savings_vs_acceptance_wei: Wei,
+    pub confirmed_at_unix_ms: u64,
+}
+
+/// Everything stored about one tx.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TxHistory {
+    pub request: Option<StoredRequest>,
+    /// Oldest first.
+    pub decisions: Vec<DecisionRecord>,
+    pub outcome: Option<Outcome>,
+}
+
+/// Requests, decisions and outcomes in a SQLite database. Queries run on the
+/// caller's thread; writes from a `StorageSink` go through its writer thread.
+#[derive(Clone)]
+pub struct Storage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Storage {
+    /// Opens or creates the database at `path`, adding any missing tables.
+    pub fn open(path: impl AsRef<Path>) -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    /// A database that lives as long as this `Storage` and its clones.
+    pub fn open_in_memory() -> Result<Self, StorageError> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, StorageError> {
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// A sink writing to this database from a thread of its own, through a
+    /// queue of `capacity` writes. The thread ends once every clone of the sink
+    /// is dropped and the queue is drained.
+    pub fn sink(&self, capacity: usize) -> StorageSink {
+        let (tx, rx) = mpsc::channel(capacity);
+        let storage = self.clone();
+        std::thread::Builder::new()
+            .name("storage-writer".into())
+            .spawn(move || storage.write_queued(rx))
+            .expect("failed to spawn the storage writer");
+        StorageSink { tx }
+    }
+
+    fn write_queued(&self, mut rx: mpsc::Receiver<Write>) {
+        while let Some(first) = rx.blocking_recv() {
+            let mut batch = vec![first];
+            while batch.len() < BATCH {
+                match rx.try_recv() {
+                    Ok(write) => batch.push(write),
+                    Err(_) => break,
+                }
+            }
+            let mut flushed = Vec::new();
+            let writes: Vec<Write> = batch
+                .into_iter()
+                .filter_map(|write| match write {
+                    Write::Flush(done) => {
+                        flushed.push(done);
+                        None
+                    }
+                    write => Some(write),
+                })
+                .collect();
+            if let Err(e) = self.write_batch(&writes) {
+                warn!("lost {} storage writes: {}", writes.len(), e);
+            }
+            for done in flushed {
+                let _ = done.send(());
+            }
+        }
+    }
+
+    /// All of `writes` in one transaction.
+    fn write_batch(&self, writes: &[Write]) -> Result<(), StorageError> {
+        let mut conn = self.conn.lock();
+        let tx = conn.transaction()?;
+        for write in writes {
+            match write {
+                Write::Request {
+                    req,
+                    received_at_unix_ms,
+                } => {
+                    tx.execute(
+                        "INSERT OR REPLACE INTO requests (tx_id, payload, received_at_unix_ms)
+                         VALUES (?1, ?2, ?3)",
+                        params![req.id, serde_json::to_string(req)?, received_at_unix_ms],
+                    )?;
+                }
+                Write::Decision(record) => {
+                    let decided_at = record.meta.decided_at_unix_ms;
+                    tx.execute(
+                        "INSERT INTO decisions (seq, tx_id, kind, payload, decided_at_unix_ms)
+                         VALUES (?1, ?2, ?3, ?4, ?5)",
+                        params![
+                            record.seq,
+                            record.decision.tx_id(),
+                            record.decision.kind(),
+                            serde_json::to_string(record)?,
+                            decided_at
+                        ],
+                    )?;
+                    if let SchedulerDecision::Confirmed {
+                        tx_id,
+                        block_number,
+                        effective_gas_price,
+                        realized_cost_wei,
+                        savings_vs_max_fee_wei,
+                        savings_vs_acceptance_wei,
+                    } = &record.decision
+                    {
+                        tx.execute(
+                            "INSERT OR REPLACE INTO outcomes (tx_id, block_number,
+                                 effective_gas_price_wei, realized_cost_wei,
+                                 savings_vs_max_fee_wei, savings_vs_acceptance_wei,
+                                 confirmed_at_unix_ms)
+                             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                            params![
+                                tx_id,
+                                block_number,
+                                effective_gas_price.0.to_string(),
+                                realized_cost_wei.0.to_string(),
+                                savings_vs_max_fee_wei.0.to_string(),
+                                savings_vs_acceptance_wei.0.to_string(),
+                                decided_at
+                            ],
+                        )?;
+                    }
+                }
+                Write::Flush(_) => {}
+            }
+        }
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// The last `limit` decisions stored, newest first.
+    pub fn recent_decisions(&self, limit: usize) -> Result<Vec<DecisionRecord>, StorageError> {
+        let conn = self.conn.lock();
+        let mut stmt = conn.prepare("SELECT payload FROM decisions ORDER BY id DESC LIMIT ?1")?;
+        let payloads = stmt.query_map([limit as u64], |row| row.get::<_, String>(0))?;
+        payloads
+            .map(|payload| Ok(serde_json::from_str(&payload?)?))
+            .collect()
+    }
+
+    /// `tx_id`'s request, its decisions and its outcome, as far as stored.
+    pub fn tx_history(&self, tx_id: u64) -> Result<TxHistory, StorageError> {
+        let conn = self.conn.lock();
+        let request = conn
+            .query_row(
+                "SELECT payload, received_at_unix_ms FROM requests WHERE tx_id = ?1",
+                [tx_id],
+                |row| Ok((row.get::<_, String>(0)?, row.get(1)?)),
+            )
+            .optional()?
+            .map(|(payload, received_at_unix_ms)| {
+                Ok::<_, StorageError>(StoredRequest {
+                    request: serde_json::from_str(&payload)?,
+                    received_at_unix_ms,
+                })
+            })
+            .transpose()?;
+        let mut stmt =
+            conn.prepare("SELECT payload FROM decisions WHERE tx_id = ?1 ORDER BY id")?;
+        let decisions = stmt
+            .query_map([tx_id], |row| row.get::<_, String>(0))?
+            .map(|payload| Ok(serde_json::from_str(&payload?)?))
+            .collect::<Result<_, StorageError>>()?;
+        let outcome = conn
+            .query_row(
+                "SELECT block_number, effective_gas_price_wei, realized_cost_wei,
+                     savings_vs_max_fee_wei, savings_vs_acceptance_wei, confirmed_at_unix_ms
+                 FROM outcomes WHERE tx_id = ?1",
+                [tx_id],
+                |row| {
+                    let wei = |i| {
+                        row.get::<_, String>(i)?.parse().map(Wei).map_err(|e| {
+                            rusqlite::Error::FromSqlConversionFailure(
+                                i,
+                                rusqlite::types::Type::Text,
+                                Box::new(e),
+                            )
+                        })
+                    };
+                    Ok(Outcome {
+                        block_number: row.get(0)?,
+                        effective_gas_price: wei(1)?,
+                        realized_cost_wei: wei(2)?,
+                        savings_vs_max_fee_wei: wei(3)?,
+                        savings_vs_acceptance_wei: wei(4)?,
+                        confirmed_at_unix_ms: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+        Ok(TxHistory {
+            request,
+            decisions,
+            outcome,
+        })
+    }
+}
+
+enum Write {
+    Request {
+        req: Box<TransactionRequest>,
+        received_at_unix_ms: u64,
+    },
+    Decision(Box<DecisionRecord>),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Queues the scheduler's decisions for `Storage`'s writer thread. Like
+/// `BufferedSink`, delivery never waits: a full queue reports `SinkError::Full`
+/// and the record is not stored.
+#[derive(Clone)]
+pub struct StorageSink {
+    tx: mpsc::Sender<Write>,
+}
+
+impl StorageSink {
+    /// Queues `req`, stamped with the current time.
+    pub fn record_request(&self, req: &TransactionRequest) -> Result<(), SinkError> {
+        let received_at_unix_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        self.queue(Write::Request {
+            req: Box::new(req.clone()),
+            received_at_unix_ms,
+        })
+    }
+
+    /// Records every request on its way from `submissions` to the scheduler; pass
+    /// the returned receiver to `Scheduler::run` in place of `submissions`.
+    pub fn track_submissions(
+        &self,
+        mut submissions: mpsc::Receiver<Submission>,
+    ) -> mpsc::Receiver<Submission> {
+        let (forward, forwarded) = mpsc::channel(submissions.max_capacity());
+        let sink = self.clone();
+        tokio::spawn(async move {
+            while let Some(submission) = submissions.recv().await {
+                if let Err(e) = sink.record_request(&submission.req) {
+                    warn!("request {} not stored: {}", submission.req.id, e);
+                }
+                if forward.send(submission).await.is_err() {
+                    return;
+                }
+            }
+        });
+        forwarded
+    }
+
+    /// Waits until everything queued so far is written, or failed to be.
+    pub async fn flush(&self) -> Result<(), SinkError> {
+        let (done, flushed) = oneshot::channel();
+        self.tx
+            .send(Write::Flush(done))
+            .await
+            .map_err(|_| SinkError::Closed)?;
+        flushed.await.map_err(|_| SinkError::Closed)
+    }
+
+    fn queue(&self, write: Write) -> Result<(), SinkError> {
+        self.tx.try_send(write).map_err(|e| match e {
+            TrySendError::Full(_) => SinkError::Full,
+            TrySendError::Closed(_) => SinkError::Closed,
+        })
+    }
+}
+
+#[async_trait]
+impl DecisionSink for StorageSink {
+    async fn deliver(&self, decision: SchedulerDecision) -> Result<(), SinkError> {
+        self.deliver_record(decision.into()).await
+    }
+
+    async fn deliver_record(&self, record: DecisionRecord) -> Result<(), SinkError> {
+        self.queue(Write::Decision(Box::new(record)))
+    }
+
+    fn name(&self) -> &str {
+        "sqlite"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{
+        FeeMode, GasEvent, SchedulerCommand, SubmissionPrivacy, TxStatus, Urgency,
+    };
+    use crate::limiter::RateLimiter;
+    use crate::model::GasModel;
+    use crate::nonce::{NonceAllocator, NonceManager};
+    use crate::scheduler::{Scheduler, SchedulerConfig, SchedulerHandle};
+    use alloy_primitives::Address;
+
+    fn request(id: u64) -> TransactionRequest {
+        TransactionRequest {
+            id,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            value: [0; 32],
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: None,
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_of_a_tx_is_queryable() {
+        let storage = Storage::open_in_memory().unwrap();
+        let sink = storage.sink(100);
+        let (event_tx, event_rx) = mpsc::channel(10);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(10);
+        let req_rx = sink.track_submissions(req_rx);
+        let nonce_manager = NonceManager::new();
+        nonce_manager.update_nonce(1, Address::repeat_byte(0xAA), 0);
+        let scheduler = Arc::new(Scheduler::new(
+            SchedulerConfig::default(),
+            Arc::new(GasModel::new(10)),
+            Arc::new(nonce_manager),
+            Arc::new(RateLimiter::new(100, 100)),
+            vec![Box::new(sink.clone())],
+        ));
+        let scheduler_task = tokio::spawn(scheduler.run(event_rx, req_rx, cmd_rx));
+
+        let event = GasEvent::BaseFeeUpdate {
+            base_fee: 40,
+            timestamp: 0,
+        };
+        event_tx.send(event).await.unwrap();
+        let mut statuses = handle.submit_with_status(request(1)).await.unwrap();
+        // commands and events go ahead of each other, so each waits on the last
+        let mut wait_for = async |wanted: fn(&TxStatus) -> bool| {
+            while !wanted(&statuses.recv().await.unwrap()) {}
+        };
+        wait_for(|status| matches!(status, TxStatus::Submitted { .. })).await;
+        let tx_hash = [0x11; 32];
+        let cmd = SchedulerCommand::Broadcast { tx_id: 1, tx_hash };
+        handle.command(cmd).await.unwrap();
+        wait_for(|status| matches!(status, TxStatus::Broadcast { .. })).await;
+        let confirmed = GasEvent::TxConfirmed {
+            tx_hash,
+            block_number: 7,
+            effective_gas_price: 44,
+            gas_used: 21_000,
+        };
+        event_tx.send(confirmed).await.unwrap();
+        wait_for(|status| matches!(status, TxStatus::Confirmed { .. })).await;
+        drop((event_tx, handle));
+        scheduler_task.await.unwrap();
+        sink.flush().await.unwrap();
+
+        let history = storage.tx_history(1).unwrap();
+        let stored = history.request.unwrap();
+        assert_eq!(stored.request, request(1));
+        assert!(stored.received_at_unix_ms > 0);
+        let kinds: Vec<_> = history
+            .decisions
+            .iter()
+            .map(|record| record.decision.kind())
+            .collect();
+        assert_eq!(kinds, ["submit", "confirmed"]);
+        assert!(
+            history
+                .decisions
+                .windows(2)
+                .all(|pair| pair[0].seq < pair[1].seq)
+        );
+        let outcome = history.outcome.unwrap();
+        assert_eq!(outcome.block_number, 7);
+        assert_eq!(outcome.effective_gas_price, Wei(44));
+        assert_eq!(outcome.realized_cost_wei, Wei(44 * 21_000));
+        assert!(matches!(
+            history.decisions[1].decision,
+            SchedulerDecision::Confirmed {
+                savings_vs_max_fee_wei,
+                savings_vs_acceptance_wei,
+                ..
+            } if savings_vs_max_fee_wei == outcome.savings_vs_max_fee_wei
+                && savings_vs_acceptance_wei == outcome.savings_vs_acceptance_wei
+        ));
+
+        let recent = storage.recent_decisions(1).unwrap();
+        assert_eq!(recent, history.decisions[1..]);
+        assert_eq!(storage.tx_history(2).unwrap().decisions, []);
+    }
+
+    #[tokio::test]
+    async fn test_file_outlives_the_sink() {
+        let path = std::env::temp_dir().join(format!("gas_saver_{}.sqlite", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let sink = Storage::open(&path).unwrap().sink(10);
+        sink.record_request(&request(3)).unwrap();
+        let decision = SchedulerDecision::Drop {
+            tx_id: 3,
+            reason: "cancelled".into(),
+        };
+        sink.deliver(decision.clone()).await.unwrap();
+        sink.flush().await.unwrap();
+        drop(sink);
+
+        let history = Storage::open(&path).unwrap().tx_history(3).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(history.request.unwrap().request, request(3));
+        assert_eq!(history.decisions.len(), 1);
+        assert_eq!(history.decisions[0].decision, decision);
+        assert_eq!(history.outcome, None);
+    }
+}
@@ -0,0 +1,530 @@
+use crate::events::{
+    DecisionRecord, FeeMode, GasEvent, SchedulerCommand, SchedulerDecision, SubmissionPrivacy,
+    TransactionRequest, Urgency,
+};
+use crate::limiter::{Limiter, NoopLimiter, RateLimiter, RateLimiterStats};
+use crate::model::GasModel;
+use crate::nonce::{NonceAllocator, NonceManager, NonceSnapshot};
+use crate::scheduler::{Scheduler, SchedulerConfig, SchedulerHandle, SchedulerSnapshot};
+use crate::sink::ChannelSink;
+use crate::source::{FeePattern, SyntheticBlocks, synthetic_hash};
+use crate::units::{Gwei, Wei};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::Instant;
+
+/// Where a scenario's scheduler clock starts unless told otherwise: 2023-11-14,
+/// fixed so that deadlines and `decided_at_unix_ms` come out the same every run.
+pub const DEFAULT_START_UNIX_MS: u64 = 1_700_000_000_000;
+const BLOCK_GAS_LIMIT: u64 = 30_000_000;
+
+/// Something the executor or the chain tells the scheduler about a tx, for
+/// `ScenarioBuilder::report`. The tx goes by `tx_hash(tx_id)` throughout.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ExecutionReport {
+    Broadcast,
+    BroadcastFailed {
+        reason: String,
+    },
+    Confirmed {
+        block_number: u64,
+        effective_gas_price: u64,
+        gas_used: u64,
+    },
+    Failed {
+        block_number: u64,
+    },
+    Dropped {
+        reason: String,
+    },
+}
+
+/// The hash a scenario's tx `tx_id` is broadcast and reported under.
+pub fn tx_hash(tx_id: u64) -> [u8; 32] {
+    let mut hash = [0xCC; 32];
+    hash[..8].copy_from_slice(&tx_id.to_be_bytes());
+    hash
+}
+
+/// An input due at some point of a scenario.
+#[derive(Debug, Clone)]
+enum Step {
+    Event(GasEvent),
+    Request(Box<TransactionRequest>),
+    Command(SchedulerCommand),
+}
+
+/// Scripts a run of the scheduler: gas events, requests, commands and execution
+/// reports at offsets from the start, played on tokio's paused clock so that a
+/// scenario takes no real time and gives the same decisions every run.
+///
+/// Inputs are played in offset order, ties in the order they were added. Each
+/// goes a millisecond after the one before at the least, so that the scheduler
+/// has handled it before the next arrives.
+pub struct ScenarioBuilder {
+    config: SchedulerConfig,
+    model_window: usize,
+    rate_limit: Option<(u64, u64)>,
+    nonce_manager: NonceManager,
+    start_unix_ms: u64,
+    end: Duration,
+    steps: Vec<(Duration, Step)>,
+}
+
+impl ScenarioBuilder {
+    /// A scheduler with the default config, a 100-block model, no limiter and
+    /// no senders.
+    pub fn new() -> Self {
+        Self {
+            config: SchedulerConfig::default(),
+            model_window: 100,
+            rate_limit: None,
+            nonce_manager: NonceManager::new(),
+            start_unix_ms: DEFAULT_START_UNIX_MS,
+            end: Duration::ZERO,
+            steps: Vec::new(),
+        }
+    }
+
+    pub fn with_config(mut self, config: SchedulerConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Blocks of base fee history the model keeps.
+    pub fn with_model_window(mut self, blocks: usize) -> Self {
+        self.model_window = blocks;
+        self
+    }
+
+    /// Share a token bucket of `burst` refilling at `rate` per second between
+    /// all submissions. It is made when the scenario starts, on its clock.
+    pub fn with_rate_limit(mut self, rate: u64, burst: u64) -> Self {
+        self.rate_limit = Some((rate, burst));
+        self
+    }
+
+    pub fn with_nonce_manager(mut self, nonce_manager: NonceManager) -> Self {
+        self.nonce_manager = nonce_manager;
+        self
+    }
+
+    /// Start `address` on `chain_id` at `nonce`, as its first
+    /// `SchedulerCommand::InitNonce` would.
+    pub fn with_sender(self, chain_id: u64, address: [u8; 20], nonce: u64) -> Self {
+        self.nonce_manager
+            .update_nonce(chain_id, address.into(), nonce);
+        self
+    }
+
+    /// Start the scheduler's clock at `unix_ms` rather than
+    /// `DEFAULT_START_UNIX_MS`.
+    pub fn with_start_time(mut self, unix_ms: u64) -> Self {
+        self.start_unix_ms = unix_ms;
+        self
+    }
+
+    /// Keep the scheduler running until `at` even after the last input, e.g.
+    /// for its sweeps to expire requests.
+    pub fn until(mut self, at: Duration) -> Self {
+        self.end = self.end.max(at);
+        self
+    }
+
+    pub fn event(mut self, at: Duration, event: GasEvent) -> Self {
+        self.steps.push((at, Step::Event(event)));
+        self
+    }
+
+    /// A half-full block `number` at `base_fee` wei.
+    pub fn block(self, at: Duration, number: u64, base_fee: u64) -> Self {
+        self.event(
+            at,
+            GasEvent::NewBlock {
+                number,
+                base_fee,
+                gas_used: BLOCK_GAS_LIMIT / 2,
+                gas_limit: BLOCK_GAS_LIMIT,
+                block_hash: synthetic_hash(number),
+                parent_hash: synthetic_hash(number.saturating_sub(1)),
+            },
+        )
+    }
+
+    /// `count` blocks from 1, `block_time` apart from `at`, with the base fees
+    /// of `pattern`; the blocks a `SyntheticSource` would send.
+    pub fn blocks(
+        self,
+        at: Duration,
+        block_time: Duration,
+        pattern: FeePattern,
+        count: u64,
+    ) -> Self {
+        SyntheticBlocks::new(pattern)
+            .take(count as usize)
+            .enumerate()
+            .fold(self, |builder, (n, event)| {
+                builder.event(at + block_time * n as u32, event)
+            })
+    }
+
+    pub fn request(mut self, at: Duration, request: TransactionRequest) -> Self {
+        self.steps.push((at, Step::Request(Box::new(request))));
+        self
+    }
+
+    pub fn command(mut self, at: Duration, command: SchedulerCommand) -> Self {
+        self.steps.push((at, Step::Command(command)));
+        self
+    }
+
+    /// What came of tx `tx_id`, under `tx_hash(tx_id)`.
+    pub fn report(self, at: Duration, tx_id: u64, report: ExecutionReport) -> Self {
+        let tx_hash = tx_hash(tx_id);
+        match report {
+            ExecutionReport::Broadcast => {
+                self.command(at, SchedulerCommand::Broadcast { tx_id, tx_hash })
+            }
+            ExecutionReport::BroadcastFailed { reason } => {
+                self.command(at, SchedulerCommand::BroadcastFailed { tx_id, reason })
+            }
+            ExecutionReport::Confirmed {
+                block_number,
+                effective_gas_price,
+                gas_used,
+            } => self.event(
+                at,
+                GasEvent::TxConfirmed {
+                    tx_hash,
+                    block_number,
+                    effective_gas_price,
+                    gas_used,
+                },
+            ),
+            ExecutionReport::Failed { block_number } => self.event(
+                at,
+                GasEvent::TxFailed {
+                    tx_hash,
+                    block_number,
+                },
+            ),
+            ExecutionReport::Dropped { reason } => {
+                self.event(at, GasEvent::TxDropped { tx_hash, reason })
+            }
+        }
+    }
+
+    /// Plays the scenario and returns what the scheduler decided and where it
+    /// left off. Pauses tokio's clock for the run and resumes it after, so call
+    /// it from a current-thread runtime whose clock isn't paused, as plain
+    /// `#[tokio::test]`s have.
+    pub async fn run(mut self) -> ScenarioOutcome {
+        tokio::time::pause();
+        let limiter: Arc<dyn Limiter> = match self.rate_limit {
+            Some((rate, burst)) => Arc::new(RateLimiter::new(rate, burst)),
+            None => Arc::new(NoopLimiter::new()),
+        };
+        let nonce_manager = Arc::new(self.nonce_manager);
+        let (decision_tx, mut decision_rx) = mpsc::channel::<DecisionRecord>(256);
+        let scheduler = Arc::new(
+            Scheduler::new(
+                self.config,
+                Arc::new(GasModel::new(self.model_window)),
+                nonce_manager.clone(),
+                limiter,
+                vec![Box::new(ChannelSink::new(decision_tx))],
+            )
+            .with_start_time(self.start_unix_ms),
+        );
+        let (event_tx, event_rx) = mpsc::channel(16);
+        let (handle, req_rx, cmd_rx) = SchedulerHandle::channel(16);
+        let task = tokio::spawn(scheduler.clone().run(event_rx, req_rx, cmd_rx));
+        let recorder = tokio::spawn(async move {
+            let mut records = Vec::new();
+            while let Some(record) = decision_rx.recv().await {
+                records.push(record);
+            }
+            records
+        });
+
+        // stable, so ties keep the order they were added in
+        self.steps.sort_by_key(|(at, _)| *at);
+        let start = Instant::now();
+        let mut last_due = None;
+        for (at, step) in self.steps {
+            let due = match last_due {
+                Some(last) if at <= last => last + Duration::from_millis(1),
+                _ => at,
+            };
+            last_due = Some(due);
+            tokio::time::sleep_until(start + due).await;
+            // can't fail: the scheduler runs until its inputs close
+            match step {
+                Step::Event(event) => {
+                    let _ = event_tx.send(event).await;
+                }
+                Step::Request(request) => {
+                    let _ = handle.submit_waiting(*request).await;
+                }
+                Step::Command(command) => {
+                    let _ = handle.command(command).await;
+                }
+            }
+        }
+        tokio::time::sleep_until(start + self.end).await;
+
+        drop((event_tx, handle));
+        let snapshot = match task.await {
+            Ok(snapshot) => snapshot,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        let limiter = scheduler.limiter_stats();
+        // the sink goes with the scheduler, which ends the recording
+        drop(scheduler);
+        let records = match recorder.await {
+            Ok(records) => records,
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        };
+        tokio::time::resume();
+        ScenarioOutcome {
+            records,
+            snapshot,
+            nonces: nonce_manager.export(),
+            limiter,
+        }
+    }
+}
+
+impl Default for ScenarioBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// What a scenario's scheduler decided, in order, and the state it ended in.
+#[derive(Debug, Clone)]
+pub struct ScenarioOutcome {
+    pub records: Vec<DecisionRecord>,
+    /// The requests still pending and the txs still submitted.
+    pub snapshot: SchedulerSnapshot,
+    pub nonces: NonceSnapshot,
+    pub limiter: RateLimiterStats,
+}
+
+impl ScenarioOutcome {
+    pub fn decisions(&self) -> impl Iterator<Item = &SchedulerDecision> {
+        self.records.iter().map(|record| &record.decision)
+    }
+
+    /// The decisions about tx `tx_id`, in order.
+    pub fn decisions_for(&self, tx_id: u64) -> Vec<&SchedulerDecision> {
+        self.decisions()
+            .filter(|decision| decision.tx_id() == Some(tx_id))
+            .collect()
+    }
+
+    /// The kind of each decision about tx `tx_id`, e.g. `["defer", "submit"]`.
+    pub fn kinds_for(&self, tx_id: u64) -> Vec<&'static str> {
+        self.decisions_for(tx_id)
+            .into_iter()
+            .map(SchedulerDecision::kind)
+            .collect()
+    }
+
+    /// The txs submitted, in the order of their `Submit`s.
+    pub fn submitted_ids(&self) -> Vec<u64> {
+        self.decisions()
+            .filter_map(|decision| match decision {
+                SchedulerDecision::Submit { tx_id, .. } => Some(*tx_id),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Panics unless exactly the txs `ids` were submitted, in that order.
+    #[track_caller]
+    pub fn assert_submits(&self, ids: &[u64]) {
+        assert_eq!(
+            self.submitted_ids(),
+            ids,
+            "submits differ; decisions: {:#?}",
+            self.records
+        );
+    }
+
+    /// Panics if the scheduler decided anything about tx `tx_id`.
+    #[track_caller]
+    pub fn assert_no_decision_for(&self, tx_id: u64) {
+        let decisions = self.decisions_for(tx_id);
+        assert!(
+            decisions.is_empty(),
+            "tx {} got decisions: {:#?}",
+            tx_id,
+            decisions
+        );
+    }
+}
+
+/// The `index`th request of `gas_saver_eth simulate`: a mix of cheap, at-target
+/// and generous caps around `base_fee`, from four senders in turn. The second
+/// one deploys a contract.
+pub fn scripted_request(index: u64, base_fee: Wei) -> TransactionRequest {
+    let deploy = index == 1;
+    let (max_fee_per_gas, urgency) = match index % 3 {
+        0 => (base_fee * 2, Urgency::Standard),
+        1 => (base_fee * 10, Urgency::High),
+        _ => (base_fee, Urgency::Low),
+    };
+    TransactionRequest {
+        id: index + 1,
+        from: [(index % 4) as u8 + 0xA0; 20],
+        to: (!deploy).then_some([0xBB; 20]),
+        // minimal init code: an empty runtime
+        data: if deploy {
+            vec![0x60, 0x00, 0x60, 0x00, 0xF3]
+        } else {
+            vec![]
+        },
+        value: [0; 32],
+        gas_limit: if deploy { 100_000 } else { 21_000 },
+        max_fee_per_gas,
+        max_priority_fee_per_gas: (Gwei(2) + Gwei(4) * (index % 3)).into(),
+        deadline: None,
+        urgency,
+        max_wait_blocks: None,
+        escalation: None,
+        chain_id: None,
+        blob: None,
+        fee_mode: FeeMode::Eip1559,
+        idempotency_key: None,
+        access_list: None,
+        authorization_list: None,
+        privacy: SubmissionPrivacy::Public,
+    }
+}
+
+#[cfg(all(test, feature = "config"))]
+mod tests {
+    use super::*;
+    use crate::config::AppConfig;
+    use crate::scheduler::MarketUpdatePolicy;
+
+    const BLOCK_TIME: Duration = Duration::from_millis(100);
+
+    /// `gas_saver_eth simulate --pattern <pattern> --txs <txs>` with the rest of
+    /// its defaults: 20 blocks 100ms apart around a 50 gwei target, and a
+    /// request every 500ms, landing mid-block.
+    fn simulation(pattern: FeePattern, txs: u64) -> ScenarioBuilder {
+        let config = AppConfig::default();
+        let target = Wei::from(Gwei(config.scheduler.target_base_fee));
+        let builder = ScenarioBuilder::new()
+            .with_config(SchedulerConfig {
+                market_updates: MarketUpdatePolicy::Every(Duration::from_millis(500)),
+                ..config.scheduler_config()
+            })
+            .with_model_window(config.model.window)
+            .with_rate_limit(config.limiter.rate, config.limiter.burst)
+            .blocks(Duration::ZERO, BLOCK_TIME, pattern, 20);
+        (0..txs).fold(builder, |builder, index| {
+            let request = scripted_request(index, target);
+            let at = BLOCK_TIME / 2 + Duration::from_millis(500) * index as u32;
+            builder
+                .with_sender(config.chain_id, request.from, 0)
+                .request(at, request)
+        })
+    }
+
+    fn gwei(n: u64) -> u64 {
+        n * 1_000_000_000
+    }
+
+    /// Runs `scenario` twice and checks both runs decided the same, to the byte.
+    async fn run_twice(scenario: impl Fn() -> ScenarioBuilder) -> ScenarioOutcome {
+        let first = scenario().run().await;
+        let second = scenario().run().await;
+        assert_eq!(
+            serde_json::to_vec(&first.records).unwrap(),
+            serde_json::to_vec(&second.records).unwrap()
+        );
+        first
+    }
+
+    #[tokio::test]
+    async fn test_spike_simulation() {
+        let outcome = run_twice(|| {
+            simulation(
+                FeePattern::Spike {
+                    base_fee: gwei(50),
+                    peak: gwei(250),
+                    at_block: 5,
+                    blocks: 5,
+                },
+                4,
+            )
+        })
+        .await;
+        outcome.assert_submits(&[1, 2, 3, 4]);
+        // the high-urgency deploy lands mid-spike and pays for it
+        let [SchedulerDecision::Submit { gas_price, .. }] = outcome.decisions_for(2)[..] else {
+            panic!("tx 2: {:?}", outcome.decisions_for(2));
+        };
+        assert_eq!(*gas_price, Wei::from(Gwei(256)));
+        let modes: Vec<_> = outcome
+            .decisions()
+            .filter(|decision| matches!(decision, SchedulerDecision::ModeChanged { .. }))
+            .collect();
+        assert_eq!(modes, [&SchedulerDecision::ModeChanged { spike: true }]);
+        assert_eq!(outcome.snapshot.submitted.len(), 4);
+        assert_eq!(outcome.nonces.accounts.len(), 4);
+        assert_eq!(outcome.limiter.acquired, 4);
+    }
+
+    #[tokio::test]
+    async fn test_flat_simulation_with_a_confirmation() {
+        let outcome = run_twice(|| {
+            simulation(FeePattern::Flat { base_fee: gwei(50) }, 3)
+                .report(Duration::from_millis(60), 1, ExecutionReport::Broadcast)
+                .report(
+                    Duration::from_millis(210),
+                    1,
+                    ExecutionReport::Confirmed {
+                        block_number: 3,
+                        effective_gas_price: gwei(52),
+                        gas_used: 21_000,
+                    },
+                )
+        })
+        .await;
+        outcome.assert_submits(&[1, 2, 3]);
+        assert_eq!(outcome.kinds_for(1), ["submit", "confirmed"]);
+        outcome.assert_no_decision_for(4);
+        let still_submitted: Vec<_> = outcome
+            .snapshot
+            .submitted
+            .iter()
+            .map(|tx| tx.req.id)
+            .collect();
+        assert_eq!(still_submitted, [2, 3]);
+    }
+
+    #[tokio::test]
+    async fn test_random_walk_simulation() {
+        let outcome = run_twice(|| {
+            simulation(
+                FeePattern::RandomWalk {
+                    start: gwei(50),
+                    max_step: gwei(10),
+                    seed: 42,
+                },
+                3,
+            )
+        })
+        .await;
+        outcome.assert_submits(&[1, 2, 3]);
+        assert_eq!(outcome.kinds_for(1), ["submit", "reprice", "reprice"]);
+        // the low-urgency tx capped at the target waits out a climb
+        assert_eq!(outcome.kinds_for(3), ["defer", "submit"]);
+        assert!(outcome.snapshot.pending.is_empty());
+    }
+}
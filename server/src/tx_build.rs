@@ -0,0 +1,646 @@
+use crate::events::{FeeMode, SchedulerDecision, TransactionRequest};
+use crate::units::Wei;
+use alloy_consensus::{TxEip1559, TxEip7702, TxLegacy, TypedTransaction};
+use alloy_eips::eip2930::{AccessList, AccessListItem};
+use alloy_eips::eip7702::{Authorization, SignedAuthorization};
+use alloy_primitives::{Address, B256, Bytes, TxKind, U256};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// Only `Submit` and `Reprice` decisions describe a tx to broadcast.
+    NotATransaction,
+    /// The decision is about a different request.
+    WrongRequest { request: u64, decision: u64 },
+    /// A `Submit` whose `create` flag disagrees with the request's `to`.
+    CreateMismatch,
+    /// The request leaves its chain to the scheduler's config; set `chain_id`
+    /// before building.
+    NoChainId,
+    /// Blob requests need a type-3 tx.
+    BlobTx,
+    /// The request is priced for the other tx type; this is its `fee_mode`.
+    WrongFeeMode(FeeMode),
+    /// Type-0 txs have no access list; the request's would be lost.
+    LegacyAccessList,
+    /// Requests with an authorization list need a type-4 tx.
+    AuthorizationTx,
+    /// Only requests with an authorization list build as type-4 txs.
+    NoAuthorizations,
+    /// Type-4 txs can't deploy contracts.
+    AuthorizationCreate,
+}
+
+impl std::fmt::Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BuildError::NotATransaction => write!(f, "decision does not describe a transaction"),
+            BuildError::WrongRequest { request, decision } => write!(
+                f,
+                "decision for tx {} applied to request {}",
+                decision, request
+            ),
+            BuildError::CreateMismatch => {
+                write!(f, "decision and request disagree on contract creation")
+            }
+            BuildError::NoChainId => write!(f, "request has no chain id"),
+            BuildError::BlobTx => write!(f, "blob requests need a type-3 transaction"),
+            BuildError::WrongFeeMode(fee_mode) => {
+                write!(f, "request is priced as {:?}", fee_mode)
+            }
+            BuildError::LegacyAccessList => {
+                write!(f, "legacy transactions can't carry an access list")
+            }
+            BuildError::AuthorizationTx => {
+                write!(f, "requests with authorizations need a type-4 transaction")
+            }
+            BuildError::NoAuthorizations => write!(f, "request has no authorizations"),
+            BuildError::AuthorizationCreate => {
+                write!(f, "type-4 transactions can't create contracts")
+            }
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Builds the unsigned type-2 tx an executor should sign for a `Submit`, or the
+/// same-nonce replacement for a `Reprice`.
+///
+/// The decision's gas price becomes `max_fee_per_gas` and its tip, already
+/// clamped by the scheduler, `max_priority_fee_per_gas`, never above that. `value` is read as a big-endian U256, the byte
+/// order `TransactionRequest::value` is stored in. The access list is copied over
+/// as is.
+pub fn build_eip1559(
+    req: &TransactionRequest,
+    decision: &SchedulerDecision,
+) -> Result<TxEip1559, BuildError> {
+    let (chain_id, nonce, gas_price, tip) = priced(req, decision, FeeMode::Eip1559)?;
+    if req.authorization_list.is_some() {
+        return Err(BuildError::AuthorizationTx);
+    }
+    Ok(TxEip1559 {
+        chain_id,
+        nonce,
+        gas_limit: req.gas_limit,
+        max_fee_per_gas: gas_price.0,
+        max_priority_fee_per_gas: tip.0,
+        to: tx_kind(req),
+        value: U256::from_be_bytes(req.value),
+        access_list: access_list(req),
+        input: Bytes::copy_from_slice(&req.data),
+    })
+}
+
+/// Builds the unsigned type-0 tx for a `FeeMode::Legacy` request, with the
+/// decision's gas price as its `gasPrice` and EIP-155 replay protection.
+pub fn build_legacy(
+    req: &TransactionRequest,
+    decision: &SchedulerDecision,
+) -> Result<TxLegacy, BuildError> {
+    let (chain_id, nonce, gas_price, _) = priced(req, decision, FeeMode::Legacy)?;
+    if !access_list(req).is_empty() {
+        return Err(BuildError::LegacyAccessList);
+    }
+    if req.authorization_list.is_some() {
+        return Err(BuildError::AuthorizationTx);
+    }
+    Ok(TxLegacy {
+        chain_id: Some(chain_id),
+        nonce,
+        gas_price: gas_price.0,
+        gas_limit: req.gas_limit,
+        to: tx_kind(req),
+        value: U256::from_be_bytes(req.value),
+        input: Bytes::copy_from_slice(&req.data),
+    })
+}
+
+/// Builds the unsigned type-4 tx for a request with an authorization list,
+/// priced as `build_eip1559` prices a type-2 tx. The authorizations are copied
+/// over as signed; nothing here recovers or checks their signers.
+pub fn build_eip7702(
+    req: &TransactionRequest,
+    decision: &SchedulerDecision,
+) -> Result<TxEip7702, BuildError> {
+    let (chain_id, nonce, gas_price, tip) = priced(req, decision, FeeMode::Eip1559)?;
+    let authorizations = req
+        .authorization_list
+        .as_ref()
+        .ok_or(BuildError::NoAuthorizations)?;
+    let to = req.to.ok_or(BuildError::AuthorizationCreate)?;
+    Ok(TxEip7702 {
+        chain_id,
+        nonce,
+        gas_limit: req.gas_limit,
+        max_fee_per_gas: gas_price.0,
+        max_priority_fee_per_gas: tip.0,
+        to: Address::from(to),
+        value: U256::from_be_bytes(req.value),
+        access_list: access_list(req),
+        authorization_list: authorizations
+            .iter()
+            .map(|auth| {
+                SignedAuthorization::new_unchecked(
+                    Authorization {
+                        chain_id: U256::from(auth.chain_id),
+                        address: Address::from(auth.address),
+                        nonce: auth.nonce,
+                    },
+                    auth.y_parity,
+                    U256::from_be_bytes(auth.r),
+                    U256::from_be_bytes(auth.s),
+                )
+            })
+            .collect(),
+        input: Bytes::copy_from_slice(&req.data),
+    })
+}
+
+/// Builds whichever of `build_legacy`, `build_eip1559` or `build_eip7702` suits
+/// `req`: type 0 in `Legacy` mode, type 4 with an authorization list, type 2
+/// otherwise.
+pub fn build(
+    req: &TransactionRequest,
+    decision: &SchedulerDecision,
+) -> Result<TypedTransaction, BuildError> {
+    if req.fee_mode == FeeMode::Legacy {
+        build_legacy(req, decision).map(TypedTransaction::Legacy)
+    } else if req.authorization_list.is_some() {
+        build_eip7702(req, decision).map(TypedTransaction::Eip7702)
+    } else {
+        build_eip1559(req, decision).map(TypedTransaction::Eip1559)
+    }
+}
+
+/// Builds the zero-value self-transfer a `FillNonceGap` asks for, at 21000 gas.
+/// Its gas price is the fee cap and the tip both, since the gap holds up every
+/// tx queued above it.
+pub fn build_gap_fill(decision: &SchedulerDecision) -> Result<TypedTransaction, BuildError> {
+    let SchedulerDecision::FillNonceGap {
+        chain_id,
+        address,
+        nonce,
+        gas_price,
+        fee_mode,
+        ..
+    } = *decision
+    else {
+        return Err(BuildError::NotATransaction);
+    };
+    let to = TxKind::Call(Address::from(address));
+    Ok(match fee_mode {
+        FeeMode::Legacy => TypedTransaction::Legacy(TxLegacy {
+            chain_id: Some(chain_id),
+            nonce,
+            gas_price: gas_price.0,
+            gas_limit: 21_000,
+            to,
+            value: U256::ZERO,
+            input: Bytes::new(),
+        }),
+        FeeMode::Eip1559 => TypedTransaction::Eip1559(TxEip1559 {
+            chain_id,
+            nonce,
+            gas_limit: 21_000,
+            max_fee_per_gas: gas_price.0,
+            max_priority_fee_per_gas: gas_price.0,
+            to,
+            value: U256::ZERO,
+            access_list: AccessList::default(),
+            input: Bytes::new(),
+        }),
+    })
+}
+
+/// Chain id, nonce, gas price and tip for `req` from `decision`, once both agree
+/// on the tx and it is one `fee_mode` can build.
+fn priced(
+    req: &TransactionRequest,
+    decision: &SchedulerDecision,
+    fee_mode: FeeMode,
+) -> Result<(u64, u64, Wei, Wei), BuildError> {
+    let (tx_id, nonce, gas_price, tip) = match *decision {
+        SchedulerDecision::Submit {
+            tx_id,
+            nonce,
+            gas_price,
+            max_priority_fee_per_gas,
+            create,
+            ..
+        } => {
+            if tx_id == req.id && create != req.is_create() {
+                return Err(BuildError::CreateMismatch);
+            }
+            (tx_id, nonce, gas_price, max_priority_fee_per_gas)
+        }
+        SchedulerDecision::Reprice {
+            tx_id,
+            old_nonce,
+            new_gas_price,
+            max_priority_fee_per_gas,
+            ..
+        } => (tx_id, old_nonce, new_gas_price, max_priority_fee_per_gas),
+        _ => return Err(BuildError::NotATransaction),
+    };
+    if tx_id != req.id {
+        return Err(BuildError::WrongRequest {
+            request: req.id,
+            decision: tx_id,
+        });
+    }
+    let chain_id = req.chain_id.ok_or(BuildError::NoChainId)?;
+    if req.blob.is_some() {
+        return Err(BuildError::BlobTx);
+    }
+    if req.fee_mode != fee_mode {
+        return Err(BuildError::WrongFeeMode(req.fee_mode));
+    }
+    Ok((chain_id, nonce, gas_price, tip.min(gas_price)))
+}
+
+fn access_list(req: &TransactionRequest) -> AccessList {
+    AccessList(
+        req.access_list
+            .iter()
+            .flatten()
+            .map(|item| AccessListItem {
+                address: Address::from(item.address),
+                storage_keys: item
+                    .storage_keys
+                    .iter()
+                    .map(|&key| B256::from(key))
+                    .collect(),
+            })
+            .collect(),
+    )
+}
+
+fn tx_kind(req: &TransactionRequest) -> TxKind {
+    match req.to {
+        Some(to) => TxKind::Call(Address::from(to)),
+        None => TxKind::Create,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{SubmissionPrivacy, Urgency};
+    use crate::units::Gwei;
+    use alloy_consensus::SignableTransaction;
+    use alloy_primitives::hex;
+
+    fn request() -> TransactionRequest {
+        TransactionRequest {
+            id: 1,
+            from: [0xAA; 20],
+            to: Some([0xBB; 20]),
+            data: vec![],
+            // 1 ether
+            value: U256::from(1_000_000_000_000_000_000u64).to_be_bytes(),
+            gas_limit: 21_000,
+            max_fee_per_gas: Wei(100),
+            max_priority_fee_per_gas: Wei(2),
+            deadline: None,
+            urgency: Urgency::Standard,
+            max_wait_blocks: None,
+            escalation: None,
+            chain_id: Some(1),
+            blob: None,
+            fee_mode: FeeMode::Eip1559,
+            idempotency_key: None,
+            access_list: None,
+            authorization_list: None,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn authorization() -> crate::events::SignedAuthorization {
+        crate::events::SignedAuthorization {
+            chain_id: 1,
+            address: [0xDD; 20],
+            nonce: 3,
+            y_parity: 1,
+            r: [0x11; 32],
+            s: [0x22; 32],
+        }
+    }
+
+    fn submit(tx_id: u64, nonce: u64, gas_price: u128, create: bool) -> SchedulerDecision {
+        SchedulerDecision::Submit {
+            tx_id,
+            nonce,
+            gas_price: Wei(gas_price),
+            max_priority_fee_per_gas: Wei(2),
+            create,
+            estimated_cost_wei: Wei::ZERO,
+            estimated_savings_wei: Wei::ZERO,
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        }
+    }
+
+    fn signing_bytes(tx: &TxEip1559) -> Vec<u8> {
+        let mut out = Vec::new();
+        tx.encode_for_signing(&mut out);
+        out
+    }
+
+    #[test]
+    fn test_transfer_encoding() {
+        let tx = build_eip1559(&request(), &submit(1, 0, 52, false)).unwrap();
+        // 0x02 || rlp([chain_id, nonce, tip, fee cap, gas, to, value, data, access list])
+        let expected = hex::decode(concat!(
+            "02e7",
+            "01",
+            "80",
+            "02",
+            "34",
+            "825208",
+            "94bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "880de0b6b3a7640000",
+            "80",
+            "c0",
+        ))
+        .unwrap();
+        assert_eq!(signing_bytes(&tx), expected);
+    }
+
+    #[test]
+    fn test_access_list_encoding() {
+        let req = TransactionRequest {
+            gas_limit: 30_000,
+            access_list: Some(vec![crate::events::AccessListItem {
+                address: [0xCC; 20],
+                storage_keys: vec![[0x01; 32]],
+            }]),
+            ..request()
+        };
+        let tx = build_eip1559(&req, &submit(1, 0, 52, false)).unwrap();
+        let expected = hex::decode(concat!(
+            "02f860",
+            "01",
+            "80",
+            "02",
+            "34",
+            "827530",
+            "94bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "880de0b6b3a7640000",
+            "80",
+            // [[address, [storage key]]]
+            "f838",
+            "f7",
+            "94cccccccccccccccccccccccccccccccccccccccc",
+            "e1",
+            "a00101010101010101010101010101010101010101010101010101010101010101",
+        ))
+        .unwrap();
+        assert_eq!(signing_bytes(&tx), expected);
+
+        let legacy = TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..req
+        };
+        assert_eq!(
+            build_legacy(&legacy, &submit(1, 0, 52, false)),
+            Err(BuildError::LegacyAccessList)
+        );
+    }
+
+    #[test]
+    fn test_authorization_encoding() {
+        let req = TransactionRequest {
+            gas_limit: 46_000,
+            authorization_list: Some(vec![authorization()]),
+            ..request()
+        };
+        let tx = build_eip7702(&req, &submit(1, 0, 52, false)).unwrap();
+        let mut out = Vec::new();
+        tx.encode_for_signing(&mut out);
+        // 0x04 || rlp([..type-2 fields, authorization list])
+        let expected = hex::decode(concat!(
+            "04f885",
+            "01",
+            "80",
+            "02",
+            "34",
+            "82b3b0",
+            "94bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "880de0b6b3a7640000",
+            "80",
+            "c0",
+            // [[chain id, address, nonce, y parity, r, s]]
+            "f85c",
+            "f85a",
+            "01",
+            "94dddddddddddddddddddddddddddddddddddddddd",
+            "03",
+            "01",
+            "a01111111111111111111111111111111111111111111111111111111111111111",
+            "a02222222222222222222222222222222222222222222222222222222222222222",
+        ))
+        .unwrap();
+        assert_eq!(out, expected);
+
+        assert_eq!(
+            build_eip1559(&req, &submit(1, 0, 52, false)),
+            Err(BuildError::AuthorizationTx)
+        );
+        assert_eq!(
+            build_eip7702(&request(), &submit(1, 0, 52, false)),
+            Err(BuildError::NoAuthorizations)
+        );
+        let create = TransactionRequest { to: None, ..req };
+        assert_eq!(
+            build_eip7702(&create, &submit(1, 0, 52, true)),
+            Err(BuildError::AuthorizationCreate)
+        );
+    }
+
+    #[test]
+    fn test_deployment_encoding() {
+        let req = TransactionRequest {
+            to: None,
+            data: vec![0x60, 0x80, 0x60, 0x40, 0x52],
+            value: [0; 32],
+            gas_limit: 60_000,
+            max_fee_per_gas: Gwei(40).into(),
+            max_priority_fee_per_gas: Gwei(1).into(),
+            chain_id: Some(10),
+            ..request()
+        };
+        let mut decision = submit(1, 5, 30_000_000_000, true);
+        if let SchedulerDecision::Submit {
+            max_priority_fee_per_gas,
+            ..
+        } = &mut decision
+        {
+            *max_priority_fee_per_gas = Gwei(1).into();
+        }
+        let tx = build_eip1559(&req, &decision).unwrap();
+        assert_eq!(tx.to, TxKind::Create);
+        let expected = hex::decode(concat!(
+            "02d9",
+            "0a",
+            "05",
+            "843b9aca00",
+            "8506fc23ac00",
+            "82ea60",
+            // empty `to` and zero value
+            "80",
+            "80",
+            "856080604052",
+            "c0",
+        ))
+        .unwrap();
+        assert_eq!(signing_bytes(&tx), expected);
+    }
+
+    #[test]
+    fn test_reprice_keeps_nonce_and_uses_the_clamped_tip() {
+        // the request would tip 90, but the scheduler clamped it to 2
+        let req = TransactionRequest {
+            max_priority_fee_per_gas: Wei(90),
+            ..request()
+        };
+        let reprice = |tip| SchedulerDecision::Reprice {
+            tx_id: 1,
+            old_nonce: 4,
+            new_gas_price: Wei(60),
+            max_priority_fee_per_gas: Wei(tip),
+            blob_gas_price: None,
+            fee_mode: FeeMode::Eip1559,
+            privacy: SubmissionPrivacy::Public,
+        };
+        let tx = build_eip1559(&req, &reprice(2)).unwrap();
+        assert_eq!(tx.nonce, 4);
+        assert_eq!(tx.max_fee_per_gas, 60);
+        assert_eq!(tx.max_priority_fee_per_gas, 2);
+        assert_eq!(tx.value, U256::from(10u64).pow(U256::from(18)));
+
+        // never above the fee cap
+        let tx = build_eip1559(&req, &reprice(90)).unwrap();
+        assert_eq!(tx.max_priority_fee_per_gas, 60);
+    }
+
+    #[test]
+    fn test_legacy_encoding() {
+        let req = TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..request()
+        };
+        let tx = build_legacy(&req, &submit(1, 0, 52, false)).unwrap();
+        let mut out = Vec::new();
+        tx.encode_for_signing(&mut out);
+        // rlp([nonce, gas price, gas, to, value, data, chain_id, 0, 0]), per EIP-155
+        let expected = hex::decode(concat!(
+            "e7",
+            "80",
+            "34",
+            "825208",
+            "94bbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbbb",
+            "880de0b6b3a7640000",
+            "80",
+            "01",
+            "80",
+            "80",
+        ))
+        .unwrap();
+        assert_eq!(out, expected);
+
+        assert_eq!(
+            build_eip1559(&req, &submit(1, 0, 52, false)),
+            Err(BuildError::WrongFeeMode(FeeMode::Legacy))
+        );
+        assert_eq!(
+            build_legacy(&request(), &submit(1, 0, 52, false)),
+            Err(BuildError::WrongFeeMode(FeeMode::Eip1559))
+        );
+    }
+
+    #[test]
+    fn test_build_errors() {
+        let req = request();
+        assert_eq!(
+            build_eip1559(&req, &submit(2, 0, 52, false)),
+            Err(BuildError::WrongRequest {
+                request: 1,
+                decision: 2,
+            })
+        );
+        assert_eq!(
+            build_eip1559(&req, &submit(1, 0, 52, true)),
+            Err(BuildError::CreateMismatch)
+        );
+        let defer = SchedulerDecision::Defer {
+            tx_id: 1,
+            reason: crate::events::DeferReason::FeeAboveMax,
+            retry_hint: None,
+        };
+        assert_eq!(
+            build_eip1559(&req, &defer),
+            Err(BuildError::NotATransaction)
+        );
+        let unchained = TransactionRequest {
+            chain_id: None,
+            ..req
+        };
+        assert_eq!(
+            build_eip1559(&unchained, &submit(1, 0, 52, false)),
+            Err(BuildError::NoChainId)
+        );
+        let blob = TransactionRequest {
+            blob: Some(crate::events::BlobParams {
+                max_fee_per_blob_gas: Wei(1),
+                blob_count: 1,
+            }),
+            ..request()
+        };
+        assert_eq!(
+            build_eip1559(&blob, &submit(1, 0, 52, false)),
+            Err(BuildError::BlobTx)
+        );
+    }
+
+    #[test]
+    fn test_build_picks_tx_type() {
+        let legacy = TransactionRequest {
+            fee_mode: FeeMode::Legacy,
+            ..request()
+        };
+        let authorized = TransactionRequest {
+            authorization_list: Some(vec![authorization()]),
+            ..request()
+        };
+        let decision = submit(1, 0, 52, false);
+        assert!(matches!(
+            build(&request(), &decision),
+            Ok(TypedTransaction::Eip1559(_))
+        ));
+        assert!(matches!(
+            build(&legacy, &decision),
+            Ok(TypedTransaction::Legacy(_))
+        ));
+        assert!(matches!(
+            build(&authorized, &decision),
+            Ok(TypedTransaction::Eip7702(_))
+        ));
+
+        let fill = SchedulerDecision::FillNonceGap {
+            tx_id: 9,
+            chain_id: 1,
+            address: [0xAA; 20],
+            nonce: 7,
+            gas_price: Wei(52),
+            fee_mode: FeeMode::Eip1559,
+        };
+        let Ok(TypedTransaction::Eip1559(tx)) = build_gap_fill(&fill) else {
+            panic!("expected a type-2 gap fill");
+        };
+        assert_eq!(tx.to, TxKind::Call(Address::from([0xAA; 20])));
+        assert_eq!((tx.nonce, tx.gas_limit, tx.value), (7, 21_000, U256::ZERO));
+        assert_eq!((tx.max_fee_per_gas, tx.max_priority_fee_per_gas), (52, 52));
+        assert_eq!(build_gap_fill(&decision), Err(BuildError::NotATransaction));
+    }
+}
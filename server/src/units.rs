@@ -0,0 +1,301 @@
+use borsh::{BorshDeserialize, BorshSerialize};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Div, Mul, Sub, SubAssign};
+
+pub const WEI_PER_GWEI: u128 = 1_000_000_000;
+pub const WEI_PER_ETHER: u128 = 1_000_000_000_000_000_000;
+
+/// Decimals shown for gwei amounts; smaller amounts are printed in wei.
+const GWEI_PLACES: u32 = 3;
+/// Decimals shown for ether amounts; smaller amounts are printed in gwei.
+const ETHER_PLACES: u32 = 6;
+
+pub fn gwei_to_wei(gwei: u64) -> u128 {
+    gwei as u128 * WEI_PER_GWEI
+}
+
+pub fn ether_to_wei(ether: u64) -> u128 {
+    ether as u128 * WEI_PER_ETHER
+}
+
+pub fn wei_to_gwei(wei: u128) -> u128 {
+    round_div(wei, WEI_PER_GWEI)
+}
+
+pub fn wei_to_ether(wei: u128) -> u128 {
+    round_div(wei, WEI_PER_ETHER)
+}
+
+/// An amount in wei: a per-gas fee or price, or a total cost. Amounts in other
+/// units only become `Wei` through an explicit conversion, so adding a gwei figure
+/// to a wei one doesn't compile.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Wei(pub u128);
+
+/// A whole number of gwei, the unit fees are usually quoted in.
+#[derive(
+    BorshSerialize,
+    BorshDeserialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    Default,
+)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Gwei(pub u64);
+
+impl Wei {
+    pub const ZERO: Wei = Wei(0);
+
+    /// Lossy: rounds to the nearest gwei, halves up, and saturates at `u64::MAX`.
+    pub fn to_gwei(self) -> Gwei {
+        Gwei(u64::try_from(wei_to_gwei(self.0)).unwrap_or(u64::MAX))
+    }
+
+    pub fn saturating_add(self, other: Wei) -> Wei {
+        Wei(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Wei) -> Wei {
+        Wei(self.0.saturating_sub(other.0))
+    }
+
+    pub fn checked_sub(self, other: Wei) -> Option<Wei> {
+        self.0.checked_sub(other.0).map(Wei)
+    }
+
+    pub fn saturating_mul(self, factor: u64) -> Wei {
+        Wei(self.0.saturating_mul(factor as u128))
+    }
+
+    /// Rounds up, unlike `/`.
+    pub fn div_ceil(self, divisor: u64) -> Wei {
+        Wei(self.0.div_ceil(divisor as u128))
+    }
+}
+
+/// Lossless.
+impl From<Gwei> for Wei {
+    fn from(gwei: Gwei) -> Self {
+        Wei(gwei_to_wei(gwei.0))
+    }
+}
+
+/// Migration shim: the integer must already be in wei, as every fee field was
+/// before these types.
+impl From<u64> for Wei {
+    fn from(wei: u64) -> Self {
+        Wei(wei as u128)
+    }
+}
+
+/// Lossless for anything up to `u64::MAX` wei (about 18 ether); fails beyond.
+impl TryFrom<Wei> for u64 {
+    type Error = std::num::TryFromIntError;
+
+    fn try_from(wei: Wei) -> Result<Self, Self::Error> {
+        u64::try_from(wei.0)
+    }
+}
+
+impl Add for Wei {
+    type Output = Wei;
+
+    fn add(self, other: Wei) -> Wei {
+        Wei(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Wei {
+    fn add_assign(&mut self, other: Wei) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Wei {
+    type Output = Wei;
+
+    fn sub(self, other: Wei) -> Wei {
+        Wei(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Wei {
+    fn sub_assign(&mut self, other: Wei) {
+        self.0 -= other.0;
+    }
+}
+
+/// A per-gas price times an amount of gas, or a fee scaled by a whole factor.
+impl Mul<u64> for Wei {
+    type Output = Wei;
+
+    fn mul(self, factor: u64) -> Wei {
+        Wei(self.0 * factor as u128)
+    }
+}
+
+/// Rounds down, like integer division.
+impl Div<u64> for Wei {
+    type Output = Wei;
+
+    fn div(self, divisor: u64) -> Wei {
+        Wei(self.0 / divisor as u128)
+    }
+}
+
+impl Sum for Wei {
+    fn sum<I: Iterator<Item = Wei>>(iter: I) -> Wei {
+        iter.fold(Wei::ZERO, Add::add)
+    }
+}
+
+/// As `format_gwei`, e.g. `30 gwei`.
+impl fmt::Display for Wei {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&format_gwei(self.0))
+    }
+}
+
+impl Gwei {
+    pub fn to_wei(self) -> Wei {
+        self.into()
+    }
+}
+
+impl Add for Gwei {
+    type Output = Gwei;
+
+    fn add(self, other: Gwei) -> Gwei {
+        Gwei(self.0 + other.0)
+    }
+}
+
+impl Mul<u64> for Gwei {
+    type Output = Gwei;
+
+    fn mul(self, factor: u64) -> Gwei {
+        Gwei(self.0 * factor)
+    }
+}
+
+impl fmt::Display for Gwei {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} gwei", self.0)
+    }
+}
+
+/// A fee or price, e.g. `30 gwei` or `1.5 gwei`. Amounts under a thousandth of a
+/// gwei print exactly, in wei.
+pub fn format_gwei(wei: u128) -> String {
+    if wei != 0 && wei < WEI_PER_GWEI / 10u128.pow(GWEI_PLACES) {
+        return format!("{} wei", wei);
+    }
+    format!("{} gwei", decimal(wei, WEI_PER_GWEI, GWEI_PLACES))
+}
+
+/// A total amount, e.g. `1.25 ETH`. Amounts under a millionth of an ether fall back
+/// to `format_gwei`.
+pub fn format_ether(wei: u128) -> String {
+    if wei < WEI_PER_ETHER / 10u128.pow(ETHER_PLACES) {
+        return format_gwei(wei);
+    }
+    format!("{} ETH", decimal(wei, WEI_PER_ETHER, ETHER_PLACES))
+}
+
+/// Rounds to the nearest whole, halves up, as every conversion to a larger unit does.
+fn round_div(value: u128, divisor: u128) -> u128 {
+    value / divisor + u128::from(value % divisor >= divisor - divisor / 2)
+}
+
+/// `wei` in `unit`s rounded to `places` decimals, without trailing zeros.
+fn decimal(wei: u128, unit: u128, places: u32) -> String {
+    let scale = 10u128.pow(places);
+    let scaled = round_div(wei, unit / scale);
+    let (whole, fraction) = (scaled / scale, scaled % scale);
+    if fraction == 0 {
+        return whole.to_string();
+    }
+    let fraction = format!("{:0width$}", fraction, width = places as usize);
+    format!("{}.{}", whole, fraction.trim_end_matches('0'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_round_halves_up() {
+        assert_eq!(gwei_to_wei(30), 30_000_000_000);
+        assert_eq!(ether_to_wei(2), 2 * WEI_PER_ETHER);
+        assert_eq!(wei_to_gwei(1_499_999_999), 1);
+        assert_eq!(wei_to_gwei(1_500_000_000), 2);
+        assert_eq!(wei_to_gwei(499_999_999), 0);
+        assert_eq!(wei_to_ether(WEI_PER_ETHER / 2), 1);
+        assert_eq!(wei_to_ether(WEI_PER_ETHER / 2 - 1), 0);
+    }
+
+    #[test]
+    fn test_typed_units() {
+        assert_eq!(Wei::from(Gwei(30)), Wei(30_000_000_000));
+        assert_eq!(Gwei(2).to_wei() * 21_000, Wei(42_000_000_000_000));
+        assert_eq!(Wei(1_499_999_999).to_gwei(), Gwei(1));
+        assert_eq!(Wei(u128::MAX).to_gwei(), Gwei(u64::MAX));
+        assert_eq!(Wei(52) + Wei::from(8u64) - Wei(10), Wei(50));
+        assert_eq!(Wei(52) * 110 / 100, Wei(57));
+        assert_eq!(Wei(5).saturating_sub(Wei(7)), Wei::ZERO);
+        assert_eq!([Wei(1), Wei(2)].into_iter().sum::<Wei>(), Wei(3));
+        assert_eq!(u64::try_from(Wei(u64::MAX as u128 + 1)).ok(), None);
+        assert_eq!(Wei(31_250_000_000).to_string(), "31.25 gwei");
+        assert_eq!(Gwei(30).to_string(), "30 gwei");
+        // the same number on the wire as the plain integers they replace
+        #[cfg(feature = "serde")]
+        assert_eq!(serde_json::to_string(&Wei(100)).unwrap(), "100");
+        assert_eq!(borsh::to_vec(&Gwei(1)).unwrap(), 1u64.to_le_bytes());
+    }
+
+    #[test]
+    fn test_format_gwei() {
+        assert_eq!(format_gwei(0), "0 gwei");
+        assert_eq!(format_gwei(70_000_000_000), "70 gwei");
+        assert_eq!(format_gwei(1_500_000_000), "1.5 gwei");
+        assert_eq!(format_gwei(1_234_567_890), "1.235 gwei");
+        assert_eq!(format_gwei(1_000_000), "0.001 gwei");
+        // below the last decimal the exact wei figure is more useful than 0
+        assert_eq!(format_gwei(999_999), "999999 wei");
+        assert_eq!(format_gwei(52), "52 wei");
+    }
+
+    #[test]
+    fn test_format_ether() {
+        assert_eq!(format_ether(WEI_PER_ETHER), "1 ETH");
+        assert_eq!(format_ether(1_250_000_000_000_000_000), "1.25 ETH");
+        assert_eq!(format_ether(123_456_789_000_000_000_000), "123.456789 ETH");
+        assert_eq!(format_ether(1_000_000_500_000_000_000), "1.000001 ETH");
+        assert_eq!(format_ether(1_000_000_000_000), "0.000001 ETH");
+        assert_eq!(format_ether(21_000 * 30_000_000_000), "0.00063 ETH");
+        assert_eq!(format_ether(999_999_999_999), "1000 gwei");
+        assert_eq!(format_ether(0), "0 gwei");
+    }
+}
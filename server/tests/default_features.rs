@@ -0,0 +1,25 @@
+use std::process::Command;
+
+/// The default build is the Borsh-only library: serde and everything built on it
+/// stay behind the `serde` feature and the features that enable it.
+#[test]
+fn test_default_features_build_without_serde() {
+    let output = Command::new(env!("CARGO"))
+        .args(["tree", "--edges", "normal", "--prefix", "none"])
+        .args(["--format", "{p}", "--manifest-path"])
+        .arg(concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml"))
+        .output()
+        .expect("cargo tree runs");
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let tree = String::from_utf8(output.stdout).unwrap();
+    let serde: Vec<&str> = tree
+        .lines()
+        .filter(|line| line.starts_with("serde"))
+        .collect();
+    assert!(serde.is_empty(), "default features pull in {:?}", serde);
+    assert!(tree.lines().any(|line| line.starts_with("borsh ")));
+}